@@ -0,0 +1,16 @@
+-- Migration: Add dead_letter_jobs table for poison messages
+-- Purpose: Messages the worker can't parse are parked here and ACKed, instead of
+-- sitting in the Redis PEL and being redelivered forever
+
+CREATE TABLE IF NOT EXISTS "dead_letter_jobs" (
+  "id" bigserial PRIMARY KEY,
+  "stream" text NOT NULL,
+  "message_id" text NOT NULL,
+  "group_name" text NOT NULL,
+  "payload" text,
+  "error" text NOT NULL,
+  "delivery_count" integer NOT NULL DEFAULT 1,
+  "created_at" timestamp with time zone DEFAULT now() NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "idx_dead_letter_jobs_created" ON "dead_letter_jobs" ("created_at");
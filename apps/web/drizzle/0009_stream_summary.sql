@@ -0,0 +1,8 @@
+-- Migration: Add stream_summary to workflow_runs
+-- Purpose: The worker's retention sweep deletes old run_stream_chunks; a per-node
+-- summary (chunk count, bytes) is kept here so pruned runs still show what streamed
+
+ALTER TABLE "workflow_runs" ADD COLUMN IF NOT EXISTS "stream_summary" jsonb;
+
+-- Supports the sweep's "finished before the cutoff" scan
+CREATE INDEX IF NOT EXISTS "idx_workflow_runs_completed" ON "workflow_runs" ("completed_at");
@@ -0,0 +1,24 @@
+-- Migration: Add workflow_schedules table
+-- Purpose: A workflow can carry several cron schedules, each with its own input
+-- and overlap mode. The single-schedule columns on workflows keep working.
+
+CREATE TABLE IF NOT EXISTS "workflow_schedules" (
+  "id" serial PRIMARY KEY,
+  "workflow_id" integer NOT NULL REFERENCES "workflows"("id") ON DELETE CASCADE,
+  "name" text,
+  "cron" text NOT NULL,
+  "timezone" text DEFAULT 'UTC',
+  "input_data" jsonb,
+  "overlap_mode" text DEFAULT 'skip',
+  "enabled" boolean DEFAULT true NOT NULL,
+  "next_run" timestamp with time zone,
+  "last_run" timestamp with time zone,
+  "created_at" timestamp DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS "idx_workflow_schedules_next_run" ON "workflow_schedules" ("next_run");
+CREATE INDEX IF NOT EXISTS "idx_workflow_schedules_workflow" ON "workflow_schedules" ("workflow_id");
+
+-- Which schedule fired a cron run (NULL for the legacy workflows.schedule_* columns),
+-- so skip-overlap is checked per schedule
+ALTER TABLE "workflow_runs" ADD COLUMN IF NOT EXISTS "schedule_id" integer;
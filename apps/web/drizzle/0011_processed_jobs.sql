@@ -0,0 +1,13 @@
+-- Migration: Add processed_jobs table
+-- Purpose: Lifecycle events carrying an idempotency_key are claimed here before
+-- they run; the primary key stops a racing redelivery from applying them twice
+
+CREATE TABLE IF NOT EXISTS "processed_jobs" (
+  "idempotency_key" text PRIMARY KEY,
+  "run_id" uuid,
+  "node_id" text NOT NULL,
+  "created_at" timestamp with time zone DEFAULT now() NOT NULL
+);
+
+-- Supports pruning claims older than IDEMPOTENCY_TTL_SECS
+CREATE INDEX IF NOT EXISTS "idx_processed_jobs_created" ON "processed_jobs" ("created_at");
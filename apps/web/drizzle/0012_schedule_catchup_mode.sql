@@ -0,0 +1,8 @@
+-- Migration: Add catch-up mode for missed cron runs
+-- Purpose: Decide what happens to slots missed while no worker was running:
+--   'skip'     - drop them, resume at the next future slot
+--   'run_once' - fire a single catch-up run for the whole missed window
+--   'run_all'  - fire one run per missed slot (bounded by SCHEDULE_CATCHUP_MAX)
+
+ALTER TABLE "workflows" ADD COLUMN IF NOT EXISTS "schedule_catchup_mode" text DEFAULT 'skip';
+ALTER TABLE "workflow_schedules" ADD COLUMN IF NOT EXISTS "catchup_mode" text DEFAULT 'skip';
@@ -0,0 +1,20 @@
+-- Migration: Add node_type_latency table
+-- Purpose: Per-node-type latency summaries flushed by each worker every
+-- NODE_LATENCY_FLUSH_SECS, so recent p50/p95 don't need a scan of run_events.
+-- Workers delete rows older than 7 days.
+
+CREATE TABLE IF NOT EXISTS "node_type_latency" (
+  "id" serial PRIMARY KEY,
+  "worker_id" text NOT NULL,
+  "node_type" text NOT NULL,
+  "window_start" timestamp with time zone NOT NULL,
+  "window_end" timestamp with time zone NOT NULL,
+  "count" bigint NOT NULL,
+  "mean_ms" double precision NOT NULL,
+  "p50_ms" double precision NOT NULL,
+  "p95_ms" double precision NOT NULL,
+  "p99_ms" double precision NOT NULL,
+  "max_ms" double precision NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS "idx_node_type_latency_type_end" ON "node_type_latency" ("node_type", "window_end");
@@ -0,0 +1,6 @@
+-- Migration: Add dry_run to workflow_runs
+-- Purpose: Dry runs validate graph wiring. Every job of such a run carries
+-- dry_run, so the worker reports what each node would do instead of calling
+-- out, writing, waiting or spawning children.
+
+ALTER TABLE "workflow_runs" ADD COLUMN IF NOT EXISTS "dry_run" boolean NOT NULL DEFAULT false;
@@ -0,0 +1,13 @@
+-- Migration: Adaptive concurrency for map batches
+-- Purpose: With adaptive_concurrency, the worker re-tunes concurrency_limit
+-- after each window of child completions (grow while errors and latency stay
+-- low, halve when errors spike), between 1 and max_concurrency. window_seq
+-- makes sure only one racing completion closes a window.
+
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "adaptive_concurrency" boolean NOT NULL DEFAULT false;
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "max_concurrency" integer;
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "window_completed" integer NOT NULL DEFAULT 0;
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "window_failed" integer NOT NULL DEFAULT 0;
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "window_started_at" timestamp with time zone NOT NULL DEFAULT now();
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "window_latency_ms" integer;
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "window_seq" integer NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- Migration: Live map results
+-- Purpose: With stream_results, each child completion streams its result as
+-- an 'item' chunk on the map node (chunk_index = item_index + 1), so the UI
+-- can fill a results grid while the batch runs. Off by default.
+
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "stream_results" boolean NOT NULL DEFAULT false;
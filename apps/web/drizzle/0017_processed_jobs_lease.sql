@@ -0,0 +1,9 @@
+-- Migration: Lease lifecycle claims in processed_jobs
+-- Purpose: A claim is final only once its event ran (completed_at); until then
+-- it is a lease a redelivery can take over if the claiming worker died
+
+ALTER TABLE "processed_jobs" ADD COLUMN IF NOT EXISTS "lease_expires_at" timestamp with time zone;
+ALTER TABLE "processed_jobs" ADD COLUMN IF NOT EXISTS "completed_at" timestamp with time zone;
+
+-- Claims taken before leases existed keep their old meaning (final)
+UPDATE "processed_jobs" SET "completed_at" = "created_at" WHERE "completed_at" IS NULL AND "lease_expires_at" IS NULL;
@@ -0,0 +1,6 @@
+-- Migration: Batch priority
+-- Purpose: Map and Parallel batches record the priority of the job that
+-- started them, so the scheduler's recovery and timeout jobs for the batch go
+-- back on that job's stream instead of the default one
+
+ALTER TABLE "batch_operations" ADD COLUMN IF NOT EXISTS "priority" text NOT NULL DEFAULT 'default';
@@ -1,4 +1,4 @@
-import { pgTable, serial, text, jsonb, timestamp, uuid, integer, bigserial, index, boolean, unique, primaryKey } from 'drizzle-orm/pg-core';
+import { pgTable, serial, text, jsonb, timestamp, uuid, integer, bigint, bigserial, doublePrecision, index, boolean, unique, primaryKey } from 'drizzle-orm/pg-core';
 
 // =============================================================================
 // WORKFLOWS - The flow definitions (nodes + edges)
@@ -21,7 +21,8 @@ export const workflows = pgTable('workflows', {
   scheduleTimezone: text('schedule_timezone').default('UTC'),
   scheduleInputData: jsonb('schedule_input_data'),  // Static input for scheduled runs
   scheduleNextRun: timestamp('schedule_next_run', { withTimezone: true }),  // Pre-computed next run time
-  scheduleOverlapMode: text('schedule_overlap_mode').default('skip'),  // 'skip', 'queue_one', 'parallel'
+  scheduleOverlapMode: text('schedule_overlap_mode').default('skip'),  // 'skip', 'queue', 'queue_one', 'parallel'
+  scheduleCatchupMode: text('schedule_catchup_mode').default('skip'),  // Missed runs: 'skip', 'run_once', 'run_all'
   
   createdAt: timestamp('created_at').defaultNow(),
   updatedAt: timestamp('updated_at').defaultNow(),
@@ -33,6 +34,28 @@ export const workflows = pgTable('workflows', {
   index('idx_workflows_active_version').on(table.activeVersionId)
 ]);
 
+// =============================================================================
+// WORKFLOW SCHEDULES - Additional cron schedules, each with its own input
+// =============================================================================
+// The schedule_* columns on workflows remain the "default" schedule
+export const workflowSchedules = pgTable('workflow_schedules', {
+  id: serial('id').primaryKey(),
+  workflowId: integer('workflow_id').references(() => workflows.id, { onDelete: 'cascade' }).notNull(),
+  name: text('name'),
+  cron: text('cron').notNull(),                  // Cron expression: "0 9 * * 1-5"
+  timezone: text('timezone').default('UTC'),
+  inputData: jsonb('input_data'),                // Static input for runs from this schedule
+  overlapMode: text('overlap_mode').default('skip'),  // 'skip', 'queue', 'queue_one', 'parallel'
+  catchupMode: text('catchup_mode').default('skip'),  // Missed runs: 'skip', 'run_once', 'run_all'
+  enabled: boolean('enabled').default(true).notNull(),
+  nextRun: timestamp('next_run', { withTimezone: true }),  // Pre-computed next run time
+  lastRun: timestamp('last_run', { withTimezone: true }),
+  createdAt: timestamp('created_at').defaultNow()
+}, (table) => [
+  index('idx_workflow_schedules_next_run').on(table.nextRun),
+  index('idx_workflow_schedules_workflow').on(table.workflowId)
+]);
+
 // =============================================================================
 // WORKFLOW VERSIONS - Immutable snapshots of published workflows
 // =============================================================================
@@ -87,6 +110,7 @@ export const workflowRuns = pgTable('workflow_runs', {
   snapshotGraph: jsonb('snapshot_graph').notNull(),
   
   // Run status: pending → running → completed | failed | cancelled | suspended
+  // (cron runs held back by the 'queue' overlap modes start as 'queued')
   status: text('status').notNull().default('pending'),
   
   // Trigger source: 'manual', 'webhook', 'schedule', 'subflow'
@@ -94,6 +118,9 @@ export const workflowRuns = pgTable('workflow_runs', {
   
   // Initial input data (e.g., webhook payload)
   inputData: jsonb('input_data'),
+
+  // workflow_schedules.id for cron runs fired by an extra schedule (null otherwise)
+  scheduleId: integer('schedule_id'),
   
   // Final output (optional, for quick access)
   outputData: jsonb('output_data'),
@@ -109,6 +136,12 @@ export const workflowRuns = pgTable('workflow_runs', {
   
   // Pinned runs are exempt from TTL cleanup
   pinned: boolean('pinned').default(false),
+
+  // Dry runs: jobs carry dry_run, so nodes describe their side effects instead of performing them
+  dryRun: boolean('dry_run').notNull().default(false),
+
+  // Per-node chunk counts kept when the retention sweep prunes stream chunks
+  streamSummary: jsonb('stream_summary'),
   
   createdAt: timestamp('created_at').defaultNow(),
   startedAt: timestamp('started_at'),
@@ -282,6 +315,10 @@ export const batchOperations = pgTable('batch_operations', {
   totalItems: integer('total_items').notNull(),
   concurrencyLimit: integer('concurrency_limit').notNull().default(5),
   failFast: boolean('fail_fast').notNull().default(false),
+  adaptiveConcurrency: boolean('adaptive_concurrency').notNull().default(false),
+  maxConcurrency: integer('max_concurrency'),  // Ceiling for adaptive concurrency
+  streamResults: boolean('stream_results').notNull().default(false),  // Stream each child result as an 'item' chunk
+  priority: text('priority').notNull().default('default'),  // Priority of the job that started the batch (scheduler re-enqueues keep it)
   timeoutMs: integer('timeout_ms'),  // Per-item timeout in milliseconds (null = no timeout)
   
   // The input array (stored for reference)
@@ -300,6 +337,13 @@ export const batchOperations = pgTable('batch_operations', {
   activeCount: integer('active_count').notNull().default(0),    // Currently running children
   completedCount: integer('completed_count').notNull().default(0),
   failedCount: integer('failed_count').notNull().default(0),
+
+  // Adaptive concurrency window (reset each time concurrency_limit is re-tuned)
+  windowCompleted: integer('window_completed').notNull().default(0),
+  windowFailed: integer('window_failed').notNull().default(0),
+  windowStartedAt: timestamp('window_started_at', { withTimezone: true }).notNull().defaultNow(),
+  windowLatencyMs: integer('window_latency_ms'),
+  windowSeq: integer('window_seq').notNull().default(0),
   
   // Status: 'running', 'completed', 'failed', 'cancelled'
   status: text('status').notNull().default('running'),
@@ -361,4 +405,70 @@ export const runAuditLog = pgTable('run_audit_log', {
   index('idx_audit_log_run').on(table.runId),
   index('idx_audit_log_workflow').on(table.workflowId),
   index('idx_audit_log_action').on(table.action)
-]);
\ No newline at end of file
+]);
+
+// =============================================================================
+// DEAD LETTER JOBS - Poison messages the worker couldn't process
+// =============================================================================
+// Written by the worker, then the Redis message is ACKed so it stops cycling.
+// Operators can inspect (and re-enqueue) these by hand.
+export const deadLetterJobs = pgTable('dead_letter_jobs', {
+  id: bigserial('id', { mode: 'number' }).primaryKey(),
+
+  // Where the message came from
+  stream: text('stream').notNull(),
+  messageId: text('message_id').notNull(),
+  groupName: text('group_name').notNull(),
+
+  // Raw payload as received (null if the message had no payload field)
+  payload: text('payload'),
+
+  // Why it was dead-lettered (e.g. parse error)
+  error: text('error').notNull(),
+
+  // How many times Redis delivered the message before we gave up
+  deliveryCount: integer('delivery_count').notNull().default(1),
+
+  createdAt: timestamp('created_at', { withTimezone: true }).defaultNow().notNull()
+}, (table) => [
+  index('idx_dead_letter_jobs_created').on(table.createdAt)
+]);
+
+// =============================================================================
+// PROCESSED JOBS - Idempotency claims for lifecycle events
+// =============================================================================
+// The worker inserts a row before running a lifecycle event that carries an
+// idempotency_key. The claim is a lease until the event completes; after that
+// a second delivery with the same key is skipped.
+export const processedJobs = pgTable('processed_jobs', {
+  idempotencyKey: text('idempotency_key').primaryKey(),
+  runId: uuid('run_id'),
+  nodeId: text('node_id').notNull(),
+  createdAt: timestamp('created_at', { withTimezone: true }).defaultNow().notNull(),
+  // A redelivery may take the claim over after this, unless completedAt is set
+  leaseExpiresAt: timestamp('lease_expires_at', { withTimezone: true }),
+  completedAt: timestamp('completed_at', { withTimezone: true })
+}, (table) => [
+  index('idx_processed_jobs_created').on(table.createdAt)
+]);
+
+// =============================================================================
+// NODE TYPE LATENCY - Per-worker latency summaries per node type
+// =============================================================================
+// Each worker writes one row per node type every NODE_LATENCY_FLUSH_SECS;
+// percentiles are estimated from histogram buckets. Rows older than 7 days are pruned.
+export const nodeTypeLatency = pgTable('node_type_latency', {
+  id: serial('id').primaryKey(),
+  workerId: text('worker_id').notNull(),
+  nodeType: text('node_type').notNull(),        // 'http', 'code', 'llm', ...
+  windowStart: timestamp('window_start', { withTimezone: true }).notNull(),
+  windowEnd: timestamp('window_end', { withTimezone: true }).notNull(),
+  count: bigint('count', { mode: 'number' }).notNull(),
+  meanMs: doublePrecision('mean_ms').notNull(),
+  p50Ms: doublePrecision('p50_ms').notNull(),
+  p95Ms: doublePrecision('p95_ms').notNull(),
+  p99Ms: doublePrecision('p99_ms').notNull(),
+  maxMs: doublePrecision('max_ms').notNull()
+}, (table) => [
+  index('idx_node_type_latency_type_end').on(table.nodeType, table.windowEnd)
+]);
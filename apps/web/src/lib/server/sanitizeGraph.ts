@@ -18,6 +18,8 @@ export function sanitizeGraph(graph: any) {
 			...(data.mapVersionNumber ? { mapVersionNumber: data.mapVersionNumber } : {}),
 			...(data.mapConcurrency ? { mapConcurrency: data.mapConcurrency } : {}),
 			...(data.mapFailFast !== undefined ? { mapFailFast: data.mapFailFast } : {}),
+			...(data.mapAdaptiveConcurrency !== undefined ? { mapAdaptiveConcurrency: data.mapAdaptiveConcurrency } : {}),
+			...(data.mapMaxConcurrency ? { mapMaxConcurrency: data.mapMaxConcurrency } : {}),
 			...(data.mapInputArray ? { mapInputArray: data.mapInputArray } : {}),
 		};
 	};
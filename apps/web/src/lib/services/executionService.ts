@@ -70,7 +70,8 @@ export function buildPayload(node: AppNode): WorkerJob | null {
 				type: 'CODE',
 				data: {
 					code: node.data.code || '',
-					inputs: finalInputs
+					inputs: finalInputs,
+					timeout_ms: node.data.timeoutMs ?? null
 				}
 			},
 			max_retries: 3
@@ -16,7 +16,7 @@ interface StreamChunk {
     run_id: string;
     node_id: string;
     chunk_index: number;
-    chunk_type: 'progress' | 'data' | 'token' | 'error' | 'complete';
+    chunk_type: 'progress' | 'data' | 'token' | 'error' | 'complete' | 'item';
     content: string;
     timestamp: number;
 }
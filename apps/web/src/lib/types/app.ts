@@ -56,6 +56,8 @@ export type AppNodeData = {
     mapInputArray?: string;          // Expression: "{{prev.items}}" or literal JSON array
     mapConcurrency?: number;         // Max parallel executions (1-50, default 5)
     mapFailFast?: boolean;           // If true, stops on first failure
+    mapAdaptiveConcurrency?: boolean; // Re-tune concurrency from error rate and latency
+    mapMaxConcurrency?: number;      // Ceiling for adaptive concurrency (default 4x, max 200)
     
     // Map Node Progress (updated via SSE)
     mapProgress?: number;            // 0-1 progress
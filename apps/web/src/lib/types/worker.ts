@@ -5,13 +5,22 @@
 export interface CodeNodeData {
 	code: string;
 	inputs?: any;
+	timeout_ms?: number | null;
 }
 
 export interface DelayNodeData {
-	duration_ms: number;
+	/** Ignored when `until` is set */
+	duration_ms?: number;
 	duration_str?: string;
+	/** Wait until a point in time instead of for a duration */
+	until?: UntilSpec;
 }
 
+/** When a calendar-aware delay ends */
+export type UntilSpec =
+	| { type: "at", value: { time: string } }
+	| { type: "cron", value: { expression: string; timezone?: string } };
+
 export interface DelayResumeData {
 	original_delay_ms: number;
 }
@@ -23,6 +32,44 @@ export interface ExecutionResult {
 	body: any;
 	timestamp: number;
 	duration_ms: number;
+	/** Non-fatal issues (e.g. clamped temperature, truncated response) */
+	warnings?: NodeWarning[];
+}
+
+export interface NodeWarning {
+	code: string;
+	message: string;
+}
+
+/** What a suspended node is waiting for. */
+export enum SuspensionKind {
+	Webhook = "webhook",
+	Delay = "delay",
+	DbWait = "db_wait",
+	SubFlow = "sub_flow",
+	Map = "map",
+	Parallel = "parallel",
+	Loop = "loop",
+}
+
+/** What resumes a suspended node. */
+export enum ResumeVia {
+	Webhook = "webhook",
+	Scheduler = "scheduler",
+	ChildRun = "child_run",
+	ChildRuns = "child_runs",
+	Branches = "branches",
+	Iterations = "iterations",
+}
+
+/** Uniform description of a suspension, carried as `suspension` in every NODE_SUSPENDED event */
+export interface Suspension {
+	kind: SuspensionKind;
+	resume_via: ResumeVia;
+	/** RFC 3339; absent when the wait has no deadline */
+	expires_at?: string;
+	/** Node-specific details (resume token, child run, batch size, ...) */
+	detail: any;
 }
 
 export enum HttpMethod {
@@ -33,21 +80,164 @@ export enum HttpMethod {
 	PATCH = "PATCH",
 }
 
+/** How the HTTP node serializes its body. */
+export enum HttpBodyEncoding {
+	/** `application/json` (default) */
+	Json = "json",
+	/** `application/x-www-form-urlencoded` from a flat JSON object */
+	Form = "form",
+	/** `multipart/form-data` from a JSON object; `{ filename, content, content_type? }` values become file parts */
+	Multipart = "multipart",
+	/** String body sent as-is with `content_type` */
+	Raw = "raw",
+}
+
+/** Values may be {{$env.NAME}} references, resolved by the worker. */
+export type HttpAuth =
+	| { type: "basic", value: { username: string; password: string } }
+	| { type: "bearer", value: { token: string } }
+	| { type: "api_key", value: { header: string; value: string } };
+
+/** Redis string key, or the node ID whose streamed `data`/`token` chunks become the body */
+export type BodySource =
+	| { type: "redis_key", value: string }
+	| { type: "node_stream", value: string };
+
 export interface HttpNodeData {
 	url: string;
 	method: HttpMethod;
 	headers?: Record<string, string>;
+	/** Merged into the URL's query string (URL-encoded by the worker) */
+	query?: Record<string, string>;
 	body?: any;
+	/** Defaults to json */
+	body_encoding?: HttpBodyEncoding;
+	/** Stream the body from here instead of `body` (not with `paginate`) */
+	body_source?: BodySource;
+	/** Content-Type override (raw bodies default to text/plain) */
+	content_type?: string;
+	auth?: HttpAuth;
+	/** In-node retries for connection failures, separate from `max_retries` */
+	connect_retries?: number;
+	/** Follow pages and return `{ items, pages, truncated }` */
+	paginate?: PaginateConfig;
+	/** GET only: serve from a Redis cache for this long after a 2xx (not for `no-store` responses) */
+	cache_ttl_ms?: number;
+}
+
+/** Where the next page comes from; paths may start with `$.` */
+export type PageCursor =
+	| { type: "cursor", value: { path: string; param: string } }
+	| { type: "next_url", value: { path: string } }
+	| { type: "link_header" };
+
+export interface PaginateConfig {
+	next: PageCursor;
+	/** Array of items in each page; defaults to the whole body */
+	items_path?: string;
+	/** Defaults to 10 */
+	max_pages?: number;
 }
 
 export interface WebhookResumeData {
 	resume_token: string;
 	payload: any;
+	/** Request body as received, checked against `signature` */
+	raw_body?: string;
+	/** `sha256=<hex>` HMAC of `raw_body` */
+	signature?: string;
 }
 
 export interface WebhookWaitData {
 	description?: string;
 	timeout_ms: number;
+	/** Resumes must be HMAC-SHA256 signed with this (may be {{$env.NAME}}) */
+	signing_secret?: string;
+	/** One resume token per outcome; the one called becomes the output handle */
+	outcomes?: string[];
+}
+
+export interface DbWaitNodeData {
+	query: string;
+	params?: any[];
+	poll_interval_ms?: number;
+	timeout_ms?: number;
+	description?: string;
+}
+
+export interface DbWaitResumeData {
+	row: any;
+}
+
+export enum JoinMode {
+	All = "all",
+	Any = "any",
+	Race = "race",
+}
+
+export interface ParallelNodeData {
+	branches: string[];
+	join_mode?: JoinMode;
+}
+
+export interface LoopNodeData {
+	/** Node id (in this workflow) run on each iteration */
+	body: string;
+	/** JS expression over `output` and `iteration`; the loop ends once it's truthy */
+	condition: string;
+	/** Iterations before the loop ends anyway (capped by LOOP_MAX_ITERATIONS) */
+	max_iterations?: number;
+}
+
+export interface AggregateSource {
+	node_id: string;
+	key?: string;
+}
+
+export interface AggregateNodeData {
+	sources: AggregateSource[];
+}
+
+export interface TransformNodeData {
+	/** jq filter, e.g. `{id, name: .user.full_name}` */
+	expression: string;
+	/** The JSON the filter runs on (`.`) */
+	input?: any;
+}
+
+/** A boolean gate: follows the "true" or "false" handle */
+export interface ConditionalNodeData {
+	/** JS expression over `input`, e.g. `input.status === 200`; truthy takes "true" */
+	expression: string;
+	/** The value the expression sees as `input` */
+	input?: any;
+}
+
+export interface EmailNodeData {
+	to: string[];
+	cc?: string[];
+	/** `{{...}}` templates (e.g. `{{$trigger.name}}`) are resolved against the run */
+	subject: string;
+	body: string;
+	/** Send `body` as text/html instead of text/plain */
+	is_html?: boolean;
+}
+
+export enum S3Operation {
+	Get = "get",
+	Put = "put",
+	Delete = "delete",
+}
+
+/** One object in the S3-compatible store configured with S3_* */
+export interface S3NodeData {
+	operation: S3Operation;
+	bucket: string;
+	key: string;
+	/** Put only: a string is stored as text, a `{ _binary: true, base64 }` value as its bytes, anything else as JSON */
+	body?: any;
+	/** Put only: overrides the Content-Type derived from `body` */
+	content_type?: string;
 }
 
 export type NodeType = 
@@ -56,7 +246,23 @@ export type NodeType =
 	| { type: "DELAY", data: DelayNodeData }
 	| { type: "DELAYRESUME", data: DelayResumeData }
 	| { type: "WEBHOOKWAIT", data: WebhookWaitData }
-	| { type: "WEBHOOKRESUME", data: WebhookResumeData };
+	| { type: "WEBHOOKRESUME", data: WebhookResumeData }
+	| { type: "DBWAIT", data: DbWaitNodeData }
+	| { type: "DBWAITRESUME", data: DbWaitResumeData }
+	| { type: "PARALLEL", data: ParallelNodeData }
+	| { type: "LOOP", data: LoopNodeData }
+	| { type: "AGGREGATE", data: AggregateNodeData }
+	| { type: "TRANSFORM", data: TransformNodeData }
+	| { type: "CONDITIONAL", data: ConditionalNodeData }
+	| { type: "EMAIL", data: EmailNodeData }
+	| { type: "S3", data: S3NodeData };
+
+/** Which job stream a job is enqueued on */
+export enum JobPriority {
+	High = "high",
+	Default = "default",
+	Low = "low",
+}
 
 export interface WorkerJob {
 	id: string;
@@ -64,5 +270,15 @@ export interface WorkerJob {
 	node: NodeType;
 	retry_count?: number;
 	max_retries: number;
+	/** Total time allowed across all attempts (ms since the first) */
+	deadline_ms?: number;
+	/** W3C traceparent/tracestate of whatever enqueued the job */
+	trace_context?: Record<string, string>;
+	/** Describe what the node would do instead of doing it */
+	dry_run?: boolean;
+	/** Picks the stream the job (and its retries) are enqueued on */
+	priority?: JobPriority;
+	/** Run-scoped values for `{{$env.NAME}}`, consulted before the worker's environment (never logged) */
+	run_env?: Record<string, string>;
 }
 
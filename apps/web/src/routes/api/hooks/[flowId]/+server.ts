@@ -321,7 +321,8 @@ function buildJobFromNode(
                     inputs: {
                         ...node.data.inputs,
                         $webhook: webhookInput  // Inject webhook payload
-                    }
+                    },
+                    timeout_ms: node.data.timeoutMs ?? null
                 }
             },
             retry_count: 0,
@@ -1,6 +1,8 @@
 import { json } from '@sveltejs/kit';
 import Redis from 'ioredis';
+import { createHmac, timingSafeEqual } from 'node:crypto';
 import { db } from '$lib/server/db';
+import { getSecretsMap } from '$lib/server/secretsCache';
 import { suspensions, runEvents, workflowRuns } from '$lib/server/db/schema';
 import { eq, and, isNull } from 'drizzle-orm';
 import { REDIS_STREAMS, EVENT_TYPES } from '@swiftgrid/shared';
@@ -8,6 +10,36 @@ import { env } from '$env/dynamic/private';
 
 const redis = new Redis(env.REDIS_URL ?? 'redis://127.0.0.1:6379');
 
+/**
+ * Resolve a signing secret's {{$env.KEY}} references (secrets first, then the
+ * server environment). Returns null if any reference can't be resolved.
+ */
+async function resolveSigningSecret(secret: string): Promise<string | null> {
+    const secretMap = await getSecretsMap();
+    let missing = false;
+    const resolved = secret.replace(/{{(.*?)}}/g, (match, variablePath) => {
+        const cleanPath = variablePath.trim();
+        if (!cleanPath.startsWith('$env.')) return match;
+        const keyName = cleanPath.replace('$env.', '');
+        const value = secretMap.get(keyName) ?? env[keyName];
+        if (value === undefined) missing = true;
+        return value ?? match;
+    });
+    return missing ? null : resolved;
+}
+
+/**
+ * Whether `signature` (`sha256=<hex>` or bare hex) is the HMAC-SHA256 of the
+ * raw body under `secret`. Same rules as the worker's check; constant-time.
+ */
+function verifySignature(secret: string, body: string, signature: string): boolean {
+    const hex = signature.trim().replace(/^sha256=/, '');
+    if (!/^[0-9a-fA-F]*$/.test(hex) || hex.length % 2 !== 0) return false;
+    const given = Buffer.from(hex, 'hex');
+    const expected = createHmac('sha256', secret).update(body).digest();
+    return given.length === expected.length && timingSafeEqual(given, expected);
+}
+
 /**
  * POST /api/hooks/resume/[token]
  * 
@@ -55,35 +87,61 @@ export async function POST({ params, request, getClientAddress }) {
         }, { status: 409 });
     }
     
-    // 4. Parse the webhook payload
+    // 4. Parse the webhook payload (keeping the raw text: signatures cover the exact bytes)
     let payload: any = {};
     const contentType = request.headers.get('content-type') || '';
+    const bodyText = await request.text();
     
     if (contentType.includes('application/json')) {
         try {
-            payload = await request.json();
+            payload = bodyText ? JSON.parse(bodyText) : {};
         } catch {
             return json({ error: 'Invalid JSON body' }, { status: 400 });
         }
     } else {
         // For non-JSON, store as text
-        payload = { raw: await request.text() };
+        payload = { raw: bodyText };
+    }
+    
+    const signature = request.headers.get('x-webhook-signature')
+        || request.headers.get('x-hub-signature-256')  // GitHub format
+        || request.headers.get('x-signature-256');     // Alternative
+    
+    // 5. Verify the signature before touching the suspension, so a forged or
+    //    unsigned call can't consume the token ahead of the genuine one
+    const signingSecret = (suspension.executionContext as any)?.signing_secret;
+    if (typeof signingSecret === 'string' && signingSecret) {
+        const secret = await resolveSigningSecret(signingSecret);
+        if (secret === null) {
+            return json({ error: 'Signing secret is not configured' }, { status: 500 });
+        }
+        if (!signature) {
+            return json({ error: 'Missing webhook signature' }, { status: 401 });
+        }
+        if (!verifySignature(secret, bodyText, signature)) {
+            return json({ error: 'Invalid webhook signature' }, { status: 401 });
+        }
     }
     
     const clientIp = getClientAddress();
     
     console.log(`Resume: Token ${token.slice(0, 8)}... for run ${suspension.runId}`);
     
-    // 5. Mark suspension as resumed
-    await db.update(suspensions)
+    // 6. Mark suspension as resumed (only if a concurrent call hasn't already)
+    const [claimed] = await db.update(suspensions)
         .set({
             resumedAt: new Date(),
             resumedBy: clientIp,
             resumePayload: payload
         })
-        .where(eq(suspensions.id, suspension.id));
+        .where(and(eq(suspensions.id, suspension.id), isNull(suspensions.resumedAt)))
+        .returning({ id: suspensions.id });
+    
+    if (!claimed) {
+        return json({ error: 'Suspension not found or already resumed' }, { status: 404 });
+    }
     
-    // 6. Log NODE_RESUMED event
+    // 7. Log NODE_RESUMED event
     await db.insert(runEvents).values({
         runId: suspension.runId,
         nodeId: suspension.nodeId,
@@ -96,7 +154,7 @@ export async function POST({ params, request, getClientAddress }) {
         }
     });
     
-    // 7. Create a WebhookResume job to continue the workflow
+    // 8. Create a WebhookResume job to continue the workflow
     const resumeJob = {
         id: suspension.nodeId,
         run_id: suspension.runId,
@@ -104,14 +162,16 @@ export async function POST({ params, request, getClientAddress }) {
             type: 'WEBHOOKRESUME',
             data: {
                 resume_token: token,
-                payload: payload
+                payload: payload,
+                raw_body: bodyText,
+                signature: signature ?? undefined
             }
         },
         retry_count: 0,
         max_retries: 0
     };
     
-    // 8. Push to Redis queue
+    // 9. Push to Redis queue
     await redis.xadd(
         REDIS_STREAMS.JOBS,
         '*',
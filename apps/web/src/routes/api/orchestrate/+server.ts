@@ -19,6 +19,13 @@ async function evaluateRouterForRun(
     routerNode: any,
     nodeOutputs: Map<string, any>
 ): Promise<{ firedOutputs: string[] } | null> {
+    // The worker already evaluated the conditions (router `evaluate` mode)
+    const evaluated = nodeOutputs.get(nodeId);
+    if (evaluated?.evaluated && Array.isArray(evaluated.matched)) {
+        console.log(`Router ${nodeId}: evaluated by worker, fired: [${evaluated.matched.join(', ')}]`);
+        return { firedOutputs: evaluated.matched };
+    }
+    
     // Get the router's config from the node
     const routeByRaw = routerNode.data.routeBy || '';
     const conditions = routerNode.data.conditions || [];
@@ -156,12 +163,18 @@ export async function POST({ request }) {
         ));
     
     const nodeOutputs = new Map<string, any>();
+    // Output handle each node chose. The worker logs it beside the result
+    // (code nodes strip `__route` out of their output), so read it from there
+    const nodeRoutes = new Map<string, string>();
     for (const event of completedEvents) {
         if (event.nodeId && event.payload && typeof event.payload === 'object') {
-            const payload = event.payload as { result?: any };
+            const payload = event.payload as { result?: any; route_to?: string | null };
             if (payload.result !== undefined) {
                 nodeOutputs.set(event.nodeId, payload.result);
             }
+            if (typeof payload.route_to === 'string') {
+                nodeRoutes.set(event.nodeId, payload.route_to);
+            }
         }
     }
     
@@ -182,6 +195,13 @@ export async function POST({ request }) {
         }
     }
     
+    // If the completed node was a Conditional, follow only the handle it chose
+    if (completedNode?.type === 'conditional') {
+        const routeTo = nodeOutputs.get(nodeId)?.route_to === 'true' ? 'true' : 'false';
+        dependentEdges = dependentEdges.filter(e => e.sourceHandle === routeTo);
+        console.log(`Conditional ${nodeId} routing to '${routeTo}' handle`);
+    }
+    
     // If the completed node was a SubFlow, route to success or error handle based on child result
     if (completedNode?.type === 'subflow') {
         const nodeOutput = nodeOutputs.get(nodeId);
@@ -219,6 +239,51 @@ export async function POST({ request }) {
         }
     }
     
+    // If the completed node was a Parallel, its branches already ran (the worker
+    // starts them itself); follow the success or error handle of the join
+    if (completedNode?.type === 'parallel') {
+        const branches = new Set<string>(completedNode.data?.branches || []);
+        const routeTo = nodeOutputs.get(nodeId)?.route_to;
+        dependentEdges = dependentEdges.filter(e => !branches.has(e.target));
+        if (routeTo === 'error') {
+            dependentEdges = dependentEdges.filter(e => e.sourceHandle === 'error');
+            console.log(`Parallel ${nodeId} routing to error handle`);
+        } else {
+            dependentEdges = dependentEdges.filter(e => !e.sourceHandle || e.sourceHandle === 'success');
+            console.log(`Parallel ${nodeId} routing to success handle`);
+        }
+    }
+    
+    // If the completed node was a Loop, its body already ran (the worker runs
+    // each iteration itself); follow the success or error handle
+    if (completedNode?.type === 'loop') {
+        const routeTo = nodeOutputs.get(nodeId)?.route_to;
+        dependentEdges = dependentEdges.filter(e => e.target !== completedNode.data?.body);
+        if (routeTo === 'error') {
+            dependentEdges = dependentEdges.filter(e => e.sourceHandle === 'error');
+            console.log(`Loop ${nodeId} routing to error handle`);
+        } else {
+            dependentEdges = dependentEdges.filter(e => !e.sourceHandle || e.sourceHandle === 'success');
+            console.log(`Loop ${nodeId} routing to success handle`);
+        }
+    }
+    
+    // If the completed node was a Webhook Wait with named outcomes, follow the
+    // handle of the outcome that resumed it
+    if (completedNode?.type === 'webhook-wait' && completedNode.data?.outcomes?.length) {
+        const outcome = nodeOutputs.get(nodeId)?.route_to;
+        dependentEdges = dependentEdges.filter(e => !e.sourceHandle || e.sourceHandle === outcome);
+        console.log(`Webhook Wait ${nodeId} routing to outcome '${outcome}'`);
+    }
+    
+    // If the completed node was a Code node that returned `__route`, follow
+    // that handle (plus any unlabelled edges); without one, every edge fires
+    if (completedNode?.type === 'code' && nodeRoutes.has(nodeId)) {
+        const routeTo = nodeRoutes.get(nodeId);
+        dependentEdges = dependentEdges.filter(e => !e.sourceHandle || e.sourceHandle === routeTo);
+        console.log(`Code ${nodeId} routing to '${routeTo}' handle`);
+    }
+    
     const nextNodeIds = dependentEdges.map(e => e.target);
     
     if (nextNodeIds.length === 0) {
@@ -247,7 +312,13 @@ export async function POST({ request }) {
         console.log(`Orchestrator: Run ${runId} - trigger: ${run.trigger}, parentRunId: ${run.parentRunId}, parentNodeId: ${run.parentNodeId}`);
         
         if (allDone) {
-            const hasFailed = failedNodeIds.size > 0;
+            // Branches an Any/Race Parallel node joined past don't fail the run
+            const toleratedFailures = new Set<string>(
+                nodes
+                    .filter(n => n.type === 'parallel' && nodeOutputs.get(n.id)?.route_to === 'success')
+                    .flatMap(n => n.data?.branches || [])
+            );
+            const hasFailed = [...failedNodeIds].some(id => !toleratedFailures.has(id));
             const finalStatus = hasFailed ? 'failed' : 'completed';
             
             // Collect final output from leaf nodes
@@ -338,7 +409,7 @@ export async function POST({ request }) {
                 
                 console.log(`Orchestrator: Resuming parent run ${run.parentRunId} node ${run.parentNodeId}`);
                 
-                // Look up the suspension to get output_path for mapping and retry info
+                // Look up the suspension so it can be marked resolved
                 const [suspension] = await db.select()
                     .from(suspensions)
                     .where(and(
@@ -351,81 +422,12 @@ export async function POST({ request }) {
                 
                 const context = (suspension?.executionContext || {}) as {
                     output_path?: string;
-                    max_retries?: number;
                     retry_count?: number;
-                    workflow_id?: number;
-                    version_id?: string;
-                    input?: any;
-                    timeout_ms?: number;
-                    depth_limit?: number;
-                    fail_on_error?: boolean;
                 };
-                
-                // Check if we should retry on failure
-                const maxRetries = context.max_retries || 0;
+                // Retries (max_retries/retry_count in the context) are handled by the
+                // worker when it processes the resume job
                 const retryCount = context.retry_count || 0;
-                
-                if (hasFailed && retryCount < maxRetries && suspension) {
-                    // Retry the sub-flow
-                    console.log(`Orchestrator: Retrying sub-flow (attempt ${retryCount + 1}/${maxRetries})`);
-                    
-                    // Update retry count in suspension
-                    const newContext = { ...context, retry_count: retryCount + 1 };
-                    await db.update(suspensions)
-                        .set({ executionContext: newContext })
-                        .where(eq(suspensions.id, suspension.id));
-                    
-                    // Push a new SubFlow job to spawn another child
-                    const retryJob = {
-                        id: run.parentNodeId,
-                        run_id: run.parentRunId,
-                        node: {
-                            type: 'SUBFLOW',
-                            data: {
-                                workflow_id: context.workflow_id,
-                                version_id: context.version_id || null,
-                                input: context.input,
-                                fail_on_error: context.fail_on_error || false,
-                                current_depth: run.depth || 0,
-                                depth_limit: context.depth_limit || 10,
-                                timeout_ms: context.timeout_ms || 0,
-                                output_path: context.output_path || null,
-                                max_retries: 0 // Don't nest retries
-                            }
-                        },
-                        retry_count: retryCount + 1,
-                        max_retries: 0
-                    };
-                    
-                    await redis.xadd(
-                        REDIS_STREAMS.JOBS,
-                        '*',
-                        'payload',
-                        JSON.stringify(retryJob)
-                    );
-                    
-                    return json({ message: 'Sub-flow retry scheduled', retry: retryCount + 1 });
-                }
-                
-                // Apply output mapping if configured
-                let finalOutput = outputData;
-                if (!hasFailed && context.output_path && outputData) {
-                    // Navigate the path (e.g., "result.data.items")
-                    const pathParts = context.output_path.split('.');
-                    let value: any = outputData;
-                    for (const part of pathParts) {
-                        if (value && typeof value === 'object' && part in value) {
-                            value = value[part];
-                        } else {
-                            console.warn(`Output mapping: path "${context.output_path}" not found in output`);
-                            value = outputData; // Fall back to full output
-                            break;
-                        }
-                    }
-                    finalOutput = value;
-                    console.log(`Orchestrator: Applied output mapping "${context.output_path}"`);
-                }
-                
+
                 // Mark suspension as resolved
                 if (suspension) {
                     await db.update(suspensions)
@@ -445,9 +447,11 @@ export async function POST({ request }) {
                         type: 'SUBFLOWRESUME',
                         data: {
                             child_run_id: runId,
-                            output: finalOutput,
+                            output: outputData,
                             success: !hasFailed,
-                            error: hasFailed ? 'Sub-flow failed' : null
+                            error: hasFailed ? 'Sub-flow failed' : null,
+                            // Extracted by the worker (null + warning if it doesn't resolve)
+                            output_path: context.output_path || null
                         }
                     },
                     retry_count: 0,
@@ -528,7 +532,9 @@ export async function POST({ request }) {
     const scheduledNodeIds: string[] = [];
     
     for (const node of nodesToSchedule) {
-        const job = buildJobFromNode(node, runId, secretMap, nodeOutputs, run.inputData, run.depth || 0);
+        const built = buildJobFromNode(node, runId, secretMap, nodeOutputs, run.inputData, run.depth || 0);
+        // Dry runs stay dry all the way down the graph
+        const job = built && run.dryRun ? { ...built, dry_run: true } : built;
         if (job) {
             // Log NODE_SCHEDULED event
             await db.insert(runEvents).values({
@@ -651,7 +657,8 @@ function buildJobFromNode(
                 type: 'CODE',
                 data: {
                     code: node.data.code || '',
-                    inputs: finalInputs
+                    inputs: finalInputs,
+                    timeout_ms: node.data.timeoutMs ?? null
                 }
             },
             retry_count: 0,
@@ -673,6 +680,14 @@ function buildJobFromNode(
             }
         }
         
+        let finalQuery: Record<string, string> | undefined;
+        if (node.data.query) {
+            finalQuery = {};
+            for (const [key, val] of Object.entries(node.data.query)) {
+                finalQuery[key] = processString(String(val));
+            }
+        }
+        
         let finalBody = node.data.body;
         if (finalBody) {
             if (typeof finalBody === 'string') {
@@ -693,7 +708,13 @@ function buildJobFromNode(
                     url: processString(node.data.url),
                     method: node.data.method,
                     headers: finalHeaders,
-                    body: finalBody
+                    query: finalQuery,
+                    body: finalBody,
+                    body_encoding: node.data.bodyEncoding,
+                    content_type: node.data.contentType,
+                    body_source: node.data.bodySource
+                        ? { ...node.data.bodySource, value: processString(node.data.bodySource.value) }
+                        : undefined
                 }
             },
             retry_count: 0,
@@ -709,7 +730,8 @@ function buildJobFromNode(
                 type: 'DELAY',
                 data: {
                     duration_ms: node.data.delayMs || 5000,
-                    duration_str: node.data.delayStr
+                    duration_str: node.data.delayStr,
+                    until: node.data.until
                 }
             },
             retry_count: 0,
@@ -726,7 +748,9 @@ function buildJobFromNode(
                 data: {
                     timeout_ms: node.data.timeoutMs || (7 * 24 * 60 * 60 * 1000),
                     timeout_str: node.data.timeoutStr,
-                    description: node.data.description || 'Wait for external event'
+                    description: node.data.description || 'Wait for external event',
+                    signing_secret: node.data.signingSecret,
+                    outcomes: node.data.outcomes
                 }
             },
             retry_count: 0,
@@ -736,7 +760,8 @@ function buildJobFromNode(
     
     if (node.type === 'router') {
         // Router node: just send the config to the worker
-        // The actual routing evaluation happens in the orchestrator when the node completes
+        // The actual routing evaluation happens in the orchestrator when the node completes,
+        // unless `evaluate` asks the worker to do it (its result then carries `matched`)
         const conditions = node.data.conditions || [];
         const defaultOutput = node.data.defaultOutput || '';
         const mode = node.data.routerMode || 'first_match';
@@ -754,7 +779,8 @@ function buildJobFromNode(
                         expression: c.expression
                     })),
                     default_output: defaultOutput,
-                    mode: mode
+                    mode: mode,
+                    evaluate: node.data.evaluate || false
                 }
             },
             retry_count: 0,
@@ -799,7 +825,7 @@ function buildJobFromNode(
                 }
             },
             retry_count: 0,
-            max_retries: 3
+            max_retries: env.LLM_MAX_RETRIES ? Number(env.LLM_MAX_RETRIES) : 5
         };
     }
     
@@ -886,6 +912,9 @@ function buildJobFromNode(
                     version_id: node.data.mapVersionId || null,
                     items: items,
                     concurrency: node.data.mapConcurrency || 5,
+                    adaptive_concurrency: node.data.mapAdaptiveConcurrency || false,
+                    max_concurrency: node.data.mapMaxConcurrency || null,
+                    stream_results: node.data.mapStreamResults || false,
                     fail_fast: node.data.mapFailFast || false,
                     timeout_ms: node.data.mapTimeoutMs || null,
                     current_depth: runDepth,
@@ -897,6 +926,168 @@ function buildJobFromNode(
         };
     }
     
+    if (node.type === 'parallel') {
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'PARALLEL',
+                data: {
+                    branches: node.data.branches || [],
+                    join_mode: node.data.joinMode || 'all'
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'loop') {
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'LOOP',
+                data: {
+                    body: node.data.body || '',
+                    condition: node.data.condition || '',
+                    max_iterations: node.data.maxIterations || 10
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'transform') {
+        let finalInput = node.data.input;
+        if (finalInput !== undefined) {
+            const inputStr = typeof finalInput === 'string'
+                ? finalInput
+                : JSON.stringify(finalInput);
+            const resolvedStr = processString(inputStr);
+            try {
+                finalInput = JSON.parse(resolvedStr);
+            } catch {
+                finalInput = resolvedStr;
+            }
+        }
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'TRANSFORM',
+                data: {
+                    expression: node.data.expression || '.',
+                    input: finalInput ?? null
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'conditional') {
+        let finalInput = node.data.input;
+        if (finalInput !== undefined) {
+            const inputStr = typeof finalInput === 'string'
+                ? finalInput
+                : JSON.stringify(finalInput);
+            const resolvedStr = processString(inputStr);
+            try {
+                finalInput = JSON.parse(resolvedStr);
+            } catch {
+                finalInput = resolvedStr;
+            }
+        }
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'CONDITIONAL',
+                data: {
+                    expression: node.data.expression || '',
+                    input: finalInput ?? null
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'email') {
+        const addresses = (value: unknown): string[] =>
+            (Array.isArray(value) ? value : String(value ?? '').split(','))
+                .map((a) => processString(String(a)).trim())
+                .filter(Boolean);
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'EMAIL',
+                data: {
+                    to: addresses(node.data.to),
+                    cc: addresses(node.data.cc),
+                    subject: processString(node.data.subject || ''),
+                    body: processString(node.data.body || ''),
+                    is_html: !!node.data.isHtml
+                }
+            },
+            retry_count: 0,
+            max_retries: 3
+        };
+    }
+    
+    if (node.type === 's3') {
+        // A body that resolves to JSON (e.g. `{{httpNode}}` with a binary response) is sent as that value
+        let body = node.data.body;
+        if (body !== undefined && body !== null && body !== '') {
+            const resolved = processString(typeof body === 'string' ? body : JSON.stringify(body));
+            try {
+                body = /^\s*[[{]/.test(resolved) ? JSON.parse(resolved) : resolved;
+            } catch {
+                body = resolved;
+            }
+        } else {
+            body = undefined;
+        }
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'S3',
+                data: {
+                    operation: node.data.operation || 'get',
+                    bucket: processString(node.data.bucket || ''),
+                    key: processString(node.data.key || ''),
+                    body: node.data.operation === 'put' ? body : undefined,
+                    content_type: node.data.contentType || undefined
+                }
+            },
+            retry_count: 0,
+            max_retries: 3
+        };
+    }
+    
+    if (node.type === 'aggregate') {
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'AGGREGATE',
+                data: {
+                    sources: node.data.sources || []
+                }
+            },
+            retry_count: 0,
+            max_retries: 3
+        };
+    }
+    
     return null;
 }
 
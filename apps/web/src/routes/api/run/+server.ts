@@ -100,17 +100,18 @@ export async function POST({ request }) {
 /**
  * Start a full workflow run with event tracking
  */
-async function handleStartRun(body: { startRun: true; workflowId?: number; graph: any; trigger?: string; startFromNode?: string }) {
-    const { workflowId, graph, trigger = 'manual', startFromNode } = body;
+async function handleStartRun(body: { startRun: true; workflowId?: number; graph: any; trigger?: string; startFromNode?: string; dryRun?: boolean }) {
+    const { workflowId, graph, trigger = 'manual', startFromNode, dryRun = false } = body;
     
-    console.log(`Starting new workflow run${startFromNode ? ` from node ${startFromNode}` : ''}...`);
+    console.log(`Starting new workflow run${startFromNode ? ` from node ${startFromNode}` : ''}${dryRun ? ' (dry run)' : ''}...`);
     
     // 1. Create the run record with snapshot
     const [run] = await db.insert(workflowRuns).values({
         workflowId: workflowId ?? null,
         snapshotGraph: graph,
         status: 'pending',
-        trigger
+        trigger,
+        dryRun
     }).returning();
     
     console.log(`Created run: ${run.id}`);
@@ -119,7 +120,7 @@ async function handleStartRun(body: { startRun: true; workflowId?: number; graph
     await db.insert(runEvents).values({
         runId: run.id,
         eventType: EVENT_TYPES.RUN_CREATED,
-        payload: { trigger, nodeCount: graph.nodes?.length ?? 0, startFromNode }
+        payload: { trigger, nodeCount: graph.nodes?.length ?? 0, startFromNode, dryRun }
     });
     
     // 3. Find starting nodes
@@ -158,7 +159,7 @@ async function handleStartRun(body: { startRun: true; workflowId?: number; graph
     
     // 6. Schedule starting nodes
     for (const node of startingNodes) {
-        const job = buildJobFromNode(node, run.id, secretMap);
+        const job = withDryRun(buildJobFromNode(node, run.id, secretMap), dryRun);
         if (job) {
             // Log NODE_SCHEDULED event
             await db.insert(runEvents).values({
@@ -184,6 +185,13 @@ async function handleStartRun(body: { startRun: true; workflowId?: number; graph
     });
 }
 
+/**
+ * Mark a job as part of a dry run (the worker then skips its side effects)
+ */
+function withDryRun(job: object | null, dryRun: boolean): object | null {
+    return job && dryRun ? { ...job, dry_run: true } : job;
+}
+
 /**
  * Build a worker job from a flow node
  */
@@ -225,7 +233,8 @@ function buildJobFromNode(
                 type: 'CODE',
                 data: {
                     code: node.data.code || '',
-                    inputs: finalInputs
+                    inputs: finalInputs,
+                    timeout_ms: node.data.timeoutMs ?? null
                 }
             },
             retry_count: 0,
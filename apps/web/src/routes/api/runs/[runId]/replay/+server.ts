@@ -138,6 +138,7 @@ function buildJobFromNode(node: any, runId: string, inputData: any): any {
           data: {
           code: node.data.code || '',
             inputs: node.data.inputs || null,
+            timeout_ms: node.data.timeoutMs ?? null,
           },
         },
       };
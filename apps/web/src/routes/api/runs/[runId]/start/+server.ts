@@ -71,7 +71,8 @@ export async function POST({ params }) {
     const scheduledNodes: string[] = [];
     
     for (const node of startingNodes) {
-        const job = buildJobFromNode(node, runId, secretMap, triggerData);
+        const built = buildJobFromNode(node, runId, secretMap, triggerData);
+        const job = built && run.dryRun ? { ...built, dry_run: true } : built;
         if (job) {
             // Log NODE_SCHEDULED event
             await db.insert(runEvents).values({
@@ -163,7 +164,8 @@ function buildJobFromNode(
                 type: 'CODE',
                 data: {
                     code: node.data.code || '',
-                    inputs: finalInputs
+                    inputs: finalInputs,
+                    timeout_ms: node.data.timeoutMs ?? null
                 }
             },
             retry_count: 0,
@@ -185,6 +187,14 @@ function buildJobFromNode(
             }
         }
         
+        let finalQuery: Record<string, string> | undefined;
+        if (node.data.query) {
+            finalQuery = {};
+            for (const [key, val] of Object.entries(node.data.query)) {
+                finalQuery[key] = processString(String(val));
+            }
+        }
+        
         let finalBody = node.data.body;
         if (finalBody) {
             if (typeof finalBody === 'string') {
@@ -205,7 +215,13 @@ function buildJobFromNode(
                     url: processString(node.data.url),
                     method: node.data.method,
                     headers: finalHeaders,
-                    body: finalBody
+                    query: finalQuery,
+                    body: finalBody,
+                    body_encoding: node.data.bodyEncoding,
+                    content_type: node.data.contentType,
+                    body_source: node.data.bodySource
+                        ? { ...node.data.bodySource, value: processString(node.data.bodySource.value) }
+                        : undefined
                 }
             },
             retry_count: 0,
@@ -221,7 +237,8 @@ function buildJobFromNode(
                 type: 'DELAY',
                 data: {
                     duration_ms: node.data.delayMs || 5000,
-                    duration_str: node.data.delayStr
+                    duration_str: node.data.delayStr,
+                    until: node.data.until
                 }
             },
             retry_count: 0,
@@ -264,7 +281,7 @@ function buildJobFromNode(
                 }
             },
             retry_count: 0,
-            max_retries: 3
+            max_retries: env.LLM_MAX_RETRIES ? Number(env.LLM_MAX_RETRIES) : 5
         };
     }
     
@@ -282,7 +299,8 @@ function buildJobFromNode(
                         expression: c.expression
                     })),
                     default_output: node.data.defaultOutput || '',
-                    mode: node.data.routerMode || 'first_match'
+                    mode: node.data.routerMode || 'first_match',
+                    evaluate: node.data.evaluate || false
                 }
             },
             retry_count: 0,
@@ -299,7 +317,9 @@ function buildJobFromNode(
                 data: {
                     timeout_ms: node.data.timeoutMs || (7 * 24 * 60 * 60 * 1000),
                     timeout_str: node.data.timeoutStr,
-                    description: node.data.description || 'Wait for external event'
+                    description: node.data.description || 'Wait for external event',
+                    signing_secret: node.data.signingSecret,
+                    outcomes: node.data.outcomes
                 }
             },
             retry_count: 0,
@@ -384,6 +404,9 @@ function buildJobFromNode(
                     version_id: node.data.mapVersionId || null,
                     items: items,
                     concurrency: node.data.mapConcurrency || 5,
+                    adaptive_concurrency: node.data.mapAdaptiveConcurrency || false,
+                    max_concurrency: node.data.mapMaxConcurrency || null,
+                    stream_results: node.data.mapStreamResults || false,
                     fail_fast: node.data.mapFailFast || false,
                     current_depth: 0,
                     depth_limit: 10
@@ -394,6 +417,168 @@ function buildJobFromNode(
         };
     }
     
+    if (node.type === 'parallel') {
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'PARALLEL',
+                data: {
+                    branches: node.data.branches || [],
+                    join_mode: node.data.joinMode || 'all'
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'loop') {
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'LOOP',
+                data: {
+                    body: node.data.body || '',
+                    condition: node.data.condition || '',
+                    max_iterations: node.data.maxIterations || 10
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'transform') {
+        let finalInput = node.data.input;
+        if (finalInput !== undefined) {
+            const inputStr = typeof finalInput === 'string'
+                ? finalInput
+                : JSON.stringify(finalInput);
+            const resolvedStr = processString(inputStr);
+            try {
+                finalInput = JSON.parse(resolvedStr);
+            } catch {
+                finalInput = resolvedStr;
+            }
+        }
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'TRANSFORM',
+                data: {
+                    expression: node.data.expression || '.',
+                    input: finalInput ?? null
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'conditional') {
+        let finalInput = node.data.input;
+        if (finalInput !== undefined) {
+            const inputStr = typeof finalInput === 'string'
+                ? finalInput
+                : JSON.stringify(finalInput);
+            const resolvedStr = processString(inputStr);
+            try {
+                finalInput = JSON.parse(resolvedStr);
+            } catch {
+                finalInput = resolvedStr;
+            }
+        }
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'CONDITIONAL',
+                data: {
+                    expression: node.data.expression || '',
+                    input: finalInput ?? null
+                }
+            },
+            retry_count: 0,
+            max_retries: 0
+        };
+    }
+    
+    if (node.type === 'email') {
+        const addresses = (value: unknown): string[] =>
+            (Array.isArray(value) ? value : String(value ?? '').split(','))
+                .map((a) => processString(String(a)).trim())
+                .filter(Boolean);
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'EMAIL',
+                data: {
+                    to: addresses(node.data.to),
+                    cc: addresses(node.data.cc),
+                    subject: processString(node.data.subject || ''),
+                    body: processString(node.data.body || ''),
+                    is_html: !!node.data.isHtml
+                }
+            },
+            retry_count: 0,
+            max_retries: 3
+        };
+    }
+    
+    if (node.type === 's3') {
+        // A body that resolves to JSON (e.g. `{{httpNode}}` with a binary response) is sent as that value
+        let body = node.data.body;
+        if (body !== undefined && body !== null && body !== '') {
+            const resolved = processString(typeof body === 'string' ? body : JSON.stringify(body));
+            try {
+                body = /^\s*[[{]/.test(resolved) ? JSON.parse(resolved) : resolved;
+            } catch {
+                body = resolved;
+            }
+        } else {
+            body = undefined;
+        }
+
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'S3',
+                data: {
+                    operation: node.data.operation || 'get',
+                    bucket: processString(node.data.bucket || ''),
+                    key: processString(node.data.key || ''),
+                    body: node.data.operation === 'put' ? body : undefined,
+                    content_type: node.data.contentType || undefined
+                }
+            },
+            retry_count: 0,
+            max_retries: 3
+        };
+    }
+    
+    if (node.type === 'aggregate') {
+        return {
+            id: node.id,
+            run_id: runId,
+            node: {
+                type: 'AGGREGATE',
+                data: {
+                    sources: node.data.sources || []
+                }
+            },
+            retry_count: 0,
+            max_retries: 3
+        };
+    }
+    
     console.warn(`Unknown node type: ${node.type}`);
     return null;
 }
@@ -10,6 +10,8 @@ interface WorkerStatus {
 	jobs_processed: number;
 	current_jobs: number;
 	uptime_secs: number;
+	/** Whether this worker currently runs the scheduler */
+	scheduler_leader?: boolean;
 	last_seen: string;
 }
 
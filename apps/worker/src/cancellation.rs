@@ -3,28 +3,61 @@
 //! Provides real-time cancellation of in-flight operations via Redis pub/sub.
 //! When a user cancels a run, a message is published to `cancel:{run_id}` and
 //! all workers processing jobs for that run will abort their operations.
+//! Long delays the run has pending in `swiftgrid_delayed` are removed too, so
+//! they don't fire later and resume a dead run.
+//!
+//! Cancelled run IDs are remembered for `CANCELLED_RUN_TTL`, so a run cancelled
+//! before this worker picked up any of its jobs still starts out cancelled, and
+//! repeated cancels for the same run (double-clicks, retries) are ignored.
+//!
+//! Pub/sub is fire-and-forget: a cancel published while the listener is
+//! reconnecting never arrives. After every reconnect the listener reconciles
+//! from Postgres, cancelling runs that were marked `cancelled` while it was
+//! disconnected.
 
+use sqlx::PgPool;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+/// How long a cancelled run ID is remembered
+const CANCELLED_RUN_TTL: Duration = Duration::from_secs(600);
+
+/// Most cancelled run IDs remembered; the oldest are forgotten first
+const MAX_CANCELLED_RUNS: usize = 10_000;
+
+/// Extra lookback when reconciling after a reconnect, for the time it took to
+/// notice the connection was gone and for clock skew with the database
+const RECONCILE_MARGIN: Duration = Duration::from_secs(30);
+
 /// Registry of active cancellation tokens, keyed by run_id.
 /// Multiple jobs for the same run share the same token.
 pub struct CancellationRegistry {
     tokens: RwLock<HashMap<Uuid, CancellationToken>>,
+    /// Recently cancelled runs and when the cancel arrived
+    cancelled: RwLock<HashMap<Uuid, Instant>>,
+}
+
+impl Default for CancellationRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CancellationRegistry {
     pub fn new() -> Self {
         Self {
             tokens: RwLock::new(HashMap::new()),
+            cancelled: RwLock::new(HashMap::new()),
         }
     }
 
     /// Get or create a cancellation token for a run.
     /// If the run already has a token (from another parallel job), return that.
+    /// A run cancelled before its first job arrived gets a cancelled token.
     pub async fn get_or_create(&self, run_id: Uuid) -> CancellationToken {
         // Fast path: check if token exists
         {
@@ -37,21 +70,41 @@ impl CancellationRegistry {
         // Slow path: acquire write lock and create token
         let mut write = self.tokens.write().await;
         // Double-check after acquiring write lock (another task may have created it)
-        write
-            .entry(run_id)
-            .or_insert_with(CancellationToken::new)
-            .clone()
+        if let Some(token) = write.get(&run_id) {
+            return token.clone();
+        }
+        // Checked under the tokens lock: a cancel either recorded the run
+        // already, or will find this token
+        let token = CancellationToken::new();
+        if self.was_cancelled(&run_id).await {
+            token.cancel();
+        }
+        write.insert(run_id, token.clone());
+        token
     }
 
     /// Cancel all jobs for a run.
-    pub async fn cancel(&self, run_id: &Uuid) {
+    /// Returns false if the run was already cancelled (a duplicate message).
+    pub async fn cancel(&self, run_id: &Uuid) -> bool {
+        {
+            let mut cancelled = self.cancelled.write().await;
+            let now = Instant::now();
+            if cancelled.get(run_id).is_some_and(|at| now.duration_since(*at) < CANCELLED_RUN_TTL) {
+                return false;
+            }
+            cancelled.insert(*run_id, now);
+            prune_cancelled(&mut cancelled, now);
+        }
+
         if let Some(token) = self.tokens.read().await.get(run_id) {
             token.cancel();
-            println!("Cancellation: Signalled cancel for run {}", run_id);
+            tracing::info!(%run_id, "Cancellation: Signalled cancel");
         }
+        true
     }
 
     /// Remove token when run completes (cleanup to prevent memory leak).
+    /// A cancelled run stays remembered so late jobs for it are still skipped.
     pub async fn remove(&self, run_id: &Uuid) {
         self.tokens.write().await.remove(run_id);
     }
@@ -61,7 +114,32 @@ impl CancellationRegistry {
         if let Some(token) = self.tokens.read().await.get(run_id) {
             token.is_cancelled()
         } else {
-            false
+            self.was_cancelled(run_id).await
+        }
+    }
+
+    /// Whether a cancel for the run arrived within `CANCELLED_RUN_TTL`.
+    async fn was_cancelled(&self, run_id: &Uuid) -> bool {
+        self.cancelled
+            .read()
+            .await
+            .get(run_id)
+            .is_some_and(|at| at.elapsed() < CANCELLED_RUN_TTL)
+    }
+}
+
+/// Forget expired cancels, then the oldest ones while over `MAX_CANCELLED_RUNS`.
+fn prune_cancelled(cancelled: &mut HashMap<Uuid, Instant>, now: Instant) {
+    if cancelled.len() <= MAX_CANCELLED_RUNS {
+        return;
+    }
+    cancelled.retain(|_, at| now.duration_since(*at) < CANCELLED_RUN_TTL);
+    if cancelled.len() > MAX_CANCELLED_RUNS {
+        let mut by_age: Vec<(Uuid, Instant)> = cancelled.iter().map(|(id, at)| (*id, *at)).collect();
+        by_age.sort_by_key(|(_, at)| *at);
+        let excess = cancelled.len() - MAX_CANCELLED_RUNS;
+        for (id, _) in by_age.into_iter().take(excess) {
+            cancelled.remove(&id);
         }
     }
 }
@@ -70,18 +148,23 @@ impl CancellationRegistry {
 /// This runs in a background task and cancels tokens when messages arrive.
 pub async fn listen_for_cancellations(
     redis_client: redis::Client,
+    db_pool: PgPool,
     registry: Arc<CancellationRegistry>,
 ) {
     use futures_util::StreamExt;
 
-    println!("Cancellation: Starting pub/sub listener...");
+    tracing::info!("Cancellation: Starting pub/sub listener...");
+
+    // When the listener last stopped receiving messages (None while subscribed)
+    let mut disconnected_at: Option<Instant> = None;
 
     loop {
         // Get a dedicated connection for pub/sub
         let mut pubsub = match redis_client.get_async_pubsub().await {
             Ok(ps) => ps,
             Err(e) => {
-                eprintln!("Cancellation: Failed to connect to Redis pub/sub: {}", e);
+                tracing::error!("Cancellation: Failed to connect to Redis pub/sub: {}", e);
+                disconnected_at.get_or_insert_with(Instant::now);
                 tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
                 continue;
             }
@@ -89,12 +172,19 @@ pub async fn listen_for_cancellations(
 
         // Subscribe to all cancel channels
         if let Err(e) = pubsub.psubscribe("cancel:*").await {
-            eprintln!("Cancellation: Failed to subscribe: {}", e);
+            tracing::error!("Cancellation: Failed to subscribe: {}", e);
+            disconnected_at.get_or_insert_with(Instant::now);
             tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
             continue;
         }
 
-        println!("Cancellation: Subscribed to cancel:* channels");
+        tracing::info!("Cancellation: Subscribed to cancel:* channels");
+
+        // Subscribed again: pick up the cancels published while we weren't
+        // (ones that also arrive as messages are ignored as duplicates)
+        if let Some(since) = disconnected_at.take() {
+            reconcile_missed(&db_pool, &redis_client, &registry, reconcile_window(since.elapsed())).await;
+        }
 
         // Process messages
         let mut stream = pubsub.on_message();
@@ -105,16 +195,123 @@ pub async fn listen_for_cancellations(
             };
 
             // Extract run_id from channel name (cancel:{run_id})
-            if let Some(run_id_str) = channel.strip_prefix("cancel:") {
-                if let Ok(run_id) = Uuid::parse_str(run_id_str) {
-                    registry.cancel(&run_id).await;
+            if let Some(run_id_str) = channel.strip_prefix("cancel:")
+                && let Ok(run_id) = Uuid::parse_str(run_id_str)
+                && !apply_cancel(&redis_client, &registry, run_id).await {
+                    tracing::debug!(%run_id, "Cancellation: Ignoring duplicate cancel");
                 }
-            }
         }
 
         // If we exit the loop, the connection was lost - reconnect
-        eprintln!("Cancellation: Pub/sub connection lost, reconnecting...");
+        disconnected_at = Some(Instant::now());
+        tracing::error!("Cancellation: Pub/sub connection lost, reconnecting...");
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 }
 
+/// Cancel a run's token and remove its pending delays. Returns false for a
+/// duplicate cancel (nothing done).
+async fn apply_cancel(redis_client: &redis::Client, registry: &CancellationRegistry, run_id: Uuid) -> bool {
+    if !registry.cancel(&run_id).await {
+        return false;
+    }
+
+    // Every worker gets the message; the removal is atomic, so only one reports it
+    match crate::nodes::delay::cancel_delayed(redis_client, &run_id.to_string()).await {
+        Ok(0) => {}
+        Ok(removed) => tracing::info!(
+            "Cancellation: Removed {} pending delay(s) for run {}",
+            removed, run_id
+        ),
+        Err(e) => tracing::error!(
+            "Cancellation: Failed to remove pending delays for run {}: {}",
+            run_id, e
+        ),
+    }
+    true
+}
+
+/// How far back to look for cancels missed during a disconnect.
+fn reconcile_window(disconnected_for: Duration) -> Duration {
+    disconnected_for + RECONCILE_MARGIN
+}
+
+/// Cancel the runs marked `cancelled` in the last `window`, whose pub/sub
+/// message may have been published while the listener was disconnected.
+async fn reconcile_missed(
+    db_pool: &PgPool,
+    redis_client: &redis::Client,
+    registry: &CancellationRegistry,
+    window: Duration,
+) {
+    let runs: Vec<Uuid> = match sqlx::query_scalar(
+        "SELECT id FROM workflow_runs WHERE status = 'cancelled' AND completed_at > NOW() - make_interval(secs => $1)",
+    )
+    .bind(window.as_secs_f64())
+    .fetch_all(db_pool)
+    .await
+    {
+        Ok(runs) => runs,
+        Err(e) => {
+            tracing::error!("Cancellation: Failed to reconcile cancels missed while disconnected: {}", e);
+            return;
+        }
+    };
+
+    let mut applied = 0;
+    for run_id in runs {
+        if apply_cancel(redis_client, registry, run_id).await {
+            applied += 1;
+        }
+    }
+    if applied > 0 {
+        tracing::warn!("Cancellation: Applied {} cancel(s) missed while disconnected", applied);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_before_first_job_and_duplicates() {
+        let registry = CancellationRegistry::new();
+        let early = Uuid::new_v4();
+
+        assert!(registry.cancel(&early).await);
+        assert!(!registry.cancel(&early).await, "second cancel is a duplicate");
+        assert!(registry.is_cancelled(&early).await);
+        assert!(registry.get_or_create(early).await.is_cancelled());
+
+        // Still cancelled for jobs arriving after the run's token was removed
+        registry.remove(&early).await;
+        assert!(registry.get_or_create(early).await.is_cancelled());
+
+        let running = Uuid::new_v4();
+        let token = registry.get_or_create(running).await;
+        assert!(!token.is_cancelled());
+        assert!(registry.cancel(&running).await);
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_reconcile_window_covers_disconnect() {
+        assert_eq!(reconcile_window(Duration::ZERO), RECONCILE_MARGIN);
+        assert_eq!(reconcile_window(Duration::from_secs(90)), Duration::from_secs(90) + RECONCILE_MARGIN);
+    }
+
+    #[test]
+    fn test_prune_cancelled_is_bounded() {
+        let now = Instant::now();
+        let mut cancelled: HashMap<Uuid, Instant> = (0..MAX_CANCELLED_RUNS)
+            .map(|i| (Uuid::new_v4(), now - Duration::from_millis(i as u64)))
+            .collect();
+        let oldest = *cancelled.iter().max_by_key(|(_, at)| now - **at).unwrap().0;
+        cancelled.insert(Uuid::new_v4(), now);
+
+        prune_cancelled(&mut cancelled, now);
+        assert_eq!(cancelled.len(), MAX_CANCELLED_RUNS);
+        assert!(!cancelled.contains_key(&oldest));
+    }
+}
@@ -0,0 +1,259 @@
+//! Per-host circuit breaker for outbound HTTP requests.
+//!
+//! When a downstream host keeps failing, retries scheduled by `handle_retry`
+//! would otherwise keep hammering it. After `failure_threshold` consecutive
+//! failures within `window`, the breaker for that host opens and requests fail
+//! fast with 503. Once `cooldown` has elapsed a single half-open probe is let
+//! through; its outcome decides whether the breaker closes or re-opens.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Default consecutive failures before the breaker opens
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// Default window in which failures must occur (60 seconds)
+const DEFAULT_WINDOW_MS: u64 = 60_000;
+
+/// Default time the breaker stays open before a probe (30 seconds)
+const DEFAULT_COOLDOWN_MS: u64 = 30_000;
+
+/// Shared breaker state, keyed by host, with the thresholds read at startup.
+#[derive(Clone, Debug)]
+pub struct CircuitBreakers {
+    states: Arc<RwLock<HashMap<String, BreakerState>>>,
+    config: BreakerConfig,
+}
+
+impl CircuitBreakers {
+    /// Thresholds every breaker in this registry uses
+    pub fn config(&self) -> &BreakerConfig {
+        &self.config
+    }
+}
+
+/// Create an empty breaker registry.
+pub fn new_registry(config: BreakerConfig) -> CircuitBreakers {
+    CircuitBreakers { states: Arc::new(RwLock::new(HashMap::new())), config }
+}
+
+/// Breaker thresholds (configurable via env vars)
+#[derive(Clone, Debug)]
+pub struct BreakerConfig {
+    pub failure_threshold: u32,
+    pub window: Duration,
+    pub cooldown: Duration,
+}
+
+impl BreakerConfig {
+    /// Load from the `HTTP_BREAKER_*` env vars (once, at startup).
+    pub fn from_env() -> Self {
+        Self {
+            failure_threshold: std::env::var("HTTP_BREAKER_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_FAILURE_THRESHOLD),
+            window: Duration::from_millis(
+                std::env::var("HTTP_BREAKER_WINDOW_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_WINDOW_MS),
+            ),
+            cooldown: Duration::from_millis(
+                std::env::var("HTTP_BREAKER_COOLDOWN_MS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_COOLDOWN_MS),
+            ),
+        }
+    }
+}
+
+/// State of a single host's breaker.
+#[derive(Debug, Default, Clone)]
+pub struct BreakerState {
+    consecutive_failures: u32,
+    window_start: Option<Instant>,
+    opened_at: Option<Instant>,
+    /// When the current half-open probe was let through
+    probe_started: Option<Instant>,
+}
+
+impl BreakerState {
+    /// Whether a request may be sent now. Transitions open -> half-open
+    /// (claiming the single probe slot) once the cooldown has elapsed.
+    ///
+    /// A probe that never reports back (e.g. cancelled mid-request) frees its
+    /// slot after another cooldown, so the breaker can't get stuck open.
+    pub fn allow(&mut self, now: Instant, config: &BreakerConfig) -> bool {
+        let Some(opened_at) = self.opened_at else {
+            return true; // Closed
+        };
+
+        if now.duration_since(opened_at) < config.cooldown {
+            return false;
+        }
+
+        if let Some(probe_started) = self.probe_started
+            && now.duration_since(probe_started) < config.cooldown
+        {
+            return false;
+        }
+
+        // Half-open: let exactly one probe through
+        self.probe_started = Some(now);
+        true
+    }
+
+    /// Record a successful request (closes the breaker).
+    pub fn on_success(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Record a failed request. Returns true if this failure opened the breaker.
+    pub fn on_failure(&mut self, now: Instant, config: &BreakerConfig) -> bool {
+        if self.probe_started.is_some() {
+            // Half-open probe failed - back to open for another cooldown
+            self.probe_started = None;
+            self.opened_at = Some(now);
+            return true;
+        }
+
+        let window_expired = self
+            .window_start
+            .map(|start| now.duration_since(start) > config.window)
+            .unwrap_or(true);
+
+        if window_expired {
+            self.window_start = Some(now);
+            self.consecutive_failures = 0;
+        }
+
+        self.consecutive_failures += 1;
+
+        if self.opened_at.is_none() && self.consecutive_failures >= config.failure_threshold {
+            self.opened_at = Some(now);
+            return true;
+        }
+
+        false
+    }
+
+    /// Check if the breaker is currently open (or half-open).
+    pub fn is_open(&self) -> bool {
+        self.opened_at.is_some()
+    }
+}
+
+/// Check whether a request to `host` is allowed.
+pub async fn allow_request(breakers: &CircuitBreakers, host: &str) -> bool {
+    // Fast path: unknown or closed hosts don't need the write lock
+    {
+        let read = breakers.states.read().await;
+        match read.get(host) {
+            None => return true,
+            Some(state) if !state.is_open() => return true,
+            Some(_) => {}
+        }
+    }
+
+    let mut write = breakers.states.write().await;
+    write
+        .entry(host.to_string())
+        .or_default()
+        .allow(Instant::now(), &breakers.config)
+}
+
+/// Record the outcome of a request to `host`.
+pub async fn record_result(
+    breakers: &CircuitBreakers,
+    host: &str,
+    success: bool,
+) {
+    let mut write = breakers.states.write().await;
+
+    if success {
+        // Drop the entry entirely so the map doesn't grow with healthy hosts
+        write.remove(host);
+        return;
+    }
+
+    write.entry(host.to_string()).or_default().on_failure(Instant::now(), &breakers.config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BreakerConfig {
+        BreakerConfig {
+            failure_threshold: 3,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let cfg = config();
+        let now = Instant::now();
+        let mut state = BreakerState::default();
+
+        assert!(!state.on_failure(now, &cfg));
+        assert!(!state.on_failure(now, &cfg));
+        assert!(state.allow(now, &cfg));
+        assert!(state.on_failure(now, &cfg));
+        assert!(!state.allow(now, &cfg));
+    }
+
+    #[test]
+    fn test_failures_outside_window_reset() {
+        let cfg = config();
+        let now = Instant::now();
+        let mut state = BreakerState::default();
+
+        state.on_failure(now, &cfg);
+        state.on_failure(now, &cfg);
+        // Third failure arrives after the window - count starts over
+        assert!(!state.on_failure(now + Duration::from_secs(61), &cfg));
+        assert!(!state.is_open());
+    }
+
+    #[test]
+    fn test_half_open_single_probe() {
+        let cfg = config();
+        let now = Instant::now();
+        let mut state = BreakerState::default();
+        for _ in 0..3 {
+            state.on_failure(now, &cfg);
+        }
+
+        let later = now + Duration::from_secs(31);
+        assert!(state.allow(later, &cfg), "probe allowed after cooldown");
+        assert!(!state.allow(later, &cfg), "only one probe at a time");
+
+        // Failed probe re-opens for another cooldown
+        assert!(state.on_failure(later, &cfg));
+        assert!(!state.allow(later + Duration::from_secs(1), &cfg));
+
+        // Successful probe closes the breaker
+        let much_later = later + Duration::from_secs(31);
+        assert!(state.allow(much_later, &cfg));
+        state.on_success();
+        assert!(!state.is_open());
+        assert!(state.allow(much_later, &cfg));
+    }
+
+    #[tokio::test]
+    async fn test_registry_uses_its_config() {
+        let breakers = new_registry(config());
+        for _ in 0..3 {
+            assert!(allow_request(&breakers, "api.example.com").await);
+            record_result(&breakers, "api.example.com", false).await;
+        }
+        assert!(!allow_request(&breakers, "api.example.com").await, "opened at the configured threshold");
+        assert!(allow_request(&breakers, "other.example.com").await);
+    }
+}
@@ -0,0 +1,366 @@
+//! Request body compression (gzip / deflate).
+//!
+//! A small DEFLATE encoder (RFC 1951) using LZ77 with hash chains and the fixed
+//! Huffman code, wrapped as gzip (RFC 1952) or zlib (RFC 1950, which is what
+//! `Content-Encoding: deflate` means over HTTP). Fixed codes trade a little
+//! ratio for simplicity; JSON payloads still shrink substantially.
+
+use crate::types::Compression;
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// How many earlier positions to try per match (speed vs ratio)
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The `Content-Encoding` header value for a compression mode.
+pub fn content_encoding(compression: Compression) -> &'static str {
+    match compression {
+        Compression::Gzip => "gzip",
+        Compression::Deflate => "deflate",
+    }
+}
+
+/// Compress `data` in the given format.
+pub fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::Gzip => gzip(data),
+        Compression::Deflate => zlib(data),
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, CM=8 (deflate), no flags, no mtime, XFL=0, OS=255 (unknown)
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    out.extend(deflate(data));
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    // CMF: deflate with 32K window; FLG makes the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Raw DEFLATE stream: a single final block with fixed Huffman codes.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.write_bits(1, 1); // BFINAL
+    w.write_bits(1, 2); // BTYPE = 01 (fixed Huffman)
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && candidate < i && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // Slots are reused as the window slides; only follow chains backwards
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            w.write_length(best_len);
+            w.write_distance(best_dist);
+            for j in i..i + best_len {
+                insert(data, j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            w.write_literal(data[i] as u16);
+            insert(data, i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+
+    w.write_literal(256); // End of block
+    w.finish()
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Record position `i` in the hash chains.
+fn insert(data: &[u8], i: usize, head: &mut [usize], prev: &mut [usize]) {
+    if i + MIN_MATCH <= data.len() {
+        let h = hash(data, i);
+        prev[i % WINDOW_SIZE] = head[h];
+        head[h] = i;
+    }
+}
+
+/// LSB-first bit writer, as DEFLATE requires.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are defined MSB-first, so they're written reversed.
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    /// Fixed literal/length code (RFC 1951 section 3.2.6).
+    fn write_literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, len: usize) {
+        let idx = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+        self.write_literal(257 + idx as u16);
+        self.write_bits((len - LENGTH_BASE[idx] as usize) as u32, LENGTH_EXTRA[idx] as u32);
+    }
+
+    fn write_distance(&mut self, dist: usize) {
+        let idx = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+        self.write_code(idx as u32, 5);
+        self.write_bits((dist - DIST_BASE[idx] as usize) as u32, DIST_EXTRA[idx] as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal inflater for fixed-Huffman blocks (the only kind we emit).
+    fn inflate_fixed(input: &[u8]) -> Vec<u8> {
+        let mut pos = 0usize; // bit position
+        let mut bit = |pos: &mut usize| -> u32 {
+            let b = (input[*pos / 8] >> (*pos % 8)) & 1;
+            *pos += 1;
+            b as u32
+        };
+        let bits = |pos: &mut usize, n: u8, bit: &mut dyn FnMut(&mut usize) -> u32| -> usize {
+            (0..n).fold(0, |acc, i| acc | (bit(pos) as usize) << i)
+        };
+
+        assert_eq!(bits(&mut pos, 3, &mut bit), 0b011, "final fixed block");
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let mut code = 0u32;
+            for _ in 0..7 {
+                code = (code << 1) | bit(&mut pos);
+            }
+            let symbol = if code <= 0x17 {
+                256 + code
+            } else {
+                code = (code << 1) | bit(&mut pos);
+                match code {
+                    0x30..=0xBF => code - 0x30,
+                    0xC0..=0xC7 => 280 + code - 0xC0,
+                    _ => 144 + ((code << 1) | bit(&mut pos)) - 0x190,
+                }
+            };
+
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => return out,
+                _ => {
+                    let idx = (symbol - 257) as usize;
+                    let len = LENGTH_BASE[idx] as usize + bits(&mut pos, LENGTH_EXTRA[idx], &mut bit);
+                    let mut dcode = 0usize;
+                    for _ in 0..5 {
+                        dcode = (dcode << 1) | bit(&mut pos) as usize;
+                    }
+                    let dist = DIST_BASE[dcode] as usize + bits(&mut pos, DIST_EXTRA[dcode], &mut bit);
+                    for _ in 0..len {
+                        out.push(out[out.len() - dist]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        let rows: Vec<_> = (0..500)
+            .map(|i| serde_json::json!({"id": i, "status": "active", "name": format!("user-{}", i)}))
+            .collect();
+        serde_json::to_vec(&rows).unwrap()
+    }
+
+    #[test]
+    fn test_deflate_roundtrip_and_shrinks() {
+        let data = sample();
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len() / 3, "{} -> {}", data.len(), compressed.len());
+        assert_eq!(inflate_fixed(&compressed), data);
+
+        assert_eq!(inflate_fixed(&deflate(b"")), b"");
+        assert_eq!(inflate_fixed(&deflate(b"ab")), b"ab");
+    }
+
+    #[test]
+    fn test_gzip_and_zlib_framing() {
+        let data = b"hello hello hello hello";
+
+        let gz = compress(data, Compression::Gzip);
+        assert_eq!(&gz[..3], &[0x1f, 0x8b, 8]);
+        assert_eq!(&gz[gz.len() - 8..gz.len() - 4], &crc32(data).to_le_bytes());
+        assert_eq!(inflate_fixed(&gz[10..gz.len() - 8]), data);
+
+        let z = compress(data, Compression::Deflate);
+        assert_eq!(((z[0] as u16) << 8 | z[1] as u16) % 31, 0);
+        assert_eq!(inflate_fixed(&z[2..z.len() - 4]), data);
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    const FOX: &[u8] = b"The quick brown fox jumps over the lazy dog. The quick brown fox jumps over the lazy dog. The quick brown cat.";
+
+    #[test]
+    fn test_literal_only_output_matches_zlib() {
+        // zlib.compress(data, 1): with nothing to match, zlib emits the same fixed block
+        assert_eq!(compress(b"a", Compression::Deflate), unhex("78014b040000620062"));
+        assert_eq!(
+            compress(b"Hello, World!", Compression::Deflate),
+            unhex("7801f348cdc9c9d75108cf2fca495104001f9e046a")
+        );
+        // gzip.compress(data, 1, mtime=0), apart from the XFL/OS header bytes
+        let gz = compress(b"Hello, World!", Compression::Gzip);
+        let reference = unhex("1f8b0800000000000403f348cdc9c9d75108cf2fca49510400d0c34aec0d000000");
+        assert_eq!(gz[..8], reference[..8]);
+        assert_eq!(gz[10..], reference[10..]);
+    }
+
+    #[test]
+    fn test_output_matches_vectors_zlib_inflates() {
+        // Pinned encoder output; each one decompresses with zlib.decompress /
+        // gzip.decompress to the input. Covers length and distance extra bits.
+        let cases: [(&[u8], Compression, &str); 5] = [
+            (b"", Compression::Gzip, "1f8b08000000000000ff03000000000000000000"),
+            (b"hello hello hello hello", Compression::Deflate, "7801cb48cdc9c957c02001680308b1"),
+            (&[b'a'; 300], Compression::Deflate, "78014b1c05440300d8a871ad"),
+            (
+                FOX,
+                Compression::Deflate,
+                "78010bc94855282ccd4cce56482aca2fcf5348cbaf50c82acd2d2856c82f4b2d5228014ae72456552aa4e4a7eb2950a43839b1440f00a197277b",
+            ),
+            (
+                FOX,
+                Compression::Gzip,
+                "1f8b08000000000000ff0bc94855282ccd4cce56482aca2fcf5348cbaf50c82acd2d2856c82f4b2d5228014ae72456552aa4e4a7eb2950a43839b1440f00b58ffe016e000000",
+            ),
+        ];
+        for (data, compression, expected) in cases {
+            assert_eq!(compress(data, compression), unhex(expected), "{:?}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn test_inflater_reads_zlib_streams() {
+        // Streams zlib itself produced (zlib.compress at levels 1 and 6), so the
+        // round-trip tests above don't rest on an inflater that shares our bugs
+        let z = unhex("7801cb48cdc9c957c8402701680308b1");
+        assert_eq!(inflate_fixed(&z[2..z.len() - 4]), b"hello hello hello hello");
+        let z = unhex("789c4b4c1c05c40200d8a871ad");
+        assert_eq!(inflate_fixed(&z[2..z.len() - 4]), [b'a'; 300]);
+        let z = unhex("78010bc94855282ccd4cce56482aca2fcf5348cbaf50c82acd2d2856c82f4b2d5228014ae72456552aa4e4a7eb298450a23839b1440f00a197277b");
+        assert_eq!(inflate_fixed(&z[2..z.len() - 4]), FOX);
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+}
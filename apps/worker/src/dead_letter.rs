@@ -0,0 +1,131 @@
+//! Dead letter queue for poison messages.
+//!
+//! Messages that can never be processed (e.g. unparseable payloads) are stored
+//! in the `dead_letter_jobs` table and then ACKed + deleted from the stream, so
+//! they stop being redelivered by stale-message recovery.
+//!
+//! The same applies to messages whose "transient" error turns out to be
+//! permanent: stale-message recovery re-adds them with a new stream ID, so the
+//! number of deliveries is carried along in a `deliveries` field. Once a message
+//! has been delivered more than `MAX_DELIVERIES` times it is dead-lettered.
+
+use redis::{AsyncCommands, RedisResult};
+use sqlx::PgPool;
+use std::collections::HashMap;
+
+/// Stream field carrying deliveries from before a message was re-added
+pub const DELIVERIES_FIELD: &str = "deliveries";
+
+/// Default deliveries before a message is dead-lettered
+const DEFAULT_MAX_DELIVERIES: u32 = 5;
+
+/// Maximum deliveries before a message is dead-lettered (`MAX_DELIVERIES`)
+pub fn max_deliveries() -> u32 {
+    std::env::var("MAX_DELIVERIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_DELIVERIES)
+}
+
+/// Deliveries recorded on a message before it was re-added to the stream.
+pub fn prior_deliveries(fields: &HashMap<String, redis::Value>) -> u32 {
+    fields
+        .get(DELIVERIES_FIELD)
+        .and_then(|v| redis::from_redis_value::<u32>(v).ok())
+        .unwrap_or(0)
+}
+
+/// Whether a message delivered `deliveries` times should be given up on.
+pub fn exceeds_max_deliveries(deliveries: u32, max: u32) -> bool {
+    deliveries > max
+}
+
+/// Look up how many times Redis has delivered a pending message.
+///
+/// Returns 1 if the message isn't in the PEL (or the lookup fails).
+pub async fn delivery_count(
+    con: &mut redis::aio::MultiplexedConnection,
+    stream: &str,
+    group_name: &str,
+    msg_id: &str,
+) -> u32 {
+    // XPENDING stream group start end count -> [[id, consumer, idle_ms, delivery_count]]
+    let pending: RedisResult<Vec<(String, String, u64, u64)>> = redis::cmd("XPENDING")
+        .arg(stream)
+        .arg(group_name)
+        .arg(msg_id)
+        .arg(msg_id)
+        .arg(1)
+        .query_async(con)
+        .await;
+
+    pending
+        .ok()
+        .and_then(|entries| entries.first().map(|(_, _, _, count)| *count as u32))
+        .unwrap_or(1)
+}
+
+/// Move a message to the dead letter table, then ACK and delete it.
+///
+/// If the insert fails the message is left pending (not ACKed), so a poison
+/// message is never silently dropped.
+#[allow(clippy::too_many_arguments)]
+pub async fn dead_letter_message(
+    con: &mut redis::aio::MultiplexedConnection,
+    pool: &PgPool,
+    stream: &str,
+    group_name: &str,
+    msg_id: &str,
+    payload: Option<&str>,
+    error: &str,
+    deliveries: u32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO dead_letter_jobs (stream, message_id, group_name, payload, error, delivery_count)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(stream)
+    .bind(msg_id)
+    .bind(group_name)
+    .bind(payload)
+    .bind(error)
+    .bind(deliveries as i32)
+    .execute(pool)
+    .await?;
+
+    tracing::error!(
+        "Dead letter: message {} moved to dead_letter_jobs after {} delivery(ies): {}",
+        msg_id, deliveries, error
+    );
+
+    let _: RedisResult<()> = con.xack(stream, group_name, &[msg_id]).await;
+    let _: RedisResult<()> = con.xdel(stream, &[msg_id]).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prior_deliveries() {
+        let mut fields = HashMap::new();
+        assert_eq!(prior_deliveries(&fields), 0);
+
+        fields.insert(
+            DELIVERIES_FIELD.to_string(),
+            redis::Value::BulkString(b"3".to_vec()),
+        );
+        assert_eq!(prior_deliveries(&fields), 3);
+    }
+
+    #[test]
+    fn test_exceeds_max_deliveries() {
+        assert!(!exceeds_max_deliveries(1, 5));
+        assert!(!exceeds_max_deliveries(5, 5));
+        assert!(exceeds_max_deliveries(6, 5));
+    }
+}
@@ -3,9 +3,15 @@
 //! All node lifecycle events are logged to PostgreSQL for observability,
 //! debugging, and replay capabilities.
 
+use crate::rerun::{self, RerunError};
+use crate::templating;
+use crate::types::{ResumeVia, Suspension, SuspensionKind, WorkerJob};
+use redis::AsyncCommands;
 use sqlx::PgPool;
+use std::collections::HashMap;
 use uuid::Uuid;
 
+
 /// Types of events that can occur during node execution.
 #[derive(Debug, Clone, Copy)]
 pub enum EventType {
@@ -16,6 +22,7 @@ pub enum EventType {
     NodeRetryScheduled,
     NodeSuspended,
     NodeResumed,
+    NodeProgress,
 }
 
 impl EventType {
@@ -29,10 +36,32 @@ impl EventType {
             EventType::NodeRetryScheduled => "NODE_RETRY_SCHEDULED",
             EventType::NodeSuspended => "NODE_SUSPENDED",
             EventType::NodeResumed => "NODE_RESUMED",
+            EventType::NodeProgress => "NODE_PROGRESS",
         }
     }
 }
 
+impl Suspension {
+    pub fn new(
+        kind: SuspensionKind,
+        resume_via: ResumeVia,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+        detail: serde_json::Value,
+    ) -> Self {
+        Self { kind, resume_via, expires_at: expires_at.map(|at| at.to_rfc3339()), detail }
+    }
+
+    /// As the `suspension` field of an event or result body
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+}
+
+/// Deadline `timeout_ms` from now, or None for no timeout (0).
+pub fn expires_in(timeout_ms: u64) -> Option<chrono::DateTime<chrono::Utc>> {
+    (timeout_ms > 0).then(|| chrono::Utc::now() + chrono::Duration::milliseconds(timeout_ms as i64))
+}
+
 /// Log an event to the run_events table.
 pub async fn log_event(
     pool: &PgPool,
@@ -97,6 +126,119 @@ pub async fn has_node_completed(
     Ok(result.is_some())
 }
 
+/// Rebuild the job for a node that completed in `run_id`, to replay it in
+/// that run for debugging.
+///
+/// The job comes from the run's graph snapshot with templates resolved
+/// against the recorded outputs, so it sees the same inputs as the original
+/// attempt. It keeps the run (its events are logged alongside the original
+/// ones) but is `isolated`, so the orchestrator schedules nothing downstream.
+/// It runs as the attempt after the last one logged for the node, so the
+/// idempotency check doesn't skip it, and with no retries of its own.
+///
+/// Returns `None` if the run doesn't exist, the node never completed, or the
+/// node can't be rebuilt (it needs a live run context).
+pub async fn build_replay_job(
+    pool: &PgPool,
+    run_id: &Uuid,
+    node_id: &str,
+) -> Result<Option<WorkerJob>, sqlx::Error> {
+    let (last_attempt, completed): (Option<i32>, Option<bool>) = sqlx::query_as(
+        r#"
+        SELECT MAX(retry_count), bool_or(event_type = 'NODE_COMPLETED')
+        FROM run_events
+        WHERE run_id = $1 AND node_id = $2
+        "#,
+    )
+    .bind(run_id)
+    .bind(node_id)
+    .fetch_one(pool)
+    .await?;
+    if completed != Some(true) {
+        return Ok(None);
+    }
+
+    let run: Option<(serde_json::Value, Option<serde_json::Value>)> =
+        sqlx::query_as("SELECT snapshot_graph, input_data FROM workflow_runs WHERE id = $1")
+            .bind(run_id)
+            .fetch_optional(pool)
+            .await?;
+    let Some((graph, input_data)) = run else {
+        return Ok(None);
+    };
+
+    let outputs = templating::recorded_outputs(pool, run_id).await?;
+    Ok(replay_job(&graph, input_data.as_ref(), &outputs, run_id, node_id, replay_attempt(last_attempt)))
+}
+
+/// Build and enqueue a replay of `node_id` (see `build_replay_job`).
+/// Returns the queued job; the result arrives like any other job's.
+pub async fn enqueue_replay(
+    pool: &PgPool,
+    redis: &redis::Client,
+    run_id: &Uuid,
+    node_id: &str,
+) -> Result<WorkerJob, RerunError> {
+    let job = build_replay_job(pool, run_id, node_id)
+        .await
+        .map_err(|e| RerunError::DatabaseError(e.to_string()))?
+        .ok_or_else(|| RerunError::Unsupported(format!("node '{}' has no completed execution to replay", node_id)))?;
+
+    let payload = serde_json::to_string(&job).map_err(|e| RerunError::Unsupported(e.to_string()))?;
+    let mut con = redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| RerunError::RedisError(e.to_string()))?;
+    let _: String = con
+        .xadd(job.priority.stream(), "*", &[("payload", payload)])
+        .await
+        .map_err(|e| RerunError::RedisError(e.to_string()))?;
+
+    tracing::info!(%run_id, node_id, attempt = job.retry_count + 1, "Replay: queued node");
+    Ok(job)
+}
+
+/// Attempt number for a fresh execution of a node that may already have run
+/// in this run (e.g. a Loop body): one past its last logged attempt.
+pub async fn next_attempt(pool: &PgPool, run_id: &Uuid, node_id: &str) -> Result<u32, sqlx::Error> {
+    let last: Option<i32> = sqlx::query_scalar("SELECT MAX(retry_count) FROM run_events WHERE run_id = $1 AND node_id = $2")
+        .bind(run_id)
+        .bind(node_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(replay_attempt(last))
+}
+
+/// Attempt number for a replay: one past the last logged attempt.
+/// Events logged without a retry count belong to attempt 0.
+fn replay_attempt(last_logged: Option<i32>) -> u32 {
+    last_logged.map_or(0, |n| n.max(0) as u32) + 1
+}
+
+fn replay_job(
+    graph: &serde_json::Value,
+    input_data: Option<&serde_json::Value>,
+    outputs: &HashMap<String, serde_json::Value>,
+    run_id: &Uuid,
+    node_id: &str,
+    attempt: u32,
+) -> Option<WorkerJob> {
+    let mut job = match rerun::rebuild_job(graph, input_data, outputs, run_id, node_id) {
+        Ok(job) => job,
+        Err(e) => {
+            tracing::error!(%run_id, node_id, "Replay: can't rebuild node: {}", e);
+            return None;
+        }
+    };
+    job.isolated = true;
+    job.retry_count = attempt;
+    // retry_count == max_retries: a failed replay isn't retried
+    job.max_retries = attempt;
+    job.idempotency_key = None;
+    job.first_attempt_at = None;
+    Some(job)
+}
+
 /// Update the status of a workflow run.
 #[allow(dead_code)]
 pub async fn update_run_status(
@@ -134,3 +276,115 @@ pub async fn update_run_status(
 
     Ok(())
 }
+
+/// Largest node result (serialized) kept whole in a NODE_COMPLETED event
+/// (`RESULT_EVENT_MAX_BYTES`; 0, the default, keeps every result whole).
+pub fn result_event_max_bytes() -> usize {
+    std::env::var("RESULT_EVENT_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+/// The stand-in stored for a result whose JSON is over `max_bytes`:
+/// `{ "_truncated": true, "size": <full bytes>, "preview": <leading JSON text> }`.
+/// None if the result fits (or there's no limit).
+pub fn truncate_result(result: &serde_json::Value, max_bytes: usize) -> Option<serde_json::Value> {
+    if max_bytes == 0 {
+        return None;
+    }
+    let text = result.to_string();
+    if text.len() <= max_bytes {
+        return None;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(serde_json::json!({
+        "_truncated": true,
+        "size": text.len(),
+        "preview": &text[..end],
+    }))
+}
+
+/// Payload of a NODE_COMPLETED event. The orchestrator reads `result` for
+/// templates and `route_to` for the output handle to follow.
+pub fn completed_payload(
+    result: Option<&serde_json::Value>,
+    duration_ms: u64,
+    route_to: Option<&str>,
+    warnings: &[crate::types::NodeWarning],
+) -> serde_json::Value {
+    serde_json::json!({
+        "result": result,
+        "duration_ms": duration_ms,
+        "route_to": route_to,
+        "warnings": warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NodeType;
+    use serde_json::json;
+
+    #[test]
+    fn test_suspension_shape() {
+        let at = chrono::DateTime::from_timestamp(1_700_000_000, 0);
+        let suspension = Suspension::new(SuspensionKind::SubFlow, ResumeVia::ChildRun, at, json!({"child_run_id": "c"}));
+        assert_eq!(
+            suspension.to_value(),
+            json!({
+                "kind": "sub_flow",
+                "resume_via": "child_run",
+                "expires_at": "2023-11-14T22:13:20+00:00",
+                "detail": {"child_run_id": "c"}
+            })
+        );
+        assert_eq!(expires_in(0), None);
+    }
+
+    #[test]
+    fn test_truncate_result() {
+        let result = json!({ "content": "héllo wörld, a long answer" });
+        assert_eq!(truncate_result(&result, 0), None);
+        assert_eq!(truncate_result(&result, 1024), None);
+
+        // Cut inside the two-byte 'é' backs off to the character before it
+        let truncated = truncate_result(&result, 14).unwrap();
+        assert_eq!(truncated["_truncated"], true);
+        assert_eq!(truncated["size"], result.to_string().len());
+        assert_eq!(truncated["preview"], r#"{"content":"h"#);
+    }
+
+    #[test]
+    fn test_replay_job_keeps_the_run_but_is_isolated_and_a_fresh_attempt() {
+        let graph = json!({
+            "nodes": [
+                {"id": "fetch", "type": "http-request", "data": {"url": "https://api.test/start", "method": "GET"}},
+                {"id": "report", "type": "http-request", "data": {"url": "https://api.test/items/{{fetch.body.id}}", "method": "POST"}},
+                {"id": "wait", "type": "delay", "data": {"durationMs": 1000}}
+            ],
+            "edges": [{"source": "fetch", "target": "report"}]
+        });
+        let outputs = HashMap::from([("fetch".to_string(), json!({"body": {"id": 42}}))]);
+        let run_id = Uuid::new_v4();
+
+        // The original run retried `report` once (attempts 0 and 1)
+        let job = replay_job(&graph, None, &outputs, &run_id, "report", replay_attempt(Some(1))).unwrap();
+        match &job.node {
+            NodeType::Http(data) => assert_eq!(data.url, "https://api.test/items/42"),
+            other => panic!("unexpected node {:?}", other),
+        }
+        assert_eq!(job.id, "report");
+        assert_eq!(job.run_id, Some(run_id.to_string()));
+        assert!(job.isolated);
+        assert_eq!(job.retry_count, 2);
+        assert_eq!(job.retry_count, job.max_retries);
+
+        assert_eq!(replay_attempt(None), 1);
+        assert!(replay_job(&graph, None, &outputs, &run_id, "wait", 1).is_none());
+    }
+}
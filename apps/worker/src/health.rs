@@ -0,0 +1,136 @@
+//! Liveness and readiness probes.
+//!
+//! With `HEALTH_PORT` set the worker serves:
+//! - `GET /healthz`: 200 while the process is up
+//! - `GET /readyz`: 200 when Redis and Postgres answer and the JS thread runs
+//!   a no-op script, each within `PROBE_TIMEOUT`; 503 (with the failing
+//!   checks in the JSON body) otherwise
+//!
+//! so an orchestrator can stop routing to a worker whose dependencies are down.
+
+use crate::nodes::JsTask;
+use crate::responder::{self, Response};
+use serde_json::json;
+use sqlx::PgPool;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// How long each readiness check may take
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Port for the probe endpoints (`HEALTH_PORT`; unset or 0 = disabled).
+pub fn port() -> Option<u16> {
+    std::env::var("HEALTH_PORT").ok().and_then(|v| v.parse().ok()).filter(|p| *p != 0)
+}
+
+/// What `/readyz` depends on.
+#[derive(Clone)]
+pub struct Dependencies {
+    pub redis: redis::Client,
+    pub db: PgPool,
+    pub js: mpsc::Sender<JsTask>,
+}
+
+/// Outcome of one readiness check (`Err` holds the reason).
+type Check = Result<(), String>;
+
+async fn with_timeout<F: std::future::Future<Output = Check>>(check: F) -> Check {
+    tokio::time::timeout(PROBE_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {}ms", PROBE_TIMEOUT.as_millis())))
+}
+
+async fn check_redis(client: &redis::Client) -> Check {
+    let mut con = client.get_multiplexed_async_connection().await.map_err(|e| e.to_string())?;
+    redis::cmd("PING").query_async::<String>(&mut con).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+async fn check_postgres(pool: &PgPool) -> Check {
+    sqlx::query("SELECT 1").execute(pool).await.map(|_| ()).map_err(|e| e.to_string())
+}
+
+async fn check_js(js: &mpsc::Sender<JsTask>) -> Check {
+    let (tx, rx) = oneshot::channel();
+    let task = JsTask {
+        code: "true".to_string(),
+        inputs: None,
+        responder: tx,
+        timeout_ms: Some(PROBE_TIMEOUT.as_millis() as u64),
+        log_sender: None,
+        cancelled: None,
+    };
+    js.send(task).await.map_err(|_| "JS thread is gone".to_string())?;
+    match rx.await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.message),
+        Err(_) => Err("JS thread dropped the task".to_string()),
+    }
+}
+
+/// Run every readiness check concurrently.
+pub async fn readiness(deps: &Dependencies) -> (u16, serde_json::Value) {
+    let (redis, postgres, js) = tokio::join!(
+        with_timeout(check_redis(&deps.redis)),
+        with_timeout(check_postgres(&deps.db)),
+        with_timeout(check_js(&deps.js)),
+    );
+    readiness_report(&[("redis", redis), ("postgres", postgres), ("js", js)])
+}
+
+/// 200 when every check passed, else 503; the body lists each check.
+fn readiness_report(checks: &[(&str, Check)]) -> (u16, serde_json::Value) {
+    let ready = checks.iter().all(|(_, c)| c.is_ok());
+    let details: serde_json::Map<String, serde_json::Value> = checks
+        .iter()
+        .map(|(name, check)| {
+            let value = match check {
+                Ok(()) => json!({ "ok": true }),
+                Err(e) => json!({ "ok": false, "error": e }),
+            };
+            (name.to_string(), value)
+        })
+        .collect();
+    (if ready { 200 } else { 503 }, json!({ "ready": ready, "checks": details }))
+}
+
+/// Serve the probes until the listener fails.
+pub async fn serve(port: u16, deps: Dependencies) {
+    tracing::info!("Health: Serving /healthz and /readyz on :{}", port);
+    responder::serve("Health", port, move |path| {
+        let deps = deps.clone();
+        async move {
+            let (status, body) = match path.as_str() {
+                "/healthz" => (200, json!({ "status": "ok" })),
+                "/readyz" => readiness(&deps).await,
+                _ => return None,
+            };
+            Some(Response::new(status, "application/json", body.to_string()))
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_report() {
+        let (status, body) = readiness_report(&[("redis", Ok(())), ("postgres", Ok(()))]);
+        assert_eq!(status, 200);
+        assert_eq!(body["ready"], true);
+
+        let (status, body) = readiness_report(&[("redis", Ok(())), ("postgres", Err("connection refused".into()))]);
+        assert_eq!(status, 503);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["checks"]["redis"]["ok"], true);
+        assert_eq!(body["checks"]["postgres"]["error"], "connection refused");
+    }
+
+    #[tokio::test]
+    async fn test_dead_js_thread_is_not_ready() {
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        assert_eq!(with_timeout(check_js(&tx)).await, Err("JS thread is gone".to_string()));
+    }
+}
@@ -0,0 +1,474 @@
+//! Custom idempotency keys for jobs.
+//!
+//! Event-based idempotency is keyed on (run_id, node_id, retry_count), which
+//! doesn't cover jobs without a run or jobs that should dedupe across runs.
+//! A job carrying an `idempotency_key` claims it in Redis before it executes,
+//! with a single `SET NX EX` so two deliveries can't both get past the check.
+//! The claim holds a `pending` marker for the job's lease (`job_key_lease`);
+//! a successful run overwrites it with the completion time, kept for
+//! `IDEMPOTENCY_TTL_SECS` (default 24h), and any other outcome deletes it so
+//! a retry can run.
+//!
+//! Lifecycle events (resumes, map completions) skip the event-based check, so
+//! two deliveries of the same one can race on different workers. When such a
+//! job carries a key it is *claimed* up front in `processed_jobs`, whose
+//! primary key makes the second claim fail at the DB level. A claim is a
+//! lease (`LIFECYCLE_CLAIM_LEASE_SECS`): it becomes final only once the event
+//! has run to an outcome, and is given back when the event fails transiently.
+//! A delivery that finds a live lease (on either kind of key) is parked in the
+//! delayed set until the lease runs out rather than dropped, so if the worker
+//! holding it dies mid-event, the parked delivery takes the claim over and
+//! the event still runs. Parking ACKs it, so a duplicate waiting on a live
+//! holder isn't dead-lettered for its redeliveries.
+
+use redis::AsyncCommands;
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Redis key prefix for completed idempotency keys
+pub const IDEMPOTENCY_KEY_PREFIX: &str = "swiftgrid_idempotency:";
+
+/// Default time a completed key is remembered (24 hours)
+const DEFAULT_TTL_SECS: u64 = 86_400;
+
+/// Default lease on a lifecycle claim (2 minutes)
+const DEFAULT_CLAIM_LEASE_SECS: u64 = 120;
+
+/// How long completed keys are remembered
+pub fn ttl() -> Duration {
+    Duration::from_secs(
+        std::env::var("IDEMPOTENCY_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECS),
+    )
+}
+
+/// How long a lifecycle claim is held before a redelivery may take it over
+/// (`LIFECYCLE_CLAIM_LEASE_SECS`)
+pub fn claim_lease() -> Duration {
+    Duration::from_secs(
+        std::env::var("LIFECYCLE_CLAIM_LEASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_CLAIM_LEASE_SECS),
+    )
+}
+
+/// How long a job's claim on its key is held before a redelivery may take it
+/// over: past the hard job timeout, so a run still in progress is never
+/// overtaken, by the lifecycle claim lease
+pub fn job_key_lease() -> Duration {
+    crate::job_timeout::job_timeout().unwrap_or_else(ttl) + claim_lease()
+}
+
+/// Value of a key claimed by a job that hasn't finished
+const PENDING: &str = "pending";
+
+/// Delete the key only while it's still a pending claim
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// Storage for job idempotency keys.
+pub trait IdempotencyStore {
+    /// Claim `key` for a run of its job, held for `lease`.
+    fn claim(
+        &self,
+        key: &str,
+        lease: Duration,
+    ) -> impl std::future::Future<Output = Result<Claim, String>> + Send;
+
+    /// Remember `key` as completed for `ttl`.
+    fn insert(
+        &self,
+        key: &str,
+        ttl: Duration,
+    ) -> impl std::future::Future<Output = Result<(), String>> + Send;
+
+    /// Give back a pending claim (a completed key stays).
+    fn release(&self, key: &str) -> impl std::future::Future<Output = Result<(), String>> + Send;
+}
+
+impl IdempotencyStore for redis::Client {
+    async fn claim(&self, key: &str, lease: Duration) -> Result<Claim, String> {
+        let mut con = self
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let key = format!("{}{}", IDEMPOTENCY_KEY_PREFIX, key);
+        let set: Option<String> = claim_cmd(&key, lease)
+            .query_async(&mut con)
+            .await
+            .map_err(|e| e.to_string())?;
+        if set.is_some() {
+            return Ok(Claim::Acquired);
+        }
+        let value: Option<String> = con.get(&key).await.map_err(|e| e.to_string())?;
+        Ok(claim_state(value.as_deref()))
+    }
+
+    async fn insert(&self, key: &str, ttl: Duration) -> Result<(), String> {
+        let mut con = self
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        con.set_ex(
+            format!("{}{}", IDEMPOTENCY_KEY_PREFIX, key),
+            chrono::Utc::now().timestamp_millis(),
+            ttl.as_secs().max(1),
+        )
+        .await
+        .map_err(|e| e.to_string())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), String> {
+        let mut con = self
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        redis::Script::new(RELEASE_SCRIPT)
+            .key(format!("{}{}", IDEMPOTENCY_KEY_PREFIX, key))
+            .arg(PENDING)
+            .invoke_async::<i64>(&mut con)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// `SET NX EX`: mark `key` pending for `lease` unless it's already set
+fn claim_cmd(key: &str, lease: Duration) -> redis::Cmd {
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(key).arg(PENDING).arg("NX").arg("EX").arg(lease.as_secs().max(1));
+    cmd
+}
+
+/// What a key that couldn't be claimed holds: a pending claim (or nothing,
+/// if it was released in between) is checked again later; anything else is a
+/// completion time.
+fn claim_state(value: Option<&str>) -> Claim {
+    match value {
+        Some(PENDING) | None => Claim::Held,
+        Some(_) => Claim::Completed,
+    }
+}
+
+/// Claim a job's key before it runs.
+///
+/// Errors are returned to the caller so a Redis outage is treated as
+/// transient (message not ACKed) rather than running the job twice.
+pub async fn claim_key(store: &impl IdempotencyStore, key: &str) -> Result<Claim, String> {
+    store.claim(key, job_key_lease()).await
+}
+
+/// Record that a job with this key completed.
+pub async fn mark_completed(store: &impl IdempotencyStore, key: &str) {
+    if let Err(e) = store.insert(key, ttl()).await {
+        tracing::error!("Failed to record idempotency key {}: {}", key, e);
+    }
+}
+
+/// Give back the claim of a job that didn't succeed, so its retry can run.
+pub async fn release_key(store: &impl IdempotencyStore, key: &str) {
+    if let Err(e) = store.release(key).await {
+        tracing::error!("Failed to release idempotency key {}: {}", key, e);
+    }
+}
+
+/// What claiming a lifecycle key found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// This delivery holds the key and runs the event
+    Acquired,
+    /// The event already ran to an outcome; skip (and ACK) this delivery
+    Completed,
+    /// Another delivery holds a live lease; check again once it runs out
+    Held,
+}
+
+/// Durable claims on lifecycle job keys (the `processed_jobs` table).
+pub trait JobClaims {
+    /// Claim `key` for `lease`, taking over a lease that expired without the
+    /// event completing.
+    fn claim(
+        &self,
+        key: &str,
+        run_id: Option<Uuid>,
+        node_id: &str,
+        lease: Duration,
+    ) -> impl std::future::Future<Output = Result<Claim, String>> + Send;
+
+    /// Make a claim final once its event ran to an outcome.
+    fn complete(&self, key: &str) -> impl std::future::Future<Output = Result<(), String>> + Send;
+
+    /// Give a claim back so a redelivery can run (after a transient failure).
+    fn release(&self, key: &str) -> impl std::future::Future<Output = Result<(), String>> + Send;
+}
+
+/// Insert a claim, or take over one whose lease ran out before its event
+/// completed; returns the key only if this call now holds it
+const CLAIM_SQL: &str = r#"
+    INSERT INTO processed_jobs (idempotency_key, run_id, node_id, lease_expires_at)
+    VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+    ON CONFLICT (idempotency_key) DO UPDATE
+        SET lease_expires_at = EXCLUDED.lease_expires_at, created_at = NOW()
+        WHERE processed_jobs.completed_at IS NULL AND processed_jobs.lease_expires_at < NOW()
+    RETURNING idempotency_key
+"#;
+
+impl JobClaims for PgPool {
+    async fn claim(&self, key: &str, run_id: Option<Uuid>, node_id: &str, lease: Duration) -> Result<Claim, String> {
+        let acquired: Option<(String,)> = sqlx::query_as(CLAIM_SQL)
+            .bind(key)
+            .bind(run_id)
+            .bind(node_id)
+            .bind(lease.as_secs_f64())
+            .fetch_optional(self)
+            .await
+            .map_err(|e| e.to_string())?;
+        if acquired.is_some() {
+            return Ok(Claim::Acquired);
+        }
+
+        let completed: Option<(bool,)> =
+            sqlx::query_as("SELECT completed_at IS NOT NULL FROM processed_jobs WHERE idempotency_key = $1")
+                .bind(key)
+                .fetch_optional(self)
+                .await
+                .map_err(|e| e.to_string())?;
+        Ok(match completed {
+            Some((true,)) => Claim::Completed,
+            // Held, or released between the two queries (the redelivery claims it)
+            _ => Claim::Held,
+        })
+    }
+
+    async fn complete(&self, key: &str) -> Result<(), String> {
+        sqlx::query("UPDATE processed_jobs SET completed_at = NOW() WHERE idempotency_key = $1")
+            .bind(key)
+            .execute(self)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), String> {
+        sqlx::query("DELETE FROM processed_jobs WHERE idempotency_key = $1 AND completed_at IS NULL")
+            .bind(key)
+            .execute(self)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Claim a lifecycle job's key. Errors are transient, like `is_duplicate`.
+pub async fn claim(store: &impl JobClaims, key: &str, run_id: Option<Uuid>, node_id: &str) -> Result<Claim, String> {
+    store.claim(key, run_id, node_id, claim_lease()).await
+}
+
+/// Make a claim final: redeliveries of the event are skipped from now on.
+pub async fn complete(store: &impl JobClaims, key: &str) {
+    if let Err(e) = store.complete(key).await {
+        tracing::error!("Failed to complete idempotency claim {}: {}", key, e);
+    }
+}
+
+/// Release a claim taken by `claim`.
+pub async fn release(store: &impl JobClaims, key: &str) {
+    if let Err(e) = store.release(key).await {
+        tracing::error!("Failed to release idempotency claim {}: {}", key, e);
+    }
+}
+
+/// Take `key` for good, first caller wins (e.g. which webhook outcome settles
+/// a wait). Unlike `claim` there's no lease: the winner decided the outcome.
+pub async fn claim_once(pool: &PgPool, key: &str, run_id: Option<Uuid>, node_id: &str) -> Result<bool, String> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO processed_jobs (idempotency_key, run_id, node_id, completed_at)
+        VALUES ($1, $2, $3, NOW())
+        ON CONFLICT (idempotency_key) DO NOTHING
+        "#,
+    )
+    .bind(key)
+    .bind(run_id)
+    .bind(node_id)
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Delete claims older than the idempotency TTL. Returns rows deleted.
+pub async fn prune_claims(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM processed_jobs WHERE idempotency_key IN (
+            SELECT idempotency_key FROM processed_jobs
+            WHERE created_at < NOW() - make_interval(secs => $1)
+            LIMIT 10000
+        )
+        "#,
+    )
+    .bind(ttl().as_secs() as f64)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    /// key -> value, with `SET NX` semantics for claims
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<String, String>>);
+
+    impl IdempotencyStore for MemoryStore {
+        async fn claim(&self, key: &str, _lease: Duration) -> Result<Claim, String> {
+            let mut keys = self.0.lock().unwrap();
+            if let Some(value) = keys.get(key) {
+                return Ok(claim_state(Some(value)));
+            }
+            keys.insert(key.to_string(), PENDING.to_string());
+            Ok(Claim::Acquired)
+        }
+
+        async fn insert(&self, key: &str, _ttl: Duration) -> Result<(), String> {
+            self.0.lock().unwrap().insert(key.to_string(), "1700000000000".to_string());
+            Ok(())
+        }
+
+        async fn release(&self, key: &str) -> Result<(), String> {
+            let mut keys = self.0.lock().unwrap();
+            if keys.get(key).map(String::as_str) == Some(PENDING) {
+                keys.remove(key);
+            }
+            Ok(())
+        }
+    }
+
+    /// key -> (completed, lease expiry)
+    #[derive(Default)]
+    struct MemoryClaims(Mutex<HashMap<String, (bool, Instant)>>);
+
+    impl JobClaims for MemoryClaims {
+        async fn claim(&self, key: &str, _run_id: Option<Uuid>, _node_id: &str, lease: Duration) -> Result<Claim, String> {
+            let mut claims = self.0.lock().unwrap();
+            match claims.get(key) {
+                Some((true, _)) => Ok(Claim::Completed),
+                Some((false, expires)) if *expires > Instant::now() => Ok(Claim::Held),
+                _ => {
+                    claims.insert(key.to_string(), (false, Instant::now() + lease));
+                    Ok(Claim::Acquired)
+                }
+            }
+        }
+
+        async fn complete(&self, key: &str) -> Result<(), String> {
+            if let Some(entry) = self.0.lock().unwrap().get_mut(key) {
+                entry.0 = true;
+            }
+            Ok(())
+        }
+
+        async fn release(&self, key: &str) -> Result<(), String> {
+            let mut claims = self.0.lock().unwrap();
+            if claims.get(key).is_some_and(|(completed, _)| !completed) {
+                claims.remove(key);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_racing_delivery_waits_until_the_event_completes() {
+        let claims = MemoryClaims::default();
+
+        assert_eq!(claim(&claims, "resume-1", None, "sub").await.unwrap(), Claim::Acquired);
+        assert_eq!(claim(&claims, "resume-1", None, "sub").await.unwrap(), Claim::Held, "left for redelivery, not dropped");
+
+        release(&claims, "resume-1").await;
+        assert_eq!(claim(&claims, "resume-1", None, "sub").await.unwrap(), Claim::Acquired, "redelivery after release runs");
+
+        complete(&claims, "resume-1").await;
+        release(&claims, "resume-1").await; // A completed claim can't be given back
+        assert_eq!(claim(&claims, "resume-1", None, "sub").await.unwrap(), Claim::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_is_taken_over() {
+        // The worker holding the claim died mid-event: its lease runs out and
+        // the redelivery claims the key instead of ACKing the event away
+        let claims = MemoryClaims::default();
+        assert_eq!(claims.claim("loop-3", None, "loop", Duration::ZERO).await.unwrap(), Claim::Acquired);
+        assert_eq!(claim(&claims, "loop-3", None, "loop").await.unwrap(), Claim::Acquired);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_key_skipped_fresh_key_runs() {
+        let store = MemoryStore::default();
+
+        assert_eq!(claim_key(&store, "order-42").await.unwrap(), Claim::Acquired);
+        mark_completed(&store, "order-42").await;
+
+        assert_eq!(claim_key(&store, "order-42").await.unwrap(), Claim::Completed, "duplicate is skipped");
+        assert_eq!(claim_key(&store, "order-43").await.unwrap(), Claim::Acquired, "fresh key runs");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_delivery_of_a_running_key_is_held() {
+        let store = MemoryStore::default();
+
+        // Both deliveries race: only the first claim runs the job
+        assert_eq!(claim_key(&store, "order-7").await.unwrap(), Claim::Acquired);
+        assert_eq!(claim_key(&store, "order-7").await.unwrap(), Claim::Held);
+
+        // A failed run gives the key back for its retry; a completed one stays
+        release_key(&store, "order-7").await;
+        assert_eq!(claim_key(&store, "order-7").await.unwrap(), Claim::Acquired);
+        mark_completed(&store, "order-7").await;
+        release_key(&store, "order-7").await;
+        assert_eq!(claim_key(&store, "order-7").await.unwrap(), Claim::Completed);
+    }
+
+    #[test]
+    fn test_claim_is_a_single_set_nx_ex() {
+        let args: Vec<String> = claim_cmd("swiftgrid_idempotency:order-1", Duration::from_secs(720))
+            .args_iter()
+            .map(|arg| match arg {
+                redis::Arg::Simple(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                redis::Arg::Cursor => "<cursor>".to_string(),
+            })
+            .collect();
+        assert_eq!(args, ["SET", "swiftgrid_idempotency:order-1", PENDING, "NX", "EX", "720"]);
+    }
+
+    #[test]
+    fn test_lifecycle_claim_only_takes_over_expired_unfinished_leases() {
+        let conflict = &CLAIM_SQL[CLAIM_SQL.find("ON CONFLICT").unwrap()..];
+        assert!(conflict.contains("WHERE processed_jobs.completed_at IS NULL AND processed_jobs.lease_expires_at < NOW()"));
+        // Nothing is returned when the conflict update is skipped, which is
+        // how a held or completed claim is told apart from an acquired one
+        assert!(conflict.trim_end().ends_with("RETURNING idempotency_key"));
+    }
+
+    #[test]
+    fn test_job_key_lease_outlasts_the_job_timeout() {
+        let lease = job_key_lease();
+        if let Some(limit) = crate::job_timeout::job_timeout() {
+            assert!(lease > limit);
+        }
+        assert_eq!(claim_state(None), Claim::Held);
+        assert_eq!(claim_state(Some("1700000000000")), Claim::Completed);
+    }
+}
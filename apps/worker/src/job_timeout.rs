@@ -0,0 +1,156 @@
+//! Hard wall-clock timeout for job execution.
+//!
+//! A last-resort safety net above node-level timeouts (HTTP client, JS
+//! interrupt, LLM stream): if a node is still executing after `JOB_TIMEOUT_MS`
+//! it is dropped (cancelling any in-flight I/O), failed without retry, and
+//! ACKed so it can't hold a concurrency slot forever. Suspended nodes return
+//! 202 right away and lifecycle events are exempt, so neither is affected.
+//!
+//! The main loop also stops reading new jobs while `MAX_CONCURRENT_JOBS` slots
+//! are held (`wait_for_slot`), so a burst queues up in Redis instead of
+//! spawning thousands of tasks that fight over the DB pool.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Default hard cap per job (10 minutes)
+const DEFAULT_JOB_TIMEOUT_MS: u64 = 600_000;
+
+/// Hard cap per job (`JOB_TIMEOUT_MS`, 0 = disabled)
+pub fn job_timeout() -> Option<Duration> {
+    let ms = std::env::var("JOB_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_JOB_TIMEOUT_MS);
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// Run `fut` under the cap. Returns None if it was terminated.
+pub async fn run_with_timeout<F: Future>(limit: Option<Duration>, fut: F) -> Option<F::Output> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Result body for a job terminated by the cap.
+pub fn timeout_body(limit: Duration) -> serde_json::Value {
+    serde_json::json!({
+        "error": format!("Job exceeded hard timeout of {}ms", limit.as_millis()),
+        "timeout": true
+    })
+}
+
+/// Default cap on jobs running at once per worker
+const DEFAULT_MAX_CONCURRENT_JOBS: usize = 100;
+
+/// Signalled whenever a slot is released
+static SLOT_RELEASED: Notify = Notify::const_new();
+
+/// Cap on jobs running at once (`MAX_CONCURRENT_JOBS`, 0 = unbounded)
+pub fn max_concurrent_jobs() -> Option<usize> {
+    let max = std::env::var("MAX_CONCURRENT_JOBS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_JOBS);
+    (max > 0).then_some(max)
+}
+
+/// Wait until fewer than `max` slots are held, then return how many are free
+/// (`usize::MAX` when unbounded). Reads take no more jobs than that.
+pub async fn wait_for_slot(in_flight: &AtomicUsize, max: Option<usize>) -> usize {
+    let Some(max) = max else { return usize::MAX };
+    let mut logged = false;
+    loop {
+        // Register before checking, so a release in between isn't missed
+        let released = SLOT_RELEASED.notified();
+        tokio::pin!(released);
+        released.as_mut().enable();
+        let held = in_flight.load(Ordering::SeqCst);
+        if held < max {
+            return max - held;
+        }
+        if !logged {
+            tracing::debug!(max, "At MAX_CONCURRENT_JOBS; waiting for a slot before reading more jobs");
+            logged = true;
+        }
+        released.await;
+    }
+}
+
+/// A held concurrency slot; released on drop, however the job ends.
+pub struct InFlightSlot(Arc<AtomicUsize>);
+
+impl InFlightSlot {
+    pub fn acquire(in_flight: &Arc<AtomicUsize>) -> Self {
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(in_flight))
+    }
+}
+
+impl Drop for InFlightSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+        SLOT_RELEASED.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_exceeding_cap_is_terminated_and_slot_released() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+
+        let job = {
+            let in_flight = Arc::clone(&in_flight);
+            tokio::spawn(async move {
+                let _slot = InFlightSlot::acquire(&in_flight);
+                run_with_timeout(Some(Duration::from_millis(50)), std::future::pending::<()>()).await
+            })
+        };
+
+        let result = tokio::time::timeout(Duration::from_secs(5), job)
+            .await
+            .expect("capped job should finish")
+            .unwrap();
+        assert!(result.is_none(), "job was terminated");
+        assert_eq!(in_flight.load(Ordering::SeqCst), 0, "slot released");
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_slot_resumes_when_a_job_finishes() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let held = InFlightSlot::acquire(&in_flight);
+        let _other = InFlightSlot::acquire(&in_flight);
+
+        let waiter = {
+            let in_flight = Arc::clone(&in_flight);
+            tokio::spawn(async move { wait_for_slot(&in_flight, Some(2)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished(), "at capacity, so no more reads");
+
+        drop(held);
+        let free = tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .expect("a released slot wakes the loop")
+            .unwrap();
+        assert_eq!(free, 1, "only the released slot is free");
+        assert_eq!(wait_for_slot(&in_flight, Some(4)).await, 3);
+
+        // Unbounded never waits
+        assert_eq!(wait_for_slot(&in_flight, None).await, usize::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_job_within_cap_completes() {
+        let result = run_with_timeout(Some(Duration::from_secs(5)), async { 42 }).await;
+        assert_eq!(result, Some(42));
+        assert_eq!(run_with_timeout(None, async { 7 }).await, Some(7));
+    }
+}
@@ -0,0 +1,212 @@
+//! The pool of JS sandbox threads and their supervisors.
+//!
+//! QuickJS runtimes are single-threaded, so code nodes run on `JS_POOL_SIZE`
+//! dedicated threads, each with its own runtime, context, memory/stack limits
+//! and interrupt handling. All of them pull from the one `JsTask` channel, so
+//! the next task goes to whichever runtime is idle (least busy) and a long
+//! script only holds up its own thread.
+//!
+//! Each thread has a supervisor: if it panics (or the runtime can't be
+//! built), a fresh runtime and context are started on the same channel, so
+//! every `js_sender` clone keeps working. The task that was running when the
+//! thread died has its responder dropped, which callers report as a
+//! retryable error; queued tasks run on the other or the new runtime.
+
+use crate::metrics;
+use crate::nodes::code::{run_js_with_logs, SandboxConfig};
+use crate::nodes::JsTask;
+use rquickjs::{AsyncContext, AsyncRuntime};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+
+/// First restart delay; doubles for each crash in quick succession.
+const RESTART_BASE_DELAY: Duration = Duration::from_millis(100);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(30);
+/// A runtime that lived this long resets the backoff.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
+
+type Receiver = Arc<Mutex<mpsc::Receiver<JsTask>>>;
+
+/// Runtimes in the pool: `JS_POOL_SIZE`, or the number of CPUs up to 4.
+pub fn pool_size() -> usize {
+    std::env::var("JS_POOL_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get().min(4)))
+}
+
+/// Start the supervised JS pool and return the sender for its tasks.
+pub fn spawn() -> mpsc::Sender<JsTask> {
+    let size = pool_size();
+    let (js_sender, js_receiver) = mpsc::channel::<JsTask>(100);
+    spawn_pool(Arc::new(Mutex::new(js_receiver)), size, serve);
+    tracing::info!("JS pool: {} runtime(s)", size);
+    js_sender
+}
+
+fn spawn_pool(receiver: Receiver, size: usize, serve: fn(Receiver)) {
+    for id in 0..size {
+        supervise(receiver.clone(), id, serve);
+    }
+}
+
+/// Run `serve` on a fresh thread until it returns normally (every sender
+/// dropped), restarting it each time it panics.
+fn supervise(receiver: Receiver, id: usize, serve: fn(Receiver)) {
+    std::thread::Builder::new()
+        .name(format!("js-supervisor-{}", id))
+        .spawn(move || {
+            let mut crashes: u32 = 0;
+            loop {
+                let started = Instant::now();
+                let rx = receiver.clone();
+                let outcome = std::thread::Builder::new()
+                    .name(format!("js-runtime-{}", id))
+                    .spawn(move || serve(rx))
+                    .and_then(|handle| handle.join().map_err(|_| std::io::Error::other("JS thread panicked")));
+
+                match outcome {
+                    Ok(()) => break,
+                    Err(e) => {
+                        if started.elapsed() >= HEALTHY_UPTIME {
+                            crashes = 0;
+                        }
+                        let delay = restart_delay(crashes);
+                        crashes = crashes.saturating_add(1);
+                        metrics::METRICS.js_restarted();
+                        tracing::error!(error = %e, runtime = id, restarts = crashes, "JS runtime died; restarting in {:?}", delay);
+                        std::thread::sleep(delay);
+                    }
+                }
+            }
+            tracing::info!(runtime = id, "JS task channel closed; JS runtime stopped");
+        })
+        .expect("failed to spawn JS supervisor thread");
+}
+
+fn restart_delay(crashes: u32) -> Duration {
+    RESTART_BASE_DELAY
+        .saturating_mul(1 << crashes.min(16))
+        .min(RESTART_MAX_DELAY)
+}
+
+/// One runtime's lifetime: build it, then run tasks until the channel closes.
+/// Panics propagate to the supervisor.
+fn serve(receiver: Receiver) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    rt.block_on(async move {
+        let js_runtime = AsyncRuntime::new().unwrap();
+
+        // Set memory limit (16MB default, configurable via JS_MEMORY_LIMIT)
+        let memory_limit: usize = std::env::var("JS_MEMORY_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(16 * 1024 * 1024);
+        js_runtime.set_memory_limit(memory_limit).await;
+
+        // Set max stack size (256KB - prevents stack overflow attacks)
+        js_runtime.set_max_stack_size(256 * 1024).await;
+
+        let js_context = AsyncContext::full(&js_runtime).await.unwrap();
+
+        tracing::info!("✓ JS Sandbox Ready (memory limit: {}MB)", memory_limit / 1024 / 1024);
+
+        // The lock is only held while waiting, never while a task runs, so
+        // the other runtimes keep taking tasks
+        while let Some(task) = next_task(&receiver).await {
+            // Timeout is enforced inside the sandbox via an interrupt handler
+            let config = SandboxConfig::for_task(task.timeout_ms);
+            metrics::METRICS.js_executed();
+            let result = run_js_with_logs(&js_context, task.code, task.inputs, config, task.log_sender, task.cancelled).await;
+
+            let _ = task.responder.send(result);
+        }
+    });
+}
+
+async fn next_task(receiver: &Receiver) -> Option<JsTask> {
+    receiver.lock().await.recv().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodes::code::JsOutput;
+    use tokio::sync::oneshot;
+
+    fn task(code: &str) -> (JsTask, oneshot::Receiver<Result<JsOutput, crate::nodes::JsError>>) {
+        let (tx, rx) = oneshot::channel();
+        let task = JsTask {
+            code: code.to_string(),
+            inputs: None,
+            responder: tx,
+            timeout_ms: None,
+            log_sender: None,
+            cancelled: None,
+        };
+        (task, rx)
+    }
+
+    static BOTH_RUNNING: once_cell::sync::Lazy<std::sync::Barrier> =
+        once_cell::sync::Lazy::new(|| std::sync::Barrier::new(2));
+
+    /// Panics on a task whose code is "panic", waits for a second runtime on
+    /// "rendezvous", echoes anything else.
+    fn flaky(receiver: Receiver) {
+        loop {
+            let Some(task) = receiver.blocking_lock().blocking_recv() else { break };
+            match task.code.as_str() {
+                "panic" => panic!("simulated engine crash"),
+                "rendezvous" => {
+                    BOTH_RUNNING.wait();
+                }
+                _ => {}
+            }
+            let _ = task.responder.send(Ok(JsOutput { value: serde_json::json!(task.code), logs: Vec::new() }));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_restarts_after_panic() {
+        let (sender, receiver) = mpsc::channel(10);
+        spawn_pool(Arc::new(Mutex::new(receiver)), 1, flaky);
+
+        let (crash, crashed) = task("panic");
+        sender.send(crash).await.unwrap();
+        // The in-flight task's responder is dropped, not left hanging
+        assert!(crashed.await.is_err());
+
+        let (ok, answer) = task("still alive");
+        sender.send(ok).await.unwrap();
+        assert_eq!(answer.await.unwrap().unwrap().value, "still alive");
+    }
+
+    #[tokio::test]
+    async fn test_pool_runs_tasks_in_parallel() {
+        let (sender, receiver) = mpsc::channel(10);
+        spawn_pool(Arc::new(Mutex::new(receiver)), 2, flaky);
+
+        // Each task blocks until the other is running, so one runtime would deadlock
+        let (first, first_done) = task("rendezvous");
+        let (second, second_done) = task("rendezvous");
+        sender.send(first).await.unwrap();
+        sender.send(second).await.unwrap();
+        let both = async { (first_done.await.unwrap(), second_done.await.unwrap()) };
+        let (a, b) = tokio::time::timeout(Duration::from_secs(5), both).await.expect("tasks ran serially");
+        assert_eq!(a.unwrap().value, "rendezvous");
+        assert_eq!(b.unwrap().value, "rendezvous");
+    }
+
+    #[test]
+    fn test_restart_delay_backs_off() {
+        assert_eq!(restart_delay(0), RESTART_BASE_DELAY);
+        assert_eq!(restart_delay(3), RESTART_BASE_DELAY * 8);
+        assert_eq!(restart_delay(40), RESTART_MAX_DELAY);
+    }
+}
@@ -0,0 +1,317 @@
+//! Minimal JSON Schema validation for node outputs.
+//!
+//! Covers the keywords contract tests actually use: `type`, `enum`, `const`,
+//! `properties`, `required`, `additionalProperties`, `items` (single schema or
+//! tuple), `minItems`/`maxItems`/`uniqueItems`/`contains`,
+//! `minLength`/`maxLength`, `minimum`/`maximum` (and exclusive forms),
+//! `multipleOf`, `minProperties`/`maxProperties`, `allOf`/`anyOf`/`oneOf`/`not`
+//! and local `$ref`s (`#/definitions/...`, `#/$defs/...`). Other keywords,
+//! including `pattern` and `format`, are ignored as the spec allows for
+//! unknown vocabulary.
+
+use serde_json::Value;
+
+/// Guards against `$ref` cycles
+const MAX_DEPTH: usize = 64;
+
+/// Validate `instance` against `schema`. Returns one message per violation,
+/// prefixed with the JSON Pointer of the offending value (empty = valid).
+pub fn validate(schema: &Value, instance: &Value) -> Vec<String> {
+    let mut validator = Validator { root: schema, errors: Vec::new() };
+    validator.check(schema, instance, "", 0);
+    validator.errors
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    errors: Vec<String>,
+}
+
+impl<'a> Validator<'a> {
+    fn fail(&mut self, path: &str, message: String) {
+        let path = if path.is_empty() { "/" } else { path };
+        self.errors.push(format!("{}: {}", path, message));
+    }
+
+    /// Whether `instance` matches without recording anything (for anyOf/oneOf/not).
+    fn matches(&self, schema: &Value, instance: &Value, depth: usize) -> bool {
+        let mut probe = Validator { root: self.root, errors: Vec::new() };
+        probe.check(schema, instance, "", depth);
+        probe.errors.is_empty()
+    }
+
+    fn check(&mut self, schema: &'a Value, instance: &Value, path: &str, depth: usize) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return self.fail(path, "no value is allowed here".to_string()),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        if depth > MAX_DEPTH {
+            return self.fail(path, "schema nesting too deep (recursive $ref?)".to_string());
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference.strip_prefix('#').and_then(|pointer| self.root.pointer(pointer)) {
+                Some(target) => self.check(target, instance, path, depth + 1),
+                None => self.fail(path, format!("unresolvable $ref '{}'", reference)),
+            }
+        }
+
+        if let Some(expected) = schema.get("type") {
+            let types: Vec<&str> = match expected {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !types.is_empty() && !types.iter().any(|t| is_type(instance, t)) {
+                return self.fail(path, format!("expected {}, got {}", types.join(" or "), type_name(instance)));
+            }
+        }
+
+        if let Some(options) = schema.get("enum").and_then(Value::as_array)
+            && !options.contains(instance)
+        {
+            self.fail(path, format!("{} is not one of {}", instance, Value::Array(options.clone())));
+        }
+        if let Some(expected) = schema.get("const")
+            && expected != instance
+        {
+            self.fail(path, format!("expected {}", expected));
+        }
+
+        match instance {
+            Value::Number(n) => self.check_number(schema, n.as_f64().unwrap_or(0.0), path),
+            Value::String(s) => {
+                let len = s.chars().count() as u64;
+                if let Some(min) = schema.get("minLength").and_then(Value::as_u64)
+                    && len < min
+                {
+                    self.fail(path, format!("shorter than {} characters", min));
+                }
+                if let Some(max) = schema.get("maxLength").and_then(Value::as_u64)
+                    && len > max
+                {
+                    self.fail(path, format!("longer than {} characters", max));
+                }
+            }
+            Value::Array(items) => self.check_array(schema, items, path, depth),
+            Value::Object(obj) => self.check_object(schema, obj, path, depth),
+            _ => {}
+        }
+
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            for sub in all {
+                self.check(sub, instance, path, depth + 1);
+            }
+        }
+        if let Some(any) = schema.get("anyOf").and_then(Value::as_array)
+            && !any.iter().any(|sub| self.matches(sub, instance, depth + 1))
+        {
+            self.fail(path, "does not match any schema in anyOf".to_string());
+        }
+        if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+            let matched = one.iter().filter(|sub| self.matches(sub, instance, depth + 1)).count();
+            if matched != 1 {
+                self.fail(path, format!("matches {} schemas in oneOf (expected exactly 1)", matched));
+            }
+        }
+        if let Some(not) = schema.get("not")
+            && self.matches(not, instance, depth + 1)
+        {
+            self.fail(path, "must not match the 'not' schema".to_string());
+        }
+    }
+
+    fn check_number(&mut self, schema: &serde_json::Map<String, Value>, n: f64, path: &str) {
+        let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+        if let Some(min) = bound("minimum")
+            && n < min
+        {
+            self.fail(path, format!("{} is less than {}", n, min));
+        }
+        if let Some(max) = bound("maximum")
+            && n > max
+        {
+            self.fail(path, format!("{} is greater than {}", n, max));
+        }
+        if let Some(min) = bound("exclusiveMinimum")
+            && n <= min
+        {
+            self.fail(path, format!("{} is not greater than {}", n, min));
+        }
+        if let Some(max) = bound("exclusiveMaximum")
+            && n >= max
+        {
+            self.fail(path, format!("{} is not less than {}", n, max));
+        }
+        if let Some(step) = bound("multipleOf")
+            && step > 0.0
+            && ((n / step) - (n / step).round()).abs() > 1e-9
+        {
+            self.fail(path, format!("{} is not a multiple of {}", n, step));
+        }
+    }
+
+    fn check_array(&mut self, schema: &'a serde_json::Map<String, Value>, items: &[Value], path: &str, depth: usize) {
+        let len = items.len() as u64;
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+            && len < min
+        {
+            self.fail(path, format!("fewer than {} items", min));
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+            && len > max
+        {
+            self.fail(path, format!("more than {} items", max));
+        }
+        if schema.get("uniqueItems").and_then(Value::as_bool) == Some(true)
+            && items.iter().enumerate().any(|(i, a)| items[..i].contains(a))
+        {
+            self.fail(path, "items are not unique".to_string());
+        }
+        if let Some(contains) = schema.get("contains")
+            && !items.iter().any(|item| self.matches(contains, item, depth + 1))
+        {
+            self.fail(path, "no item matches 'contains'".to_string());
+        }
+
+        match schema.get("items") {
+            Some(Value::Array(tuple)) => {
+                for (i, (sub, item)) in tuple.iter().zip(items).enumerate() {
+                    self.check(sub, item, &format!("{}/{}", path, i), depth + 1);
+                }
+            }
+            Some(sub) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.check(sub, item, &format!("{}/{}", path, i), depth + 1);
+                }
+            }
+            None => {}
+        }
+    }
+
+    fn check_object(
+        &mut self,
+        schema: &'a serde_json::Map<String, Value>,
+        obj: &serde_json::Map<String, Value>,
+        path: &str,
+        depth: usize,
+    ) {
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for key in required.iter().filter_map(Value::as_str) {
+                if !obj.contains_key(key) {
+                    self.fail(path, format!("missing required property '{}'", key));
+                }
+            }
+        }
+
+        let len = obj.len() as u64;
+        if let Some(min) = schema.get("minProperties").and_then(Value::as_u64)
+            && len < min
+        {
+            self.fail(path, format!("fewer than {} properties", min));
+        }
+        if let Some(max) = schema.get("maxProperties").and_then(Value::as_u64)
+            && len > max
+        {
+            self.fail(path, format!("more than {} properties", max));
+        }
+
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, value) in obj {
+            let child = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+            match properties.and_then(|p| p.get(key)) {
+                Some(sub) => self.check(sub, value, &child, depth + 1),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => self.fail(&child, "additional property not allowed".to_string()),
+                    Some(sub) => self.check(sub, value, &child, depth + 1),
+                    None => {}
+                },
+            }
+        }
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        _ => true, // Unknown type names don't constrain
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["id", "email", "roles"],
+            "properties": {
+                "id": {"type": "integer", "minimum": 1},
+                "email": {"type": "string", "minLength": 3},
+                "roles": {"type": "array", "items": {"$ref": "#/$defs/role"}, "minItems": 1},
+                "status": {"enum": ["active", "disabled"]}
+            },
+            "additionalProperties": false,
+            "$defs": {"role": {"type": "string"}}
+        })
+    }
+
+    #[test]
+    fn test_valid_instance_has_no_violations() {
+        let user = json!({"id": 7, "email": "a@b.c", "roles": ["admin"], "status": "active"});
+        assert!(validate(&user_schema(), &user).is_empty());
+    }
+
+    #[test]
+    fn test_reports_each_violation_with_its_path() {
+        let user = json!({"id": "7", "roles": ["admin", 3], "status": "gone", "extra": true});
+        let errors = validate(&user_schema(), &user);
+        assert_eq!(
+            errors,
+            vec![
+                "/: missing required property 'email'",
+                "/extra: additional property not allowed",
+                "/id: expected integer, got string",
+                "/roles/1: expected string, got integer",
+                "/status: \"gone\" is not one of [\"active\",\"disabled\"]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_combinators() {
+        let schema = json!({"anyOf": [{"type": "string"}, {"type": "null"}]});
+        assert!(validate(&schema, &json!(null)).is_empty());
+        assert_eq!(validate(&schema, &json!(1)), vec!["/: does not match any schema in anyOf"]);
+
+        let schema = json!({"oneOf": [{"type": "number"}, {"type": "integer"}]});
+        assert!(validate(&schema, &json!(1.5)).is_empty());
+        assert_eq!(validate(&schema, &json!(2)).len(), 1, "2 matches both");
+
+        // Recursive refs terminate
+        let schema = json!({"$ref": "#"});
+        assert!(!validate(&schema, &json!(1)).is_empty());
+    }
+}
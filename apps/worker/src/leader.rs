@@ -0,0 +1,317 @@
+//! Scheduler leader election.
+//!
+//! Every worker process spawns the scheduler loop, but only the one holding
+//! the Redis lease at `swiftgrid:scheduler_leader` does any work; the others
+//! just keep trying to take it. The lease is a `SET NX PX` key holding the
+//! worker id, renewed (compare-and-expire) well within its TTL, so a leader
+//! that dies loses it after at most `SCHEDULER_LEADER_TTL_MS` (default 15s)
+//! and the next follower to poll takes over.
+//!
+//! Renewal runs in its own task (`LeaderLease::spawn_renewal`), so a long
+//! scheduler pass can't let the lease lapse; the scheduler checks
+//! `Leadership::holds` before each step instead of trusting a check made at
+//! the top of the pass.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Redis key holding the current leader's worker id
+pub const LEADER_KEY: &str = "swiftgrid:scheduler_leader";
+
+/// Default lease lifetime
+const DEFAULT_TTL_MS: u64 = 15_000;
+
+/// Extend the lease only if we still hold it
+const RENEW_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return 0
+"#;
+
+/// How long a lease lasts without renewal
+pub fn ttl() -> Duration {
+    Duration::from_millis(
+        std::env::var("SCHEDULER_LEADER_TTL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_MS),
+    )
+}
+
+/// Storage for the leader lease.
+pub trait LeaseStore {
+    /// Take the lease if nobody holds it.
+    fn acquire(
+        &self,
+        holder: &str,
+        ttl: Duration,
+    ) -> impl std::future::Future<Output = Result<bool, String>> + Send;
+
+    /// Extend the lease if `holder` still holds it.
+    fn renew(
+        &self,
+        holder: &str,
+        ttl: Duration,
+    ) -> impl std::future::Future<Output = Result<bool, String>> + Send;
+}
+
+impl LeaseStore for redis::Client {
+    async fn acquire(&self, holder: &str, ttl: Duration) -> Result<bool, String> {
+        let mut con = self
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let reply: Option<String> = acquire_cmd(holder, ttl)
+            .query_async(&mut con)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(reply.is_some())
+    }
+
+    async fn renew(&self, holder: &str, ttl: Duration) -> Result<bool, String> {
+        let mut con = self
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let renewed: i64 = renew_cmd(holder, ttl)
+            .query_async(&mut con)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(renewed == 1)
+    }
+}
+
+/// `SET NX PX`: take the lease only if the key is free
+fn acquire_cmd(holder: &str, ttl: Duration) -> redis::Cmd {
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(LEADER_KEY).arg(holder).arg("NX").arg("PX").arg(ttl.as_millis() as u64);
+    cmd
+}
+
+/// Compare-and-expire: extend the lease only while it still names `holder`
+fn renew_cmd(holder: &str, ttl: Duration) -> redis::Cmd {
+    let mut cmd = redis::cmd("EVAL");
+    cmd.arg(RENEW_SCRIPT).arg(1).arg(LEADER_KEY).arg(holder).arg(ttl.as_millis() as u64);
+    cmd
+}
+
+/// Whether this worker holds the lease, as last renewed. Cheap to clone and
+/// check, for the loop doing the leader's work.
+#[derive(Clone, Debug)]
+pub struct Leadership {
+    is_leader: Arc<AtomicBool>,
+    /// When the lease we last renewed runs out (measured from before the
+    /// renewal was sent, so it never outlives the key in Redis)
+    held_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Leadership {
+    /// Whether this worker leads right now: the last renewal succeeded and
+    /// its TTL hasn't run out, even if the renewal task has stalled since.
+    pub fn holds(&self) -> bool {
+        self.is_leader.load(Ordering::Relaxed)
+            && self.held_until.lock().unwrap().is_some_and(|until| Instant::now() < until)
+    }
+}
+
+/// This worker's view of the scheduler lease.
+#[derive(Debug)]
+pub struct LeaderLease {
+    holder: String,
+    ttl: Duration,
+    leadership: Leadership,
+    last_refresh: Option<Instant>,
+}
+
+impl LeaderLease {
+    /// `is_leader` is shared with the heartbeat so it can report the role.
+    pub fn new(holder: impl Into<String>, ttl: Duration, is_leader: Arc<AtomicBool>) -> Self {
+        let leadership = Leadership { is_leader, held_until: Arc::new(Mutex::new(None)) };
+        Self { holder: holder.into(), ttl, leadership, last_refresh: None }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leadership.is_leader.load(Ordering::Relaxed)
+    }
+
+    pub fn leadership(&self) -> Leadership {
+        self.leadership.clone()
+    }
+
+    /// Keep the lease refreshed from a background task, every third of the
+    /// TTL, independent of how long the leader's work takes.
+    pub fn spawn_renewal<S: LeaseStore + Send + Sync + 'static>(mut self, store: S) -> Leadership {
+        let leadership = self.leadership();
+        let every = (self.ttl / 3).max(Duration::from_millis(100));
+        tokio::spawn(async move {
+            loop {
+                self.refresh(&store).await;
+                tokio::time::sleep(every).await;
+            }
+        });
+        leadership
+    }
+
+    /// Renew (as leader) or try to take (as follower) the lease, at most every
+    /// third of the TTL. Returns whether this worker leads. Store errors count as
+    /// not leading: a missed tick is better than two schedulers.
+    pub async fn refresh<S: LeaseStore>(&mut self, store: &S) -> bool {
+        if let Some(at) = self.last_refresh
+            && at.elapsed() < self.ttl / 3
+        {
+            return self.is_leader();
+        }
+        let started = Instant::now();
+        self.last_refresh = Some(started);
+
+        let was_leader = self.is_leader();
+        let leading = if was_leader {
+            // A lapsed lease that nobody took yet can be re-acquired straight away
+            match store.renew(&self.holder, self.ttl).await {
+                Ok(true) => Ok(true),
+                Ok(false) => store.acquire(&self.holder, self.ttl).await,
+                Err(e) => Err(e),
+            }
+        } else {
+            store.acquire(&self.holder, self.ttl).await
+        };
+
+        let leading = leading.unwrap_or_else(|e| {
+            tracing::error!("Scheduler: Leader lease check failed: {}", e);
+            false
+        });
+        if leading != was_leader {
+            if leading {
+                tracing::info!("Scheduler: {} is now the scheduler leader", self.holder);
+            } else {
+                tracing::info!("Scheduler: {} lost the scheduler lease, standing by", self.holder);
+            }
+        }
+        *self.leadership.held_until.lock().unwrap() = leading.then(|| started + self.ttl);
+        self.leadership.is_leader.store(leading, Ordering::Relaxed);
+        leading
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Single lease; `expire` simulates the TTL lapsing.
+    #[derive(Default)]
+    struct MemoryLease(Mutex<Option<String>>);
+
+    impl MemoryLease {
+        fn expire(&self) {
+            *self.0.lock().unwrap() = None;
+        }
+    }
+
+    impl LeaseStore for MemoryLease {
+        async fn acquire(&self, holder: &str, _ttl: Duration) -> Result<bool, String> {
+            let mut lease = self.0.lock().unwrap();
+            if lease.is_some() {
+                return Ok(false);
+            }
+            *lease = Some(holder.to_string());
+            Ok(true)
+        }
+
+        async fn renew(&self, holder: &str, _ttl: Duration) -> Result<bool, String> {
+            Ok(self.0.lock().unwrap().as_deref() == Some(holder))
+        }
+    }
+
+    fn args(cmd: &redis::Cmd) -> Vec<String> {
+        cmd.args_iter()
+            .map(|arg| match arg {
+                redis::Arg::Simple(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                redis::Arg::Cursor => "<cursor>".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lease_commands() {
+        let ttl = Duration::from_secs(15);
+        assert_eq!(
+            args(&acquire_cmd("worker-a", ttl)),
+            ["SET", LEADER_KEY, "worker-a", "NX", "PX", "15000"]
+        );
+        assert_eq!(
+            args(&renew_cmd("worker-a", ttl)),
+            ["EVAL", RENEW_SCRIPT, "1", LEADER_KEY, "worker-a", "15000"]
+        );
+        // The renewal only extends a key that still holds our id
+        assert!(RENEW_SCRIPT.contains("redis.call('GET', KEYS[1]) == ARGV[1]"));
+    }
+
+    fn lease(holder: &str) -> LeaderLease {
+        // Zero TTL so every refresh hits the store
+        LeaderLease::new(holder, Duration::ZERO, Arc::new(AtomicBool::new(false)))
+    }
+
+    #[tokio::test]
+    async fn test_one_leader_and_failover() {
+        let store = MemoryLease::default();
+        let mut a = lease("worker-a");
+        let mut b = lease("worker-b");
+
+        assert!(a.refresh(&store).await);
+        assert!(!b.refresh(&store).await);
+        assert!(a.refresh(&store).await, "leader renews");
+
+        // worker-a dies; its lease expires and worker-b takes over
+        store.expire();
+        assert!(b.refresh(&store).await);
+        assert!(!a.refresh(&store).await, "old leader steps down");
+        assert!(!a.is_leader());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_is_throttled_to_a_third_of_the_ttl() {
+        let store = MemoryLease::default();
+        let mut a = LeaderLease::new("worker-a", Duration::from_secs(60), Arc::new(AtomicBool::new(false)));
+        assert!(a.refresh(&store).await);
+
+        // Within the throttle window the cached role is returned without a store call
+        store.expire();
+        assert!(a.refresh(&store).await);
+    }
+
+    #[tokio::test]
+    async fn test_leadership_lapses_with_the_ttl_without_renewal() {
+        let store = MemoryLease::default();
+        let mut a = LeaderLease::new("worker-a", Duration::from_millis(50), Arc::new(AtomicBool::new(false)));
+        let leadership = a.leadership();
+        assert!(!leadership.holds());
+
+        assert!(a.refresh(&store).await);
+        assert!(leadership.holds());
+
+        // Nobody renewed (a stalled task, an unreachable Redis): the lease in
+        // Redis is gone by now, so the work loop must stop acting as leader
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(a.is_leader());
+        assert!(!leadership.holds());
+    }
+
+    #[tokio::test]
+    async fn test_losing_the_lease_is_seen_by_the_work_loop() {
+        let store = MemoryLease::default();
+        let mut a = lease("worker-a");
+        let leadership = a.leadership();
+        // Zero TTL leases lapse at once; use a real one for this check
+        a.ttl = Duration::from_secs(60);
+        assert!(a.refresh(&store).await);
+        assert!(leadership.holds());
+
+        *store.0.lock().unwrap() = Some("worker-b".to_string());
+        a.last_refresh = None;
+        assert!(!a.refresh(&store).await);
+        assert!(!leadership.holds());
+    }
+}
@@ -11,18 +11,68 @@
 //! - `scheduler`: Background job scheduler
 //! - `nodes`: Node type execution handlers
 //! - `cancellation`: Real-time cancellation via Redis pub/sub
+//! - `circuit_breaker`: Per-host circuit breaker for HTTP and WebSocket nodes
+//! - `compression`: gzip/deflate request body compression
+//! - `dead_letter`: Dead letter queue for poison messages
+//! - `health`: `/healthz` and `/readyz` probes for container orchestration
+//! - `idempotency`: Custom idempotency keys for cross-run dedup
+//! - `js_runtime`: Pool of supervised JS sandbox threads, restarted if they panic
+//! - `job_timeout`: Hard wall-clock cap on job execution
+//! - `json_schema`: JSON Schema validation for HTTP response contracts
+//! - `leader`: Redis lease so only one worker runs the scheduler
+//! - `logging`: `tracing` subscriber with pretty and JSON (`LOG_FORMAT`) output
+//! - `metrics`: Prometheus counters and the `/metrics` endpoint
+//! - `node_error`: Typed node failures (transient, permanent, cancelled, suspended)
+//! - `orchestrator`: Orchestrator notifications with retry/fallback queue
+//! - `priority`: High/default/low job streams and the weighted order workers read them in
+//! - `token_budget`: Per-provider tokens-per-minute budgets for LLM nodes
+//! - `templating`: `{{...}}` resolution against recorded node outputs
+//! - `trace_context`: W3C trace context propagation to downstream HTTP calls
+//! - `rerun`: Re-execute a single node of a past run for debugging
+//! - `responder`: Bare HTTP/1.1 responder behind the metrics and health ports
+//! - `retention`: Scheduled cleanup of old stream chunks and run events
+//! - `startup`: Waits (with backoff) for Postgres and Redis when the worker starts first
+//! - `ssrf`: Blocks HTTP nodes from calling internal addresses (allowlist, DNS rebinding)
+//! - `warnings`: Non-fatal warnings attached to node results
 
 pub mod cancellation;
+pub mod circuit_breaker;
+pub mod compression;
+pub mod dead_letter;
 pub mod events;
+pub mod health;
+pub mod idempotency;
+pub mod job_timeout;
+pub mod js_runtime;
+pub mod json_schema;
+pub mod leader;
+pub mod logging;
+pub mod metrics;
+pub mod node_error;
 pub mod nodes;
+pub mod orchestrator;
+pub mod priority;
+pub mod rerun;
+pub mod responder;
+pub mod retention;
 pub mod retry;
 pub mod scheduler;
+pub mod ssrf;
+pub mod startup;
 pub mod streaming;
+pub mod templating;
+pub mod token_budget;
+pub mod trace_context;
 pub mod types;
+pub mod warnings;
 
 // Re-export commonly used items
 pub use cancellation::CancellationRegistry;
+pub use circuit_breaker::CircuitBreakers;
 pub use events::{log_event, EventType};
+pub use node_error::{NodeError, NodeResult};
 pub use retry::{calculate_backoff, is_retryable_error};
 pub use streaming::StreamContext;
+pub use token_budget::TokenBudgets;
 pub use types::*;
+pub use warnings::Warnings;
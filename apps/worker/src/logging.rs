@@ -0,0 +1,285 @@
+//! Structured logging.
+//!
+//! The worker logs through `tracing`; this module is the subscriber that prints
+//! it. `LOG_FORMAT=json` writes one JSON object per line for log aggregators,
+//! anything else (default `pretty`) a human-readable line. Fields of the
+//! enclosing spans are attached to every event, so everything logged while a
+//! job runs carries its `run_id`, `node_id` and `attempt`.
+//!
+//! Info and below go to stdout, warnings and errors to stderr. Debug events
+//! are only printed with `WORKER_VERBOSE=1`.
+
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_env() -> Self {
+        match std::env::var("LOG_FORMAT").as_deref() {
+            Ok("json") => LogFormat::Json,
+            _ => LogFormat::Pretty,
+        }
+    }
+}
+
+/// Install the logger as the global subscriber. Call once, at startup.
+pub fn init() {
+    let verbose = std::env::var("WORKER_VERBOSE").map(|v| v == "1" || v == "true").unwrap_or(false);
+    let logger = Logger::new(LogFormat::from_env(), if verbose { Level::DEBUG } else { Level::INFO });
+    if tracing::subscriber::set_global_default(logger).is_err() {
+        eprintln!("Logging: a subscriber is already installed");
+    }
+}
+
+struct SpanData {
+    parent: Option<u64>,
+    fields: Map<String, Value>,
+    refs: usize,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct Logger {
+    format: LogFormat,
+    max_level: Level,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl Logger {
+    pub fn new(format: LogFormat, max_level: Level) -> Self {
+        Self { format, max_level, next_id: AtomicU64::new(1), spans: Mutex::new(HashMap::new()) }
+    }
+
+    fn current(&self) -> Option<u64> {
+        ENTERED.with(|e| e.borrow().last().copied())
+    }
+
+    /// Fields of `id` and its ancestors; inner spans win over outer ones.
+    fn span_fields(&self, id: Option<u64>) -> Map<String, Value> {
+        let spans = self.spans.lock().unwrap();
+        let mut chain = Vec::new();
+        let mut next = id;
+        while let Some(id) = next {
+            let Some(span) = spans.get(&id) else { break };
+            chain.push(&span.fields);
+            next = span.parent;
+        }
+        let mut fields = Map::new();
+        for span_fields in chain.into_iter().rev() {
+            fields.extend(span_fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        fields
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.max_level
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let parent = match attrs.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if attrs.is_contextual() => self.current(),
+            None => None,
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        self.spans.lock().unwrap().insert(id, SpanData { parent, fields: visitor.fields, refs: 1 });
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.fields.extend(visitor.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let parent = match event.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if event.is_contextual() => self.current(),
+            None => None,
+        };
+        let mut fields = self.span_fields(parent);
+        fields.extend(visitor.fields);
+        let message = match fields.remove("message") {
+            Some(Value::String(s)) => s,
+            Some(other) => other.to_string(),
+            None => String::new(),
+        };
+
+        let metadata = event.metadata();
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let line = format_line(self.format, &timestamp, *metadata.level(), metadata.target(), &message, fields);
+        if *metadata.level() <= Level::WARN {
+            let _ = writeln!(std::io::stderr(), "{}", line);
+        } else {
+            let _ = writeln!(std::io::stdout(), "{}", line);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|e| e.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        let id = span.into_u64();
+        ENTERED.with(|e| {
+            let mut entered = e.borrow_mut();
+            if let Some(pos) = entered.iter().rposition(|s| *s == id) {
+                entered.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&id.into_u64()) {
+            span.refs += 1;
+        }
+        id.clone()
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(span) = spans.get_mut(&id.into_u64()) else {
+            return false;
+        };
+        span.refs -= 1;
+        if span.refs == 0 {
+            spans.remove(&id.into_u64());
+            return true;
+        }
+        false
+    }
+}
+
+/// Render one event.
+fn format_line(
+    format: LogFormat,
+    timestamp: &str,
+    level: Level,
+    target: &str,
+    message: &str,
+    fields: Map<String, Value>,
+) -> String {
+    match format {
+        LogFormat::Json => {
+            let mut line = Map::new();
+            line.insert("timestamp".to_string(), Value::from(timestamp));
+            line.insert("level".to_string(), Value::from(level.as_str()));
+            line.insert("target".to_string(), Value::from(target));
+            line.insert("message".to_string(), Value::from(message));
+            line.extend(fields);
+            Value::Object(line).to_string()
+        }
+        LogFormat::Pretty => {
+            let mut line = format!("{} {:>5} {}", timestamp, level.as_str(), message);
+            for (key, value) in fields {
+                match value {
+                    Value::String(s) => line.push_str(&format!(" {}={}", key, s)),
+                    other => line.push_str(&format!(" {}={}", key, other)),
+                }
+            }
+            line
+        }
+    }
+}
+
+/// Collects recorded fields as JSON values.
+#[derive(Default)]
+struct FieldVisitor {
+    fields: Map<String, Value>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields.insert(field.name().to_string(), Value::from(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_json_and_pretty_lines() {
+        let f = fields(json!({"run_id": "r1", "attempt": 2}));
+        let line = format_line(LogFormat::Json, "2026-01-01T00:00:00.000Z", Level::INFO, "swiftgrid", "Node completed", f.clone());
+        let parsed: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed["level"], "INFO");
+        assert_eq!(parsed["message"], "Node completed");
+        assert_eq!(parsed["run_id"], "r1");
+        assert_eq!(parsed["attempt"], 2);
+
+        let line = format_line(LogFormat::Pretty, "2026-01-01T00:00:00.000Z", Level::WARN, "swiftgrid", "Retrying", f);
+        assert_eq!(line, "2026-01-01T00:00:00.000Z  WARN Retrying attempt=2 run_id=r1");
+    }
+
+    #[test]
+    fn test_span_fields_are_inherited() {
+        let logger = Logger::new(LogFormat::Json, Level::DEBUG);
+        tracing::subscriber::with_default(logger, || {
+            let span = tracing::info_span!("job", run_id = "r1", node_id = "n1");
+            let _guard = span.enter();
+            let inner = tracing::info_span!("step", node_id = "n2");
+            let _inner = inner.enter();
+
+            tracing::dispatcher::get_default(|dispatch| {
+                let logger = dispatch.downcast_ref::<Logger>().unwrap();
+                let fields = logger.span_fields(logger.current());
+                assert_eq!(fields["run_id"], "r1");
+                assert_eq!(fields["node_id"], "n2", "inner span wins");
+            });
+        });
+    }
+}
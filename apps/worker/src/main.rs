@@ -3,11 +3,10 @@
 //! This worker consumes jobs from Redis streams and executes workflow nodes.
 
 use redis::{AsyncCommands, RedisResult, streams::{StreamReadOptions, StreamReadReply}};
-use rquickjs::{AsyncContext, AsyncRuntime};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::error::Error;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use once_cell::sync::Lazy;
@@ -18,47 +17,51 @@ use uuid::Uuid;
 // Import from library modules
 use swiftgrid_worker::{
     cancellation::{self, CancellationRegistry},
-    events::{has_node_completed, log_event, log_event_with_retry, EventType},
-    nodes::{self, code::run_js_safely, JsTask},
-    retry::{calculate_backoff, is_retryable_error},
+    circuit_breaker::{self, BreakerConfig, CircuitBreakers},
+    dead_letter,
+    health,
+    idempotency,
+    job_timeout::{self, InFlightSlot},
+    js_runtime,
+    leader,
+    logging,
+    metrics,
+    node_error::{self, NodeError, NodeResult},
+    orchestrator,
+    priority,
+    events::{self, has_node_completed, log_event, log_event_with_retry, EventType},
+    nodes::{self, code::{self, JsErrorKind, SandboxConfig}, llm::RateLimitHolds, JsTask},
+    retry::{is_retryable_error, retry_backoff, retry_within_deadline, DURABLE_RETRY_AFTER},
     scheduler,
+    ssrf::{self, SsrfPolicy},
+    startup,
     streaming::StreamContext,
-    types::{ExecutionResult, NodeType, WorkerJob},
+    token_budget::{self, TokenBudgets},
+    trace_context::TraceContext,
+    types::{ExecutionResult, JobPriority, NodeType, NodeWarning, ResumeVia, RunEnv, Suspension, SuspensionKind, WorkerJob},
+    warnings::Warnings,
 };
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 // =============================================================================
 // CONSTANTS
 // =============================================================================
 
-const STREAM_JOBS: &str = "swiftgrid_stream";
 const STREAM_RESULTS: &str = "swiftgrid_results";
 
-// Worker statistics for heartbeat
-static JOBS_PROCESSED: AtomicU64 = AtomicU64::new(0);
+// Worker statistics for heartbeat (job counters live in metrics::METRICS)
 static START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
 
-// Performance: Set WORKER_VERBOSE=1 to enable debug logging in hot paths
-// Default is OFF for maximum performance
-fn is_verbose() -> bool {
-    std::env::var("WORKER_VERBOSE").map(|v| v == "1" || v == "true").unwrap_or(false)
-}
-
-macro_rules! verbose_log {
-    ($($arg:tt)*) => {
-        if is_verbose() {
-            println!($($arg)*);
-        }
-    };
-}
-
 // =============================================================================
 // MAIN
 // =============================================================================
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    println!("SwiftGrid Worker initializing...");
+    // Debug events (hot-path detail) only with WORKER_VERBOSE=1
+    logging::init();
+    tracing::info!("SwiftGrid Worker initializing...");
 
     // Database connection pool
     let database_url = std::env::var("DATABASE_URL")
@@ -72,13 +75,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .and_then(|v| v.parse().ok())
         .unwrap_or(20);
 
-    let db_pool = PgPoolOptions::new()
-        .max_connections(pool_size)
-        .acquire_timeout(Duration::from_secs(30)) // Wait up to 30s for a connection
-        .connect(&database_url)
-        .await?;
+    // Dependencies may still be starting (e.g. in a container orchestrator):
+    // wait for them instead of crash-looping
+    let startup_wait = startup::startup_max_wait();
+    let db_pool = startup::wait_for("PostgreSQL", startup_wait, || {
+        PgPoolOptions::new()
+            .max_connections(pool_size)
+            .acquire_timeout(Duration::from_secs(30)) // Wait up to 30s for a connection
+            .connect(&database_url)
+    })
+    .await?;
 
-    println!("✓ Connected to PostgreSQL");
+    tracing::info!("✓ Connected to PostgreSQL");
 
     // Pool pressure monitor (logs when busy connections exceed 80% of max)
     {
@@ -87,10 +95,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let max = pool_size.max(1);
             loop {
                 let size = db_pool.size();
-                let idle = db_pool.num_idle();
+                let idle = db_pool.num_idle() as u32;
                 let busy = size.saturating_sub(idle);
                 if busy as f32 / max as f32 >= 0.8 {
-                    println!(
+                    tracing::warn!(
                         "⚠️  DB pool pressure: busy={} idle={} max={}",
                         busy,
                         idle,
@@ -106,9 +114,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let redis_url =
         std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
     let redis_client = redis::Client::open(redis_url)?;
-    let mut con = redis_client.get_multiplexed_async_connection().await?;
+    let mut con = startup::wait_for("Redis", startup_wait, || redis_client.get_multiplexed_async_connection()).await?;
 
-    println!("✓ Connected to Redis");
+    tracing::info!("✓ Connected to Redis");
 
     // HTTP client (reused for all requests)
     static APP_USER_AGENT: &str =
@@ -118,66 +126,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .timeout(Duration::from_secs(30))
         .build()?;
 
-    // JS runtime thread
-    let (js_sender, mut js_receiver) = mpsc::channel::<JsTask>(100);
+    // HTTP nodes get their own client that refuses internal addresses; the
+    // shared one above also talks to the API, which is usually on localhost
+    let ssrf_policy = Arc::new(SsrfPolicy::from_env());
+    let node_http_client = ssrf::guard(
+        reqwest::Client::builder()
+            .user_agent(APP_USER_AGENT)
+            .timeout(Duration::from_secs(30)),
+        ssrf_policy.clone(),
+    )
+    .build()?;
 
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .unwrap();
-
-        rt.block_on(async move {
-            let js_runtime = AsyncRuntime::new().unwrap();
-            
-            // Set memory limit (16MB default, configurable via JS_MEMORY_LIMIT)
-            let memory_limit: usize = std::env::var("JS_MEMORY_LIMIT")
-                .ok()
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(16 * 1024 * 1024);
-            js_runtime.set_memory_limit(memory_limit).await;
-            
-            // Set max stack size (256KB - prevents stack overflow attacks)
-            js_runtime.set_max_stack_size(256 * 1024).await;
-            
-            let js_context = AsyncContext::full(&js_runtime).await.unwrap();
-
-            println!("✓ JS Sandbox Ready (memory limit: {}MB)", memory_limit / 1024 / 1024);
-
-            // Timeout in ms (default 5 seconds)
-            let timeout_ms: u64 = std::env::var("JS_TIMEOUT_MS")
-                .ok()
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(5000);
-
-            while let Some(task) = js_receiver.recv().await {
-                // Set up interrupt handler with deadline for THIS execution
-                let deadline = std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms);
-                
-                // The interrupt handler is called periodically during JS execution
-                // Return true to abort, false to continue
-                js_runtime.set_interrupt_handler(Some(Box::new(move || {
-                    std::time::Instant::now() > deadline
-                }))).await;
-                
-                let result = run_js_safely(&js_context, task.code, task.inputs).await;
-                
-                // Clear interrupt handler after execution
-                js_runtime.set_interrupt_handler(None::<Box<dyn FnMut() -> bool>>).await;
-                
-                let _ = task.responder.send(result);
-            }
-        });
-    });
+    // JS runtime pool (each thread restarted by its supervisor if it dies)
+    let js_sender = js_runtime::spawn();
 
     // Redis consumer group setup
     let group_name = "workers_group";
     let consumer_name = format!("worker_{}", &Uuid::new_v4().to_string()[..8]);
-    let _: RedisResult<()> = con
-        .xgroup_create_mkstream(STREAM_JOBS, group_name, "$")
-        .await;
+    for stream in priority::STREAMS {
+        let _: RedisResult<()> = con.xgroup_create_mkstream(stream, group_name, "$").await;
+    }
 
-    println!(
+    tracing::info!(
         "Worker '{}' listening for jobs... (Ctrl+C to stop)",
         consumer_name
     );
@@ -187,59 +157,126 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Cancellation registry (shared across all jobs)
     let cancel_registry = Arc::new(CancellationRegistry::new());
 
+    // Per-host circuit breakers for HTTP nodes (shared across all jobs)
+    let circuit_breakers = circuit_breaker::new_registry(BreakerConfig::from_env());
+
+    // Per-provider TPM budgets for LLM nodes (shared across all jobs)
+    let token_budgets = token_budget::new_registry();
+
+    // Provider rate-limit resets for LLM nodes, per host and API key (shared across all jobs)
+    let rate_limit_holds = RateLimitHolds::new();
+
+    // Cap on in-flight map children (counted in Redis across all batches and workers)
+    let map_limiter = nodes::ChildLimiter::from_env();
+
     // Spawn the cancellation listener (Redis pub/sub)
     let cancel_redis = redis_client.clone();
+    let cancel_db = db_pool.clone();
     let cancel_registry_listener = cancel_registry.clone();
     tokio::spawn(async move {
-        cancellation::listen_for_cancellations(cancel_redis, cancel_registry_listener).await;
+        cancellation::listen_for_cancellations(cancel_redis, cancel_db, cancel_registry_listener).await;
     });
 
-    // Spawn the scheduler loop
+    // Spawn the scheduler loop (only the lease holder does work)
     let scheduler_redis = redis_client.clone();
     let scheduler_db = db_pool.clone();
+    let scheduler_http = http_client.clone();
+    let is_leader = Arc::new(AtomicBool::new(false));
+    let lease = leader::LeaderLease::new(consumer_name.clone(), leader::ttl(), Arc::clone(&is_leader));
+    let leadership = lease.spawn_renewal(redis_client.clone());
     tokio::spawn(async move {
-        scheduler::run(scheduler_redis, scheduler_db).await;
+        scheduler::run(scheduler_redis, scheduler_db, scheduler_http, leadership).await;
     });
 
+    // Serve Prometheus metrics if METRICS_PORT is set
+    if let Some(port) = metrics::port() {
+        let metrics_in_flight = Arc::clone(&in_flight);
+        tokio::spawn(async move {
+            metrics::serve(port, metrics_in_flight).await;
+        });
+    }
+
+    // Liveness/readiness probes if HEALTH_PORT is set
+    if let Some(port) = health::port() {
+        let deps = health::Dependencies {
+            redis: redis_client.clone(),
+            db: db_pool.clone(),
+            js: js_sender.clone(),
+        };
+        tokio::spawn(async move {
+            health::serve(port, deps).await;
+        });
+    }
+
+    // Periodically record per-node-type latency summaries
+    if let Some(interval) = metrics::latency_flush_interval() {
+        let latency_db = db_pool.clone();
+        let latency_worker_id = consumer_name.clone();
+        tokio::spawn(async move {
+            metrics::flush_latency_loop(latency_db, latency_worker_id, interval).await;
+        });
+    }
+
     // Spawn the heartbeat loop
     let heartbeat_redis = redis_client.clone();
     let heartbeat_worker_id = consumer_name.clone();
     let heartbeat_in_flight = Arc::clone(&in_flight);
     tokio::spawn(async move {
-        heartbeat_loop(heartbeat_redis, heartbeat_worker_id, heartbeat_in_flight).await;
+        heartbeat_loop(heartbeat_redis, heartbeat_worker_id, heartbeat_in_flight, is_leader).await;
     });
 
-    // Main job processing loop
+    // Main job processing loop (`reads` rotates which priority tier is read first)
+    let max_jobs = job_timeout::max_concurrent_jobs();
+    tracing::info!("Max concurrent jobs: {}", max_jobs.map_or("unbounded".to_string(), |m| m.to_string()));
+    let mut reads: u64 = 0;
     loop {
         tokio::select! {
             _ = tokio::signal::ctrl_c() => {
-                println!("\nShutdown signal received, stopping...");
+                tracing::info!("Shutdown signal received, stopping...");
                 break;
             }
-            result = read_next_job(&mut con, group_name, &consumer_name) => {
-                if let Some((msg_id, job)) = result {
-                    verbose_log!(
-                        "Processing Node: {} (run: {:?}, attempt: {})",
-                        job.id,
-                        job.run_id,
-                        job.retry_count + 1
+            jobs = async {
+                // Backpressure: leave jobs in Redis while every slot is taken
+                let free_slots = job_timeout::wait_for_slot(&in_flight, max_jobs).await;
+                read_next_jobs(&mut con, &db_pool, group_name, &consumer_name, &mut reads, free_slots).await
+            } => {
+                for (stream, msg_id, job, deliveries) in jobs {
+                    // Everything logged while the job runs carries these fields
+                    let span = tracing::info_span!(
+                        "job",
+                        run_id = job.run_id.as_deref(),
+                        node_id = %job.id,
+                        attempt = job.retry_count + 1,
+                        delivery = deliveries,
+                        trace_id = tracing::field::Empty,
+                        status = tracing::field::Empty,
                     );
+                    tracing::debug!(parent: &span, "Processing job");
 
                     let h_client = http_client.clone();
+                    let n_client = node_http_client.clone();
+                    let policy = ssrf_policy.clone();
                     let r_client = redis_client.clone();
                     let pool = db_pool.clone();
                     let j_sender = js_sender.clone();
-                    let in_flight_clone = Arc::clone(&in_flight);
                     let cancel_reg = cancel_registry.clone();
+                    let breakers = circuit_breakers.clone();
+                    let budgets = token_budgets.clone();
+                    let holds = rate_limit_holds.clone();
+                    let limiter = map_limiter.clone();
                     let group = group_name.to_string();
 
-                    in_flight.fetch_add(1, Ordering::SeqCst);
+                    // Released when the task ends, even if the job panics
+                    let slot = InFlightSlot::acquire(&in_flight);
 
-                    tokio::spawn(async move {
-                        process_job(job, h_client, r_client, pool, j_sender, msg_id, group, cancel_reg).await;
-                        in_flight_clone.fetch_sub(1, Ordering::SeqCst);
-                        JOBS_PROCESSED.fetch_add(1, Ordering::Relaxed);
-                    });
+                    tokio::spawn(
+                        async move {
+                            let _slot = slot;
+                            process_job(job, h_client, n_client, policy, r_client, pool, j_sender, msg_id, stream, group, cancel_reg, breakers, budgets, holds, limiter).await;
+                            metrics::METRICS.job_processed();
+                        }
+                        .instrument(span),
+                    );
                 }
             }
         }
@@ -248,13 +285,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Wait for in-flight jobs
     let pending = in_flight.load(Ordering::SeqCst);
     if pending > 0 {
-        println!("Waiting for {} in-flight job(s) to complete...", pending);
+        tracing::info!("Waiting for {} in-flight job(s) to complete...", pending);
         while in_flight.load(Ordering::SeqCst) > 0 {
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
     }
 
-    println!("Worker '{}' shut down gracefully.", consumer_name);
+    tracing::info!("Worker '{}' shut down gracefully.", consumer_name);
     Ok(())
 }
 
@@ -262,40 +299,119 @@ async fn main() -> Result<(), Box<dyn Error>> {
 // JOB READING
 // =============================================================================
 
-async fn read_next_job(
+/// Read the next job(s), trying the priority streams in `priority::read_order`.
+///
+/// Each stream is polled without blocking, and the first that has a message
+/// wins. Only when all are empty does the read block, on as many streams as
+/// there are `free_slots` (taken in read order), so a read never takes
+/// more jobs than `MAX_CONCURRENT_JOBS` leaves room for.
+async fn read_next_jobs(
     con: &mut redis::aio::MultiplexedConnection,
+    db_pool: &PgPool,
     group_name: &str,
     consumer_name: &str,
-) -> Option<(String, WorkerJob)> {
-    let opts = StreamReadOptions::default()
-        .group(group_name, consumer_name)
-        .count(1)
-        .block(1000);
-
-    let reply: StreamReadReply = con
-        .xread_options::<&str, &str, StreamReadReply>(&[STREAM_JOBS], &[">"], &opts)
+    reads: &mut u64,
+    free_slots: usize,
+) -> Vec<(&'static str, String, WorkerJob, u32)> {
+    let order = priority::read_order(*reads);
+    *reads = reads.wrapping_add(1);
+
+    let poll = StreamReadOptions::default().group(group_name, consumer_name).count(1);
+    for stream in order {
+        let Ok(reply) = con
+            .xread_options::<&str, &str, StreamReadReply>(&[stream], &[">"], &poll)
+            .await
+        else {
+            continue;
+        };
+        if reply.keys.iter().any(|key| !key.ids.is_empty()) {
+            return take_jobs(con, db_pool, group_name, reply).await;
+        }
+    }
+
+    // COUNT is per stream, so each stream blocked on can hand back one job
+    let streams = &order[..free_slots.clamp(1, order.len())];
+    let block = poll.block(1000);
+    match con
+        .xread_options::<&str, &str, StreamReadReply>(streams, &[">"; 3][..streams.len()], &block)
         .await
-        .ok()?;
+    {
+        Ok(reply) => take_jobs(con, db_pool, group_name, reply).await,
+        Err(_) => Vec::new(),
+    }
+}
 
+/// Turn a read into jobs, dead-lettering anything that isn't one.
+async fn take_jobs(
+    con: &mut redis::aio::MultiplexedConnection,
+    db_pool: &PgPool,
+    group_name: &str,
+    reply: StreamReadReply,
+) -> Vec<(&'static str, String, WorkerJob, u32)> {
+    let mut jobs = Vec::new();
     for stream_key_result in reply.keys {
+        let Some(stream) = priority::STREAMS.into_iter().find(|s| *s == stream_key_result.key) else {
+            continue;
+        };
         for message in stream_key_result.ids {
             let msg_id = message.id.clone();
-
-            if let Some(payload_str) = message.map.get("payload") {
-                let payload_string: String = redis::from_redis_value(payload_str).ok()?;
-
-                match serde_json::from_str::<WorkerJob>(&payload_string) {
-                    Ok(job) => return Some((msg_id, job)),
-                    Err(e) => {
-                        eprintln!("Failed to parse WorkerJob: {}", e);
-                        eprintln!("  Raw payload: {}", &payload_string[..payload_string.len().min(500)]);
+            // This read is one more delivery on top of any before stale recovery re-added it
+            let deliveries = dead_letter::prior_deliveries(&message.map) + 1;
+            let max_deliveries = dead_letter::max_deliveries();
+
+            // Anything we can't turn into a WorkerJob is a poison message: park it
+            // in the dead letter table so it doesn't cycle through recovery forever
+            let (payload, error) = match message.map.get("payload") {
+                Some(payload_value) => match redis::from_redis_value::<String>(payload_value) {
+                    Ok(payload_string) if dead_letter::exceeds_max_deliveries(deliveries, max_deliveries) => {
+                        tracing::error!(
+                            msg_id = %msg_id,
+                            deliveries,
+                            "Message exceeded max deliveries ({}), giving up",
+                            max_deliveries
+                        );
+                        (
+                            Some(payload_string),
+                            format!("Exceeded max deliveries ({})", max_deliveries),
+                        )
                     }
-                }
+                    Ok(payload_string) => match serde_json::from_str::<WorkerJob>(&payload_string) {
+                        Ok(job) => {
+                            jobs.push((stream, msg_id, job, deliveries));
+                            continue;
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                msg_id = %msg_id,
+                                payload = &payload_string[..payload_string.len().min(500)],
+                                "Failed to parse WorkerJob: {}", e
+                            );
+                            (Some(payload_string), format!("Failed to parse WorkerJob: {}", e))
+                        }
+                    },
+                    Err(e) => (None, format!("Payload is not a string: {}", e)),
+                },
+                None => (None, "Message has no payload field".to_string()),
+            };
+
+            if let Err(e) = dead_letter::dead_letter_message(
+                con,
+                db_pool,
+                stream,
+                group_name,
+                &msg_id,
+                payload.as_deref(),
+                &error,
+                deliveries,
+            )
+            .await
+            {
+                tracing::error!(msg_id = %msg_id, error = %e, "Failed to dead-letter message; not acknowledging, it will be redelivered");
             }
         }
     }
 
-    None
+    jobs
 }
 
 // =============================================================================
@@ -317,20 +433,35 @@ fn is_lifecycle_event(node: &NodeType) -> bool {
             | NodeType::SubFlowResume(_) // Resumes parent after child completes
             | NodeType::DelayResume(_)   // Resumes after delay expires
             | NodeType::WebhookResume(_) // Resumes after webhook received
+            | NodeType::DbWaitResume(_)  // Resumes after the scheduler saw the condition hold
+            | NodeType::ParallelBranchComplete(_) // Records a branch result, maybe joins
+            | NodeType::LoopIterationComplete(_) // Checks the condition, loops again or resumes
     )
 }
 
+#[allow(clippy::too_many_arguments)] // the worker's shared clients and registries, passed explicitly
 async fn process_job(
     job: WorkerJob,
     http_client: reqwest::Client,
+    node_http_client: reqwest::Client,
+    ssrf_policy: Arc<SsrfPolicy>,
     redis_client: redis::Client,
     db_pool: PgPool,
     js_sender: mpsc::Sender<JsTask>,
     msg_id: String,
+    stream: &'static str,
     group_name: String,
     cancel_registry: Arc<CancellationRegistry>,
+    circuit_breakers: CircuitBreakers,
+    token_budgets: TokenBudgets,
+    rate_limit_holds: RateLimitHolds,
+    map_limiter: nodes::ChildLimiter,
 ) {
     let start = Instant::now();
+    // Retry deadlines count from the first attempt, which earlier retries carried along
+    let first_attempt_at = job.first_attempt_at.unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+    });
     let job_id = job.id.clone();
     let job_isolated = job.isolated;
     let run_id = job.run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
@@ -339,7 +470,7 @@ async fn process_job(
     let is_lifecycle = is_lifecycle_event(&job.node);
     
     if is_lifecycle {
-        verbose_log!("Processing Lifecycle Event: {} (run: {:?})", job_id, job.run_id);
+        tracing::debug!("Processing lifecycle event");
     }
 
     // Get or create cancellation token for this run
@@ -353,8 +484,8 @@ async fn process_job(
     if let Some(ref rid) = run_id {
         // Check if already cancelled via token (fast path)
         if cancel_token.is_cancelled() {
-            verbose_log!("  -> Skipping {} - run {} is cancelled (token)", job_id, rid);
-            ack_message(&redis_client, &group_name, &msg_id).await;
+            tracing::debug!("Skipping job: run is cancelled (token)");
+            ack_message(&redis_client, stream, &group_name, &msg_id).await;
             return;
         }
 
@@ -368,14 +499,13 @@ async fn process_job(
         
         match status_result {
             Ok(Some((status,))) if status == "cancelled" || status == "failed" => {
-                verbose_log!("  -> Skipping {} - run {} is {}", job_id, rid, status);
-                ack_message(&redis_client, &group_name, &msg_id).await;
+                tracing::debug!(run_status = %status, "Skipping job: run is no longer active");
+                ack_message(&redis_client, stream, &group_name, &msg_id).await;
                 return;
             }
             Err(e) => {
                 // TRANSIENT ERROR: Can't check status, don't ACK
-                eprintln!("  -> TRANSIENT ERROR: status check failed for {}: {}", job_id, e);
-                eprintln!("  -> NOT acknowledging - message will be redelivered");
+                tracing::warn!(error = %e, "Run status check failed; not acknowledging, message will be redelivered");
                 return;
             }
             _ => {} // Status is ok or not cancelled - continue
@@ -387,23 +517,15 @@ async fn process_job(
         if !is_lifecycle {
             match has_node_completed(&db_pool, rid, &job_id, job.retry_count).await {
                 Ok(true) => {
-                    verbose_log!(
-                        "  -> Skipping node {} (attempt {}) - already executed (idempotency)",
-                        job_id,
-                        job.retry_count + 1
-                    );
-                    ack_message(&redis_client, &group_name, &msg_id).await;
+                    tracing::debug!("Skipping job: attempt already executed (idempotency)");
+                    ack_message(&redis_client, stream, &group_name, &msg_id).await;
                     return;
                 }
                 Err(e) => {
                     // CRITICAL: Pool timeout = transient error
                     // Do NOT ACK - let Redis consumer group redeliver this message
                     // This gives us at-least-once semantics instead of at-most-once
-                    eprintln!(
-                        "  -> TRANSIENT ERROR: idempotency check failed for {}: {}",
-                        job_id, e
-                    );
-                    eprintln!("  -> NOT acknowledging - message will be redelivered");
+                    tracing::warn!(error = %e, "Idempotency check failed; not acknowledging, message will be redelivered");
                     return; // Exit WITHOUT ack_message - Redis will redeliver after visibility timeout
                 }
                 Ok(false) => {} // Normal case: proceed with execution
@@ -411,9 +533,71 @@ async fn process_job(
         }
     }
 
+    // Custom idempotency key: dedupe across runs (and for jobs without a run).
+    // Claimed up front, so a concurrent delivery can't run the job as well
+    let job_key_claim = if is_lifecycle { None } else { job.idempotency_key.as_deref() };
+    if let Some(key) = job_key_claim {
+        match idempotency::claim_key(&redis_client, key).await {
+            Ok(idempotency::Claim::Acquired) => {}
+            Ok(idempotency::Claim::Completed) => {
+                tracing::debug!(idempotency_key = %key, "Skipping job: idempotency key already completed");
+                ack_message(&redis_client, stream, &group_name, &msg_id).await;
+                return;
+            }
+            Ok(idempotency::Claim::Held) => {
+                tracing::debug!(idempotency_key = %key, "Job key claimed by another delivery; checking again once its lease expires");
+                defer_held_delivery(&job, idempotency::job_key_lease(), &redis_client, stream, &group_name, &msg_id).await;
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Idempotency key check failed; not acknowledging, message will be redelivered");
+                return;
+            }
+        }
+    }
+
+    // Lifecycle events with a key are claimed in the DB, so a racing delivery
+    // on another worker is skipped instead of applied twice
+    let lifecycle_claim = if is_lifecycle { job.idempotency_key.as_deref() } else { None };
+    if let Some(key) = lifecycle_claim {
+        match idempotency::claim(&db_pool, key, run_id, &job_id).await {
+            Ok(idempotency::Claim::Acquired) => {}
+            Ok(idempotency::Claim::Completed) => {
+                tracing::debug!(idempotency_key = %key, "Skipping lifecycle event: idempotency key already completed");
+                ack_message(&redis_client, stream, &group_name, &msg_id).await;
+                return;
+            }
+            Ok(idempotency::Claim::Held) => {
+                tracing::debug!(idempotency_key = %key, "Lifecycle event claimed by another delivery; checking again once its lease expires");
+                defer_held_delivery(&job, idempotency::claim_lease(), &redis_client, stream, &group_name, &msg_id).await;
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Idempotency claim failed; not acknowledging, message will be redelivered");
+                return;
+            }
+        }
+    }
+
+    // The job's span: a child of the enqueuer's trace, or a new root
+    let trace = TraceContext::start(job.trace_context.as_ref());
+    tracing::Span::current().record("trace_id", trace.trace_id.as_str());
+    tracing::debug!(span_id = %trace.span_id, "Trace context");
+
     // Log NODE_STARTED event
     if let Some(ref rid) = run_id {
-        let _ = log_event(&db_pool, rid, &job_id, EventType::NodeStarted, serde_json::json!({})).await;
+        let _ = log_event(
+            &db_pool,
+            rid,
+            &job_id,
+            EventType::NodeStarted,
+            serde_json::json!({
+                "trace_id": trace.trace_id,
+                "span_id": trace.span_id,
+                "parent_span_id": trace.parent_span_id,
+            }),
+        )
+        .await;
     }
 
     // Create streaming context for real-time output
@@ -424,35 +608,81 @@ async fn process_job(
     // Clone node for potential retry (before moving into execute_node)
     let node_clone = job.node.clone();
 
-    // Execute the node with cancellation support
-    let (status, body, was_cancelled) = execute_node(
+    // Execute the node with cancellation support, under the hard job timeout
+    // (lifecycle events are exempt - they're internal state updates)
+    let limit = if is_lifecycle { None } else { job_timeout::job_timeout() };
+    let warnings = Warnings::default();
+    let execution = execute_node(
         node_clone.clone(),
         &job_id,
         &job.run_id,
+        job.priority,
         http_client.clone(),
+        node_http_client,
+        &ssrf_policy,
         &redis_client,
         &db_pool,
         &js_sender,
         stream_ctx.as_ref(),
         &cancel_token,
-    )
-    .await;
+        &circuit_breakers,
+        &token_budgets,
+        &rate_limit_holds,
+        &map_limiter,
+        &warnings,
+        &trace,
+        job.dry_run,
+        job.run_env.as_ref(),
+    );
+    let (outcome, timed_out) = match job_timeout::run_with_timeout(limit, execution).await {
+        Some(outcome) => (outcome, false),
+        None => {
+            let limit = limit.unwrap_or_default();
+            tracing::warn!(limit_ms = limit.as_millis() as u64, "Job exceeded hard timeout, terminating");
+            if let Some(ctx) = stream_ctx.as_ref() {
+                ctx.error("Job exceeded hard timeout").await;
+            }
+            (Err(NodeError::Permanent { status: 504, body: job_timeout::timeout_body(limit) }), true)
+        }
+    };
 
     let duration_ms = start.elapsed().as_millis() as u64;
-    let is_success = status >= 200 && status < 300;
-    
+    metrics::METRICS.observe_duration(metrics::node_type_label(&node_clone), start.elapsed());
+    let is_success = outcome.is_ok();
+    let is_transient = matches!(outcome, Err(NodeError::Transient(_)));
+    let was_cancelled = matches!(outcome, Err(NodeError::Cancelled(_)));
+    // The event ran to an outcome, so redeliveries must skip it from now on
+    // (a transient failure gives the claim back below instead)
+    if let Some(key) = lifecycle_claim
+        && !is_transient
+    {
+        idempotency::complete(&db_pool, key).await;
+    }
+    // Only a success keeps a job's key; any other outcome lets its retry (or a
+    // redelivery) claim it again
+    if let Some(key) = job_key_claim
+        && !is_success
+    {
+        idempotency::release_key(&redis_client, key).await;
+    }
     // Lifecycle events (MapChildComplete, MapStep, etc.) should NOT be treated as suspended
     // They are internal state updates that return 202 but should just be ACKed and done
     // Only actual "start of suspension" events (Map init, SubFlow spawn) should suspend
-    let is_suspended = !is_lifecycle && status == 202
-        && body
-            .as_ref()
-            .map(|b| b.get("suspended").is_some() || b.get("batch_id").is_some())
-            .unwrap_or(false);
+    let is_suspended = !is_lifecycle && matches!(outcome, Err(NodeError::Suspended(_)));
+    let node_warnings = warnings.take();
+    let (status, mut body) = node_error::into_parts(outcome);
+    tracing::Span::current().record("status", status);
+    tracing::debug!(duration_ms, "Node finished");
+
+    // Output handle requested by the node (if any)
+    let route_to = nodes::extract_route_to(&job.node, &mut body);
+
+    // A lifecycle event that settled its node completes it like any other node
+    let settles_node = nodes::settles_node(&job.node, status, is_success, is_transient);
 
     // Handle lifecycle events (MapChildComplete, MapStep, Resume, etc.)
     // These are internal state updates - just publish progress to SSE and ACK
-    if is_lifecycle {
+    if is_lifecycle && !settles_node {
         // Publish progress update to SSE (so UI can update progress bar)
         let receipt = ExecutionResult {
             node_id: job_id.clone(),
@@ -465,43 +695,49 @@ async fn process_job(
                 .as_millis() as u64,
             duration_ms,
             isolated: true, // Don't trigger downstream from frontend
+            route_to: route_to.clone(),
+            warnings: Vec::new(),
         };
 
-        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await {
-            if let Ok(receipt_json) = serde_json::to_string(&receipt) {
+        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await
+            && let Ok(receipt_json) = serde_json::to_string(&receipt) {
                 let _: RedisResult<String> = con
                     .xadd(STREAM_RESULTS, "*", &[("payload", receipt_json)])
                     .await;
             }
-        }
         
         // Check if this lifecycle event succeeded or failed
-        if status == 500 {
+        if is_transient {
             // TRANSIENT ERROR: Lifecycle event failed (likely pool timeout)
             // Do NOT ACK - let scheduler recovery pick it up for retry
-            eprintln!(
-                "  -> TRANSIENT ERROR: Lifecycle event {} failed with status 500",
-                job_id
+            tracing::warn!(
+                error = body.as_ref().and_then(|b| b.get("error")).and_then(|e| e.as_str()).unwrap_or("unknown"),
+                "Lifecycle event failed transiently; not acknowledging, message will be redelivered"
             );
-            eprintln!("  -> NOT acknowledging - message will be redelivered");
+            // The redelivery must be able to claim the key again
+            if let Some(key) = lifecycle_claim {
+                idempotency::release(&db_pool, key).await;
+            }
             return; // Exit WITHOUT ack_message
+        } else if !is_success {
+            tracing::error!("Lifecycle event failed");
         } else if status == 200 {
             // Success case - batch completed, notify orchestrator to schedule downstream
-            verbose_log!("  -> Lifecycle event: batch completed, notifying orchestrator");
+            tracing::debug!("Lifecycle event: batch completed, notifying orchestrator");
             if let Some(ref rid) = run_id {
-                notify_orchestrator(rid, &job_id, true).await;
+                notify_orchestrator(&http_client, &redis_client, rid, &job_id, true).await;
             }
         } else {
             // Progress update (202) - just ACK (silent for performance)
         }
         
-        ack_message(&redis_client, &group_name, &msg_id).await;
+        ack_message(&redis_client, stream, &group_name, &msg_id).await;
         return;
     }
 
     // Handle cancelled nodes
     if was_cancelled {
-        verbose_log!("  -> Node {} cancelled", job_id);
+        tracing::debug!("Node cancelled");
         if let Some(ref rid) = run_id {
             let _ = log_event_with_retry(
                 &db_pool,
@@ -529,17 +765,18 @@ async fn process_job(
                 .as_millis() as u64,
             duration_ms,
             isolated: job_isolated,
+            route_to: None,
+            warnings: Vec::new(),
         };
 
-        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await {
-            if let Ok(receipt_json) = serde_json::to_string(&receipt) {
+        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await
+            && let Ok(receipt_json) = serde_json::to_string(&receipt) {
                 let _: RedisResult<String> = con
                     .xadd(STREAM_RESULTS, "*", &[("payload", receipt_json)])
                     .await;
             }
-        }
         
-        ack_message(&redis_client, &group_name, &msg_id).await;
+        ack_message(&redis_client, stream, &group_name, &msg_id).await;
         // Cleanup token if this was the last job for this run
         if let Some(ref rid) = run_id {
             cancel_registry.remove(rid).await;
@@ -549,7 +786,7 @@ async fn process_job(
 
     // Handle suspended nodes (e.g., sub-flow waiting for child, map waiting for iterations)
     if is_suspended {
-        verbose_log!("  -> Node suspended, waiting for external trigger");
+        tracing::debug!("Node suspended, waiting for external trigger");
         
         // Log NODE_SUSPENDED event so orchestrator knows not to re-schedule this node
         if let Some(ref rid) = run_id {
@@ -561,6 +798,7 @@ async fn process_job(
                 Some(job.retry_count),
                 serde_json::json!({
                     "duration_ms": duration_ms,
+                    "suspension": body.as_ref().and_then(|b| b.get("suspension")),
                     "suspended_body": body
                 }),
             )
@@ -579,52 +817,54 @@ async fn process_job(
                 .as_millis() as u64,
             duration_ms,
             isolated: job_isolated,
+            route_to: None,
+            warnings: Vec::new(),
         };
 
-        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await {
-            if let Ok(receipt_json) = serde_json::to_string(&receipt) {
+        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await
+            && let Ok(receipt_json) = serde_json::to_string(&receipt) {
                 let _: RedisResult<String> = con
                     .xadd(STREAM_RESULTS, "*", &[("payload", receipt_json)])
                     .await;
             }
-        }
         
-        ack_message(&redis_client, &group_name, &msg_id).await;
+        ack_message(&redis_client, stream, &group_name, &msg_id).await;
         return;
     }
 
-    // Check for transient DB errors (status 500 with pool timeout indicators)
-    // These should NOT be ACKed - let scheduler recovery handle them
-    let is_transient_db_error = status == 500 && body
-        .as_ref()
-        .and_then(|b| b.get("error"))
-        .and_then(|e| e.as_str())
-        .map(|s| s.contains("pool timed out") || s.contains("Database error"))
-        .unwrap_or(false);
-
-    if is_transient_db_error {
-        eprintln!(
-            "  -> TRANSIENT DB ERROR: Node {} failed with pool timeout",
-            job_id
+    // Transient infrastructure errors (pool timeouts, lost connections)
+    // should NOT be ACKed - let scheduler recovery handle them
+    if is_transient {
+        tracing::warn!(
+            error = body.as_ref().and_then(|b| b.get("error")).and_then(|e| e.as_str()).unwrap_or("unknown"),
+            "Node failed transiently; not acknowledging, message will be redelivered"
         );
-        eprintln!("  -> NOT acknowledging - message will be redelivered");
         return; // Exit WITHOUT ack_message
     }
 
-    // Handle retry logic
-    if !is_success && is_retryable_error(status) && job.retry_count < job.max_retries {
-        handle_retry(
+    // Handle retry logic (a job killed by the hard timeout is not retried)
+    let retried = !is_success
+        && !timed_out
+        && is_retryable_error(status)
+        && job.retry_count < job.max_retries
+        && handle_retry(
             &job,
             node_clone,
             status,
-            &body,
+            &mut body,
+            first_attempt_at,
             &run_id,
             &db_pool,
             &redis_client,
             job_isolated,
         )
         .await;
-    } else {
+    if !retried {
+        // Only successful completions are remembered - failed jobs may be re-triggered
+        if is_success && let Some(key) = job_key_claim {
+            idempotency::mark_completed(&redis_client, key).await;
+        }
+
         // Final result
         handle_final_result(
             &job,
@@ -632,8 +872,11 @@ async fn process_job(
             body,
             duration_ms,
             is_success,
+            route_to,
+            node_warnings,
             &run_id,
             &db_pool,
+            &http_client,
             &redis_client,
             job_isolated,
         )
@@ -641,7 +884,7 @@ async fn process_job(
     }
 
     // ACK the message
-    ack_message(&redis_client, &group_name, &msg_id).await;
+    ack_message(&redis_client, stream, &group_name, &msg_id).await;
 }
 
 // =============================================================================
@@ -649,242 +892,309 @@ async fn process_job(
 // =============================================================================
 
 /// Execute a node with cancellation support.
-/// Returns (status_code, body, was_cancelled).
+/// Returns the completion `(status_code, body)`, or how the node didn't complete.
+#[allow(clippy::too_many_arguments)]
 async fn execute_node(
     node: NodeType,
     job_id: &str,
     run_id: &Option<String>,
+    priority: JobPriority,
     http_client: reqwest::Client,
+    node_http_client: reqwest::Client,
+    ssrf_policy: &SsrfPolicy,
     redis_client: &redis::Client,
     db_pool: &PgPool,
     js_sender: &mpsc::Sender<JsTask>,
     stream_ctx: Option<&StreamContext>,
     cancel_token: &CancellationToken,
-) -> (u16, Option<serde_json::Value>, bool) {
+    circuit_breakers: &CircuitBreakers,
+    token_budgets: &TokenBudgets,
+    rate_limit_holds: &RateLimitHolds,
+    map_limiter: &nodes::ChildLimiter,
+    warnings: &Warnings,
+    trace: &TraceContext,
+    dry_run: bool,
+    run_env: Option<&RunEnv>,
+) -> NodeResult {
+    // Dry run: report what would happen (before secrets are resolved into the node)
+    if dry_run && let Some(body) = nodes::dry_run::simulate(&node) {
+        tracing::debug!("Dry run: skipping side effects");
+        return Ok((200, Some(body)));
+    }
+
+    let mut node = node;
+    nodes::resolve_env_secrets(&mut node, run_env).map_err(|e| NodeError::permanent(400, e))?;
+
     match node {
         NodeType::Http(data) => {
-            let result = nodes::http::execute(http_client, data, stream_ctx, cancel_token).await;
-            (result.0, result.1, result.2)
+            let run_uuid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
+            let upload = nodes::http_upload::open(&data, redis_client, db_pool, run_uuid).await?;
+            let (status, body, cancelled) = nodes::http::execute(node_http_client, redis_client, data, upload, stream_ctx, cancel_token, circuit_breakers, ssrf_policy, warnings, trace).await;
+            NodeError::classify(status, body, cancelled)
+        }
+
+        NodeType::WebSocket(data) => {
+            let (status, body, cancelled) = nodes::websocket::execute(data, stream_ctx, cancel_token, circuit_breakers, ssrf_policy).await;
+            NodeError::classify(status, body, cancelled)
         }
 
         NodeType::Code(data) => {
-            let (status, body) = execute_code_node(data, js_sender).await;
-            (status, body, false) // Code execution doesn't support cancellation yet
+            let (status, body, cancelled) = execute_code_node(data, js_sender, stream_ctx, cancel_token).await;
+            NodeError::classify(status, body, cancelled)
         }
 
         NodeType::Delay(data) => {
-            nodes::delay::execute(data, job_id, run_id, redis_client, cancel_token).await
+            let (status, body, cancelled) = nodes::delay::execute(data, job_id, run_id, priority, redis_client, cancel_token).await;
+            NodeError::classify(status, body, cancelled)
         }
 
         NodeType::DelayResume(data) => {
             let (status, body) = nodes::delay::execute_resume(data.original_delay_ms);
-            (status, body, false)
+            NodeError::classify(status, body, false)
         }
 
         NodeType::WebhookWait(data) => {
             let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
             let (status, body) = nodes::webhook::execute_wait(data, job_id, rid.as_ref(), db_pool).await;
-            (status, body, false) // Webhook wait is a suspension, not cancellable mid-execution
+            NodeError::classify(status, body, false) // Webhook wait is a suspension, not cancellable mid-execution
         }
 
         NodeType::WebhookResume(data) => {
             let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
-            let (status, body) = nodes::webhook::execute_resume(data, job_id, rid.as_ref(), db_pool).await;
-            (status, body, false)
+            nodes::webhook::execute_resume(data, job_id, rid.as_ref(), db_pool).await // Signed waits reject bad signatures with 401
+        }
+
+        NodeType::DbWait(data) => {
+            let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
+            nodes::db_wait::execute_wait(data, job_id, rid.as_ref(), priority, db_pool).await // One bounded query, then a suspension
+        }
+
+        NodeType::DbWaitResume(data) => {
+            let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
+            nodes::db_wait::execute_resume(data, job_id, rid.as_ref(), db_pool).await
+        }
+
+        NodeType::DbUpsert(data) => {
+            nodes::db_upsert::execute(db_pool, data).await // Single statement, no cancellation point
+        }
+
+        NodeType::Transform(data) => {
+            nodes::transform::execute(data).await // Runs off the async threads, bounded by TRANSFORM_TIMEOUT_MS
+        }
+
+        NodeType::Email(data) => {
+            let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
+            nodes::email::execute(data, rid.as_ref(), db_pool, cancel_token, ssrf_policy).await // Greylisting (4xx) fails with a retryable 503
+        }
+
+        NodeType::S3(data) => {
+            nodes::s3::execute(node_http_client, data, cancel_token, ssrf_policy).await // Auth failures (403) fail with a retryable 503
+        }
+
+        NodeType::Aggregate(data) => {
+            let run_uuid = lifecycle_run_id(run_id, "Aggregate nodes require a run context (cannot run in isolated mode)")?;
+            nodes::aggregate::execute(db_pool, &run_uuid, &data).await
         }
 
         NodeType::Router(data) => {
-            let (status, body) = nodes::router::execute(data);
-            (status, body, false) // Router is instant, no cancellation needed
+            let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
+            nodes::router::execute(data, rid.as_ref(), db_pool, js_sender).await // Router is quick, no cancellation needed
+        }
+
+        NodeType::Conditional(data) => {
+            nodes::conditional::execute(data, js_sender).await // One expression in the sandbox, no cancellation needed
         }
 
         NodeType::Llm(data) => {
-            nodes::llm::execute(http_client, data, stream_ctx, cancel_token).await
+            let (status, body, cancelled) = nodes::llm::execute(node_http_client, ssrf_policy, data, stream_ctx, cancel_token, token_budgets, rate_limit_holds, warnings).await;
+            NodeError::classify(status, body, cancelled)
         }
 
         NodeType::SubFlow(data) => {
             let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
             
-            if let Some(parent_run_id) = rid {
-                // Get current depth from the run
-                let depth: i32 = sqlx::query_scalar(
-                    "SELECT COALESCE(depth, 0) FROM workflow_runs WHERE id = $1"
-                )
-                .bind(parent_run_id)
-                .fetch_one(db_pool)
-                .await
-                .unwrap_or(0);
-
-                // Spawn the child run
-                match nodes::spawn_child_run(
-                    db_pool,
-                    &data,
-                    &parent_run_id,
-                    job_id,
-                    depth as u32,
-                ).await {
-                    Ok(spawn_result) => {
-                        println!(
-                            "  -> SubFlow: Spawned child run {} for workflow '{}'",
-                            &spawn_result.child_run_id.to_string()[..8],
-                            spawn_result.child_workflow_name
-                        );
+            let Some(parent_run_id) = rid else {
+                // No run_id - can't spawn sub-flow in isolated mode
+                return Err(NodeError::permanent(400, "SubFlow nodes require a run context (cannot run in isolated mode)"));
+            };
 
-                        // Suspend the parent run
-                        if let Err(e) = nodes::suspend_parent_run(db_pool, &parent_run_id).await {
-                            eprintln!("  -> SubFlow: Failed to suspend parent: {}", e);
-                        }
+            // Get current depth from the run
+            let depth: i32 = sqlx::query_scalar(
+                "SELECT COALESCE(depth, 0) FROM workflow_runs WHERE id = $1"
+            )
+            .bind(parent_run_id)
+            .fetch_one(db_pool)
+            .await
+            .unwrap_or(0);
 
-                        // Start the child run via API (handles template interpolation)
-                        let api_base_url = std::env::var("API_BASE_URL")
-                            .unwrap_or_else(|_| "http://localhost:5173".to_string());
-                        if let Err(e) = start_child_run(
-                            &http_client,
-                            &api_base_url,
-                            spawn_result.child_run_id,
-                        ).await {
-                            eprintln!("  -> SubFlow: Failed to start child: {}", e);
-                            return (
-                                500,
-                                Some(serde_json::json!({
-                                    "error": format!("Failed to start child run: {}", e),
-                                })),
-                                false,
-                            );
-                        }
+            // Spawn the child run
+            let spawn_result = nodes::spawn_child_run(
+                db_pool,
+                &data,
+                &parent_run_id,
+                job_id,
+                depth as u32,
+                priority,
+            )
+            .await
+            .inspect_err(|e| tracing::error!("SubFlow: Failed to spawn child: {}", e))?;
 
-                        // Return 202 (suspended) - the orchestrator should NOT schedule downstream
-                        (
-                            202,
-                            Some(serde_json::json!({
-                                "suspended": true,
-                                "child_run_id": spawn_result.child_run_id.to_string(),
-                                "workflow_name": spawn_result.child_workflow_name,
-                            })),
-                            false,
-                        )
-                    }
-                    Err(e) => {
-                        eprintln!("  -> SubFlow: Failed to spawn child: {}", e);
-                        (
-                            500,
-                            Some(serde_json::json!({
-                                "error": e.to_string(),
-                            })),
-                            false,
-                        )
-                    }
-                }
-            } else {
-                // No run_id - can't spawn sub-flow in isolated mode
-                (
-                    400,
-                    Some(serde_json::json!({
-                        "error": "SubFlow nodes require a run context (cannot run in isolated mode)",
-                    })),
-                    false,
-                )
+            tracing::info!(
+                child_run_id = %spawn_result.child_run_id,
+                "SubFlow: Spawned child run for workflow '{}'",
+                spawn_result.child_workflow_name
+            );
+
+            // Suspend the parent run
+            if let Err(e) = nodes::suspend_parent_run(db_pool, &parent_run_id).await {
+                tracing::error!("SubFlow: Failed to suspend parent: {}", e);
             }
+
+            // Start the child run via API (handles template interpolation)
+            let api_base_url = std::env::var("API_BASE_URL")
+                .unwrap_or_else(|_| "http://localhost:5173".to_string());
+            if let Err(e) = start_child_run(
+                &http_client,
+                &api_base_url,
+                spawn_result.child_run_id,
+            ).await {
+                tracing::error!("SubFlow: Failed to start child: {}", e);
+                return Err(NodeError::permanent(500, format!("Failed to start child run: {}", e)));
+            }
+
+            // Suspended - the orchestrator should NOT schedule downstream
+            Err(NodeError::Suspended(serde_json::json!({
+                "suspended": true,
+                "child_run_id": spawn_result.child_run_id.to_string(),
+                "workflow_name": spawn_result.child_workflow_name,
+                "suspension": Suspension::new(
+                    SuspensionKind::SubFlow,
+                    ResumeVia::ChildRun,
+                    events::expires_in(data.timeout_ms),
+                    serde_json::json!({
+                        "child_run_id": spawn_result.child_run_id.to_string(),
+                        "workflow_name": spawn_result.child_workflow_name,
+                    }),
+                ),
+            })))
         }
 
         NodeType::SubFlowResume(data) => {
-            // Child completed - resume the parent
-            // The fail_on_error flag is stored in the suspension, we need to look it up
-            // For now, assume fail_on_error = false (route to error handle on failure)
-            let (status, body) = nodes::handle_resume(&data, false);
-            (status, Some(body), false)
-        }
+            // Child completed - resume the parent with the settings recorded at spawn
+            let rid = run_id.as_ref().and_then(|s| Uuid::parse_str(s).ok());
 
-        NodeType::Map(data) => {
-            // Map/Iterator node - spawn children for each item
-            if let Some(run_id_str) = &run_id {
-                let run_uuid = match uuid::Uuid::parse_str(run_id_str) {
-                    Ok(u) => u,
-                    Err(e) => {
-                        return (
-                            400,
-                            Some(serde_json::json!({ "error": format!("Invalid run_id: {}", e) })),
-                            false,
-                        );
-                    }
-                };
-                
-                match nodes::handle_map_init(db_pool, &run_uuid, job_id, &data, 0).await {
-                    Ok(result) => {
-                        // Note: third value is "was_cancelled", not "is_suspended"
-                        // Suspension is handled via status_code 202 check in process_job
-                        (result.status_code, result.body, false)
-                    }
-                    Err(e) => {
-                        eprintln!("  -> Map: Failed to initialize: {}", e);
-                        (500, Some(serde_json::json!({ "error": e.to_string() })), false)
-                    }
+            // A failed child with retries left is replaced instead of resuming
+            if !data.success
+                && let Some(parent_run_id) = rid
+                && let Some((spawn_result, attempt)) = nodes::respawn_child_run(db_pool, &parent_run_id, job_id).await?
+            {
+                tracing::info!(
+                    child_run_id = %spawn_result.child_run_id,
+                    child_attempt = attempt,
+                    "SubFlow: Child failed, retrying with a new child run"
+                );
+                if let Err(e) = nodes::suspend_parent_run(db_pool, &parent_run_id).await {
+                    tracing::error!("SubFlow: Failed to suspend parent: {}", e);
                 }
-            } else {
-                (
-                    400,
+                let api_base_url = std::env::var("API_BASE_URL")
+                    .unwrap_or_else(|_| "http://localhost:5173".to_string());
+                if let Err(e) = start_child_run(&http_client, &api_base_url, spawn_result.child_run_id).await {
+                    tracing::error!("SubFlow: Failed to start retry child: {}", e);
+                }
+                // Progress update: the parent stays suspended on the new child
+                return Ok((
+                    202,
                     Some(serde_json::json!({
-                        "error": "Map nodes require a run context (cannot run in isolated mode)",
+                        "retrying": true,
+                        "attempt": attempt,
+                        "failed_child_run_id": data.child_run_id,
+                        "child_run_id": spawn_result.child_run_id.to_string(),
                     })),
-                    false,
-                )
+                ));
             }
+
+            let options = match rid {
+                // A transient failure leaves the resume un-ACKed for redelivery
+                Some(parent_run_id) => nodes::load_resume_options(db_pool, &parent_run_id, job_id).await?,
+                None => Default::default(),
+            };
+            let output_path = options.output_path.as_deref().or(data.output_path.as_deref());
+            let (status, body) = nodes::handle_resume(&data, options.fail_on_error, output_path);
+            NodeError::classify(status, Some(body), false)
+        }
+
+        NodeType::Map(data) => {
+            // Map/Iterator node - spawn children for each item
+            let run_uuid = lifecycle_run_id(run_id, "Map nodes require a run context (cannot run in isolated mode)")?;
+            let result = nodes::handle_map_init(db_pool, redis_client, map_limiter, &run_uuid, job_id, priority, &data, 0, stream_ctx)
+                .await
+                .inspect_err(|e| tracing::error!("Map: Failed to initialize: {}", e))?;
+            // A started batch suspends the node (via its batch_id); an empty one completes
+            NodeError::classify(result.status_code, result.body, false)
         }
 
         NodeType::MapStep(data) => {
             // Spawn next batch of children (lifecycle event - never "cancelled")
-            if let Some(run_id_str) = &run_id {
-                let run_uuid = match uuid::Uuid::parse_str(run_id_str) {
-                    Ok(u) => u,
-                    Err(e) => {
-                        return (
-                            400,
-                            Some(serde_json::json!({ "error": format!("Invalid run_id: {}", e) })),
-                            false,
-                        );
-                    }
-                };
-                
-                match nodes::handle_map_step(db_pool, &run_uuid, job_id, &data).await {
-                    Ok(result) => (result.status_code, result.body, false), // Never cancelled
-                    Err(e) => {
-                        eprintln!("  -> MapStep: Failed: {}", e);
-                        (500, Some(serde_json::json!({ "error": e.to_string() })), false)
-                    }
-                }
-            } else {
-                (400, Some(serde_json::json!({ "error": "MapStep requires run context" })), false)
-            }
+            let run_uuid = lifecycle_run_id(run_id, "MapStep requires run context")?;
+            let result = nodes::handle_map_step(db_pool, redis_client, map_limiter, &run_uuid, job_id, &data)
+                .await
+                .inspect_err(|e| tracing::error!("MapStep: Failed: {}", e))?;
+            Ok((result.status_code, result.body))
         }
 
         NodeType::MapChildComplete(data) => {
             // A map child completed - record result and maybe spawn more (lifecycle event - never "cancelled")
-            if let Some(run_id_str) = &run_id {
-                let run_uuid = match uuid::Uuid::parse_str(run_id_str) {
-                    Ok(u) => u,
-                    Err(e) => {
-                        return (
-                            400,
-                            Some(serde_json::json!({ "error": format!("Invalid run_id: {}", e) })),
-                            false,
-                        );
-                    }
-                };
-                
-                match nodes::handle_child_complete(db_pool, redis_client, &run_uuid, job_id, &data).await {
-                    Ok(result) => (result.status_code, result.body, false), // Never cancelled
-                    Err(e) => {
-                        eprintln!("  -> MapChildComplete: Failed: {}", e);
-                        (500, Some(serde_json::json!({ "error": e.to_string() })), false)
-                    }
+            let run_uuid = lifecycle_run_id(run_id, "MapChildComplete requires run context")?;
+            let result = nodes::handle_child_complete(db_pool, redis_client, map_limiter, &run_uuid, job_id, &data, stream_ctx)
+                .await
+                .inspect_err(|e| tracing::error!("MapChildComplete: Failed: {}", e))?;
+            Ok((result.status_code, result.body))
+        }
+
+        NodeType::Parallel(data) => {
+            // Start every branch in this run; suspends until they join
+            let run_uuid = lifecycle_run_id(run_id, "Parallel nodes require a run context (cannot run in isolated mode)")?;
+            nodes::parallel::handle_parallel_init(db_pool, redis_client, &run_uuid, job_id, priority, &data, trace).await
+        }
+
+        NodeType::ParallelBranchComplete(data) => {
+            let run_uuid = lifecycle_run_id(run_id, "ParallelBranchComplete requires run context")?;
+            match nodes::parallel::handle_branch_complete(db_pool, &data).await? {
+                nodes::parallel::BranchJoin::Pending(body) => Ok((202, Some(body))),
+                nodes::parallel::BranchJoin::Joined(body) => Ok((200, Some(body))),
+                nodes::parallel::BranchJoin::AlreadyJoined(body) => {
+                    // Nothing waits on a losing branch, but the run can only
+                    // finish once the orchestrator has seen every node settle
+                    notify_orchestrator(&http_client, redis_client, &run_uuid, &data.branch_node_id, data.success).await;
+                    Ok((202, Some(body)))
                 }
-            } else {
-                (400, Some(serde_json::json!({ "error": "MapChildComplete requires run context" })), false)
+            }
+        }
+
+        NodeType::Loop(data) => {
+            // Start the first iteration in this run; suspends until the loop ends
+            let run_uuid = lifecycle_run_id(run_id, "Loop nodes require a run context (cannot run in isolated mode)")?;
+            nodes::loop_node::handle_loop_init(db_pool, redis_client, &run_uuid, job_id, &data, trace).await
+        }
+
+        NodeType::LoopIterationComplete(data) => {
+            let run_uuid = lifecycle_run_id(run_id, "LoopIterationComplete requires run context")?;
+            match nodes::loop_node::handle_iteration_complete(db_pool, redis_client, js_sender, &run_uuid, job_id, &data, trace).await? {
+                nodes::loop_node::LoopStep::Continue(body) => Ok((202, Some(body))),
+                nodes::loop_node::LoopStep::Done(body) => Ok((200, Some(body))),
             }
         }
     }
 }
 
+/// Parse the run id a map job needs (400 when missing or malformed).
+fn lifecycle_run_id(run_id: &Option<String>, missing: &str) -> Result<Uuid, NodeError> {
+    let Some(run_id_str) = run_id else {
+        return Err(NodeError::permanent(400, missing));
+    };
+    Uuid::parse_str(run_id_str).map_err(|e| NodeError::permanent(400, format!("Invalid run_id: {}", e)))
+}
+
 /// Start a child run by calling the TypeScript API endpoint.
 /// This ensures proper template interpolation ({{$trigger.field}}) is handled.
 async fn start_child_run(
@@ -913,57 +1223,145 @@ async fn start_child_run(
 async fn execute_code_node(
     data: swiftgrid_worker::types::CodeNodeData,
     js_sender: &mpsc::Sender<JsTask>,
-) -> (u16, Option<serde_json::Value>) {
+    stream_ctx: Option<&StreamContext>,
+    cancel_token: &CancellationToken,
+) -> (u16, Option<serde_json::Value>, bool) {
+    // Huge inputs can exhaust the sandbox's memory before the script even starts
+    let max_input_bytes = code::max_input_bytes();
+    if let Some(size) = data.inputs.as_ref().and_then(|i| code::oversized_input(i, max_input_bytes)) {
+        return (
+            413,
+            Some(serde_json::json!({
+                "error": format!("Code node INPUT is {} bytes, over the {} byte limit", size, max_input_bytes),
+                "input_bytes": size,
+                "limit": max_input_bytes,
+            })),
+            false,
+        );
+    }
+
     let (tx, rx) = oneshot::channel();
+    // The JS thread can't await the token, so cancellation is relayed through a flag
+    // its interrupt handler polls
+    let cancelled = Arc::new(AtomicBool::new(false));
+    // Wait a bit longer than the JS timeout so the sandbox reports its own error first
+    let channel_timeout = SandboxConfig::for_task(data.timeout_ms).channel_timeout();
+    // console output streams live as data chunks when there's a run to stream to
+    let (log_tx, mut log_rx) = mpsc::unbounded_channel();
     let task = JsTask {
         code: data.code,
         inputs: data.inputs,
         responder: tx,
-        timeout_ms: None, // Use default timeout from SandboxConfig
+        timeout_ms: data.timeout_ms, // None = default timeout from SandboxConfig
+        log_sender: stream_ctx.is_some().then_some(log_tx),
+        cancelled: Some(cancelled.clone()),
     };
 
     if js_sender.send(task).await.is_err() {
         return (
             500,
             Some(serde_json::json!({"error": "JS Engine crashed"})),
+            false,
         );
     }
 
-    match tokio::time::timeout(Duration::from_secs(30), rx).await {
-        Ok(Ok(Ok(val))) => (200, Some(val)),
-        Ok(Ok(Err(e))) => (400, Some(serde_json::json!({"error": e}))),
+    let response = tokio::time::timeout(channel_timeout, rx);
+    tokio::pin!(response);
+    let response = loop {
+        tokio::select! {
+            result = &mut response => break result,
+            _ = cancel_token.cancelled(), if !cancelled.load(Ordering::Relaxed) => {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+            Some(line) = log_rx.recv() => {
+                if let Some(ctx) = stream_ctx {
+                    ctx.data(&line).await;
+                }
+            }
+        }
+    };
+    if let Some(ctx) = stream_ctx {
+        while let Ok(line) = log_rx.try_recv() {
+            ctx.data(&line).await;
+        }
+    }
+
+    match response {
+        Ok(Ok(Ok(out))) => (200, Some(attach_logs(out.value, out.logs)), false),
+        // User errors are 400 (no retry); runtime/resource errors are 500 (retryable)
+        Ok(Ok(Err(e))) => {
+            let mut body = serde_json::json!({"error": e.message});
+            if !e.logs.is_empty() {
+                body["logs"] = serde_json::json!(e.logs);
+            }
+            (e.status_code(), Some(body), e.kind == JsErrorKind::Cancelled)
+        }
+        // The JS thread died mid-task; its supervisor restarts it, so a retry runs fresh
         Ok(Err(_)) => (
             500,
-            Some(serde_json::json!({"error": "JS channel closed"})),
+            Some(serde_json::json!({"error": "JS runtime crashed while running this code; it has been restarted"})),
+            false,
         ),
         Err(_) => (
             500,
-            Some(serde_json::json!({"error": "JS execution timeout (30s)"})),
+            Some(serde_json::json!({
+                "error": format!("JS execution timeout ({}ms)", channel_timeout.as_millis())
+            })),
+            false,
         ),
     }
 }
 
+/// Add captured console output to an object result as `_logs`.
+/// Other return values are left as-is (their logs were still streamed).
+fn attach_logs(mut value: serde_json::Value, logs: Vec<String>) -> serde_json::Value {
+    if !logs.is_empty()
+        && let Some(obj) = value.as_object_mut()
+    {
+        obj.insert("_logs".to_string(), serde_json::json!(logs));
+    }
+    value
+}
+
 // =============================================================================
 // RESULT HANDLING
 // =============================================================================
 
+/// Schedule the next attempt. Returns false (nothing scheduled) when it
+/// wouldn't start before the job's `deadline_ms`; the failure is then final.
+#[allow(clippy::too_many_arguments)]
 async fn handle_retry(
     job: &WorkerJob,
     node_clone: NodeType,
     status: u16,
-    body: &Option<serde_json::Value>,
+    body: &mut Option<serde_json::Value>,
+    first_attempt_at: u64,
     run_id: &Option<Uuid>,
     db_pool: &PgPool,
     redis_client: &redis::Client,
     isolated: bool,
-) {
+) -> bool {
     let next_attempt = job.retry_count + 1;
-    let backoff = calculate_backoff(next_attempt);
+    let backoff = retry_backoff(next_attempt, body.as_ref());
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+    if !retry_within_deadline(first_attempt_at, job.deadline_ms, now_ms, backoff) {
+        tracing::info!(
+            next_attempt,
+            deadline_ms = job.deadline_ms.unwrap_or_default(),
+            "Not retrying: next attempt would start past the deadline"
+        );
+        if let Some(obj) = body.as_mut().and_then(|b| b.as_object_mut()) {
+            obj.insert("retry_deadline_exceeded".to_string(), serde_json::json!(true));
+        }
+        return false;
+    }
     let retry_at = chrono::Utc::now() + chrono::Duration::milliseconds(backoff.as_millis() as i64);
 
-    println!(
-        "  → Scheduling retry {} of {} in {:?}",
-        next_attempt, job.max_retries, backoff
+    tracing::info!(
+        next_attempt,
+        max_retries = job.max_retries,
+        backoff_ms = backoff.as_millis() as u64,
+        "Scheduling retry"
     );
 
     // Log retry event
@@ -991,8 +1389,30 @@ async fn handle_retry(
         retry_count: next_attempt,
         max_retries: job.max_retries,
         isolated,
+        idempotency_key: job.idempotency_key.clone(),
+        deadline_ms: job.deadline_ms,
+        first_attempt_at: Some(first_attempt_at),
+        trace_context: job.trace_context.clone(),
+        parallel_branch: job.parallel_branch.clone(),
+        loop_iteration: job.loop_iteration.clone(),
+        dry_run: job.dry_run,
+        priority: job.priority,
+        run_env: job.run_env.clone(),
     };
 
+    // The message is ACKed once we return, so a long wait has to be durable
+    if backoff > DURABLE_RETRY_AFTER {
+        let payload = serde_json::to_string(&retry_job).unwrap();
+        let delay_ms = backoff.as_millis() as u64;
+        match nodes::delay::schedule_job(redis_client, &payload, retry_job.run_id.as_deref(), delay_ms).await {
+            Ok(()) => {
+                metrics::METRICS.retry_scheduled();
+                return true;
+            }
+            Err(e) => tracing::warn!("Failed to park retry in the delayed set, retrying in-process: {}", e),
+        }
+    }
+
     let redis_for_retry = redis_client.clone();
 
     tokio::spawn(async move {
@@ -1000,26 +1420,46 @@ async fn handle_retry(
         if let Ok(mut con) = redis_for_retry.get_multiplexed_async_connection().await {
             let _: RedisResult<String> = con
                 .xadd(
-                    STREAM_JOBS,
+                    retry_job.priority.stream(),
                     "*",
                     &[("payload", serde_json::to_string(&retry_job).unwrap())],
                 )
                 .await;
         }
     });
+    metrics::METRICS.retry_scheduled();
+    true
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_final_result(
     job: &WorkerJob,
     status: u16,
     body: Option<serde_json::Value>,
     duration_ms: u64,
     is_success: bool,
+    route_to: Option<String>,
+    warnings: Vec<NodeWarning>,
     run_id: &Option<Uuid>,
     db_pool: &PgPool,
+    http_client: &reqwest::Client,
     redis_client: &redis::Client,
     isolated: bool,
 ) {
+    // A result over RESULT_EVENT_MAX_BYTES is logged truncated; the receipt
+    // below still carries all of it
+    let mut warnings = warnings;
+    let stored_result = body
+        .as_ref()
+        .filter(|_| is_success && run_id.is_some())
+        .and_then(|b| events::truncate_result(b, events::result_event_max_bytes()));
+    if let Some(stored) = &stored_result {
+        warnings.push(NodeWarning {
+            code: "result_truncated".to_string(),
+            message: format!("Result of {} bytes exceeds RESULT_EVENT_MAX_BYTES; the run log keeps a preview", stored["size"]),
+        });
+    }
+
     // Log completion/failure event with retry_count for idempotency
     if let Some(rid) = run_id {
         if is_success {
@@ -1029,10 +1469,12 @@ async fn handle_final_result(
                 &job.id,
                 EventType::NodeCompleted,
                 Some(job.retry_count),
-                serde_json::json!({
-                    "result": body,
-                    "duration_ms": duration_ms,
-                }),
+                events::completed_payload(
+                    stored_result.as_ref().or(body.as_ref()),
+                    duration_ms,
+                    route_to.as_deref(),
+                    &warnings,
+                ),
             )
             .await;
         } else {
@@ -1065,62 +1507,77 @@ async fn handle_final_result(
             .as_millis() as u64,
         duration_ms,
         isolated,
+        route_to,
+        warnings,
     };
 
-    if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await {
-        if let Ok(receipt_json) = serde_json::to_string(&receipt) {
+    if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await
+        && let Ok(receipt_json) = serde_json::to_string(&receipt) {
             let _: RedisResult<String> = con
                 .xadd(STREAM_RESULTS, "*", &[("payload", receipt_json)])
                 .await;
         }
+
+    // A Parallel branch reports to its node, a Loop body to its Loop node
+    // (isolated, so the orchestrator isn't told)
+    if let Some(rid) = run_id {
+        if let Some(branch) = &job.parallel_branch {
+            nodes::parallel::report_branch(redis_client, branch, rid, &job.id, job.priority, is_success, receipt.body).await;
+        } else if let Some(iteration) = &job.loop_iteration {
+            nodes::loop_node::report_iteration(redis_client, iteration, rid, &job.id, is_success, receipt.body).await;
+        }
     }
     
     // Call orchestrator to schedule next nodes (server-side, not relying on frontend)
     // This is critical for child runs (sub-flows, map iterations) that have no frontend
-    if !isolated {
-        if let Some(rid) = run_id {
-            notify_orchestrator(rid, &job.id, is_success).await;
+    if !isolated
+        && let Some(rid) = run_id {
+            notify_orchestrator(http_client, redis_client, rid, &job.id, is_success).await;
         }
-    }
 }
 
 /// Notify the orchestrator that a node has completed.
 /// This triggers scheduling of dependent nodes and handles parent notifications for child runs.
-async fn notify_orchestrator(run_id: &Uuid, node_id: &str, success: bool) {
-    verbose_log!("  -> Notifying orchestrator: run={}, node={}, success={}", run_id, node_id, success);
-    
-    let base_url = std::env::var("ORCHESTRATOR_URL")
-        .unwrap_or_else(|_| "http://localhost:5173".to_string());
-    
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(format!("{}/api/orchestrate", base_url))
-        .json(&serde_json::json!({
-            "runId": run_id.to_string(),
-            "nodeId": node_id,
-            "success": success
-        }))
-        .send()
-        .await;
-    
-    match resp {
-        Ok(r) => {
-            if r.status().is_success() {
-                verbose_log!("  -> Orchestrator OK");
-            } else {
-                verbose_log!("  -> Orchestrator returned {}", r.status());
-            }
-        }
-        Err(e) => {
-            eprintln!("  -> Failed to notify orchestrator: {}", e);
-        }
+/// Retries with backoff in the background, then falls back to the scheduler-drained retry queue.
+async fn notify_orchestrator(
+    http_client: &reqwest::Client,
+    redis_client: &redis::Client,
+    run_id: &Uuid,
+    node_id: &str,
+    success: bool,
+) {
+    tracing::debug!(%run_id, node_id, success, "Notifying orchestrator");
+    orchestrator::notify(http_client, redis_client, run_id, node_id, success).await;
+}
+
+/// A delivery whose key another delivery holds comes back once the holder's
+/// `lease` has run out: by then the key is completed (and this one skipped)
+/// or free again because the holder died (and this one takes over). Parked in
+/// the delayed set and ACKed here, so waiting on a live holder never counts
+/// towards the message's deliveries. If parking fails it stays un-ACKed for
+/// redelivery instead.
+async fn defer_held_delivery(
+    job: &WorkerJob,
+    lease: Duration,
+    redis_client: &redis::Client,
+    stream: &str,
+    group_name: &str,
+    msg_id: &str,
+) {
+    let Ok(payload) = serde_json::to_string(job) else {
+        return;
+    };
+    let delay_ms = lease.as_millis() as u64;
+    match nodes::delay::schedule_job(redis_client, &payload, job.run_id.as_deref(), delay_ms).await {
+        Ok(()) => ack_message(redis_client, stream, group_name, msg_id).await,
+        Err(e) => tracing::warn!(error = %e, "Failed to park held delivery; leaving it for redelivery"),
     }
 }
 
-async fn ack_message(redis_client: &redis::Client, group_name: &str, msg_id: &str) {
+async fn ack_message(redis_client: &redis::Client, stream: &str, group_name: &str, msg_id: &str) {
     if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await {
-        let _: RedisResult<()> = con.xack(STREAM_JOBS, group_name, &[msg_id]).await;
-        let _: RedisResult<()> = con.xdel(STREAM_JOBS, &[msg_id]).await;
+        let _: RedisResult<()> = con.xack(stream, group_name, &[msg_id]).await;
+        let _: RedisResult<()> = con.xdel(stream, &[msg_id]).await;
     }
 }
 
@@ -1134,6 +1591,7 @@ async fn heartbeat_loop(
     redis_client: redis::Client,
     worker_id: String,
     in_flight: Arc<AtomicUsize>,
+    is_leader: Arc<AtomicBool>,
 ) {
     let mut interval = tokio::time::interval(Duration::from_secs(1)); // Fast heartbeat for real-time UI
     
@@ -1144,7 +1602,7 @@ async fn heartbeat_loop(
         interval.tick().await;
         
         // Gather stats
-        let jobs_processed = JOBS_PROCESSED.load(Ordering::Relaxed);
+        let jobs_processed = metrics::METRICS.jobs_processed();
         let current_jobs = in_flight.load(Ordering::SeqCst);
         let uptime_secs = START_TIME.elapsed().as_secs();
         
@@ -1160,6 +1618,7 @@ async fn heartbeat_loop(
             "jobs_processed": jobs_processed,
             "current_jobs": current_jobs,
             "uptime_secs": uptime_secs,
+            "scheduler_leader": is_leader.load(Ordering::Relaxed),
             "last_seen": chrono::Utc::now().to_rfc3339(),
         });
         
@@ -0,0 +1,342 @@
+//! Prometheus metrics for the worker.
+//!
+//! Counters live in a process-wide `METRICS`; `serve` exposes them in the
+//! Prometheus text format on `GET /metrics` when `METRICS_PORT` is set. The
+//! server is the bare TCP `responder`: scrapes are tiny and this avoids pulling
+//! an HTTP server framework into the worker.
+//!
+//! Job durations are also summarised per node type over a rolling window and
+//! flushed to `node_type_latency` every `NODE_LATENCY_FLUSH_SECS` (default 60),
+//! so "are HTTP nodes slow right now?" is a single-row lookup. Percentiles are
+//! interpolated within the histogram buckets, so they are estimates.
+
+use crate::responder::{self, Response};
+use crate::types::NodeType;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the `job_duration_seconds` buckets
+const DURATION_BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Per-bucket (non-cumulative) counts; the last slot is +Inf
+    buckets: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+    max: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        let idx = DURATION_BUCKETS.iter().position(|le| secs <= *le).unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[idx] += 1;
+        self.sum += secs;
+        self.count += 1;
+        self.max = self.max.max(secs);
+    }
+
+    /// Estimated `q` quantile (seconds), interpolating linearly inside the
+    /// bucket it falls in (the +Inf bucket ends at the observed max).
+    fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = q.clamp(0.0, 1.0) * self.count as f64;
+        let mut seen = 0u64;
+        for (idx, count) in self.buckets.iter().enumerate() {
+            if *count == 0 || ((seen + count) as f64) < rank {
+                seen += count;
+                continue;
+            }
+            let lower = if idx == 0 { 0.0 } else { DURATION_BUCKETS[idx - 1] };
+            let upper = DURATION_BUCKETS.get(idx).copied().unwrap_or(self.max).min(self.max);
+            let fraction = (rank - seen as f64) / *count as f64;
+            return (lower + (upper - lower) * fraction).min(self.max);
+        }
+        self.max
+    }
+}
+
+/// Per-node-type latency over one flush window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencySummary {
+    pub node_type: &'static str,
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    jobs_processed: AtomicU64,
+    retries: AtomicU64,
+    js_executions: AtomicU64,
+    js_restarts: AtomicU64,
+    durations: Mutex<BTreeMap<&'static str, Histogram>>,
+    /// Same observations, reset by each `take_window`
+    window: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    pub fn job_processed(&self) {
+        self.jobs_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn jobs_processed(&self) -> u64 {
+        self.jobs_processed.load(Ordering::Relaxed)
+    }
+
+    pub fn retry_scheduled(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn js_executed(&self) {
+        self.js_executions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn js_restarted(&self) {
+        self.js_restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_duration(&self, node_type: &'static str, duration: Duration) {
+        for histograms in [&self.durations, &self.window] {
+            histograms
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(node_type)
+                .or_default()
+                .observe(duration.as_secs_f64());
+        }
+    }
+
+    /// Summaries of everything observed since the last call.
+    pub fn take_window(&self) -> Vec<LatencySummary> {
+        let window = std::mem::take(&mut *self.window.lock().unwrap_or_else(|e| e.into_inner()));
+        window
+            .into_iter()
+            .map(|(node_type, hist)| LatencySummary {
+                node_type,
+                count: hist.count,
+                mean_ms: hist.sum / hist.count.max(1) as f64 * 1000.0,
+                p50_ms: hist.quantile(0.5) * 1000.0,
+                p95_ms: hist.quantile(0.95) * 1000.0,
+                p99_ms: hist.quantile(0.99) * 1000.0,
+                max_ms: hist.max * 1000.0,
+            })
+            .collect()
+    }
+
+    /// Everything in the Prometheus text exposition format.
+    pub fn render(&self, in_flight: usize) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}");
+        };
+
+        counter(&mut out, "jobs_processed_total", "Jobs this worker finished processing.", self.jobs_processed());
+        let _ = writeln!(
+            out,
+            "# HELP jobs_in_flight Jobs currently executing.\n# TYPE jobs_in_flight gauge\njobs_in_flight {}",
+            in_flight
+        );
+        counter(&mut out, "retries_total", "Retries scheduled for failed jobs.", self.retries.load(Ordering::Relaxed));
+        counter(&mut out, "js_executions_total", "JavaScript executions run.", self.js_executions.load(Ordering::Relaxed));
+        counter(&mut out, "js_runtime_restarts_total", "JS runtimes restarted after a crash.", self.js_restarts.load(Ordering::Relaxed));
+
+        let _ = writeln!(
+            out,
+            "# HELP job_duration_seconds Job execution time by node type.\n# TYPE job_duration_seconds histogram"
+        );
+        let durations = self.durations.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        for (node_type, hist) in durations {
+            let mut cumulative = 0;
+            for (le, count) in DURATION_BUCKETS.iter().zip(hist.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "job_duration_seconds_bucket{{node_type=\"{node_type}\",le=\"{le}\"}} {cumulative}");
+            }
+            let _ = writeln!(out, "job_duration_seconds_bucket{{node_type=\"{node_type}\",le=\"+Inf\"}} {}", hist.count);
+            let _ = writeln!(out, "job_duration_seconds_sum{{node_type=\"{node_type}\"}} {}", hist.sum);
+            let _ = writeln!(out, "job_duration_seconds_count{{node_type=\"{node_type}\"}} {}", hist.count);
+        }
+        out
+    }
+}
+
+/// `node_type` label for a job.
+pub fn node_type_label(node: &NodeType) -> &'static str {
+    match node {
+        NodeType::Http(_) => "http",
+        NodeType::Code(_) => "code",
+        NodeType::Delay(_) => "delay",
+        NodeType::DelayResume(_) => "delay_resume",
+        NodeType::WebhookWait(_) => "webhook_wait",
+        NodeType::WebhookResume(_) => "webhook_resume",
+        NodeType::DbWait(_) => "db_wait",
+        NodeType::DbWaitResume(_) => "db_wait_resume",
+        NodeType::Router(_) => "router",
+        NodeType::Conditional(_) => "conditional",
+        NodeType::Llm(_) => "llm",
+        NodeType::SubFlow(_) => "subflow",
+        NodeType::SubFlowResume(_) => "subflow_resume",
+        NodeType::Map(_) => "map",
+        NodeType::MapStep(_) => "map_step",
+        NodeType::MapChildComplete(_) => "map_child_complete",
+        NodeType::Parallel(_) => "parallel",
+        NodeType::ParallelBranchComplete(_) => "parallel_branch_complete",
+        NodeType::Loop(_) => "loop",
+        NodeType::LoopIterationComplete(_) => "loop_iteration_complete",
+        NodeType::Aggregate(_) => "aggregate",
+        NodeType::WebSocket(_) => "websocket",
+        NodeType::DbUpsert(_) => "db_upsert",
+        NodeType::Transform(_) => "transform",
+        NodeType::Email(_) => "email",
+        NodeType::S3(_) => "s3",
+    }
+}
+
+/// How often latency summaries are written (`NODE_LATENCY_FLUSH_SECS`; 0 = never).
+pub fn latency_flush_interval() -> Option<Duration> {
+    let secs: u64 = std::env::var("NODE_LATENCY_FLUSH_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Write one window's summaries and drop rows older than a week.
+async fn flush_latency(
+    pool: &PgPool,
+    worker_id: &str,
+    window_start: DateTime<Utc>,
+    summaries: &[LatencySummary],
+) -> Result<(), sqlx::Error> {
+    for s in summaries {
+        sqlx::query(
+            r#"
+            INSERT INTO node_type_latency
+                (worker_id, node_type, window_start, window_end, count, mean_ms, p50_ms, p95_ms, p99_ms, max_ms)
+            VALUES ($1, $2, $3, NOW(), $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(worker_id)
+        .bind(s.node_type)
+        .bind(window_start)
+        .bind(s.count as i64)
+        .bind(s.mean_ms)
+        .bind(s.p50_ms)
+        .bind(s.p95_ms)
+        .bind(s.p99_ms)
+        .bind(s.max_ms)
+        .execute(pool)
+        .await?;
+    }
+    sqlx::query("DELETE FROM node_type_latency WHERE window_end < NOW() - INTERVAL '7 days'")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Flush latency summaries to `node_type_latency` every `interval`.
+pub async fn flush_latency_loop(pool: PgPool, worker_id: String, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await; // First tick completes immediately
+    let mut window_start = Utc::now();
+
+    loop {
+        ticker.tick().await;
+        let summaries = METRICS.take_window();
+        let window_end = Utc::now();
+        if !summaries.is_empty()
+            && let Err(e) = flush_latency(&pool, &worker_id, window_start, &summaries).await
+        {
+            tracing::error!("Metrics: Failed to flush node latency: {}", e);
+        }
+        window_start = window_end;
+    }
+}
+
+/// Port for the metrics endpoint (`METRICS_PORT`; unset or 0 = disabled).
+pub fn port() -> Option<u16> {
+    std::env::var("METRICS_PORT").ok().and_then(|v| v.parse().ok()).filter(|p| *p != 0)
+}
+
+/// Serve `GET /metrics` until the listener fails.
+pub async fn serve(port: u16, in_flight: Arc<AtomicUsize>) {
+    tracing::info!("Metrics: Serving Prometheus metrics on :{}/metrics", port);
+    responder::serve("Metrics", port, move |path| {
+        let in_flight = Arc::clone(&in_flight);
+        async move {
+            (path == "/metrics").then(|| {
+                let body = METRICS.render(in_flight.load(Ordering::SeqCst));
+                Response::new(200, "text/plain; version=0.0.4", body)
+            })
+        }
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.job_processed();
+        metrics.job_processed();
+        metrics.retry_scheduled();
+        metrics.js_executed();
+        metrics.observe_duration("http", Duration::from_millis(40));
+        metrics.observe_duration("http", Duration::from_secs(3));
+        metrics.observe_duration("http", Duration::from_secs(90));
+
+        let text = metrics.render(4);
+        assert!(text.contains("jobs_processed_total 2\n"));
+        assert!(text.contains("jobs_in_flight 4\n"));
+        assert!(text.contains("retries_total 1\n"));
+        assert!(text.contains("js_executions_total 1\n"));
+        assert!(text.contains("# TYPE job_duration_seconds histogram\n"));
+        // Buckets are cumulative; the 90s job only lands in +Inf
+        assert!(text.contains("job_duration_seconds_bucket{node_type=\"http\",le=\"0.05\"} 1\n"));
+        assert!(text.contains("job_duration_seconds_bucket{node_type=\"http\",le=\"5\"} 2\n"));
+        assert!(text.contains("job_duration_seconds_bucket{node_type=\"http\",le=\"30\"} 2\n"));
+        assert!(text.contains("job_duration_seconds_bucket{node_type=\"http\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("job_duration_seconds_count{node_type=\"http\"} 3\n"));
+    }
+
+    #[test]
+    fn test_latency_window_percentiles() {
+        let metrics = Metrics::default();
+        for ms in 1..=100 {
+            metrics.observe_duration("http", Duration::from_millis(ms));
+        }
+        metrics.observe_duration("code", Duration::from_millis(2));
+
+        let window = metrics.take_window();
+        assert_eq!(window.len(), 2);
+        let http = window.iter().find(|s| s.node_type == "http").unwrap();
+        assert_eq!(http.count, 100);
+        assert!((http.mean_ms - 50.5).abs() < 1e-6);
+        // Estimates stay inside the bucket that holds the true value
+        assert!((25.0..=50.0).contains(&http.p50_ms), "p50 {}", http.p50_ms);
+        assert!((50.0..=100.0).contains(&http.p95_ms), "p95 {}", http.p95_ms);
+        assert!(http.p99_ms <= http.max_ms);
+        assert!((http.max_ms - 100.0).abs() < 1e-6);
+
+        // The window resets; the Prometheus histogram doesn't
+        assert!(metrics.take_window().is_empty());
+        assert!(metrics.render(0).contains("job_duration_seconds_count{node_type=\"http\"} 100\n"));
+    }
+}
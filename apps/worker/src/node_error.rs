@@ -0,0 +1,135 @@
+//! Typed outcome of a node execution that didn't simply succeed.
+//!
+//! `process_job` decides what to do with a job (ACK, retry, suspend, leave
+//! for redelivery) from the `NodeError` variant instead of sniffing status
+//! codes and error strings.
+
+use crate::nodes::{MapError, SubFlowError};
+use serde_json::{json, Value};
+
+/// Result of `execute_node`: `Ok((status, body))` for completions (and
+/// lifecycle progress updates), `Err` for everything else.
+pub type NodeResult = Result<(u16, Option<Value>), NodeError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeError {
+    /// Infrastructure failure (pool timeout, lost connection). The message is
+    /// not ACKed, so the consumer group redelivers it.
+    Transient(String),
+    /// The node itself failed. Retried per `is_retryable_error(status)` and
+    /// `max_retries`, then recorded as the node's final result.
+    Permanent { status: u16, body: Value },
+    /// The run was cancelled while the node was executing.
+    Cancelled(Value),
+    /// The node is waiting on an external trigger (sub-flow child, map batch, webhook).
+    Suspended(Value),
+}
+
+impl NodeError {
+    pub fn permanent(status: u16, message: impl Into<String>) -> Self {
+        NodeError::Permanent { status, body: json!({ "error": message.into() }) }
+    }
+
+    /// Classify a handler's `(status, body, was_cancelled)` tuple.
+    pub fn classify(status: u16, body: Option<Value>, cancelled: bool) -> NodeResult {
+        if cancelled {
+            return Err(NodeError::Cancelled(body.unwrap_or_else(|| json!({ "error": "Cancelled" }))));
+        }
+        let suspends = status == 202
+            && body
+                .as_ref()
+                .is_some_and(|b| b.get("suspended").is_some() || b.get("batch_id").is_some());
+        if suspends {
+            return Err(NodeError::Suspended(body.unwrap_or_default()));
+        }
+        if (200..300).contains(&status) {
+            return Ok((status, body));
+        }
+        Err(NodeError::Permanent { status, body: body.unwrap_or_else(|| json!({ "error": format!("HTTP {}", status) })) })
+    }
+
+    /// A database error: connection-level failures are transient, the rest
+    /// (constraint violations, bad SQL) are the node's fault.
+    pub fn from_sqlx(context: &str, e: &sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) | sqlx::Error::WorkerCrashed => {
+                NodeError::Transient(format!("{}: {}", context, e))
+            }
+            _ => NodeError::permanent(500, format!("{}: {}", context, e)),
+        }
+    }
+
+    /// Status code and body for receipts and events.
+    pub fn into_parts(self) -> (u16, Option<Value>) {
+        match self {
+            NodeError::Transient(message) => (500, Some(json!({ "error": message }))),
+            NodeError::Permanent { status, body } => (status, Some(body)),
+            NodeError::Cancelled(body) => (499, Some(body)),
+            NodeError::Suspended(body) => (202, Some(body)),
+        }
+    }
+}
+
+/// `(status, body)` for either side of a `NodeResult`.
+pub fn into_parts(result: NodeResult) -> (u16, Option<Value>) {
+    match result {
+        Ok(parts) => parts,
+        Err(e) => e.into_parts(),
+    }
+}
+
+impl From<MapError> for NodeError {
+    fn from(e: MapError) -> Self {
+        match e {
+            MapError::DatabaseError(_) => NodeError::Transient(e.to_string()),
+            // An oversized batch is a config error; retrying won't shrink it
+            MapError::TooManyItems { .. } | MapError::DepthLimitExceeded { .. } => NodeError::permanent(400, e.to_string()),
+            MapError::Cancelled(_) => NodeError::Cancelled(json!({ "error": e.to_string() })),
+            MapError::ExecutionError(_) => NodeError::permanent(500, e.to_string()),
+        }
+    }
+}
+
+impl From<SubFlowError> for NodeError {
+    fn from(e: SubFlowError) -> Self {
+        match e {
+            SubFlowError::DatabaseError(_) => NodeError::Transient(e.to_string()),
+            _ => NodeError::permanent(500, e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_handler_tuples() {
+        assert_eq!(NodeError::classify(200, Some(json!({"ok": true})), false), Ok((200, Some(json!({"ok": true})))));
+        assert!(matches!(NodeError::classify(499, None, true), Err(NodeError::Cancelled(_))));
+        assert!(matches!(
+            NodeError::classify(202, Some(json!({"suspended": true})), false),
+            Err(NodeError::Suspended(_))
+        ));
+        // A bare 202 (lifecycle progress) is a completion, not a suspension
+        assert!(NodeError::classify(202, Some(json!({"progress": 3})), false).is_ok());
+        assert_eq!(
+            NodeError::classify(503, None, false).unwrap_err().into_parts().0,
+            503
+        );
+    }
+
+    #[test]
+    fn test_database_errors_are_transient_by_kind() {
+        assert!(matches!(NodeError::from_sqlx("Upsert failed", &sqlx::Error::PoolTimedOut), NodeError::Transient(_)));
+        assert!(matches!(
+            NodeError::from_sqlx("Upsert failed", &sqlx::Error::RowNotFound),
+            NodeError::Permanent { status: 500, .. }
+        ));
+        assert!(matches!(NodeError::from(MapError::DatabaseError("x".into())), NodeError::Transient(_)));
+        assert!(matches!(
+            NodeError::from(MapError::TooManyItems { count: 2, limit: 1 }),
+            NodeError::Permanent { status: 400, .. }
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+//! Aggregate node execution.
+//!
+//! Combines the outputs of several upstream nodes into one object without a
+//! code node. Each source's latest `NODE_COMPLETED` result in the run is read
+//! from `run_events` and stored under the source's key (its node id unless
+//! renamed); a source that hasn't completed gives `null`.
+
+use crate::node_error::{NodeError, NodeResult};
+use crate::types::{AggregateNodeData, AggregateSource};
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Read the sources' results and merge them.
+pub async fn execute(pool: &PgPool, run_id: &Uuid, data: &AggregateNodeData) -> NodeResult {
+    validate_keys(&data.sources).map_err(|e| NodeError::permanent(400, e))?;
+
+    let node_ids: Vec<&str> = data.sources.iter().map(|s| s.node_id.as_str()).collect();
+    let rows: Vec<(String, Option<Value>)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT ON (node_id) node_id, payload FROM run_events
+        WHERE run_id = $1 AND event_type = 'NODE_COMPLETED' AND node_id = ANY($2)
+        ORDER BY node_id, id DESC
+        "#,
+    )
+    .bind(run_id)
+    .bind(&node_ids)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to load source outputs", &e))?;
+
+    let outputs: HashMap<String, Value> = rows
+        .into_iter()
+        .map(|(node_id, payload)| (node_id, payload.and_then(|p| p.get("result").cloned()).unwrap_or(Value::Null)))
+        .collect();
+
+    Ok((200, Some(merge(&data.sources, &outputs))))
+}
+
+/// Two sources writing the same key would silently drop one of them.
+fn validate_keys(sources: &[AggregateSource]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for source in sources {
+        if !seen.insert(source.output_key()) {
+            return Err(format!("Aggregate key '{}' is used by more than one source", source.output_key()));
+        }
+    }
+    Ok(())
+}
+
+/// `{key: result}` for every source, `null` where the source has no result.
+fn merge(sources: &[AggregateSource], outputs: &HashMap<String, Value>) -> Value {
+    let merged: Map<String, Value> = sources
+        .iter()
+        .map(|s| (s.output_key().to_string(), outputs.get(&s.node_id).cloned().unwrap_or(Value::Null)))
+        .collect();
+    Value::Object(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn source(node_id: &str, key: Option<&str>) -> AggregateSource {
+        AggregateSource { node_id: node_id.to_string(), key: key.map(String::from) }
+    }
+
+    #[test]
+    fn test_merge_renames_and_nulls_missing_sources() {
+        let outputs = HashMap::from([
+            ("fetch_user".to_string(), json!({"id": 7})),
+            ("fetch_orders".to_string(), json!([1, 2])),
+        ]);
+        let sources = [source("fetch_user", Some("user")), source("fetch_orders", None), source("skipped", None)];
+
+        assert_eq!(
+            merge(&sources, &outputs),
+            json!({"user": {"id": 7}, "fetch_orders": [1, 2], "skipped": null})
+        );
+    }
+
+    #[test]
+    fn test_duplicate_keys_are_rejected() {
+        assert!(validate_keys(&[source("a", Some("x")), source("b", None)]).is_ok());
+        let err = validate_keys(&[source("a", Some("b")), source("b", None)]).unwrap_err();
+        assert!(err.contains("'b'"));
+    }
+}
@@ -4,12 +4,23 @@
 //! - Execution timeout (default 5s, configurable)
 //! - Memory limit (default 16MB)
 //! - Instruction limit (prevents infinite loops)
+//! - Cancellation (`JsTask::cancelled`, polled by the interrupt handler)
+//!
+//! Code runs as an async function: a returned Promise (or `return await ...`)
+//! is settled by driving the QuickJS job queue before the result is serialized.
+//!
+//! Nothing is spliced into a script in Rust: the code is compiled as a function
+//! body from a string value (checked first to be exactly one body) and `INPUT`
+//! is parsed inside the sandbox from its JSON. Inputs over `JS_MAX_INPUT_BYTES` are rejected before dispatch.
+//!
+//! `console.log`/`info`/`warn`/`error`/`debug` are captured and returned with
+//! the result (and can be streamed live through `JsTask::log_sender`).
 
-use rquickjs::{AsyncContext, Value};
+use rquickjs::{AsyncContext, CatchResultExt, CaughtError, Ctx, Exception, Function, Value};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::oneshot;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 
 /// Default execution timeout in milliseconds
 const DEFAULT_TIMEOUT_MS: u64 = 5000;
@@ -20,12 +31,125 @@ const DEFAULT_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
 /// Default instruction limit (10 million ops - enough for complex code, stops infinite loops)
 const DEFAULT_INSTRUCTION_LIMIT: u64 = 10_000_000;
 
+/// Default longest timeout a node may ask for (5 minutes)
+const DEFAULT_MAX_TIMEOUT_MS: u64 = 300_000;
+
+/// Default extra time the caller waits for the JS thread beyond the JS timeout
+const DEFAULT_CHANNEL_MARGIN_MS: u64 = 5000;
+
+/// Default cap on the serialized size of a code node's `INPUT` (4MB)
+const DEFAULT_MAX_INPUT_BYTES: usize = 4 * 1024 * 1024;
+
+/// Most leftover promise jobs discarded after a task
+const MAX_DRAINED_JOBS: usize = 10_000;
+
+/// Max console lines captured per execution
+const MAX_LOG_LINES: usize = 1000;
+
+/// Max bytes kept from a single console line
+const MAX_LOG_LINE_BYTES: usize = 10 * 1024;
+
+/// Installs `console`, forwarding formatted lines to the Rust capture function
+const CONSOLE_SHIM: &str = r#"
+globalThis.console = (() => {
+    const fmt = (a) => {
+        if (typeof a === 'string') return a;
+        try { return JSON.stringify(a) ?? String(a); } catch { return String(a); }
+    };
+    const level = (name) => (...args) => __swiftgrid_log(name, args.map(fmt).join(' '));
+    return { log: level('log'), info: level('info'), debug: level('debug'), warn: level('warn'), error: level('error') };
+})();
+"#;
+
+/// Compiles `__swiftgrid_code` as an async function of `INPUT` (so `await`
+/// works) and calls it with the parsed input; the result is always a Promise
+const RUN_USER_CODE: &str = "new (async () => {}).constructor('INPUT', globalThis.__swiftgrid_code)(globalThis.__swiftgrid_input)";
+
+/// 'ok' when `__swiftgrid_code` compiles as exactly one function body,
+/// 'syntax' when it doesn't compile, 'escaped' when it closes the wrapper
+const CHECK_CODE_BOUNDARY: &str = r#"
+(() => {
+    const code = globalThis.__swiftgrid_code;
+    const toString = Function.prototype.toString;
+    const AsyncFunction = (async () => {}).constructor;
+    let fn;
+    try {
+        fn = new AsyncFunction('INPUT', code);
+    } catch (e) {
+        return e instanceof SyntaxError ? 'syntax' : 'escaped';
+    }
+    return toString.call(fn) === 'async function anonymous(INPUT\n) {\n' + code + '\n}' ? 'ok' : 'escaped';
+})()
+"#;
+
 /// Task sent to the JS runtime thread.
 pub struct JsTask {
     pub code: String,
     pub inputs: Option<serde_json::Value>,
-    pub responder: oneshot::Sender<Result<serde_json::Value, String>>,
+    pub responder: oneshot::Sender<Result<JsOutput, JsError>>,
     pub timeout_ms: Option<u64>,
+    /// Receives each console line as it's logged (for live streaming)
+    pub log_sender: Option<mpsc::UnboundedSender<String>>,
+    /// Set when the run is cancelled; the interrupt handler then aborts the script
+    pub cancelled: Option<Arc<AtomicBool>>,
+}
+
+/// A successful JS execution.
+#[derive(Debug)]
+pub struct JsOutput {
+    pub value: serde_json::Value,
+    /// Captured console output
+    pub logs: Vec<String>,
+}
+
+/// Whether a JS failure is the user's fault or the runtime's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsErrorKind {
+    /// Deterministic: syntax errors, thrown exceptions, infinite loops.
+    /// Running it again gives the same result.
+    User,
+    /// Runtime/resource failure (memory pressure, engine errors); may succeed on retry.
+    Runtime,
+    /// The run was cancelled while the script was executing.
+    Cancelled,
+}
+
+/// A failed JS execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsError {
+    pub kind: JsErrorKind,
+    pub message: String,
+    /// Console output captured before the failure
+    pub logs: Vec<String>,
+}
+
+impl JsError {
+    pub fn user(message: impl Into<String>) -> Self {
+        Self { kind: JsErrorKind::User, message: message.into(), logs: Vec::new() }
+    }
+
+    pub fn runtime(message: impl Into<String>) -> Self {
+        Self { kind: JsErrorKind::Runtime, message: message.into(), logs: Vec::new() }
+    }
+
+    pub fn cancelled() -> Self {
+        Self { kind: JsErrorKind::Cancelled, message: "Execution cancelled".to_string(), logs: Vec::new() }
+    }
+
+    /// Status code for the node result: 400 (not retried), 500 (retryable) or 499 (cancelled).
+    pub fn status_code(&self) -> u16 {
+        match self.kind {
+            JsErrorKind::User => 400,
+            JsErrorKind::Runtime => 500,
+            JsErrorKind::Cancelled => 499,
+        }
+    }
+}
+
+impl std::fmt::Display for JsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
 /// Sandbox configuration for JS execution
@@ -34,6 +158,8 @@ pub struct SandboxConfig {
     pub timeout_ms: u64,
     pub memory_limit: usize,
     pub instruction_limit: u64,
+    /// Grace period on top of `timeout_ms` before the caller gives up waiting
+    pub channel_margin_ms: u64,
 }
 
 impl Default for SandboxConfig {
@@ -51,10 +177,83 @@ impl Default for SandboxConfig {
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(DEFAULT_INSTRUCTION_LIMIT),
+            channel_margin_ms: std::env::var("JS_CHANNEL_MARGIN_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_CHANNEL_MARGIN_MS),
         }
     }
 }
 
+impl SandboxConfig {
+    /// Default config with a task's timeout override applied, capped at
+    /// `JS_MAX_TIMEOUT_MS`.
+    pub fn for_task(timeout_ms: Option<u64>) -> Self {
+        let mut config = Self::default();
+        if let Some(timeout_ms) = timeout_ms {
+            config.timeout_ms = timeout_ms.min(max_timeout_ms());
+        }
+        config
+    }
+
+    /// How long to wait on the JS thread's response channel.
+    ///
+    /// Derived from the JS timeout so the sandbox always gets to report its own
+    /// timeout error before the caller gives up.
+    pub fn channel_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.saturating_add(self.channel_margin_ms))
+    }
+}
+
+/// Longest timeout a node's `timeout_ms` can set (`JS_MAX_TIMEOUT_MS`).
+pub fn max_timeout_ms() -> u64 {
+    std::env::var("JS_MAX_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_TIMEOUT_MS)
+}
+
+/// When a script started now with `timeout` must stop. Saturates instead of
+/// panicking, so an absurd timeout can't take down the JS thread.
+fn deadline_after(timeout: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(timeout)
+        .or_else(|| now.checked_add(Duration::from_millis(DEFAULT_MAX_TIMEOUT_MS)))
+        .unwrap_or(now)
+}
+
+/// Largest serialized `INPUT` a code node accepts (`JS_MAX_INPUT_BYTES`, 0 = no limit).
+pub fn max_input_bytes() -> usize {
+    std::env::var("JS_MAX_INPUT_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_INPUT_BYTES)
+}
+
+/// The serialized size of `inputs` when it's over `limit` (0 = no limit).
+///
+/// Counts without building the string, so an oversized input isn't copied
+/// just to be rejected.
+pub fn oversized_input(inputs: &serde_json::Value, limit: usize) -> Option<usize> {
+    struct Count(usize);
+    impl std::io::Write for Count {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    if limit == 0 {
+        return None;
+    }
+    let mut count = Count(0);
+    serde_json::to_writer(&mut count, inputs).ok()?;
+    (count.0 > limit).then_some(count.0)
+}
+
 /// Execute JavaScript code safely in a sandboxed context.
 /// 
 /// Protections:
@@ -65,7 +264,7 @@ pub async fn run_js_safely(
     ctx: &AsyncContext,
     code: String,
     inputs: Option<serde_json::Value>,
-) -> Result<serde_json::Value, String> {
+) -> Result<JsOutput, JsError> {
     let config = SandboxConfig::default();
     run_js_with_config(ctx, code, inputs, config).await
 }
@@ -76,7 +275,30 @@ pub async fn run_js_with_config(
     code: String,
     inputs: Option<serde_json::Value>,
     config: SandboxConfig,
-) -> Result<serde_json::Value, String> {
+) -> Result<JsOutput, JsError> {
+    run_js_with_logs(ctx, code, inputs, config, None, None).await
+}
+
+/// Execute JavaScript, also sending each console line to `log_sender` as it happens.
+///
+/// Setting `cancelled` aborts the script at the next interrupt check with a
+/// `JsErrorKind::Cancelled` error.
+pub async fn run_js_with_logs(
+    ctx: &AsyncContext,
+    code: String,
+    inputs: Option<serde_json::Value>,
+    config: SandboxConfig,
+    log_sender: Option<mpsc::UnboundedSender<String>>,
+    cancelled: Option<Arc<AtomicBool>>,
+) -> Result<JsOutput, JsError> {
+    let cancelled = cancelled.unwrap_or_default();
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(JsError::cancelled());
+    }
+
+    let logs = Arc::new(Mutex::new(Vec::new()));
+    let captured = logs.clone();
+
     // Instruction counter for loop protection
     // Note: Full instruction counting requires QuickJS interrupt handler setup at runtime level
     // For now we rely on timeout as the primary protection against infinite loops
@@ -85,38 +307,83 @@ pub async fn run_js_with_config(
     
     // Wrap execution in a timeout
     let timeout = Duration::from_millis(config.timeout_ms);
-    
+
+    // eval() is synchronous, so the tokio timeout below can't preempt a busy loop.
+    // The interrupt handler is polled by QuickJS during execution and aborts it
+    // once the deadline has passed or the run is cancelled.
+    // `timed_out` records that the deadline (not a cancel) stopped the script,
+    // so the failure is reported from that rather than from the error text.
+    let deadline = deadline_after(timeout);
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let interrupt_cancelled = cancelled.clone();
+    let interrupt_timed_out = timed_out.clone();
+    ctx.runtime()
+        .set_interrupt_handler(Some(Box::new(move || {
+            if interrupt_cancelled.load(Ordering::Relaxed) {
+                return true;
+            }
+            let expired = Instant::now() > deadline;
+            if expired {
+                interrupt_timed_out.store(true, Ordering::Relaxed);
+            }
+            expired
+        })))
+        .await;
+
+    let boundary = check_code_boundary(ctx, &code).await;
+
+    let eval_cancelled = cancelled.clone();
+    let eval_timed_out = timed_out.clone();
     let execution = ctx.async_with(|ctx| {
         Box::pin(async move {
             // Set up interrupt handler to count instructions and stop infinite loops
             // Note: QuickJS interrupt callback is set at runtime level, not context
             // We'll use a simpler approach - check instruction count periodically
 
+            // Fresh capture buffer per task (the context is reused across tasks)
+            let log_fn = Function::new(ctx.clone(), move |level: String, text: String| {
+                capture_log(&captured, log_sender.as_ref(), &level, text);
+            })
+            .map_err(|e| JsError::runtime(format!("Failed to install console: {}", e)))?;
+            ctx.globals()
+                .set("__swiftgrid_log", log_fn)
+                .and_then(|_| ctx.eval::<(), _>(CONSOLE_SHIM))
+                .map_err(|e| JsError::runtime(format!("Failed to install console: {}", e)))?;
+
             let input_json = serde_json::to_string(&inputs.unwrap_or(serde_json::json!({})))
                 .unwrap_or_else(|_| "{}".to_string());
 
-            // Wrap user code in an IIFE with INPUT available
-            let script = format!(
-                r#"
-                (function(INPUT) {{
-                    {code}
-                }})({input_json}) 
-                "#,
-                code = code,
-                input_json = input_json
-            );
-
-            match ctx.eval::<Value, _>(script) {
+            // Parsed by QuickJS into a global rather than spliced into the source,
+            // so the data can never be read as code
+            let input = ctx
+                .json_parse(input_json)
+                .map_err(|e| JsError::runtime(format!("Failed to load INPUT: {}", e)))?;
+            ctx.globals()
+                .set("__swiftgrid_input", input)
+                .map_err(|e| JsError::runtime(format!("Failed to load INPUT: {}", e)))?;
+
+            // The user code becomes a function body through the Function
+            // constructor; `check_code_boundary` already made sure it can't
+            // close that function early.
+            ctx.globals()
+                .set("__swiftgrid_code", code)
+                .map_err(|e| JsError::runtime(format!("Failed to load code: {}", e)))?;
+
+            let outcome = ctx
+                .eval::<Value, _>(RUN_USER_CODE)
+                .and_then(|v| settle(&ctx, v, deadline, &eval_timed_out))
+                .catch(&ctx);
+            match outcome {
                 Ok(v) => {
                     // Check if we exceeded limits during execution
                     if exceeded_clone.load(Ordering::Relaxed) {
-                        return Err("Execution limit exceeded (possible infinite loop)".to_string());
+                        return Err(JsError::user("Execution limit exceeded (possible infinite loop)"));
                     }
                     
                     // Serialize result to JSON
                     let json_func: rquickjs::Function = ctx
                         .eval("JSON.stringify")
-                        .map_err(|e| format!("Failed to get JSON.stringify: {}", e))?;
+                        .map_err(|e| JsError::runtime(format!("Failed to get JSON.stringify: {}", e)))?;
                     
                     match json_func.call::<_, String>((v,)) {
                         Ok(json_str) => {
@@ -125,43 +392,157 @@ pub async fn run_js_with_config(
                         Err(_) => Ok(serde_json::Value::Null),
                     }
                 }
-                Err(e) => {
-                    let error_msg = format!("{}", e);
-                    let error_lower = error_msg.to_lowercase();
-                    
-                    // Check for common error patterns
-                    // QuickJS often returns "Exception generated by QuickJS" for various limits
-                    if error_lower.contains("interrupted") {
-                        Err(format!(
-                            "Execution timeout: code exceeded {}s limit (possible infinite loop)",
-                            config.timeout_ms / 1000
-                        ))
-                    } else if error_lower.contains("out of memory") || error_lower.contains("memory") {
-                        Err(format!("Memory limit exceeded (max {}MB)", config.memory_limit / 1024 / 1024))
-                    } else if error_lower.contains("stack") || error_lower.contains("recursion") {
-                        Err("Stack overflow: too much recursion or deeply nested calls".to_string())
-                    } else if error_msg.contains("Exception generated by QuickJS") {
-                        // Generic QuickJS exception - could be memory, stack, or interrupt
-                        // Check if we're near the timeout deadline
-                        Err("Execution limit hit: timeout, memory, or stack limit exceeded".to_string())
-                    } else {
-                        Err(format!("JS Error: {}", error_msg))
-                    }
-                }
+                Err(_) if eval_cancelled.load(Ordering::Relaxed) => Err(JsError::cancelled()),
+                Err(_) if eval_timed_out.load(Ordering::Relaxed) => Err(JsError::user(format!(
+                    "Execution timeout: code exceeded {}ms limit (possible infinite loop)",
+                    config.timeout_ms
+                ))),
+                Err(e) => Err(classify_failure(e, config.memory_limit)),
             }
         })
     });
     
     // Apply timeout
-    match tokio::time::timeout(timeout, execution).await {
-        Ok(result) => result,
-        Err(_) => Err(format!(
-            "Execution timeout: code exceeded {}ms limit",
-            config.timeout_ms
+    let result = match boundary {
+        Err(e) => Err(e),
+        Ok(()) => match tokio::time::timeout(timeout, execution).await {
+            Ok(result) => result,
+            Err(_) if cancelled.load(Ordering::Relaxed) => Err(JsError::cancelled()),
+            Err(_) => Err(JsError::user(format!(
+                "Execution timeout: code exceeded {}ms limit",
+                config.timeout_ms
+            ))),
+        },
+    };
+
+    // Jobs an abandoned promise chain left queued must not run during the next
+    // task; with the interrupt always firing, each one aborts right away
+    if ctx.runtime().is_job_pending().await {
+        ctx.runtime().set_interrupt_handler(Some(Box::new(|| true))).await;
+        ctx.with(|ctx| {
+            for _ in 0..MAX_DRAINED_JOBS {
+                if !ctx.execute_pending_job() {
+                    break;
+                }
+            }
+        })
+        .await;
+    }
+    ctx.runtime().set_interrupt_handler(None).await;
+
+    let logs = std::mem::take(&mut *logs.lock().unwrap());
+    match result {
+        Ok(value) => Ok(JsOutput { value, logs }),
+        Err(e) => Err(JsError { logs, ..e }),
+    }
+}
+
+/// Record one console line (bounded), forwarding it to the live sender if any.
+/// Wait for a returned Promise by running the QuickJS job queue.
+///
+/// The sandbox has no timers or I/O, so once the queue is empty a pending
+/// promise can never settle; that fails right away instead of hanging. A
+/// chain that keeps queueing jobs is stopped at the task's deadline, reported
+/// like an interrupted script.
+fn settle<'js>(
+    ctx: &Ctx<'js>,
+    value: Value<'js>,
+    deadline: Instant,
+    timed_out: &AtomicBool,
+) -> rquickjs::Result<Value<'js>> {
+    let Some(promise) = value.as_promise() else {
+        return Ok(value);
+    };
+    loop {
+        if let Some(result) = promise.result::<Value>() {
+            return result;
+        }
+        if Instant::now() > deadline {
+            timed_out.store(true, Ordering::Relaxed);
+            return Err(Exception::throw_internal(ctx, "interrupted"));
+        }
+        if !ctx.execute_pending_job() {
+            return Err(Exception::throw_message(
+                ctx,
+                "Returned promise never settled (nothing left for it to wait on)",
+            ));
+        }
+    }
+}
+
+/// Turn a failed run (not cancelled, not timed out) into a `JsError` by what
+/// QuickJS threw.
+///
+/// The engine reports an exhausted memory limit as an `InternalError`, or as
+/// a bare `null` when it couldn't even allocate that; both are retryable. A
+/// stack overflow is a `RangeError` like any other the code throws, and
+/// everything else is the user's error.
+fn classify_failure(error: CaughtError<'_>, memory_limit: usize) -> JsError {
+    let out_of_memory = match &error {
+        CaughtError::Exception(e) => e.get::<_, String>("name").is_ok_and(|name| name == "InternalError"),
+        CaughtError::Value(v) => v.is_null(),
+        CaughtError::Error(e) => matches!(e, rquickjs::Error::Allocation),
+    };
+    if out_of_memory {
+        JsError::runtime(format!("Memory limit exceeded (max {}MB)", memory_limit / 1024 / 1024))
+    } else {
+        JsError::user(format!("JS Error: {}", error))
+    }
+}
+
+/// Check that the user code compiles as one function body.
+///
+/// QuickJS's Function constructor splices the body into source text, so code
+/// with an extra `})` would close the wrapper and run the rest at top level.
+/// The check runs in a scratch context (under the task's interrupt handler),
+/// so whatever such code does there never reaches the task's context.
+async fn check_code_boundary(ctx: &AsyncContext, code: &str) -> Result<(), JsError> {
+    let scratch = AsyncContext::full(ctx.runtime())
+        .await
+        .map_err(|e| JsError::runtime(format!("Failed to create JS context: {}", e)))?;
+    let code = code.to_string();
+    let verdict = scratch
+        .with(|ctx| {
+            ctx.globals().set("__swiftgrid_code", code)?;
+            ctx.eval::<String, _>(CHECK_CODE_BOUNDARY)
+        })
+        .await;
+    match verdict.as_deref() {
+        // Syntax errors are reported by the real run
+        Ok("ok") | Ok("syntax") => Ok(()),
+        _ => Err(JsError::user(
+            "JS Error: code closes its function wrapper early (unbalanced braces, e.g. an extra '}')",
         )),
     }
 }
 
+fn capture_log(logs: &Mutex<Vec<String>>, sender: Option<&mpsc::UnboundedSender<String>>, level: &str, text: String) {
+    let mut line = match level {
+        "warn" | "error" => format!("[{}] {}", level, text),
+        _ => text,
+    };
+    if line.len() > MAX_LOG_LINE_BYTES {
+        let mut end = MAX_LOG_LINE_BYTES;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        line.truncate(end);
+        line.push_str("...");
+    }
+
+    let mut logs = logs.lock().unwrap();
+    if logs.len() > MAX_LOG_LINES {
+        return;
+    }
+    if logs.len() == MAX_LOG_LINES {
+        line = format!("... console output truncated after {} lines", MAX_LOG_LINES);
+    }
+    if let Some(sender) = sender {
+        let _ = sender.send(line.clone());
+    }
+    logs.push(line);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,7 +559,7 @@ mod tests {
         let (_rt, ctx) = create_test_context().await;
         let result = run_js_safely(&ctx, "return 42;".to_string(), None).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), serde_json::json!(42));
+        assert_eq!(result.unwrap().value, serde_json::json!(42));
     }
 
     #[tokio::test]
@@ -191,7 +572,40 @@ mod tests {
             Some(inputs),
         ).await;
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), serde_json::json!(15));
+        assert_eq!(result.unwrap().value, serde_json::json!(15));
+    }
+
+    #[tokio::test]
+    async fn test_inputs_are_data_not_code() {
+        let (_rt, ctx) = create_test_context().await;
+        // Would close the IIFE call if it were spliced into the source
+        let inputs = serde_json::json!({"s": "}); throw new Error('escaped'); ({"});
+        let result = run_js_safely(&ctx, "return INPUT.s.length;".to_string(), Some(inputs)).await;
+        assert_eq!(result.unwrap().value, serde_json::json!(34));
+    }
+
+    #[tokio::test]
+    async fn test_code_cannot_escape_wrapper() {
+        let (_rt, ctx) = create_test_context().await;
+        // Spliced into `(function(INPUT) { ... })(...)` this would run at top level
+        let code = "return 1; })({}); globalThis.leaked = true; (function() {";
+        let result = run_js_safely(&ctx, code.to_string(), None).await;
+        assert_eq!(result.unwrap_err().kind, JsErrorKind::User);
+
+        let leaked = run_js_safely(&ctx, "return globalThis.leaked === true;".to_string(), None).await;
+        assert_eq!(leaked.unwrap().value, serde_json::json!(false));
+
+        // A trailing line comment doesn't swallow the wrapper's closing brace
+        let commented = run_js_safely(&ctx, "return '}' // done".to_string(), None).await;
+        assert_eq!(commented.unwrap().value, serde_json::json!("}"));
+    }
+
+    #[test]
+    fn test_oversized_input() {
+        let inputs = serde_json::json!({"blob": "x".repeat(100)});
+        assert_eq!(oversized_input(&inputs, 50), Some(111));
+        assert_eq!(oversized_input(&inputs, 200), None);
+        assert_eq!(oversized_input(&inputs, 0), None);
     }
 
     #[tokio::test]
@@ -201,6 +615,7 @@ mod tests {
             timeout_ms: 100, // 100ms timeout
             memory_limit: DEFAULT_MEMORY_LIMIT,
             instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+            channel_margin_ms: DEFAULT_CHANNEL_MARGIN_MS,
         };
         
         // This would run forever without timeout
@@ -212,7 +627,9 @@ mod tests {
         ).await;
         
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("timeout"));
+        let err = result.unwrap_err();
+        assert!(err.message.contains("timeout"));
+        assert_eq!(err.status_code(), 400, "infinite loops are not retried");
     }
 
     #[tokio::test]
@@ -220,7 +637,9 @@ mod tests {
         let (_rt, ctx) = create_test_context().await;
         let result = run_js_safely(&ctx, "return {{{".to_string(), None).await;
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("JS Error"));
+        let err = result.unwrap_err();
+        assert!(err.message.contains("JS Error"));
+        assert_eq!(err.kind, JsErrorKind::User);
     }
 
     #[tokio::test]
@@ -232,8 +651,249 @@ mod tests {
             None,
         ).await;
         assert!(result.is_ok());
-        let val = result.unwrap();
+        let val = result.unwrap().value;
         assert_eq!(val["message"], "hello");
         assert_eq!(val["count"], 42);
     }
+
+    #[tokio::test]
+    async fn test_route_to_from_code_node() {
+        use crate::types::{CodeNodeData, ExecutionResult, NodeType};
+
+        let (_rt, ctx) = create_test_context().await;
+        let result = run_js_safely(
+            &ctx,
+            r#"return { __route: "branchB", value: 1 };"#.to_string(),
+            None,
+        ).await;
+
+        let node = NodeType::Code(CodeNodeData { code: String::new(), inputs: None, timeout_ms: None });
+        let mut body = Some(result.unwrap().value);
+        let route_to = crate::nodes::extract_route_to(&node, &mut body);
+        assert_eq!(route_to.as_deref(), Some("branchB"));
+        // The routing key is stripped from the node's output
+        assert_eq!(body, Some(serde_json::json!({ "value": 1 })));
+
+        let receipt = ExecutionResult {
+            node_id: "code-1".to_string(),
+            run_id: None,
+            status_code: 200,
+            body,
+            timestamp: 0,
+            duration_ms: 0,
+            isolated: false,
+            route_to,
+            warnings: Vec::new(),
+        };
+        let json = serde_json::to_value(&receipt).unwrap();
+        assert_eq!(json["route_to"], "branchB");
+
+        // The NODE_COMPLETED event the orchestrator routes from carries the
+        // handle beside the result, not inside it
+        let event = crate::events::completed_payload(receipt.body.as_ref(), 0, receipt.route_to.as_deref(), &[]);
+        assert_eq!(event["route_to"], "branchB");
+        assert_eq!(event["result"], serde_json::json!({ "value": 1 }));
+    }
+
+    #[tokio::test]
+    async fn test_no_route_to_by_default() {
+        use crate::types::{CodeNodeData, NodeType};
+
+        let (_rt, ctx) = create_test_context().await;
+        let result = run_js_safely(&ctx, "return { value: 1 };".to_string(), None).await;
+
+        let node = NodeType::Code(CodeNodeData { code: String::new(), inputs: None, timeout_ms: None });
+        let mut body = Some(result.unwrap().value);
+        assert_eq!(crate::nodes::extract_route_to(&node, &mut body), None);
+    }
+
+    #[test]
+    fn test_channel_timeout_follows_task_timeout() {
+        let config = SandboxConfig::for_task(Some(60_000));
+        assert_eq!(config.timeout_ms, 60_000);
+        // A long JS timeout must not be cut short by the channel wait
+        assert!(config.channel_timeout() > Duration::from_millis(60_000));
+
+        let default = SandboxConfig::for_task(None);
+        assert!(default.channel_timeout() > Duration::from_millis(default.timeout_ms));
+    }
+
+    #[tokio::test]
+    async fn test_huge_timeout_is_capped_and_cannot_overflow() {
+        let config = SandboxConfig::for_task(Some(u64::MAX));
+        assert_eq!(config.timeout_ms, max_timeout_ms());
+        assert!(deadline_after(Duration::MAX) > Instant::now());
+
+        // Even unclamped, an overflowing deadline must not panic the JS thread
+        let (_rt, ctx) = create_test_context().await;
+        let config = SandboxConfig { timeout_ms: u64::MAX, ..SandboxConfig::default() };
+        let result = run_js_with_config(&ctx, "return 1;".to_string(), None, config).await;
+        assert_eq!(result.unwrap().value, serde_json::json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_long_task_not_cut_off_by_channel() {
+        let (_rt, ctx) = create_test_context().await;
+        let config = SandboxConfig {
+            timeout_ms: 2000,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+            channel_margin_ms: 0,
+        };
+        let channel_timeout = config.channel_timeout();
+
+        // Busy-wait ~300ms: well within the JS timeout, so the channel must wait for it
+        let code = "const end = Date.now() + 300; while (Date.now() < end) {} return 'done';";
+        let result = tokio::time::timeout(
+            channel_timeout,
+            run_js_with_config(&ctx, code.to_string(), None, config),
+        )
+        .await;
+
+        assert_eq!(result.unwrap().unwrap().value, serde_json::json!("done"));
+    }
+
+    #[tokio::test]
+    async fn test_per_task_timeout_sets_interrupt_deadline() {
+        let (_rt, ctx) = create_test_context().await;
+        let config = SandboxConfig::for_task(Some(150));
+        assert_eq!(config.timeout_ms, 150);
+
+        let start = Instant::now();
+        let err = run_js_with_config(&ctx, "while(true) {}".to_string(), None, config)
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("timeout"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(2), "stopped at the node's deadline, not the default");
+    }
+
+    #[tokio::test]
+    async fn test_returned_promise_is_awaited() {
+        let (_rt, ctx) = create_test_context().await;
+        let code = "const double = async (x) => x * 2; return await double(INPUT.n) + 1;";
+        let result = run_js_safely(&ctx, code.to_string(), Some(serde_json::json!({"n": 20}))).await;
+        assert_eq!(result.unwrap().value, serde_json::json!(41));
+
+        let chained = "return Promise.resolve({ ok: true }).then((r) => ({ ...r, step: 2 }));";
+        let result = run_js_safely(&ctx, chained.to_string(), None).await;
+        assert_eq!(result.unwrap().value, serde_json::json!({"ok": true, "step": 2}));
+
+        let rejected = run_js_safely(&ctx, "await null; throw new Error('nope');".to_string(), None).await;
+        let err = rejected.unwrap_err();
+        assert_eq!(err.kind, JsErrorKind::User);
+        assert!(err.message.contains("nope"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_unsettled_promises_fail_instead_of_hanging() {
+        let (_rt, ctx) = create_test_context().await;
+        let err = run_js_safely(&ctx, "return new Promise(() => {});".to_string(), None).await.unwrap_err();
+        assert!(err.message.contains("never settled"), "{}", err);
+
+        // Endless job chain: stopped at the task's deadline
+        let start = Instant::now();
+        let code = "const spin = () => Promise.resolve().then(spin); spin(); return new Promise(() => {});";
+        let err = run_js_with_config(&ctx, code.to_string(), None, SandboxConfig::for_task(Some(150)))
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("timeout"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // The abandoned chain doesn't eat into the next task's time
+        let start = Instant::now();
+        let next = run_js_safely(&ctx, "return await 'fresh';".to_string(), None).await;
+        assert_eq!(next.unwrap().value, serde_json::json!("fresh"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_flag_interrupts_busy_loop() {
+        let (_rt, ctx) = create_test_context().await;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = cancelled.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        let start = Instant::now();
+        let config = SandboxConfig::for_task(Some(10_000));
+        let err = run_js_with_logs(&ctx, "while(true) {}".to_string(), None, config, None, Some(cancelled))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, JsErrorKind::Cancelled);
+        assert_eq!(err.status_code(), 499);
+        assert!(start.elapsed() < Duration::from_secs(2), "aborted on cancel, not at the timeout");
+    }
+
+    #[tokio::test]
+    async fn test_out_of_memory_is_retryable() {
+        let rt = AsyncRuntime::new().unwrap();
+        rt.set_memory_limit(2 * 1024 * 1024).await;
+        let ctx = AsyncContext::full(&rt).await.unwrap();
+
+        let code = "const a = []; while (true) { a.push('x'.repeat(1024)); }";
+        let err = run_js_safely(&ctx, code.to_string(), None).await.unwrap_err();
+        assert_eq!(err.kind, JsErrorKind::Runtime, "{}", err);
+        assert_eq!(err.status_code(), 500);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_classified_by_exception_type() {
+        let rt = AsyncRuntime::new().unwrap();
+        rt.set_max_stack_size(256 * 1024).await;
+        let ctx = AsyncContext::full(&rt).await.unwrap();
+
+        // Messages that merely mention a limit are still the user's error
+        for code in ["throw new Error('out of memory');", "throw new Error('stack trace was interrupted');"] {
+            let err = run_js_safely(&ctx, code.to_string(), None).await.unwrap_err();
+            assert_eq!(err.kind, JsErrorKind::User, "{}", err);
+        }
+
+        // A stack overflow is a RangeError like the code's own, not a runtime failure
+        let err = run_js_safely(&ctx, "const f = () => f() + 1; return f();".to_string(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, JsErrorKind::User, "{}", err);
+        assert!(err.message.contains("Maximum call stack size exceeded"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_console_output_is_captured_and_streamed() {
+        let (_rt, ctx) = create_test_context().await;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let code = r#"
+            console.log("starting", 1, { a: [1, 2] });
+            console.warn("careful");
+            console.error(new Error("boom").message);
+            return "ok";
+        "#;
+
+        let out = run_js_with_logs(&ctx, code.to_string(), None, SandboxConfig::default(), Some(tx), None)
+            .await
+            .unwrap();
+        assert_eq!(out.value, serde_json::json!("ok"));
+        assert_eq!(out.logs, vec![r#"starting 1 {"a":[1,2]}"#, "[warn] careful", "[error] boom"]);
+
+        let mut streamed = Vec::new();
+        while let Ok(line) = rx.try_recv() {
+            streamed.push(line);
+        }
+        assert_eq!(streamed, out.logs);
+
+        // Logs from before a failure are kept, and the next task starts clean
+        let err = run_js_safely(&ctx, "console.log('before'); throw new Error('x');".to_string(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.logs, vec!["before"]);
+    }
+
+    #[tokio::test]
+    async fn test_console_output_is_bounded() {
+        let (_rt, ctx) = create_test_context().await;
+        let code = "for (let i = 0; i < 5000; i++) console.log(i); console.log('x'.repeat(50000)); return 1;";
+        let out = run_js_safely(&ctx, code.to_string(), None).await.unwrap();
+        assert_eq!(out.logs.len(), MAX_LOG_LINES + 1);
+        assert!(out.logs.last().unwrap().contains("truncated"));
+    }
 }
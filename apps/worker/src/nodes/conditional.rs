@@ -0,0 +1,88 @@
+//! Conditional node execution.
+//!
+//! A boolean gate: one JS expression over `input`, evaluated in the sandbox.
+//! The node completes with `{ matched, route_to }`, where `route_to` is the
+//! handle the orchestrator follows ("true" or "false"); the other handle's
+//! branch never runs. An expression that throws fails the node (400), since
+//! silently taking the "false" branch would hide the bug.
+
+use crate::node_error::{NodeError, NodeResult};
+use crate::nodes::code::SandboxConfig;
+use crate::nodes::JsTask;
+use crate::types::ConditionalNodeData;
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+
+/// Evaluates `INPUT.expression` against `INPUT.input`. The expression
+/// arrives as data, never spliced into the script.
+const EXPRESSION_JS: &str = r#"
+const expression = new Function('input', 'return (' + INPUT.expression + ');');
+return !!expression(INPUT.input);
+"#;
+
+/// Execute a conditional node.
+pub async fn execute(data: ConditionalNodeData, js_sender: &mpsc::Sender<JsTask>) -> NodeResult {
+    if data.expression.trim().is_empty() {
+        return Err(NodeError::permanent(400, "Conditional expression is empty"));
+    }
+    let input = data.input.unwrap_or(Value::Null);
+
+    let (tx, rx) = oneshot::channel();
+    let task = JsTask {
+        code: EXPRESSION_JS.to_string(),
+        inputs: Some(json!({ "expression": data.expression, "input": input })),
+        responder: tx,
+        timeout_ms: None,
+        log_sender: None,
+        cancelled: None,
+    };
+    if js_sender.send(task).await.is_err() {
+        return Err(NodeError::permanent(500, "JS Engine crashed"));
+    }
+
+    let channel_timeout = SandboxConfig::default().channel_timeout();
+    match tokio::time::timeout(channel_timeout, rx).await {
+        Ok(Ok(Ok(out))) => {
+            let matched = out.value == Value::Bool(true);
+            tracing::debug!(matched, "Conditional evaluated");
+            Ok((200, Some(result_body(matched))))
+        }
+        Ok(Ok(Err(e))) => Err(NodeError::permanent(e.status_code(), format!("Conditional expression failed: {}", e))),
+        Ok(Err(_)) => Err(NodeError::permanent(500, "JS runtime crashed during conditional; it has been restarted")),
+        Err(_) => Err(NodeError::permanent(
+            500,
+            format!("Conditional timeout ({}ms)", channel_timeout.as_millis()),
+        )),
+    }
+}
+
+fn result_body(matched: bool) -> Value {
+    json!({ "matched": matched, "route_to": if matched { "true" } else { "false" } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_body_routes_by_outcome() {
+        assert_eq!(result_body(true), json!({ "matched": true, "route_to": "true" }));
+        assert_eq!(result_body(false), json!({ "matched": false, "route_to": "false" }));
+    }
+
+    #[tokio::test]
+    async fn test_expression_js_reads_input() {
+        let runtime = rquickjs::AsyncRuntime::new().unwrap();
+        let ctx = rquickjs::AsyncContext::full(&runtime).await.unwrap();
+        let run = |expression: &str, input: Value| {
+            let inputs = json!({ "expression": expression, "input": input });
+            crate::nodes::code::run_js_safely(&ctx, EXPRESSION_JS.to_string(), Some(inputs))
+        };
+
+        assert_eq!(run("input.status === 200", json!({"status": 200})).await.unwrap().value, true);
+        assert_eq!(run("input.items.length > 0", json!({"items": []})).await.unwrap().value, false);
+        // Truthiness, not identity: the gate always yields a boolean
+        assert_eq!(run("input", json!("non-empty")).await.unwrap().value, true);
+        assert!(run("input.missing.field", json!({})).await.is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Database batch insert/upsert node execution.
+//!
+//! Writes many rows in a single statement using the worker's pool instead of
+//! one HTTP/code node per row. Rows are bound as a single JSONB parameter and
+//! expanded with `jsonb_populate_recordset`, so Postgres converts each value to
+//! the column's type and no row data is ever interpolated into SQL.
+//!
+//! With `conflict_columns`, rows repeating a conflict key are collapsed first
+//! (the last one wins; Postgres refuses to update a row twice in one
+//! statement). Rows are then written in one statement per distinct set of
+//! keys, inside a transaction, so a row only inserts and updates the columns
+//! it has: a missing key keeps the column's default or existing value rather
+//! than writing NULL.
+//!
+//! Only tables listed in `DB_UPSERT_TABLES` can be written. Entries are either
+//! `table` (any column) or `table:col_a|col_b` (only those columns), separated
+//! by commas, e.g. `DB_UPSERT_TABLES="orders:id|status|total,audit_log"`.
+
+use crate::node_error::{NodeError, NodeResult};
+use crate::types::DbUpsertNodeData;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+
+/// Maximum rows per upsert (keeps statements and JSONB params bounded)
+const MAX_ROWS: usize = 10_000;
+
+/// Writable tables and (optionally) the columns allowed for each.
+#[derive(Debug, Default)]
+pub struct TableAllowlist {
+    tables: HashMap<String, Option<HashSet<String>>>,
+}
+
+impl TableAllowlist {
+    /// Load from `DB_UPSERT_TABLES` (empty = nothing writable).
+    pub fn from_env() -> Self {
+        Self::parse(&std::env::var("DB_UPSERT_TABLES").unwrap_or_default())
+    }
+
+    /// Parse `table[:col|col],...`.
+    pub fn parse(spec: &str) -> Self {
+        let tables = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((table, cols)) => (
+                    table.trim().to_string(),
+                    Some(cols.split('|').map(|c| c.trim().to_string()).collect()),
+                ),
+                None => (entry.to_string(), None),
+            })
+            .collect();
+        Self { tables }
+    }
+
+    fn check(&self, table: &str, columns: &[String]) -> Result<(), String> {
+        let Some(allowed) = self.tables.get(table) else {
+            return Err(format!("Table '{}' is not in the upsert allowlist", table));
+        };
+        if let Some(allowed) = allowed
+            && let Some(col) = columns.iter().find(|c| !allowed.contains(*c))
+        {
+            return Err(format!("Column '{}' is not writable on table '{}'", col, table));
+        }
+        Ok(())
+    }
+}
+
+/// Execute a batch upsert.
+/// Connection-level database failures are transient; the rest fail the node.
+pub async fn execute(pool: &PgPool, data: DbUpsertNodeData) -> NodeResult {
+    let allowlist = TableAllowlist::from_env();
+
+    let statements = prepare(&data, &allowlist).map_err(|e| NodeError::permanent(400, e))?;
+    let rows: usize = statements.iter().map(|s| s.rows.len()).sum();
+    let duplicates = data.rows.len() - rows;
+    if rows == 0 {
+        return Ok((200, Some(serde_json::json!({ "rows": 0, "rows_affected": 0 }))));
+    }
+
+    tracing::info!("DB upsert: {} row(s) into {} ({} statement(s))", rows, data.table, statements.len());
+
+    let upsert_error = |e: sqlx::Error| NodeError::from_sqlx("Upsert failed", &e);
+    let mut tx = pool.begin().await.map_err(upsert_error)?;
+    let mut rows_affected = 0;
+    for statement in statements {
+        let result = sqlx::query(&statement.sql)
+            .bind(serde_json::Value::Array(statement.rows))
+            .execute(&mut *tx)
+            .await
+            .map_err(upsert_error)?;
+        rows_affected += result.rows_affected();
+    }
+    tx.commit().await.map_err(upsert_error)?;
+
+    Ok((
+        200,
+        Some(serde_json::json!({
+            "rows": rows,
+            "rows_affected": rows_affected,
+            "duplicates": duplicates
+        })),
+    ))
+}
+
+/// One statement and the rows bound to it (all with the same keys).
+#[derive(Debug, PartialEq)]
+struct Statement {
+    sql: String,
+    rows: Vec<serde_json::Value>,
+}
+
+/// Validate the node data and build its statements: rows collapsed by
+/// conflict key, then grouped by their set of keys (in first-seen order).
+fn prepare(data: &DbUpsertNodeData, allowlist: &TableAllowlist) -> Result<Vec<Statement>, String> {
+    validate_identifier(&data.table)?;
+
+    if data.rows.len() > MAX_ROWS {
+        return Err(format!("Too many rows ({}, max {})", data.rows.len(), MAX_ROWS));
+    }
+
+    let mut all_columns: Vec<String> = Vec::new();
+    for row in &data.rows {
+        let obj = row.as_object().ok_or("Each row must be a JSON object")?;
+        for key in obj.keys() {
+            if !all_columns.contains(key) {
+                all_columns.push(key.clone());
+            }
+        }
+        if let Some(col) = data.conflict_columns.iter().find(|c| !obj.contains_key(*c)) {
+            return Err(format!("Conflict column '{}' is missing from a row", col));
+        }
+    }
+
+    for col in all_columns.iter().chain(&data.conflict_columns) {
+        validate_identifier(col)?;
+    }
+    allowlist.check(&data.table, &all_columns)?;
+
+    let mut groups: Vec<(Vec<String>, Vec<serde_json::Value>)> = Vec::new();
+    for row in dedupe_by_conflict_key(&data.rows, &data.conflict_columns) {
+        let columns: Vec<String> = row.as_object().map(|o| o.keys().cloned().collect()).unwrap_or_default();
+        if columns.is_empty() {
+            continue;
+        }
+        match groups.iter_mut().find(|(cols, _)| *cols == columns) {
+            Some((_, rows)) => rows.push(row),
+            None => groups.push((columns, vec![row])),
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(columns, rows)| Statement { sql: build_upsert_sql(&data.table, &columns, &data.conflict_columns), rows })
+        .collect())
+}
+
+/// Collapse rows with the same conflict key values: the last row wins, at the
+/// position the key was first seen. Without conflict columns rows are kept as is.
+fn dedupe_by_conflict_key(rows: &[serde_json::Value], conflict_columns: &[String]) -> Vec<serde_json::Value> {
+    if conflict_columns.is_empty() {
+        return rows.to_vec();
+    }
+    let mut position: HashMap<String, usize> = HashMap::new();
+    let mut deduped: Vec<serde_json::Value> = Vec::with_capacity(rows.len());
+    for row in rows {
+        let key = serde_json::Value::Array(conflict_columns.iter().map(|c| row[c.as_str()].clone()).collect()).to_string();
+        match position.get(&key) {
+            Some(&i) => deduped[i] = row.clone(),
+            None => {
+                position.insert(key, deduped.len());
+                deduped.push(row.clone());
+            }
+        }
+    }
+    deduped
+}
+
+/// Build `INSERT ... SELECT FROM jsonb_populate_recordset($1) [ON CONFLICT ...]`.
+/// Identifiers must already be validated.
+fn build_upsert_sql(table: &str, columns: &[String], conflict_columns: &[String]) -> String {
+    let table = quote_identifier(table);
+    let column_list = columns.iter().map(|c| quote_identifier(c)).collect::<Vec<_>>().join(", ");
+
+    let mut sql = format!(
+        "INSERT INTO {table} ({column_list}) SELECT {column_list} FROM jsonb_populate_recordset(NULL::{table}, $1)"
+    );
+
+    if !conflict_columns.is_empty() {
+        let conflict_list = conflict_columns.iter().map(|c| quote_identifier(c)).collect::<Vec<_>>().join(", ");
+        let updates: Vec<String> = columns
+            .iter()
+            .filter(|c| !conflict_columns.contains(c))
+            .map(|c| format!("{0} = EXCLUDED.{0}", quote_identifier(c)))
+            .collect();
+
+        if updates.is_empty() {
+            sql.push_str(&format!(" ON CONFLICT ({conflict_list}) DO NOTHING"));
+        } else {
+            sql.push_str(&format!(" ON CONFLICT ({conflict_list}) DO UPDATE SET {}", updates.join(", ")));
+        }
+    }
+
+    sql
+}
+
+/// Plain identifiers only (`schema.table` allowed): letters, digits, underscores.
+fn validate_identifier(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.split('.').count() <= 2
+        && name.split('.').all(|part| {
+            part.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+
+    if valid { Ok(()) } else { Err(format!("Invalid identifier '{}'", name)) }
+}
+
+fn quote_identifier(name: &str) -> String {
+    name.split('.').map(|part| format!("\"{}\"", part)).collect::<Vec<_>>().join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(table: &str, rows: serde_json::Value, conflict: &[&str]) -> DbUpsertNodeData {
+        DbUpsertNodeData {
+            table: table.to_string(),
+            rows: serde_json::from_value(rows).unwrap(),
+            conflict_columns: conflict.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_multi_row_upsert() {
+        let allowlist = TableAllowlist::parse("orders:id|status|total");
+        let data = data(
+            "orders",
+            serde_json::json!([
+                {"id": 1, "status": "paid", "total": 10.5},
+                {"id": 2, "status": "open"}
+            ]),
+            &["id"],
+        );
+
+        let statements = prepare(&data, &allowlist).unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(
+            statements[0].sql,
+            "INSERT INTO \"orders\" (\"id\", \"status\", \"total\") \
+             SELECT \"id\", \"status\", \"total\" FROM jsonb_populate_recordset(NULL::\"orders\", $1) \
+             ON CONFLICT (\"id\") DO UPDATE SET \"status\" = EXCLUDED.\"status\", \"total\" = EXCLUDED.\"total\""
+        );
+        // The row without a total doesn't overwrite the stored one with NULL
+        assert_eq!(
+            statements[1].sql,
+            "INSERT INTO \"orders\" (\"id\", \"status\") \
+             SELECT \"id\", \"status\" FROM jsonb_populate_recordset(NULL::\"orders\", $1) \
+             ON CONFLICT (\"id\") DO UPDATE SET \"status\" = EXCLUDED.\"status\""
+        );
+        assert_eq!(statements[1].rows, vec![serde_json::json!({"id": 2, "status": "open"})]);
+    }
+
+    #[test]
+    fn test_rows_with_the_same_conflict_key_collapse_to_the_last() {
+        let allowlist = TableAllowlist::parse("orders");
+        let data = data(
+            "orders",
+            serde_json::json!([
+                {"id": 1, "status": "open"},
+                {"id": 2, "status": "open"},
+                {"id": 1, "status": "paid"}
+            ]),
+            &["id"],
+        );
+
+        let statements = prepare(&data, &allowlist).unwrap();
+        assert_eq!(statements.len(), 1);
+        assert_eq!(
+            statements[0].rows,
+            vec![serde_json::json!({"id": 1, "status": "paid"}), serde_json::json!({"id": 2, "status": "open"})]
+        );
+
+        // Plain inserts keep every row
+        let plain = self::data("orders", serde_json::json!([{"id": 1}, {"id": 1}]), &[]);
+        assert_eq!(prepare(&plain, &allowlist).unwrap()[0].rows.len(), 2);
+
+        // Every row needs the conflict key
+        let missing = self::data("orders", serde_json::json!([{"id": 1}, {"status": "x"}]), &["id"]);
+        assert!(prepare(&missing, &allowlist).unwrap_err().contains("missing"));
+    }
+
+    #[test]
+    fn test_rejects_non_allowlisted_table_and_column() {
+        let allowlist = TableAllowlist::parse("orders:id|status");
+
+        let err = prepare(&data("users", serde_json::json!([{"id": 1}]), &[]), &allowlist).unwrap_err();
+        assert!(err.contains("not in the upsert allowlist"));
+
+        let err = prepare(&data("orders", serde_json::json!([{"id": 1, "admin": true}]), &[]), &allowlist)
+            .unwrap_err();
+        assert!(err.contains("not writable"));
+    }
+
+    #[test]
+    fn test_rejects_injection_in_identifiers() {
+        let allowlist = TableAllowlist::parse("orders");
+        let rows = serde_json::json!([{"id\" text); DROP TABLE orders; --": 1}]);
+        assert!(prepare(&data("orders", rows, &[]), &allowlist).is_err());
+        assert!(validate_identifier("orders; DROP").is_err());
+        assert!(validate_identifier("public.orders").is_ok());
+    }
+}
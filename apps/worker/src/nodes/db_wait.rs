@@ -0,0 +1,260 @@
+//! DbWait node execution.
+//!
+//! Suspends a node until a Postgres condition holds. The query is checked once
+//! when the node runs; if it isn't satisfied yet, a `db_wait` suspension is
+//! created and the scheduler re-checks it every `poll_interval_ms` until the
+//! query returns a truthy row (the node resumes with that row) or the
+//! suspension's `expires_at` passes (the node fails like any expired wait).
+//!
+//! Only a single SELECT (or WITH ... SELECT) is accepted, and it always runs
+//! inside a READ ONLY transaction with a statement timeout. Parameters are bound
+//! by JSON type (text, bigint, float8, boolean, jsonb); cast in the query where
+//! the column type differs, e.g. `WHERE id = $1::uuid`.
+
+use crate::events::{log_event, EventType};
+use crate::node_error::{NodeError, NodeResult};
+use crate::types::{DbWaitNodeData, DbWaitResumeData, JobPriority, ResumeVia, Suspension, SuspensionKind};
+use serde_json::{json, Value};
+use sqlx::postgres::{PgArguments, Postgres};
+use sqlx::query::QueryScalar;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Upper bound for a single condition check
+const STATEMENT_TIMEOUT_MS: u64 = 5_000;
+
+/// Accept a single SELECT/WITH statement. Returns it without a trailing `;`.
+pub fn validate_query(query: &str) -> Result<&str, String> {
+    let query = query.trim();
+    let query = query.strip_suffix(';').unwrap_or(query).trim_end();
+
+    if query.contains(';') {
+        return Err("DbWait query must be a single statement".to_string());
+    }
+    let first_word = query.split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+    if first_word != "select" && first_word != "with" {
+        return Err("DbWait query must be a SELECT".to_string());
+    }
+    Ok(query)
+}
+
+/// A row satisfies the wait if it exists and, for a single-column result,
+/// that column isn't NULL, false, 0 or "".
+pub fn is_satisfied(row: &Value) -> bool {
+    let Some(obj) = row.as_object() else {
+        return false;
+    };
+    if obj.len() != 1 {
+        return true;
+    }
+    match obj.values().next() {
+        Some(Value::Null) | Some(Value::Bool(false)) | None => false,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(_) => true,
+    }
+}
+
+/// Run the condition query and return its first row if it satisfies the wait.
+pub async fn evaluate(pool: &PgPool, query: &str, params: &[Value]) -> Result<Option<Value>, sqlx::Error> {
+    // Validated again here: the scheduler evaluates stored contexts too
+    let query = validate_query(query).map_err(sqlx::Error::Protocol)?;
+    let sql = format!("SELECT to_jsonb(db_wait_row) FROM ({}) AS db_wait_row LIMIT 1", query);
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION READ ONLY").execute(&mut *tx).await?;
+    sqlx::query(&format!("SET LOCAL statement_timeout = {}", STATEMENT_TIMEOUT_MS))
+        .execute(&mut *tx)
+        .await?;
+    let row: Option<Value> = bind_params(sqlx::query_scalar(&sql), params).fetch_optional(&mut *tx).await?;
+    tx.rollback().await?;
+
+    Ok(row.filter(is_satisfied))
+}
+
+fn bind_params<'q>(
+    mut query: QueryScalar<'q, Postgres, Value, PgArguments>,
+    params: &'q [Value],
+) -> QueryScalar<'q, Postgres, Value, PgArguments> {
+    for param in params {
+        query = match param {
+            Value::Null => query.bind(None::<String>),
+            Value::Bool(b) => query.bind(*b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => query.bind(i),
+                None => query.bind(n.as_f64()),
+            },
+            Value::String(s) => query.bind(s.as_str()),
+            other => query.bind(other),
+        };
+    }
+    query
+}
+
+/// Execute a DbWait node: complete now if the condition already holds,
+/// otherwise suspend for the scheduler to poll.
+pub async fn execute_wait(
+    data: DbWaitNodeData,
+    job_id: &str,
+    run_id: Option<&Uuid>,
+    priority: JobPriority,
+    db_pool: &PgPool,
+) -> NodeResult {
+    validate_query(&data.query).map_err(|e| NodeError::permanent(400, e))?;
+    let Some(rid) = run_id else {
+        return Err(NodeError::permanent(400, "DbWait node requires a run_id"));
+    };
+
+    if let Some(row) = evaluate(db_pool, &data.query, &data.params)
+        .await
+        .map_err(|e| NodeError::from_sqlx("DbWait query failed", &e))?
+    {
+        tracing::info!("DbWait condition already satisfied");
+        return Ok((200, Some(json!({ "resumed": false, "row": row }))));
+    }
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::milliseconds(data.timeout_ms as i64);
+    let next_check = chrono::Utc::now() + chrono::Duration::milliseconds(data.poll_interval_ms as i64);
+
+    tracing::info!(
+        "Suspending until DbWait condition holds (every {}ms, expires: {})",
+        data.poll_interval_ms,
+        expires_at.format("%Y-%m-%d %H:%M")
+    );
+
+    let suspension = Suspension::new(
+        SuspensionKind::DbWait,
+        ResumeVia::Scheduler,
+        Some(expires_at),
+        json!({ "description": data.description, "poll_interval_ms": data.poll_interval_ms }),
+    )
+    .to_value();
+
+    let _ = log_event(
+        db_pool,
+        rid,
+        job_id,
+        EventType::NodeSuspended,
+        json!({
+            "type": "db_wait",
+            "description": data.description,
+            "poll_interval_ms": data.poll_interval_ms,
+            "expires_at": expires_at.to_rfc3339(),
+            "suspension": suspension,
+        }),
+    )
+    .await;
+
+    sqlx::query(
+        r#"
+        INSERT INTO suspensions (run_id, node_id, suspension_type, resume_after, execution_context, expires_at)
+        VALUES ($1, $2, 'db_wait', $3, $4, $5)
+        "#,
+    )
+    .bind(rid)
+    .bind(job_id)
+    .bind(next_check)
+    .bind(suspension_context(&data, priority))
+    .bind(expires_at)
+    .execute(db_pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to create DbWait suspension", &e))?;
+
+    Err(NodeError::Suspended(json!({
+        "suspended": true,
+        "poll_interval_ms": data.poll_interval_ms,
+        "expires_at": expires_at.to_rfc3339(),
+        "description": data.description,
+        "suspension": suspension,
+    })))
+}
+
+/// Execute a DbWait resume (pushed by the scheduler once the condition holds).
+pub async fn execute_resume(data: DbWaitResumeData, job_id: &str, run_id: Option<&Uuid>, db_pool: &PgPool) -> NodeResult {
+    tracing::info!("DbWait condition satisfied, resuming");
+
+    if let Some(rid) = run_id {
+        let _ = log_event(
+            db_pool,
+            rid,
+            job_id,
+            EventType::NodeResumed,
+            json!({ "source": "db_wait", "row": data.row }),
+        )
+        .await;
+    }
+
+    Ok((200, Some(json!({ "resumed": true, "row": data.row }))))
+}
+
+/// What the suspension records: the node's settings, plus the job's priority so
+/// the resume goes back on the same stream.
+fn suspension_context(data: &DbWaitNodeData, priority: JobPriority) -> Value {
+    let mut context = serde_json::to_value(data).unwrap_or_default();
+    if let Some(fields) = context.as_object_mut() {
+        fields.insert("priority".to_string(), json!(priority));
+    }
+    context
+}
+
+/// Job that resumes a satisfied DbWait suspension.
+pub fn resume_job(suspension_id: Uuid, node_id: &str, run_id: Uuid, priority: JobPriority, row: Value) -> Value {
+    json!({
+        "id": node_id,
+        "run_id": run_id.to_string(),
+        "node": {
+            "type": "DBWAITRESUME",
+            "data": { "row": row }
+        },
+        "retry_count": 0,
+        "max_retries": 0,
+        "priority": priority,
+        "idempotency_key": format!("db_wait:{}", suspension_id)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WorkerJob;
+
+    #[test]
+    fn test_only_single_selects_are_accepted() {
+        assert_eq!(validate_query("  SELECT paid FROM invoices WHERE id = $1; "), Ok("SELECT paid FROM invoices WHERE id = $1"));
+        assert!(validate_query("with t as (select 1) select * from t").is_ok());
+        assert!(validate_query("DELETE FROM invoices").is_err());
+        assert!(validate_query("SELECT 1; DROP TABLE invoices").is_err());
+        assert!(validate_query("").is_err());
+    }
+
+    #[test]
+    fn test_row_truthiness() {
+        assert!(is_satisfied(&json!({ "paid": true })));
+        assert!(is_satisfied(&json!({ "count": 3 })));
+        assert!(!is_satisfied(&json!({ "paid": false })));
+        assert!(!is_satisfied(&json!({ "paid": null })));
+        assert!(!is_satisfied(&json!({ "count": 0 })));
+        // Multi-column rows only need to exist
+        assert!(is_satisfied(&json!({ "id": 1, "paid": false })));
+
+        let job: WorkerJob = serde_json::from_value(resume_job(
+            Uuid::nil(),
+            "wait-1",
+            Uuid::nil(),
+            JobPriority::High,
+            json!({ "paid": true }),
+        ))
+        .unwrap();
+        assert!(matches!(job.node, crate::types::NodeType::DbWaitResume(_)));
+        assert_eq!(job.priority, JobPriority::High);
+    }
+
+    #[test]
+    fn test_suspension_context_keeps_settings_and_priority() {
+        let data: DbWaitNodeData = serde_json::from_value(json!({ "query": "SELECT paid FROM invoices" })).unwrap();
+        let context = suspension_context(&data, JobPriority::Low);
+        assert_eq!(context["priority"], "low");
+        let restored: DbWaitNodeData = serde_json::from_value(context).unwrap();
+        assert_eq!(restored.query, data.query);
+    }
+}
@@ -2,9 +2,23 @@
 //!
 //! Handles both short delays (inline sleep) and long delays (scheduled via Redis).
 //! Includes cancellation support for inline delays.
+//!
+//! Every time a run enters a delay node its cycle counter is bumped. A graph
+//! that loops back into the same delay more than `DELAY_MAX_CYCLES` times is
+//! treated as an infinite loop and the node fails with 508 (not retried).
+//!
+//! With `until` the delay ends at a point in time instead: an absolute time,
+//! or the next occurrence of a cron expression in a timezone ("next business
+//! day at 9am"). It is turned into a duration when the node runs and then
+//! takes the same inline or scheduled path.
+//!
+//! Long delays are also indexed per run (a set of the run's ZSET members), so
+//! cancelling a run removes its pending resumes without scanning every delay.
 
-use crate::types::DelayNodeData;
-use redis::{AsyncCommands, RedisResult};
+use crate::types::{DelayNodeData, JobPriority, ResumeVia, Suspension, SuspensionKind, UntilSpec};
+use chrono::{DateTime, Utc};
+use redis::RedisResult;
+use std::future::Future;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio_util::sync::CancellationToken;
 
@@ -15,6 +29,106 @@ const SHORT_DELAY_THRESHOLD_MS: u64 = 60_000;
 /// Redis sorted set for delayed jobs
 const DELAYED_JOBS_KEY: &str = "swiftgrid_delayed";
 
+/// Redis key prefix for the per-run index of pending delayed jobs
+pub const RUN_INDEX_PREFIX: &str = "swiftgrid:delayed_by_run:";
+
+/// How long a run's index outlives its latest delay
+const RUN_INDEX_MARGIN_SECS: u64 = 24 * 60 * 60;
+
+/// Remove every indexed delayed job for a run and the index itself
+const CANCEL_SCRIPT: &str = r#"
+local members = redis.call('SMEMBERS', KEYS[2])
+local removed = 0
+for _, member in ipairs(members) do
+    removed = removed + redis.call('ZREM', KEYS[1], member)
+end
+redis.call('DEL', KEYS[2])
+return removed
+"#;
+
+/// Redis key prefix for per-run, per-node delay cycle counters
+const CYCLE_KEY_PREFIX: &str = "swiftgrid:delay_cycles:";
+
+/// Counters outlive any realistic run
+const CYCLE_KEY_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Default cycles one delay node may go through in a run
+const DEFAULT_MAX_CYCLES: u64 = 1000;
+
+/// Status for a suspected infinite loop (508 Loop Detected)
+pub const LOOP_DETECTED_STATUS: u16 = 508;
+
+/// Cycle limit from `DELAY_MAX_CYCLES` (0 = no limit)
+fn max_cycles() -> u64 {
+    std::env::var("DELAY_MAX_CYCLES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CYCLES)
+}
+
+/// Per-run delay cycle counters.
+pub trait CycleCounter {
+    /// Increment the counter for `node_id` in `run_id`, returning the new count.
+    fn increment(&self, run_id: &str, node_id: &str) -> impl Future<Output = Result<u64, String>> + Send;
+}
+
+impl CycleCounter for redis::Client {
+    async fn increment(&self, run_id: &str, node_id: &str) -> Result<u64, String> {
+        let mut con = self
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| e.to_string())?;
+        let (count,): (u64,) = increment_pipe(run_id, node_id)
+            .query_async(&mut con)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(count)
+    }
+}
+
+/// `INCRBY 1` the run's counter for the node and (re)arm its expiry, in one
+/// transaction; only the new count is returned.
+fn increment_pipe(run_id: &str, node_id: &str) -> redis::Pipeline {
+    let key = format!("{}{}:{}", CYCLE_KEY_PREFIX, run_id, node_id);
+    let mut pipe = redis::pipe();
+    pipe.atomic().incr(&key, 1).expire(&key, CYCLE_KEY_TTL_SECS).ignore();
+    pipe
+}
+
+/// Count a cycle of `node_id` and return the failure to report if the run has
+/// gone past `limit`. Counter errors are logged and never block the delay.
+pub async fn check_cycles(
+    counter: &impl CycleCounter,
+    run_id: &str,
+    node_id: &str,
+    limit: u64,
+) -> Option<(u16, serde_json::Value)> {
+    if limit == 0 {
+        return None;
+    }
+    let cycles = match counter.increment(run_id, node_id).await {
+        Ok(cycles) => cycles,
+        Err(e) => {
+            tracing::warn!("Delay: cycle counter unavailable: {}", e);
+            return None;
+        }
+    };
+    (cycles > limit).then(|| {
+        (
+            LOOP_DETECTED_STATUS,
+            serde_json::json!({
+                "error": format!(
+                    "Suspected infinite loop: delay node '{}' was entered {} times in this run (limit {})",
+                    node_id, cycles, limit
+                ),
+                "loop_detected": true,
+                "cycles": cycles,
+                "limit": limit
+            }),
+        )
+    })
+}
+
 /// Execute a delay node with cancellation support.
 /// Returns (status_code, body, was_cancelled).
 ///
@@ -24,20 +138,31 @@ pub async fn execute(
     data: DelayNodeData,
     job_id: &str,
     run_id: &Option<String>,
+    priority: JobPriority,
     redis_client: &redis::Client,
     cancel_token: &CancellationToken,
 ) -> (u16, Option<serde_json::Value>, bool) {
-    let delay_ms = data.duration_ms;
+    if let Some(run_id) = run_id
+        && let Some((status, body)) = check_cycles(redis_client, run_id, job_id, max_cycles()).await
+    {
+        tracing::error!("Delay: {}", body["error"].as_str().unwrap_or("loop detected"));
+        return (status, Some(body), false);
+    }
+
+    let delay_ms = match delay_ms(&data, Utc::now()) {
+        Ok(delay_ms) => delay_ms,
+        Err(e) => return (400, Some(serde_json::json!({ "error": e })), false),
+    };
 
     if delay_ms <= SHORT_DELAY_THRESHOLD_MS {
         // Short delay: sleep inline with cancellation support
-        println!("  -> Sleeping for {}ms", delay_ms);
+        tracing::info!("Sleeping for {}ms", delay_ms);
         
         tokio::select! {
             biased;
 
             _ = cancel_token.cancelled() => {
-                println!("  -> Delay cancelled");
+                tracing::info!("Delay cancelled");
                 return (
                     499,
                     Some(serde_json::json!({
@@ -63,8 +188,9 @@ pub async fn execute(
         )
     } else {
         // Long delay: schedule for later via Redis ZSET
-        // Note: Long delays are handled by the scheduler, cancellation happens
-        // when the resume job is picked up (it will check run status)
+        // Note: Long delays are handled by the scheduler; cancelling the run
+        // removes the resume job via the run index (and a resume that slips
+        // through still checks run status when it's picked up)
         let resume_at = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -72,26 +198,13 @@ pub async fn execute(
             + delay_ms;
 
         // Create a "resume" job that will be picked up by the scheduler
-        let resume_job = serde_json::json!({
-            "id": job_id,
-            "run_id": run_id,
-            "node": { "type": "DELAY_RESUME", "data": { "original_delay_ms": delay_ms } },
-            "retry_count": 0,
-            "max_retries": 0
-        });
-
-        if let Ok(mut con) = redis_client.get_multiplexed_async_connection().await {
-            let _: RedisResult<()> = con
-                .zadd(
-                    DELAYED_JOBS_KEY,
-                    serde_json::to_string(&resume_job).unwrap(),
-                    resume_at as f64,
-                )
-                .await;
+        let member = resume_job(job_id, run_id.as_deref(), priority, delay_ms).to_string();
+        if let Err(e) = schedule_job(redis_client, &member, run_id.as_deref(), delay_ms).await {
+            tracing::error!("Failed to schedule delay resume: {}", e);
         }
 
-        println!(
-            "  -> Scheduled delay for {}ms (resume at {})",
+        tracing::info!(
+            "Scheduled delay for {}ms (resume at {})",
             delay_ms, resume_at
         );
 
@@ -101,16 +214,105 @@ pub async fn execute(
             Some(serde_json::json!({
                 "scheduled": true,
                 "resume_at": resume_at,
-                "delayed_ms": delay_ms
+                "delayed_ms": delay_ms,
+                "suspension": Suspension::new(
+                    SuspensionKind::Delay,
+                    ResumeVia::Scheduler,
+                    chrono::DateTime::from_timestamp_millis(resume_at as i64),
+                    serde_json::json!({ "delayed_ms": delay_ms }),
+                ),
             })),
             false, // Not cancelled - just scheduled
         )
     }
 }
 
+/// How long the node waits from `now`: `duration_ms`, or the time left
+/// until `until` (0 if it has passed).
+fn delay_ms(data: &DelayNodeData, now: DateTime<Utc>) -> Result<u64, String> {
+    let target = match &data.until {
+        None => return Ok(data.duration_ms),
+        Some(until) => resolve_until(until, now)?,
+    };
+    Ok((target - now).num_milliseconds().max(0) as u64)
+}
+
+/// The instant an `until` spec points at. Cron expressions go through the
+/// scheduler's validation, so an unknown timezone is an error rather than UTC.
+fn resolve_until(until: &UntilSpec, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    match until {
+        UntilSpec::At { time } => DateTime::parse_from_rfc3339(time)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| format!("Invalid until time '{}': {}", time, e)),
+        UntilSpec::Cron { expression, timezone } => {
+            let timezone = timezone.as_deref().unwrap_or("UTC");
+            crate::scheduler::next_cron_run_after(expression, timezone, now)
+                .map_err(|e| format!("Invalid until cron: {}", e))
+        }
+    }
+}
+
+/// The job that completes a long delay, on the delayed job's priority stream.
+fn resume_job(job_id: &str, run_id: Option<&str>, priority: JobPriority, delay_ms: u64) -> serde_json::Value {
+    serde_json::json!({
+        "id": job_id,
+        "run_id": run_id,
+        "node": { "type": "DELAYRESUME", "data": { "original_delay_ms": delay_ms } },
+        "retry_count": 0,
+        "max_retries": 0,
+        "priority": priority
+    })
+}
+
+/// Park a serialized job in `swiftgrid_delayed` for the scheduler to enqueue
+/// once `delay_ms` has passed, indexed under its run so a cancel drops it.
+pub async fn schedule_job(
+    redis_client: &redis::Client,
+    job_json: &str,
+    run_id: Option<&str>,
+    delay_ms: u64,
+) -> RedisResult<()> {
+    let resume_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64 + delay_ms;
+    let mut con = redis_client.get_multiplexed_async_connection().await?;
+    let mut pipe = redis::pipe();
+    pipe.atomic().zadd(DELAYED_JOBS_KEY, job_json, resume_at as f64).ignore();
+    if let Some(run_id) = run_id {
+        let index = run_index_key(run_id);
+        pipe.sadd(&index, job_json)
+            .ignore()
+            .expire(&index, (delay_ms / 1000 + RUN_INDEX_MARGIN_SECS) as i64)
+            .ignore();
+    }
+    pipe.query_async(&mut con).await
+}
+
+/// Key of the set indexing a run's pending delayed jobs.
+pub fn run_index_key(run_id: &str) -> String {
+    format!("{}{}", RUN_INDEX_PREFIX, run_id)
+}
+
+/// Run a delayed job (a `swiftgrid_delayed` member) belongs to, if any.
+pub fn delayed_job_run_id(job_json: &str) -> Option<String> {
+    let job: serde_json::Value = serde_json::from_str(job_json).ok()?;
+    job.get("run_id")?.as_str().map(str::to_string)
+}
+
+/// Drop a cancelled run's pending long delays so they never resume it.
+/// Returns how many were removed (0 if another worker got there first).
+pub async fn cancel_delayed(redis_client: &redis::Client, run_id: &str) -> RedisResult<usize> {
+    let mut con = redis_client.get_multiplexed_async_connection().await?;
+    redis::cmd("EVAL")
+        .arg(CANCEL_SCRIPT)
+        .arg(2)
+        .arg(DELAYED_JOBS_KEY)
+        .arg(run_index_key(run_id))
+        .query_async(&mut con)
+        .await
+}
+
 /// Handle a delay resume (called by scheduler when delay has elapsed).
 pub fn execute_resume(original_delay_ms: u64) -> (u16, Option<serde_json::Value>) {
-    println!("  → Delay resumed after {}ms", original_delay_ms);
+    tracing::info!("Delay resumed after {}ms", original_delay_ms);
 
     (
         200,
@@ -121,3 +323,117 @@ pub fn execute_resume(original_delay_ms: u64) -> (u16, Option<serde_json::Value>
     )
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_increment_is_one_transaction_that_rearms_the_expiry() {
+        let pipe = increment_pipe("run-1", "wait");
+        assert!(pipe.is_transaction());
+        let commands: Vec<Vec<String>> = pipe
+            .cmd_iter()
+            .map(|cmd| {
+                cmd.args_iter()
+                    .map(|arg| match arg {
+                        redis::Arg::Simple(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                        redis::Arg::Cursor => "<cursor>".to_string(),
+                    })
+                    .collect()
+            })
+            .collect();
+        let key = format!("{}run-1:wait", CYCLE_KEY_PREFIX);
+        assert_eq!(
+            commands,
+            [vec!["INCRBY".to_string(), key.clone(), "1".to_string()], vec!["EXPIRE".to_string(), key, CYCLE_KEY_TTL_SECS.to_string()]]
+        );
+    }
+
+    #[derive(Default)]
+    struct MemoryCounter(Mutex<HashMap<String, u64>>);
+
+    impl CycleCounter for MemoryCounter {
+        async fn increment(&self, run_id: &str, node_id: &str) -> Result<u64, String> {
+            let mut counts = self.0.lock().unwrap();
+            let count = counts.entry(format!("{}:{}", run_id, node_id)).or_default();
+            *count += 1;
+            Ok(*count)
+        }
+    }
+
+    #[test]
+    fn test_delayed_jobs_are_indexed_by_their_run() {
+        let job = serde_json::json!({
+            "id": "wait",
+            "run_id": "0b6f2c1e-2a4d-4c1b-9f1e-6c0a7d9e8f10",
+            "node": { "type": "DELAYRESUME", "data": { "original_delay_ms": 120000 } }
+        })
+        .to_string();
+        let run_id = delayed_job_run_id(&job).unwrap();
+        assert_eq!(run_index_key(&run_id), "swiftgrid:delayed_by_run:0b6f2c1e-2a4d-4c1b-9f1e-6c0a7d9e8f10");
+        assert!(delayed_job_run_id(r#"{"id": "wait"}"#).is_none());
+        assert!(delayed_job_run_id("not json").is_none());
+    }
+
+    #[test]
+    fn test_resume_job_keeps_priority() {
+        let job = resume_job("wait", Some("0b6f2c1e-2a4d-4c1b-9f1e-6c0a7d9e8f10"), JobPriority::Low, 120_000).to_string();
+        let parsed: crate::types::WorkerJob = serde_json::from_str(&job).unwrap();
+        assert!(matches!(parsed.node, crate::types::NodeType::DelayResume(_)));
+        assert_eq!(parsed.priority, JobPriority::Low);
+        assert_eq!(crate::priority::stream_for_payload(&job), crate::priority::STREAM_LOW);
+    }
+
+    #[test]
+    fn test_until_resolves_to_a_delay() {
+        let now = DateTime::parse_from_rfc3339("2024-03-08T15:00:00Z").unwrap().with_timezone(&Utc); // a Friday
+        let delay = |until: UntilSpec| {
+            delay_ms(&DelayNodeData { duration_ms: 5000, duration_str: None, until: Some(until) }, now)
+        };
+
+        assert_eq!(delay_ms(&DelayNodeData { duration_ms: 5000, duration_str: None, until: None }, now), Ok(5000));
+        assert_eq!(delay(UntilSpec::At { time: "2024-03-08T15:00:30Z".into() }), Ok(30_000));
+        assert_eq!(delay(UntilSpec::At { time: "2024-03-08T14:00:00Z".into() }), Ok(0));
+        assert!(delay(UntilSpec::At { time: "tomorrow".into() }).is_err());
+
+        // Next business day at 9am in New York: Monday 13:00 UTC (EDT, since DST began on Sunday)
+        let business_hours = UntilSpec::Cron {
+            expression: "0 9 * * MON-FRI".into(),
+            timezone: Some("America/New_York".into()),
+        };
+        assert_eq!(delay(business_hours), Ok((2 * 24 + 22) * 3600 * 1000));
+        let bad_zone = UntilSpec::Cron { expression: "0 9 * * *".into(), timezone: Some("Mars/Olympus".into()) };
+        assert!(delay(bad_zone).unwrap_err().contains("Unknown timezone"));
+    }
+
+    #[tokio::test]
+    async fn test_exceeding_cycle_limit_fails_with_loop_detected() {
+        let counter = MemoryCounter::default();
+        for _ in 0..3 {
+            assert!(check_cycles(&counter, "run-1", "wait", 3).await.is_none());
+        }
+
+        let (status, body) = check_cycles(&counter, "run-1", "wait", 3).await.unwrap();
+        assert_eq!(status, LOOP_DETECTED_STATUS);
+        assert_eq!(body["loop_detected"], true);
+        assert_eq!(body["cycles"], 4);
+        assert!(body["error"].as_str().unwrap().contains("infinite loop"));
+        // A loop can't be fixed by retrying
+        assert!(!crate::retry::is_retryable_error(status));
+
+        // Counted per run and per node
+        assert!(check_cycles(&counter, "run-2", "wait", 3).await.is_none());
+        assert!(check_cycles(&counter, "run-1", "other", 3).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_zero_limit_disables_detection() {
+        let counter = MemoryCounter::default();
+        for _ in 0..10 {
+            assert!(check_cycles(&counter, "run-1", "wait", 0).await.is_none());
+        }
+        assert!(counter.0.lock().unwrap().is_empty());
+    }
+}
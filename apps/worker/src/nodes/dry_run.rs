@@ -0,0 +1,130 @@
+//! Dry-run results.
+//!
+//! A job with `dry_run` set doesn't perform its side effects: nodes that would
+//! call out (HTTP, WebSocket, LLM, S3), write (DB upsert), wait (delay, webhook,
+//! DB wait) or start other work (sub-flow, map, parallel, loop) return a synthetic
+//! success describing what they would have done. Nodes without side effects
+//! (code, router, conditional, aggregate) still run, so routing through them is real.
+//! Events and orchestration happen as usual, which exercises the graph wiring.
+
+use crate::types::NodeType;
+use serde_json::{json, Value};
+
+/// The synthetic result for a node, or None if it should run for real.
+///
+/// Credentials (headers, auth, api keys) are never echoed.
+pub fn simulate(node: &NodeType) -> Option<Value> {
+    let would = match node {
+        NodeType::Http(data) => json!({
+            "action": "http_request",
+            "method": data.method,
+            "url": data.url,
+            "query": data.query,
+            "has_body": data.body.is_some() || data.body_source.is_some(),
+            "body_source": data.body_source,
+        }),
+        NodeType::WebSocket(data) => json!({
+            "action": "websocket_connect",
+            "url": data.url,
+            "sends_message": data.send.is_some(),
+        }),
+        NodeType::Llm(data) => json!({
+            "action": "llm_request",
+            "base_url": data.base_url,
+            "model": data.model,
+            "messages": data.messages.len(),
+            "stream": data.stream,
+        }),
+        NodeType::DbUpsert(data) => json!({
+            "action": "db_upsert",
+            "table": data.table,
+            "rows": data.rows.len(),
+            "conflict_columns": data.conflict_columns,
+        }),
+        NodeType::Delay(data) => json!({ "action": "delay", "duration_ms": data.duration_ms, "until": data.until }),
+        NodeType::WebhookWait(data) => json!({
+            "action": "webhook_wait",
+            "description": data.description,
+            "outcomes": data.outcomes,
+        }),
+        NodeType::DbWait(data) => json!({ "action": "db_wait", "query": data.query }),
+        NodeType::SubFlow(data) => json!({
+            "action": "start_subflow",
+            "workflow_id": data.workflow_id,
+            "version_id": data.version_id,
+        }),
+        NodeType::Map(data) => json!({
+            "action": "map",
+            "workflow_id": data.workflow_id,
+            "items": data.items.len(),
+            "concurrency": data.concurrency,
+        }),
+        NodeType::Parallel(data) => json!({
+            "action": "parallel",
+            "branches": data.branches,
+            "join_mode": data.join_mode,
+        }),
+        NodeType::Loop(data) => json!({
+            "action": "loop",
+            "body": data.body,
+            "condition": data.condition,
+            "max_iterations": data.max_iterations,
+        }),
+        NodeType::Email(data) => json!({
+            "action": "send_email",
+            "to": data.to,
+            "cc": data.cc,
+            "subject": data.subject,
+        }),
+        NodeType::S3(data) => json!({
+            "action": "s3_request",
+            "operation": data.operation,
+            "bucket": data.bucket,
+            "key": data.key,
+            "has_body": data.body.is_some(),
+        }),
+        // No side effects, or lifecycle events (which a dry run never produces)
+        _ => return None,
+    };
+
+    Some(json!({ "dry_run": true, "would": would }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_describes_side_effects_without_secrets() {
+        let node: NodeType = serde_json::from_value(json!({
+            "type": "HTTP",
+            "data": {
+                "url": "https://api.example.com/orders",
+                "method": "POST",
+                "headers": {"Authorization": "Bearer s3cret"},
+                "body": {"id": 1}
+            }
+        }))
+        .unwrap();
+        let body = simulate(&node).unwrap();
+        assert_eq!(body["dry_run"], true);
+        assert_eq!(body["would"]["method"], "POST");
+        assert_eq!(body["would"]["url"], "https://api.example.com/orders");
+        assert!(!body.to_string().contains("s3cret"));
+
+        let llm: NodeType = serde_json::from_value(json!({
+            "type": "LLM",
+            "data": {"base_url": "https://api.openai.com/v1", "api_key": "sk-123", "model": "gpt-4o", "messages": []}
+        }))
+        .unwrap();
+        let body = simulate(&llm).unwrap();
+        assert_eq!(body["would"]["model"], "gpt-4o");
+        assert!(!body.to_string().contains("sk-123"));
+    }
+
+    #[test]
+    fn test_pure_nodes_run_for_real() {
+        let code: NodeType = serde_json::from_value(json!({"type": "CODE", "data": {"code": "return 1"}})).unwrap();
+        assert!(simulate(&code).is_none());
+    }
+}
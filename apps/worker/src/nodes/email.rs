@@ -0,0 +1,559 @@
+//! Email node execution.
+//!
+//! Sends one message over SMTP to the server configured in the environment
+//! (`SMTP_HOST`, `SMTP_PORT`, `SMTP_USERNAME`, `SMTP_PASSWORD`, `SMTP_FROM`,
+//! `SMTP_TLS`). `{{...}}` in the subject and body (e.g. `{{$trigger.email}}`)
+//! is resolved against the run first.
+//!
+//! The SMTP dialogue is handled here (like the WebSocket framing); TLS is
+//! rustls with the webpki roots, either implicit (`SMTP_TLS=tls`) or upgraded
+//! with STARTTLS (the default). The body is sent base64-encoded, so any text
+//! or HTML survives transit unchanged.
+//!
+//! `SMTP_HOST` goes through the SSRF policy like any node destination (a local
+//! relay must be listed in `HTTP_ALLOWED_HOSTS`), and the connection is made
+//! to the addresses that check resolved.
+//!
+//! Replies decide whether the retry machinery tries again: 4xx replies
+//! (greylisting, mailbox busy) and connection failures become 503, 5xx
+//! replies (unknown mailbox, policy rejection) a non-retryable 422.
+
+use crate::node_error::{NodeError, NodeResult};
+use crate::ssrf::{self, SsrfPolicy};
+use crate::templating::{self, TemplateContext};
+use crate::types::EmailNodeData;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Upper bound on one delivery, from connect to the server accepting the message
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest reply line we read; a server sending more is broken
+const MAX_REPLY_LINE: usize = 4096;
+
+/// How the connection to the SMTP server is secured (`SMTP_TLS`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SmtpTls {
+    /// Plain connection upgraded with STARTTLS (`starttls`, the default)
+    StartTls,
+    /// TLS from the first byte (`tls`)
+    Implicit,
+    /// No TLS (`none`), for local relays only
+    None,
+}
+
+#[derive(Debug, Clone)]
+struct SmtpConfig {
+    host: String,
+    port: u16,
+    tls: SmtpTls,
+    credentials: Option<(String, String)>,
+    from: String,
+}
+
+impl SmtpConfig {
+    fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        let host = var("SMTP_HOST").ok_or("SMTP_HOST is not set")?;
+        let from = var("SMTP_FROM").ok_or("SMTP_FROM is not set")?;
+        if !valid_address(&from) {
+            return Err(format!("SMTP_FROM is not a valid address: {}", from));
+        }
+        let tls = match var("SMTP_TLS").as_deref().map(str::to_ascii_lowercase).as_deref() {
+            None | Some("starttls") => SmtpTls::StartTls,
+            Some("tls") => SmtpTls::Implicit,
+            Some("none") => SmtpTls::None,
+            Some(other) => return Err(format!("SMTP_TLS must be starttls, tls or none (got {})", other)),
+        };
+        let port = match var("SMTP_PORT") {
+            Some(port) => port.parse().map_err(|_| format!("SMTP_PORT is not a port: {}", port))?,
+            None => match tls {
+                SmtpTls::StartTls => 587,
+                SmtpTls::Implicit => 465,
+                SmtpTls::None => 25,
+            },
+        };
+        let credentials = var("SMTP_USERNAME").map(|user| (user, var("SMTP_PASSWORD").unwrap_or_default()));
+
+        Ok(Self { host, port, tls, credentials, from })
+    }
+}
+
+/// Why a delivery failed.
+#[derive(Debug, PartialEq)]
+enum SmtpError {
+    /// Couldn't reach the server or the connection broke
+    Connection(String),
+    /// The server answered with a code we didn't expect
+    Reply { code: u16, message: String },
+    /// The server said something that isn't SMTP
+    Protocol(String),
+}
+
+impl SmtpError {
+    /// The node failure: 4xx replies and connection problems are retried
+    /// (503), 5xx replies are final (422).
+    fn into_node_error(self) -> NodeError {
+        let (status, body) = match self {
+            SmtpError::Connection(e) => (503, json!({ "error": format!("SMTP connection failed: {}", e) })),
+            SmtpError::Reply { code, message } => {
+                let status = if (400..500).contains(&code) { 503 } else { 422 };
+                (status, json!({ "error": format!("SMTP server replied {}: {}", code, message), "smtp_code": code }))
+            }
+            SmtpError::Protocol(e) => (502, json!({ "error": format!("SMTP protocol error: {}", e) })),
+        };
+        NodeError::Permanent { status, body }
+    }
+}
+
+impl From<std::io::Error> for SmtpError {
+    fn from(e: std::io::Error) -> Self {
+        SmtpError::Connection(e.to_string())
+    }
+}
+
+/// Execute an Email node: resolve templates, then deliver over SMTP.
+pub async fn execute(
+    mut data: EmailNodeData,
+    run_id: Option<&Uuid>,
+    db_pool: &PgPool,
+    cancel_token: &CancellationToken,
+    ssrf_policy: &SsrfPolicy,
+) -> NodeResult {
+    let config = SmtpConfig::from_env().map_err(|e| NodeError::permanent(500, e))?;
+    validate(&data).map_err(|e| NodeError::permanent(400, e))?;
+    let addrs = match ssrf_policy.resolve_host(&config.host, config.port).await {
+        Ok(addrs) => addrs,
+        Err(e @ ssrf::SsrfError::Unresolvable { .. }) => {
+            return Err(SmtpError::Connection(e.to_string()).into_node_error());
+        }
+        Err(e) => return Err(NodeError::Permanent { status: 403, body: ssrf::blocked_body(&e) }),
+    };
+    resolve_templates(&mut data, run_id, db_pool).await?;
+
+    let message_id = format!("<{}@{}>", Uuid::new_v4(), domain_of(&config.from));
+    let message = build_message(&config.from, &data, &message_id, chrono::Utc::now());
+    let recipients: Vec<&str> = data.to.iter().chain(&data.cc).map(String::as_str).collect();
+
+    let delivery = tokio::time::timeout(SEND_TIMEOUT, send(&config, &addrs, &recipients, &message));
+    let reply = tokio::select! {
+        biased;
+        _ = cancel_token.cancelled() => {
+            return Err(NodeError::Cancelled(json!({ "error": "Email cancelled" })));
+        }
+        result = delivery => match result {
+            Ok(reply) => reply.map_err(SmtpError::into_node_error)?,
+            Err(_) => return Err(NodeError::permanent(504, format!("SMTP delivery timed out after {}s", SEND_TIMEOUT.as_secs()))),
+        },
+    };
+
+    tracing::info!("Email: accepted for {} recipient(s)", recipients.len());
+    Ok((
+        200,
+        Some(json!({
+            "accepted": true,
+            "recipients": recipients.len(),
+            "message_id": message_id,
+            "smtp_response": reply,
+        })),
+    ))
+}
+
+fn validate(data: &EmailNodeData) -> Result<(), String> {
+    if data.to.is_empty() {
+        return Err("Email needs at least one `to` address".to_string());
+    }
+    match data.to.iter().chain(&data.cc).find(|a| !valid_address(a)) {
+        Some(bad) => Err(format!("Invalid email address: {}", bad)),
+        None => Ok(()),
+    }
+}
+
+/// A bare `local@domain` address, with nothing that could break out of an
+/// SMTP command or header.
+fn valid_address(address: &str) -> bool {
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.is_empty()
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | ',' | ';'))
+}
+
+fn domain_of(address: &str) -> &str {
+    address.rsplit_once('@').map(|(_, domain)| domain).unwrap_or("localhost")
+}
+
+/// Resolve `{{...}}` in the subject and body against the run's input and
+/// recorded node outputs. Messages without templates skip the lookups.
+async fn resolve_templates(data: &mut EmailNodeData, run_id: Option<&Uuid>, db_pool: &PgPool) -> Result<(), NodeError> {
+    let Some(rid) = run_id.filter(|_| data.subject.contains("{{") || data.body.contains("{{")) else {
+        return Ok(());
+    };
+    let input: Option<(Option<Value>,)> = sqlx::query_as("SELECT input_data FROM workflow_runs WHERE id = $1")
+        .bind(rid)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|e| NodeError::from_sqlx("Failed to load run input", &e))?;
+    let input = input.and_then(|(i,)| i);
+    let outputs = templating::recorded_outputs(db_pool, rid)
+        .await
+        .map_err(|e| NodeError::from_sqlx("Failed to load node outputs", &e))?;
+
+    let ctx = TemplateContext { node_outputs: &outputs, input: input.as_ref() };
+    data.subject = templating::resolve_str(&data.subject, &ctx);
+    data.body = templating::resolve_str(&data.body, &ctx);
+    Ok(())
+}
+
+/// The RFC 5322 message: headers, then the body as base64 in 76-column lines.
+fn build_message(from: &str, data: &EmailNodeData, message_id: &str, date: chrono::DateTime<chrono::Utc>) -> String {
+    let content_type = if data.is_html { "text/html" } else { "text/plain" };
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\n",
+        from,
+        data.to.join(", ")
+    );
+    if !data.cc.is_empty() {
+        message.push_str(&format!("Cc: {}\r\n", data.cc.join(", ")));
+    }
+    message.push_str(&format!(
+        "Subject: {}\r\nDate: {}\r\nMessage-ID: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+        encode_header(&data.subject),
+        date.to_rfc2822(),
+        message_id,
+        content_type
+    ));
+
+    let encoded = BASE64.encode(data.body.as_bytes());
+    for line in encoded.as_bytes().chunks(76) {
+        // base64 is ASCII, so every chunk is valid UTF-8
+        message.push_str(std::str::from_utf8(line).unwrap_or_default());
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// A header value as-is when it's plain printable ASCII, otherwise as an
+/// RFC 2047 encoded word (which also keeps line breaks out of the header).
+fn encode_header(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64.encode(value.as_bytes()))
+    }
+}
+
+/// Connect to `addrs` (`config.host`, already checked), secure the connection
+/// as configured and deliver the message.
+/// Returns the server's final reply to the message data.
+async fn send(config: &SmtpConfig, addrs: &[SocketAddr], recipients: &[&str], message: &str) -> Result<String, SmtpError> {
+    let tcp = TcpStream::connect(addrs).await?;
+    let helo = helo_name();
+
+    match config.tls {
+        SmtpTls::Implicit => {
+            let mut conn = SmtpConnection::new(tls_connect(tcp, &config.host).await?);
+            conn.expect(&[220]).await?;
+            conn.command(&format!("EHLO {}", helo), &[250]).await?;
+            deliver(&mut conn, config, recipients, message).await
+        }
+        SmtpTls::None => {
+            let mut conn = SmtpConnection::new(tcp);
+            conn.expect(&[220]).await?;
+            conn.command(&format!("EHLO {}", helo), &[250]).await?;
+            deliver(&mut conn, config, recipients, message).await
+        }
+        SmtpTls::StartTls => {
+            let mut plain = SmtpConnection::new(tcp);
+            plain.expect(&[220]).await?;
+            let ehlo = plain.command(&format!("EHLO {}", helo), &[250]).await?;
+            if !ehlo.lines().any(|line| line.eq_ignore_ascii_case("STARTTLS")) {
+                return Err(SmtpError::Protocol("server doesn't offer STARTTLS (set SMTP_TLS=none to send in plain text)".into()));
+            }
+            plain.command("STARTTLS", &[220]).await?;
+
+            let mut conn = SmtpConnection::new(tls_connect(plain.into_inner(), &config.host).await?);
+            conn.command(&format!("EHLO {}", helo), &[250]).await?;
+            deliver(&mut conn, config, recipients, message).await
+        }
+    }
+}
+
+/// Authenticate (when configured), send the envelope and the message, quit.
+async fn deliver<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut SmtpConnection<S>,
+    config: &SmtpConfig,
+    recipients: &[&str],
+    message: &str,
+) -> Result<String, SmtpError> {
+    if let Some((user, password)) = &config.credentials {
+        let token = BASE64.encode(format!("\0{}\0{}", user, password));
+        conn.command(&format!("AUTH PLAIN {}", token), &[235]).await?;
+    }
+    conn.command(&format!("MAIL FROM:<{}>", config.from), &[250]).await?;
+    for recipient in recipients {
+        conn.command(&format!("RCPT TO:<{}>", recipient), &[250, 251]).await?;
+    }
+    conn.command("DATA", &[354]).await?;
+    let accepted = conn.command(&format!("{}.", message), &[250]).await?;
+
+    // The message is accepted; a failed goodbye doesn't change that
+    let _ = conn.command("QUIT", &[221]).await;
+    Ok(accepted.text())
+}
+
+fn helo_name() -> String {
+    std::env::var("HOSTNAME").ok().filter(|h| !h.is_empty()).unwrap_or_else(|| "localhost".to_string())
+}
+
+static TLS_CONFIG: Lazy<Arc<rustls::ClientConfig>> = Lazy::new(|| {
+    let roots = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+});
+
+async fn tls_connect(tcp: TcpStream, host: &str) -> Result<tokio_rustls::client::TlsStream<TcpStream>, SmtpError> {
+    let name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .map_err(|e| SmtpError::Connection(format!("invalid SMTP_HOST for TLS: {}", e)))?;
+    let connector = tokio_rustls::TlsConnector::from(TLS_CONFIG.clone());
+    connector
+        .connect(name, tcp)
+        .await
+        .map_err(|e| SmtpError::Connection(format!("TLS handshake failed: {}", e)))
+}
+
+/// A complete (possibly multi-line) SMTP reply.
+#[derive(Debug, PartialEq)]
+struct Reply {
+    code: u16,
+    text: Vec<String>,
+}
+
+impl Reply {
+    fn lines(&self) -> impl Iterator<Item = &str> {
+        self.text.iter().map(String::as_str)
+    }
+
+    fn text(&self) -> String {
+        format!("{} {}", self.code, self.text.join(" "))
+    }
+}
+
+/// One reply line: (code, whether it's the last line, text).
+fn parse_reply_line(line: &str) -> Option<(u16, bool, &str)> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let code = line.get(..3)?.parse().ok()?;
+    match line.as_bytes().get(3) {
+        None => Some((code, true, "")),
+        Some(b' ') => Some((code, true, &line[4..])),
+        Some(b'-') => Some((code, false, &line[4..])),
+        Some(_) => None,
+    }
+}
+
+struct SmtpConnection<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SmtpConnection<S> {
+    fn new(stream: S) -> Self {
+        Self { stream: BufReader::new(stream) }
+    }
+
+    fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Send a command line and read the reply, which must have one of `codes`.
+    async fn command(&mut self, line: &str, codes: &[u16]) -> Result<Reply, SmtpError> {
+        let stream = self.stream.get_mut();
+        stream.write_all(line.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        self.expect(codes).await
+    }
+
+    /// Read a reply, which must have one of `codes`.
+    async fn expect(&mut self, codes: &[u16]) -> Result<Reply, SmtpError> {
+        let reply = self.read_reply().await?;
+        if codes.contains(&reply.code) {
+            Ok(reply)
+        } else {
+            Err(SmtpError::Reply { code: reply.code, message: reply.text.join(" ") })
+        }
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply, SmtpError> {
+        let mut text = Vec::new();
+        loop {
+            let mut line = String::new();
+            let read = (&mut self.stream).take(MAX_REPLY_LINE as u64).read_line(&mut line).await?;
+            if read == 0 {
+                return Err(SmtpError::Connection("server closed the connection".into()));
+            }
+            let (code, last, line_text) =
+                parse_reply_line(&line).ok_or_else(|| SmtpError::Protocol(format!("unexpected reply: {}", line.trim_end())))?;
+            text.push(line_text.to_string());
+            if last {
+                return Ok(Reply { code, text });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    fn email(subject: &str, body: &str) -> EmailNodeData {
+        EmailNodeData {
+            to: vec!["ops@example.com".into()],
+            cc: vec!["lead@example.com".into()],
+            subject: subject.into(),
+            body: body.into(),
+            is_html: false,
+        }
+    }
+
+    fn config() -> SmtpConfig {
+        SmtpConfig {
+            host: "mail.example.com".into(),
+            port: 25,
+            tls: SmtpTls::None,
+            credentials: Some(("bot".into(), "pw".into())),
+            from: "bot@example.com".into(),
+        }
+    }
+
+    #[test]
+    fn test_reply_lines() {
+        assert_eq!(parse_reply_line("250-mail.example.com\r\n"), Some((250, false, "mail.example.com")));
+        assert_eq!(parse_reply_line("250 STARTTLS\r\n"), Some((250, true, "STARTTLS")));
+        assert_eq!(parse_reply_line("354\r\n"), Some((354, true, "")));
+        assert_eq!(parse_reply_line("hello"), None);
+    }
+
+    #[test]
+    fn test_addresses_cannot_inject_commands_or_headers() {
+        assert!(valid_address("ops@example.com"));
+        assert!(!valid_address("ops@example.com>\r\nRCPT TO:<x@evil.com"));
+        assert!(!valid_address("a@b.com, c@d.com"));
+        assert!(!valid_address("nobody"));
+
+        let mut data = email("s", "b");
+        data.cc.push("bad address@example.com".into());
+        assert_eq!(validate(&data), Err("Invalid email address: bad address@example.com".to_string()));
+        data.to.clear();
+        assert!(validate(&data).is_err());
+    }
+
+    #[test]
+    fn test_message_encodes_subject_and_body() {
+        let date = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let plain = build_message("bot@example.com", &email("Report ready", "Hi"), "<id@example.com>", date);
+        assert!(plain.starts_with("From: bot@example.com\r\nTo: ops@example.com\r\nCc: lead@example.com\r\nSubject: Report ready\r\n"));
+        assert!(plain.contains("Content-Type: text/plain; charset=utf-8\r\n"));
+        assert!(plain.ends_with("\r\n\r\nSGk=\r\n"));
+
+        // A line break in the subject can't start a new header
+        let mut data = email("Done\r\nBcc: everyone@example.com", "<b>ok</b>");
+        data.is_html = true;
+        let html = build_message("bot@example.com", &data, "<id@example.com>", date);
+        assert!(!html.contains("\r\nBcc:"));
+        assert!(html.contains("Subject: =?UTF-8?B?"));
+        assert!(html.contains("Content-Type: text/html; charset=utf-8\r\n"));
+    }
+
+    #[test]
+    fn test_transient_replies_are_retryable() {
+        let status = |e: SmtpError| match e.into_node_error() {
+            NodeError::Permanent { status, .. } => status,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(status(SmtpError::Reply { code: 451, message: "greylisted".into() }), 503);
+        assert_eq!(status(SmtpError::Reply { code: 550, message: "no such user".into() }), 422);
+        assert_eq!(status(SmtpError::Connection("refused".into())), 503);
+        assert!(crate::retry::is_retryable_error(503));
+        assert!(!crate::retry::is_retryable_error(422));
+    }
+
+    /// Play the server side of a session: answer each command with the next reply.
+    async fn serve(mut server: DuplexStream, replies: &[&str]) -> String {
+        let mut received = String::new();
+        let mut buf = vec![0u8; 8192];
+        for reply in replies {
+            // Wait for a full command (or the message's terminating dot)
+            while !received.ends_with("\r\n") {
+                let n = server.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    return received;
+                }
+                received.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+            }
+            server.write_all(reply.as_bytes()).await.unwrap();
+            received.push('|');
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_delivers_to_every_recipient() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let replies = [
+            "235 2.7.0 Authentication successful\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+            "354 End data with <CR><LF>.<CR><LF>\r\n",
+            "250-2.0.0 Ok:\r\n250 queued as ABC123\r\n",
+            "221 Bye\r\n",
+        ];
+        let server = tokio::spawn(async move { serve(server, &replies).await });
+
+        let mut conn = SmtpConnection::new(client);
+        let reply = deliver(&mut conn, &config(), &["ops@example.com", "lead@example.com"], "Subject: x\r\n\r\nSGk=\r\n")
+            .await
+            .unwrap();
+        assert_eq!(reply, "250 2.0.0 Ok: queued as ABC123");
+
+        let transcript = server.await.unwrap();
+        let commands: Vec<&str> = transcript.split("\r\n|").collect();
+        assert_eq!(commands[0], format!("AUTH PLAIN {}", BASE64.encode("\0bot\0pw")));
+        assert_eq!(commands[1], "MAIL FROM:<bot@example.com>");
+        assert_eq!(commands[2], "RCPT TO:<ops@example.com>");
+        assert_eq!(commands[3], "RCPT TO:<lead@example.com>");
+        assert_eq!(commands[4], "DATA");
+        assert_eq!(commands[5], "Subject: x\r\n\r\nSGk=\r\n.");
+        assert_eq!(commands[6], "QUIT");
+    }
+
+    #[tokio::test]
+    async fn test_rejected_recipient_stops_delivery() {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let replies = ["235 ok\r\n", "250 OK\r\n", "450 4.2.0 Greylisted, try again later\r\n"];
+        tokio::spawn(async move { serve(server, &replies).await });
+
+        let mut conn = SmtpConnection::new(client);
+        let err = deliver(&mut conn, &config(), &["ops@example.com"], "x\r\n").await.unwrap_err();
+        assert_eq!(err, SmtpError::Reply { code: 450, message: "4.2.0 Greylisted, try again later".into() });
+    }
+}
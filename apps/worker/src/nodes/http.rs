@@ -1,22 +1,203 @@
 //! HTTP node execution.
 //!
 //! Makes HTTP requests with streaming progress updates and cancellation support.
+//! Requests go through a per-host circuit breaker so a failing host isn't hammered.
+//! With `stream_body`, large or SSE-style responses are forwarded chunk-by-chunk
+//! instead of being buffered in memory.
+//! With `response_schema`, successful JSON responses are checked against it and
+//! a mismatch fails the node with 422 (not retried).
+//! URLs that point at internal addresses are refused with 403 (see `ssrf`).
+//! `auth` sets the credentials header (its `{{$env.NAME}}` secrets are resolved
+//! beforehand by `nodes::resolve_env_secrets`).
+//! With `paginate`, pages are followed (cursor, next URL or `Link` header) up to
+//! `max_pages` and their items combined into one array.
+//! Buffered bodies are read incrementally and abandoned with 413 once they pass
+//! `max_response_bytes` (default `HTTP_MAX_RESPONSE_BYTES`).
+//! GET nodes with `cache_ttl_ms` are answered from Redis while a successful
+//! response is cached (see `http_cache`).
+//! Connection failures (and timeouts of idempotent methods) are retried in the
+//! node with a short backoff, `connect_retries` times (default
+//! `HTTP_CONNECT_RETRIES`), before the job-level retry takes over.
+//! With `body_source`, the request body is streamed from Redis or an earlier
+//! node's streamed output instead of `body` (see `http_upload`).
 
+use crate::circuit_breaker::{self, CircuitBreakers};
+use crate::compression;
+use crate::json_schema;
+use crate::ssrf::{self, SsrfPolicy};
 use crate::streaming::StreamContext;
-use crate::types::HttpNodeData;
+use crate::trace_context::{self, TraceContext};
+use crate::nodes::http_cache;
+use crate::nodes::http_upload::StreamedBody;
+use crate::nodes::subflow::extract_path;
+use crate::types::{Compression, HttpAuth, HttpBodyEncoding, HttpMethod, HttpNodeData, PageCursor, PaginateConfig};
+use crate::warnings::Warnings;
+use base64::Engine;
+use std::collections::HashMap;
+use std::time::Duration;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use tokio_util::sync::CancellationToken;
 
+/// Default largest binary response that gets base64-encoded into the result (10MB)
+const DEFAULT_MAX_BINARY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Largest binary body returned inline (`HTTP_MAX_BINARY_BYTES`)
+fn max_binary_bytes() -> usize {
+    std::env::var("HTTP_MAX_BINARY_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BINARY_BYTES)
+}
+
+/// Default largest response body read into memory (50MB)
+const DEFAULT_MAX_RESPONSE_BYTES: u64 = 50 * 1024 * 1024;
+
+/// Largest buffered response body (`HTTP_MAX_RESPONSE_BYTES`)
+fn max_response_bytes() -> u64 {
+    std::env::var("HTTP_MAX_RESPONSE_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
+}
+
+/// Default minimum body size worth compressing (1KB)
+const DEFAULT_COMPRESS_MIN_BYTES: usize = 1024;
+
+/// Smallest body that gets compressed (`HTTP_COMPRESS_MIN_BYTES`)
+fn compress_min_bytes() -> usize {
+    std::env::var("HTTP_COMPRESS_MIN_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COMPRESS_MIN_BYTES)
+}
+
+/// Default in-node retries for connection failures (`HTTP_CONNECT_RETRIES`)
+const DEFAULT_CONNECT_RETRIES: u32 = 2;
+
+/// Delay before the first in-node retry, doubling after each
+const CONNECT_RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+fn connect_retries() -> u32 {
+    std::env::var("HTTP_CONNECT_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CONNECT_RETRIES)
+}
+
+/// Whether a transport error is retried inside the node. Connection failures
+/// never reached the server, so they're always safe to repeat; a timeout
+/// might have, so it's only repeated for idempotent methods.
+fn retries_transport_error(connect: bool, timeout: bool, method: &HttpMethod) -> bool {
+    connect || (timeout && !matches!(method, HttpMethod::POST | HttpMethod::PATCH))
+}
+
+/// Delay before in-node retry number `attempt` (1-based)
+fn connect_retry_backoff(attempt: u32) -> Duration {
+    CONNECT_RETRY_BACKOFF * 2u32.saturating_pow(attempt.saturating_sub(1).min(4))
+}
+
 /// Execute an HTTP request node with cancellation support.
 /// Returns (status_code, body, was_cancelled).
+#[allow(clippy::too_many_arguments)]
 pub async fn execute(
     client: reqwest::Client,
+    redis: &redis::Client,
     data: HttpNodeData,
+    upload: Option<StreamedBody>,
     stream_ctx: Option<&StreamContext>,
     cancel_token: &CancellationToken,
+    breakers: &CircuitBreakers,
+    ssrf_policy: &SsrfPolicy,
+    warnings: &Warnings,
+    trace: &TraceContext,
+) -> (u16, Option<serde_json::Value>, bool) {
+    let cache = http_cache::cache_key(&data);
+    if let Some((key, _)) = &cache
+        && let Some((status, body)) = http_cache::get(redis, key).await
+    {
+        if let Some(ctx) = stream_ctx {
+            ctx.progress(&format!("GET {} (cached)", &data.url)).await;
+            ctx.complete().await;
+        }
+        return (status, body, false);
+    }
+
+    let mut meta = ResponseMeta::default();
+    let (status, body, cancelled) = match data.paginate.clone() {
+        Some(paginate) => {
+            let pages = Pages { client, stream_ctx, cancel_token, breakers, ssrf_policy, warnings, trace };
+            execute_paginated(pages, data, paginate, &mut meta).await
+        }
+        None => execute_page(client, data, upload, stream_ctx, cancel_token, breakers, ssrf_policy, warnings, trace, &mut meta).await,
+    };
+
+    if let Some((key, ttl_ms)) = cache
+        && !cancelled
+        && !meta.no_store
+        && (200..300).contains(&status)
+    {
+        http_cache::put(redis, &key, ttl_ms, status, body.as_ref()).await;
+    }
+    (status, body, cancelled)
+}
+
+/// What a response said about itself besides its body.
+#[derive(Default)]
+struct ResponseMeta {
+    /// The `Link` header's rel="next" URL
+    next_link: Option<String>,
+    /// `Cache-Control: no-store`
+    no_store: bool,
+}
+
+/// Send one request, recording its `ResponseMeta` in `meta`.
+#[allow(clippy::too_many_arguments)]
+async fn execute_page(
+    client: reqwest::Client,
+    data: HttpNodeData,
+    upload: Option<StreamedBody>,
+    stream_ctx: Option<&StreamContext>,
+    cancel_token: &CancellationToken,
+    breakers: &CircuitBreakers,
+    ssrf_policy: &SsrfPolicy,
+    warnings: &Warnings,
+    trace: &TraceContext,
+    meta: &mut ResponseMeta,
 ) -> (u16, Option<serde_json::Value>, bool) {
     let method_str = format!("{:?}", data.method);
     let reqwest_method: reqwest::Method = method_str.parse().unwrap();
 
+    let url = reqwest::Url::parse(&data.url).ok();
+
+    // Refuse internal destinations before anything is sent
+    if let Some(ref url) = url
+        && let Err(e) = ssrf_policy.check_url(url).await
+    {
+        if let Some(ctx) = stream_ctx {
+            ctx.error(&format!("Request blocked: {}", e)).await;
+        }
+        return (403, Some(ssrf::blocked_body(&e)), false);
+    }
+
+    // Circuit breaker: fail fast if this host has been failing repeatedly
+    let host = url.as_ref().and_then(|u| u.host_str().map(|h| h.to_string()));
+
+    if let Some(ref host) = host
+        && !circuit_breaker::allow_request(breakers, host).await
+    {
+        if let Some(ctx) = stream_ctx {
+            ctx.error(&format!("Circuit open for {}", host)).await;
+        }
+        return (
+            503,
+            Some(serde_json::json!({
+                "error": format!("Circuit breaker open for host {}", host),
+                "circuit_open": true
+            })),
+            false,
+        );
+    }
+
     // Stream progress: starting
     if let Some(ctx) = stream_ctx {
         ctx.progress(&format!("{} {}", method_str, &data.url)).await;
@@ -24,13 +205,74 @@ pub async fn execute(
 
     let mut req = client.request(reqwest_method, &data.url);
 
+    // Propagate the job's span unless the node sets its own trace headers
+    let sets_trace = data.headers.as_ref().is_some_and(|h| {
+        h.keys().any(|k| k.eq_ignore_ascii_case(trace_context::TRACEPARENT))
+    });
+    if !sets_trace {
+        for (k, v) in trace.headers() {
+            req = req.header(k, v);
+        }
+    }
+
+    let auth = match data.auth.as_ref().map(auth_header).transpose() {
+        Ok(auth) => auth,
+        Err(e) => {
+            if let Some(ctx) = stream_ctx {
+                ctx.error(&e).await;
+            }
+            return (400, Some(serde_json::json!({ "error": e })), false);
+        }
+    };
+
     if let Some(h) = data.headers {
         for (k, v) in h {
+            // The auth header wins over a hand-written one
+            if auth.as_ref().is_some_and(|(name, _)| name.eq_ignore_ascii_case(&k)) {
+                continue;
+            }
             req = req.header(k, v);
         }
     }
-    if let Some(b) = data.body {
-        req = req.json(&b);
+    if let Some((name, value)) = auth {
+        req = req.header(name, value);
+    }
+    if let Some(query) = data.query.as_ref() {
+        req = req.query(&sorted_query(query));
+    }
+    if let Some(upload) = upload {
+        let content_type = data.content_type.as_deref().unwrap_or("application/octet-stream");
+        req = req.header(reqwest::header::CONTENT_TYPE, content_type);
+        if let Some(len) = upload.len {
+            req = req.header(reqwest::header::CONTENT_LENGTH, len);
+        }
+        req = req.body(upload.body);
+    } else if let Some(b) = data.body {
+        req = match apply_body(req, b, data.body_encoding.unwrap_or_default(), data.content_type.as_deref()) {
+            Ok(r) => r,
+            Err(e) => {
+                if let Some(ctx) = stream_ctx {
+                    ctx.error(&e).await;
+                }
+                return (400, Some(serde_json::json!({ "error": e })), false);
+            }
+        };
+    }
+
+    let mut request = match req.build() {
+        Ok(r) => r,
+        Err(e) => {
+            let e = format!("Invalid request: {}", e);
+            if let Some(ctx) = stream_ctx {
+                ctx.error(&e).await;
+            }
+            return (400, Some(serde_json::json!({ "error": e })), false);
+        }
+    };
+    if let Some(compression) = data.compress
+        && !compress_body(&mut request, compression, compress_min_bytes())
+    {
+        warn_if_compression_skipped(&request, warnings);
     }
 
     // Stream progress: sending
@@ -41,26 +283,73 @@ pub async fn execute(
     // Track request timing
     let request_start = std::time::Instant::now();
 
-    // Execute with cancellation support using tokio::select!
-    let result = tokio::select! {
-        biased; // Check cancellation first
+    // Connection blips are retried here, before the whole job is retried.
+    // Streaming request bodies can't be cloned, so they get a single try.
+    let max_attempts = data.connect_retries.unwrap_or_else(connect_retries);
+    let mut attempt = 0;
+    let result = loop {
+        let retry = if attempt < max_attempts { request.try_clone() } else { None };
 
-        _ = cancel_token.cancelled() => {
-            // Cancelled before or during request
-            if let Some(ctx) = stream_ctx {
-                ctx.progress("Cancelled").await;
+        // Execute with cancellation support using tokio::select!
+        let result = tokio::select! {
+            biased; // Check cancellation first
+
+            _ = cancel_token.cancelled() => {
+                // Cancelled before or during request
+                if let Some(ctx) = stream_ctx {
+                    ctx.progress("Cancelled").await;
+                }
+                return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
             }
-            return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
-        }
 
-        result = req.send() => result
+            result = client.execute(request) => result
+        };
+
+        match (result, retry) {
+            (Err(e), Some(next)) if retries_transport_error(e.is_connect(), e.is_timeout(), &data.method) => {
+                attempt += 1;
+                let backoff = connect_retry_backoff(attempt);
+                tracing::debug!("HTTP connection error, retrying in {:?} ({}/{}): {}", backoff, attempt, max_attempts, e);
+                if let Some(ctx) = stream_ctx {
+                    ctx.progress(&format!("Connection failed, retrying ({}/{})...", attempt, max_attempts)).await;
+                }
+                tokio::select! {
+                    biased;
+                    _ = cancel_token.cancelled() => {
+                        if let Some(ctx) = stream_ctx {
+                            ctx.progress("Cancelled").await;
+                        }
+                        return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
+                    }
+                    _ = tokio::time::sleep(backoff) => {}
+                }
+                request = next;
+            }
+            (result, _) => break result,
+        }
     };
 
     let network_ms = request_start.elapsed().as_millis() as u64;
 
+    // Feed the outcome back into the breaker (transport errors and 5xx count as failures)
+    if let Some(ref host) = host {
+        let success = matches!(&result, Ok(resp) if !resp.status().is_server_error());
+        circuit_breaker::record_result(breakers, host, success).await;
+    }
+
     match result {
         Ok(resp) => {
             let status = resp.status().as_u16();
+            meta.next_link = resp
+                .headers()
+                .get(reqwest::header::LINK)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_next_link);
+            meta.no_store = resp
+                .headers()
+                .get(reqwest::header::CACHE_CONTROL)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(http_cache::is_no_store);
 
             // Stream progress: receiving
             if let Some(ctx) = stream_ctx {
@@ -81,11 +370,74 @@ pub async fn execute(
                 return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
             }
 
+            if data.stream_body {
+                return stream_response(resp, stream_ctx, cancel_token, network_ms).await;
+            }
+
+            let content_type = resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+
+            let response_limit = data.max_response_bytes.unwrap_or_else(max_response_bytes);
+
             let body_start = std::time::Instant::now();
-            let text = resp.text().await.unwrap_or_default();
+
+            // Binary responses (images, PDFs, ...) would be mangled by UTF-8 decoding
+            if let Some(ct) = content_type.as_deref().filter(|ct| is_binary_content_type(ct)) {
+                let max_bytes = max_binary_bytes();
+                // Don't download what we'd refuse anyway
+                let mut body = match resp.content_length() {
+                    Some(len) if len as usize > max_bytes => binary_too_large(ct, len as usize, max_bytes),
+                    _ => match read_body_limited(resp, response_limit).await {
+                        Ok(bytes) => binary_body(ct, &bytes, max_bytes),
+                        Err(e) => return body_read_failed(e, status, stream_ctx).await,
+                    },
+                };
+                warn_if_payload_dropped(&body, warnings);
+                let body_ms = body_start.elapsed().as_millis() as u64;
+                body["_timing"] = serde_json::json!({
+                    "network_ms": network_ms,
+                    "body_read_ms": body_ms,
+                    "total_ms": network_ms + body_ms
+                });
+
+                if let Some(ctx) = stream_ctx {
+                    ctx.complete().await;
+                }
+                return (status, Some(body), false);
+            }
+
+            let text = match read_body_limited(resp, response_limit).await {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => return body_read_failed(e, status, stream_ctx).await,
+            };
             let body_ms = body_start.elapsed().as_millis() as u64;
             
-            let body = match serde_json::from_str::<serde_json::Value>(&text) {
+            let parsed = serde_json::from_str::<serde_json::Value>(&text);
+
+            // Contract check on successful responses (before metadata is injected)
+            if let Some(schema) = data.response_schema.as_ref()
+                && (200..300).contains(&status)
+                && let Some(violations) = schema_violations(schema, parsed.as_ref().ok())
+            {
+                if let Some(ctx) = stream_ctx {
+                    ctx.error(&format!("Response failed schema validation ({} violation(s))", violations.len()))
+                        .await;
+                }
+                return (
+                    422,
+                    Some(serde_json::json!({
+                        "error": "Response failed schema validation",
+                        "violations": violations,
+                        "status": status
+                    })),
+                    false,
+                );
+            }
+
+            let body = match parsed {
                 Ok(mut json) => {
                     // Inject timing metadata
                     if let Some(obj) = json.as_object_mut() {
@@ -131,3 +483,911 @@ pub async fn execute(
         }
     }
 }
+
+/// Everything a page request needs besides the node data.
+struct Pages<'a> {
+    client: reqwest::Client,
+    stream_ctx: Option<&'a StreamContext>,
+    cancel_token: &'a CancellationToken,
+    breakers: &'a CircuitBreakers,
+    ssrf_policy: &'a SsrfPolicy,
+    warnings: &'a Warnings,
+    trace: &'a TraceContext,
+}
+
+/// How the next page is requested.
+#[derive(Debug, PartialEq)]
+enum NextPage {
+    Url(String),
+    Cursor(String),
+}
+
+/// Follow pages until there's no next one (or `max_pages`), returning
+/// `{"items": [...], "pages": n, "truncated": bool}`. Each page goes through
+/// `execute_page`, so SSRF checks, the circuit breaker and body limits apply
+/// per request. A failing page fails the node with that page's result.
+/// `meta.no_store` is set if any page was `no-store`.
+async fn execute_paginated(
+    pages: Pages<'_>,
+    data: HttpNodeData,
+    paginate: PaginateConfig,
+    meta: &mut ResponseMeta,
+) -> (u16, Option<serde_json::Value>, bool) {
+    let mut page = data;
+    page.paginate = None;
+    page.stream_body = false;
+
+    let mut items = Vec::new();
+    let mut count: u32 = 0;
+    let truncated = loop {
+        if pages.cancel_token.is_cancelled() {
+            if let Some(ctx) = pages.stream_ctx {
+                ctx.progress("Cancelled").await;
+            }
+            return (
+                499,
+                Some(serde_json::json!({ "error": "Request cancelled", "pages": count })),
+                true,
+            );
+        }
+
+        let mut page_meta = ResponseMeta::default();
+        let (status, body, cancelled) = execute_page(
+            pages.client.clone(),
+            page.clone(),
+            None,
+            None,
+            pages.cancel_token,
+            pages.breakers,
+            pages.ssrf_policy,
+            pages.warnings,
+            pages.trace,
+            &mut page_meta,
+        )
+        .await;
+        count += 1;
+        meta.no_store |= page_meta.no_store;
+
+        if cancelled || !(200..300).contains(&status) {
+            let mut body = body.unwrap_or_else(|| serde_json::json!({ "error": format!("HTTP {}", status) }));
+            if let Some(obj) = body.as_object_mut() {
+                obj.insert("page".to_string(), serde_json::json!(count));
+            }
+            if let Some(ctx) = pages.stream_ctx {
+                ctx.error(&format!("Page {} failed with {}", count, status)).await;
+            }
+            return (status, Some(body), cancelled);
+        }
+
+        let body = body.unwrap_or_default();
+        let page_items = match page_items(&body, paginate.items_path.as_deref()) {
+            Ok(page_items) => page_items,
+            Err(e) => {
+                if let Some(ctx) = pages.stream_ctx {
+                    ctx.error(&e).await;
+                }
+                return (422, Some(serde_json::json!({ "error": e, "page": count })), false);
+            }
+        };
+        let page_len = page_items.len();
+        items.extend(page_items);
+
+        if let Some(ctx) = pages.stream_ctx {
+            ctx.progress(&format!("Page {}: {} items ({} total)", count, page_len, items.len())).await;
+        }
+
+        let Some(next) = next_page(&paginate.next, &body, page_meta.next_link) else {
+            break false;
+        };
+        if count >= paginate.max_pages {
+            break true;
+        }
+        match next {
+            NextPage::Url(url) => {
+                // Relative links resolve against the page that returned them
+                page.url = reqwest::Url::parse(&page.url)
+                    .and_then(|base| base.join(&url))
+                    .map(|u| u.to_string())
+                    .unwrap_or(url);
+                // The next URL already carries its query
+                page.query = None;
+            }
+            NextPage::Cursor(cursor) => {
+                let PageCursor::Cursor { param, .. } = &paginate.next else { unreachable!() };
+                page.query.get_or_insert_with(HashMap::new).insert(param.clone(), cursor);
+            }
+        }
+    };
+
+    if truncated {
+        pages.warnings.push(
+            "pagination_truncated",
+            format!("Stopped after max_pages ({}) with more pages available", paginate.max_pages),
+        );
+    }
+    if let Some(ctx) = pages.stream_ctx {
+        ctx.complete().await;
+    }
+
+    (
+        200,
+        Some(serde_json::json!({ "items": items, "pages": count, "truncated": truncated })),
+        false,
+    )
+}
+
+/// The items a page contributes: the array at `items_path` (or the body when
+/// it's an array), otherwise the body as a single item.
+fn page_items(body: &serde_json::Value, items_path: Option<&str>) -> Result<Vec<serde_json::Value>, String> {
+    match items_path.map(strip_json_path) {
+        Some(path) => match extract_path(body, path) {
+            Some(serde_json::Value::Array(items)) => Ok(items.clone()),
+            _ => Err(format!("items_path '{}' is not an array in the response", path)),
+        },
+        None => match body {
+            serde_json::Value::Array(items) => Ok(items.clone()),
+            serde_json::Value::Null => Ok(Vec::new()),
+            other => Ok(vec![other.clone()]),
+        },
+    }
+}
+
+/// The next page to request, or None on the last page (missing, null or
+/// empty cursor/URL).
+fn next_page(cursor: &PageCursor, body: &serde_json::Value, next_link: Option<String>) -> Option<NextPage> {
+    let at = |path: &str| {
+        extract_path(body, strip_json_path(path))
+            .map(value_to_string)
+            .filter(|s| !s.is_empty() && s != "null")
+    };
+    match cursor {
+        PageCursor::Cursor { path, .. } => at(path).map(NextPage::Cursor),
+        PageCursor::NextUrl { path } => at(path).map(NextPage::Url),
+        PageCursor::LinkHeader => next_link.map(NextPage::Url),
+    }
+}
+
+/// Accept `$.meta.next` as well as `meta.next`.
+fn strip_json_path(path: &str) -> &str {
+    let path = path.trim();
+    path.strip_prefix("$.").or_else(|| path.strip_prefix('$')).unwrap_or(path)
+}
+
+/// The rel="next" target of a `Link` header, e.g.
+/// `<https://api.example.com/items?page=2>; rel="next", <...>; rel="last"`.
+fn parse_next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let target = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+        parts
+            .any(|param| {
+                let param = param.trim();
+                param.strip_prefix("rel=").is_some_and(|rel| {
+                    rel.trim_matches('"').split_whitespace().any(|r| r.eq_ignore_ascii_case("next"))
+                })
+            })
+            .then(|| target.to_string())
+    })
+}
+
+/// Why a buffered response body couldn't be read.
+#[derive(Debug, PartialEq)]
+enum BodyReadError {
+    /// The body exceeded the limit; `read` is how far we got (or the declared length)
+    TooLarge { read: u64, limit: u64 },
+    Stream(String),
+}
+
+/// Read the whole body, giving up as soon as it grows past `limit` bytes.
+/// A declared Content-Length over the limit is refused without reading anything.
+async fn read_body_limited(resp: reqwest::Response, limit: u64) -> Result<Vec<u8>, BodyReadError> {
+    if let Some(len) = resp.content_length().filter(|&len| len > limit) {
+        return Err(BodyReadError::TooLarge { read: len, limit });
+    }
+    collect_limited(resp.bytes_stream(), limit).await
+}
+
+async fn collect_limited<S, B, E>(mut stream: S, limit: u64) -> Result<Vec<u8>, BodyReadError>
+where
+    S: futures_util::Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    use futures_util::StreamExt;
+
+    let mut buf = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| BodyReadError::Stream(e.to_string()))?;
+        let read = (buf.len() + chunk.as_ref().len()) as u64;
+        if read > limit {
+            return Err(BodyReadError::TooLarge { read, limit });
+        }
+        buf.extend_from_slice(chunk.as_ref());
+    }
+    Ok(buf)
+}
+
+/// Node result for a body that couldn't be read (413 when over the limit).
+async fn body_read_failed(
+    err: BodyReadError,
+    status: u16,
+    stream_ctx: Option<&StreamContext>,
+) -> (u16, Option<serde_json::Value>, bool) {
+    let (code, body) = match err {
+        BodyReadError::TooLarge { read, limit } => (
+            413,
+            serde_json::json!({
+                "error": format!("Response body exceeds limit of {} bytes (read {})", limit, read),
+                "too_large": true,
+                "status": status
+            }),
+        ),
+        BodyReadError::Stream(e) => (
+            500,
+            serde_json::json!({ "error": format!("Failed to read response body: {}", e), "status": status }),
+        ),
+    };
+    if let Some(ctx) = stream_ctx {
+        ctx.error(body["error"].as_str().unwrap_or_default()).await;
+    }
+    (code, Some(body), false)
+}
+
+/// The `(name, value)` header for the node's credentials.
+fn auth_header(auth: &HttpAuth) -> Result<(String, String), String> {
+    match auth {
+        HttpAuth::Basic { username, password } => {
+            let credentials = format!("{}:{}", username, password);
+            Ok(("Authorization".to_string(), format!("Basic {}", BASE64.encode(credentials))))
+        }
+        HttpAuth::Bearer { token } => Ok(("Authorization".to_string(), format!("Bearer {}", token))),
+        HttpAuth::ApiKey { header, value } => {
+            if header.trim().is_empty() {
+                return Err("Invalid auth: API key header name is empty".to_string());
+            }
+            Ok((header.trim().to_string(), value.clone()))
+        }
+    }
+}
+
+/// Query pairs in a stable order, so the same node always builds the same URL.
+fn sorted_query(query: &HashMap<String, String>) -> Vec<(&str, &str)> {
+    let mut pairs: Vec<(&str, &str)> = query.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    pairs.sort();
+    pairs
+}
+
+/// Violations of `schema` by a response, or None if it conforms.
+/// A body that isn't JSON at all is itself a violation.
+fn schema_violations(schema: &serde_json::Value, body: Option<&serde_json::Value>) -> Option<Vec<String>> {
+    let violations = match body {
+        Some(json) => json_schema::validate(schema, json),
+        None => vec!["/: response body is not valid JSON".to_string()],
+    };
+    (!violations.is_empty()).then_some(violations)
+}
+
+/// Whether a response Content-Type should be treated as binary.
+pub(crate) fn is_binary_content_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    mime.starts_with("image/")
+        || mime.starts_with("audio/")
+        || mime.starts_with("video/")
+        || matches!(
+            mime.as_str(),
+            "application/pdf" | "application/octet-stream" | "application/zip" | "application/gzip"
+        )
+}
+
+/// Wrap binary bytes as `{"_binary": true, "content_type", "size", "base64"}`.
+/// Bodies over `max_bytes` are reported without the payload.
+pub(crate) fn binary_body(content_type: &str, bytes: &[u8], max_bytes: usize) -> serde_json::Value {
+    if bytes.len() > max_bytes {
+        return binary_too_large(content_type, bytes.len(), max_bytes);
+    }
+
+    serde_json::json!({
+        "_binary": true,
+        "content_type": content_type,
+        "size": bytes.len(),
+        "base64": BASE64.encode(bytes)
+    })
+}
+
+/// Record a warning when a binary body was returned without its payload.
+fn warn_if_payload_dropped(body: &serde_json::Value, warnings: &Warnings) {
+    if body["too_large"] == true {
+        warnings.push(
+            "response_truncated",
+            format!(
+                "Binary response of {} bytes exceeds HTTP_MAX_BINARY_BYTES; payload omitted",
+                body["size"]
+            ),
+        );
+    }
+}
+
+fn binary_too_large(content_type: &str, size: usize, max_bytes: usize) -> serde_json::Value {
+    serde_json::json!({
+        "_binary": true,
+        "content_type": content_type,
+        "size": size,
+        "base64": null,
+        "too_large": true,
+        "error": format!("Binary body of {} bytes exceeds limit of {} bytes", size, max_bytes)
+    })
+}
+
+/// Forward the response body chunk-by-chunk via `StreamContext::data`.
+///
+/// Only a summary is returned so memory stays bounded regardless of body size.
+async fn stream_response(
+    resp: reqwest::Response,
+    stream_ctx: Option<&StreamContext>,
+    cancel_token: &CancellationToken,
+    network_ms: u64,
+) -> (u16, Option<serde_json::Value>, bool) {
+    use futures_util::StreamExt;
+
+    let status = resp.status().as_u16();
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    let body_start = std::time::Instant::now();
+    let mut bytes: u64 = 0;
+    let mut chunks: u64 = 0;
+    // Multi-byte characters can straddle chunks; hold back the incomplete tail
+    let mut pending: Vec<u8> = Vec::new();
+
+    let mut stream = resp.bytes_stream();
+    loop {
+        // Wait for the next chunk or cancellation, whichever comes first, so
+        // a server that stalls mid-body can't hold a cancelled node
+        let chunk_result = tokio::select! {
+            _ = cancel_token.cancelled() => {
+                if let Some(ctx) = stream_ctx {
+                    ctx.progress("Cancelled").await;
+                }
+                return (
+                    499,
+                    Some(serde_json::json!({ "error": "Request cancelled", "bytes": bytes })),
+                    true,
+                );
+            }
+            next = stream.next() => match next {
+                Some(chunk_result) => chunk_result,
+                None => break,
+            },
+        };
+
+        let chunk = match chunk_result {
+            Ok(c) => c,
+            Err(e) => {
+                if let Some(ctx) = stream_ctx {
+                    ctx.error(&format!("Stream error: {}", e)).await;
+                }
+                return (
+                    500,
+                    Some(serde_json::json!({ "error": format!("Stream error: {}", e), "bytes": bytes })),
+                    false,
+                );
+            }
+        };
+
+        bytes += chunk.len() as u64;
+        chunks += 1;
+
+        pending.extend_from_slice(&chunk);
+        let text = take_utf8_prefix(&mut pending);
+        if !text.is_empty()
+            && let Some(ctx) = stream_ctx
+        {
+            ctx.data(&text).await;
+        }
+    }
+
+    if let Some(ctx) = stream_ctx {
+        if !pending.is_empty() {
+            ctx.data(&String::from_utf8_lossy(&pending)).await;
+        }
+        ctx.complete().await;
+    }
+
+    let body_ms = body_start.elapsed().as_millis() as u64;
+
+    (
+        status,
+        Some(serde_json::json!({
+            "bytes": bytes,
+            "chunks": chunks,
+            "content_type": content_type,
+            "streamed": true,
+            "_timing": {
+                "network_ms": network_ms,
+                "body_read_ms": body_ms,
+                "total_ms": network_ms + body_ms
+            }
+        })),
+        false,
+    )
+}
+
+/// Remove and return the longest decodable prefix of `buf`, leaving an
+/// incomplete trailing UTF-8 sequence in place. Invalid bytes are replaced.
+fn take_utf8_prefix(buf: &mut Vec<u8>) -> String {
+    match std::str::from_utf8(buf) {
+        Ok(s) => {
+            let s = s.to_string();
+            buf.clear();
+            s
+        }
+        Err(e) if e.error_len().is_none() => {
+            let tail = buf.split_off(e.valid_up_to());
+            let s = String::from_utf8_lossy(buf).into_owned();
+            *buf = tail;
+            s
+        }
+        Err(_) => {
+            let s = String::from_utf8_lossy(buf).into_owned();
+            buf.clear();
+            s
+        }
+    }
+}
+
+/// Compress an already-serialized request body in place and set `Content-Encoding`.
+///
+/// Runs after `apply_body`, so it works with every in-memory encoding. Streaming
+/// bodies (multipart), missing bodies, and bodies below `min_bytes` are left as-is.
+/// Returns whether the body was compressed.
+fn compress_body(request: &mut reqwest::Request, compression: Compression, min_bytes: usize) -> bool {
+    let Some(bytes) = request.body().and_then(|b| b.as_bytes()) else {
+        return false;
+    };
+    if bytes.len() < min_bytes {
+        return false;
+    }
+
+    let compressed = compression::compress(bytes, compression);
+    *request.body_mut() = Some(compressed.into());
+    request.headers_mut().insert(
+        reqwest::header::CONTENT_ENCODING,
+        reqwest::header::HeaderValue::from_static(compression::content_encoding(compression)),
+    );
+    true
+}
+
+/// Record a warning when `compress` was set but the body is streamed
+/// (multipart or `body_source`), which goes out uncompressed.
+fn warn_if_compression_skipped(request: &reqwest::Request, warnings: &Warnings) {
+    if request.body().is_some_and(|b| b.as_bytes().is_none()) {
+        warnings.push(
+            "compression_skipped",
+            "Streamed request bodies (multipart, body_source) can't be compressed; sent uncompressed",
+        );
+    }
+}
+
+/// Render a JSON value as a plain string (strings unquoted, everything else as JSON).
+fn value_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Attach the body to the request using the configured encoding.
+///
+/// - `Json`: serialized as JSON
+/// - `Form`: a JSON object sent as urlencoded key/value pairs
+/// - `Multipart`: a JSON object where each value becomes a text part, or a file
+///   part when given as `{ "filename": "...", "content": "...", "content_type": "..." }`
+/// - `Raw`: the string sent as-is with `content_type` (default "text/plain")
+fn apply_body(
+    req: reqwest::RequestBuilder,
+    body: serde_json::Value,
+    encoding: HttpBodyEncoding,
+    content_type: Option<&str>,
+) -> Result<reqwest::RequestBuilder, String> {
+    match encoding {
+        HttpBodyEncoding::Json => {
+            let req = match content_type {
+                Some(ct) => req.header(reqwest::header::CONTENT_TYPE, ct),
+                None => req,
+            };
+            Ok(req.json(&body))
+        }
+        HttpBodyEncoding::Form => {
+            let obj = body
+                .as_object()
+                .ok_or("Form body must be a JSON object")?;
+            let pairs: Vec<(String, String)> = obj
+                .iter()
+                .map(|(k, v)| (k.clone(), value_to_string(v)))
+                .collect();
+            Ok(req.form(&pairs))
+        }
+        HttpBodyEncoding::Multipart => {
+            let obj = body
+                .as_object()
+                .ok_or("Multipart body must be a JSON object")?;
+            let mut form = reqwest::multipart::Form::new();
+            for (name, value) in obj {
+                let file_name = value.get("filename").and_then(|f| f.as_str());
+                form = match file_name {
+                    Some(file_name) => {
+                        let content = value.get("content").map(value_to_string).unwrap_or_default();
+                        let mut part = reqwest::multipart::Part::text(content)
+                            .file_name(file_name.to_string());
+                        if let Some(ct) = value.get("content_type").and_then(|c| c.as_str()) {
+                            part = part
+                                .mime_str(ct)
+                                .map_err(|e| format!("Invalid content_type for part '{}': {}", name, e))?;
+                        }
+                        form.part(name.clone(), part)
+                    }
+                    None => form.text(name.clone(), value_to_string(value)),
+                };
+            }
+            Ok(req.multipart(form))
+        }
+        HttpBodyEncoding::Raw => Ok(req
+            .header(reqwest::header::CONTENT_TYPE, content_type.unwrap_or("text/plain"))
+            .body(value_to_string(&body))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_errors_retry_in_node() {
+        assert!(retries_transport_error(true, false, &HttpMethod::POST));
+        assert!(retries_transport_error(false, true, &HttpMethod::GET));
+        assert!(retries_transport_error(false, true, &HttpMethod::PUT));
+        // A timed out POST may have been processed; leave it to the job retry
+        assert!(!retries_transport_error(false, true, &HttpMethod::POST));
+        assert!(!retries_transport_error(false, false, &HttpMethod::GET));
+
+        assert_eq!(connect_retry_backoff(1), Duration::from_millis(200));
+        assert_eq!(connect_retry_backoff(3), Duration::from_millis(800));
+        assert_eq!(connect_retry_backoff(50), Duration::from_millis(3200));
+    }
+
+    #[test]
+    fn test_binary_response_is_base64_wrapped() {
+        assert!(is_binary_content_type("image/png"));
+        assert!(is_binary_content_type("application/pdf"));
+        assert!(is_binary_content_type("Application/Octet-Stream; charset=binary"));
+
+        let bytes = [0x89, b'P', b'N', b'G', 0xff, 0x00];
+        let body = binary_body("image/png", &bytes, 1024);
+        assert_eq!(body["_binary"], true);
+        assert_eq!(body["content_type"], "image/png");
+        assert_eq!(body["size"], 6);
+        assert_eq!(BASE64.decode(body["base64"].as_str().unwrap()).unwrap(), bytes);
+
+        let too_big = binary_body("image/png", &bytes, 4);
+        assert_eq!(too_big["too_large"], true);
+        assert!(too_big["base64"].is_null());
+    }
+
+    #[test]
+    fn test_oversized_binary_response_warns() {
+        let warnings = Warnings::default();
+        warn_if_payload_dropped(&binary_body("image/png", &[0u8; 64], 1024), &warnings);
+        assert!(warnings.take().is_empty());
+
+        let body = binary_body("image/png", &[0u8; 64], 16);
+        warn_if_payload_dropped(&body, &warnings);
+        let taken = warnings.take();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].code, "response_truncated");
+        assert!(taken[0].message.contains("64 bytes"));
+    }
+
+    #[test]
+    fn test_schema_violations() {
+        let schema = serde_json::json!({"type": "object", "required": ["id"]});
+        assert_eq!(schema_violations(&schema, Some(&serde_json::json!({"id": 1}))), None);
+        assert_eq!(
+            schema_violations(&schema, Some(&serde_json::json!({}))),
+            Some(vec!["/: missing required property 'id'".to_string()])
+        );
+        assert!(schema_violations(&schema, None).unwrap()[0].contains("not valid JSON"));
+        // 422 is a contract failure, not something a retry fixes
+        assert!(!crate::retry::is_retryable_error(422));
+    }
+
+    #[test]
+    fn test_auth_headers() {
+        let basic = HttpAuth::Basic { username: "ada".into(), password: "pw".into() };
+        assert_eq!(auth_header(&basic).unwrap(), ("Authorization".to_string(), "Basic YWRhOnB3".to_string()));
+
+        let bearer = HttpAuth::Bearer { token: "tok".into() };
+        assert_eq!(auth_header(&bearer).unwrap().1, "Bearer tok");
+
+        let api_key = HttpAuth::ApiKey { header: "X-API-Key".into(), value: "k".into() };
+        assert_eq!(auth_header(&api_key).unwrap(), ("X-API-Key".to_string(), "k".to_string()));
+
+        let unnamed = HttpAuth::ApiKey { header: " ".into(), value: "k".into() };
+        assert!(auth_header(&unnamed).is_err());
+
+        let parsed: HttpAuth = serde_json::from_value(serde_json::json!({
+            "type": "api_key", "value": {"header": "X-Key", "value": "v"}
+        }))
+        .unwrap();
+        assert_eq!(parsed, HttpAuth::ApiKey { header: "X-Key".into(), value: "v".into() });
+    }
+
+    #[tokio::test]
+    async fn test_body_read_stops_past_limit() {
+        let chunks = || {
+            futures_util::stream::iter(vec![
+                Ok::<_, std::io::Error>(vec![b'a'; 4]),
+                Ok(vec![b'b'; 4]),
+                Ok(vec![b'c'; 4]),
+            ])
+        };
+        assert_eq!(collect_limited(chunks(), 12).await.unwrap().len(), 12);
+        assert_eq!(
+            collect_limited(chunks(), 6).await,
+            Err(BodyReadError::TooLarge { read: 8, limit: 6 })
+        );
+        // 413 is the response being too big, not something a retry fixes
+        assert!(!crate::retry::is_retryable_error(413));
+    }
+
+    #[tokio::test]
+    async fn test_stream_response_cancels_while_the_server_stalls() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Sends the headers and one chunk, then never sends another
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n")
+                .await;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        let resp = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+        let cancel_token = CancellationToken::new();
+        let canceller = cancel_token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        let (status, body, cancelled) =
+            tokio::time::timeout(Duration::from_secs(5), stream_response(resp, None, &cancel_token, 0))
+                .await
+                .expect("cancellation must not wait for the next chunk");
+        assert_eq!((status, cancelled), (499, true));
+        assert_eq!(body.unwrap()["bytes"], 5);
+    }
+
+    #[test]
+    fn test_parse_next_link() {
+        let header = r#"<https://api.example.com/items?page=3>; rel="next", <https://api.example.com/items?page=9>; rel="last""#;
+        assert_eq!(parse_next_link(header).as_deref(), Some("https://api.example.com/items?page=3"));
+        assert_eq!(parse_next_link(r#"</items?page=2>; rel="prev next""#).as_deref(), Some("/items?page=2"));
+        assert_eq!(parse_next_link(r#"<https://api.example.com/items?page=1>; rel="prev""#), None);
+    }
+
+    #[test]
+    fn test_next_page_and_items() {
+        let body = serde_json::json!({"data": [{"id": 1}, {"id": 2}], "meta": {"next_cursor": "abc", "next": null}});
+        let cursor = PageCursor::Cursor { path: "$.meta.next_cursor".into(), param: "cursor".into() };
+        assert_eq!(next_page(&cursor, &body, None), Some(NextPage::Cursor("abc".into())));
+        assert_eq!(next_page(&PageCursor::NextUrl { path: "meta.next".into() }, &body, None), None);
+        assert_eq!(
+            next_page(&PageCursor::LinkHeader, &body, Some("/p2".into())),
+            Some(NextPage::Url("/p2".into()))
+        );
+
+        assert_eq!(page_items(&body, Some("data")).unwrap().len(), 2);
+        assert!(page_items(&body, Some("meta")).is_err());
+        assert_eq!(page_items(&serde_json::json!([1, 2, 3]), None).unwrap().len(), 3);
+
+        let config: PaginateConfig = serde_json::from_value(serde_json::json!({"next": {"type": "link_header"}})).unwrap();
+        assert_eq!(config.max_pages, 10);
+        assert_eq!(config.next, PageCursor::LinkHeader);
+    }
+
+    #[test]
+    fn test_text_response_is_not_binary() {
+        assert!(!is_binary_content_type("application/json"));
+        assert!(!is_binary_content_type("text/html; charset=utf-8"));
+        assert!(!is_binary_content_type("application/xml"));
+    }
+
+    #[test]
+    fn test_utf8_split_across_chunks() {
+        let euro = "€".as_bytes(); // 3 bytes
+        let mut pending = b"price: ".to_vec();
+        pending.extend_from_slice(&euro[..1]);
+
+        assert_eq!(take_utf8_prefix(&mut pending), "price: ");
+        assert_eq!(pending, &euro[..1]);
+
+        pending.extend_from_slice(&euro[1..]);
+        assert_eq!(take_utf8_prefix(&mut pending), "€");
+        assert!(pending.is_empty());
+    }
+
+    fn build(body: serde_json::Value, encoding: HttpBodyEncoding, ct: Option<&str>) -> reqwest::Request {
+        let req = reqwest::Client::new().post("http://localhost/test");
+        apply_body(req, body, encoding, ct).unwrap().build().unwrap()
+    }
+
+    fn body_bytes(req: &reqwest::Request) -> String {
+        String::from_utf8(req.body().unwrap().as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_json_body_is_default_encoding() {
+        assert_eq!(HttpBodyEncoding::default(), HttpBodyEncoding::Json);
+        let req = build(serde_json::json!({"a": 1}), HttpBodyEncoding::Json, None);
+        assert_eq!(req.headers()["content-type"], "application/json");
+        assert_eq!(body_bytes(&req), r#"{"a":1}"#);
+    }
+
+    #[test]
+    fn test_query_is_encoded_and_merged_with_the_url() {
+        let query = HashMap::from([
+            ("q".to_string(), "a b&c".to_string()),
+            ("page".to_string(), "2".to_string()),
+        ]);
+        let req = reqwest::Client::new()
+            .get("http://localhost/search?lang=en")
+            .query(&sorted_query(&query))
+            .build()
+            .unwrap();
+        assert_eq!(req.url().query(), Some("lang=en&page=2&q=a+b%26c"));
+    }
+
+    #[test]
+    fn test_form_body() {
+        let req = build(serde_json::json!({"name": "a b", "n": 2}), HttpBodyEncoding::Form, None);
+        assert_eq!(req.headers()["content-type"], "application/x-www-form-urlencoded");
+        let body = body_bytes(&req);
+        assert!(body.contains("name=a+b"));
+        assert!(body.contains("n=2"));
+    }
+
+    #[test]
+    fn test_form_body_rejects_non_object() {
+        let req = reqwest::Client::new().post("http://localhost/test");
+        assert!(apply_body(req, serde_json::json!([1, 2]), HttpBodyEncoding::Form, None).is_err());
+    }
+
+    #[test]
+    fn test_raw_body() {
+        let req = build(serde_json::json!("<xml/>"), HttpBodyEncoding::Raw, Some("application/xml"));
+        assert_eq!(req.headers()["content-type"], "application/xml");
+        assert_eq!(body_bytes(&req), "<xml/>");
+    }
+
+    #[test]
+    fn test_compress_json_body_above_threshold() {
+        let rows: Vec<_> = (0..200).map(|i| serde_json::json!({"id": i, "status": "ok"})).collect();
+        let mut req = build(serde_json::json!(rows), HttpBodyEncoding::Json, None);
+        let original_len = req.body().unwrap().as_bytes().unwrap().len();
+
+        assert!(compress_body(&mut req, Compression::Gzip, 1024));
+        assert_eq!(req.headers()["content-encoding"], "gzip");
+        assert_eq!(req.headers()["content-type"], "application/json");
+        let compressed = req.body().unwrap().as_bytes().unwrap();
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+        assert!(compressed.len() < original_len);
+    }
+
+    #[test]
+    fn test_compress_skips_small_and_missing_bodies() {
+        let mut small = build(serde_json::json!({"a": 1}), HttpBodyEncoding::Json, None);
+        assert!(!compress_body(&mut small, Compression::Deflate, 1024));
+        assert!(small.headers().get("content-encoding").is_none());
+
+        let mut empty = reqwest::Client::new().get("http://localhost/test").build().unwrap();
+        assert!(!compress_body(&mut empty, Compression::Gzip, 0));
+
+        // Only the small body was a deliberate skip; it warns about neither
+        let warnings = Warnings::default();
+        warn_if_compression_skipped(&small, &warnings);
+        warn_if_compression_skipped(&empty, &warnings);
+        assert!(warnings.take().is_empty());
+    }
+
+    #[test]
+    fn test_multipart_body_is_sent_uncompressed_with_warning() {
+        let mut req = build(
+            serde_json::json!({ "notes": "x".repeat(4096) }),
+            HttpBodyEncoding::Multipart,
+            None,
+        );
+        assert!(!compress_body(&mut req, Compression::Gzip, 0));
+        assert!(req.headers().get("content-encoding").is_none());
+
+        let warnings = Warnings::default();
+        warn_if_compression_skipped(&req, &warnings);
+        let taken = warnings.take();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].code, "compression_skipped");
+    }
+
+    #[test]
+    fn test_multipart_body() {
+        let req = build(
+            serde_json::json!({
+                "field": "value",
+                "file": { "filename": "a.txt", "content": "hello", "content_type": "text/plain" }
+            }),
+            HttpBodyEncoding::Multipart,
+            None,
+        );
+        let ct = req.headers()["content-type"].to_str().unwrap();
+        assert!(ct.starts_with("multipart/form-data; boundary="));
+    }
+
+    #[tokio::test]
+    async fn test_multipart_parts_are_encoded_on_the_wire() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The form body is a stream, so read what actually goes out
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(socket.read_u8().await.unwrap());
+            }
+            let head = String::from_utf8(head).unwrap();
+            let len: usize = head
+                .lines()
+                .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length: ").map(|v| v.parse().unwrap()))
+                .expect("text parts have a known length");
+            let mut body = vec![0u8; len];
+            socket.read_exact(&mut body).await.unwrap();
+            socket.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await.unwrap();
+            String::from_utf8(body).unwrap()
+        });
+
+        let client = reqwest::Client::new();
+        let req = apply_body(
+            client.post(format!("http://{}/upload", addr)),
+            serde_json::json!({
+                "count": 3,
+                "field": "value",
+                "file": { "filename": "a.txt", "content": "hello", "content_type": "text/plain" }
+            }),
+            HttpBodyEncoding::Multipart,
+            None,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        let ct = req.headers()["content-type"].to_str().unwrap().to_string();
+        let boundary = ct.strip_prefix("multipart/form-data; boundary=").unwrap();
+        client.execute(req).await.unwrap();
+
+        let expected = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"count\"\r\n\r\n3\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nhello\r\n\
+             --{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(server.await.unwrap(), expected);
+    }
+}
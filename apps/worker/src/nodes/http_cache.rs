@@ -0,0 +1,136 @@
+//! Response cache for HTTP GET nodes with `cache_ttl_ms`.
+//!
+//! Successful responses are kept in Redis for the node's TTL, keyed by a
+//! SHA-256 of everything that shapes the request (URL, query, headers, auth,
+//! pagination), so credentials never appear in the key and two nodes only
+//! share an entry when they'd send the same request. Responses marked
+//! `Cache-Control: no-store` aren't kept. The cache is best-effort: a Redis
+//! error just means a miss.
+
+use crate::types::{HttpMethod, HttpNodeData};
+use redis::AsyncCommands;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Redis key prefix for cached responses
+const CACHE_KEY_PREFIX: &str = "swiftgrid_http_cache:";
+
+/// The cache key and TTL for a node, or None if its response isn't cacheable
+/// (not a GET, no TTL, or a streamed request or response body).
+pub fn cache_key(data: &HttpNodeData) -> Option<(String, u64)> {
+    let ttl_ms = data.cache_ttl_ms.filter(|&ttl| ttl > 0)?;
+    if !matches!(data.method, HttpMethod::GET) || data.stream_body || data.body_source.is_some() {
+        return None;
+    }
+
+    let mut headers: Vec<(String, &str)> = data
+        .headers
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.to_ascii_lowercase(), v.as_str()))
+        .collect();
+    headers.sort();
+    let mut query: Vec<(&str, &str)> = data.query.iter().flatten().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    query.sort();
+
+    let shape = json!({
+        "url": data.url,
+        "query": query,
+        "headers": headers,
+        "auth": data.auth,
+        "paginate": data.paginate,
+    });
+    let digest = Sha256::digest(shape.to_string().as_bytes());
+    Some((format!("{}{:x}", CACHE_KEY_PREFIX, digest), ttl_ms))
+}
+
+/// Whether a response's `Cache-Control` forbids storing it.
+pub fn is_no_store(cache_control: &str) -> bool {
+    cache_control
+        .split(',')
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+/// A cached (status, body), marked `_cached` when the body is an object.
+pub async fn get(redis: &redis::Client, key: &str) -> Option<(u16, Option<Value>)> {
+    let mut con = redis.get_multiplexed_async_connection().await.ok()?;
+    let cached: Option<String> = con
+        .get(key)
+        .await
+        .inspect_err(|e| tracing::debug!("HTTP cache read failed: {}", e))
+        .ok()?;
+    let entry: Value = serde_json::from_str(&cached?).ok()?;
+    let status = entry.get("status")?.as_u64()? as u16;
+    let mut body = entry.get("body").cloned().filter(|b| !b.is_null());
+    if let Some(obj) = body.as_mut().and_then(|b| b.as_object_mut()) {
+        obj.insert("_cached".to_string(), json!(true));
+    }
+    Some((status, body))
+}
+
+/// Store a response for `ttl_ms`. Its `_timing` is dropped, since a hit won't
+/// have made the request.
+pub async fn put(redis: &redis::Client, key: &str, ttl_ms: u64, status: u16, body: Option<&Value>) {
+    let mut body = body.cloned();
+    if let Some(obj) = body.as_mut().and_then(|b| b.as_object_mut()) {
+        obj.remove("_timing");
+    }
+    let entry = json!({ "status": status, "body": body }).to_string();
+
+    let stored: redis::RedisResult<()> = match redis.get_multiplexed_async_connection().await {
+        Ok(mut con) => con.pset_ex(key, entry, ttl_ms).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = stored {
+        tracing::debug!("HTTP cache write failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn node(method: &str, ttl: Option<u64>, token: &str) -> HttpNodeData {
+        serde_json::from_value(json!({
+            "url": "https://api.example.com/rates",
+            "method": method,
+            "headers": {"Authorization": format!("Bearer {}", token), "Accept": "application/json"},
+            "query": {"base": "EUR"},
+            "cache_ttl_ms": ttl
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_only_get_with_ttl_is_cached() {
+        assert!(cache_key(&node("GET", Some(60_000), "a")).is_some());
+        assert!(cache_key(&node("GET", None, "a")).is_none());
+        assert!(cache_key(&node("GET", Some(0), "a")).is_none());
+        assert!(cache_key(&node("POST", Some(60_000), "a")).is_none());
+    }
+
+    #[test]
+    fn test_key_depends_on_request_not_secrets_in_plain_text() {
+        let (key, ttl) = cache_key(&node("GET", Some(60_000), "s3cret")).unwrap();
+        assert_eq!(ttl, 60_000);
+        assert!(!key.contains("s3cret"));
+        assert_eq!(Some(key.clone()), cache_key(&node("GET", Some(5), "s3cret")).map(|(k, _)| k));
+        assert_ne!(Some(key), cache_key(&node("GET", Some(60_000), "other")).map(|(k, _)| k));
+
+        // Header name case and map order don't matter
+        let mut data = node("GET", Some(1), "a");
+        data.headers = Some(HashMap::from([
+            ("accept".to_string(), "application/json".to_string()),
+            ("authorization".to_string(), "Bearer a".to_string()),
+        ]));
+        assert_eq!(cache_key(&data).map(|(k, _)| k), cache_key(&node("GET", Some(1), "a")).map(|(k, _)| k));
+    }
+
+    #[test]
+    fn test_no_store() {
+        assert!(is_no_store("no-store"));
+        assert!(is_no_store("private, No-Store, max-age=0"));
+        assert!(!is_no_store("no-cache, max-age=60"));
+    }
+}
@@ -0,0 +1,196 @@
+//! Streamed request bodies for HTTP nodes with `body_source`.
+//!
+//! Large payloads built upstream are sent without being inlined in the node
+//! config or buffered in the worker: the body is read from its source a slice
+//! at a time while reqwest sends it.
+//! - `redis_key`: a Redis string, read with GETRANGE (sent with Content-Length)
+//! - `node_stream`: the `data`/`token` chunks an earlier node of the same run
+//!   streamed, read from `run_stream_chunks` in `chunk_index` order (sent chunked)
+//!
+//! A streamed body can't be replayed, so these requests skip the in-node
+//! connect retries (the job-level retry reopens the source) and can't be
+//! combined with `body` or `paginate`. A missing source fails with 404.
+
+use crate::node_error::NodeError;
+use crate::types::{BodySource, HttpNodeData};
+use bytes::Bytes;
+use futures_util::stream;
+use redis::AsyncCommands;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Bytes read from Redis per GETRANGE
+const REDIS_SLICE_BYTES: u64 = 256 * 1024;
+
+/// Chunk rows read from PostgreSQL per query
+const CHUNK_PAGE_ROWS: i64 = 256;
+
+/// A request body read from its source as it's sent.
+pub struct StreamedBody {
+    pub body: reqwest::Body,
+    /// Total size, when the source knows it up front
+    pub len: Option<u64>,
+}
+
+/// Reject settings that can't go with a streamed body.
+pub fn validate(data: &HttpNodeData) -> Result<(), String> {
+    if data.body_source.is_none() {
+        return Ok(());
+    }
+    if data.body.is_some() {
+        return Err("Set either body or body_source, not both".to_string());
+    }
+    if data.paginate.is_some() {
+        return Err("body_source can't be used with paginate (the body can only be sent once)".to_string());
+    }
+    Ok(())
+}
+
+/// Open the node's body source, if it has one.
+pub async fn open(
+    data: &HttpNodeData,
+    redis: &redis::Client,
+    pool: &PgPool,
+    run_id: Option<Uuid>,
+) -> Result<Option<StreamedBody>, NodeError> {
+    validate(data).map_err(|e| NodeError::permanent(400, e))?;
+    match &data.body_source {
+        None => Ok(None),
+        Some(BodySource::RedisKey(key)) => open_redis(redis, key.clone()).await.map(Some),
+        Some(BodySource::NodeStream(node_id)) => {
+            let run_id = run_id.ok_or_else(|| NodeError::permanent(400, "node_stream body sources need a run"))?;
+            open_node_stream(pool.clone(), run_id, node_id.clone()).await.map(Some)
+        }
+    }
+}
+
+async fn open_redis(redis: &redis::Client, key: String) -> Result<StreamedBody, NodeError> {
+    let redis_error = |e: redis::RedisError| NodeError::Transient(format!("Body source redis key {}: {}", key, e));
+    let mut con = redis.get_multiplexed_async_connection().await.map_err(redis_error)?;
+    let exists: bool = con.exists(&key).await.map_err(redis_error)?;
+    if !exists {
+        return Err(NodeError::permanent(404, format!("Body source redis key {} not found", key)));
+    }
+    let len: u64 = con
+        .strlen(&key)
+        .await
+        .map_err(|e| NodeError::permanent(400, format!("Body source redis key {} is not a string: {}", key, e)))?;
+
+    let slices = stream::try_unfold((con, 0u64), move |(mut con, offset)| {
+        let key = key.clone();
+        async move {
+            let Some((from, to)) = next_slice(offset, len) else {
+                return Ok(None);
+            };
+            let bytes: Vec<u8> = con.getrange(&key, from, to).await.map_err(std::io::Error::other)?;
+            if bytes.is_empty() {
+                return Err(std::io::Error::other(format!("Redis key {} shrank while it was being sent", key)));
+            }
+            let next = offset + bytes.len() as u64;
+            Ok(Some((Bytes::from(bytes), (con, next))))
+        }
+    });
+    Ok(StreamedBody { body: reqwest::Body::wrap_stream(slices), len: Some(len) })
+}
+
+/// Inclusive GETRANGE bounds of the slice starting at `offset`, or None once
+/// `len` bytes have been read.
+fn next_slice(offset: u64, len: u64) -> Option<(isize, isize)> {
+    if offset >= len {
+        return None;
+    }
+    let end = (offset + REDIS_SLICE_BYTES).min(len) - 1;
+    Some((offset as isize, end as isize))
+}
+
+async fn open_node_stream(pool: PgPool, run_id: Uuid, node_id: String) -> Result<StreamedBody, NodeError> {
+    let context = format!("Body source node {}", node_id);
+    let first = chunk_page(&pool, run_id, &node_id, -1).await.map_err(|e| NodeError::from_sqlx(&context, &e))?;
+    if first.is_empty() {
+        return Err(NodeError::permanent(
+            404,
+            format!("Node {} has no streamed output in this run", node_id),
+        ));
+    }
+
+    // The first page is already in hand; later pages are read as it's sent
+    let pages = stream::try_unfold(Some(first), move |page| {
+        let pool = pool.clone();
+        let node_id = node_id.clone();
+        async move {
+            let Some(page) = page else {
+                return Ok(None);
+            };
+            let Some(&(last_index, _)) = page.last() else {
+                return Ok(None);
+            };
+            let next = if (page.len() as i64) < CHUNK_PAGE_ROWS {
+                None
+            } else {
+                let rows = chunk_page(&pool, run_id, &node_id, last_index).await.map_err(std::io::Error::other)?;
+                (!rows.is_empty()).then_some(rows)
+            };
+            let content: String = page.into_iter().map(|(_, content)| content).collect();
+            Ok::<_, std::io::Error>(Some((Bytes::from(content), next)))
+        }
+    });
+    Ok(StreamedBody { body: reqwest::Body::wrap_stream(pages), len: None })
+}
+
+/// Output chunks of `node_id` after `after_index`, oldest first.
+async fn chunk_page(pool: &PgPool, run_id: Uuid, node_id: &str, after_index: i32) -> Result<Vec<(i32, String)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        SELECT chunk_index, content FROM run_stream_chunks
+        WHERE run_id = $1 AND node_id = $2 AND chunk_type IN ('data', 'token') AND chunk_index > $3
+        ORDER BY chunk_index
+        LIMIT $4
+        "#,
+    )
+    .bind(run_id)
+    .bind(node_id)
+    .bind(after_index)
+    .bind(CHUNK_PAGE_ROWS)
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn node(extra: serde_json::Value) -> HttpNodeData {
+        let mut data = json!({ "url": "https://example.com/upload", "method": "PUT" });
+        data.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(data).unwrap()
+    }
+
+    #[test]
+    fn test_body_source_deserializes() {
+        let data = node(json!({ "body_source": { "type": "redis_key", "value": "export:42" } }));
+        assert_eq!(data.body_source, Some(BodySource::RedisKey("export:42".to_string())));
+        let data = node(json!({ "body_source": { "type": "node_stream", "value": "build_csv" } }));
+        assert_eq!(data.body_source, Some(BodySource::NodeStream("build_csv".to_string())));
+    }
+
+    #[test]
+    fn test_validate_rejects_inline_body_and_paginate() {
+        let source = json!({ "type": "redis_key", "value": "k" });
+        assert!(validate(&node(json!({ "body_source": source }))).is_ok());
+        assert!(validate(&node(json!({ "body": { "a": 1 } }))).is_ok());
+        assert!(validate(&node(json!({ "body_source": source, "body": "x" }))).is_err());
+        let paginated = node(json!({ "body_source": source, "paginate": { "next": { "type": "link_header" } } }));
+        assert!(validate(&paginated).is_err());
+    }
+
+    #[test]
+    fn test_next_slice_covers_value_once() {
+        assert_eq!(next_slice(0, 0), None);
+        assert_eq!(next_slice(0, 10), Some((0, 9)));
+        let len = REDIS_SLICE_BYTES * 2 + 5;
+        assert_eq!(next_slice(0, len), Some((0, REDIS_SLICE_BYTES as isize - 1)));
+        assert_eq!(next_slice(REDIS_SLICE_BYTES * 2, len), Some((REDIS_SLICE_BYTES as isize * 2, len as isize - 1)));
+        assert_eq!(next_slice(len, len), None);
+    }
+}
@@ -1,50 +1,512 @@
 //! LLM (Large Language Model) node execution.
 //!
-//! Supports any OpenAI-compatible API including OpenAI, Groq, Together, and Ollama.
+//! Supports any OpenAI-compatible API including OpenAI, Groq, Together, and Ollama,
+//! and Anthropic's Messages API (`api_style: anthropic`). Results have the same
+//! shape for both (`content`, `usage`, `tool_calls`).
 //! Includes cancellation support for streaming responses.
+//!
+//! With `max_context_tokens`, conversations that wouldn't fit are shortened
+//! before sending (see `LlmTruncation`) and the result reports what was cut.
+//!
+//! Rate limits: a 429 (or 503) reports the provider's reset (`retry-after`,
+//! `x-ratelimit-reset-*`, `anthropic-ratelimit-*-reset`) as `retry_after_ms`,
+//! which the retry path waits for. Until then, further requests to that host
+//! with the same API key (`RateLimitHolds`) wait out short resets and fail
+//! fast with 429 on long ones, instead of spending attempts on certain
+//! rejections.
+//!
+//! `base_url` is user-controlled, so it goes through the SSRF policy like an
+//! HTTP node's URL (internal addresses, e.g. a local Ollama, need
+//! `HTTP_ALLOWED_HOSTS`).
 
+use crate::ssrf::{self, SsrfPolicy};
 use crate::streaming::StreamContext;
-use crate::types::LlmNodeData;
+use crate::token_budget::{self, BudgetConfig, TokenBudgets};
+use crate::types::{LlmApiStyle, LlmMessage, LlmNodeData, LlmTruncation};
+use crate::warnings::Warnings;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
+/// Default attempts after the first for LLM jobs (`LLM_MAX_RETRIES`)
+const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// `max_retries` for LLM jobs built by the worker (`LLM_MAX_RETRIES`)
+pub fn max_retries() -> u32 {
+    std::env::var("LLM_MAX_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_RETRIES)
+}
+
+/// Longest a request waits for its host's rate limit to reset; longer resets
+/// fail with 429 so the job is retried later instead of holding a slot
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+
+/// Rate-limit resets providers asked us to wait for, keyed by host and
+/// credential (limits are per API key, so one tenant's 429 must not stall
+/// another's requests to the same provider). Shared across jobs like
+/// `TokenBudgets`.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimitHolds {
+    until: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl RateLimitHolds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember that `key` was asked to wait `delay` (the later reset wins).
+    fn hold(&self, key: &str, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut held = self.until.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = held.entry(key.to_string()).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+
+    /// Time left on `key`'s hold, if any.
+    fn remaining(&self, key: &str) -> Option<Duration> {
+        let mut held = self.until.lock().unwrap_or_else(|e| e.into_inner());
+        let remaining = held.get(key)?.checked_duration_since(Instant::now()).filter(|d| !d.is_zero());
+        if remaining.is_none() {
+            held.remove(key);
+        }
+        remaining
+    }
+}
+
+/// Hold key for a host and credential; the key itself is hashed, never stored.
+fn hold_key(host: &str, api_key: &str) -> String {
+    let mut hasher = std::hash::DefaultHasher::new();
+    api_key.hash(&mut hasher);
+    format!("{}#{:016x}", host, hasher.finish())
+}
+
+/// How long a rate-limited response asks us to wait. An explicit
+/// `retry-after-ms` / `retry-after` wins; otherwise the reset of the window
+/// that is actually exhausted (its `*-remaining-*` header is `0`), else the
+/// soonest reset.
+fn rate_limit_delay(headers: &reqwest::header::HeaderMap, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Duration::try_from_secs_f64(ms.max(0.0) / 1000.0).ok();
+    }
+    if let Some(value) = header("retry-after") {
+        if let Ok(secs) = value.parse::<f64>() {
+            return Duration::try_from_secs_f64(secs.max(0.0)).ok();
+        }
+        if let Ok(at) = chrono::DateTime::parse_from_rfc2822(value) {
+            return Some((at.with_timezone(&chrono::Utc) - now).to_std().unwrap_or_default());
+        }
+    }
+
+    let resets: Vec<(&str, Duration)> = headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = name.as_str();
+            let window = name
+                .strip_prefix("x-ratelimit-reset")
+                .or_else(|| name.strip_prefix("anthropic-ratelimit-")?.strip_suffix("-reset"))?;
+            Some((window, parse_reset(value.to_str().ok()?.trim(), now)?))
+        })
+        .collect();
+    let exhausted = |window: &str| {
+        let remaining = match window.strip_prefix('-') {
+            // OpenAI: `x-ratelimit-reset-tokens` pairs with `x-ratelimit-remaining-tokens`
+            Some(suffix) => header(&format!("x-ratelimit-remaining-{}", suffix)),
+            None if window.is_empty() => header("x-ratelimit-remaining"),
+            // Anthropic: `anthropic-ratelimit-tokens-reset` pairs with `...-tokens-remaining`
+            None => header(&format!("anthropic-ratelimit-{}-remaining", window)),
+        };
+        remaining.and_then(|v| v.parse::<f64>().ok()) == Some(0.0)
+    };
+
+    resets
+        .iter()
+        .filter(|(window, _)| exhausted(window))
+        .map(|(_, delay)| *delay)
+        .max()
+        .or_else(|| resets.iter().map(|(_, delay)| *delay).min())
+}
+
+/// A window reset: an RFC 3339 time (Anthropic), seconds, or a duration
+/// like `6m0s` / `20ms` (OpenAI).
+fn parse_reset(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    if let Ok(at) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some((at.with_timezone(&chrono::Utc) - now).to_std().unwrap_or_default());
+    }
+    if let Ok(secs) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs.max(0.0)).ok();
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).filter(|&n| n > 0)?;
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        total += number
+            * match &rest[..unit_len] {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => return None,
+            };
+        rest = &rest[unit_len..];
+    }
+    Duration::try_from_secs_f64(total).ok().filter(|_| !value.is_empty())
+}
+
+/// How often a streaming response logs a NODE_PROGRESS milestone
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default token flush interval in milliseconds (0 = emit every token)
+const DEFAULT_TOKEN_FLUSH_MS: u64 = 0;
+
+/// Token flush interval for a node: its own setting, else `LLM_TOKEN_FLUSH_MS`.
+fn token_flush_interval(data: &LlmNodeData) -> Duration {
+    let ms = data.token_flush_ms.map(u64::from).unwrap_or_else(|| {
+        std::env::var("LLM_TOKEN_FLUSH_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TOKEN_FLUSH_MS)
+    });
+    Duration::from_millis(ms)
+}
+
+/// Default characters per token for estimates
+const DEFAULT_CHARS_PER_TOKEN: usize = 4;
+
+/// Characters per token used by every estimate (`LLM_CHARS_PER_TOKEN`, min 1)
+fn chars_per_token() -> usize {
+    std::env::var("LLM_CHARS_PER_TOKEN")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CHARS_PER_TOKEN)
+        .max(1)
+}
+
+/// Rough prompt + completion token estimate for TPM budgeting (~4 chars per
+/// token plus per-message overhead). Providers count `max_tokens` against the
+/// limit up front, so it's included.
+fn estimate_tokens(data: &LlmNodeData) -> u64 {
+    prompt_tokens(&data.messages) + data.max_tokens.unwrap_or(0) as u64
+}
+
+fn text_tokens(text: &str) -> u64 {
+    text.len().div_ceil(chars_per_token()) as u64
+}
+
+fn message_tokens(m: &LlmMessage) -> u64 {
+    text_tokens(&m.role) + text_tokens(&m.content) + 4
+}
+
+fn prompt_tokens(messages: &[LlmMessage]) -> u64 {
+    messages.iter().map(message_tokens).sum()
+}
+
+/// `usage` for a result body. When the provider sent none, both sides are
+/// estimated from character counts and flagged `"estimated": true`.
+fn usage_json(reported: Option<(u32, u32)>, messages: &[LlmMessage], completion: &str, tool_calls: &[serde_json::Value]) -> serde_json::Value {
+    match reported {
+        Some((prompt, completion)) => serde_json::json!({
+            "prompt_tokens": prompt,
+            "completion_tokens": completion,
+            "total_tokens": prompt + completion
+        }),
+        None => {
+            let prompt = prompt_tokens(messages);
+            let completion = text_tokens(completion)
+                + tool_calls
+                    .iter()
+                    .map(|c| text_tokens(c["function"]["name"].as_str().unwrap_or("")) + text_tokens(c["function"]["arguments"].as_str().unwrap_or("")))
+                    .sum::<u64>();
+            serde_json::json!({
+                "prompt_tokens": prompt,
+                "completion_tokens": completion,
+                "total_tokens": prompt + completion,
+                "estimated": true
+            })
+        }
+    }
+}
+
+/// Token counts from a provider `usage` object (None when absent or null).
+fn reported_usage(usage: &serde_json::Value) -> Option<(u32, u32)> {
+    if !usage.is_object() {
+        return None;
+    }
+    Some((
+        usage["prompt_tokens"].as_u64().unwrap_or(0) as u32,
+        usage["completion_tokens"].as_u64().unwrap_or(0) as u32,
+    ))
+}
+
+/// Valid sampling temperature range for OpenAI-compatible APIs
+const TEMPERATURE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+
+/// Anthropic only accepts 0.0 - 1.0
+const ANTHROPIC_TEMPERATURE_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+
+/// `anthropic-version` header sent with Messages API requests
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires `max_tokens`; used when the node doesn't set one
+const DEFAULT_ANTHROPIC_MAX_TOKENS: u32 = 1024;
+
+/// Clamp `temperature` into range (providers reject out-of-range values with a 400).
+fn clamp_temperature(temperature: f32, range: std::ops::RangeInclusive<f32>, warnings: &Warnings) -> f32 {
+    let clamped = if temperature.is_nan() {
+        1.0
+    } else {
+        temperature.clamp(*range.start(), *range.end())
+    };
+    if clamped != temperature {
+        warnings.push("temperature_clamped", format!("temperature {} clamped to {}", temperature, clamped));
+    }
+    clamped
+}
+
+/// Characters of each dropped message kept in a summary note
+const SUMMARY_SNIPPET_CHARS: usize = 200;
+
+/// What `fit_to_context` removed.
+#[derive(Debug, PartialEq)]
+struct TruncationReport {
+    strategy: LlmTruncation,
+    dropped_messages: usize,
+    original_tokens: u64,
+    final_tokens: u64,
+}
+
+impl TruncationReport {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "strategy": self.strategy,
+            "dropped_messages": self.dropped_messages,
+            "original_tokens": self.original_tokens,
+            "final_tokens": self.final_tokens,
+        })
+    }
+}
+
+/// Shorten `messages` so their estimate fits `budget` prompt tokens.
+///
+/// The oldest non-system messages go first; system messages and the latest
+/// message always stay (even if they alone are over budget). `Summarize`
+/// replaces what was dropped with one system note holding the start of each
+/// dropped message, sized to the room that's left. That note is plain
+/// truncation; no model is asked to summarize anything.
+fn fit_to_context(
+    messages: Vec<LlmMessage>,
+    budget: u64,
+    strategy: LlmTruncation,
+) -> (Vec<LlmMessage>, Option<TruncationReport>) {
+    let original_tokens = prompt_tokens(&messages);
+    if original_tokens <= budget {
+        return (messages, None);
+    }
+
+    // Droppable: everything but system messages and the last message
+    let last = messages.len().saturating_sub(1);
+    let droppable: Vec<usize> = (0..last).filter(|&i| messages[i].role != "system").collect();
+
+    let mut dropped = 0;
+    let mut summary = None;
+    loop {
+        let kept = keep_messages(&messages, &droppable[..dropped], None);
+        let kept_tokens = prompt_tokens(&kept);
+        if strategy == LlmTruncation::Summarize && dropped > 0 {
+            let note = summary_note(&messages, &droppable[..dropped], budget.saturating_sub(kept_tokens));
+            if let Some(note) = note {
+                summary = Some(note);
+                break;
+            }
+        } else if kept_tokens <= budget {
+            break;
+        }
+        if dropped == droppable.len() {
+            break;
+        }
+        dropped += 1;
+    }
+
+    let kept = keep_messages(&messages, &droppable[..dropped], summary);
+    let report = TruncationReport {
+        strategy,
+        dropped_messages: dropped,
+        original_tokens,
+        final_tokens: prompt_tokens(&kept),
+    };
+    (kept, Some(report))
+}
+
+/// `messages` without `dropped`, with an optional note in place of the first.
+fn keep_messages(messages: &[LlmMessage], dropped: &[usize], note: Option<LlmMessage>) -> Vec<LlmMessage> {
+    let mut is_dropped = vec![false; messages.len()];
+    for &i in dropped {
+        is_dropped[i] = true;
+    }
+    let mut note = note;
+    let mut kept = Vec::with_capacity(messages.len() - dropped.len() + 1);
+    for (m, is_dropped) in messages.iter().zip(is_dropped) {
+        if is_dropped {
+            if let Some(note) = note.take() {
+                kept.push(note);
+            }
+        } else {
+            kept.push(m.clone());
+        }
+    }
+    kept
+}
+
+/// A system note quoting the start of each dropped message, cut to at most
+/// `room` tokens, or None if there isn't room for a meaningful one.
+fn summary_note(messages: &[LlmMessage], dropped: &[usize], room: u64) -> Option<LlmMessage> {
+    let header = format!("Summary of {} earlier message(s):", dropped.len());
+    let overhead = message_tokens(&LlmMessage { role: "system".to_string(), content: header.clone(), tool_calls: None, tool_call_id: None });
+    let max_chars = room.checked_sub(overhead)? as usize * chars_per_token();
+    if max_chars < 32 {
+        return None;
+    }
+
+    let mut content = header;
+    for &i in dropped {
+        let m = &messages[i];
+        let snippet: String = m.content.chars().take(SUMMARY_SNIPPET_CHARS).collect();
+        let ellipsis = if snippet.len() < m.content.len() { "..." } else { "" };
+        content.push_str(&format!("\n- {}: {}{}", m.role, snippet.trim(), ellipsis));
+    }
+    if content.len() > max_chars {
+        let mut end = max_chars;
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        content.truncate(end);
+    }
+    Some(LlmMessage { role: "system".to_string(), content, tool_calls: None, tool_call_id: None })
+}
+
+/// Coalesces token deltas into time-windowed batches, so fast models don't
+/// produce one chunk (SSE message + DB row) per token.
+struct TokenBatcher {
+    interval: Duration,
+    pending: String,
+    last_flush: Instant,
+}
+
+impl TokenBatcher {
+    fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            pending: String::new(),
+            last_flush: now,
+        }
+    }
+
+    /// Add a delta. Returns the accumulated batch once the window has elapsed
+    /// (immediately when the interval is zero).
+    fn push(&mut self, delta: &str, now: Instant) -> Option<String> {
+        self.pending.push_str(delta);
+        if now.duration_since(self.last_flush) >= self.interval {
+            self.last_flush = now;
+            self.flush()
+        } else {
+            None
+        }
+    }
+
+    /// Take whatever is still pending.
+    fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.pending))
+        }
+    }
+}
+
+/// Spaces out NODE_PROGRESS milestones: the first is due straight away,
+/// later ones once `interval` has passed since the last.
+struct MilestoneThrottle {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl MilestoneThrottle {
+    fn new(interval: Duration) -> Self {
+        Self { interval, last: None }
+    }
+
+    /// The milestone due at `now`, if any: "first_token", then "streaming".
+    fn due(&mut self, now: Instant) -> Option<&'static str> {
+        let milestone = match self.last {
+            None => "first_token",
+            Some(last) if now.duration_since(last) >= self.interval => "streaming",
+            Some(_) => return None,
+        };
+        self.last = Some(now);
+        Some(milestone)
+    }
+}
+
 /// Execute an LLM chat completion request with cancellation support.
 /// Returns (status_code, body, was_cancelled).
+#[allow(clippy::too_many_arguments)]
 pub async fn execute(
     client: reqwest::Client,
+    ssrf_policy: &SsrfPolicy,
     data: LlmNodeData,
     stream_ctx: Option<&StreamContext>,
     cancel_token: &CancellationToken,
+    budgets: &TokenBudgets,
+    holds: &RateLimitHolds,
+    warnings: &Warnings,
 ) -> (u16, Option<serde_json::Value>, bool) {
-    println!(
-        "  → LLM: model={}, messages={}, stream={}",
+    tracing::info!(
+        "LLM: model={}, messages={}, stream={}",
         data.model,
         data.messages.len(),
         data.stream
     );
 
-    // Build the request body
-    let mut request_body = serde_json::json!({
-        "model": data.model,
-        "messages": data.messages.iter().map(|m| serde_json::json!({
-            "role": m.role,
-            "content": m.content
-        })).collect::<Vec<_>>(),
-        "stream": data.stream
-    });
-
-    // Add optional parameters
-    if let Some(temp) = data.temperature {
-        request_body["temperature"] = serde_json::json!(temp);
-    }
-    if let Some(max) = data.max_tokens {
-        request_body["max_tokens"] = serde_json::json!(max);
+    // Shorten the conversation to fit the context window (minus the completion)
+    let mut data = data;
+    let mut truncation = None;
+    if let Some(context) = data.max_context_tokens {
+        let budget = (context as u64).saturating_sub(data.max_tokens.unwrap_or(0) as u64);
+        let strategy = data.truncation.unwrap_or_default();
+        let (messages, report) = fit_to_context(std::mem::take(&mut data.messages), budget, strategy);
+        data.messages = messages;
+        if let Some(report) = report {
+            tracing::info!(
+                "LLM: truncated {} message(s) ({} -> ~{} tokens)",
+                report.dropped_messages, report.original_tokens, report.final_tokens
+            );
+            warnings.push(
+                "context_truncated",
+                format!(
+                    "{} message(s) {} to fit max_context_tokens",
+                    report.dropped_messages,
+                    if report.strategy == LlmTruncation::Summarize { "condensed into a note" } else { "dropped" }
+                ),
+            );
+            truncation = Some(report.to_json());
+        }
     }
 
-    // Build the endpoint URL
-    let endpoint = format!(
-        "{}/chat/completions",
-        data.base_url.trim_end_matches('/')
-    );
+    let request_body = match build_request_body(&data, warnings) {
+        Ok(body) => body,
+        Err(e) => return (400, Some(serde_json::json!({ "error": e })), false),
+    };
+    let endpoint = endpoint(&data);
 
     // Stream progress
     if let Some(ctx) = stream_ctx {
@@ -57,6 +519,257 @@ pub async fn execute(
         return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
     }
 
+    // Refuse internal destinations before anything is sent
+    let checked = match reqwest::Url::parse(&endpoint) {
+        Ok(url) => ssrf_policy.check_url(&url).await,
+        Err(e) => return (400, Some(serde_json::json!({ "error": format!("Invalid base_url: {}", e) })), false),
+    };
+    if let Err(e) = checked {
+        if let Some(ctx) = stream_ctx {
+            ctx.error(&format!("Request blocked: {}", e)).await;
+        }
+        return (403, Some(ssrf::blocked_body(&e)), false);
+    }
+
+    let host = reqwest::Url::parse(&data.base_url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default();
+
+    // Don't send into a rate limit the provider already told us about
+    let hold = hold_key(&host, &data.api_key);
+    if let Some(remaining) = holds.remaining(&hold) {
+        if remaining > MAX_RATE_LIMIT_WAIT {
+            let retry_after_ms = remaining.as_millis() as u64;
+            return (
+                429,
+                Some(serde_json::json!({
+                    "error": format!("{} is rate limited for another {}ms", host, retry_after_ms),
+                    "retry_after_ms": retry_after_ms,
+                })),
+                false,
+            );
+        }
+        if let Some(ctx) = stream_ctx {
+            ctx.progress(&format!("Waiting {}ms for {}'s rate limit to reset...", remaining.as_millis(), host)).await;
+        }
+        tokio::select! {
+            biased;
+            _ = cancel_token.cancelled() => {
+                return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
+            }
+            _ = tokio::time::sleep(remaining) => {}
+        }
+    }
+
+    // Wait for room in the provider's TPM window (if one is configured)
+    let budget_config = BudgetConfig::from_env();
+    let reservation = match budget_config.limit_for(&host, &data.model) {
+        Some(limit) => {
+            let key = format!("{}/{}", host, data.model);
+            let tokens = estimate_tokens(&data);
+            match token_budget::acquire(budgets, &key, limit, tokens, budget_config.window, cancel_token).await {
+                Some(reservation) => Some(reservation),
+                None => return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true),
+            }
+        }
+        None => None,
+    };
+
+    let result = send_request(client, &endpoint, &request_body, &data, stream_ctx, cancel_token).await;
+
+    // Correct the estimate once the provider reports real usage
+    if let Some(reservation) = reservation
+        && let Some(total) = result.1.as_ref().and_then(|b| b["usage"]["total_tokens"].as_u64())
+        && total > 0
+    {
+        token_budget::settle(budgets, reservation, total).await;
+    }
+
+    let (status, mut body, was_cancelled) = result;
+    if let Some(retry_after_ms) = body.as_ref().and_then(|b| b["retry_after_ms"].as_u64()) {
+        tracing::warn!("LLM: {} rate limited (status {}), reset in {}ms", host, status, retry_after_ms);
+        holds.hold(&hold, Duration::from_millis(retry_after_ms));
+    }
+    if let Some(truncation) = truncation
+        && let Some(obj) = body.as_mut().and_then(|b| b.as_object_mut())
+    {
+        obj.insert("truncation".to_string(), truncation);
+    }
+    (status, body, was_cancelled)
+}
+
+/// Request body in the node's API style. Errors on tool settings the style
+/// can't express.
+fn build_request_body(data: &LlmNodeData, warnings: &Warnings) -> Result<serde_json::Value, String> {
+    let style = data.api_style.unwrap_or_default();
+    let mut request_body = match style {
+        LlmApiStyle::OpenAi => {
+            let mut body = serde_json::json!({
+                "model": data.model,
+                "messages": data.messages.iter().map(|m| {
+                    let mut message = serde_json::json!({ "role": m.role, "content": m.content });
+                    if let Some(tool_calls) = &m.tool_calls {
+                        message["tool_calls"] = tool_calls.clone();
+                    }
+                    if let Some(id) = &m.tool_call_id {
+                        message["tool_call_id"] = serde_json::json!(id);
+                    }
+                    message
+                }).collect::<Vec<_>>(),
+                "stream": data.stream
+            });
+            // Without this OpenAI never sends usage on streamed responses
+            if data.stream {
+                body["stream_options"] = serde_json::json!({ "include_usage": true });
+            }
+            if let Some(max) = data.max_tokens {
+                body["max_tokens"] = serde_json::json!(max);
+            }
+            body
+        }
+        LlmApiStyle::Anthropic => {
+            // System prompts are a top-level field, not a message role
+            let system: Vec<&str> = data.messages.iter().filter(|m| m.role == "system").map(|m| m.content.as_str()).collect();
+            let mut body = serde_json::json!({
+                "model": data.model,
+                "messages": data.messages.iter().filter(|m| m.role != "system").map(anthropic_message).collect::<Vec<_>>(),
+                "max_tokens": data.max_tokens.unwrap_or(DEFAULT_ANTHROPIC_MAX_TOKENS),
+                "stream": data.stream
+            });
+            if !system.is_empty() {
+                body["system"] = serde_json::json!(system.join("\n\n"));
+            }
+            body
+        }
+    };
+
+    // Add optional parameters
+    if let Some(temp) = data.temperature {
+        let range = match style {
+            LlmApiStyle::OpenAi => TEMPERATURE_RANGE,
+            LlmApiStyle::Anthropic => ANTHROPIC_TEMPERATURE_RANGE,
+        };
+        request_body["temperature"] = serde_json::json!(clamp_temperature(temp, range, warnings));
+    }
+    if let Some(tools) = &data.tools {
+        request_body["tools"] = match style {
+            LlmApiStyle::OpenAi => tools.clone(),
+            LlmApiStyle::Anthropic => anthropic_tools(tools)?,
+        };
+    }
+    if let Some(tool_choice) = &data.tool_choice {
+        request_body["tool_choice"] = match style {
+            LlmApiStyle::OpenAi => tool_choice.clone(),
+            LlmApiStyle::Anthropic => anthropic_tool_choice(tool_choice)?,
+        };
+    }
+    Ok(request_body)
+}
+
+/// A message in Anthropic's shape: tool calls become `tool_use` blocks and
+/// tool results a user turn with a `tool_result` block.
+fn anthropic_message(message: &LlmMessage) -> serde_json::Value {
+    if let Some(id) = &message.tool_call_id {
+        return serde_json::json!({
+            "role": "user",
+            "content": [{ "type": "tool_result", "tool_use_id": id, "content": message.content }]
+        });
+    }
+    let Some(calls) = message.tool_calls.as_ref().and_then(|c| c.as_array()) else {
+        return serde_json::json!({ "role": message.role, "content": message.content });
+    };
+
+    let mut blocks = Vec::new();
+    if !message.content.is_empty() {
+        blocks.push(serde_json::json!({ "type": "text", "text": message.content }));
+    }
+    for call in calls {
+        // OpenAI sends arguments as a JSON string, Anthropic wants the object
+        let arguments = &call["function"]["arguments"];
+        let input = arguments
+            .as_str()
+            .and_then(|a| serde_json::from_str(a).ok())
+            .unwrap_or_else(|| if arguments.is_object() { arguments.clone() } else { serde_json::json!({}) });
+        blocks.push(serde_json::json!({
+            "type": "tool_use",
+            "id": call["id"],
+            "name": call["function"]["name"],
+            "input": input,
+        }));
+    }
+    serde_json::json!({ "role": message.role, "content": blocks })
+}
+
+/// OpenAI-style function tools as Anthropic's `{name, description, input_schema}`.
+/// Tools already in Anthropic's shape are passed through.
+fn anthropic_tools(tools: &serde_json::Value) -> Result<serde_json::Value, String> {
+    let tools = tools.as_array().ok_or("tools must be an array")?;
+    tools
+        .iter()
+        .map(|tool| {
+            if tool.get("input_schema").is_some() {
+                return Ok(tool.clone());
+            }
+            let function = tool.get("function").filter(|f| f["name"].is_string()).ok_or_else(|| {
+                format!("tool {} is not a function tool and can't be sent to an Anthropic API", tool)
+            })?;
+            let mut mapped = serde_json::json!({
+                "name": function["name"],
+                "input_schema": function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+            });
+            if let Some(description) = function.get("description") {
+                mapped["description"] = description.clone();
+            }
+            Ok(mapped)
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map(serde_json::Value::Array)
+}
+
+/// OpenAI's `tool_choice` as Anthropic's `{type: "auto" | "any" | "tool", name}`.
+fn anthropic_tool_choice(choice: &serde_json::Value) -> Result<serde_json::Value, String> {
+    match choice {
+        serde_json::Value::String(mode) => match mode.as_str() {
+            "auto" => Ok(serde_json::json!({ "type": "auto" })),
+            "required" | "any" => Ok(serde_json::json!({ "type": "any" })),
+            other => Err(format!("tool_choice \"{}\" is not supported with api_style anthropic", other)),
+        },
+        serde_json::Value::Object(obj) => {
+            // Already Anthropic-shaped
+            if matches!(obj.get("type").and_then(|t| t.as_str()), Some("auto" | "any" | "tool")) && !obj.contains_key("function") {
+                return Ok(choice.clone());
+            }
+            match choice["function"]["name"].as_str() {
+                Some(name) => Ok(serde_json::json!({ "type": "tool", "name": name })),
+                None => Err(format!("tool_choice {} is not supported with api_style anthropic", choice)),
+            }
+        }
+        _ => Err(format!("tool_choice {} is not supported with api_style anthropic", choice)),
+    }
+}
+
+fn endpoint(data: &LlmNodeData) -> String {
+    let path = match data.api_style.unwrap_or_default() {
+        LlmApiStyle::OpenAi => "chat/completions",
+        LlmApiStyle::Anthropic => "messages",
+    };
+    format!("{}/{}", data.base_url.trim_end_matches('/'), path)
+}
+
+/// Send the completion request and handle the response.
+/// Returns (status_code, body, was_cancelled).
+async fn send_request(
+    client: reqwest::Client,
+    endpoint: &str,
+    request_body: &serde_json::Value,
+    data: &LlmNodeData,
+    stream_ctx: Option<&StreamContext>,
+    cancel_token: &CancellationToken,
+) -> (u16, Option<serde_json::Value>, bool) {
     // Make the API request with cancellation support
     let response = tokio::select! {
         biased;
@@ -68,22 +781,28 @@ pub async fn execute(
             return (499, Some(serde_json::json!({ "error": "Request cancelled" })), true);
         }
 
-        result = client
-        .post(&endpoint)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", data.api_key))
-        .json(&request_body)
+        result = authorize(client.post(endpoint), data)
+            .header("Content-Type", "application/json")
+            .json(request_body)
             .send() => result
     };
 
     match response {
         Ok(resp) => {
             let status_code = resp.status().as_u16();
+            let retry_after = matches!(status_code, 429 | 503)
+                .then(|| rate_limit_delay(resp.headers(), chrono::Utc::now()))
+                .flatten();
 
             if data.stream && status_code == 200 {
-                handle_streaming_response(resp, &data, stream_ctx, cancel_token).await
+                handle_streaming_response(resp, data, stream_ctx, cancel_token).await
             } else {
-                let (status, body) = handle_non_streaming_response(resp, status_code, &data, stream_ctx).await;
+                let (status, mut body) = handle_non_streaming_response(resp, status_code, data, stream_ctx).await;
+                if let Some(delay) = retry_after
+                    && let Some(obj) = body.as_mut().and_then(|b| b.as_object_mut())
+                {
+                    obj.insert("retry_after_ms".to_string(), serde_json::json!(delay.as_millis() as u64));
+                }
                 (status, body, false)
             }
         }
@@ -97,6 +816,139 @@ pub async fn execute(
     }
 }
 
+/// Auth headers for the node's API style.
+fn authorize(request: reqwest::RequestBuilder, data: &LlmNodeData) -> reqwest::RequestBuilder {
+    match data.api_style.unwrap_or_default() {
+        LlmApiStyle::OpenAi => request.header("Authorization", format!("Bearer {}", data.api_key)),
+        LlmApiStyle::Anthropic => request
+            .header("x-api-key", &data.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION),
+    }
+}
+
+/// What a streamed response has accumulated besides its text.
+#[derive(Default)]
+struct StreamState {
+    model: Option<String>,
+    usage: Option<(u32, u32)>,
+    tool_calls: ToolCallAccumulator,
+    finish_reason: Option<String>,
+    /// Anthropic content block index -> tool call index
+    tool_blocks: HashMap<u64, usize>,
+}
+
+impl StreamState {
+    /// Apply one SSE `data:` payload; returns the text delta it carried.
+    fn apply(&mut self, style: LlmApiStyle, chunk: &serde_json::Value) -> Option<String> {
+        match style {
+            LlmApiStyle::OpenAi => self.apply_openai(chunk),
+            LlmApiStyle::Anthropic => self.apply_anthropic(chunk),
+        }
+    }
+
+    fn apply_openai(&mut self, chunk: &serde_json::Value) -> Option<String> {
+        // Tool calls arrive as fragments keyed by index
+        if let Some(deltas) = chunk["choices"][0]["delta"]["tool_calls"].as_array() {
+            for delta in deltas {
+                self.tool_calls.push(delta);
+            }
+        }
+        if let Some(reason) = chunk["choices"][0]["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+        if let Some(m) = chunk["model"].as_str() {
+            self.model = Some(m.to_string());
+        }
+        // Usage arrives in the final chunk (earlier ones carry `"usage": null`)
+        if let Some(reported) = reported_usage(&chunk["usage"]) {
+            self.usage = Some(reported);
+        }
+        chunk["choices"][0]["delta"]["content"].as_str().map(str::to_string)
+    }
+
+    fn apply_anthropic(&mut self, chunk: &serde_json::Value) -> Option<String> {
+        match chunk["type"].as_str()? {
+            "message_start" => {
+                let message = &chunk["message"];
+                if let Some(m) = message["model"].as_str() {
+                    self.model = Some(m.to_string());
+                }
+                let input = message["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32;
+                let output = message["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32;
+                self.usage = Some((input, output));
+                None
+            }
+            "content_block_start" if chunk["content_block"]["type"] == "tool_use" => {
+                let block = &chunk["content_block"];
+                let index = self.tool_blocks.len();
+                self.tool_blocks.insert(chunk["index"].as_u64().unwrap_or(0), index);
+                self.tool_calls.push(&serde_json::json!({
+                    "index": index,
+                    "id": block["id"],
+                    "type": "function",
+                    "function": { "name": block["name"] }
+                }));
+                None
+            }
+            "content_block_delta" => {
+                let delta = &chunk["delta"];
+                match delta["type"].as_str()? {
+                    "text_delta" => delta["text"].as_str().map(str::to_string),
+                    "input_json_delta" => {
+                        let index = *self.tool_blocks.get(&chunk["index"].as_u64()?)?;
+                        self.tool_calls.push(&serde_json::json!({
+                            "index": index,
+                            "function": { "arguments": delta["partial_json"] }
+                        }));
+                        None
+                    }
+                    _ => None,
+                }
+            }
+            "message_delta" => {
+                if let Some(reason) = chunk["delta"]["stop_reason"].as_str() {
+                    self.finish_reason = Some(reason.to_string());
+                }
+                // output_tokens here is cumulative
+                if let Some(output) = chunk["usage"]["output_tokens"].as_u64() {
+                    let input = self.usage.map(|u| u.0).unwrap_or(0);
+                    self.usage = Some((input, output as u32));
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Text, tool calls, usage (input, output tokens) and stop reason of a message
+type MessageParts = (String, Vec<serde_json::Value>, Option<(u32, u32)>, Option<String>);
+
+/// Text, tool calls, usage and stop reason of a non-streamed Anthropic message.
+fn anthropic_message_parts(body: &serde_json::Value) -> MessageParts {
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    for block in body["content"].as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => content.push_str(block["text"].as_str().unwrap_or("")),
+            Some("tool_use") => tool_calls.push(serde_json::json!({
+                "id": block["id"],
+                "type": "function",
+                "function": { "name": block["name"], "arguments": block["input"].to_string() }
+            })),
+            _ => {}
+        }
+    }
+    let usage = body["usage"].is_object().then(|| {
+        (
+            body["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32,
+            body["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32,
+        )
+    });
+    let stop_reason = body["stop_reason"].as_str().map(str::to_string);
+    (content, tool_calls, usage, stop_reason)
+}
+
 /// Handle a streaming SSE response from the LLM API with cancellation support.
 /// Returns (status_code, body, was_cancelled).
 async fn handle_streaming_response(
@@ -107,20 +959,38 @@ async fn handle_streaming_response(
 ) -> (u16, Option<serde_json::Value>, bool) {
     use futures_util::StreamExt;
     
+    let style = data.api_style.unwrap_or_default();
     let mut full_content = String::new();
-    let mut prompt_tokens: u32 = 0;
-    let mut completion_tokens: u32 = 0;
-    let mut model_used = data.model.clone();
+    let mut state = StreamState::default();
     let mut buffer = String::new();
     let mut was_cancelled = false;
 
+    // Durable progress milestones (first token, then every PROGRESS_EVENT_INTERVAL)
+    let stream_start = Instant::now();
+    let mut milestones = MilestoneThrottle::new(PROGRESS_EVENT_INTERVAL);
+
+    let mut batcher = TokenBatcher::new(token_flush_interval(data), stream_start);
+
+    // Overall deadline so a provider that trickles (or stalls) can't hold the worker
+    let deadline = data.timeout_ms.map(|ms| tokio::time::Instant::from_std(stream_start) + Duration::from_millis(ms));
+    let mut timed_out = false;
+
     // Stream the response bytes as they arrive
     let mut stream = resp.bytes_stream();
     
-    while let Some(chunk_result) = stream.next().await {
+    loop {
+        let next = tokio::select! {
+            next = stream.next() => next,
+            _ = sleep_until_deadline(deadline) => {
+                timed_out = true;
+                break;
+            }
+        };
+        let Some(chunk_result) = next else { break };
+
         // Check for cancellation between chunks - this is the key cancellation point!
         if cancel_token.is_cancelled() {
-            println!("  -> LLM stream cancelled after {} chars", full_content.len());
+            tracing::info!("LLM stream cancelled after {} chars", full_content.len());
             if let Some(ctx) = stream_ctx {
                 ctx.progress("Cancelled").await;
             }
@@ -150,37 +1020,56 @@ async fn handle_streaming_response(
                 continue;
             }
             
-            if line.starts_with("data: ") {
-                let json_str = &line[6..];
+            if let Some(json_str) = line.strip_prefix("data: ") {
                 if json_str == "[DONE]" {
                     continue;
                 }
 
                 if let Ok(chunk) = serde_json::from_str::<serde_json::Value>(json_str) {
                     // Extract content delta
-                    if let Some(delta) = chunk["choices"][0]["delta"]["content"].as_str() {
-                        full_content.push_str(delta);
-                        // Stream each token to the UI in real-time!
+                    if let Some(delta) = state.apply(style, &chunk) {
+                        full_content.push_str(&delta);
+                        // Stream tokens to the UI in real-time (coalesced if configured)
                         if let Some(ctx) = stream_ctx {
-                            ctx.token(delta).await;
-                        }
-                    }
-
-                    // Capture model if provided
-                    if let Some(m) = chunk["model"].as_str() {
-                        model_used = m.to_string();
-                    }
+                            if let Some(batch) = batcher.push(&delta, Instant::now()) {
+                                ctx.token(&batch).await;
+                            }
 
-                    // Some providers include usage in the final chunk
-                    if let Some(usage) = chunk.get("usage") {
-                        prompt_tokens = usage["prompt_tokens"].as_u64().unwrap_or(0) as u32;
-                        completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0) as u32;
+                            if let Some(milestone) = milestones.due(Instant::now()) {
+                                ctx.milestone(serde_json::json!({
+                                    "milestone": milestone,
+                                    "chars": full_content.len(),
+                                    "elapsed_ms": stream_start.elapsed().as_millis() as u64,
+                                }))
+                                .await;
+                            }
+                        }
                     }
                 }
             }
         }
     }
 
+    let model_used = state.model.clone().unwrap_or_else(|| data.model.clone());
+
+    if timed_out {
+        let timeout_ms = data.timeout_ms.unwrap_or_default();
+        tracing::info!("LLM stream exceeded {}ms after {} chars", timeout_ms, full_content.len());
+        if let Some(ctx) = stream_ctx {
+            ctx.error(&format!("Stream exceeded {}ms timeout", timeout_ms)).await;
+        }
+        return (
+            504,
+            Some(serde_json::json!({
+                "error": format!("LLM stream exceeded timeout of {}ms", timeout_ms),
+                "timeout": true,
+                "partial_content": full_content,
+                "model": model_used
+            })),
+            false,
+        );
+    }
+
     if was_cancelled {
         return (
             499,
@@ -194,23 +1083,80 @@ async fn handle_streaming_response(
     }
 
     if let Some(ctx) = stream_ctx {
+        if let Some(batch) = batcher.flush() {
+            ctx.token(&batch).await;
+        }
         ctx.complete().await;
     }
 
-    (
-        200,
-        Some(serde_json::json!({
-            "content": full_content,
-            "model": model_used,
-            "usage": {
-                "prompt_tokens": prompt_tokens,
-                "completion_tokens": completion_tokens,
-                "total_tokens": prompt_tokens + completion_tokens
-            },
-            "streamed": true
-        })),
-        false,
-    )
+    let tool_calls = state.tool_calls.finish();
+    let mut body = serde_json::json!({
+        "content": full_content,
+        "model": model_used,
+        "usage": usage_json(state.usage, &data.messages, &full_content, &tool_calls),
+        "streamed": true
+    });
+    attach_tool_calls(&mut body, tool_calls, state.finish_reason);
+    (200, Some(body), false)
+}
+
+/// Sleep until `deadline`, or forever without one.
+async fn sleep_until_deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Rebuilds streamed `tool_calls` from their deltas.
+///
+/// The first delta for a call carries its `index`, `id` and function `name`;
+/// later ones only append to `function.arguments`.
+#[derive(Default)]
+struct ToolCallAccumulator {
+    calls: Vec<serde_json::Value>,
+}
+
+impl ToolCallAccumulator {
+    fn push(&mut self, delta: &serde_json::Value) {
+        let index = delta["index"].as_u64().unwrap_or(self.calls.len().saturating_sub(1) as u64) as usize;
+        while self.calls.len() <= index {
+            self.calls.push(serde_json::json!({
+                "id": "",
+                "type": "function",
+                "function": { "name": "", "arguments": "" }
+            }));
+        }
+        let call = &mut self.calls[index];
+        if let Some(id) = delta["id"].as_str() {
+            call["id"] = serde_json::json!(id);
+        }
+        if let Some(kind) = delta["type"].as_str() {
+            call["type"] = serde_json::json!(kind);
+        }
+        if let Some(name) = delta["function"]["name"].as_str() {
+            let full = format!("{}{}", call["function"]["name"].as_str().unwrap_or(""), name);
+            call["function"]["name"] = serde_json::json!(full);
+        }
+        if let Some(args) = delta["function"]["arguments"].as_str() {
+            let full = format!("{}{}", call["function"]["arguments"].as_str().unwrap_or(""), args);
+            call["function"]["arguments"] = serde_json::json!(full);
+        }
+    }
+
+    fn finish(self) -> Vec<serde_json::Value> {
+        self.calls
+    }
+}
+
+/// Add `tool_calls` (and `finish_reason`) to a result body when the model called tools.
+fn attach_tool_calls(body: &mut serde_json::Value, tool_calls: Vec<serde_json::Value>, finish_reason: Option<String>) {
+    if !tool_calls.is_empty() {
+        body["tool_calls"] = serde_json::Value::Array(tool_calls);
+    }
+    if let Some(reason) = finish_reason {
+        body["finish_reason"] = serde_json::json!(reason);
+    }
 }
 
 /// Handle a non-streaming response from the LLM API.
@@ -227,32 +1173,30 @@ async fn handle_non_streaming_response(
 
     if status_code == 200 {
         // Extract the assistant's message
-        let content = body["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("")
-            .to_string();
+        let (content, tool_calls, usage, finish_reason) = match data.api_style.unwrap_or_default() {
+            LlmApiStyle::OpenAi => (
+                body["choices"][0]["message"]["content"].as_str().unwrap_or("").to_string(),
+                body["choices"][0]["message"]["tool_calls"].as_array().cloned().unwrap_or_default(),
+                reported_usage(&body["usage"]),
+                body["choices"][0]["finish_reason"].as_str().map(str::to_string),
+            ),
+            LlmApiStyle::Anthropic => anthropic_message_parts(&body),
+        };
 
         let model_used = body["model"].as_str().unwrap_or(&data.model).to_string();
-        let prompt_tokens = body["usage"]["prompt_tokens"].as_u64().unwrap_or(0) as u32;
-        let completion_tokens = body["usage"]["completion_tokens"].as_u64().unwrap_or(0) as u32;
 
         if let Some(ctx) = stream_ctx {
             ctx.progress("Complete").await;
         }
 
-        (
-            200,
-            Some(serde_json::json!({
-                "content": content,
-                "model": model_used,
-                "usage": {
-                    "prompt_tokens": prompt_tokens,
-                    "completion_tokens": completion_tokens,
-                    "total_tokens": prompt_tokens + completion_tokens
-                },
-                "streamed": false
-            })),
-        )
+        let mut result = serde_json::json!({
+            "content": content,
+            "model": model_used,
+            "usage": usage_json(usage, &data.messages, &content, &tool_calls),
+            "streamed": false
+        });
+        attach_tool_calls(&mut result, tool_calls, finish_reason);
+        (200, Some(result))
     } else {
         // Error response
         let error_msg = body["error"]["message"]
@@ -269,3 +1213,446 @@ async fn handle_non_streaming_response(
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_delay_from_provider_headers() {
+        let now = chrono::DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z").unwrap().with_timezone(&chrono::Utc);
+        let headers = |pairs: &[(&'static str, &str)]| {
+            let mut map = reqwest::header::HeaderMap::new();
+            for (name, value) in pairs {
+                map.insert(*name, value.parse().unwrap());
+            }
+            map
+        };
+
+        assert_eq!(rate_limit_delay(&headers(&[("retry-after", "7")]), now), Some(Duration::from_secs(7)));
+        assert_eq!(
+            rate_limit_delay(&headers(&[("retry-after", "Wed, 01 Jan 2025 00:00:30 GMT")]), now),
+            Some(Duration::from_secs(30))
+        );
+        // OpenAI: the exhausted window, not the later one
+        assert_eq!(
+            rate_limit_delay(
+                &headers(&[
+                    ("x-ratelimit-remaining-requests", "0"),
+                    ("x-ratelimit-reset-requests", "120ms"),
+                    ("x-ratelimit-remaining-tokens", "2400"),
+                    ("x-ratelimit-reset-tokens", "1m6s"),
+                ]),
+                now
+            ),
+            Some(Duration::from_millis(120))
+        );
+        assert_eq!(
+            rate_limit_delay(
+                &headers(&[
+                    ("x-ratelimit-remaining-requests", "12"),
+                    ("x-ratelimit-reset-requests", "120ms"),
+                    ("x-ratelimit-remaining-tokens", "0"),
+                    ("x-ratelimit-reset-tokens", "1m6s"),
+                ]),
+                now
+            ),
+            Some(Duration::from_secs(66))
+        );
+        // Without remaining counts: the soonest reset
+        assert_eq!(
+            rate_limit_delay(&headers(&[("x-ratelimit-reset-requests", "120ms"), ("x-ratelimit-reset-tokens", "1m6s")]), now),
+            Some(Duration::from_millis(120))
+        );
+        // Anthropic: the exhausted window's reset
+        assert_eq!(
+            rate_limit_delay(
+                &headers(&[
+                    ("anthropic-ratelimit-requests-remaining", "40"),
+                    ("anthropic-ratelimit-requests-reset", "2025-01-01T00:00:02Z"),
+                    ("anthropic-ratelimit-output-tokens-remaining", "0"),
+                    ("anthropic-ratelimit-output-tokens-reset", "2025-01-01T00:00:45Z"),
+                ]),
+                now
+            ),
+            Some(Duration::from_secs(45))
+        );
+        // Anthropic: reset timestamps, unless retry-after says otherwise
+        let anthropic = [("anthropic-ratelimit-tokens-reset", "2025-01-01T00:00:12Z")];
+        assert_eq!(rate_limit_delay(&headers(&anthropic), now), Some(Duration::from_secs(12)));
+        assert_eq!(
+            rate_limit_delay(&headers(&[anthropic[0], ("retry-after", "3")]), now),
+            Some(Duration::from_secs(3))
+        );
+
+        assert_eq!(rate_limit_delay(&headers(&[("x-ratelimit-reset-tokens", "soon")]), now), None);
+        assert_eq!(rate_limit_delay(&headers(&[]), now), None);
+    }
+
+    #[test]
+    fn test_hold_keeps_the_later_reset_per_credential() {
+        let holds = RateLimitHolds::new();
+        let tenant_a = hold_key("llm-hold.test", "sk-a");
+        holds.hold(&tenant_a, Duration::from_secs(30));
+        holds.hold(&tenant_a, Duration::from_secs(1));
+        assert!(holds.remaining(&tenant_a).unwrap() > Duration::from_secs(20));
+        assert_eq!(holds.remaining(&hold_key("llm-hold.test", "sk-b")), None);
+        assert_eq!(holds.remaining(&hold_key("llm-other.test", "sk-a")), None);
+    }
+
+    #[test]
+    fn test_rapid_deltas_coalesce() {
+        let start = Instant::now();
+        let mut batcher = TokenBatcher::new(Duration::from_millis(50), start);
+        let deltas = ["Hel", "lo", ", ", "wor", "ld", "!"];
+
+        let mut chunks = Vec::new();
+        for (i, delta) in deltas.iter().enumerate() {
+            // Deltas arrive 10ms apart
+            let now = start + Duration::from_millis(10 * (i as u64 + 1));
+            chunks.extend(batcher.push(delta, now));
+        }
+        chunks.extend(batcher.flush());
+
+        assert!(chunks.len() < deltas.len(), "got {} chunks", chunks.len());
+        assert_eq!(chunks.concat(), "Hello, world!");
+    }
+
+    #[test]
+    fn test_zero_interval_emits_every_token() {
+        let start = Instant::now();
+        let mut batcher = TokenBatcher::new(Duration::ZERO, start);
+
+        assert_eq!(batcher.push("a", start).as_deref(), Some("a"));
+        assert_eq!(batcher.push("b", start).as_deref(), Some("b"));
+        assert_eq!(batcher.flush(), None);
+    }
+
+    #[test]
+    fn test_progress_milestones_respect_the_interval() {
+        let start = Instant::now();
+        let mut milestones = MilestoneThrottle::new(PROGRESS_EVENT_INTERVAL);
+
+        // A token every 250ms for 20s
+        let emitted: Vec<(Duration, &str)> = (0..=80)
+            .map(|i| Duration::from_millis(i * 250))
+            .filter_map(|at| milestones.due(start + at).map(|m| (at, m)))
+            .collect();
+
+        assert_eq!(emitted.len(), 5, "{:?}", emitted);
+        assert_eq!(emitted[0], (Duration::ZERO, "first_token"));
+        assert!(emitted[1..].iter().all(|(_, m)| *m == "streaming"));
+        assert!(emitted.windows(2).all(|w| w[1].0 - w[0].0 >= PROGRESS_EVENT_INTERVAL));
+    }
+
+    fn msg(role: &str, content: &str) -> LlmMessage {
+        LlmMessage { role: role.to_string(), content: content.to_string(), tool_calls: None, tool_call_id: None }
+    }
+
+    /// System prompt, then 10 turns of ~100 tokens each
+    fn long_conversation() -> Vec<LlmMessage> {
+        let mut messages = vec![msg("system", "You are terse.")];
+        for i in 0..10 {
+            let role = if i % 2 == 0 { "user" } else { "assistant" };
+            messages.push(msg(role, &format!("turn {} {}", i, "x".repeat(400))));
+        }
+        messages
+    }
+
+    #[test]
+    fn test_fits_already_untouched() {
+        let (messages, report) = fit_to_context(long_conversation(), 10_000, LlmTruncation::DropOldest);
+        assert_eq!(messages.len(), 11);
+        assert!(report.is_none());
+    }
+
+    #[test]
+    fn test_drop_oldest_keeps_system_and_latest() {
+        let budget = 350;
+        let (messages, report) = fit_to_context(long_conversation(), budget, LlmTruncation::DropOldest);
+        let report = report.unwrap();
+
+        assert_eq!(messages[0].role, "system");
+        assert!(messages.last().unwrap().content.starts_with("turn 9 "));
+        assert!(messages[1].content.starts_with(&format!("turn {} ", report.dropped_messages)));
+        assert_eq!(messages.len(), 11 - report.dropped_messages);
+        assert!(report.final_tokens <= budget);
+        assert_eq!(report.final_tokens, prompt_tokens(&messages));
+
+        // Nothing droppable left: system + latest stay even if over budget
+        let (messages, report) = fit_to_context(long_conversation(), 10, LlmTruncation::DropOldest);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(report.unwrap().dropped_messages, 9);
+    }
+
+    #[test]
+    fn test_summarize_replaces_dropped_with_a_note() {
+        let budget = 400;
+        let (messages, report) = fit_to_context(long_conversation(), budget, LlmTruncation::Summarize);
+        let report = report.unwrap();
+
+        assert_eq!(messages[0].content, "You are terse.");
+        assert_eq!(messages[1].role, "system");
+        assert!(messages[1].content.starts_with(&format!("Summary of {} earlier", report.dropped_messages)));
+        assert!(messages[1].content.contains("- user: turn 0 "));
+        assert!(messages.last().unwrap().content.starts_with("turn 9 "));
+        assert!(report.final_tokens <= budget, "{} > {}", report.final_tokens, budget);
+        assert_eq!(report.to_json()["strategy"], "summarize");
+    }
+
+    #[test]
+    fn test_out_of_range_temperature_is_clamped_with_warning() {
+        let warnings = Warnings::default();
+        assert_eq!(clamp_temperature(0.7, TEMPERATURE_RANGE, &warnings), 0.7);
+        assert!(warnings.take().is_empty());
+
+        assert_eq!(clamp_temperature(3.5, TEMPERATURE_RANGE, &warnings), 2.0);
+        assert_eq!(clamp_temperature(-1.0, TEMPERATURE_RANGE, &warnings), 0.0);
+        let taken = warnings.take();
+        assert_eq!(taken.len(), 2);
+        assert_eq!(taken[0].code, "temperature_clamped");
+        assert_eq!(taken[0].message, "temperature 3.5 clamped to 2");
+    }
+
+    #[test]
+    fn test_streamed_tool_call_deltas_are_reassembled() {
+        let mut acc = ToolCallAccumulator::default();
+        let deltas = [
+            serde_json::json!({"index": 0, "id": "call_a", "type": "function", "function": {"name": "get_weather", "arguments": ""}}),
+            serde_json::json!({"index": 0, "function": {"arguments": "{\"city\":"}}),
+            serde_json::json!({"index": 1, "id": "call_b", "type": "function", "function": {"name": "get_time", "arguments": "{}"}}),
+            serde_json::json!({"index": 0, "function": {"arguments": "\"Oslo\"}"}}),
+        ];
+        for delta in &deltas {
+            acc.push(delta);
+        }
+
+        let calls = acc.finish();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0]["id"], "call_a");
+        assert_eq!(calls[0]["function"]["name"], "get_weather");
+        assert_eq!(calls[0]["function"]["arguments"], "{\"city\":\"Oslo\"}");
+        assert_eq!(calls[1]["function"]["name"], "get_time");
+
+        let mut body = serde_json::json!({"content": ""});
+        attach_tool_calls(&mut body, calls, Some("tool_calls".to_string()));
+        assert_eq!(body["tool_calls"].as_array().unwrap().len(), 2);
+        assert_eq!(body["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn test_tool_call_results_round_trip_into_the_next_request() {
+        let tool_calls = serde_json::json!([
+            {"id": "call_a", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Oslo\"}"}}
+        ]);
+        let data: LlmNodeData = serde_json::from_value(serde_json::json!({
+            "base_url": "https://api.openai.com/v1",
+            "api_key": "sk-test",
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "Weather in Oslo?"},
+                {"role": "assistant", "content": "", "tool_calls": tool_calls},
+                {"role": "tool", "content": "{\"temp\":4}", "tool_call_id": "call_a"}
+            ]
+        }))
+        .unwrap();
+
+        // Messages keep the fields through (de)serialization, and omit them when unset
+        let round_tripped: Vec<LlmMessage> = serde_json::from_value(serde_json::to_value(&data.messages).unwrap()).unwrap();
+        assert_eq!(round_tripped[1].tool_calls.as_ref(), Some(&tool_calls));
+        assert_eq!(round_tripped[2].tool_call_id.as_deref(), Some("call_a"));
+        assert!(serde_json::to_value(&round_tripped[0]).unwrap().get("tool_calls").is_none());
+
+        let body = build_request_body(&data, &Warnings::default()).unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        assert!(messages[0].get("tool_calls").is_none());
+        assert_eq!(messages[1]["tool_calls"], tool_calls);
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_a");
+    }
+
+    #[test]
+    fn test_missing_usage_is_estimated_and_flagged() {
+        let messages = vec![msg("user", "12345678")];
+        let reported = usage_json(Some((10, 5)), &messages, "ignored", &[]);
+        assert_eq!(reported["total_tokens"], 15);
+        assert!(reported.get("estimated").is_none());
+
+        // "user" = 1 token, content = 2, overhead 4; completion "abcdefgh" = 2
+        let estimated = usage_json(reported_usage(&serde_json::Value::Null), &messages, "abcdefgh", &[]);
+        assert_eq!(estimated["prompt_tokens"], 7);
+        assert_eq!(estimated["completion_tokens"], 2);
+        assert_eq!(estimated["estimated"], true);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_times_out_with_partial_content() {
+        use tokio::io::AsyncWriteExt;
+
+        // Provider that sends one token and then stalls
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let chunk = "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n";
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n";
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(format!("{:x}\r\n{}\r\n", chunk.len(), chunk).as_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
+
+        let data: LlmNodeData = serde_json::from_value(serde_json::json!({
+            "base_url": format!("http://{}", addr),
+            "api_key": "",
+            "model": "test-model",
+            "messages": [],
+            "stream": true,
+            "timeout_ms": 200
+        }))
+        .unwrap();
+        let resp = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+
+        let start = Instant::now();
+        let (status, body, cancelled) = handle_streaming_response(resp, &data, None, &CancellationToken::new()).await;
+        assert_eq!(status, 504);
+        assert!(!cancelled);
+        let body = body.unwrap();
+        assert_eq!(body["partial_content"], "Hel");
+        assert_eq!(body["timeout"], true);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    fn anthropic_node() -> LlmNodeData {
+        serde_json::from_value(serde_json::json!({
+            "base_url": "https://api.anthropic.com/v1/",
+            "api_key": "sk-ant",
+            "model": "claude-test",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hi"}
+            ],
+            "temperature": 1.5,
+            "api_style": "anthropic"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_anthropic_request_extracts_system_prompt() {
+        let data = anthropic_node();
+        let warnings = Warnings::default();
+        let body = build_request_body(&data, &warnings).unwrap();
+
+        assert_eq!(endpoint(&data), "https://api.anthropic.com/v1/messages");
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["max_tokens"], DEFAULT_ANTHROPIC_MAX_TOKENS);
+        assert_eq!(body["temperature"], 1.0);
+        assert!(body.get("stream_options").is_none());
+        assert_eq!(warnings.take().len(), 1);
+    }
+
+    #[test]
+    fn test_anthropic_request_maps_tools() {
+        let mut data = anthropic_node();
+        data.tools = Some(serde_json::json!([{
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Current weather",
+                "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }
+        }]));
+        data.tool_choice = Some(serde_json::json!({"type": "function", "function": {"name": "get_weather"}}));
+        data.messages.push(LlmMessage {
+            role: "assistant".to_string(),
+            content: String::new(),
+            tool_calls: Some(serde_json::json!([
+                {"id": "call_a", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Oslo\"}"}}
+            ])),
+            tool_call_id: None,
+        });
+        data.messages.push(LlmMessage {
+            role: "tool".to_string(),
+            content: "{\"temp\":4}".to_string(),
+            tool_calls: None,
+            tool_call_id: Some("call_a".to_string()),
+        });
+        let body = build_request_body(&data, &Warnings::default()).unwrap();
+
+        assert_eq!(
+            body["tools"],
+            serde_json::json!([{
+                "name": "get_weather",
+                "description": "Current weather",
+                "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }])
+        );
+        assert_eq!(body["tool_choice"], serde_json::json!({"type": "tool", "name": "get_weather"}));
+        assert_eq!(
+            body["messages"][1],
+            serde_json::json!({
+                "role": "assistant",
+                "content": [{"type": "tool_use", "id": "call_a", "name": "get_weather", "input": {"city": "Oslo"}}]
+            })
+        );
+        assert_eq!(
+            body["messages"][2],
+            serde_json::json!({
+                "role": "user",
+                "content": [{"type": "tool_result", "tool_use_id": "call_a", "content": "{\"temp\":4}"}]
+            })
+        );
+
+        for (choice, mapped) in [("auto", "auto"), ("required", "any")] {
+            data.tool_choice = Some(serde_json::json!(choice));
+            let body = build_request_body(&data, &Warnings::default()).unwrap();
+            assert_eq!(body["tool_choice"], serde_json::json!({"type": mapped}));
+        }
+        data.tool_choice = Some(serde_json::json!("none"));
+        assert!(build_request_body(&data, &Warnings::default()).is_err());
+    }
+
+    #[test]
+    fn test_anthropic_stream_events_are_parsed() {
+        let events = [
+            serde_json::json!({"type": "message_start", "message": {"model": "claude-x", "usage": {"input_tokens": 12, "output_tokens": 1}}}),
+            serde_json::json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            serde_json::json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hello"}}),
+            serde_json::json!({"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "lookup", "input": {}}}),
+            serde_json::json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"q\":"}}),
+            serde_json::json!({"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"x\"}"}}),
+            serde_json::json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 9}}),
+        ];
+        let mut state = StreamState::default();
+        let text: String = events.iter().filter_map(|e| state.apply(LlmApiStyle::Anthropic, e)).collect();
+
+        assert_eq!(text, "Hello");
+        assert_eq!(state.model.as_deref(), Some("claude-x"));
+        assert_eq!(state.usage, Some((12, 9)));
+        assert_eq!(state.finish_reason.as_deref(), Some("tool_use"));
+        let calls = state.tool_calls.finish();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0]["id"], "toolu_1");
+        assert_eq!(calls[0]["function"]["name"], "lookup");
+        assert_eq!(calls[0]["function"]["arguments"], "{\"q\":\"x\"}");
+    }
+
+    #[test]
+    fn test_anthropic_message_parts() {
+        let body = serde_json::json!({
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_2", "name": "lookup", "input": {"q": "y"}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 5, "output_tokens": 7}
+        });
+        let (content, calls, usage, stop) = anthropic_message_parts(&body);
+        assert_eq!(content, "Let me check.");
+        assert_eq!(calls[0]["function"]["arguments"], "{\"q\":\"y\"}");
+        assert_eq!(usage, Some((5, 7)));
+        assert_eq!(stop.as_deref(), Some("tool_use"));
+    }
+}
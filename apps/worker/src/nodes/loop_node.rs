@@ -0,0 +1,395 @@
+//! Loop node: run a body node again and again until a condition holds.
+//!
+//! Like a Parallel branch, the body is a node of the same workflow (drawn as
+//! an edge from the Loop node) that runs as a job in the run itself, rebuilt
+//! from the snapshot, `isolated`, and tagged with `loop_iteration`. Its final
+//! result comes back as a LOOPITERATIONCOMPLETE lifecycle job, which evaluates
+//! `condition` in the JS sandbox against that output: truthy resumes the Loop
+//! node, falsy queues the next iteration. Every iteration is rebuilt with the
+//! latest recorded outputs, so the body's templates see its previous result.
+//!
+//! `max_iterations` (capped by `LOOP_MAX_ITERATIONS`) ends the loop even if
+//! the condition never holds. A failed body or a condition that throws ends
+//! it on the error handle. The body must be a leaf and can't suspend the run.
+
+use crate::events;
+use crate::node_error::{NodeError, NodeResult};
+use crate::nodes::code::SandboxConfig;
+use crate::nodes::JsTask;
+use crate::rerun;
+use crate::templating;
+use crate::trace_context::TraceContext;
+use crate::types::{LoopIteration, LoopIterationCompleteData, LoopNodeData, ResumeVia, Suspension, SuspensionKind};
+use redis::{AsyncCommands, RedisResult};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// Default worker-wide cap on a loop's iterations
+const DEFAULT_MAX_ITERATIONS: u32 = 1000;
+
+/// Evaluates `INPUT.condition` against the body's output. The expression
+/// arrives as data, never spliced into the script.
+const CONDITION_JS: &str = r#"
+const condition = new Function('output', 'iteration', 'return (' + INPUT.condition + ');');
+return !!condition(INPUT.output, INPUT.iteration);
+"#;
+
+/// What an iteration's completion did to its Loop node.
+#[derive(Debug)]
+pub enum LoopStep {
+    /// The next iteration is queued; the body is progress
+    Continue(Value),
+    /// The loop ended; the body is the Loop node's output
+    Done(Value),
+}
+
+/// Worker-wide cap on iterations (`LOOP_MAX_ITERATIONS`)
+fn max_iterations_cap() -> u32 {
+    std::env::var("LOOP_MAX_ITERATIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n: &u32| n > 0)
+        .unwrap_or(DEFAULT_MAX_ITERATIONS)
+}
+
+/// Reject a loop without a valid body or iteration count; returns the
+/// effective `max_iterations`.
+fn validate(node_id: &str, data: &LoopNodeData, cap: u32) -> Result<u32, String> {
+    if data.body.is_empty() {
+        return Err("Loop node needs a body node".to_string());
+    }
+    if data.body == node_id {
+        return Err("Loop node can't be its own body".to_string());
+    }
+    if data.condition.trim().is_empty() {
+        return Err("Loop node needs a condition".to_string());
+    }
+    if data.max_iterations == 0 {
+        return Err("max_iterations must be at least 1".to_string());
+    }
+    Ok(data.max_iterations.min(cap))
+}
+
+/// Start the first iteration and suspend the node until the loop ends.
+pub async fn handle_loop_init(
+    pool: &PgPool,
+    redis: &redis::Client,
+    run_id: &Uuid,
+    node_id: &str,
+    data: &LoopNodeData,
+    trace: &TraceContext,
+) -> NodeResult {
+    let max_iterations = validate(node_id, data, max_iterations_cap()).map_err(|e| NodeError::permanent(400, e))?;
+
+    let marker = LoopIteration {
+        loop_node_id: node_id.to_string(),
+        iteration: 0,
+        condition: data.condition.clone(),
+        max_iterations,
+    };
+    enqueue_iteration(pool, redis, run_id, &data.body, marker, trace).await?;
+
+    tracing::info!("Loop: started body '{}' (up to {} iteration(s))", data.body, max_iterations);
+
+    Err(NodeError::Suspended(json!({
+        "body": data.body,
+        "max_iterations": max_iterations,
+        "suspension": Suspension::new(
+            SuspensionKind::Loop,
+            ResumeVia::Iterations,
+            None,
+            json!({ "body": data.body, "max_iterations": max_iterations }),
+        ),
+    })))
+}
+
+/// Rebuild the body against the run's latest outputs and queue it.
+async fn enqueue_iteration(
+    pool: &PgPool,
+    redis: &redis::Client,
+    run_id: &Uuid,
+    body: &str,
+    marker: LoopIteration,
+    trace: &TraceContext,
+) -> Result<(), NodeError> {
+    let run: Option<(Value, Option<Value>)> =
+        sqlx::query_as("SELECT snapshot_graph, input_data FROM workflow_runs WHERE id = $1")
+            .bind(run_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| NodeError::from_sqlx("Failed to load run", &e))?;
+    let Some((graph, input_data)) = run else {
+        return Err(NodeError::permanent(404, format!("Run not found: {}", run_id)));
+    };
+    let outputs = templating::recorded_outputs(pool, run_id)
+        .await
+        .map_err(|e| NodeError::from_sqlx("Failed to load node outputs", &e))?;
+    // Each iteration is a fresh execution of the body, numbered past the last
+    // one so the per-attempt idempotency check doesn't skip it
+    let attempt = events::next_attempt(pool, run_id, body)
+        .await
+        .map_err(|e| NodeError::from_sqlx("Failed to load body attempts", &e))?;
+
+    let mut job = rerun::rebuild_job(&graph, input_data.as_ref(), &outputs, run_id, body)
+        .map_err(|e| NodeError::permanent(400, format!("Loop body '{}': {}", body, e)))?;
+    job.isolated = true;
+    job.max_retries += attempt;
+    job.retry_count = attempt;
+    job.first_attempt_at = None;
+    job.idempotency_key = None;
+    job.trace_context = Some(trace.headers().into_iter().map(|(k, v)| (k.to_string(), v)).collect::<HashMap<_, _>>());
+    job.loop_iteration = Some(marker);
+
+    let payload = serde_json::to_string(&job).map_err(|e| NodeError::permanent(500, e.to_string()))?;
+    let mut con = redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| NodeError::Transient(format!("Redis connection error: {}", e)))?;
+    let _: String = con
+        .xadd(job.priority.stream(), "*", &[("payload", payload)])
+        .await
+        .map_err(|e| NodeError::Transient(format!("Failed to enqueue loop body: {}", e)))?;
+    Ok(())
+}
+
+/// Lifecycle job reporting an iteration's final result to its Loop node.
+/// Keyed by iteration, so a redelivered report can't fork the loop.
+pub fn iteration_complete_job(
+    marker: &LoopIteration,
+    run_id: &Uuid,
+    body_node_id: &str,
+    success: bool,
+    output: Option<Value>,
+) -> Value {
+    let error = (!success).then(|| {
+        output
+            .as_ref()
+            .and_then(|b| b.get("error"))
+            .and_then(|e| e.as_str())
+            .unwrap_or("Loop body failed")
+            .to_string()
+    });
+    json!({
+        "id": marker.loop_node_id,
+        "run_id": run_id.to_string(),
+        "node": {
+            "type": "LOOPITERATIONCOMPLETE",
+            "data": {
+                "body_node_id": body_node_id,
+                "iteration": marker.iteration,
+                "condition": marker.condition,
+                "max_iterations": marker.max_iterations,
+                "success": success,
+                "output": output,
+                "error": error
+            }
+        },
+        "retry_count": 0,
+        "max_retries": 0,
+        "idempotency_key": format!("loop:{}:{}:{}", run_id, marker.loop_node_id, marker.iteration)
+    })
+}
+
+/// Push an iteration's final result to its Loop node.
+pub async fn report_iteration(
+    redis: &redis::Client,
+    marker: &LoopIteration,
+    run_id: &Uuid,
+    body_node_id: &str,
+    success: bool,
+    output: Option<Value>,
+) {
+    let job = iteration_complete_job(marker, run_id, body_node_id, success, output);
+    let pushed: RedisResult<String> = match redis.get_multiplexed_async_connection().await {
+        Ok(mut con) => con.xadd(crate::priority::STREAM_DEFAULT, "*", &[("payload", job.to_string())]).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = pushed {
+        tracing::error!(
+            "Loop: Failed to report iteration {} of {} to {}: {}",
+            marker.iteration, body_node_id, marker.loop_node_id, e
+        );
+    }
+}
+
+/// Whether the loop ends after `iterations` and, if so, whether the
+/// condition was met (false = it hit `max_iterations`).
+fn loop_decision(condition_met: bool, iterations: u32, max_iterations: u32) -> Option<bool> {
+    if condition_met {
+        Some(true)
+    } else {
+        (iterations >= max_iterations).then_some(false)
+    }
+}
+
+/// Check the condition on a finished iteration; queue the next one or end the loop.
+pub async fn handle_iteration_complete(
+    pool: &PgPool,
+    redis: &redis::Client,
+    js_sender: &mpsc::Sender<JsTask>,
+    run_id: &Uuid,
+    loop_node_id: &str,
+    data: &LoopIterationCompleteData,
+    trace: &TraceContext,
+) -> Result<LoopStep, NodeError> {
+    let iterations = data.iteration + 1;
+    let output = data.output.clone().unwrap_or(Value::Null);
+
+    if !data.success {
+        let error = data.error.clone().unwrap_or_else(|| "Loop body failed".to_string());
+        tracing::info!("Loop: body '{}' failed on iteration {}", data.body_node_id, iterations);
+        return Ok(LoopStep::Done(failed_body(iterations, &output, &error)));
+    }
+
+    let condition_met = match evaluate_condition(&data.condition, &output, iterations, js_sender).await? {
+        Ok(met) => met,
+        Err(e) => {
+            let error = format!("Loop condition failed: {}", e);
+            return Ok(LoopStep::Done(failed_body(iterations, &output, &error)));
+        }
+    };
+
+    let Some(condition_met) = loop_decision(condition_met, iterations, data.max_iterations) else {
+        let marker = LoopIteration {
+            loop_node_id: loop_node_id.to_string(),
+            iteration: iterations,
+            condition: data.condition.clone(),
+            max_iterations: data.max_iterations,
+        };
+        enqueue_iteration(pool, redis, run_id, &data.body_node_id, marker, trace).await?;
+        return Ok(LoopStep::Continue(json!({
+            "status": "running",
+            "iterations": iterations,
+            "max_iterations": data.max_iterations,
+        })));
+    };
+
+    tracing::info!(
+        "Loop: ended after {} iteration(s) ({})",
+        iterations,
+        if condition_met { "condition met" } else { "max_iterations reached" }
+    );
+    Ok(LoopStep::Done(json!({
+        "output": output,
+        "iterations": iterations,
+        "condition_met": condition_met,
+        "route_to": "success"
+    })))
+}
+
+fn failed_body(iterations: u32, output: &Value, error: &str) -> Value {
+    json!({
+        "output": output,
+        "iterations": iterations,
+        "condition_met": false,
+        "error": error,
+        "route_to": "error"
+    })
+}
+
+/// Run the condition in the sandbox. The outer error is the sandbox being
+/// unavailable; the inner one is the expression throwing.
+async fn evaluate_condition(
+    condition: &str,
+    output: &Value,
+    iteration: u32,
+    js_sender: &mpsc::Sender<JsTask>,
+) -> Result<Result<bool, String>, NodeError> {
+    let (tx, rx) = oneshot::channel();
+    let task = JsTask {
+        code: CONDITION_JS.to_string(),
+        inputs: Some(json!({ "condition": condition, "output": output, "iteration": iteration })),
+        responder: tx,
+        timeout_ms: None,
+        log_sender: None,
+        cancelled: None,
+    };
+    if js_sender.send(task).await.is_err() {
+        return Err(NodeError::permanent(500, "JS Engine crashed"));
+    }
+
+    let channel_timeout = SandboxConfig::default().channel_timeout();
+    match tokio::time::timeout(channel_timeout, rx).await {
+        Ok(Ok(Ok(out))) => Ok(Ok(out.value == Value::Bool(true))),
+        Ok(Ok(Err(e))) => Ok(Err(e.to_string())),
+        Ok(Err(_)) => Err(NodeError::permanent(500, "JS runtime crashed during loop condition; it has been restarted")),
+        Err(_) => Err(NodeError::permanent(
+            500,
+            format!("Loop condition timeout ({}ms)", channel_timeout.as_millis()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{NodeType, WorkerJob};
+
+    fn data(body: &str, max_iterations: u32) -> LoopNodeData {
+        LoopNodeData { body: body.to_string(), condition: "output.done".to_string(), max_iterations }
+    }
+
+    #[test]
+    fn test_validate_caps_iterations() {
+        assert_eq!(validate("loop", &data("poll", 5), 1000), Ok(5));
+        assert_eq!(validate("loop", &data("poll", 1_000_000), 1000), Ok(1000));
+        assert!(validate("loop", &data("poll", 0), 1000).is_err());
+        assert!(validate("loop", &data("loop", 5), 1000).is_err());
+        assert!(validate("loop", &data("", 5), 1000).is_err());
+    }
+
+    #[test]
+    fn test_loop_decision() {
+        assert_eq!(loop_decision(true, 1, 5), Some(true));
+        assert_eq!(loop_decision(false, 4, 5), None);
+        // The cap ends the loop even though the condition never held
+        assert_eq!(loop_decision(false, 5, 5), Some(false));
+    }
+
+    #[test]
+    fn test_iteration_complete_job_round_trips() {
+        let marker = LoopIteration {
+            loop_node_id: "loop".to_string(),
+            iteration: 2,
+            condition: "output.done".to_string(),
+            max_iterations: 5,
+        };
+        let job: WorkerJob = serde_json::from_value(iteration_complete_job(
+            &marker,
+            &Uuid::nil(),
+            "poll",
+            false,
+            Some(json!({ "error": "HTTP 500" })),
+        ))
+        .unwrap();
+
+        assert_eq!(job.id, "loop");
+        assert_eq!(job.idempotency_key.as_deref(), Some(format!("loop:{}:loop:2", Uuid::nil()).as_str()));
+        match job.node {
+            NodeType::LoopIterationComplete(data) => {
+                assert_eq!(data.body_node_id, "poll");
+                assert_eq!(data.iteration, 2);
+                assert_eq!(data.error.as_deref(), Some("HTTP 500"));
+            }
+            other => panic!("unexpected node {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_condition_js_reads_output_and_iteration() {
+        let runtime = rquickjs::AsyncRuntime::new().unwrap();
+        let ctx = rquickjs::AsyncContext::full(&runtime).await.unwrap();
+        let run = |condition: &str, output: Value, iteration: u32| {
+            let inputs = json!({ "condition": condition, "output": output, "iteration": iteration });
+            crate::nodes::code::run_js_safely(&ctx, CONDITION_JS.to_string(), Some(inputs))
+        };
+
+        assert_eq!(run("output.body.status === 'done'", json!({"body": {"status": "done"}}), 1).await.unwrap().value, true);
+        assert_eq!(run("output.body.status === 'done'", json!({"body": {"status": "pending"}}), 1).await.unwrap().value, false);
+        assert_eq!(run("iteration >= 3", Value::Null, 3).await.unwrap().value, true);
+        assert!(run("output.missing.field", json!({}), 1).await.is_err());
+    }
+}
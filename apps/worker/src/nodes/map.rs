@@ -2,12 +2,34 @@
 //!
 //! Executes a workflow for each item in an array with configurable concurrency.
 //! Uses the suspension pattern similar to SubFlow, but manages multiple children.
+//!
+//! On top of each batch's own `concurrency`, a `ChildLimiter`
+//! (`MAP_MAX_INFLIGHT_CHILDREN`) counted in Redis bounds how many map children
+//! are in flight across all batches, runs and workers.
+//!
+//! With `adaptive_concurrency`, the batch's `concurrency_limit` is re-tuned
+//! after every window of completions (see `adapt_concurrency`); all spawn
+//! paths read that column, so the new limit applies to the next spawn.
+//!
+//! Children are enqueued on the low-priority stream (see `priority`). The
+//! batch records the Map job's own priority, which the scheduler's recovery
+//! and timeout jobs for it keep.
+//!
+//! With `fail_fast`, the first failed child finishes the batch and cancels the
+//! children still in flight (`cancel:{child_run_id}`); their results are ignored.
+//!
+//! Progress is also logged as NODE_PROGRESS milestones, which survive stream
+//! trimming: one when the first wave spawns and one per 10% of finished children.
 
-use crate::types::{MapNodeData, MapStepData, MapChildCompleteData, ExecutionResult};
-use crate::events::{log_event_with_retry, EventType};
+use crate::priority;
+use crate::types::{MapNodeData, MapStepData, MapChildCompleteData, ExecutionResult, JobPriority, ResumeVia, Suspension, SuspensionKind};
+use crate::events::{self, log_event_with_retry, EventType};
+use crate::streaming::{ProgressSink, StreamContext};
 use chrono;
+use serde::Serialize;
 use serde_json::json;
 use sqlx::PgPool;
+use std::sync::Arc;
 use uuid::Uuid;
 
 /// Error type for map operations
@@ -17,6 +39,7 @@ pub enum MapError {
     DatabaseError(String),
     ExecutionError(String),
     Cancelled(String),
+    TooManyItems { count: usize, limit: usize },
 }
 
 impl std::fmt::Display for MapError {
@@ -28,7 +51,233 @@ impl std::fmt::Display for MapError {
             MapError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             MapError::ExecutionError(msg) => write!(f, "Execution error: {}", msg),
             MapError::Cancelled(msg) => write!(f, "Cancelled: {}", msg),
+            MapError::TooManyItems { count, limit } => {
+                write!(f, "Map has {} items, more than the allowed {} (MAP_MAX_ITEMS)", count, limit)
+            }
+    }
+    }
+}
+
+/// `item_index` the scheduler uses for "finalize this batch now" jobs
+/// (timeouts, stuck children, or a missed completion)
+pub const FINALIZE_MARKER_INDEX: i32 = -1;
+
+/// Default number of completions between cancellation checks
+const DEFAULT_CANCEL_CHECK_EVERY: u32 = 10;
+
+/// Completions between cancellation checks (`MAP_CANCEL_CHECK_EVERY`, min 1)
+fn cancel_check_every() -> u32 {
+    std::env::var("MAP_CANCEL_CHECK_EVERY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CANCEL_CHECK_EVERY)
+        .max(1)
+}
+
+/// Default upper bound on a single Map node's `items`
+const DEFAULT_MAX_ITEMS: usize = 100_000;
+
+/// Largest `items` array a Map node may fan out over (`MAP_MAX_ITEMS`, 0 = no limit)
+fn max_items() -> usize {
+    std::env::var("MAP_MAX_ITEMS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_ITEMS)
+}
+
+/// Reject batches larger than `limit` before anything is written.
+fn check_item_count(count: usize, limit: usize) -> Result<(), MapError> {
+    if limit > 0 && count > limit {
+        return Err(MapError::TooManyItems { count, limit });
+    }
+    Ok(())
+}
+
+/// Whether the completion that brought the batch to `total_finished` should
+/// check for run cancellation. The first completion always checks, so batches
+/// smaller than the interval still get at least one check.
+fn should_check_cancellation(total_finished: i32, every: u32) -> bool {
+    let every = every.max(1) as i32;
+    total_finished == 1 || total_finished % every == 0
+}
+
+/// Default cap on in-flight map children across all workers
+const DEFAULT_MAX_INFLIGHT_CHILDREN: usize = 1000;
+
+/// Redis hash holding the in-flight child counts: `total` across all batches,
+/// plus one field per running batch (its id) with that batch's share
+const INFLIGHT_KEY: &str = "map:inflight_children";
+
+/// Grants up to ARGV[2] slots to batch ARGV[1] (see `grant`) and counts them.
+/// KEYS[1] = INFLIGHT_KEY; ARGV = batch id, wanted, max, batch_active.
+const ACQUIRE_SCRIPT: &str = r#"
+local total = tonumber(redis.call('HGET', KEYS[1], 'total') or '0')
+local wanted = tonumber(ARGV[2])
+local granted = math.min(wanted, math.max(tonumber(ARGV[3]) - total, 0))
+if granted == 0 and wanted > 0 and tonumber(ARGV[4]) <= 0 then
+    granted = 1
+end
+if granted > 0 then
+    redis.call('HINCRBY', KEYS[1], 'total', granted)
+    redis.call('HINCRBY', KEYS[1], ARGV[1], granted)
+end
+return granted
+"#;
+
+/// Gives back up to ARGV[2] slots of batch ARGV[1] (all of them when ARGV[2]
+/// is -1), never more than the batch holds, so late or duplicate releases
+/// can't drive the total below what's really in flight.
+/// KEYS[1] = INFLIGHT_KEY; ARGV = batch id, count.
+const RELEASE_SCRIPT: &str = r#"
+local held = tonumber(redis.call('HGET', KEYS[1], ARGV[1]) or '0')
+local count = tonumber(ARGV[2])
+if count < 0 or count > held then
+    count = held
+end
+if count > 0 then
+    redis.call('HINCRBY', KEYS[1], 'total', -count)
+end
+if held - count <= 0 then
+    redis.call('HDEL', KEYS[1], ARGV[1])
+else
+    redis.call('HINCRBY', KEYS[1], ARGV[1], -count)
+end
+return count
+"#;
+
+/// Slots a batch with `batch_active` children in flight gets when it asks for
+/// `wanted` and `in_flight` of `max` are taken. A batch with nothing in flight
+/// always gets one, since no completion would otherwise come along to spawn
+/// it (the total may overdraft `max` by that child).
+fn grant(wanted: usize, in_flight: usize, max: usize, batch_active: i32) -> usize {
+    let granted = wanted.min(max.saturating_sub(in_flight));
+    if granted == 0 && wanted > 0 && batch_active <= 0 {
+        return 1;
+    }
+    granted
+}
+
+/// Bound on in-flight map children across all workers, counted in Redis.
+///
+/// Each spawn adds its children to the total and to its batch's count; a
+/// recorded MAPCHILDCOMPLETE takes one back off. When a batch finishes
+/// (completed, failed, fail_fast, timed out or cancelled) whatever it still
+/// holds is dropped, so children that never report (cancelled, crashed
+/// workers) don't leak slots. Spawning never waits: a batch gets as many
+/// children as `grant` allows and the remaining slots are retried on its next
+/// completion.
+///
+/// If Redis is unreachable the limiter grants by `grant` with nothing counted
+/// (one child for an idle batch, none otherwise), and a failed release is
+/// picked up when the batch finishes.
+#[derive(Clone)]
+pub struct ChildLimiter {
+    max: usize,
+    acquire_script: Arc<redis::Script>,
+    release_script: Arc<redis::Script>,
+}
+
+impl ChildLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            acquire_script: Arc::new(redis::Script::new(ACQUIRE_SCRIPT)),
+            release_script: Arc::new(redis::Script::new(RELEASE_SCRIPT)),
+        }
+    }
+
+    /// Limit from `MAP_MAX_INFLIGHT_CHILDREN`
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("MAP_MAX_INFLIGHT_CHILDREN")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_INFLIGHT_CHILDREN),
+        )
     }
+
+    /// Claim up to `wanted` slots for a batch that has `batch_active`
+    /// children in flight. Returns how many children may be spawned.
+    pub async fn acquire(&self, redis: &redis::Client, batch_id: &Uuid, wanted: usize, batch_active: i32) -> usize {
+        if wanted == 0 {
+            return 0;
+        }
+        let granted: redis::RedisResult<usize> = async {
+            let mut conn = redis.get_multiplexed_async_connection().await?;
+            self.acquire_script
+                .key(INFLIGHT_KEY)
+                .arg(batch_id.to_string())
+                .arg(wanted)
+                .arg(self.max)
+                .arg(batch_active)
+                .invoke_async(&mut conn)
+                .await
+        }
+        .await;
+        granted.unwrap_or_else(|e| {
+            tracing::warn!(%batch_id, "Map: in-flight child count unavailable: {}", e);
+            grant(wanted, self.max, self.max, batch_active)
+        })
+    }
+
+    /// Return slots for `count` finished (or never spawned) children of a batch.
+    pub async fn release(&self, redis: &redis::Client, batch_id: &Uuid, count: usize) {
+        if count > 0 {
+            self.invoke_release(redis, batch_id, count as i64).await;
+        }
+    }
+
+    /// Return every slot a finished batch still holds.
+    pub async fn release_batch(&self, redis: &redis::Client, batch_id: &Uuid) {
+        self.invoke_release(redis, batch_id, -1).await;
+    }
+
+    async fn invoke_release(&self, redis: &redis::Client, batch_id: &Uuid, count: i64) {
+        let released: redis::RedisResult<i64> = async {
+            let mut conn = redis.get_multiplexed_async_connection().await?;
+            self.release_script
+                .key(INFLIGHT_KEY)
+                .arg(batch_id.to_string())
+                .arg(count)
+                .invoke_async(&mut conn)
+                .await
+        }
+        .await;
+        if let Err(e) = released {
+            tracing::warn!(%batch_id, "Map: failed to release in-flight children: {}", e);
+        }
+    }
+}
+
+/// Counters and spawn metadata a child completion's UPDATE returns:
+/// completed, failed, active, total, fail_fast, current_index, concurrency,
+/// child workflow, child version, items, child graph, child depth, node id,
+/// adaptive window (if adaptive) and stream_results
+type ChildCompleteRow = (
+    i32, i32, i32, i32, bool, i32, i32, i32, String, serde_json::Value, serde_json::Value, i32, String,
+    Option<serde_json::Value>, bool,
+);
+
+/// Batch state a MAPSTEP locks: current_index, active, concurrency, total,
+/// child workflow, items, child version and status
+type StepRow = (i32, i32, i32, i32, i32, serde_json::Value, String, String);
+
+/// What a MAPCHILDCOMPLETE's `item_index` refers to.
+#[derive(Debug, PartialEq)]
+enum ChildIndex {
+    /// Scheduler asked us to finalize the batch
+    FinalizeMarker,
+    /// A real child result
+    Item(i32),
+    /// Any other negative index (never produced by us)
+    Invalid,
+}
+
+fn classify_item_index(item_index: i32) -> ChildIndex {
+    match item_index {
+        FINALIZE_MARKER_INDEX => ChildIndex::FinalizeMarker,
+        i if i >= 0 => ChildIndex::Item(i),
+        _ => ChildIndex::Invalid,
     }
 }
 
@@ -46,8 +295,8 @@ async fn is_run_cancelled(pool: &PgPool, run_id: &Uuid) -> bool {
     matches!(result, Some((status,)) if status == "cancelled")
 }
 
-/// Cancel a batch operation and mark it as cancelled
-async fn cancel_batch(pool: &PgPool, batch_id: &Uuid) -> Result<(), MapError> {
+/// Cancel a batch operation, mark it as cancelled and give back its in-flight slots
+async fn cancel_batch(pool: &PgPool, redis: &redis::Client, limiter: &ChildLimiter, batch_id: &Uuid) -> Result<(), MapError> {
     sqlx::query(
         "UPDATE batch_operations SET status = 'cancelled', completed_at = NOW() WHERE id = $1 AND status = 'running'"
     )
@@ -55,19 +304,132 @@ async fn cancel_batch(pool: &PgPool, batch_id: &Uuid) -> Result<(), MapError> {
     .execute(pool)
     .await
     .map_err(|e| MapError::DatabaseError(e.to_string()))?;
+    limiter.release_batch(redis, batch_id).await;
     
     Ok(())
 }
 
+/// Stop a fail-fast batch's children that are still in flight: mark their
+/// runs cancelled and publish `cancel:{child_run_id}` so workers abort them.
+/// Their slots went back when the batch finished, and their results will be
+/// ignored. Returns how many children were cancelled.
+async fn cancel_active_children(
+    pool: &PgPool,
+    redis: &redis::Client,
+    batch_id: &Uuid,
+    run_id: &Uuid,
+    node_id: &str,
+) -> Result<usize, MapError> {
+    let cancelled: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        UPDATE workflow_runs r SET status = 'cancelled', completed_at = NOW()
+        WHERE r.parent_run_id = $1 AND r.parent_node_id = $2
+          AND r.status NOT IN ('completed', 'failed', 'cancelled')
+          AND NOT EXISTS (
+              SELECT 1 FROM batch_results br WHERE br.batch_id = $3 AND br.child_run_id = r.id
+          )
+        RETURNING r.id
+        "#
+    )
+    .bind(run_id)
+    .bind(node_id)
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| MapError::DatabaseError(e.to_string()))?;
+
+    if cancelled.is_empty() {
+        return Ok(0);
+    }
+
+    let mut conn = redis.get_multiplexed_async_connection().await
+        .map_err(|e| MapError::ExecutionError(format!("Redis connection error: {}", e)))?;
+    let mut pipe = redis::pipe();
+    for child_run_id in &cancelled {
+        pipe.cmd("PUBLISH").arg(format!("cancel:{}", child_run_id)).arg("fail_fast").ignore();
+    }
+    pipe.query_async::<()>(&mut conn).await
+        .map_err(|e| MapError::ExecutionError(format!("Redis pipeline error: {}", e)))?;
+
+    tracing::info!(%batch_id, cancelled = cancelled.len(), "Map: fail_fast cancelled in-flight children");
+    Ok(cancelled.len())
+}
+
 impl std::error::Error for MapError {}
 
+/// Chunk type of a streamed child result (`stream_results`)
+const ITEM_CHUNK_TYPE: &str = "item";
+
+/// A child's result as a stream chunk: its index (1 + item index, so every
+/// item has its own and 0 stays the batch's first progress chunk) and content.
+fn item_chunk(data: &MapChildCompleteData) -> (usize, String) {
+    let content = json!({
+        "index": data.item_index,
+        "success": data.success,
+        "output": data.output,
+        "error": data.error,
+        "child_run_id": data.child_run_id,
+    });
+    (data.item_index.max(0) as usize + 1, content.to_string())
+}
+
+/// Report how many children a spawn wave launched out of the batch total.
+async fn emit_spawn_progress(sink: &impl ProgressSink, spawned: usize, total: usize) {
+    sink.progress(&format!("Spawning {}/{}", spawned, total)).await;
+}
+
+/// Finished children between NODE_PROGRESS milestones, as a percentage of the batch
+const PROGRESS_MILESTONE_PERCENT: i64 = 10;
+
+/// The percentage a batch's `finished`th finished child brings it to, if
+/// that crosses a `PROGRESS_MILESTONE_PERCENT` step (so a batch logs at most
+/// ten milestones however many items it has).
+fn completion_milestone(finished: i32, total: i32) -> Option<i64> {
+    if total <= 0 || finished <= 0 {
+        return None;
+    }
+    let step = |n: i32| n as i64 * 100 / total as i64 / PROGRESS_MILESTONE_PERCENT;
+    let reached = step(finished.min(total));
+    (reached > step(finished - 1)).then_some(reached * PROGRESS_MILESTONE_PERCENT)
+}
+
+/// Claim slots for a new batch's first children, spawn them with `spawn`, and
+/// report the wave. Slots are handed back if spawning fails.
+async fn launch_initial_wave(
+    redis: &redis::Client,
+    limiter: &ChildLimiter,
+    batch_id: &Uuid,
+    wanted: usize,
+    total: usize,
+    spawn: impl AsyncFnOnce(usize) -> Result<(), MapError>,
+    progress: Option<&impl ProgressSink>,
+) -> Result<usize, MapError> {
+    let count = limiter.acquire(redis, batch_id, wanted, 0).await;
+    if let Err(e) = spawn(count).await {
+        limiter.release(redis, batch_id, count).await;
+        return Err(e);
+    }
+
+    // Let the UI show the initial wave immediately instead of waiting for the first completion
+    if let Some(sink) = progress {
+        emit_spawn_progress(sink, count, total).await;
+        sink.milestone(json!({ "milestone": "spawned", "spawned": count, "total": total })).await;
+    }
+    Ok(count)
+}
+
 /// Initialize a Map operation: create batch record and spawn initial children
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_map_init(
     pool: &PgPool,
+    redis: &redis::Client,
+    limiter: &ChildLimiter,
     run_id: &Uuid,
     node_id: &str,
+    priority: JobPriority,
     data: &MapNodeData,
     retry_count: u32,
+    progress: Option<&impl ProgressSink>,
 ) -> Result<ExecutionResult, MapError> {
     let start = std::time::Instant::now();
     
@@ -84,6 +446,11 @@ pub async fn handle_map_init(
         });
     }
     
+    if let Err(e) = check_item_count(data.items.len(), max_items()) {
+        tracing::warn!(node_id, "Map: Rejected {} items (limit {})", data.items.len(), max_items());
+        return Err(e);
+    }
+
     let total_items = data.items.len() as i32;
     if total_items == 0 {
         // Empty array - complete immediately with empty results
@@ -106,12 +473,19 @@ pub async fn handle_map_init(
                 .as_millis() as u64,
             duration_ms: start.elapsed().as_millis() as u64,
             isolated: false,
+            route_to: None,
+            warnings: Vec::new(),
         });
     }
     
     // Create batch_operations record
     let batch_id = Uuid::new_v4();
-    let concurrency = data.concurrency.min(200).max(1) as i32; // Raised from 50 to 200
+    let concurrency = data.concurrency.clamp(1, 200) as i32; // Raised from 50 to 200
+    let max_concurrency = data
+        .max_concurrency
+        .map(|m| m as i32)
+        .unwrap_or(concurrency * 4)
+        .clamp(concurrency, 200);
     
     // Convert version_id string to UUID
     let version_uuid = data.version_id.as_ref().and_then(|v| Uuid::parse_str(v).ok());
@@ -164,8 +538,9 @@ pub async fn handle_map_init(
         r#"
         INSERT INTO batch_operations (
             id, run_id, node_id, total_items, concurrency_limit, fail_fast, timeout_ms,
-            input_items, child_workflow_id, child_version_id, child_graph, child_depth, status
-        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, 'running')
+            input_items, child_workflow_id, child_version_id, child_graph, child_depth, status,
+            adaptive_concurrency, max_concurrency, stream_results, priority
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, 'running', $13, $14, $15, $16)
         "#
     )
     .bind(batch_id)
@@ -180,10 +555,22 @@ pub async fn handle_map_init(
     .bind(version_uuid)
     .bind(&child_graph)  // Cached graph
     .bind(child_depth)   // Cached depth
+    .bind(data.adaptive_concurrency)
+    .bind(max_concurrency)
+    .bind(data.stream_results)
+    .bind(priority.as_str())
     .execute(pool)
     .await
     .map_err(|e| MapError::DatabaseError(e.to_string()))?;
     
+    let suspension = Suspension::new(
+        SuspensionKind::Map,
+        ResumeVia::ChildRuns,
+        data.timeout_ms.and_then(events::expires_in),
+        json!({ "batch_id": batch_id.to_string(), "total_items": total_items, "concurrency": concurrency }),
+    )
+    .to_value();
+
     // Log node suspended event
     let _ = log_event_with_retry(
         pool,
@@ -194,13 +581,22 @@ pub async fn handle_map_init(
         json!({
             "batch_id": batch_id.to_string(),
             "total_items": total_items,
-            "concurrency": concurrency
+            "concurrency": concurrency,
+            "suspension": suspension,
         }),
     ).await;
     
-    // Spawn initial batch of children
-    let initial_count = (concurrency as usize).min(data.items.len());
-    spawn_children(pool, &batch_id, run_id, data, 0, initial_count).await?;
+    // Spawn initial batch of children (as many as the worker-wide limit allows)
+    let initial_count = launch_initial_wave(
+        redis,
+        limiter,
+        &batch_id,
+        (concurrency as usize).min(data.items.len()),
+        data.items.len(),
+        async |count| spawn_children(pool, redis, &batch_id, run_id, data, 0, count).await,
+        progress,
+    )
+    .await?;
     
     // Update current_index
     sqlx::query("UPDATE batch_operations SET current_index = $1, active_count = $2 WHERE id = $3")
@@ -220,7 +616,8 @@ pub async fn handle_map_init(
             "batch_id": batch_id.to_string(),
             "status": "running",
             "total": total_items,
-            "spawned": initial_count
+            "spawned": initial_count,
+            "suspension": suspension,
         })),
         timestamp: std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -228,46 +625,50 @@ pub async fn handle_map_init(
             .as_millis() as u64,
         duration_ms: start.elapsed().as_millis() as u64,
         isolated: false,
+        route_to: None,
+        warnings: Vec::new(),
     })
 }
 
 /// Handle child completion: record result, update counters, spawn next or complete
 pub async fn handle_child_complete(
     pool: &PgPool,
-    _redis: &redis::Client, // No longer used - children spawned directly, not via MAPSTEP
+    redis: &redis::Client,
+    limiter: &ChildLimiter,
     run_id: &Uuid,
     node_id: &str,
     data: &MapChildCompleteData,
+    stream_ctx: Option<&StreamContext>,
 ) -> Result<ExecutionResult, MapError> {
     let start = std::time::Instant::now();
     let batch_id = Uuid::parse_str(&data.batch_id)
         .map_err(|e| MapError::ExecutionError(format!("Invalid batch_id: {}", e)))?;
     
-    // Check if this is a timeout marker from the scheduler (item_index = -1)
-    if data.item_index == -1 {
-        // Batch timed out - complete it with whatever results we have
-        return complete_batch(pool, run_id, node_id, &batch_id, true, start).await;
+    match classify_item_index(data.item_index) {
+        // Finalize with whatever results we have. A successful marker means all
+        // results are in but completion was missed; a failed one is a timeout/stuck batch.
+        ChildIndex::FinalizeMarker => {
+            return complete_batch(pool, redis, limiter, run_id, node_id, &batch_id, !data.success, start).await;
+        }
+        ChildIndex::Invalid => {
+            return Err(MapError::ExecutionError(format!("Invalid item_index: {}", data.item_index)));
+        }
+        ChildIndex::Item(_) => {}
     }
     
-    // Check cancellation periodically (every ~10 completions) to reduce DB queries
-    // Use item_index % 10 as a cheap way to sample
-    let run_cancelled = if data.item_index % 10 == 0 {
-        is_run_cancelled(pool, run_id).await
-    } else {
-        false
-    };
-    
     // Insert result into batch_results (append-only, no locking)
-    // ON CONFLICT DO NOTHING means duplicates are silently ignored
+    // ON CONFLICT DO NOTHING means duplicates are silently ignored, and so are
+    // results arriving after the batch finished (e.g. children cancelled by fail_fast)
     let insert_result = sqlx::query(
         r#"
         INSERT INTO batch_results (batch_id, item_index, child_run_id, status, output, error_message)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        SELECT $1, $2, $3, $4, $5, $6
+        WHERE EXISTS (SELECT 1 FROM batch_operations WHERE id = $1 AND status = 'running')
         ON CONFLICT (batch_id, item_index) DO NOTHING
         "#
     )
     .bind(batch_id)
-    .bind(data.item_index as i32)
+    .bind(data.item_index)
     .bind(Uuid::parse_str(&data.child_run_id).ok())
     .bind(if data.success { "completed" } else { "failed" })
     .bind(&data.output)
@@ -278,8 +679,11 @@ pub async fn handle_child_complete(
     
     // Check if this was a duplicate (no row inserted)
     // If rows_affected() == 0, the ON CONFLICT triggered and we should skip counter updates
-    if insert_result.rows_affected() == 0 {
-        // This is a duplicate MAPCHILDCOMPLETE - fetch current state
+    if insert_result.rows_affected() > 0 {
+        // First report for this child: its slot goes back
+        limiter.release(redis, &batch_id, 1).await;
+    } else {
+        // A duplicate MAPCHILDCOMPLETE, or a late one for a finished batch - fetch current state
         let (completed_count, failed_count, total_items, status): (i32, i32, i32, String) = sqlx::query_as(
             "SELECT completed_count, failed_count, total_items, status FROM batch_operations WHERE id = $1"
         )
@@ -293,7 +697,7 @@ pub async fn handle_child_complete(
         // BUG FIX: Check if batch should be completed (might have been missed due to race)
         if status == "running" && total_finished >= total_items {
             // Batch is actually done but wasn't marked complete - fix it now
-            return complete_batch(pool, run_id, node_id, &batch_id, false, start).await;
+            return complete_batch(pool, redis, limiter, run_id, node_id, &batch_id, false, start).await;
         }
         
         // Return current progress (idempotent response)
@@ -308,7 +712,8 @@ pub async fn handle_child_complete(
                 "failed": failed_count,
                 "total": total_items,
                 "progress": (total_finished as f64) / (total_items as f64),
-                "duplicate": true
+                "duplicate": status == "running",
+                "ignored": status != "running"
             })),
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -316,21 +721,31 @@ pub async fn handle_child_complete(
                 .as_millis() as u64,
             duration_ms: start.elapsed().as_millis() as u64,
             isolated: true,
+            route_to: None,
+            warnings: Vec::new(),
         });
     }
     
     // Atomically update counters AND get all fields needed for spawning (eliminates ALL extra queries)
     let (completed_count, failed_count, active_count, total_items, fail_fast, current_index, concurrency, 
-         workflow_id, version_id_str, input_items, child_graph, child_depth, batch_node_id): 
-        (i32, i32, i32, i32, bool, i32, i32, i32, String, serde_json::Value, serde_json::Value, i32, String) = if data.success {
+         workflow_id, version_id_str, input_items, child_graph, child_depth, batch_node_id, adaptive_window, stream_results): 
+        ChildCompleteRow = if data.success {
         sqlx::query_as(
             r#"
             UPDATE batch_operations 
-            SET completed_count = completed_count + 1, active_count = active_count - 1
+            SET completed_count = completed_count + 1, active_count = active_count - 1,
+                window_completed = window_completed + 1
             WHERE id = $1
             RETURNING completed_count, failed_count, active_count, total_items, fail_fast, current_index, 
                       concurrency_limit, child_workflow_id, COALESCE(child_version_id::text, ''), input_items,
-                      COALESCE(child_graph, '{}'), COALESCE(child_depth, 1), node_id
+                      COALESCE(child_graph, '{}'), COALESCE(child_depth, 1), node_id,
+                      CASE WHEN adaptive_concurrency THEN jsonb_build_object(
+                          'completed', window_completed, 'failed', window_failed,
+                          'elapsed_ms', (EXTRACT(EPOCH FROM (NOW() - window_started_at)) * 1000)::bigint,
+                          'seq', window_seq, 'latency_ms', window_latency_ms,
+                          'max', COALESCE(max_concurrency, concurrency_limit)
+                      ) END,
+                      stream_results
             "#
         )
         .bind(batch_id)
@@ -341,11 +756,19 @@ pub async fn handle_child_complete(
         sqlx::query_as(
             r#"
             UPDATE batch_operations 
-            SET failed_count = failed_count + 1, active_count = active_count - 1
+            SET failed_count = failed_count + 1, active_count = active_count - 1,
+                window_failed = window_failed + 1
             WHERE id = $1
             RETURNING completed_count, failed_count, active_count, total_items, fail_fast, current_index, 
                       concurrency_limit, child_workflow_id, COALESCE(child_version_id::text, ''), input_items,
-                      COALESCE(child_graph, '{}'), COALESCE(child_depth, 1), node_id
+                      COALESCE(child_graph, '{}'), COALESCE(child_depth, 1), node_id,
+                      CASE WHEN adaptive_concurrency THEN jsonb_build_object(
+                          'completed', window_completed, 'failed', window_failed,
+                          'elapsed_ms', (EXTRACT(EPOCH FROM (NOW() - window_started_at)) * 1000)::bigint,
+                          'seq', window_seq, 'latency_ms', window_latency_ms,
+                          'max', COALESCE(max_concurrency, concurrency_limit)
+                      ) END,
+                      stream_results
             "#
         )
         .bind(batch_id)
@@ -356,36 +779,75 @@ pub async fn handle_child_complete(
     
     let version_id = if version_id_str.is_empty() { None } else { Some(version_id_str) };
     let _ = batch_node_id; // Used for reference, node_id comes from function param
+
+    // Live results grid: this child's result, before the batch may finish below
+    if stream_results && let Some(ctx) = stream_ctx {
+        let (index, content) = item_chunk(data);
+        ctx.send_chunk_at(index, ITEM_CHUNK_TYPE, &content).await;
+    }
     
     let total_finished = completed_count + failed_count;
+
+    if let Some(ctx) = stream_ctx
+        && let Some(percent) = completion_milestone(total_finished, total_items)
+    {
+        ctx.milestone(json!({
+            "milestone": "items_finished",
+            "percent": percent,
+            "completed": completed_count,
+            "failed": failed_count,
+            "total": total_items,
+        }))
+        .await;
+    }
     
-    // Check if fail_fast triggered
+    // Check if fail_fast triggered: finish the batch, then stop what's still running
     if fail_fast && failed_count > 0 {
-        return complete_batch(pool, run_id, node_id, &batch_id, true, start).await;
+        let result = complete_batch(pool, redis, limiter, run_id, node_id, &batch_id, true, start).await?;
+        if let Err(e) = cancel_active_children(pool, redis, &batch_id, run_id, node_id).await {
+            tracing::warn!(%batch_id, "Map: failed to cancel in-flight children: {}", e);
+        }
+        return Ok(result);
     }
     
     // Check if all done
     if total_finished >= total_items {
-        return complete_batch(pool, run_id, node_id, &batch_id, false, start).await;
+        return complete_batch(pool, redis, limiter, run_id, node_id, &batch_id, false, start).await;
     }
     
+    // Adaptive batches re-tune their limit once a window of completions is in
+    let concurrency = match adaptive_window {
+        Some(window) => adapt_batch_concurrency(pool, &batch_id, concurrency, &window).await?,
+        None => concurrency,
+    };
+
+    // Check cancellation periodically to reduce DB queries (always on the first completion)
+    let run_cancelled = should_check_cancellation(total_finished, cancel_check_every())
+        && is_run_cancelled(pool, run_id).await;
+    
     // Spawn more children DIRECTLY using CACHED metadata (0 extra queries!)
     if !run_cancelled && active_count < concurrency && current_index < total_items {
         // Calculate how many to spawn
         let slots_available = (concurrency - active_count).max(0) as usize;
         let items_remaining = (total_items - current_index).max(0) as usize;
-        let to_spawn = slots_available.min(items_remaining);
+        let to_spawn = limiter.acquire(redis, &batch_id, slots_available.min(items_remaining), active_count).await;
         
         if to_spawn > 0 {
             // Parse input items
-            let items: Vec<serde_json::Value> = serde_json::from_value(input_items.clone())
-                .map_err(|e| MapError::ExecutionError(format!("Invalid input_items: {}", e)))?;
+            let items: Vec<serde_json::Value> = match serde_json::from_value(input_items.clone()) {
+                Ok(items) => items,
+                Err(e) => {
+                    limiter.release(redis, &batch_id, to_spawn).await;
+                    return Err(MapError::ExecutionError(format!("Invalid input_items: {}", e)));
+                }
+            };
             
             let version_uuid = version_id.as_ref().and_then(|v| Uuid::parse_str(v).ok());
             
             // Spawn using CACHED graph/depth (no DB queries!)
-            spawn_children_cached(
+            let spawned = spawn_children_cached(
                 pool,
+                redis,
                 &batch_id,
                 run_id,
                 node_id,
@@ -396,7 +858,11 @@ pub async fn handle_child_complete(
                 &items,
                 current_index as usize,
                 to_spawn,
-            ).await?;
+            ).await;
+            if let Err(e) = spawned {
+                limiter.release(redis, &batch_id, to_spawn).await;
+                return Err(e);
+            }
             
             // Update batch state atomically
             sqlx::query(
@@ -411,7 +877,7 @@ pub async fn handle_child_complete(
         }
     } else if run_cancelled {
         // Mark batch as cancelled if we detected cancellation
-        cancel_batch(pool, &batch_id).await?;
+        cancel_batch(pool, redis, limiter, &batch_id).await?;
     }
     
     // Return progress update (still running)
@@ -425,7 +891,8 @@ pub async fn handle_child_complete(
             "completed": completed_count,
             "failed": failed_count,
             "total": total_items,
-            "progress": (total_finished as f64) / (total_items as f64)
+            "progress": (total_finished as f64) / (total_items as f64),
+            "concurrency": concurrency
         })),
         timestamp: std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -433,6 +900,8 @@ pub async fn handle_child_complete(
             .as_millis() as u64,
         duration_ms: start.elapsed().as_millis() as u64,
         isolated: true,  // Don't trigger downstream yet
+        route_to: None,
+        warnings: Vec::new(),
     })
 }
 
@@ -442,6 +911,8 @@ pub async fn handle_child_complete(
 /// MAPSTEP jobs arrive simultaneously.
 pub async fn handle_map_step(
     pool: &PgPool,
+    redis: &redis::Client,
+    limiter: &ChildLimiter,
     run_id: &Uuid,
     node_id: &str,
     data: &MapStepData,
@@ -453,7 +924,7 @@ pub async fn handle_map_step(
     // Check if run has been cancelled - don't spawn more children
     if is_run_cancelled(pool, run_id).await {
         // Mark batch as cancelled
-        cancel_batch(pool, &batch_id).await?;
+        cancel_batch(pool, redis, limiter, &batch_id).await?;
         return Ok(ExecutionResult {
             node_id: node_id.to_string(),
             run_id: Some(run_id.to_string()),
@@ -465,6 +936,8 @@ pub async fn handle_map_step(
                 .as_millis() as u64,
             duration_ms: start.elapsed().as_millis() as u64,
             isolated: true,
+            route_to: None,
+            warnings: Vec::new(),
         });
     }
     
@@ -474,7 +947,7 @@ pub async fn handle_map_step(
         .map_err(|e| MapError::DatabaseError(format!("Failed to start transaction: {}", e)))?;
     
     // Lock the row and read current state
-    let batch_opt: Option<(i32, i32, i32, i32, i32, serde_json::Value, String, String)> = sqlx::query_as(
+    let batch_opt: Option<StepRow> = sqlx::query_as(
         r#"
         SELECT current_index, active_count, concurrency_limit, total_items, child_workflow_id, 
                input_items, COALESCE(child_version_id::text, ''), status
@@ -513,13 +986,15 @@ pub async fn handle_map_step(
                 .as_millis() as u64,
             duration_ms: start.elapsed().as_millis() as u64,
             isolated: true,
+            route_to: None,
+            warnings: Vec::new(),
         });
     }
     
     // Calculate how many to spawn
     let slots_available = (concurrency - active_count).max(0) as usize;
     let items_remaining = (total_items - current_index).max(0) as usize;
-    let to_spawn = slots_available.min(items_remaining);
+    let to_spawn = limiter.acquire(redis, &batch_id, slots_available.min(items_remaining), active_count).await;
     
     if to_spawn == 0 {
         tx.rollback().await.ok();
@@ -540,33 +1015,48 @@ pub async fn handle_map_step(
                 .as_millis() as u64,
             duration_ms: start.elapsed().as_millis() as u64,
             isolated: true,
+            route_to: None,
+            warnings: Vec::new(),
         });
     }
     
     // Atomically claim the slots by updating current_index and active_count
-    sqlx::query(
+    let claimed = sqlx::query(
         "UPDATE batch_operations SET current_index = $1, active_count = $2 WHERE id = $3"
     )
     .bind(current_index + to_spawn as i32)
     .bind(active_count + to_spawn as i32)
     .bind(batch_id)
     .execute(&mut *tx)
-    .await
-    .map_err(|e| MapError::DatabaseError(e.to_string()))?;
+    .await;
+    if let Err(e) = claimed {
+        limiter.release(redis, &batch_id, to_spawn).await;
+        return Err(MapError::DatabaseError(e.to_string()));
+    }
     
     // Commit the transaction - this releases the lock and makes our claim visible
-    tx.commit().await
-        .map_err(|e| MapError::DatabaseError(format!("Failed to commit transaction: {}", e)))?;
+    if let Err(e) = tx.commit().await {
+        limiter.release(redis, &batch_id, to_spawn).await;
+        return Err(MapError::DatabaseError(format!("Failed to commit transaction: {}", e)));
+    }
     
     // Now spawn the children (outside transaction, so other workers can proceed)
-    let items: Vec<serde_json::Value> = serde_json::from_value(input_items)
-        .map_err(|e| MapError::ExecutionError(format!("Invalid input_items: {}", e)))?;
+    let items: Vec<serde_json::Value> = match serde_json::from_value(input_items) {
+        Ok(items) => items,
+        Err(e) => {
+            limiter.release(redis, &batch_id, to_spawn).await;
+            return Err(MapError::ExecutionError(format!("Invalid input_items: {}", e)));
+        }
+    };
     
     let map_data = MapNodeData {
         workflow_id,
         version_id,
         items,
         concurrency: concurrency as u32,
+        adaptive_concurrency: false, // Tuned at batch level, not per-spawn
+        max_concurrency: None,
+        stream_results: false, // Streamed per completion, not per-spawn
         fail_fast: false,
         timeout_ms: None,  // Timeout is checked at batch level, not per-spawn
         current_depth: 0,
@@ -574,7 +1064,10 @@ pub async fn handle_map_step(
     };
     
     // Spawn children starting from current_index (the slots we claimed)
-    spawn_children(pool, &batch_id, run_id, &map_data, current_index as usize, to_spawn).await?;
+    if let Err(e) = spawn_children(pool, redis, &batch_id, run_id, &map_data, current_index as usize, to_spawn).await {
+        limiter.release(redis, &batch_id, to_spawn).await;
+        return Err(e);
+    }
     
     // Note: counters were already updated in the transaction above
     
@@ -593,6 +1086,8 @@ pub async fn handle_map_step(
             .as_millis() as u64,
         duration_ms: start.elapsed().as_millis() as u64,
         isolated: true,
+        route_to: None,
+        warnings: Vec::new(),
     })
 }
 
@@ -604,6 +1099,7 @@ pub async fn handle_map_step(
 /// - Redis pipelining for all job pushes
 async fn spawn_children(
     pool: &PgPool,
+    redis: &redis::Client,
     batch_id: &Uuid,
     parent_run_id: &Uuid,
     data: &MapNodeData,
@@ -717,12 +1213,8 @@ async fn spawn_children(
         .map_err(|e| MapError::DatabaseError(e.to_string()))?;
         
     // Build jobs for each starting node of each child run
-    // DIRECT REDIS PUSH: Skip HTTP orchestrator entirely
-    let redis_client = redis::Client::open(
-        std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string())
-    ).map_err(|e| MapError::ExecutionError(format!("Redis client error: {}", e)))?;
-    
-    let mut conn = redis_client.get_multiplexed_async_connection().await
+    // DIRECT REDIS PUSH: Skip HTTP orchestrator entirely (shared client)
+    let mut conn = redis.get_multiplexed_async_connection().await
         .map_err(|e| MapError::ExecutionError(format!("Redis connection error: {}", e)))?;
     
     // REDIS PIPELINING: Push all jobs in a single network round-trip
@@ -738,7 +1230,7 @@ async fn spawn_children(
         for start_node in &starting_nodes {
             if let Some(job) = build_child_job(start_node, child_run_id, &input_data) {
                 pipe.cmd("XADD")
-                    .arg("swiftgrid_stream")
+                    .arg(priority::STREAM_LOW)
                     .arg("*")
                     .arg("payload")
                     .arg(job);
@@ -758,8 +1250,10 @@ async fn spawn_children(
 /// This is the optimized version called from handle_child_complete.
 /// All metadata (graph, depth, node_id) comes from the UPDATE RETURNING,
 /// eliminating 3-4 SELECT queries per spawn batch.
+#[allow(clippy::too_many_arguments)] // everything the completion's UPDATE returned
 async fn spawn_children_cached(
     pool: &PgPool,
+    redis: &redis::Client,
     batch_id: &Uuid,
     parent_run_id: &Uuid,
     parent_node_id: &str,
@@ -830,12 +1324,8 @@ async fn spawn_children_cached(
     .await
     .map_err(|e| MapError::DatabaseError(e.to_string()))?;
     
-    // DIRECT REDIS PUSH with pipelining
-    let redis_client = redis::Client::open(
-        std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string())
-    ).map_err(|e| MapError::ExecutionError(format!("Redis client error: {}", e)))?;
-    
-    let mut conn = redis_client.get_multiplexed_async_connection().await
+    // DIRECT REDIS PUSH with pipelining (shared client)
+    let mut conn = redis.get_multiplexed_async_connection().await
         .map_err(|e| MapError::ExecutionError(format!("Redis connection error: {}", e)))?;
     
     let mut pipe = redis::pipe();
@@ -850,7 +1340,7 @@ async fn spawn_children_cached(
         for start_node in &starting_nodes {
             if let Some(job) = build_child_job(start_node, child_run_id, &input_data) {
                 pipe.cmd("XADD")
-                    .arg("swiftgrid_stream")
+                    .arg(priority::STREAM_LOW)
                     .arg("*")
                     .arg("payload")
                     .arg(job);
@@ -903,7 +1393,42 @@ fn build_child_job(node: &serde_json::Value, run_id: &Uuid, input_data: &serde_j
         result
     };
     
-    let job = match node_type {
+    let mut job = match node_type {
+        "websocket" => {
+            json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "WEBSOCKET",
+                    "data": {
+                        "url": process_string(node_data.get("url").and_then(|v| v.as_str()).unwrap_or("")),
+                        "send": node_data.get("send"),
+                        "collect_until": node_data.get("collectUntil"),
+                        "headers": node_data.get("headers")
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
+        "db-upsert" => {
+            json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "DBUPSERT",
+                    "data": {
+                        "table": node_data.get("table").and_then(|v| v.as_str()).unwrap_or(""),
+                        "rows": node_data.get("rows").cloned().unwrap_or(json!([])),
+                        "conflict_columns": node_data.get("conflictColumns").cloned().unwrap_or(json!([]))
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
         "code" | "code-execution" => {
             // Process inputs template
             let inputs_str = node_data.get("inputs").and_then(|v| v.as_str()).unwrap_or("{}");
@@ -917,7 +1442,8 @@ fn build_child_job(node: &serde_json::Value, run_id: &Uuid, input_data: &serde_j
                     "type": "CODE",
                     "data": {
                         "code": node_data.get("code").and_then(|v| v.as_str()).unwrap_or("return {};"),
-                        "inputs": inputs
+                        "inputs": inputs,
+                        "timeout_ms": node_data.get("timeoutMs")
                     }
                 },
                 "retry_count": 0,
@@ -935,7 +1461,19 @@ fn build_child_job(node: &serde_json::Value, run_id: &Uuid, input_data: &serde_j
                         "url": process_string(node_data.get("url").and_then(|v| v.as_str()).unwrap_or("")),
                         "method": node_data.get("method").and_then(|v| v.as_str()).unwrap_or("GET"),
                         "headers": node_data.get("headers"),
-                        "body": node_data.get("body")
+                        "query": node_data.get("query").and_then(|q| q.as_object()).map(|q| {
+                            q.iter()
+                                .map(|(k, v)| (k.clone(), json!(process_string(v.as_str().unwrap_or_default()))))
+                                .collect::<serde_json::Map<_, _>>()
+                        }),
+                        "body": node_data.get("body"),
+                        "body_encoding": node_data.get("bodyEncoding"),
+                        "content_type": node_data.get("contentType"),
+                        "stream_body": node_data.get("streamBody").and_then(|v| v.as_bool()).unwrap_or(false),
+                        "compress": node_data.get("compress"),
+                        "response_schema": node_data.get("responseSchema"),
+                        "max_response_bytes": node_data.get("maxResponseBytes"),
+                        "paginate": node_data.get("paginate")
                     }
                 },
                 "retry_count": 0,
@@ -945,13 +1483,233 @@ fn build_child_job(node: &serde_json::Value, run_id: &Uuid, input_data: &serde_j
         }
         _ => return None, // Skip unsupported node types for now
     };
+    // Bulk work: a big batch mustn't hold up interactive runs
+    job["priority"] = json!(JobPriority::Low);
     
     serde_json::to_string(&job).ok()
 }
 
 /// Complete the batch: aggregate results and return final output
+/// Fewest completions an adaptive window judges
+const MIN_ADAPTIVE_WINDOW: i32 = 10;
+
+/// Completions and elapsed time since an adaptive batch last re-tuned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AdaptiveWindow {
+    completed: i32,
+    failed: i32,
+    elapsed_ms: u64,
+}
+
+/// Completions before re-tuning: at least one full round of children.
+fn adaptive_window_size(concurrency: i32) -> i32 {
+    concurrency.max(MIN_ADAPTIVE_WINDOW)
+}
+
+/// The next concurrency limit after a window, plus the window's per-item latency.
+///
+/// AIMD, like TCP congestion control: more than 20% failures halves the
+/// limit; under 5% failures with latency no more than 1.5x the previous
+/// window's grows it by a quarter (at least one); anything in between holds.
+/// Latency comes from Little's Law on the window's throughput, the same
+/// math as the `stats` in the batch result.
+fn adapt_concurrency(current: i32, max: i32, window: AdaptiveWindow, previous_latency_ms: Option<u64>) -> (i32, u64) {
+    let finished = window.completed + window.failed;
+    let error_rate = if finished > 0 { window.failed as f64 / finished as f64 } else { 0.0 };
+    let latency_ms = avg_latency_ms(current, throughput(finished, window.elapsed_ms as f64 / 1000.0));
+    let latency_ok = previous_latency_ms.is_none_or(|prev| latency_ms as f64 <= prev as f64 * 1.5);
+
+    let next = if error_rate > 0.2 {
+        current / 2
+    } else if error_rate < 0.05 && latency_ok {
+        current + (current / 4).max(1)
+    } else {
+        current
+    };
+    (next.clamp(1, max.max(1)), latency_ms)
+}
+
+/// Close the batch's window if it's full and store the re-tuned limit.
+/// Returns the limit to spawn with.
+///
+/// Completions race, so the window is closed with a `window_seq` guard:
+/// only the completion that advances the sequence applies its decision.
+async fn adapt_batch_concurrency(
+    pool: &PgPool,
+    batch_id: &Uuid,
+    concurrency: i32,
+    window: &serde_json::Value,
+) -> Result<i32, MapError> {
+    let int = |key: &str| window.get(key).and_then(|v| v.as_i64());
+    let stats = AdaptiveWindow {
+        completed: int("completed").unwrap_or(0) as i32,
+        failed: int("failed").unwrap_or(0) as i32,
+        elapsed_ms: int("elapsed_ms").unwrap_or(0).max(0) as u64,
+    };
+    if stats.completed + stats.failed < adaptive_window_size(concurrency) {
+        return Ok(concurrency);
+    }
+
+    let max = int("max").unwrap_or(concurrency as i64) as i32;
+    let previous_latency = int("latency_ms").map(|l| l.max(0) as u64);
+    let (next, latency_ms) = adapt_concurrency(concurrency, max, stats, previous_latency);
+
+    let closed = sqlx::query(
+        r#"
+        UPDATE batch_operations
+        SET concurrency_limit = $3, window_completed = 0, window_failed = 0, window_started_at = NOW(),
+            window_latency_ms = $4, window_seq = window_seq + 1
+        WHERE id = $1 AND window_seq = $2
+        "#,
+    )
+    .bind(batch_id)
+    .bind(int("seq").unwrap_or(0) as i32)
+    .bind(next)
+    .bind(latency_ms.min(i32::MAX as u64) as i32)
+    .execute(pool)
+    .await
+    .map_err(|e| MapError::DatabaseError(e.to_string()))?;
+
+    if closed.rows_affected() == 0 {
+        // Another completion closed this window first
+        return Ok(concurrency);
+    }
+    if next != concurrency {
+        tracing::info!(
+            batch_id = %batch_id,
+            from = concurrency,
+            to = next,
+            failed = stats.failed,
+            finished = stats.completed + stats.failed,
+            latency_ms,
+            "Map: adaptive concurrency adjusted"
+        );
+    }
+    Ok(next)
+}
+
+/// Live progress of a map batch, for progress bars that don't read the chunk stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchProgress {
+    pub batch_id: String,
+    /// `running`, `completed`, `failed` or `cancelled`
+    pub status: String,
+    pub total: i32,
+    pub completed: i32,
+    pub failed: i32,
+    /// Finished (completed + failed) items, 0-100
+    pub percent: f64,
+    pub elapsed_ms: u64,
+    pub items_per_sec: f64,
+    /// Per-item latency implied by throughput and concurrency (Little's Law)
+    pub avg_latency_ms: u64,
+    /// Time left at the current throughput; None until an item has finished,
+    /// and once the batch is no longer running
+    pub eta_ms: Option<u64>,
+}
+
+/// Read a batch's progress. None if there's no such batch.
+pub async fn get_batch_progress(pool: &PgPool, batch_id: &Uuid) -> Result<Option<BatchProgress>, MapError> {
+    type ProgressRow = (String, i32, i32, i32, i32, chrono::DateTime<chrono::Utc>, Option<chrono::DateTime<chrono::Utc>>);
+    let row: Option<ProgressRow> =
+        sqlx::query_as(
+            r#"
+            SELECT status, total_items, completed_count, failed_count, concurrency_limit, created_at, completed_at
+            FROM batch_operations WHERE id = $1
+            "#,
+        )
+        .bind(batch_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| MapError::DatabaseError(e.to_string()))?;
+
+    Ok(row.map(|(status, total, completed, failed, concurrency, created_at, completed_at)| {
+        let end = completed_at.unwrap_or_else(chrono::Utc::now);
+        let elapsed_ms = (end - created_at).num_milliseconds().max(0) as u64;
+        batch_progress(*batch_id, status, total, completed, failed, concurrency, elapsed_ms)
+    }))
+}
+
+fn batch_progress(
+    batch_id: Uuid,
+    status: String,
+    total: i32,
+    completed: i32,
+    failed: i32,
+    concurrency: i32,
+    elapsed_ms: u64,
+) -> BatchProgress {
+    let finished = completed + failed;
+    let items_per_sec = throughput(finished, elapsed_ms as f64 / 1000.0);
+    let remaining = (total - finished).max(0);
+    let eta_ms = (status == "running" && items_per_sec > 0.0)
+        .then(|| (remaining as f64 / items_per_sec * 1000.0).round() as u64);
+
+    BatchProgress {
+        batch_id: batch_id.to_string(),
+        status,
+        total,
+        completed,
+        failed,
+        percent: if total > 0 { (finished as f64 / total as f64 * 100.0).min(100.0) } else { 100.0 },
+        elapsed_ms,
+        items_per_sec,
+        avg_latency_ms: avg_latency_ms(concurrency, items_per_sec),
+        eta_ms,
+    }
+}
+
+/// Items finished per second.
+fn throughput(items: i32, elapsed_secs: f64) -> f64 {
+    if elapsed_secs > 0.0 {
+        items as f64 / elapsed_secs
+    } else {
+        0.0
+    }
+}
+
+/// Lay out a batch's `(item_index, status, output, error)` rows in input
+/// order, whatever order they arrive in: `results[i]` is item i's output
+/// (null if it failed or never finished) and `errors` is sorted by `index`.
+fn collect_results(
+    total_items: usize,
+    rows: Vec<(i32, String, Option<serde_json::Value>, Option<String>)>,
+) -> (Vec<Option<serde_json::Value>>, Vec<serde_json::Value>) {
+    let mut outputs: Vec<Option<serde_json::Value>> = vec![None; total_items];
+    let mut errors: Vec<(i32, serde_json::Value)> = Vec::new();
+
+    for (idx, status, output, error) in rows {
+        if idx < 0 || idx as usize >= total_items {
+            continue;
+        }
+        if status == "completed" {
+            outputs[idx as usize] = output;
+        } else {
+            errors.push((idx, json!({
+                "index": idx,
+                "error": error.unwrap_or_else(|| "Unknown error".to_string())
+            })));
+        }
+    }
+
+    errors.sort_by_key(|(idx, _)| *idx);
+    (outputs, errors.into_iter().map(|(_, error)| error).collect())
+}
+
+/// Effective latency per item (Little's Law: Latency = Concurrency / Throughput)
+fn avg_latency_ms(concurrency: i32, items_per_sec: f64) -> u64 {
+    if items_per_sec > 0.0 {
+        ((concurrency as f64 / items_per_sec) * 1000.0).round() as u64
+    } else {
+        0
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn complete_batch(
     pool: &PgPool,
+    redis: &redis::Client,
+    limiter: &ChildLimiter,
     run_id: &Uuid,
     node_id: &str,
     batch_id: &Uuid,
@@ -966,6 +1724,8 @@ async fn complete_batch(
         .execute(pool)
         .await
         .map_err(|e| MapError::DatabaseError(e.to_string()))?;
+    // Children still in flight (cancelled, timed out, never reporting) stop counting
+    limiter.release_batch(redis, batch_id).await;
     
     // Fetch all results in order
     let results: Vec<(i32, String, Option<serde_json::Value>, Option<String>)> = sqlx::query_as(
@@ -994,22 +1754,8 @@ async fn complete_batch(
     let total_duration_ms = (chrono::Utc::now() - created_at).num_milliseconds().max(0) as u64;
     let total_duration_secs = total_duration_ms as f64 / 1000.0;
     
-    // Build results arrays
-    let mut outputs: Vec<Option<serde_json::Value>> = vec![None; total_items as usize];
-    let mut errors: Vec<serde_json::Value> = Vec::new();
-    
-    for (idx, status, output, error) in results {
-        if idx >= 0 && (idx as usize) < outputs.len() {
-            if status == "completed" {
-                outputs[idx as usize] = output;
-            } else {
-                errors.push(json!({
-                    "index": idx,
-                    "error": error.unwrap_or_else(|| "Unknown error".to_string())
-                }));
-            }
-        }
-    }
+    // Build results arrays, aligned with the input items
+    let (outputs, errors) = collect_results(total_items.max(0) as usize, results);
     
     // Log completion
     let _ = log_event_with_retry(
@@ -1026,19 +1772,13 @@ async fn complete_batch(
         }),
     ).await;
     
-    // Determine status code
-    let status_code = if failed_early || failed_count == total_items {
-        500
-    } else {
-        200
-    };
+    // Determine status code and output handle
+    let batch_failed = failed_early || failed_count == total_items;
+    let status_code = if batch_failed { 500 } else { 200 };
+    let route_to = if batch_failed { "error" } else { "success" };
     
     // Calculate throughput and latency metrics
-    let items_per_sec = if total_duration_secs > 0.0 {
-        (total_items as f64 / total_duration_secs).round()
-    } else {
-        0.0
-    };
+    let items_per_sec = throughput(total_items, total_duration_secs).round();
     
     // Get configured concurrency for the batch
     let concurrency: i32 = sqlx::query_scalar("SELECT concurrency_limit FROM batch_operations WHERE id = $1")
@@ -1047,12 +1787,7 @@ async fn complete_batch(
         .await
         .unwrap_or(50);
     
-    // Calculate effective latency per item (Little's Law: Latency = Concurrency / Throughput)
-    let avg_latency_ms = if items_per_sec > 0.0 {
-        ((concurrency as f64 / items_per_sec) * 1000.0).round() as u64
-    } else {
-        0
-    };
+    let avg_latency_ms = avg_latency_ms(concurrency, items_per_sec);
     
     // Suggest optimal concurrency based on current throughput
     let suggested_concurrency = if avg_latency_ms > 0 {
@@ -1085,7 +1820,7 @@ async fn complete_batch(
                 "concurrency_used": concurrency,
                 "suggested_concurrency": suggested_concurrency
             },
-            "route_to": if failed_early || failed_count == total_items { "error" } else { "success" }
+            "route_to": route_to
         })),
         timestamp: std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -1093,5 +1828,235 @@ async fn complete_batch(
             .as_millis() as u64,
         duration_ms: start.elapsed().as_millis() as u64,
         isolated: false,
+        route_to: Some(route_to.to_string()),
+        warnings: Vec::new(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// In-memory sink that records progress messages and milestones
+    #[derive(Default)]
+    struct RecordingSink {
+        messages: Mutex<Vec<String>>,
+        milestones: Mutex<Vec<serde_json::Value>>,
+    }
+
+    impl ProgressSink for RecordingSink {
+        async fn progress(&self, message: &str) {
+            self.messages.lock().unwrap().push(message.to_string());
+        }
+
+        async fn milestone(&self, payload: serde_json::Value) {
+            self.milestones.lock().unwrap().push(payload);
+        }
+    }
+
+    #[test]
+    fn test_completion_milestones_every_ten_percent() {
+        let milestones = |total: i32| -> Vec<(i32, i64)> {
+            (1..=total).filter_map(|n| completion_milestone(n, total).map(|p| (n, p))).collect()
+        };
+
+        let large = milestones(1000);
+        assert_eq!(large.len(), 10);
+        assert_eq!(large[0], (100, 10));
+        assert_eq!(large[9], (1000, 100));
+        assert!(large.windows(2).all(|w| w[1].0 - w[0].0 >= 100));
+
+        // Small batches: every child crosses a step, percentages still rise
+        let small = milestones(4);
+        assert_eq!(small, vec![(1, 20), (2, 50), (3, 70), (4, 100)]);
+        assert!(milestones(0).is_empty());
+    }
+
+
+    #[test]
+    fn test_batch_progress_estimates_time_left() {
+        let id = Uuid::new_v4();
+        // 40 of 100 done in 20s at concurrency 10: 2 items/s, 30s left
+        let progress = batch_progress(id, "running".into(), 100, 35, 5, 10, 20_000);
+        assert_eq!(progress.percent, 40.0);
+        assert_eq!(progress.items_per_sec, 2.0);
+        assert_eq!(progress.avg_latency_ms, 5000);
+        assert_eq!(progress.eta_ms, Some(30_000));
+
+        let starting = batch_progress(id, "running".into(), 100, 0, 0, 10, 500);
+        assert_eq!(starting.eta_ms, None);
+        let done = batch_progress(id, "completed".into(), 100, 100, 0, 10, 50_000);
+        assert_eq!((done.percent, done.eta_ms), (100.0, None));
+    }
+
+    #[test]
+    fn test_adapt_concurrency() {
+        let window = |completed, failed, elapsed_ms| AdaptiveWindow { completed, failed, elapsed_ms };
+
+        // Clean window: ramp up by a quarter, bounded by max
+        let (next, latency) = adapt_concurrency(8, 100, window(20, 0, 10_000), None);
+        assert_eq!((next, latency), (10, 4000));
+        assert_eq!(adapt_concurrency(90, 100, window(100, 0, 10_000), None).0, 100);
+        assert_eq!(adapt_concurrency(2, 100, window(10, 0, 10_000), None).0, 3);
+
+        // Latency blew up compared to the previous window: hold
+        assert_eq!(adapt_concurrency(8, 100, window(20, 0, 10_000), Some(2000)).0, 8);
+
+        // Errors spike: halve, never below 1
+        assert_eq!(adapt_concurrency(8, 100, window(15, 5, 10_000), None).0, 4);
+        assert_eq!(adapt_concurrency(1, 100, window(0, 10, 10_000), None).0, 1);
+
+        // Some errors, not a spike: hold
+        assert_eq!(adapt_concurrency(8, 100, window(18, 2, 10_000), None).0, 8);
+    }
+
+    #[test]
+    fn test_item_count_guard() {
+        assert!(check_item_count(10, 10).is_ok());
+        assert!(check_item_count(1_000_000, 0).is_ok());
+        match check_item_count(11, 10) {
+            Err(MapError::TooManyItems { count, limit }) => assert_eq!((count, limit), (11, 10)),
+            other => panic!("expected TooManyItems, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_child_limiter_caps_inflight_children() {
+        assert_eq!(grant(3, 0, 5, 0), 3);
+        // Asks beyond what's free get what's left, never more
+        assert_eq!(grant(4, 3, 5, 3), 2);
+        assert_eq!(grant(2, 5, 5, 1), 0, "batch with children in flight waits for completions");
+        assert_eq!(grant(10, 3, 5, 1), 2);
+        assert_eq!(grant(0, 0, 5, 0), 0);
+    }
+
+    #[test]
+    fn test_child_limiter_never_stalls_an_idle_batch() {
+        // A batch larger than the free slots with nothing in flight still
+        // gets one child, so its completions can drive the rest (also when
+        // an earlier overdraft left the total above max)
+        assert_eq!(grant(50, 5, 5, 0), 1);
+        assert_eq!(grant(50, 6, 5, 0), 1);
+        assert_eq!(grant(0, 5, 5, 0), 0);
+    }
+
+    #[test]
+    fn test_child_limiter_scripts_follow_grant_and_clamp_releases() {
+        // The Lua acquire applies the same rule as `grant`
+        assert!(ACQUIRE_SCRIPT.contains("math.min(wanted, math.max(tonumber(ARGV[3]) - total, 0))"));
+        assert!(ACQUIRE_SCRIPT.contains("tonumber(ARGV[4]) <= 0"));
+        // Releases never give back more than the batch holds, and a batch
+        // that holds nothing has no field left behind
+        assert!(RELEASE_SCRIPT.contains("count > held"));
+        assert!(RELEASE_SCRIPT.contains("redis.call('HDEL', KEYS[1], ARGV[1])"));
+        assert_eq!(ChildLimiter::new(0).max, 1);
+    }
+
+    #[test]
+    fn test_small_batch_still_checks_cancellation() {
+        // A 3-item batch never reaches the 10-completion interval
+        let checks = (1..=3).filter(|&finished| should_check_cancellation(finished, 10)).count();
+        assert_eq!(checks, 1);
+
+        assert!(should_check_cancellation(20, 10));
+        assert!(!should_check_cancellation(15, 10));
+        // Zero interval means "every completion", not a division by zero
+        assert!(should_check_cancellation(7, 0));
+    }
+
+    #[test]
+    fn test_item_chunks_have_their_own_index() {
+        let data = |item_index: i32, success: bool| MapChildCompleteData {
+            batch_id: Uuid::nil().to_string(),
+            item_index,
+            child_run_id: "child".to_string(),
+            success,
+            output: success.then(|| json!({ "n": item_index })),
+            error: (!success).then(|| "boom".to_string()),
+        };
+
+        let (index, content) = item_chunk(&data(0, true));
+        assert_eq!(index, 1, "index 0 stays the batch's first progress chunk");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&content).unwrap(),
+            json!({ "index": 0, "success": true, "output": { "n": 0 }, "error": null, "child_run_id": "child" })
+        );
+
+        let (index, content) = item_chunk(&data(41, false));
+        assert_eq!(index, 42);
+        assert!(content.contains(r#""error":"boom""#));
+    }
+
+    #[test]
+    fn test_results_follow_input_order() {
+        let completed = |idx: i32| (idx, "completed".to_string(), Some(json!(idx * 10)), None);
+        let failed = |idx: i32| (idx, "failed".to_string(), None, Some(format!("item {} failed", idx)));
+        // Completion order, not item order
+        let rows = vec![failed(4), completed(2), failed(1), completed(0), failed(3), completed(9)];
+
+        let (results, errors) = collect_results(5, rows);
+
+        assert_eq!(results, vec![Some(json!(0)), None, Some(json!(20)), None, None]);
+        assert_eq!(json!(results), json!([0, null, 20, null, null]));
+        let indexes: Vec<_> = errors.iter().map(|e| e["index"].clone()).collect();
+        assert_eq!(indexes, vec![json!(1), json!(3), json!(4)]);
+        assert_eq!(errors[0]["error"], "item 1 failed");
+    }
+
+    #[test]
+    fn test_finalize_marker_index() {
+        assert_eq!(classify_item_index(FINALIZE_MARKER_INDEX), ChildIndex::FinalizeMarker);
+        assert_eq!(classify_item_index(0), ChildIndex::Item(0));
+        assert_eq!(classify_item_index(10), ChildIndex::Item(10));
+        assert_eq!(classify_item_index(-7), ChildIndex::Invalid);
+    }
+
+    #[tokio::test]
+    async fn test_initial_wave_reports_progress_through_the_sink() {
+        // Nothing listens here, so the limiter falls back to one child for an idle batch
+        let redis = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let limiter = ChildLimiter::new(1000);
+        let batch_id = Uuid::new_v4();
+
+        let sink = RecordingSink::default();
+        let spawned = Mutex::new(Vec::new());
+        let count = launch_initial_wave(
+            &redis,
+            &limiter,
+            &batch_id,
+            200,
+            10_000,
+            async |count| {
+                spawned.lock().unwrap().push(count);
+                Ok(())
+            },
+            Some(&sink),
+        )
+        .await
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(*spawned.lock().unwrap(), vec![1]);
+        assert_eq!(*sink.messages.lock().unwrap(), vec!["Spawning 1/10000".to_string()]);
+        assert_eq!(
+            *sink.milestones.lock().unwrap(),
+            vec![json!({ "milestone": "spawned", "spawned": 1, "total": 10_000 })]
+        );
+
+        // A wave that fails to spawn reports nothing
+        let sink = RecordingSink::default();
+        let result = launch_initial_wave(
+            &redis,
+            &limiter,
+            &batch_id,
+            200,
+            10_000,
+            async |_| Err(MapError::DatabaseError("insert failed".to_string())),
+            Some(&sink),
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(sink.messages.lock().unwrap().is_empty());
+        assert!(sink.milestones.lock().unwrap().is_empty());
+    }
+}
@@ -2,18 +2,153 @@
 //!
 //! Each node type has its own execution logic in a separate module.
 
+use crate::templating;
+use crate::types::{HttpAuth, NodeType, RunEnv};
+
+pub mod aggregate;
 pub mod code;
+pub mod conditional;
+pub mod db_upsert;
+pub mod db_wait;
 pub mod delay;
+pub mod dry_run;
+pub mod email;
 pub mod http;
+pub mod http_cache;
+pub mod http_upload;
 pub mod llm;
+pub mod loop_node;
 pub mod map;
+pub mod parallel;
 pub mod router;
+pub mod s3;
 pub mod subflow;
+pub mod transform;
 pub mod webhook;
+pub mod websocket;
 
 // Re-export for convenience
-pub use code::JsTask;
+pub use code::{JsError, JsErrorKind, JsTask};
 pub use http::execute as execute_http;
 pub use llm::execute as execute_llm;
-pub use map::{handle_map_init, handle_map_step, handle_child_complete, MapError};
-pub use subflow::{spawn_child_run, handle_resume, suspend_parent_run, SubFlowError};
+pub use map::{handle_map_init, handle_map_step, handle_child_complete, get_batch_progress, BatchProgress, ChildLimiter, MapError};
+pub use subflow::{spawn_child_run, respawn_child_run, handle_resume, load_resume_options, suspend_parent_run, SubFlowError};
+
+/// Key a code node can return to pick its output handle: `return { __route: "branchB", ... }`
+pub const CODE_ROUTE_KEY: &str = "__route";
+
+/// Extract the output handle a node asked the orchestrator to follow.
+///
+/// - Code nodes: `__route` in the returned object (removed from the body)
+/// - SubFlow/webhook resume, Map, Parallel and Loop completion: the `route_to` they already put in their body
+/// - Everything else: None. HTTP/LLM bodies come from external services, so a
+///   `route_to` key in them is data, not routing.
+pub fn extract_route_to(node: &NodeType, body: &mut Option<serde_json::Value>) -> Option<String> {
+    let obj = body.as_mut()?.as_object_mut()?;
+
+    match node {
+        NodeType::Code(_) => match obj.remove(CODE_ROUTE_KEY)? {
+            serde_json::Value::String(route) => Some(route),
+            _ => None,
+        },
+        NodeType::SubFlowResume(_)
+        | NodeType::Conditional(_)
+        | NodeType::WebhookResume(_)
+        | NodeType::Map(_)
+        | NodeType::MapChildComplete(_)
+        | NodeType::ParallelBranchComplete(_)
+        | NodeType::LoopIterationComplete(_) => {
+            obj.get("route_to").and_then(|r| r.as_str()).map(|r| r.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Whether a lifecycle event's outcome settles its node, i.e. completes or
+/// fails it like any other node (and the event is ACKed). A sub-flow resume,
+/// parallel join or finished loop does unless it's a progress update (202) or
+/// a transient failure the event is redelivered for; that includes a child
+/// failing with `fail_on_error` (502). An accepted webhook resume does; a
+/// rejected one leaves the node waiting.
+pub fn settles_node(node: &NodeType, status: u16, is_success: bool, is_transient: bool) -> bool {
+    match node {
+        NodeType::SubFlowResume(_) | NodeType::ParallelBranchComplete(_) | NodeType::LoopIterationComplete(_) => {
+            status != 202 && !is_transient
+        }
+        NodeType::WebhookResume(_) => is_success,
+        _ => false,
+    }
+}
+
+/// Replace `{{$env.NAME}}` in the fields that carry credentials (HTTP headers
+/// and auth, LLM api_key) with the run's `run_env` value or else the worker's
+/// environment, so secrets can stay out of the stored graph. An unset
+/// variable is an error.
+pub fn resolve_env_secrets(node: &mut NodeType, run_env: Option<&RunEnv>) -> Result<(), String> {
+    let resolve = |s: &mut String| -> Result<(), String> {
+        if s.contains("{{") {
+            *s = templating::resolve_run_env(s, run_env)?;
+        }
+        Ok(())
+    };
+
+    match node {
+        NodeType::Http(data) => {
+            for value in data.headers.iter_mut().flat_map(|h| h.values_mut()) {
+                resolve(value)?;
+            }
+            match &mut data.auth {
+                Some(HttpAuth::Basic { username, password }) => {
+                    resolve(username)?;
+                    resolve(password)?;
+                }
+                Some(HttpAuth::Bearer { token }) => resolve(token)?,
+                Some(HttpAuth::ApiKey { value, .. }) => resolve(value)?,
+                None => {}
+            }
+        }
+        NodeType::Llm(data) => resolve(&mut data.api_key).map_err(|e| format!("Invalid api_key: {}", e))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_env_secrets() {
+        let path = std::env::var("PATH").unwrap();
+        let mut node: NodeType = serde_json::from_value(serde_json::json!({
+            "type": "HTTP",
+            "data": {
+                "url": "https://api.example.com",
+                "method": "GET",
+                "headers": {"X-Path": "{{$env.PATH}}", "Accept": "application/json"},
+                "auth": {"type": "bearer", "value": {"token": "{{ $env.PATH }}"}}
+            }
+        }))
+        .unwrap();
+        resolve_env_secrets(&mut node, None).unwrap();
+        let NodeType::Http(data) = &node else { unreachable!() };
+        assert_eq!(data.headers.as_ref().unwrap()["X-Path"], path);
+        assert_eq!(data.headers.as_ref().unwrap()["Accept"], "application/json");
+        assert_eq!(data.auth, Some(HttpAuth::Bearer { token: path }));
+
+        let mut llm: NodeType = serde_json::from_value(serde_json::json!({
+            "type": "LLM",
+            "data": {
+                "base_url": "https://api.openai.com/v1",
+                "api_key": "{{$env.SWIFTGRID_TEST_UNSET_KEY}}",
+                "model": "gpt-4o",
+                "messages": []
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            resolve_env_secrets(&mut llm, None).unwrap_err(),
+            "Invalid api_key: Environment variable SWIFTGRID_TEST_UNSET_KEY is not set"
+        );
+    }
+}
@@ -0,0 +1,417 @@
+//! Parallel node: fan out to several branch nodes and join their results.
+//!
+//! The branches are nodes of the same workflow (listed in `branches`, drawn as
+//! edges from the Parallel node). Instead of child runs like Map, each branch
+//! runs as a job in the parent run itself, rebuilt from the snapshot like a
+//! rerun, `isolated` so the orchestrator doesn't schedule past it, and tagged
+//! with `parallel_branch`. Its final result comes back to the Parallel node as
+//! a PARALLELBRANCHCOMPLETE lifecycle job.
+//!
+//! Progress reuses the Map bookkeeping: one `batch_operations` row per fan-out
+//! (`input_items` = the branch ids) and one `batch_results` row per branch
+//! (`item_index` = its position). `join_mode` decides when the node resumes;
+//! with Any/Race the remaining branches keep running but their results are
+//! dropped. Branches must be leaves and can't be nodes that suspend the run.
+//!
+//! Branches and their completions keep the Parallel job's `priority`.
+
+use crate::node_error::{NodeError, NodeResult};
+use crate::rerun;
+use crate::templating;
+use crate::trace_context::TraceContext;
+use crate::types::{
+    JobPriority, JoinMode, ParallelBranch, ParallelBranchCompleteData, ParallelNodeData, ResumeVia, Suspension, SuspensionKind,
+};
+use redis::{AsyncCommands, RedisResult};
+use serde_json::{json, Map, Value};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// What a branch completion did to its Parallel node.
+#[derive(Debug)]
+pub enum BranchJoin {
+    /// Recorded; still waiting on other branches
+    Pending(Value),
+    /// This completion resumed the node; the body is its output
+    Joined(Value),
+    /// The node had already resumed (a losing Any/Race branch, or a duplicate)
+    AlreadyJoined(Value),
+}
+
+/// Reject empty, duplicate or self-referencing branch lists.
+fn validate_branches(node_id: &str, branches: &[String]) -> Result<(), String> {
+    if branches.is_empty() {
+        return Err("Parallel node needs at least one branch".to_string());
+    }
+    let mut seen = HashSet::new();
+    for branch in branches {
+        if branch == node_id {
+            return Err("Parallel node can't be its own branch".to_string());
+        }
+        if !seen.insert(branch.as_str()) {
+            return Err(format!("Branch '{}' is listed twice", branch));
+        }
+    }
+    Ok(())
+}
+
+/// Start every branch and suspend the node until they join.
+pub async fn handle_parallel_init(
+    pool: &PgPool,
+    redis: &redis::Client,
+    run_id: &Uuid,
+    node_id: &str,
+    priority: JobPriority,
+    data: &ParallelNodeData,
+    trace: &TraceContext,
+) -> NodeResult {
+    validate_branches(node_id, &data.branches).map_err(|e| NodeError::permanent(400, e))?;
+
+    let run: Option<(Value, Option<Value>, i32)> =
+        sqlx::query_as("SELECT snapshot_graph, input_data, workflow_id FROM workflow_runs WHERE id = $1")
+            .bind(run_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| NodeError::from_sqlx("Failed to load run", &e))?;
+    let Some((graph, input_data, workflow_id)) = run else {
+        return Err(NodeError::permanent(404, format!("Run not found: {}", run_id)));
+    };
+    let outputs = templating::recorded_outputs(pool, run_id)
+        .await
+        .map_err(|e| NodeError::from_sqlx("Failed to load node outputs", &e))?;
+
+    let batch_id = Uuid::new_v4();
+    let trace_context: HashMap<String, String> = trace.headers().into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+    let mut payloads = Vec::with_capacity(data.branches.len());
+    for (index, branch) in data.branches.iter().enumerate() {
+        let mut job = rerun::rebuild_job(&graph, input_data.as_ref(), &outputs, run_id, branch)
+            .map_err(|e| NodeError::permanent(400, format!("Branch '{}': {}", branch, e)))?;
+        job.isolated = true;
+        job.priority = priority;
+        job.trace_context = Some(trace_context.clone());
+        job.parallel_branch = Some(ParallelBranch {
+            batch_id: batch_id.to_string(),
+            parallel_node_id: node_id.to_string(),
+            branch_index: index as i32,
+            join_mode: data.join_mode,
+        });
+        payloads.push(serde_json::to_string(&job).map_err(|e| NodeError::permanent(500, e.to_string()))?);
+    }
+
+    // Every branch starts at once, so the cursor and active count start full
+    let total = data.branches.len() as i32;
+    sqlx::query(
+        r#"
+        INSERT INTO batch_operations (
+            id, run_id, node_id, total_items, concurrency_limit, input_items,
+            child_workflow_id, current_index, active_count, status, priority
+        ) VALUES ($1, $2, $3, $4, $4, $5, $6, $4, $4, 'running', $7)
+        "#,
+    )
+    .bind(batch_id)
+    .bind(run_id)
+    .bind(node_id)
+    .bind(total)
+    .bind(json!(data.branches))
+    .bind(workflow_id)
+    .bind(priority.as_str())
+    .execute(pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to create parallel batch", &e))?;
+
+    let mut con = redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| NodeError::Transient(format!("Redis connection error: {}", e)))?;
+    let mut pipe = redis::pipe();
+    for payload in &payloads {
+        pipe.xadd(priority.stream(), "*", &[("payload", payload)]).ignore();
+    }
+    pipe.query_async::<()>(&mut con)
+        .await
+        .map_err(|e| NodeError::Transient(format!("Failed to enqueue branches: {}", e)))?;
+
+    tracing::info!("Parallel: started {} branch(es), join {:?}", total, data.join_mode);
+
+    Err(NodeError::Suspended(json!({
+        "batch_id": batch_id.to_string(),
+        "branches": data.branches,
+        "join_mode": data.join_mode,
+        "suspension": Suspension::new(
+            SuspensionKind::Parallel,
+            ResumeVia::Branches,
+            None,
+            json!({ "batch_id": batch_id.to_string(), "branches": data.branches, "join_mode": data.join_mode }),
+        ),
+    })))
+}
+
+/// Lifecycle job reporting a branch's final result to its Parallel node.
+pub fn branch_complete_job(
+    branch: &ParallelBranch,
+    run_id: &Uuid,
+    branch_node_id: &str,
+    priority: JobPriority,
+    success: bool,
+    output: Option<Value>,
+) -> Value {
+    let error = (!success).then(|| {
+        output
+            .as_ref()
+            .and_then(|b| b.get("error"))
+            .and_then(|e| e.as_str())
+            .unwrap_or("Branch failed")
+            .to_string()
+    });
+    json!({
+        "id": branch.parallel_node_id,
+        "run_id": run_id.to_string(),
+        "node": {
+            "type": "PARALLELBRANCHCOMPLETE",
+            "data": {
+                "batch_id": branch.batch_id,
+                "branch_index": branch.branch_index,
+                "branch_node_id": branch_node_id,
+                "join_mode": branch.join_mode,
+                "success": success,
+                "output": output,
+                "error": error
+            }
+        },
+        "retry_count": 0,
+        "max_retries": 0,
+        "priority": priority
+    })
+}
+
+/// Push a branch's final result to its Parallel node.
+pub async fn report_branch(
+    redis: &redis::Client,
+    branch: &ParallelBranch,
+    run_id: &Uuid,
+    branch_node_id: &str,
+    priority: JobPriority,
+    success: bool,
+    output: Option<Value>,
+) {
+    let job = branch_complete_job(branch, run_id, branch_node_id, priority, success, output);
+    let pushed: RedisResult<String> = match redis.get_multiplexed_async_connection().await {
+        Ok(mut con) => con.xadd(priority.stream(), "*", &[("payload", job.to_string())]).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = pushed {
+        tracing::error!(
+            "Parallel: Failed to report branch {} to {}: {}",
+            branch_node_id, branch.parallel_node_id, e
+        );
+    }
+}
+
+/// Whether a join is decided once a branch arrives, and with what outcome.
+/// `completed`/`failed` already include this branch.
+fn join_decision(mode: JoinMode, completed: i32, failed: i32, total: i32, success: bool) -> Option<bool> {
+    let finished = completed + failed >= total;
+    match mode {
+        JoinMode::All => finished.then_some(failed == 0),
+        JoinMode::Any if completed > 0 => Some(true),
+        JoinMode::Any => finished.then_some(false),
+        JoinMode::Race => Some(success),
+    }
+}
+
+/// Record a branch result and resume the node once its join is decided.
+pub async fn handle_branch_complete(pool: &PgPool, data: &ParallelBranchCompleteData) -> Result<BranchJoin, NodeError> {
+    let batch_id = Uuid::parse_str(&data.batch_id)
+        .map_err(|e| NodeError::permanent(400, format!("Invalid batch_id: {}", e)))?;
+
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO batch_results (batch_id, item_index, status, output, error_message)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (batch_id, item_index) DO NOTHING
+        "#,
+    )
+    .bind(batch_id)
+    .bind(data.branch_index)
+    .bind(if data.success { "completed" } else { "failed" })
+    .bind(&data.output)
+    .bind(&data.error)
+    .execute(pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to record branch result", &e))?;
+    if inserted.rows_affected() == 0 {
+        return Ok(BranchJoin::AlreadyJoined(json!({ "batch_id": data.batch_id, "duplicate": true })));
+    }
+
+    let counters: Option<(i32, i32, i32)> = sqlx::query_as(
+        r#"
+        UPDATE batch_operations
+        SET completed_count = completed_count + CASE WHEN $2 THEN 1 ELSE 0 END,
+            failed_count = failed_count + CASE WHEN $2 THEN 0 ELSE 1 END,
+            active_count = active_count - 1
+        WHERE id = $1 AND status = 'running'
+        RETURNING completed_count, failed_count, total_items
+        "#,
+    )
+    .bind(batch_id)
+    .bind(data.success)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to update parallel batch", &e))?;
+    let Some((completed, failed, total)) = counters else {
+        return Ok(BranchJoin::AlreadyJoined(json!({ "batch_id": data.batch_id, "status": "joined" })));
+    };
+
+    let Some(ok) = join_decision(data.join_mode, completed, failed, total, data.success) else {
+        return Ok(BranchJoin::Pending(json!({
+            "batch_id": data.batch_id,
+            "status": "running",
+            "completed": completed,
+            "failed": failed,
+            "total": total,
+            "progress": (completed + failed) as f64 / total as f64
+        })));
+    };
+
+    // Two branches can decide an Any join at once; only one resumes the node
+    let claimed: Option<Value> = sqlx::query_scalar(
+        "UPDATE batch_operations SET status = $2, completed_at = NOW() WHERE id = $1 AND status = 'running' RETURNING input_items",
+    )
+    .bind(batch_id)
+    .bind(if ok { "completed" } else { "failed" })
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to finish parallel batch", &e))?;
+    let Some(input_items) = claimed else {
+        return Ok(BranchJoin::AlreadyJoined(json!({ "batch_id": data.batch_id, "status": "joined" })));
+    };
+    let branches: Vec<String> = serde_json::from_value(input_items).unwrap_or_default();
+
+    let results: Vec<(i32, String, Option<Value>, Option<String>)> = sqlx::query_as(
+        "SELECT item_index, status, output, error_message FROM batch_results WHERE batch_id = $1 ORDER BY item_index",
+    )
+    .bind(batch_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to load branch results", &e))?;
+
+    let winner = (data.join_mode != JoinMode::All).then_some(data.branch_node_id.as_str());
+    let body = join_body(&branches, &results, data.join_mode, winner, ok);
+
+    tracing::info!(
+        "Parallel: joined ({:?}, {}/{} completed, {} failed)",
+        data.join_mode, completed, total, failed
+    );
+    Ok(BranchJoin::Joined(body))
+}
+
+/// The Parallel node's output: results and errors keyed by branch id.
+fn join_body(
+    branches: &[String],
+    results: &[(i32, String, Option<Value>, Option<String>)],
+    mode: JoinMode,
+    winner: Option<&str>,
+    ok: bool,
+) -> Value {
+    let mut outputs = Map::new();
+    let mut errors = Map::new();
+    for (index, status, output, error) in results {
+        let Some(branch) = usize::try_from(*index).ok().and_then(|i| branches.get(i)) else {
+            continue;
+        };
+        if status == "completed" {
+            outputs.insert(branch.clone(), output.clone().unwrap_or(Value::Null));
+        } else {
+            errors.insert(branch.clone(), json!(error.as_deref().unwrap_or("Unknown error")));
+        }
+    }
+    let route_to = if ok { "success" } else { "error" };
+    json!({
+        "stats": {
+            "total": branches.len(),
+            "completed": outputs.len(),
+            "failed": errors.len(),
+        },
+        "results": outputs,
+        "errors": errors,
+        "join_mode": mode,
+        "winner": winner,
+        "route_to": route_to
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WorkerJob;
+
+    #[test]
+    fn test_join_modes() {
+        // All waits for every branch and fails if any did
+        assert_eq!(join_decision(JoinMode::All, 1, 0, 3, true), None);
+        assert_eq!(join_decision(JoinMode::All, 3, 0, 3, true), Some(true));
+        assert_eq!(join_decision(JoinMode::All, 2, 1, 3, false), Some(false));
+
+        // Any resumes on the first success and fails only when all failed
+        assert_eq!(join_decision(JoinMode::Any, 0, 1, 3, false), None);
+        assert_eq!(join_decision(JoinMode::Any, 1, 1, 3, true), Some(true));
+        assert_eq!(join_decision(JoinMode::Any, 0, 3, 3, false), Some(false));
+
+        // Race takes whatever arrives first
+        assert_eq!(join_decision(JoinMode::Race, 0, 1, 3, false), Some(false));
+        assert_eq!(join_decision(JoinMode::Race, 1, 0, 3, true), Some(true));
+    }
+
+    #[test]
+    fn test_join_body_is_keyed_by_branch() {
+        let branches = vec!["fetch_a".to_string(), "fetch_b".to_string(), "fetch_c".to_string()];
+        let results = vec![
+            (0, "completed".to_string(), Some(json!({ "status": 200 })), None),
+            (2, "failed".to_string(), None, Some("HTTP 503".to_string())),
+        ];
+        let body = join_body(&branches, &results, JoinMode::Any, Some("fetch_a"), true);
+
+        assert_eq!(body["results"]["fetch_a"], json!({ "status": 200 }));
+        assert_eq!(body["errors"]["fetch_c"], "HTTP 503");
+        assert!(body["results"].get("fetch_b").is_none(), "fetch_b is still running");
+        assert_eq!(body["winner"], "fetch_a");
+        assert_eq!(body["route_to"], "success");
+        assert_eq!(body["stats"], json!({ "total": 3, "completed": 1, "failed": 1 }));
+
+        assert!(validate_branches("par", &branches).is_ok());
+        assert!(validate_branches("par", &[]).is_err());
+        assert!(validate_branches("par", &["par".to_string()]).is_err());
+        assert!(validate_branches("par", &["a".to_string(), "a".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_branch_complete_job_round_trips() {
+        let branch = ParallelBranch {
+            batch_id: Uuid::nil().to_string(),
+            parallel_node_id: "par".to_string(),
+            branch_index: 1,
+            join_mode: JoinMode::Race,
+        };
+        let job: WorkerJob = serde_json::from_value(branch_complete_job(
+            &branch,
+            &Uuid::nil(),
+            "fetch_b",
+            JobPriority::High,
+            false,
+            Some(json!({ "error": "timeout" })),
+        ))
+        .unwrap();
+
+        assert_eq!(job.id, "par");
+        assert_eq!(job.priority, JobPriority::High, "the join stays on the branch's stream");
+        match job.node {
+            crate::types::NodeType::ParallelBranchComplete(data) => {
+                assert_eq!(data.branch_node_id, "fetch_b");
+                assert_eq!(data.join_mode, JoinMode::Race);
+                assert_eq!(data.error.as_deref(), Some("timeout"));
+            }
+            other => panic!("unexpected node {:?}", other),
+        }
+    }
+}
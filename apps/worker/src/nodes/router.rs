@@ -1,35 +1,228 @@
 //! Router node execution.
 //!
-//! Conditional branching based on data. The actual condition evaluation
+//! Conditional branching based on data. By default the condition evaluation
 //! happens in the orchestrator; the worker just acknowledges and returns config.
+//! With `evaluate`, the worker resolves `route_by` against the run's recorded
+//! outputs and runs each condition in the JS sandbox itself, returning the
+//! fired handle(s) as `matched` (so branching works without the orchestrator).
 
-use crate::types::RouterNodeData;
+use crate::node_error::{NodeError, NodeResult};
+use crate::nodes::code::SandboxConfig;
+use crate::nodes::JsTask;
+use crate::templating::{self, TemplateContext};
+use crate::types::{RouterCondition, RouterNodeData};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+/// Evaluates every expression against `INPUT.value`. Expressions arrive as
+/// data, never spliced into the script; one that throws reports its error.
+const EVALUATE_JS: &str = r#"
+return INPUT.expressions.map((expr) => {
+    try {
+        return { matched: !!new Function('value', 'return (' + expr + ');')(INPUT.value) };
+    } catch (e) {
+        return { error: String(e) };
+    }
+});
+"#;
 
 /// Execute a router node.
 ///
-/// The router node's conditions are evaluated by the orchestrator since it needs
-/// access to resolved variables from previous nodes. The worker just returns
-/// the routing configuration.
-pub fn execute(data: RouterNodeData) -> (u16, Option<serde_json::Value>) {
-    println!(
-        "  → Router: '{}' mode with {} conditions",
+/// Without `evaluate` the conditions are left to the orchestrator, which has
+/// the resolved variables from previous nodes; the worker just returns the
+/// routing configuration.
+pub async fn execute(
+    data: RouterNodeData,
+    run_id: Option<&Uuid>,
+    db_pool: &PgPool,
+    js_sender: &mpsc::Sender<JsTask>,
+) -> NodeResult {
+    tracing::info!(
+        "Router: '{}' mode with {} conditions (evaluate: {})",
         data.mode,
-        data.conditions.len()
+        data.conditions.len(),
+        data.evaluate
     );
 
-    (
-        200,
-        Some(serde_json::json!({
-            "router": true,
-            "route_by": data.route_by,
-            "conditions": data.conditions.iter().map(|c| serde_json::json!({
-                "id": c.id,
-                "label": c.label,
-                "expression": c.expression
-            })).collect::<Vec<_>>(),
-            "default_output": data.default_output,
-            "mode": data.mode
+    let mut body = config_body(&data);
+    if !data.evaluate {
+        return Ok((200, Some(body)));
+    }
+
+    let route_by = resolve_route_by(&data.route_by, run_id, db_pool).await?;
+    let results = evaluate_conditions(&data.conditions, &route_by, js_sender).await?;
+    let (matched, errors) = select_outputs(&data.conditions, &results, &data.default_output, &data.mode);
+
+    tracing::debug!(route_by = %route_by, matched = ?matched, "Router evaluated");
+
+    body["evaluated"] = json!(true);
+    body["route_by_value"] = route_by;
+    body["matched"] = json!(matched);
+    if !errors.is_empty() {
+        body["condition_errors"] = Value::Object(errors);
+    }
+    Ok((200, Some(body)))
+}
+
+fn config_body(data: &RouterNodeData) -> Value {
+    json!({
+        "router": true,
+        "route_by": data.route_by,
+        "conditions": data.conditions.iter().map(|c| json!({
+            "id": c.id,
+            "label": c.label,
+            "expression": c.expression
+        })).collect::<Vec<_>>(),
+        "default_output": data.default_output,
+        "mode": data.mode
+    })
+}
+
+/// Resolve `{{...}}` in `route_by` against the run, then read it like the
+/// orchestrator does: numbers, booleans and JSON keep their type.
+async fn resolve_route_by(route_by: &str, run_id: Option<&Uuid>, db_pool: &PgPool) -> Result<Value, NodeError> {
+    let resolved = match run_id.filter(|_| route_by.contains("{{")) {
+        Some(rid) => {
+            let input: Option<(Option<Value>,)> = sqlx::query_as("SELECT input_data FROM workflow_runs WHERE id = $1")
+                .bind(rid)
+                .fetch_optional(db_pool)
+                .await
+                .map_err(|e| NodeError::from_sqlx("Failed to load run input", &e))?;
+            let input = input.and_then(|(i,)| i);
+            let outputs = templating::recorded_outputs(db_pool, rid)
+                .await
+                .map_err(|e| NodeError::from_sqlx("Failed to load node outputs", &e))?;
+            templating::resolve_str(route_by, &TemplateContext { node_outputs: &outputs, input: input.as_ref() })
+        }
+        None => route_by.to_string(),
+    };
+    Ok(parse_route_value(&resolved))
+}
+
+fn parse_route_value(s: &str) -> Value {
+    serde_json::from_str(s).unwrap_or_else(|_| Value::String(s.to_string()))
+}
+
+/// Run every condition in one sandbox task. One result per condition:
+/// `{"matched": bool}` or `{"error": "..."}`.
+async fn evaluate_conditions(
+    conditions: &[RouterCondition],
+    value: &Value,
+    js_sender: &mpsc::Sender<JsTask>,
+) -> Result<Vec<Value>, NodeError> {
+    let (tx, rx) = oneshot::channel();
+    let task = JsTask {
+        code: EVALUATE_JS.to_string(),
+        inputs: Some(json!({
+            "value": value,
+            "expressions": conditions.iter().map(|c| &c.expression).collect::<Vec<_>>(),
         })),
-    )
+        responder: tx,
+        timeout_ms: None,
+        log_sender: None,
+        cancelled: None,
+    };
+    if js_sender.send(task).await.is_err() {
+        return Err(NodeError::permanent(500, "JS Engine crashed"));
+    }
+
+    let channel_timeout = SandboxConfig::default().channel_timeout();
+    match tokio::time::timeout(channel_timeout, rx).await {
+        Ok(Ok(Ok(out))) => match out.value {
+            Value::Array(results) => Ok(results),
+            other => Err(NodeError::permanent(500, format!("Unexpected router evaluation result: {}", other))),
+        },
+        Ok(Ok(Err(e))) => Err(NodeError::permanent(e.status_code(), format!("Router evaluation failed: {}", e))),
+        Ok(Err(_)) => Err(NodeError::permanent(500, "JS runtime crashed during router evaluation; it has been restarted")),
+        Err(_) => Err(NodeError::permanent(
+            500,
+            format!("Router evaluation timeout ({}ms)", channel_timeout.as_millis()),
+        )),
+    }
 }
 
+/// The handles that fire, plus the error of each condition that threw.
+///
+/// `first_match` stops at the first true condition, `broadcast` fires every
+/// one; with none true, `default_output` fires (when set). A condition that
+/// throws counts as false, like in the orchestrator.
+fn select_outputs(
+    conditions: &[RouterCondition],
+    results: &[Value],
+    default_output: &str,
+    mode: &str,
+) -> (Vec<String>, serde_json::Map<String, Value>) {
+    let mut matched = Vec::new();
+    let mut errors = serde_json::Map::new();
+
+    for (condition, result) in conditions.iter().zip(results) {
+        if let Some(error) = result.get("error") {
+            errors.insert(condition.id.clone(), error.clone());
+            continue;
+        }
+        if result["matched"] == true {
+            matched.push(condition.id.clone());
+            if mode == "first_match" {
+                break;
+            }
+        }
+    }
+
+    if matched.is_empty() && !default_output.is_empty() {
+        matched.push(default_output.to_string());
+    }
+    (matched, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions() -> Vec<RouterCondition> {
+        ["ok", "big", "bad"]
+            .iter()
+            .map(|id| RouterCondition { id: id.to_string(), label: id.to_string(), expression: String::new() })
+            .collect()
+    }
+
+    #[test]
+    fn test_select_outputs_by_mode() {
+        let results = vec![json!({"matched": true}), json!({"matched": true}), json!({"error": "ReferenceError: x"})];
+
+        let (first, errors) = select_outputs(&conditions(), &results, "default", "first_match");
+        assert_eq!(first, vec!["ok"]);
+        assert!(errors.is_empty());
+
+        let (all, errors) = select_outputs(&conditions(), &results, "default", "broadcast");
+        assert_eq!(all, vec!["ok", "big"]);
+        assert_eq!(errors["bad"], "ReferenceError: x");
+
+        let none = vec![json!({"matched": false}); 3];
+        assert_eq!(select_outputs(&conditions(), &none, "default", "first_match").0, vec!["default"]);
+        assert!(select_outputs(&conditions(), &none, "", "first_match").0.is_empty());
+    }
+
+    #[test]
+    fn test_parse_route_value() {
+        assert_eq!(parse_route_value("200"), json!(200));
+        assert_eq!(parse_route_value("true"), json!(true));
+        assert_eq!(parse_route_value(r#"{"a":1}"#), json!({"a": 1}));
+        assert_eq!(parse_route_value("pending"), json!("pending"));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_js_treats_expressions_as_data() {
+        let runtime = rquickjs::AsyncRuntime::new().unwrap();
+        let ctx = rquickjs::AsyncContext::full(&runtime).await.unwrap();
+        let inputs = json!({
+            "value": 204,
+            "expressions": ["value >= 200 && value < 300", "value.missing.field", "value > 500"]
+        });
+        let out = crate::nodes::code::run_js_safely(&ctx, EVALUATE_JS.to_string(), Some(inputs)).await.unwrap();
+        assert_eq!(out.value[0], json!({"matched": true}));
+        assert!(out.value[1]["error"].as_str().unwrap().contains("TypeError"));
+        assert_eq!(out.value[2], json!({"matched": false}));
+    }
+}
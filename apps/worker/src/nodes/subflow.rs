@@ -1,12 +1,15 @@
 //! Sub-flow node execution.
 //!
 //! Spawns a child workflow run and suspends the parent until completion.
+//! Templates in the node's `input` (`{{nodes.x.data}}`, `{{$input.field}}`) are
+//! resolved against the parent run before the child is created.
 
 use chrono;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::types::{SubFlowNodeData, SubFlowResumeData};
+use crate::templating::{self, TemplateContext};
+use crate::types::{JobPriority, SubFlowNodeData, SubFlowResumeData};
 
 /// Error type for sub-flow operations
 #[derive(Debug)]
@@ -48,12 +51,141 @@ pub struct SpawnResult {
 
 /// Spawn a child workflow run.
 /// Returns the child run ID. The parent should be suspended after this.
+/// `priority` is the parent job's, recorded so a timeout resumes on its stream.
 pub async fn spawn_child_run(
     db_pool: &PgPool,
     data: &SubFlowNodeData,
     parent_run_id: &Uuid,
     parent_node_id: &str,
     parent_depth: u32,
+    priority: JobPriority,
+) -> Result<SpawnResult, SubFlowError> {
+    let input = parent_context_input(db_pool, data.input.as_ref(), parent_run_id).await?;
+    let spawned = create_child_run(db_pool, data, input.as_ref(), parent_run_id, parent_node_id, parent_depth).await?;
+
+    // Create a suspension record to track the sub-flow state
+    // This stores output_path for mapping when child completes
+    sqlx::query(
+        r#"
+        INSERT INTO suspensions (run_id, node_id, suspension_type, resume_after, execution_context)
+        VALUES ($1, $2, 'subflow', $3, $4)
+        "#
+    )
+    .bind(parent_run_id)
+    .bind(parent_node_id)
+    .bind(timeout_at(data.timeout_ms))
+    .bind(serde_json::json!({
+        "child_run_id": spawned.child_run_id.to_string(),
+        "fail_on_error": data.fail_on_error,
+        "output_path": data.output_path,
+        "max_retries": data.max_retries,
+        "retry_count": 0,
+        "workflow_id": data.workflow_id,
+        "version_id": data.version_id,
+        "input": input,
+        "timeout_ms": data.timeout_ms,
+        "depth_limit": data.depth_limit,
+        "priority": priority,
+    }))
+    .execute(db_pool)
+    .await
+    .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
+
+    Ok(spawned)
+}
+
+/// Spawn a fresh child for a failed sub-flow if it has retries left.
+///
+/// Reuses the settings (and resolved input) recorded in the parent node's
+/// suspension, bumps its `retry_count` and re-opens it for the new child.
+/// Returns the new child and its attempt number, or None once retries are
+/// exhausted. Like `spawn_child_run`, the child still has to be started.
+pub async fn respawn_child_run(
+    db_pool: &PgPool,
+    parent_run_id: &Uuid,
+    parent_node_id: &str,
+) -> Result<Option<(SpawnResult, u32)>, SubFlowError> {
+    let suspension: Option<(Uuid, serde_json::Value, i32)> = sqlx::query_as(
+        r#"
+        SELECT s.id, s.execution_context, COALESCE(r.depth, 0)
+        FROM suspensions s JOIN workflow_runs r ON r.id = s.run_id
+        WHERE s.run_id = $1 AND s.node_id = $2 AND s.suspension_type = 'subflow'
+        ORDER BY s.created_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(parent_run_id)
+    .bind(parent_node_id)
+    .fetch_optional(db_pool)
+    .await
+    .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
+
+    let Some((suspension_id, mut context, parent_depth)) = suspension else {
+        return Ok(None);
+    };
+    let Some((data, attempt)) = retry_plan(&context) else {
+        return Ok(None);
+    };
+
+    let spawned =
+        create_child_run(db_pool, &data, data.input.as_ref(), parent_run_id, parent_node_id, parent_depth as u32)
+            .await?;
+
+    context["child_run_id"] = serde_json::json!(spawned.child_run_id.to_string());
+    context["retry_count"] = serde_json::json!(attempt);
+    sqlx::query(
+        r#"
+        UPDATE suspensions
+        SET execution_context = $1, resume_after = $2, resumed_at = NULL, resumed_by = NULL
+        WHERE id = $3
+        "#,
+    )
+    .bind(&context)
+    .bind(timeout_at(data.timeout_ms))
+    .bind(suspension_id)
+    .execute(db_pool)
+    .await
+    .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
+
+    Ok(Some((spawned, attempt)))
+}
+
+/// The sub-flow to spawn for the next attempt (and its number), if the
+/// suspension context has retries left.
+fn retry_plan(context: &serde_json::Value) -> Option<(SubFlowNodeData, u32)> {
+    let field = |key: &str| context.get(key).and_then(|v| v.as_u64());
+    let retry_count = field("retry_count").unwrap_or(0) as u32;
+    let max_retries = field("max_retries").unwrap_or(0) as u32;
+    if retry_count >= max_retries {
+        return None;
+    }
+
+    let data = SubFlowNodeData {
+        workflow_id: field("workflow_id")? as i32,
+        version_id: context.get("version_id").and_then(|v| v.as_str()).map(str::to_string),
+        input: context.get("input").filter(|v| !v.is_null()).cloned(),
+        fail_on_error: context.get("fail_on_error").and_then(|v| v.as_bool()).unwrap_or(false),
+        current_depth: 0,
+        depth_limit: field("depth_limit").map(|d| d as u32).unwrap_or(10),
+        timeout_ms: field("timeout_ms").unwrap_or(0),
+        output_path: context.get("output_path").and_then(|v| v.as_str()).map(str::to_string),
+        max_retries,
+    };
+    Some((data, retry_count + 1))
+}
+
+fn timeout_at(timeout_ms: u64) -> Option<chrono::DateTime<chrono::Utc>> {
+    (timeout_ms > 0).then(|| chrono::Utc::now() + chrono::Duration::milliseconds(timeout_ms as i64))
+}
+
+/// Create (but don't start) a child run of `data`'s workflow.
+async fn create_child_run(
+    db_pool: &PgPool,
+    data: &SubFlowNodeData,
+    input: Option<&serde_json::Value>,
+    parent_run_id: &Uuid,
+    parent_node_id: &str,
+    parent_depth: u32,
 ) -> Result<SpawnResult, SubFlowError> {
     // Check depth limit
     let new_depth = parent_depth + 1;
@@ -73,7 +205,7 @@ pub async fn spawn_child_run(
     .await
     .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
 
-    let (workflow_id, workflow_name, active_version_id) = workflow.ok_or_else(|| {
+    let (workflow_id, workflow_name, active_version_id) = workflow.ok_or({
         SubFlowError::WorkflowNotFound {
             workflow_id: data.workflow_id,
         }
@@ -104,7 +236,7 @@ pub async fn spawn_child_run(
         parsed
     } else {
         // Use active published version
-        active_version_id.ok_or_else(|| SubFlowError::NoPublishedVersion {
+        active_version_id.ok_or(SubFlowError::NoPublishedVersion {
             workflow_id: data.workflow_id,
         })?
     };
@@ -139,7 +271,7 @@ pub async fn spawn_child_run(
     .bind(workflow_id)
     .bind(version_id)
     .bind(&graph)
-    .bind(&data.input)
+    .bind(input)
     .bind(parent_run_id)
     .bind(parent_node_id)
     .bind(new_depth as i32)
@@ -167,61 +299,128 @@ pub async fn spawn_child_run(
     .await
     .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
 
-    // Create a suspension record to track the sub-flow state
-    // This stores output_path for mapping when child completes
-    let timeout_at = if data.timeout_ms > 0 {
-        Some(chrono::Utc::now() + chrono::Duration::milliseconds(data.timeout_ms as i64))
-    } else {
-        None
+    Ok(SpawnResult {
+        child_run_id,
+        child_workflow_name: workflow_name,
+    })
+}
+
+/// The child's input with templates resolved against the parent run's input
+/// data and recorded node outputs. Literal inputs skip the lookups.
+async fn parent_context_input(
+    db_pool: &PgPool,
+    input: Option<&serde_json::Value>,
+    parent_run_id: &Uuid,
+) -> Result<Option<serde_json::Value>, SubFlowError> {
+    let Some(input) = input.filter(|i| templating::has_templates(i)) else {
+        return Ok(input.cloned());
     };
-    
-    sqlx::query(
+
+    let parent_input: Option<(Option<serde_json::Value>,)> =
+        sqlx::query_as("SELECT input_data FROM workflow_runs WHERE id = $1")
+            .bind(parent_run_id)
+            .fetch_optional(db_pool)
+            .await
+            .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
+    let parent_input = parent_input.and_then(|(i,)| i);
+    let outputs = templating::recorded_outputs(db_pool, parent_run_id)
+        .await
+        .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
+
+    let ctx = TemplateContext { node_outputs: &outputs, input: parent_input.as_ref() };
+    Ok(Some(templating::resolve_input(input, &ctx)))
+}
+
+/// Sub-flow settings recorded in the parent's suspension when the child spawned.
+#[derive(Debug, Default, PartialEq)]
+pub struct ResumeOptions {
+    pub fail_on_error: bool,
+    pub output_path: Option<String>,
+}
+
+impl ResumeOptions {
+    fn from_context(context: &serde_json::Value) -> Self {
+        Self {
+            fail_on_error: context.get("fail_on_error").and_then(|v| v.as_bool()).unwrap_or(false),
+            output_path: context
+                .get("output_path")
+                .and_then(|v| v.as_str())
+                .filter(|p| !p.is_empty())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Load the resume options for the parent node's latest sub-flow suspension
+/// (defaults if there is none, e.g. a run started before they were recorded).
+pub async fn load_resume_options(
+    db_pool: &PgPool,
+    parent_run_id: &Uuid,
+    parent_node_id: &str,
+) -> Result<ResumeOptions, SubFlowError> {
+    let context: Option<(serde_json::Value,)> = sqlx::query_as(
         r#"
-        INSERT INTO suspensions (run_id, node_id, suspension_type, resume_after, execution_context)
-        VALUES ($1, $2, 'subflow', $3, $4)
-        "#
+        SELECT execution_context FROM suspensions
+        WHERE run_id = $1 AND node_id = $2 AND suspension_type = 'subflow'
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
     )
     .bind(parent_run_id)
     .bind(parent_node_id)
-    .bind(timeout_at)
-    .bind(serde_json::json!({
-        "child_run_id": child_run_id.to_string(),
-        "fail_on_error": data.fail_on_error,
-        "output_path": data.output_path,
-        "max_retries": data.max_retries,
-        "retry_count": 0,
-        "workflow_id": data.workflow_id,
-        "version_id": data.version_id,
-        "input": data.input,
-        "timeout_ms": data.timeout_ms,
-        "depth_limit": data.depth_limit,
-    }))
-    .execute(db_pool)
+    .fetch_optional(db_pool)
     .await
     .map_err(|e| SubFlowError::DatabaseError(e.to_string()))?;
 
-    Ok(SpawnResult {
-        child_run_id,
-        child_workflow_name: workflow_name,
-    })
+    Ok(context.map(|(c,)| ResumeOptions::from_context(&c)).unwrap_or_default())
 }
 
 /// Handle the resume after a child sub-flow completes.
 /// Returns (status_code, body) for the parent node.
-pub fn handle_resume(data: &SubFlowResumeData, fail_on_error: bool) -> (u16, serde_json::Value) {
+///
+/// With `output_path` (e.g. `result.data.items`) only that field of the
+/// child's output is returned; a path that doesn't resolve gives a null output
+/// with a `warning` instead of failing the node.
+pub fn handle_resume(
+    data: &SubFlowResumeData,
+    fail_on_error: bool,
+    output_path: Option<&str>,
+) -> (u16, serde_json::Value) {
     if data.success {
-        // Success - return child's output
-        (
-            200,
-            serde_json::json!({
-                "child_run_id": data.child_run_id,
-                "output": data.output,
-            }),
-        )
+        // Success - return child's output (or the mapped part of it)
+        let path = output_path.map(str::trim).filter(|p| !p.is_empty());
+        let Some(path) = path else {
+            return (
+                200,
+                serde_json::json!({
+                    "child_run_id": data.child_run_id,
+                    "output": data.output,
+                }),
+            );
+        };
+
+        match data.output.as_ref().and_then(|output| extract_path(output, path)) {
+            Some(value) => (
+                200,
+                serde_json::json!({
+                    "child_run_id": data.child_run_id,
+                    "output": value,
+                }),
+            ),
+            None => (
+                200,
+                serde_json::json!({
+                    "child_run_id": data.child_run_id,
+                    "output": null,
+                    "warning": format!("output_path '{}' not found in sub-flow output", path),
+                }),
+            ),
+        }
     } else if fail_on_error {
-        // Failure with fail_on_error = true - propagate error
+        // Failure with fail_on_error = true - propagate error (502: the child failed,
+        // distinct from the 500s the worker redelivers on)
         (
-            500,
+            502,
             serde_json::json!({
                 "error": data.error.clone().unwrap_or_else(|| "Sub-flow failed".to_string()),
                 "child_run_id": data.child_run_id,
@@ -241,6 +440,15 @@ pub fn handle_resume(data: &SubFlowResumeData, fail_on_error: bool) -> (u16, ser
     }
 }
 
+/// Follow a dotted path through objects (and array indices, e.g. `items.0.id`).
+pub(crate) fn extract_path<'v>(value: &'v serde_json::Value, path: &str) -> Option<&'v serde_json::Value> {
+    path.split('.').try_fold(value, |v, part| match v {
+        serde_json::Value::Object(map) => map.get(part),
+        serde_json::Value::Array(items) => items.get(part.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
 /// Mark the parent run as suspended (waiting for child).
 pub async fn suspend_parent_run(
     db_pool: &PgPool,
@@ -255,3 +463,125 @@ pub async fn suspend_parent_run(
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_templated_input_resolves_from_parent_context() {
+        let outputs = HashMap::from([("fetch".to_string(), json!({"data": {"ids": [3, 4]}}))]);
+        let parent_input = json!({"user": "ada"});
+        let ctx = TemplateContext { node_outputs: &outputs, input: Some(&parent_input) };
+
+        let input = json!({"ids": "{{nodes.fetch.data.ids}}", "greeting": "hi {{$input.user}}"});
+        assert!(templating::has_templates(&input));
+        assert_eq!(templating::resolve_input(&input, &ctx), json!({"ids": [3, 4], "greeting": "hi ada"}));
+    }
+
+    #[test]
+    fn test_static_input_passes_through() {
+        let input = json!({"ids": [1, 2], "mode": "full", "nested": {"n": 1.5, "ok": true}});
+        // No placeholders means no parent lookups and no rewriting
+        assert!(!templating::has_templates(&input));
+
+        let outputs = HashMap::new();
+        let ctx = TemplateContext { node_outputs: &outputs, input: None };
+        assert_eq!(templating::resolve_input(&input, &ctx), input);
+    }
+
+    fn resume(output: serde_json::Value) -> SubFlowResumeData {
+        SubFlowResumeData {
+            child_run_id: "child-1".to_string(),
+            output: Some(output),
+            success: true,
+            error: None,
+            output_path: None,
+        }
+    }
+
+    #[test]
+    fn test_output_path_extracts_nested_field() {
+        let data = resume(json!({"result": {"data": {"items": [{"id": 1}, {"id": 2}]}}}));
+
+        let (status, body) = handle_resume(&data, false, Some("result.data.items"));
+        assert_eq!(status, 200);
+        assert_eq!(body["output"], json!([{"id": 1}, {"id": 2}]));
+        assert!(body.get("warning").is_none());
+
+        let (_, body) = handle_resume(&data, false, Some("result.data.items.1.id"));
+        assert_eq!(body["output"], 2);
+
+        // No (or blank) path returns the full output
+        let (_, body) = handle_resume(&data, false, Some(" "));
+        assert_eq!(body["output"], data.output.clone().unwrap());
+    }
+
+    #[test]
+    fn test_unresolved_output_path_returns_null_with_warning() {
+        let data = resume(json!({"result": {"data": 5}}));
+        let (status, body) = handle_resume(&data, false, Some("result.missing"));
+        assert_eq!(status, 200);
+        assert!(body["output"].is_null());
+        assert!(body["warning"].as_str().unwrap().contains("result.missing"));
+    }
+
+    #[test]
+    fn test_retry_plan_until_retries_are_exhausted() {
+        let context = json!({
+            "child_run_id": "c1", "workflow_id": 7, "version_id": null, "input": {"n": 1},
+            "fail_on_error": false, "output_path": null, "max_retries": 2, "retry_count": 0,
+            "timeout_ms": 0, "depth_limit": 10
+        });
+        let (data, attempt) = retry_plan(&context).unwrap();
+        assert_eq!(attempt, 1);
+        assert_eq!(data.workflow_id, 7);
+        assert_eq!(data.input, Some(json!({"n": 1})));
+
+        let mut context = context;
+        context["retry_count"] = json!(1);
+        assert_eq!(retry_plan(&context).unwrap().1, 2);
+        context["retry_count"] = json!(2);
+        assert!(retry_plan(&context).is_none(), "retries exhausted");
+
+        // No retries configured
+        assert!(retry_plan(&json!({"workflow_id": 7})).is_none());
+    }
+
+    #[test]
+    fn test_resume_options_from_suspension_context() {
+        let context = json!({"child_run_id": "c", "fail_on_error": true, "output_path": "result.items"});
+        assert_eq!(
+            ResumeOptions::from_context(&context),
+            ResumeOptions { fail_on_error: true, output_path: Some("result.items".to_string()) }
+        );
+        assert_eq!(ResumeOptions::from_context(&json!({"output_path": null})), ResumeOptions::default());
+
+        // fail_on_error decides between failing the parent and the error handle
+        let mut data = resume(json!(null));
+        data.success = false;
+        assert_eq!(handle_resume(&data, true, None).0, 502);
+        assert_eq!(handle_resume(&data, false, None).0, 299);
+    }
+
+    #[test]
+    fn test_failed_child_with_fail_on_error_settles_the_parent() {
+        use crate::node_error::NodeError;
+        use crate::types::NodeType;
+
+        // The resume must fail the parent and be ACKed, not come back as a
+        // 500 the worker keeps redelivering
+        let mut data = resume(json!(null));
+        data.success = false;
+        let (status, body) = handle_resume(&data, true, None);
+        let outcome = NodeError::classify(status, Some(body), false);
+        assert!(matches!(outcome, Err(NodeError::Permanent { status: 502, .. })));
+
+        let node = NodeType::SubFlowResume(data);
+        assert!(crate::nodes::settles_node(&node, status, false, false));
+        // Only a progress update or a transient failure leaves the node waiting
+        assert!(!crate::nodes::settles_node(&node, 202, true, false));
+        assert!(!crate::nodes::settles_node(&node, 500, false, true));
+    }
+}
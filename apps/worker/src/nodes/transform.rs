@@ -0,0 +1,142 @@
+//! Transform node execution.
+//!
+//! Reshapes JSON with a jq filter (via `jaq`) instead of a code node: no JS
+//! thread hop, and a filter can only compute a value, never reach out. One
+//! output becomes the result, several are collected into an array, none
+//! gives `null`.
+//!
+//! jaq can't be interrupted, so the filter runs on a blocking thread and the
+//! node fails after `TRANSFORM_TIMEOUT_MS`; outputs are capped at
+//! `MAX_OUTPUTS` so a generator like `repeat(1)` stops there.
+
+use crate::node_error::{NodeError, NodeResult};
+use crate::types::TransformNodeData;
+use jaq_core::load::{self, Arena, File, Loader};
+use jaq_core::{data, unwrap_valr, Compiler, Ctx, Vars};
+use jaq_json::{read, Val};
+use serde_json::Value;
+use std::time::Duration;
+
+/// Default wall-clock limit for one filter run
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// Most values one filter may produce
+const MAX_OUTPUTS: usize = 10_000;
+
+fn timeout() -> Duration {
+    Duration::from_millis(
+        std::env::var("TRANSFORM_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TIMEOUT_MS),
+    )
+}
+
+/// Run the filter on the node's input.
+pub async fn execute(data: TransformNodeData) -> NodeResult {
+    let input = data.input.unwrap_or(Value::Null);
+    let limit = timeout();
+    let run = tokio::task::spawn_blocking(move || run_filter(&data.expression, &input));
+
+    match tokio::time::timeout(limit, run).await {
+        Ok(Ok(Ok(value))) => Ok((200, Some(value))),
+        Ok(Ok(Err(e))) => Err(NodeError::permanent(400, e)),
+        Ok(Err(e)) => Err(NodeError::permanent(500, format!("Transform failed: {}", e))),
+        Err(_) => Err(NodeError::permanent(
+            400,
+            format!("Transform exceeded {}ms (filter may not terminate)", limit.as_millis()),
+        )),
+    }
+}
+
+/// Compile `expression` and apply it to `input`.
+fn run_filter(expression: &str, input: &Value) -> Result<Value, String> {
+    let defs = jaq_core::defs().chain(jaq_std::defs()).chain(jaq_json::defs());
+    let funs = jaq_core::funs().chain(jaq_std::funs()).chain(jaq_json::funs());
+
+    let arena = Arena::default();
+    let modules = Loader::new(defs)
+        .load(&arena, File { code: expression, path: () })
+        .map_err(|errs| invalid(errs.into_iter().flat_map(|(_, e)| load_errors(e))))?;
+    let filter = Compiler::default()
+        .with_funs(funs)
+        .compile(modules)
+        .map_err(|errs| {
+            invalid(errs.into_iter().flat_map(|(_, e)| e).map(|(name, kind)| format!("undefined {} '{}'", kind.as_str(), name)))
+        })?;
+
+    let input = read::parse_single(&serde_json::to_vec(input).map_err(|e| e.to_string())?)
+        .map_err(|e| format!("Invalid input: {}", e))?;
+    let ctx = Ctx::<data::JustLut<Val>>::new(&filter.lut, Vars::new([]));
+
+    let mut outputs = Vec::new();
+    for output in filter.id.run((ctx, input)).map(unwrap_valr) {
+        let value = output.map_err(|e| format!("jq error: {}", e))?;
+        if outputs.len() == MAX_OUTPUTS {
+            return Err(format!("Transform produced more than {} values", MAX_OUTPUTS));
+        }
+        outputs.push(to_json(&value)?);
+    }
+
+    Ok(match outputs.len() {
+        0 => Value::Null,
+        1 => outputs.pop().unwrap_or_default(),
+        _ => Value::Array(outputs),
+    })
+}
+
+fn invalid(errors: impl Iterator<Item = String>) -> String {
+    format!("Invalid jq expression: {}", errors.collect::<Vec<_>>().join("; "))
+}
+
+fn load_errors(error: load::Error<&str>) -> Vec<String> {
+    match error {
+        load::Error::Io(errs) => errs.into_iter().map(|(path, e)| format!("{}: {}", path, e)).collect(),
+        load::Error::Lex(errs) => errs.into_iter().map(|(expect, at)| expected(expect.as_str(), at)).collect(),
+        load::Error::Parse(errs) => errs.into_iter().map(|(expect, at)| expected(expect.as_str(), at)).collect(),
+    }
+}
+
+/// `at` is the rest of the expression from where parsing stopped.
+fn expected(what: &str, at: &str) -> String {
+    match at.trim() {
+        "" => format!("expected {} at end of expression", what),
+        rest => format!("expected {} at '{}'", what, rest.chars().take(20).collect::<String>()),
+    }
+}
+
+fn to_json(value: &Val) -> Result<Value, String> {
+    serde_json::from_str(&value.to_string()).map_err(|_| format!("jq produced a value that isn't JSON: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_reshapes_input() {
+        let input = json!({"user": {"id": 7, "name": "Ada"}, "items": [{"sku": "a", "qty": 2}, {"sku": "b", "qty": 0}]});
+        let out = run_filter("{id: .user.id, skus: [.items[] | select(.qty > 0) | .sku]}", &input).unwrap();
+        assert_eq!(out, json!({"id": 7, "skus": ["a"]}));
+    }
+
+    #[test]
+    fn test_output_count_shapes_result() {
+        let input = json!([1, 2, 3]);
+        assert_eq!(run_filter(".[] | . * 10", &input).unwrap(), json!([10, 20, 30]));
+        assert_eq!(run_filter("length", &input).unwrap(), json!(3));
+        assert_eq!(run_filter("empty", &input).unwrap(), Value::Null);
+        assert!(run_filter("repeat(1)", &input).unwrap_err().contains("more than"));
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        assert_eq!(
+            run_filter(".foo |", &json!({})).unwrap_err(),
+            "Invalid jq expression: expected term at end of expression"
+        );
+        assert_eq!(run_filter("nosuch(1)", &json!({})).unwrap_err(), "Invalid jq expression: undefined filter 'nosuch'");
+        assert!(run_filter("error(\"nope\")", &json!({})).unwrap_err().contains("nope"));
+    }
+}
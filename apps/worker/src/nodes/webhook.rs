@@ -1,28 +1,75 @@
 //! Webhook wait/resume node execution.
 //!
 //! Handles workflow suspension waiting for external webhooks.
+//! With a `signing_secret`, a resume is only accepted when its raw body carries
+//! a valid HMAC-SHA256 signature. The web endpoint checks it before claiming
+//! the suspension (401 otherwise); the worker checks again in case the job was
+//! queued some other way, reopening the suspension for the genuine call.
+//! With `outcomes` (e.g. approve/reject), each outcome gets its own token and
+//! the one that fires is returned as `outcome` and followed as the output handle.
 
 use crate::events::{log_event, EventType};
-use crate::types::{WebhookResumeData, WebhookWaitData};
+use crate::idempotency;
+use crate::node_error::{NodeError, NodeResult};
+use crate::templating;
+use crate::types::{ResumeVia, Suspension, SuspensionKind, WebhookResumeData, WebhookWaitData};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use sqlx::PgPool;
 use uuid::Uuid;
 
 /// Execute a webhook wait node (suspend until external POST arrives).
+///
+/// With `outcomes`, one resume token is issued per outcome; the first one
+/// called decides the node's `outcome` (and output handle), the rest are closed.
 pub async fn execute_wait(
     data: WebhookWaitData,
     job_id: &str,
     run_id: Option<&Uuid>,
     db_pool: &PgPool,
 ) -> (u16, Option<serde_json::Value>) {
-    let resume_token = Uuid::new_v4().to_string();
+    if let Err(e) = validate_outcomes(data.outcomes.as_deref()) {
+        return (400, Some(serde_json::json!({ "error": e })));
+    }
+
+    // (outcome, token) pairs; a plain wait has a single unnamed token
+    let tokens: Vec<(Option<&str>, String)> = match data.outcomes.as_deref() {
+        Some(outcomes) => outcomes.iter().map(|o| (Some(o.as_str()), Uuid::new_v4().to_string())).collect(),
+        None => vec![(None, Uuid::new_v4().to_string())],
+    };
+    let wait_id = Uuid::new_v4().to_string();
     let expires_at = chrono::Utc::now() + chrono::Duration::milliseconds(data.timeout_ms as i64);
 
-    println!(
-        "  → Suspending for webhook (token: {}, expires: {})",
-        &resume_token[..8],
+    tracing::info!(
+        "Suspending for webhook (token: {}, outcomes: {}, expires: {})",
+        token_prefix(&tokens[0].1),
+        tokens.len(),
         expires_at.format("%Y-%m-%d %H:%M")
     );
 
+    let outcomes = data.outcomes.as_ref().map(|_| {
+        tokens
+            .iter()
+            .map(|(outcome, token)| {
+                (
+                    outcome.unwrap_or_default().to_string(),
+                    serde_json::json!({ "resume_token": token, "resume_url": resume_url(token) }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>()
+    });
+    let suspension = Suspension::new(
+        SuspensionKind::Webhook,
+        ResumeVia::Webhook,
+        Some(expires_at),
+        serde_json::json!({
+            "description": data.description,
+            "resume_url": outcomes.is_none().then(|| resume_url(&tokens[0].1)),
+            "outcomes": data.outcomes,
+        }),
+    )
+    .to_value();
+
     // Create suspension in database
     if let Some(rid) = run_id {
         // Log suspension event
@@ -33,43 +80,83 @@ pub async fn execute_wait(
             EventType::NodeSuspended,
             serde_json::json!({
                 "type": "webhook",
-                "resume_token": resume_token,
+                "resume_token": outcomes.is_none().then(|| &tokens[0].1),
+                "outcomes": outcomes,
                 "description": data.description,
                 "expires_at": expires_at.to_rfc3339(),
+                "suspension": suspension,
             }),
         )
         .await;
 
-        // Create suspension record
-        let _ = sqlx::query(
-            r#"
-            INSERT INTO suspensions (run_id, node_id, suspension_type, resume_token, execution_context, expires_at)
-            VALUES ($1, $2, 'webhook', $3, $4, $5)
-            "#,
-        )
-        .bind(rid)
-        .bind(job_id)
-        .bind(&resume_token)
-        .bind(serde_json::json!({
-            "description": data.description,
-            "timeout_ms": data.timeout_ms,
-        }))
-        .bind(expires_at)
-        .execute(db_pool)
-        .await;
+        // Create suspension records (one per token)
+        for (outcome, token) in &tokens {
+            let _ = sqlx::query(
+                r#"
+                INSERT INTO suspensions (run_id, node_id, suspension_type, resume_token, execution_context, expires_at)
+                VALUES ($1, $2, 'webhook', $3, $4, $5)
+                "#,
+            )
+            .bind(rid)
+            .bind(job_id)
+            .bind(token)
+            .bind(serde_json::json!({
+                "description": data.description,
+                "timeout_ms": data.timeout_ms,
+                "signing_secret": data.signing_secret,
+                "outcome": outcome,
+                "wait_id": wait_id,
+            }))
+            .bind(expires_at)
+            .execute(db_pool)
+            .await;
+        }
     }
 
     // Return 202 (Accepted) - signals "suspended, don't publish result yet"
-    (
-        202,
-        Some(serde_json::json!({
-            "suspended": true,
-            "resume_token": resume_token,
-            "resume_url": format!("/api/hooks/resume/{}", resume_token),
-            "expires_at": expires_at.to_rfc3339(),
-            "description": data.description
-        })),
-    )
+    let mut body = serde_json::json!({
+        "suspended": true,
+        "expires_at": expires_at.to_rfc3339(),
+        "description": data.description,
+        "suspension": suspension,
+    });
+    match outcomes {
+        Some(outcomes) => body["outcomes"] = serde_json::Value::Object(outcomes),
+        None => {
+            body["resume_token"] = serde_json::json!(tokens[0].1);
+            body["resume_url"] = serde_json::json!(resume_url(&tokens[0].1));
+        }
+    }
+    (202, Some(body))
+}
+
+/// The start of a token, enough to find it in logs without leaking it.
+fn token_prefix(token: &str) -> &str {
+    token.get(..8).unwrap_or(token)
+}
+
+fn resume_url(token: &str) -> String {
+    format!("/api/hooks/resume/{}", token)
+}
+
+/// Outcome names must be non-empty and distinct (each becomes an output handle).
+fn validate_outcomes(outcomes: Option<&[String]>) -> Result<(), String> {
+    let Some(outcomes) = outcomes else {
+        return Ok(());
+    };
+    if outcomes.is_empty() {
+        return Err("Webhook wait outcomes must not be empty".to_string());
+    }
+    let mut seen = std::collections::HashSet::new();
+    for outcome in outcomes {
+        if outcome.trim().is_empty() {
+            return Err("Webhook wait outcome names must not be blank".to_string());
+        }
+        if !seen.insert(outcome.as_str()) {
+            return Err(format!("Duplicate webhook wait outcome '{}'", outcome));
+        }
+    }
+    Ok(())
 }
 
 /// Execute a webhook resume (called when webhook POST arrives).
@@ -78,8 +165,41 @@ pub async fn execute_resume(
     job_id: &str,
     run_id: Option<&Uuid>,
     db_pool: &PgPool,
-) -> (u16, Option<serde_json::Value>) {
-    println!("  → Webhook resumed (token: {})", &data.resume_token[..8]);
+) -> NodeResult {
+    let context = load_context(db_pool, &data.resume_token).await?;
+    let context_str = |key: &str| context[key].as_str().filter(|s| !s.is_empty());
+
+    if let Some(secret) = context_str("signing_secret")
+        && let Err(e) = check_signature(secret, &data)
+    {
+        tracing::warn!("Webhook resume rejected (token: {}): {}", token_prefix(&data.resume_token), e);
+        reopen_suspension(db_pool, &data.resume_token).await?;
+        return Err(NodeError::Permanent {
+            status: 401,
+            body: serde_json::json!({ "error": e, "resumed": false }),
+        });
+    }
+
+    // Only the first outcome called settles the wait
+    let outcome = context_str("outcome");
+    if let (Some(outcome), Some(wait_id)) = (outcome, context_str("wait_id")) {
+        let key = format!("webhook_outcome:{}", wait_id);
+        let won = idempotency::claim_once(db_pool, &key, run_id.copied(), job_id)
+            .await
+            .map_err(|e| NodeError::Transient(format!("Failed to claim webhook outcome: {}", e)))?;
+        if !won {
+            return Err(NodeError::Permanent {
+                status: 409,
+                body: serde_json::json!({
+                    "error": format!("Webhook wait already resumed by another outcome (not '{}')", outcome),
+                    "resumed": false
+                }),
+            });
+        }
+        close_other_outcomes(db_pool, wait_id, &data.resume_token, outcome).await;
+    }
+
+    tracing::info!("Webhook resumed (token: {}, outcome: {:?})", token_prefix(&data.resume_token), outcome);
 
     // Log resume event
     if let Some(rid) = run_id {
@@ -91,19 +211,164 @@ pub async fn execute_resume(
             serde_json::json!({
                 "source": "webhook",
                 "resume_token": data.resume_token,
+                "outcome": outcome,
                 "payload": data.payload,
             }),
         )
         .await;
     }
 
-    (
-        200,
-        Some(serde_json::json!({
-            "resumed": true,
-            "webhook_payload": data.payload,
-            "message": "Webhook received, workflow resumed"
-        })),
+    let mut body = serde_json::json!({
+        "resumed": true,
+        "webhook_payload": data.payload,
+        "message": "Webhook received, workflow resumed"
+    });
+    if let Some(outcome) = outcome {
+        body["outcome"] = serde_json::json!(outcome);
+        body["route_to"] = serde_json::json!(outcome);
+    }
+    Ok((200, Some(body)))
+}
+
+/// The execution context recorded when the wait suspended (null if unknown).
+async fn load_context(db_pool: &PgPool, resume_token: &str) -> Result<serde_json::Value, NodeError> {
+    let context: Option<(serde_json::Value,)> =
+        sqlx::query_as("SELECT execution_context FROM suspensions WHERE resume_token = $1")
+            .bind(resume_token)
+            .fetch_optional(db_pool)
+            .await
+            .map_err(|e| NodeError::from_sqlx("Failed to load webhook suspension", &e))?;
+
+    Ok(context.map(|(c,)| c).unwrap_or_default())
+}
+
+/// Mark the wait's other outcome tokens resumed so calling them is refused.
+async fn close_other_outcomes(db_pool: &PgPool, wait_id: &str, resume_token: &str, outcome: &str) {
+    let result = sqlx::query(
+        r#"
+        UPDATE suspensions
+        SET resumed_at = NOW(), resumed_by = $3
+        WHERE execution_context->>'wait_id' = $1 AND resume_token <> $2 AND resumed_at IS NULL
+        "#,
+    )
+    .bind(wait_id)
+    .bind(resume_token)
+    .bind(format!("outcome:{}", outcome))
+    .execute(db_pool)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("Failed to close other webhook outcomes: {}", e);
+    }
+}
+
+/// The web API marks the suspension resumed before queueing the job; undo
+/// that so a correctly signed call can still resume the run. (The web API
+/// verifies signatures itself, so this only matters for jobs queued elsewhere.)
+async fn reopen_suspension(db_pool: &PgPool, resume_token: &str) -> Result<(), NodeError> {
+    sqlx::query(
+        "UPDATE suspensions SET resumed_at = NULL, resumed_by = NULL, resume_payload = NULL WHERE resume_token = $1",
     )
+    .bind(resume_token)
+    .execute(db_pool)
+    .await
+    .map_err(|e| NodeError::from_sqlx("Failed to reopen webhook suspension", &e))?;
+    Ok(())
 }
 
+/// Check a resume's signature against the wait's secret (`{{$env.NAME}}` allowed).
+fn check_signature(secret: &str, data: &WebhookResumeData) -> Result<(), String> {
+    let secret = templating::resolve_env(secret).map_err(|e| format!("Invalid signing secret: {}", e))?;
+    let signature = data.signature.as_deref().ok_or("Missing webhook signature")?;
+    let body = data.raw_body.as_deref().ok_or("Missing raw body for signature check")?;
+    if !verify_signature(&secret, body.as_bytes(), signature) {
+        return Err("Invalid webhook signature".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `signature` (`sha256=<hex>` or bare hex) is the HMAC-SHA256 of
+/// `body` under `secret`. The comparison is constant-time.
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let hex = signature.trim();
+    let hex = hex.strip_prefix("sha256=").unwrap_or(hex);
+    let Some(expected) = decode_hex(hex) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // echo -n '{"paid":true}' | openssl dgst -sha256 -hmac whsec
+    const SIGNATURE: &str = "sha256=fd7e19cc9209320b8022c7a29d60cd87bce992dab5fd7f06f7332ef72b809124";
+
+    fn resume(signature: Option<&str>, raw_body: Option<&str>) -> WebhookResumeData {
+        WebhookResumeData {
+            resume_token: "token".into(),
+            payload: None,
+            raw_body: raw_body.map(String::from),
+            signature: signature.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let body = br#"{"paid":true}"#;
+        assert!(verify_signature("whsec", body, SIGNATURE));
+        assert!(verify_signature("whsec", body, SIGNATURE.trim_start_matches("sha256=")));
+        assert!(!verify_signature("other", body, SIGNATURE));
+        assert!(!verify_signature("whsec", br#"{"paid":false}"#, SIGNATURE));
+        assert!(!verify_signature("whsec", body, "sha256=zz"));
+    }
+
+    #[test]
+    fn test_token_prefix_handles_short_tokens() {
+        assert_eq!(token_prefix("0123456789abcdef"), "01234567");
+        assert_eq!(token_prefix("abc"), "abc");
+        assert_eq!(token_prefix(""), "");
+    }
+
+    #[test]
+    fn test_validate_outcomes() {
+        let names = |n: &[&str]| n.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(validate_outcomes(None), Ok(()));
+        assert_eq!(validate_outcomes(Some(&names(&["approve", "reject"]))), Ok(()));
+        assert!(validate_outcomes(Some(&[])).is_err());
+        assert!(validate_outcomes(Some(&names(&["approve", " "]))).is_err());
+        assert_eq!(
+            validate_outcomes(Some(&names(&["approve", "approve"]))).unwrap_err(),
+            "Duplicate webhook wait outcome 'approve'"
+        );
+    }
+
+    #[test]
+    fn test_check_signature_requires_signature_and_body() {
+        let body = r#"{"paid":true}"#;
+        assert_eq!(check_signature("whsec", &resume(Some(SIGNATURE), Some(body))), Ok(()));
+        assert_eq!(
+            check_signature("whsec", &resume(None, Some(body))).unwrap_err(),
+            "Missing webhook signature"
+        );
+        assert!(check_signature("whsec", &resume(Some(SIGNATURE), None)).is_err());
+        assert_eq!(
+            check_signature("whsec", &resume(Some("sha256=00"), Some(body))).unwrap_err(),
+            "Invalid webhook signature"
+        );
+    }
+}
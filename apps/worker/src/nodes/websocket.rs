@@ -0,0 +1,701 @@
+//! WebSocket node execution.
+//!
+//! Connects to a WebSocket endpoint, optionally sends one text message, and
+//! collects responses until the `collect_until` condition is met. Each received
+//! message is streamed to the UI as a data chunk.
+//!
+//! The handshake goes through reqwest's HTTP/1.1 upgrade, so TLS behaves the
+//! same as for HTTP nodes, and so does the SSRF policy (internal addresses are
+//! refused with 403). Handshakes also count towards the host's circuit breaker.
+//!
+//! Framing (RFC 6455) is handled here rather than by tokio-tungstenite. Its
+//! `client_async` would take a stream connected behind the SSRF guard too,
+//! but that means a second TLS setup next to reqwest's, while the upgrade
+//! hands us a stream that already went through it. The node is only a client
+//! that sends one text message and reads whole messages back, with no
+//! extensions or subprotocols, so the framing needed is small.
+//! `WEBSOCKET_MAX_WAIT_MS` caps how long a collection may wait (default 60s).
+
+use crate::circuit_breaker::{self, CircuitBreakers};
+use crate::ssrf::{self, SsrfPolicy};
+use crate::streaming::StreamContext;
+use crate::types::{CollectMode, WebSocketNodeData};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha1::{Digest, Sha1};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+/// GUID appended to the key when computing `Sec-WebSocket-Accept`
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// How long the TCP (and TLS) connect may take before the handshake fails
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default upper bound on any collection, so a silent server can't hold a worker forever
+const DEFAULT_MAX_WAIT_MS: u64 = 60_000;
+
+/// Largest message we accept (16MB)
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Largest payload of a control frame (RFC 6455 section 5.5)
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// A complete (reassembled) data message or close frame.
+#[derive(Debug, PartialEq)]
+enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The close frame's status code, if it had one
+    Close(Option<[u8; 2]>),
+}
+
+/// One frame as read off the wire (payload already unmasked).
+#[derive(Debug)]
+struct Frame {
+    fin: bool,
+    /// RSV1-3, which must be 0 without a negotiated extension
+    rsv: u8,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+}
+
+/// Longest a collection may wait (`WEBSOCKET_MAX_WAIT_MS`).
+fn max_wait() -> Duration {
+    Duration::from_millis(
+        std::env::var("WEBSOCKET_MAX_WAIT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_WAIT_MS),
+    )
+}
+
+/// Message limit and how long to collect for; `max_wait` bounds every mode,
+/// including a node's own timeout.
+fn collection_bounds(mode: &CollectMode, max_wait: Duration) -> (Option<usize>, Duration) {
+    match mode {
+        CollectMode::Messages(n) => (Some(*n as usize), max_wait),
+        CollectMode::Timeout(ms) => (None, Duration::from_millis(*ms as u64).min(max_wait)),
+        CollectMode::CloseFrame => (None, max_wait),
+    }
+}
+
+/// Execute a WebSocket node with cancellation support.
+/// Returns (status_code, body, was_cancelled).
+pub async fn execute(
+    data: WebSocketNodeData,
+    stream_ctx: Option<&StreamContext>,
+    cancel_token: &CancellationToken,
+    breakers: &CircuitBreakers,
+    ssrf_policy: &SsrfPolicy,
+) -> (u16, Option<serde_json::Value>, bool) {
+    let url = match handshake_url(&data.url) {
+        Ok(url) => url,
+        Err(e) => return (400, Some(serde_json::json!({ "error": e })), false),
+    };
+
+    // Refuse internal destinations before connecting
+    if let Err(e) = ssrf_policy.check_url(&url).await {
+        if let Some(ctx) = stream_ctx {
+            ctx.error(&format!("Request blocked: {}", e)).await;
+        }
+        return (403, Some(ssrf::blocked_body(&e)), false);
+    }
+
+    // Fail fast if this host has been failing repeatedly (shared with HTTP nodes)
+    let host = url.host_str().map(|h| h.to_string());
+    if let Some(ref host) = host
+        && !circuit_breaker::allow_request(breakers, host).await
+    {
+        if let Some(ctx) = stream_ctx {
+            ctx.error(&format!("Circuit open for {}", host)).await;
+        }
+        return (
+            503,
+            Some(serde_json::json!({
+                "error": format!("Circuit breaker open for host {}", host),
+                "circuit_open": true
+            })),
+            false,
+        );
+    }
+
+    if let Some(ctx) = stream_ctx {
+        ctx.progress(&format!("Connecting to {}", data.url)).await;
+    }
+
+    // Upgrades only work over HTTP/1.1, so don't let ALPN pick h2
+    let builder = ssrf::guard(
+        reqwest::Client::builder().http1_only().connect_timeout(CONNECT_TIMEOUT),
+        Arc::new(ssrf_policy.clone()),
+    );
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            return (500, Some(serde_json::json!({ "error": format!("Failed to build client: {}", e) })), false);
+        }
+    };
+
+    let key = BASE64.encode(rand::random::<[u8; 16]>());
+    let mut req = client
+        .get(url)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", &key);
+
+    if let Some(h) = data.headers {
+        for (k, v) in h {
+            req = req.header(k, v);
+        }
+    }
+
+    let result = tokio::select! {
+        biased;
+
+        _ = cancel_token.cancelled() => {
+            return (499, Some(serde_json::json!({ "error": "WebSocket cancelled" })), true);
+        }
+
+        result = req.send() => result,
+    };
+
+    // Transport errors and 5xx handshake responses count as failures
+    if let Some(ref host) = host {
+        let success = matches!(&result, Ok(resp) if !resp.status().is_server_error());
+        circuit_breaker::record_result(breakers, host, success).await;
+    }
+
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            if let Some(ctx) = stream_ctx {
+                ctx.error(&format!("Connection failed: {}", e)).await;
+            }
+            return (500, Some(serde_json::json!({ "error": format!("WebSocket connection failed: {}", e) })), false);
+        }
+    };
+
+    if resp.status() != reqwest::StatusCode::SWITCHING_PROTOCOLS {
+        let status = resp.status().as_u16();
+        let status = if status >= 400 { status } else { 502 };
+        return (
+            status,
+            Some(serde_json::json!({
+                "error": format!("WebSocket handshake failed: server responded {}", resp.status())
+            })),
+            false,
+        );
+    }
+
+    let accept = resp
+        .headers()
+        .get("sec-websocket-accept")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    if accept.as_deref() != Some(accept_key(&key).as_str()) {
+        return (
+            502,
+            Some(serde_json::json!({ "error": "WebSocket handshake failed: invalid Sec-WebSocket-Accept" })),
+            false,
+        );
+    }
+
+    let mut stream = match resp.upgrade().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            return (502, Some(serde_json::json!({ "error": format!("WebSocket upgrade failed: {}", e) })), false);
+        }
+    };
+
+    if let Some(ctx) = stream_ctx {
+        ctx.progress("Connected").await;
+    }
+
+    if let Some(message) = data.send
+        && let Err(e) = write_frame(&mut stream, OP_TEXT, message.as_bytes()).await
+    {
+        return (500, Some(serde_json::json!({ "error": format!("WebSocket send failed: {}", e) })), false);
+    }
+
+    let collect_until = data.collect_until.unwrap_or_default();
+    let max_wait = max_wait();
+    let (limit, wait) = collection_bounds(&collect_until, max_wait);
+    let deadline = tokio::time::Instant::now() + wait;
+
+    let mut messages: Vec<serde_json::Value> = Vec::new();
+    let mut server_closed = false;
+    // Our half of the close handshake echoes the server's status code
+    let mut close_payload: Vec<u8> = Vec::new();
+
+    loop {
+        if limit.is_some_and(|n| messages.len() >= n) {
+            break;
+        }
+
+        tokio::select! {
+            biased;
+
+            _ = cancel_token.cancelled() => {
+                let _ = write_frame(&mut stream, OP_CLOSE, &[]).await;
+                return (499, Some(serde_json::json!({ "error": "WebSocket cancelled" })), true);
+            }
+
+            _ = tokio::time::sleep_until(deadline) => {
+                if matches!(collect_until, CollectMode::Timeout(_)) {
+                    break;
+                }
+                let _ = write_frame(&mut stream, OP_CLOSE, &[]).await;
+                return (
+                    504,
+                    Some(serde_json::json!({
+                        "error": format!("WebSocket timed out after {}ms", max_wait.as_millis()),
+                        "messages": messages
+                    })),
+                    false,
+                );
+            }
+
+            result = read_message(&mut stream) => match result {
+                Ok(Message::Close(code)) => {
+                    server_closed = true;
+                    close_payload = code.map(Vec::from).unwrap_or_default();
+                    break;
+                }
+                Ok(message) => {
+                    let (raw, value) = message_to_json(message);
+                    if let Some(ctx) = stream_ctx {
+                        ctx.data(&raw).await;
+                    }
+                    messages.push(value);
+                }
+                Err(e) => {
+                    if let Some(ctx) = stream_ctx {
+                        ctx.error(&e).await;
+                    }
+                    return (
+                        500,
+                        Some(serde_json::json!({ "error": format!("WebSocket error: {}", e), "messages": messages })),
+                        false,
+                    );
+                }
+            }
+        }
+    }
+
+    // Close handshake: echo the server's close, or start our own
+    let _ = write_frame(&mut stream, OP_CLOSE, &close_payload).await;
+    let _ = stream.shutdown().await;
+
+    if let Some(ctx) = stream_ctx {
+        let reason = if server_closed { "server closed" } else { "done" };
+        ctx.progress(&format!("Received {} message(s) ({})", messages.len(), reason)).await;
+    }
+
+    (200, Some(serde_json::Value::Array(messages)), false)
+}
+
+/// Map a `ws://`/`wss://` URL to the `http://`/`https://` URL used for the handshake.
+fn handshake_url(url: &str) -> Result<reqwest::Url, String> {
+    let mut parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid WebSocket URL: {}", e))?;
+    let scheme = match parsed.scheme() {
+        "ws" => "http",
+        "wss" => "https",
+        other => return Err(format!("Unsupported WebSocket scheme '{}' (use ws:// or wss://)", other)),
+    };
+    // Url only allows switching between "special" schemes via string round-trip
+    let rest = &parsed.as_str()[parsed.scheme().len()..];
+    parsed = reqwest::Url::parse(&format!("{}{}", scheme, rest)).map_err(|e| e.to_string())?;
+    Ok(parsed)
+}
+
+/// `Sec-WebSocket-Accept` value the server must return for `key`.
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(WS_GUID.as_bytes());
+    BASE64.encode(hasher.finalize())
+}
+
+/// Text messages are parsed as JSON when possible; binary messages are base64.
+fn message_to_json(message: Message) -> (String, serde_json::Value) {
+    match message {
+        Message::Text(text) => {
+            let value = serde_json::from_str(&text).unwrap_or_else(|_| serde_json::Value::String(text.clone()));
+            (text, value)
+        }
+        Message::Binary(bytes) => {
+            let encoded = BASE64.encode(&bytes);
+            (encoded.clone(), serde_json::Value::String(encoded))
+        }
+        Message::Close(_) => (String::new(), serde_json::Value::Null),
+    }
+}
+
+/// Encode a single client frame (FIN set, masked as required for clients).
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+
+    let len = payload.len();
+    if len < 126 {
+        frame.push(0x80 | len as u8);
+    } else if len <= u16::MAX as usize {
+        frame.push(0x80 | 126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        frame.push(0x80 | 127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, opcode: u8, payload: &[u8]) -> Result<(), String> {
+    let frame = encode_frame(opcode, payload, rand::random());
+    stream.write_all(&frame).await.map_err(|e| e.to_string())?;
+    stream.flush().await.map_err(|e| e.to_string())
+}
+
+/// Read one frame.
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Frame, String> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).await.map_err(|e| format!("Connection closed: {}", e))?;
+
+    let fin = head[0] & 0x80 != 0;
+    let rsv = (head[0] >> 4) & 0x07;
+    let opcode = head[0] & 0x0F;
+    let masked = head[1] & 0x80 != 0;
+
+    let len = match head[1] & 0x7F {
+        126 => stream.read_u16().await.map_err(|e| e.to_string())? as u64,
+        127 => stream.read_u64().await.map_err(|e| e.to_string())?,
+        n => n as u64,
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(format!("Frame too large ({} bytes)", len));
+    }
+
+    let mut mask = [0u8; 4];
+    if masked {
+        stream.read_exact(&mut mask).await.map_err(|e| e.to_string())?;
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload).await.map_err(|e| e.to_string())?;
+    if masked {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+
+    Ok(Frame { fin, rsv, opcode, masked, payload })
+}
+
+/// Reject server frames RFC 6455 says a client must fail the connection on.
+fn check_server_frame(frame: &Frame) -> Result<(), String> {
+    if frame.masked {
+        return Err("Server sent a masked frame".to_string());
+    }
+    if frame.rsv != 0 {
+        return Err(format!("Reserved frame bits set ({:#05b}) without an extension", frame.rsv));
+    }
+    if frame.opcode & 0x8 != 0 {
+        if !frame.fin {
+            return Err("Fragmented control frame".to_string());
+        }
+        if frame.payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(format!("Control frame payload too large ({} bytes)", frame.payload.len()));
+        }
+        if frame.opcode == OP_CLOSE && frame.payload.len() == 1 {
+            return Err("Close frame with a truncated status code".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Read the next complete message, reassembling fragments and answering pings.
+async fn read_message<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<Message, String> {
+    let mut message_opcode: Option<u8> = None;
+    let mut buffer: Vec<u8> = Vec::new();
+
+    loop {
+        let frame = read_frame(stream).await?;
+        check_server_frame(&frame)?;
+        let Frame { fin, opcode, payload, .. } = frame;
+
+        match opcode {
+            OP_PING => {
+                write_frame(stream, OP_PONG, &payload).await?;
+                continue;
+            }
+            OP_PONG => continue,
+            OP_CLOSE => return Ok(Message::Close(payload.get(..2).map(|code| [code[0], code[1]]))),
+            OP_TEXT | OP_BINARY if message_opcode.is_none() => message_opcode = Some(opcode),
+            OP_CONTINUATION if message_opcode.is_some() => {}
+            other => return Err(format!("Unexpected frame opcode {:#x}", other)),
+        }
+
+        if buffer.len() + payload.len() > MAX_MESSAGE_SIZE {
+            return Err("Message too large".to_string());
+        }
+        buffer.extend_from_slice(&payload);
+
+        if fin {
+            return match message_opcode {
+                Some(OP_TEXT) => String::from_utf8(buffer)
+                    .map(Message::Text)
+                    .map_err(|_| "Invalid UTF-8 in text message".to_string()),
+                _ => Ok(Message::Binary(buffer)),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit_breaker::BreakerConfig;
+
+    /// Unmasked server frame
+    fn server_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 | opcode } else { opcode }, payload.len() as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_accept_key_rfc_example() {
+        // RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_handshake_url() {
+        assert_eq!(handshake_url("wss://example.com/feed?x=1").unwrap().as_str(), "https://example.com/feed?x=1");
+        assert_eq!(handshake_url("ws://localhost:8080/").unwrap().as_str(), "http://localhost:8080/");
+        assert!(handshake_url("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn test_collect_mode_serde() {
+        let mode: CollectMode = serde_json::from_value(serde_json::json!({"mode": "messages", "value": 3})).unwrap();
+        assert_eq!(mode, CollectMode::Messages(3));
+        let mode: CollectMode = serde_json::from_value(serde_json::json!({"mode": "close_frame"})).unwrap();
+        assert_eq!(mode, CollectMode::CloseFrame);
+    }
+
+    #[test]
+    fn test_node_timeout_is_capped_by_max_wait() {
+        let max_wait = Duration::from_secs(60);
+        assert_eq!(collection_bounds(&CollectMode::Timeout(5_000), max_wait), (None, Duration::from_secs(5)));
+        assert_eq!(collection_bounds(&CollectMode::Timeout(u32::MAX), max_wait), (None, max_wait));
+        assert_eq!(collection_bounds(&CollectMode::Messages(3), max_wait), (Some(3), max_wait));
+    }
+
+    #[tokio::test]
+    async fn test_frame_roundtrip_masked() {
+        let payload = vec![b'x'; 300]; // Exercises the 16-bit length form
+        let frame = encode_frame(OP_TEXT, &payload, [1, 2, 3, 4]);
+        let frame = read_frame(&mut frame.as_slice()).await.unwrap();
+        assert!(frame.fin && frame.masked);
+        assert_eq!(frame.opcode, OP_TEXT);
+        assert_eq!(frame.payload, payload);
+    }
+
+    #[tokio::test]
+    async fn test_fragments_reassembled_and_ping_answered() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let mut frames = server_frame(false, OP_TEXT, b"hel");
+        frames.extend(server_frame(true, OP_PING, b"p"));
+        frames.extend(server_frame(true, OP_CONTINUATION, b"lo"));
+        frames.extend(server_frame(true, OP_CLOSE, &[]));
+        server.write_all(&frames).await.unwrap();
+
+        assert_eq!(read_message(&mut client).await.unwrap(), Message::Text("hello".to_string()));
+        assert_eq!(read_message(&mut client).await.unwrap(), Message::Close(None));
+
+        // The ping got a (masked) pong back
+        let pong = read_frame(&mut server).await.unwrap();
+        assert_eq!(pong.opcode, OP_PONG);
+        assert_eq!(pong.payload, b"p");
+    }
+
+    /// `read_message` over raw server bytes
+    async fn read_bytes(bytes: &[u8]) -> Result<Message, String> {
+        let (mut client, mut server) = tokio::io::duplex(1 << 17);
+        server.write_all(bytes).await.unwrap();
+        read_message(&mut client).await
+    }
+
+    #[tokio::test]
+    async fn test_rfc_6455_example_frames() {
+        // Section 5.7: a masked "Hello" from the client, byte for byte
+        let masked = encode_frame(OP_TEXT, b"Hello", [0x37, 0xfa, 0x21, 0x3d]);
+        assert_eq!(masked, [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+        let pong = encode_frame(OP_PONG, b"Hello", [0x37, 0xfa, 0x21, 0x3d]);
+        assert_eq!(pong, [0x8a, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+
+        // Single-frame and fragmented unmasked "Hello" from the server
+        let single = [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+        assert_eq!(read_bytes(&single).await.unwrap(), Message::Text("Hello".to_string()));
+        let fragmented = [0x01, 0x03, 0x48, 0x65, 0x6c, 0x80, 0x02, 0x6c, 0x6f];
+        assert_eq!(read_bytes(&fragmented).await.unwrap(), Message::Text("Hello".to_string()));
+
+        // 256 bytes and 64KiB use the 16- and 64-bit extended lengths
+        assert_eq!(encode_frame(OP_BINARY, &[0; 256], [0; 4])[..4], [0x82, 0xfe, 0x01, 0x00]);
+        assert_eq!(
+            encode_frame(OP_BINARY, &[0; 65536], [0; 4])[..10],
+            [0x82, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00]
+        );
+        let mut binary = vec![0x82, 0x7e, 0x01, 0x00];
+        binary.extend([7u8; 256]);
+        assert_eq!(read_bytes(&binary).await.unwrap(), Message::Binary(vec![7; 256]));
+        let mut binary = vec![0x82, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00];
+        binary.extend(vec![7u8; 65536]);
+        assert_eq!(read_bytes(&binary).await.unwrap(), Message::Binary(vec![7; 65536]));
+
+        // Close with status 1000 keeps the code for the echo
+        assert_eq!(read_bytes(&[0x88, 0x02, 0x03, 0xe8]).await.unwrap(), Message::Close(Some([0x03, 0xe8])));
+    }
+
+    #[tokio::test]
+    async fn test_protocol_violations_fail_the_connection() {
+        let mut long_ping = vec![0x89, 0x7e, 0x00, 0x7e];
+        long_ping.extend([0u8; 126]);
+        let cases: [(&str, Vec<u8>); 8] = [
+            ("masked server frame", vec![0x81, 0x81, 1, 2, 3, 4, b'a' ^ 1]),
+            ("reserved bit", vec![0xc1, 0x01, b'a']),
+            ("fragmented ping", vec![0x09, 0x00]),
+            ("oversized ping", long_ping),
+            ("one-byte close", vec![0x88, 0x01, 0x03]),
+            ("continuation first", vec![0x80, 0x01, b'a']),
+            ("reserved opcode", vec![0x83, 0x00]),
+            ("new message mid-fragment", vec![0x01, 0x01, b'a', 0x81, 0x01, b'b']),
+        ];
+        for (name, bytes) in cases {
+            assert!(read_bytes(&bytes).await.is_err(), "{} was accepted", name);
+        }
+        assert!(read_bytes(&[0x81, 0x02, 0xc3, 0x28]).await.is_err(), "invalid UTF-8 text was accepted");
+    }
+
+    /// Accept one connection and check the opening handshake against section
+    /// 4.1, answering with `status` (101 completes it).
+    async fn accept_handshake(listener: &tokio::net::TcpListener, status: u16) -> tokio::net::TcpStream {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(socket.read_u8().await.unwrap());
+        }
+        let request = String::from_utf8(request).unwrap();
+        let header = |name: &str| {
+            request
+                .lines()
+                .filter_map(|l| l.split_once(':'))
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.trim().to_string())
+                .unwrap_or_default()
+        };
+        assert!(request.starts_with("GET /feed HTTP/1.1\r\n"), "{}", request);
+        assert!(header("upgrade").eq_ignore_ascii_case("websocket"));
+        assert!(header("connection").to_ascii_lowercase().contains("upgrade"));
+        assert_eq!(header("sec-websocket-version"), "13");
+        let key = header("sec-websocket-key");
+        assert_eq!(BASE64.decode(&key).unwrap().len(), 16, "the key is a 16-byte nonce");
+
+        let reply = if status == 101 {
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )
+        } else {
+            format!("HTTP/1.1 {} Unavailable\r\nContent-Length: 0\r\n\r\n", status)
+        };
+        socket.write_all(reply.as_bytes()).await.unwrap();
+        socket
+    }
+
+    fn local_node(port: u16, send: Option<&str>) -> WebSocketNodeData {
+        WebSocketNodeData {
+            url: format!("ws://127.0.0.1:{}/feed", port),
+            send: send.map(str::to_string),
+            collect_until: None,
+            headers: None,
+        }
+    }
+
+    fn permissive() -> SsrfPolicy {
+        SsrfPolicy { allow_private: true, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_session_against_local_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let mut socket = accept_handshake(&listener, 101).await;
+            // Client frames must be masked (section 5.3)
+            let sent = read_frame(&mut socket).await.unwrap();
+            socket.write_all(&server_frame(true, OP_TEXT, br#"{"n":1}"#)).await.unwrap();
+            socket.write_all(&server_frame(true, OP_BINARY, &[1, 2])).await.unwrap();
+            socket.write_all(&server_frame(true, OP_CLOSE, &[0x03, 0xe8])).await.unwrap();
+            let close = read_frame(&mut socket).await.unwrap();
+            (sent, close)
+        });
+
+        let (status, body, cancelled) = execute(
+            local_node(port, Some("subscribe")),
+            None,
+            &CancellationToken::new(),
+            &circuit_breaker::new_registry(BreakerConfig::from_env()),
+            &permissive(),
+        )
+        .await;
+        assert_eq!((status, cancelled), (200, false), "{:?}", body);
+        assert_eq!(body.unwrap(), serde_json::json!([{ "n": 1 }, "AQI="]));
+
+        let (sent, close) = server.await.unwrap();
+        assert!(sent.masked && sent.fin);
+        assert_eq!((sent.opcode, sent.payload), (OP_TEXT, b"subscribe".to_vec()));
+        // Our close echoes the server's status code (section 5.5.1)
+        assert!(close.masked);
+        assert_eq!((close.opcode, close.payload), (OP_CLOSE, vec![0x03, 0xe8]));
+    }
+
+    #[tokio::test]
+    async fn test_failing_handshakes_open_the_breaker() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let breakers = circuit_breaker::new_registry(BreakerConfig::from_env());
+        let threshold = breakers.config().failure_threshold as usize;
+        tokio::spawn(async move {
+            for _ in 0..threshold {
+                accept_handshake(&listener, 503).await;
+            }
+        });
+
+        for _ in 0..threshold {
+            let (status, _, _) =
+                execute(local_node(port, None), None, &CancellationToken::new(), &breakers, &permissive()).await;
+            assert_eq!(status, 503);
+        }
+        // Now refused without connecting
+        let (status, body, _) =
+            execute(local_node(port, None), None, &CancellationToken::new(), &breakers, &permissive()).await;
+        assert_eq!(status, 503);
+        assert_eq!(body.unwrap()["circuit_open"], true);
+    }
+}
@@ -0,0 +1,223 @@
+//! Orchestrator notifications.
+//!
+//! After a node finishes, the worker tells the web orchestrator so it can
+//! schedule downstream nodes. Child runs (sub-flows, map iterations) have no
+//! frontend to recover them, so a lost notification strands the run. The first
+//! attempt is made inline; a failed one is retried with `calculate_backoff` in
+//! a background task (the job doesn't wait on it), and if every attempt fails,
+//! a marker is pushed to a Redis list that the scheduler drains later.
+//!
+//! The drain moves each marker into a processing list (`LMOVE`) before sending
+//! it and only deletes it once delivered, so a scheduler that dies mid-send
+//! leaves it there for the next drain instead of losing it.
+
+use crate::retry::calculate_backoff;
+use redis::{AsyncCommands, Direction, RedisResult};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Redis list of notifications that still need to be delivered
+pub const NOTIFY_RETRY_KEY: &str = "swiftgrid:orchestrate_retry";
+
+/// Redis list holding the marker a drain is currently sending
+pub const NOTIFY_PROCESSING_KEY: &str = "swiftgrid:orchestrate_retry:processing";
+
+/// Default retries after the first attempt
+const DEFAULT_NOTIFY_RETRIES: u32 = 3;
+
+/// Max markers the scheduler re-sends per drain
+const DRAIN_BATCH: usize = 50;
+
+/// Retries after the first attempt (`ORCHESTRATOR_NOTIFY_RETRIES`)
+fn notify_retries() -> u32 {
+    std::env::var("ORCHESTRATOR_NOTIFY_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_NOTIFY_RETRIES)
+}
+
+/// A notification that couldn't be delivered yet.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NotifyMarker {
+    pub run_id: String,
+    pub node_id: String,
+    pub success: bool,
+}
+
+enum NotifyError {
+    /// Network error or 5xx - worth retrying
+    Transient(String),
+    /// 4xx - the orchestrator refused it; retrying won't help
+    Rejected(String),
+}
+
+async fn post_once(client: &reqwest::Client, marker: &NotifyMarker) -> Result<(), NotifyError> {
+    let base_url = std::env::var("ORCHESTRATOR_URL")
+        .unwrap_or_else(|_| "http://localhost:5173".to_string());
+
+    let resp = client
+        .post(format!("{}/api/orchestrate", base_url))
+        .json(marker)
+        .send()
+        .await
+        .map_err(|e| NotifyError::Transient(e.to_string()))?;
+
+    let status = resp.status();
+    if status.is_success() {
+        Ok(())
+    } else if status.is_client_error() {
+        Err(NotifyError::Rejected(format!("Orchestrator returned {}", status)))
+    } else {
+        Err(NotifyError::Transient(format!("Orchestrator returned {}", status)))
+    }
+}
+
+/// Notify the orchestrator that a node has completed.
+///
+/// Retries run in the background after a transient failure, falling back to
+/// the durable retry list if every attempt fails.
+pub async fn notify(
+    http_client: &reqwest::Client,
+    redis_client: &redis::Client,
+    run_id: &Uuid,
+    node_id: &str,
+    success: bool,
+) {
+    let marker = NotifyMarker {
+        run_id: run_id.to_string(),
+        node_id: node_id.to_string(),
+        success,
+    };
+
+    match post_once(http_client, &marker).await {
+        Ok(()) => {}
+        Err(NotifyError::Rejected(e)) => {
+            tracing::warn!("Orchestrator rejected notification for {}/{}: {}", marker.run_id, marker.node_id, e);
+        }
+        Err(NotifyError::Transient(e)) => {
+            tracing::error!("Failed to notify orchestrator (attempt 1/{}): {}", notify_retries() + 1, e);
+            let http_client = http_client.clone();
+            let redis_client = redis_client.clone();
+            tokio::spawn(async move {
+                retry(&http_client, &redis_client, marker, e).await;
+            });
+        }
+    }
+}
+
+/// Attempts after the first, then the retry list if they all fail.
+async fn retry(http_client: &reqwest::Client, redis_client: &redis::Client, marker: NotifyMarker, first_error: String) {
+    let retries = notify_retries();
+    let mut last_error = first_error;
+
+    for attempt in 1..=retries {
+        tokio::time::sleep(calculate_backoff(attempt)).await;
+
+        match post_once(http_client, &marker).await {
+            Ok(()) => return,
+            Err(NotifyError::Rejected(e)) => {
+                tracing::warn!("Orchestrator rejected notification for {}/{}: {}", marker.run_id, marker.node_id, e);
+                return;
+            }
+            Err(NotifyError::Transient(e)) => {
+                tracing::error!(
+                    "Failed to notify orchestrator (attempt {}/{}): {}",
+                    attempt + 1,
+                    retries + 1,
+                    e
+                );
+                last_error = e;
+            }
+        }
+    }
+
+    tracing::warn!("Giving up on orchestrator for now ({}), queueing for scheduler retry", last_error);
+    enqueue(redis_client, &marker).await;
+}
+
+async fn enqueue(redis_client: &redis::Client, marker: &NotifyMarker) {
+    let Ok(json) = serde_json::to_string(marker) else {
+        return;
+    };
+    match redis_client.get_multiplexed_async_connection().await {
+        Ok(mut con) => {
+            let result: RedisResult<()> = con.lpush(NOTIFY_RETRY_KEY, &json).await;
+            if let Err(e) = result {
+                tracing::error!("CRITICAL: failed to queue orchestrator retry {}: {}", json, e);
+            }
+        }
+        Err(e) => tracing::error!("CRITICAL: failed to queue orchestrator retry {}: {}", json, e),
+    }
+}
+
+/// Re-send queued notifications (called periodically by the scheduler).
+///
+/// Oldest first; stops at the first transient failure so an unavailable
+/// orchestrator isn't hammered, leaving the marker at the front of the queue.
+pub async fn drain_retry_queue(http_client: &reqwest::Client, redis_client: &redis::Client) {
+    let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
+        return;
+    };
+
+    // A marker left mid-send by a scheduler that died goes back to the front
+    loop {
+        let stranded: RedisResult<Option<String>> =
+            con.lmove(NOTIFY_PROCESSING_KEY, NOTIFY_RETRY_KEY, Direction::Left, Direction::Right).await;
+        if !matches!(stranded, Ok(Some(_))) {
+            break;
+        }
+    }
+
+    for _ in 0..DRAIN_BATCH {
+        let item: RedisResult<Option<String>> =
+            con.lmove(NOTIFY_RETRY_KEY, NOTIFY_PROCESSING_KEY, Direction::Right, Direction::Left).await;
+        let Ok(Some(json)) = item else {
+            return;
+        };
+
+        let delivered = match serde_json::from_str::<NotifyMarker>(&json) {
+            Err(_) => {
+                tracing::warn!("Scheduler: Dropping malformed orchestrator retry marker: {}", json);
+                true
+            }
+            Ok(marker) => match post_once(http_client, &marker).await {
+                Ok(()) => {
+                    tracing::info!("Scheduler: Delivered queued orchestrator notification for {}/{}", marker.run_id, marker.node_id);
+                    true
+                }
+                Err(NotifyError::Rejected(e)) => {
+                    tracing::warn!("Scheduler: Orchestrator rejected queued notification {}: {}", json, e);
+                    true
+                }
+                Err(NotifyError::Transient(_)) => false,
+            },
+        };
+
+        if delivered {
+            let _: RedisResult<()> = con.lrem(NOTIFY_PROCESSING_KEY, 1, &json).await;
+        } else {
+            // Put it back where it was and try again next time
+            let _: RedisResult<Option<String>> =
+                con.lmove(NOTIFY_PROCESSING_KEY, NOTIFY_RETRY_KEY, Direction::Left, Direction::Right).await;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_matches_orchestrate_payload() {
+        let marker = NotifyMarker {
+            run_id: "r1".to_string(),
+            node_id: "n1".to_string(),
+            success: true,
+        };
+        let json = serde_json::to_value(&marker).unwrap();
+        assert_eq!(json, serde_json::json!({ "runId": "r1", "nodeId": "n1", "success": true }));
+        assert_eq!(serde_json::from_value::<NotifyMarker>(json).unwrap(), marker);
+    }
+}
@@ -0,0 +1,125 @@
+//! Priority job streams.
+//!
+//! Jobs are enqueued on one of three streams by their `priority`: high,
+//! default and low. The default stream is the original `swiftgrid_stream`, so
+//! producers that don't set a priority (the API, lifecycle events) keep
+//! working unchanged.
+//! Jobs enqueued on a job's behalf (Parallel branches and their joins,
+//! DbWait/SubFlow resumes, the scheduler's batch recovery) keep its priority.
+//!
+//! Workers try the streams highest first, except that every few reads a lower
+//! tier gets the first look (weighted 4:2:1). Under a sustained flood of
+//! high-priority jobs the other tiers slow down but are never starved.
+
+use crate::types::JobPriority;
+use serde::Deserialize;
+
+pub const STREAM_HIGH: &str = "swiftgrid_stream:high";
+pub const STREAM_DEFAULT: &str = "swiftgrid_stream";
+pub const STREAM_LOW: &str = "swiftgrid_stream:low";
+
+/// Every job stream, highest priority first
+pub const STREAMS: [&str; 3] = [STREAM_HIGH, STREAM_DEFAULT, STREAM_LOW];
+
+/// Reads per cycle in which each tier (high, default, low) is tried first
+const LEAD_WEIGHTS: [u64; 3] = [4, 2, 1];
+
+impl JobPriority {
+    /// The stream jobs of this priority are enqueued on
+    pub fn stream(self) -> &'static str {
+        match self {
+            JobPriority::High => STREAM_HIGH,
+            JobPriority::Default => STREAM_DEFAULT,
+            JobPriority::Low => STREAM_LOW,
+        }
+    }
+
+    /// Name as stored in `batch_operations.priority` and suspension contexts
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobPriority::High => "high",
+            JobPriority::Default => "default",
+            JobPriority::Low => "low",
+        }
+    }
+
+    /// Parse a stored name; a missing or unknown one is the default priority
+    pub fn from_stored(name: Option<&str>) -> Self {
+        match name {
+            Some("high") => JobPriority::High,
+            Some("low") => JobPriority::Low,
+            _ => JobPriority::Default,
+        }
+    }
+}
+
+/// Stream for an already serialized job: its `priority`, or the default
+/// stream if it has none.
+pub fn stream_for_payload(payload: &str) -> &'static str {
+    #[derive(Deserialize)]
+    struct Priority {
+        #[serde(default)]
+        priority: JobPriority,
+    }
+    serde_json::from_str::<Priority>(payload)
+        .map(|p| p.priority)
+        .unwrap_or_default()
+        .stream()
+}
+
+/// The order to try the streams in on a worker's `tick`th read: the tier
+/// whose turn it is, then the others highest first.
+pub fn read_order(tick: u64) -> [&'static str; 3] {
+    let mut slot = tick % LEAD_WEIGHTS.iter().sum::<u64>();
+    let mut lead = 0;
+    for (tier, &weight) in LEAD_WEIGHTS.iter().enumerate() {
+        if slot < weight {
+            lead = tier;
+            break;
+        }
+        slot -= weight;
+    }
+
+    let rest = STREAMS.iter().enumerate().filter(|&(tier, _)| tier != lead).map(|(_, s)| *s);
+    let mut order = [STREAMS[lead]; 3];
+    for (next, stream) in order[1..].iter_mut().zip(rest) {
+        *next = stream;
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_order_is_weighted_but_never_starves() {
+        let leads: Vec<&str> = (0..7).map(|tick| read_order(tick)[0]).collect();
+        assert_eq!(leads.iter().filter(|&&s| s == STREAM_HIGH).count(), 4);
+        assert_eq!(leads.iter().filter(|&&s| s == STREAM_DEFAULT).count(), 2);
+        assert_eq!(leads.iter().filter(|&&s| s == STREAM_LOW).count(), 1);
+
+        // Whoever goes first, the rest follow in priority order
+        assert_eq!(read_order(0), [STREAM_HIGH, STREAM_DEFAULT, STREAM_LOW]);
+        assert_eq!(read_order(6), [STREAM_LOW, STREAM_HIGH, STREAM_DEFAULT]);
+        assert_eq!(read_order(4), [STREAM_DEFAULT, STREAM_HIGH, STREAM_LOW]);
+    }
+
+    #[test]
+    fn test_stored_priority_round_trips() {
+        for priority in [JobPriority::High, JobPriority::Default, JobPriority::Low] {
+            assert_eq!(JobPriority::from_stored(Some(priority.as_str())), priority);
+            assert_eq!(serde_json::json!(priority), priority.as_str());
+        }
+        assert_eq!(JobPriority::from_stored(None), JobPriority::Default);
+        assert_eq!(JobPriority::from_stored(Some("urgent")), JobPriority::Default);
+    }
+
+    #[test]
+    fn test_stream_for_payload() {
+        assert_eq!(stream_for_payload(r#"{"id":"a","priority":"high"}"#), STREAM_HIGH);
+        assert_eq!(stream_for_payload(r#"{"id":"a","priority":"low"}"#), STREAM_LOW);
+        assert_eq!(stream_for_payload(r#"{"id":"a"}"#), STREAM_DEFAULT);
+        assert_eq!(stream_for_payload("not json"), STREAM_DEFAULT);
+    }
+}
@@ -0,0 +1,228 @@
+//! Rerun a single node of an existing run, for debugging.
+//!
+//! The node's job is rebuilt from the run's graph snapshot, with templates
+//! resolved against the outputs recorded in `run_events`, then executed as a
+//! detached job: no `run_id` (so nothing is logged to the run and the
+//! orchestrator isn't notified), `isolated`, and no retries. The result is read
+//! back from the results stream by the job's unique ID.
+
+use crate::job_timeout;
+use crate::scheduler::build_job_payload;
+use crate::templating::{self, TemplateContext};
+use crate::types::{ExecutionResult, NodeType, WorkerJob};
+use redis::AsyncCommands;
+use redis::streams::{StreamReadOptions, StreamReadReply};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const STREAM_RESULTS: &str = "swiftgrid_results";
+
+/// Extra time to wait for a result beyond the hard job timeout
+const RESULT_MARGIN: Duration = Duration::from_secs(30);
+
+/// Error type for node reruns
+#[derive(Debug)]
+pub enum RerunError {
+    RunNotFound(Uuid),
+    NodeNotFound(String),
+    Unsupported(String),
+    DatabaseError(String),
+    RedisError(String),
+    Timeout(String),
+}
+
+impl std::fmt::Display for RerunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RerunError::RunNotFound(id) => write!(f, "Run not found: {}", id),
+            RerunError::NodeNotFound(id) => write!(f, "Node not found in run snapshot: {}", id),
+            RerunError::Unsupported(msg) => write!(f, "Cannot rerun node: {}", msg),
+            RerunError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
+            RerunError::RedisError(msg) => write!(f, "Redis error: {}", msg),
+            RerunError::Timeout(id) => write!(f, "Timed out waiting for rerun of {}", id),
+        }
+    }
+}
+
+/// Re-execute `node_id` from `run_id` against its recorded inputs and wait
+/// for the result. The original run is left untouched.
+pub async fn rerun_node(
+    pool: &PgPool,
+    redis: &redis::Client,
+    run_id: &Uuid,
+    node_id: &str,
+) -> Result<ExecutionResult, RerunError> {
+    let run: Option<(serde_json::Value, Option<serde_json::Value>)> =
+        sqlx::query_as("SELECT snapshot_graph, input_data FROM workflow_runs WHERE id = $1")
+            .bind(run_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| RerunError::DatabaseError(e.to_string()))?;
+    let (graph, input_data) = run.ok_or(RerunError::RunNotFound(*run_id))?;
+
+    let outputs = templating::recorded_outputs(pool, run_id)
+        .await
+        .map_err(|e| RerunError::DatabaseError(e.to_string()))?;
+    let job = build_rerun_job(&graph, input_data.as_ref(), &outputs, run_id, node_id)?;
+    let job_id = job.id.clone();
+
+    let mut con = redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| RerunError::RedisError(e.to_string()))?;
+
+    // Start reading results after whatever is already in the stream
+    let latest: redis::streams::StreamRangeReply = con
+        .xrevrange_count(STREAM_RESULTS, "+", "-", 1)
+        .await
+        .map_err(|e| RerunError::RedisError(e.to_string()))?;
+    let mut last_id = latest.ids.first().map(|e| e.id.clone()).unwrap_or_else(|| "0-0".to_string());
+
+    let payload = serde_json::to_string(&job).map_err(|e| RerunError::Unsupported(e.to_string()))?;
+    let _: String = con
+        .xadd(job.priority.stream(), "*", &[("payload", payload)])
+        .await
+        .map_err(|e| RerunError::RedisError(e.to_string()))?;
+
+    tracing::info!(%run_id, node_id, job_id = %job_id, "Rerun: queued node");
+
+    // Blocking reads get their own connection so they don't stall the multiplexer
+    let mut reader = redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| RerunError::RedisError(e.to_string()))?;
+
+    let deadline = Instant::now() + job_timeout::job_timeout().unwrap_or(Duration::from_secs(600)) + RESULT_MARGIN;
+    while Instant::now() < deadline {
+        let reply: StreamReadReply = reader
+            .xread_options(&[STREAM_RESULTS], &[&last_id], &StreamReadOptions::default().block(1000).count(100))
+            .await
+            .map_err(|e| RerunError::RedisError(e.to_string()))?;
+
+        for entry in reply.keys.into_iter().flat_map(|k| k.ids) {
+            last_id = entry.id.clone();
+            let Some(json) = entry.get::<String>("payload") else {
+                continue;
+            };
+            if let Ok(mut result) = serde_json::from_str::<ExecutionResult>(&json)
+                && result.node_id == job_id
+            {
+                result.node_id = node_id.to_string();
+                return Ok(result);
+            }
+        }
+    }
+
+    Err(RerunError::Timeout(job_id))
+}
+
+/// Rebuild a node's job from the snapshot as a detached, single-attempt job.
+fn build_rerun_job(
+    graph: &serde_json::Value,
+    input_data: Option<&serde_json::Value>,
+    outputs: &HashMap<String, serde_json::Value>,
+    run_id: &Uuid,
+    node_id: &str,
+) -> Result<WorkerJob, RerunError> {
+    let mut job = rebuild_job(graph, input_data, outputs, run_id, node_id)?;
+    job.id = format!("rerun:{}:{}", node_id, Uuid::new_v4());
+    job.run_id = None;
+    job.isolated = true;
+    job.retry_count = 0;
+    job.max_retries = 0;
+    job.idempotency_key = None;
+    Ok(job)
+}
+
+/// Rebuild a node's job as the orchestrator would have queued it, with
+/// templates resolved against `outputs`. Nodes that need a live run context
+/// (they suspend the run or spawn child runs) are `Unsupported`.
+pub(crate) fn rebuild_job(
+    graph: &serde_json::Value,
+    input_data: Option<&serde_json::Value>,
+    outputs: &HashMap<String, serde_json::Value>,
+    run_id: &Uuid,
+    node_id: &str,
+) -> Result<WorkerJob, RerunError> {
+    let node = graph
+        .get("nodes")
+        .and_then(|n| n.as_array())
+        .and_then(|nodes| nodes.iter().find(|n| n.get("id").and_then(|id| id.as_str()) == Some(node_id)))
+        .ok_or_else(|| RerunError::NodeNotFound(node_id.to_string()))?;
+
+    let ctx = TemplateContext { node_outputs: outputs, input: input_data };
+    let mut node = node.clone();
+    if let Some(data) = node.get("data") {
+        node["data"] = templating::resolve_value(data, &ctx);
+    }
+
+    // Code nodes take their (resolved) inputs, as when the orchestrator built them
+    let code_inputs = node.get("data").and_then(|d| d.get("inputs")).cloned();
+    let payload = build_job_payload(&node, run_id, &code_inputs).ok_or_else(|| {
+        let node_type = node.get("type").and_then(|t| t.as_str()).unwrap_or("unknown");
+        RerunError::Unsupported(format!("node type '{}' can't be rebuilt", node_type))
+    })?;
+    let job: WorkerJob =
+        serde_json::from_str(&payload).map_err(|e| RerunError::Unsupported(e.to_string()))?;
+
+    if matches!(job.node, NodeType::Delay(_) | NodeType::WebhookWait(_) | NodeType::DbWait(_) | NodeType::SubFlow(_) | NodeType::Map(_) | NodeType::Parallel(_) | NodeType::Loop(_)) {
+        return Err(RerunError::Unsupported(format!("node '{}' needs a live run context", node_id)));
+    }
+    Ok(job)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn graph() -> serde_json::Value {
+        json!({
+            "nodes": [
+                {"id": "fetch", "type": "http-request", "data": {"url": "https://api.test/start", "method": "GET"}},
+                {"id": "report", "type": "http-request", "data": {"url": "https://api.test/items/{{fetch.body.id}}", "method": "POST"}},
+                {"id": "wait", "type": "delay", "data": {"durationMs": 1000}}
+            ],
+            "edges": [{"source": "fetch", "target": "report"}]
+        })
+    }
+
+    #[test]
+    fn test_rerun_job_uses_recorded_outputs_and_is_detached() {
+        let outputs = HashMap::from([("fetch".to_string(), json!({"body": {"id": 42}}))]);
+        let run_id = Uuid::new_v4();
+
+        let job = build_rerun_job(&graph(), None, &outputs, &run_id, "report").unwrap();
+        match &job.node {
+            NodeType::Http(data) => assert_eq!(data.url, "https://api.test/items/42"),
+            other => panic!("unexpected node {:?}", other),
+        }
+
+        // No run_id: the worker logs no events to the original run and never
+        // notifies its orchestrator; no retries means exactly one result
+        assert!(job.run_id.is_none());
+        assert!(job.isolated);
+        assert_eq!(job.max_retries, 0);
+        assert!(job.id.starts_with("rerun:report:"));
+
+        // Each rerun is distinguishable on the results stream
+        let again = build_rerun_job(&graph(), None, &outputs, &run_id, "report").unwrap();
+        assert_ne!(job.id, again.id);
+    }
+
+    #[test]
+    fn test_rerun_rejects_unknown_and_run_bound_nodes() {
+        let outputs = HashMap::new();
+        let run_id = Uuid::new_v4();
+        assert!(matches!(
+            build_rerun_job(&graph(), None, &outputs, &run_id, "nope"),
+            Err(RerunError::NodeNotFound(_))
+        ));
+        assert!(matches!(
+            build_rerun_job(&graph(), None, &outputs, &run_id, "wait"),
+            Err(RerunError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,178 @@
+//! Bare HTTP/1.1 responder for the metrics and health ports.
+//!
+//! Both endpoints answer a handful of `GET`s from scrapers and probes, so a
+//! TCP listener that reads the request head and writes one response is all
+//! they need. The read is bounded in time (`READ_TIMEOUT`) and size
+//! (`MAX_REQUEST_BYTES`) so a client that trickles or floods bytes can't pin
+//! a task.
+
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How long a client has to send its request head
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest request head we read; longer ones get 431
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// How long writing the response may take
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A response to write; the connection is closed after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: String) -> Self {
+        Self { status, content_type, body }
+    }
+
+    fn not_found() -> Self {
+        Self::new(404, "text/plain", String::new())
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Serve `port` until the process exits. `handle` gets each request's path
+/// (for `GET`s only) and returns the response, or `None` for 404. `name`
+/// prefixes the log lines.
+pub async fn serve<F, Fut>(name: &'static str, port: u16, handle: F)
+where
+    F: Fn(String) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Option<Response>> + Send + 'static,
+{
+    let listener = match tokio::net::TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("{}: Failed to bind port {}: {}", name, port, e);
+            return;
+        }
+    };
+
+    loop {
+        let Ok((socket, _)) = listener.accept().await else {
+            continue;
+        };
+        tokio::spawn(respond(socket, READ_TIMEOUT, handle.clone()));
+    }
+}
+
+/// Read one request from `socket` (within `read_timeout`) and answer it.
+async fn respond<S, F, Fut>(mut socket: S, read_timeout: Duration, handle: F)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Option<Response>>,
+{
+    let response = match tokio::time::timeout(read_timeout, read_head(&mut socket)).await {
+        Ok(Ok(Some(head))) => match get_path(&head) {
+            Some(path) => handle(path.to_string()).await.unwrap_or_else(Response::not_found),
+            None => Response::not_found(),
+        },
+        Ok(Ok(None)) => Response::new(431, "text/plain", String::new()),
+        // Timed out or the client went away
+        Ok(Err(_)) | Err(_) => return,
+    };
+    let _ = tokio::time::timeout(WRITE_TIMEOUT, socket.write_all(response.encode().as_bytes())).await;
+}
+
+/// Read up to the end of the request head; `None` if it exceeds
+/// `MAX_REQUEST_BYTES`.
+async fn read_head<S: AsyncRead + Unpin>(socket: &mut S) -> std::io::Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.windows(4).any(|w| w == b"\r\n\r\n") {
+            return Ok(Some(String::from_utf8_lossy(&head).into_owned()));
+        }
+        if head.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+    }
+}
+
+/// The path of a `GET` request line.
+fn get_path(head: &str) -> Option<&str> {
+    let mut parts = head.lines().next()?.split(' ');
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => Some(path),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exchange(request: &[u8]) -> String {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let task = tokio::spawn(respond(server, READ_TIMEOUT, |path: String| async move {
+            (path == "/ping").then(|| Response::new(200, "text/plain", "pong".to_string()))
+        }));
+        client.write_all(request).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        task.await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_routes_get_requests() {
+        let response = exchange(b"GET /ping HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\npong"));
+
+        assert!(exchange(b"GET /other HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404 "));
+        assert!(exchange(b"POST /ping HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404 "));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_is_refused() {
+        let mut request = b"GET /ping HTTP/1.1\r\nX-Filler: ".to_vec();
+        request.extend(std::iter::repeat_n(b'a', MAX_REQUEST_BYTES + 1));
+        assert!(exchange(&request).await.starts_with("HTTP/1.1 431 "));
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_is_dropped() {
+        let (mut client, server) = tokio::io::duplex(1024);
+        let task = tokio::spawn(respond(server, Duration::from_millis(50), |_: String| async { None }));
+        client.write_all(b"GET /ping HTT").await.unwrap();
+
+        // Nothing is written back once the read times out
+        task.await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.is_empty());
+    }
+}
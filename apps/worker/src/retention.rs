@@ -0,0 +1,337 @@
+//! Retention sweep for stream chunks and run events.
+//!
+//! `run_stream_chunks` gets a row per streamed token/progress message and is
+//! never read again once a run is old, so the scheduler periodically deletes
+//! chunks for runs that finished more than `RESULT_RETENTION_DAYS` ago.
+//! Optionally `run_events` are pruned too (`RETENTION_PRUNE_EVENTS`). Pinned
+//! runs are exempt. Deletes run in batches of `RETENTION_BATCH_SIZE` rows so
+//! no single statement holds locks for long.
+//!
+//! Before a run's chunks go, a per-node summary (chunk count and bytes) is
+//! written to `workflow_runs.stream_summary` unless `RETENTION_KEEP_SUMMARY`
+//! is off.
+
+use sqlx::PgPool;
+use std::future::Future;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Default rows per DELETE statement
+const DEFAULT_BATCH_SIZE: i64 = 5_000;
+
+/// Runs handled per sweep
+const RUNS_PER_SWEEP: i64 = 100;
+
+/// Cap on DELETE statements per table per sweep (the rest waits for the next)
+const MAX_BATCHES_PER_SWEEP: u32 = 20;
+
+/// Retention settings (configurable via env vars)
+#[derive(Clone, Debug)]
+pub struct RetentionConfig {
+    /// How long after completion data is kept (None = keep forever)
+    pub retention: Option<Duration>,
+    pub prune_events: bool,
+    pub keep_summary: bool,
+    pub batch_size: i64,
+}
+
+impl RetentionConfig {
+    pub fn from_env() -> Self {
+        let days: u64 = std::env::var("RESULT_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        Self {
+            retention: (days > 0).then(|| Duration::from_secs(days * 86_400)),
+            prune_events: std::env::var("RETENTION_PRUNE_EVENTS")
+                .map(|v| v == "true" || v == "1")
+                .unwrap_or(false),
+            keep_summary: std::env::var("RETENTION_KEEP_SUMMARY")
+                .map(|v| v != "false" && v != "0")
+                .unwrap_or(true),
+            batch_size: std::env::var("RETENTION_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n: &i64| n > 0)
+                .unwrap_or(DEFAULT_BATCH_SIZE),
+        }
+    }
+}
+
+/// What a sweep removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct SweepStats {
+    pub runs: usize,
+    pub chunks: u64,
+    pub events: u64,
+}
+
+/// Storage the sweep operates on.
+pub trait RetentionStore {
+    /// Unpinned runs completed more than `older_than` ago that still have
+    /// chunks (or events, if `with_events`).
+    fn expired_runs(
+        &self,
+        older_than: Duration,
+        with_events: bool,
+        limit: i64,
+    ) -> impl Future<Output = Result<Vec<Uuid>, String>> + Send;
+
+    /// Record a chunk summary for runs that don't have one yet.
+    fn save_summaries(&self, runs: &[Uuid]) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Delete up to `limit` chunks of these runs. Returns rows deleted.
+    fn delete_chunks(&self, runs: &[Uuid], limit: i64) -> impl Future<Output = Result<u64, String>> + Send;
+
+    /// Delete up to `limit` events of these runs. Returns rows deleted.
+    fn delete_events(&self, runs: &[Uuid], limit: i64) -> impl Future<Output = Result<u64, String>> + Send;
+}
+
+impl RetentionStore for PgPool {
+    async fn expired_runs(&self, older_than: Duration, with_events: bool, limit: i64) -> Result<Vec<Uuid>, String> {
+        let rows: Vec<(Uuid,)> = sqlx::query_as(
+            r#"
+            SELECT r.id FROM workflow_runs r
+            WHERE r.completed_at < NOW() - make_interval(secs => $1)
+              AND r.pinned IS NOT TRUE
+              AND (EXISTS (SELECT 1 FROM run_stream_chunks c WHERE c.run_id = r.id)
+                   OR ($2 AND EXISTS (SELECT 1 FROM run_events e WHERE e.run_id = r.id)))
+            ORDER BY r.completed_at
+            LIMIT $3
+            "#,
+        )
+        .bind(older_than.as_secs_f64())
+        .bind(with_events)
+        .bind(limit)
+        .fetch_all(self)
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    async fn save_summaries(&self, runs: &[Uuid]) -> Result<(), String> {
+        sqlx::query(
+            r#"
+            UPDATE workflow_runs r SET stream_summary = s.summary
+            FROM (
+                SELECT run_id, jsonb_object_agg(node_id, jsonb_build_object('chunks', chunks, 'bytes', bytes)) AS summary
+                FROM (
+                    SELECT run_id, node_id, COUNT(*) AS chunks, SUM(LENGTH(content)) AS bytes
+                    FROM run_stream_chunks WHERE run_id = ANY($1)
+                    GROUP BY run_id, node_id
+                ) per_node
+                GROUP BY run_id
+            ) s
+            WHERE r.id = s.run_id AND r.stream_summary IS NULL
+            "#,
+        )
+        .bind(runs)
+        .execute(self)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    }
+
+    async fn delete_chunks(&self, runs: &[Uuid], limit: i64) -> Result<u64, String> {
+        sqlx::query(&delete_batch_sql("run_stream_chunks"))
+            .bind(runs)
+            .bind(limit)
+            .execute(self)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(|e| e.to_string())
+    }
+
+    async fn delete_events(&self, runs: &[Uuid], limit: i64) -> Result<u64, String> {
+        sqlx::query(&delete_batch_sql("run_events"))
+            .bind(runs)
+            .bind(limit)
+            .execute(self)
+            .await
+            .map(|r| r.rows_affected())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// One batch of a table's rows for the runs in `$1`, at most `$2` of them.
+/// Postgres has no `DELETE ... LIMIT`, so the batch is picked by id first.
+fn delete_batch_sql(table: &str) -> String {
+    format!(
+        "DELETE FROM {table} WHERE id IN (SELECT id FROM {table} WHERE run_id = ANY($1) LIMIT $2)",
+        table = table
+    )
+}
+
+/// Run one retention pass.
+pub async fn sweep(store: &impl RetentionStore, config: &RetentionConfig) -> Result<SweepStats, String> {
+    let Some(retention) = config.retention else {
+        return Ok(SweepStats::default());
+    };
+
+    let runs = store.expired_runs(retention, config.prune_events, RUNS_PER_SWEEP).await?;
+    if runs.is_empty() {
+        return Ok(SweepStats::default());
+    }
+
+    if config.keep_summary {
+        store.save_summaries(&runs).await?;
+    }
+
+    let mut stats = SweepStats { runs: runs.len(), ..Default::default() };
+    for _ in 0..MAX_BATCHES_PER_SWEEP {
+        let deleted = store.delete_chunks(&runs, config.batch_size).await?;
+        stats.chunks += deleted;
+        if deleted < config.batch_size as u64 {
+            break;
+        }
+    }
+
+    if config.prune_events {
+        for _ in 0..MAX_BATCHES_PER_SWEEP {
+            let deleted = store.delete_events(&runs, config.batch_size).await?;
+            stats.events += deleted;
+            if deleted < config.batch_size as u64 {
+                break;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    struct Run {
+        age: Duration,
+        pinned: bool,
+        summary: Option<u64>,
+    }
+
+    #[test]
+    fn test_delete_batch_sql_is_bounded_and_scoped_to_the_runs() {
+        assert_eq!(
+            delete_batch_sql("run_events"),
+            "DELETE FROM run_events WHERE id IN (SELECT id FROM run_events WHERE run_id = ANY($1) LIMIT $2)"
+        );
+    }
+
+    #[derive(Default)]
+    struct MemoryStore {
+        runs: Mutex<HashMap<Uuid, Run>>,
+        chunks: Mutex<Vec<Uuid>>, // one entry per chunk row
+        events: Mutex<Vec<Uuid>>,
+    }
+
+    impl MemoryStore {
+        fn add_run(&self, age_days: u64, pinned: bool, chunks: usize) -> Uuid {
+            let id = Uuid::new_v4();
+            let age = Duration::from_secs(age_days * 86_400);
+            self.runs.lock().unwrap().insert(id, Run { age, pinned, summary: None });
+            self.chunks.lock().unwrap().extend(std::iter::repeat_n(id, chunks));
+            self.events.lock().unwrap().extend(std::iter::repeat_n(id, 2));
+            id
+        }
+
+        fn count(rows: &Mutex<Vec<Uuid>>, run: Uuid) -> usize {
+            rows.lock().unwrap().iter().filter(|&&r| r == run).count()
+        }
+
+        fn delete(rows: &Mutex<Vec<Uuid>>, runs: &[Uuid], limit: i64) -> u64 {
+            let mut rows = rows.lock().unwrap();
+            let mut deleted = 0;
+            rows.retain(|r| {
+                if deleted < limit as u64 && runs.contains(r) {
+                    deleted += 1;
+                    false
+                } else {
+                    true
+                }
+            });
+            deleted
+        }
+    }
+
+    impl RetentionStore for MemoryStore {
+        async fn expired_runs(&self, older_than: Duration, with_events: bool, limit: i64) -> Result<Vec<Uuid>, String> {
+            let runs = self.runs.lock().unwrap();
+            Ok(runs
+                .iter()
+                .filter(|(id, run)| {
+                    run.age > older_than
+                        && !run.pinned
+                        && (Self::count(&self.chunks, **id) > 0 || (with_events && Self::count(&self.events, **id) > 0))
+                })
+                .map(|(id, _)| *id)
+                .take(limit as usize)
+                .collect())
+        }
+
+        async fn save_summaries(&self, runs: &[Uuid]) -> Result<(), String> {
+            let mut all = self.runs.lock().unwrap();
+            for id in runs {
+                let chunks = Self::count(&self.chunks, *id) as u64;
+                if let Some(run) = all.get_mut(id) {
+                    run.summary.get_or_insert(chunks);
+                }
+            }
+            Ok(())
+        }
+
+        async fn delete_chunks(&self, runs: &[Uuid], limit: i64) -> Result<u64, String> {
+            Ok(Self::delete(&self.chunks, runs, limit))
+        }
+
+        async fn delete_events(&self, runs: &[Uuid], limit: i64) -> Result<u64, String> {
+            Ok(Self::delete(&self.events, runs, limit))
+        }
+    }
+
+    fn config(prune_events: bool) -> RetentionConfig {
+        RetentionConfig {
+            retention: Some(Duration::from_secs(30 * 86_400)),
+            prune_events,
+            keep_summary: true,
+            batch_size: 2, // force several batches
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deletes_expired_chunks_and_keeps_recent() {
+        let store = MemoryStore::default();
+        let old = store.add_run(45, false, 5);
+        let recent = store.add_run(3, false, 4);
+        let pinned = store.add_run(90, true, 3);
+
+        let stats = sweep(&store, &config(false)).await.unwrap();
+        assert_eq!(stats, SweepStats { runs: 1, chunks: 5, events: 0 });
+
+        assert_eq!(MemoryStore::count(&store.chunks, old), 0);
+        assert_eq!(MemoryStore::count(&store.chunks, recent), 4);
+        assert_eq!(MemoryStore::count(&store.chunks, pinned), 3);
+        assert_eq!(MemoryStore::count(&store.events, old), 2, "events kept unless configured");
+
+        // Summary captured before the chunks went
+        assert_eq!(store.runs.lock().unwrap()[&old].summary, Some(5));
+        assert_eq!(store.runs.lock().unwrap()[&recent].summary, None);
+
+        // Nothing left to do
+        assert_eq!(sweep(&store, &config(false)).await.unwrap(), SweepStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_prunes_events_when_enabled_and_disabled_by_default() {
+        let store = MemoryStore::default();
+        let old = store.add_run(45, false, 1);
+
+        let disabled = RetentionConfig { retention: None, ..config(true) };
+        assert_eq!(sweep(&store, &disabled).await.unwrap(), SweepStats::default());
+
+        let stats = sweep(&store, &config(true)).await.unwrap();
+        assert_eq!(stats, SweepStats { runs: 1, chunks: 1, events: 2 });
+        assert_eq!(MemoryStore::count(&store.events, old), 0);
+    }
+}
@@ -18,6 +18,26 @@ pub fn calculate_backoff(attempt: u32) -> Duration {
     Duration::from_millis(base_ms + jitter_ms)
 }
 
+/// Longest provider-suggested wait the retry path honors
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// Backoffs longer than this are parked in the delayed-jobs ZSET rather than
+/// slept in-process, so the retry survives a worker restart
+pub const DURABLE_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Backoff before retry `attempt`. A failed attempt whose body carries
+/// `retry_after_ms` (e.g. an LLM provider's rate-limit reset) waits that long,
+/// plus jitter and capped at 10 minutes, instead of the exponential backoff.
+pub fn retry_backoff(attempt: u32, body: Option<&serde_json::Value>) -> Duration {
+    match body.and_then(|b| b.get("retry_after_ms")).and_then(|v| v.as_u64()) {
+        Some(ms) => {
+            let jitter_ms = rand::rng().random_range(0..=500);
+            Duration::from_millis(ms + jitter_ms).min(MAX_RETRY_AFTER)
+        }
+        None => calculate_backoff(attempt),
+    }
+}
+
 /// Check if an HTTP status code indicates a retryable error.
 ///
 /// Retryable errors are transient and may succeed on retry:
@@ -31,6 +51,17 @@ pub fn is_retryable_error(status_code: u16) -> bool {
     matches!(status_code, 408 | 429 | 500 | 502 | 503 | 504)
 }
 
+/// Whether a retry after `backoff` still starts within the job's deadline.
+///
+/// `deadline_ms` counts from `first_attempt_at` (both unix ms); no deadline
+/// means count-bounded retries only.
+pub fn retry_within_deadline(first_attempt_at: u64, deadline_ms: Option<u64>, now_ms: u64, backoff: Duration) -> bool {
+    match deadline_ms {
+        Some(deadline_ms) => now_ms + backoff.as_millis() as u64 <= first_attempt_at.saturating_add(deadline_ms),
+        None => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -47,6 +78,27 @@ mod tests {
         assert!(b3.as_millis() >= 8000 && b3.as_millis() <= 8500);
     }
 
+    #[test]
+    fn test_retry_after_hint_replaces_backoff() {
+        let hinted = retry_backoff(1, Some(&serde_json::json!({ "retry_after_ms": 30_000 })));
+        assert!(hinted.as_millis() >= 30_000 && hinted.as_millis() <= 30_500);
+
+        let capped = retry_backoff(1, Some(&serde_json::json!({ "retry_after_ms": 86_400_000 })));
+        assert_eq!(capped, MAX_RETRY_AFTER);
+
+        let plain = retry_backoff(2, Some(&serde_json::json!({ "error": "boom" })));
+        assert!(plain.as_millis() >= 4000 && plain.as_millis() <= 4500);
+    }
+
+    #[test]
+    fn test_retry_deadline() {
+        let backoff = Duration::from_secs(4);
+        assert!(retry_within_deadline(0, None, 1_000_000, backoff));
+        // 10s budget: a retry at 3s + 4s fits, at 7s + 4s doesn't
+        assert!(retry_within_deadline(0, Some(10_000), 3_000, backoff));
+        assert!(!retry_within_deadline(0, Some(10_000), 7_000, backoff));
+    }
+
     #[test]
     fn test_retryable_errors() {
         assert!(is_retryable_error(408));
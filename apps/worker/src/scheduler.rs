@@ -4,7 +4,16 @@
 //! - Redis delayed jobs ready to execute (every 1s)
 //! - PostgreSQL expired webhook suspensions (every 10s)
 //! - PostgreSQL scheduled workflows due to run (every 10s)
-
+//! - Retention sweep of old stream chunks / run events (every 60s)
+
+use crate::dead_letter;
+use crate::idempotency;
+use crate::leader::Leadership;
+use crate::nodes::{db_wait, delay, llm};
+use crate::types::{DbWaitNodeData, JobPriority};
+use crate::orchestrator;
+use crate::priority;
+use crate::retention::{self, RetentionConfig};
 use chrono::{DateTime, Utc};
 use chrono_tz::Tz;
 use cron::Schedule;
@@ -16,36 +25,90 @@ use uuid::Uuid;
 
 /// Redis sorted set for delayed jobs
 const DELAYED_JOBS_KEY: &str = "swiftgrid_delayed";
-/// Redis stream for active jobs
-const ACTIVE_JOBS_KEY: &str = "swiftgrid_stream";
+
+/// Delayed-jobs poll interval: 1s while jobs are flowing, doubling after each
+/// empty poll up to this cap.
+const DELAYED_POLL_MAX: Duration = Duration::from_secs(5);
+
+/// Backoff for the delayed-jobs poll so an idle system doesn't hit Redis every second.
+#[derive(Debug)]
+struct IdleBackoff {
+    base: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl IdleBackoff {
+    fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max, current: base }
+    }
+
+    /// Record a poll result and return how long to wait before the next one.
+    fn next(&mut self, found: bool) -> Duration {
+        let wait = if found { self.base } else { self.current };
+        self.current = if found { self.base } else { (self.current * 2).min(self.max) };
+        wait
+    }
+}
+
+/// Run `step` only if this worker still holds the lease. The check is made
+/// right before the step starts, since the lease can change hands during a
+/// long pass.
+async fn if_leading(leader: &Leadership, step: impl std::future::Future<Output = ()>) {
+    if leader.holds() {
+        step.await;
+    }
+}
 
 /// Run the scheduler loop.
 ///
+/// Every worker runs this, but only the holder of the leader lease does any
+/// work; the rest stand by to take over when it lapses. The lease is renewed
+/// by its own task (see `leader`), and each step checks it's still held.
+///
 /// This function runs forever, checking for:
-/// - Delayed jobs ready to execute (every 1s)
+/// - Delayed jobs ready to execute (every 1s, backing off to 5s when idle)
 /// - Expired webhook suspensions (every 10s)
 /// - Scheduled workflows due to run (every 10s)
-pub async fn run(redis_client: redis::Client, db_pool: PgPool) {
-    println!("Scheduler started (polling every 1s)");
-    println!("  - Delayed jobs: every 1s");
-    println!("  - Stale message recovery: every 5s");
-    println!("  - Expired suspensions: every 10s");
-    println!("  - Cron workflows: every 10s");
+///
+/// `http_client` is the worker's API client (with its request timeout), used
+/// for orchestrator notification retries so a hung API can't stall the loop.
+pub async fn run(redis_client: redis::Client, db_pool: PgPool, http_client: reqwest::Client, leader: Leadership) {
+    tracing::info!("Scheduler started (polling every 1s)");
+    tracing::info!("  - Delayed jobs: every 1s (up to {}s when idle)", DELAYED_POLL_MAX.as_secs());
+    tracing::info!("  - Stale message recovery: every 5s (idle > {}ms)", stale_job_idle_ms());
+    tracing::info!("  - Orchestrator notification retries: every 5s");
+    tracing::info!("  - Expired suspensions: every 10s");
+    tracing::info!("  - Cron workflows: every 10s");
+    tracing::info!("  - Retention sweep: every 60s");
 
     let poll_interval = Duration::from_secs(1);
     let mut slow_check_counter = 0u32;
     let mut recovery_counter = 0u32;
+    let mut retention_counter = 0u32;
+    let mut delayed_backoff = IdleBackoff::new(poll_interval, DELAYED_POLL_MAX);
+    let mut next_delayed_poll = tokio::time::Instant::now();
 
     loop {
-        // Check delayed jobs every iteration (1s)
-        process_delayed_jobs(&redis_client).await;
+        // Followers wait for the renewal task to win the lease
+        if !leader.holds() {
+            tokio::time::sleep(poll_interval).await;
+            continue;
+        }
+
+        // Check delayed jobs when due (every 1s while busy, backing off when idle)
+        if tokio::time::Instant::now() >= next_delayed_poll {
+            let moved = process_delayed_jobs(&redis_client).await;
+            next_delayed_poll = tokio::time::Instant::now() + delayed_backoff.next(moved > 0);
+        }
 
         // Recover stale pending messages every 5 seconds
         // This handles messages that weren't ACKed due to transient errors
         recovery_counter += 1;
         if recovery_counter >= 5 {
             recovery_counter = 0;
-            recover_stale_pending_messages(&redis_client).await;
+            if_leading(&leader, recover_stale_pending_messages(&redis_client)).await;
+            if_leading(&leader, orchestrator::drain_retry_queue(&http_client, &redis_client)).await;
         }
 
         // Check for slow tasks every 10 seconds
@@ -55,23 +118,61 @@ pub async fn run(redis_client: redis::Client, db_pool: PgPool) {
             
             // Run these in parallel
             tokio::join!(
-                check_expired_suspensions(&db_pool),
-                check_subflow_timeouts(&db_pool, &redis_client),
-                check_batch_timeouts(&db_pool, &redis_client),
-                check_stale_batches(&db_pool, &redis_client),
-                check_scheduled_workflows(&db_pool, &redis_client)
+                if_leading(&leader, check_expired_suspensions(&db_pool)),
+                if_leading(&leader, check_db_wait_conditions(&db_pool, &redis_client)),
+                if_leading(&leader, check_subflow_timeouts(&db_pool, &redis_client)),
+                if_leading(&leader, check_batch_timeouts(&db_pool, &redis_client)),
+                if_leading(&leader, check_stale_batches(&db_pool, &redis_client)),
+                if_leading(&leader, check_scheduled_workflows(&db_pool, &redis_client)),
+                if_leading(&leader, promote_queued_runs(&db_pool, &redis_client))
             );
         }
 
+        // Prune old stream chunks (and optionally events) every 60 seconds
+        retention_counter += 1;
+        if retention_counter >= 60 && leader.holds() {
+            retention_counter = 0;
+            match retention::sweep(&db_pool, &RetentionConfig::from_env()).await {
+                Ok(stats) if stats.runs > 0 => tracing::info!(
+                    "Scheduler: Retention pruned {} chunk(s), {} event(s) from {} run(s)",
+                    stats.chunks, stats.events, stats.runs
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::error!("Scheduler: Retention sweep failed: {}", e),
+            }
+            if let Err(e) = idempotency::prune_claims(&db_pool).await {
+                tracing::error!("Scheduler: Failed to prune idempotency claims: {}", e);
+            }
+        }
+
         tokio::time::sleep(poll_interval).await;
     }
 }
 
+/// Default idle time before a pending message counts as stuck (`STALE_JOB_IDLE_MS`)
+const DEFAULT_STALE_JOB_IDLE_MS: u64 = 30_000;
+
+/// Messages reclaimed per XAUTOCLAIM call
+const RECOVERY_PAGE: usize = 100;
+
+/// XAUTOCLAIM calls per stream per recovery pass, so a huge PEL is worked
+/// through over several passes instead of stalling the scheduler loop
+const RECOVERY_MAX_PAGES: usize = 10;
+
+fn stale_job_idle_ms() -> u64 {
+    std::env::var("STALE_JOB_IDLE_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_STALE_JOB_IDLE_MS)
+}
+
 /// Recover stale pending messages that weren't ACKed.
 /// 
-/// When a worker fails to ACK a message (e.g., due to pool timeout), the message
-/// stays in the Pending Entries List (PEL). This function uses XAUTOCLAIM to 
-/// reclaim messages that have been pending for more than 30 seconds.
+/// When a worker fails to ACK a message (a transient error, or it crashed
+/// mid-job), the message stays in that consumer's Pending Entries List (PEL)
+/// and nothing would ever read it again. This function uses XAUTOCLAIM to
+/// reclaim messages that have been pending for longer than `STALE_JOB_IDLE_MS`
+/// and re-adds them to their stream.
 /// 
 /// This implements at-least-once delivery semantics - messages may be processed
 /// multiple times, but will never be lost due to transient errors.
@@ -79,82 +180,195 @@ async fn recover_stale_pending_messages(redis_client: &redis::Client) {
     let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
         return;
     };
+    let min_idle_ms = stale_job_idle_ms();
+    for stream in priority::STREAMS {
+        recover_stale_stream(&mut con, stream, min_idle_ms).await;
+    }
+}
 
-    // Use XAUTOCLAIM to reclaim messages pending for more than 30 seconds
-    // This moves them from another consumer's PEL to ours, making them available for retry
-    // XAUTOCLAIM stream group consumer min-idle-time start [COUNT count]
-    let result: RedisResult<(String, Vec<(String, Vec<(String, String)>)>)> = redis::cmd("XAUTOCLAIM")
-        .arg(ACTIVE_JOBS_KEY)
-        .arg("workers_group")
-        .arg("scheduler_recovery")  // Use a dedicated consumer name for recovery
-        .arg("30000")  // 30 seconds min idle time
-        .arg("0-0")    // Start from beginning of PEL
-        .arg("COUNT")
-        .arg(10)       // Reclaim up to 10 messages at a time
-        .query_async(&mut con)
-        .await;
+/// A message reclaimed by XAUTOCLAIM: (id, fields)
+type ClaimedMessage = (String, Vec<(String, String)>);
 
-    match result {
-        Ok((_, messages)) if !messages.is_empty() => {
-            println!("Scheduler: Recovering {} stale pending message(s)", messages.len());
-            
-            // Re-add these messages to the stream so they get picked up by workers
-            for (msg_id, fields) in messages {
-                // Extract the payload from the message fields
-                if let Some((_, payload)) = fields.iter().find(|(k, _)| k == "payload") {
-                    // Re-add to stream for reprocessing
-                    let _: RedisResult<String> = con
-                        .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", payload.as_str())])
-                        .await;
-                    
-                    // ACK the old message to remove it from PEL
-                    let _: RedisResult<()> = con.xack(ACTIVE_JOBS_KEY, "workers_group", &[&msg_id]).await;
-                }
+/// Split an XAUTOCLAIM reply into its next cursor and the claimed messages.
+///
+/// Redis 6.2 replies `[cursor, messages]`; Redis 7 appends the IDs of entries
+/// that were deleted from the stream (and drops them from the PEL itself).
+fn parse_autoclaim(reply: redis::Value) -> RedisResult<(String, Vec<ClaimedMessage>)> {
+    let redis::Value::Array(mut items) = reply else {
+        return Err((redis::ErrorKind::TypeError, "XAUTOCLAIM reply is not an array").into());
+    };
+    if items.len() < 2 {
+        return Err((redis::ErrorKind::TypeError, "XAUTOCLAIM reply is too short").into());
+    }
+    items.truncate(2);
+    let messages = redis::from_redis_value(&items.pop().unwrap_or(redis::Value::Nil))?;
+    let cursor = redis::from_redis_value(&items.pop().unwrap_or(redis::Value::Nil))?;
+    Ok((cursor, messages))
+}
+
+/// Recover one job stream; messages are re-added to the stream they came from.
+async fn recover_stale_stream(con: &mut redis::aio::MultiplexedConnection, stream: &str, min_idle_ms: u64) {
+    let mut cursor = "0-0".to_string();
+    for _ in 0..RECOVERY_MAX_PAGES {
+        // XAUTOCLAIM stream group consumer min-idle-time start [COUNT count]
+        // This moves them from another consumer's PEL to ours, making them available for retry
+        let reply: RedisResult<redis::Value> = redis::cmd("XAUTOCLAIM")
+            .arg(stream)
+            .arg("workers_group")
+            .arg("scheduler_recovery") // Use a dedicated consumer name for recovery
+            .arg(min_idle_ms)
+            .arg(&cursor)
+            .arg("COUNT")
+            .arg(RECOVERY_PAGE)
+            .query_async(con)
+            .await;
+
+        let (next, messages) = match reply.and_then(parse_autoclaim) {
+            Ok(claimed) => claimed,
+            Err(e) if e.code() == Some("NOGROUP") => return, // Group not created yet
+            Err(e) => {
+                tracing::warn!("Scheduler: XAUTOCLAIM on {} failed: {}", stream, e);
+                return;
             }
+        };
+
+        if !messages.is_empty() {
+            tracing::info!("Scheduler: Recovering {} stale pending message(s) from {}", messages.len(), stream);
+        }
+        for (msg_id, fields) in messages {
+            requeue_claimed(con, stream, &msg_id, &fields).await;
+        }
+
+        // "0-0" means the whole PEL has been scanned
+        if next == "0-0" {
+            return;
         }
-        Ok(_) => {} // No stale messages, which is good
-        Err(_) => {} // XAUTOCLAIM might fail if group doesn't exist yet, ignore
+        cursor = next;
+    }
+}
+
+/// Re-add a reclaimed message to its stream so workers pick it up, then ACK
+/// the old entry to remove it from the PEL.
+async fn requeue_claimed(con: &mut redis::aio::MultiplexedConnection, stream: &str, msg_id: &str, fields: &[(String, String)]) {
+    let Some((_, payload)) = fields.iter().find(|(k, _)| k == "payload") else {
+        // Nothing to reprocess; don't leave it pending forever
+        tracing::warn!("Scheduler: Dropping stale message {} on {} without a payload", msg_id, stream);
+        let _: RedisResult<()> = con.xack(stream, "workers_group", &[msg_id]).await;
+        return;
+    };
+
+    // Carry the delivery count over to the new message. The PEL count
+    // includes our own XAUTOCLAIM, which isn't a processing attempt.
+    let prior: u32 = fields
+        .iter()
+        .find(|(k, _)| k == dead_letter::DELIVERIES_FIELD)
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+    let pel_count = dead_letter::delivery_count(con, stream, "workers_group", msg_id).await;
+    let deliveries = prior + pel_count.saturating_sub(1).max(1);
+    tracing::info!("Message {} has been delivered {} time(s)", msg_id, deliveries);
+
+    // Re-add to stream for reprocessing
+    let deliveries = deliveries.to_string();
+    let added: RedisResult<String> = con
+        .xadd(
+            stream,
+            "*",
+            &[("payload", payload.as_str()), (dead_letter::DELIVERIES_FIELD, deliveries.as_str())],
+        )
+        .await;
+    if let Err(e) = added {
+        // Leave it pending; the next pass reclaims it again
+        tracing::warn!("Scheduler: Failed to re-add stale message {}: {}", msg_id, e);
+        return;
     }
+
+    // ACK the old message to remove it from PEL
+    let _: RedisResult<()> = con.xack(stream, "workers_group", &[msg_id]).await;
 }
 
+/// Push a job the scheduler built onto the stream for its `priority` (the
+/// default stream when it carries none).
+async fn enqueue(con: &mut redis::aio::MultiplexedConnection, payload: &str) {
+    let _: RedisResult<String> = con
+        .xadd(priority::stream_for_payload(payload), "*", &[("payload", payload)])
+        .await;
+}
+
+/// Most delayed jobs moved to the stream per poll
+const DELAYED_BATCH: isize = 100;
+
+/// Move due delayed jobs (KEYS[1], score <= ARGV[1], at most ARGV[2]) onto
+/// the stream for their priority (KEYS[2..4]: high, default, low) and drop
+/// them from their run's index (prefix ARGV[3]). Each job is pushed before
+/// it is removed, so an enqueue that errors leaves it (and everything after
+/// it) in the set for the next poll.
+const MOVE_DUE_SCRIPT: &str = r#"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+local streams = { high = KEYS[2], default = KEYS[3], low = KEYS[4] }
+for _, job in ipairs(due) do
+    local ok, decoded = pcall(cjson.decode, job)
+    local stream, run_id = KEYS[3], nil
+    if ok and type(decoded) == 'table' then
+        stream = streams[decoded.priority] or KEYS[3]
+        if type(decoded.run_id) == 'string' then
+            run_id = decoded.run_id
+        end
+    end
+    redis.call('XADD', stream, '*', 'payload', job)
+    redis.call('ZREM', KEYS[1], job)
+    if run_id then
+        redis.call('SREM', ARGV[3] .. run_id, job)
+    end
+end
+return #due
+"#;
+
 /// Process delayed jobs that are ready to execute.
-async fn process_delayed_jobs(redis_client: &redis::Client) {
+///
+/// One script finds, enqueues and removes the due jobs, so two scheduler
+/// instances polling at once never enqueue the same job twice, and a job is
+/// never removed without having been enqueued.
+async fn process_delayed_jobs(redis_client: &redis::Client) -> usize {
     let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
-        return;
+        return 0;
     };
 
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_millis() as f64;
+        .as_millis() as u64;
 
-    // Get all jobs that are ready (score <= now)
-    let ready_jobs: Vec<String> = match con
-        .zrangebyscore_limit(DELAYED_JOBS_KEY, "-inf", now, 0, 10)
-        .await
-    {
-        Ok(jobs) => jobs,
+    match move_due_jobs(&mut con, DELAYED_JOBS_KEY, priority::STREAMS, now).await {
+        Ok(0) => 0,
+        Ok(moved) => {
+            tracing::info!("Scheduler: Moved {} delayed job(s) to the stream", moved);
+            moved
+        }
         Err(e) => {
-            eprintln!("Scheduler: Failed to query delayed jobs: {}", e);
-            return;
+            tracing::error!("Scheduler: Failed to move delayed jobs: {}", e);
+            0
         }
-    };
-
-    if ready_jobs.is_empty() {
-        return;
     }
+}
 
-    println!(
-        "Scheduler: Found {} delayed job(s) ready to run",
-        ready_jobs.len()
-    );
-
-    for job_json in &ready_jobs {
-        let _: RedisResult<()> = con.zrem(DELAYED_JOBS_KEY, job_json).await;
-        let _: RedisResult<String> = con
-            .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", job_json.as_str())])
-            .await;
-    }
+/// Run `MOVE_DUE_SCRIPT` on `delayed` with `streams` (high, default, low).
+async fn move_due_jobs(
+    con: &mut redis::aio::MultiplexedConnection,
+    delayed: &str,
+    streams: [&str; 3],
+    now_ms: u64,
+) -> RedisResult<usize> {
+    redis::Script::new(MOVE_DUE_SCRIPT)
+        .key(delayed)
+        .key(streams[0])
+        .key(streams[1])
+        .key(streams[2])
+        .arg(now_ms)
+        .arg(DELAYED_BATCH)
+        .arg(delay::RUN_INDEX_PREFIX)
+        .invoke_async(con)
+        .await
 }
 
 /// Check for expired suspensions and fail them.
@@ -173,13 +387,13 @@ async fn check_expired_suspensions(pool: &PgPool) {
     {
         Ok(rows) => rows,
         Err(e) => {
-            eprintln!("Scheduler: Failed to query expired suspensions: {}", e);
+            tracing::error!("Scheduler: Failed to query expired suspensions: {}", e);
             return;
         }
     };
 
     for (suspension_id, node_id, run_id) in expired {
-        println!(
+        tracing::info!(
             "Scheduler: Expiring suspension for node {} in run {}",
             node_id, run_id
         );
@@ -190,7 +404,7 @@ async fn check_expired_suspensions(pool: &PgPool) {
             VALUES ($1, $2, 'NODE_FAILED', $3)
             "#,
         )
-        .bind(&run_id)
+        .bind(run_id)
         .bind(&node_id)
         .bind(serde_json::json!({
             "error": "Suspension timeout expired",
@@ -209,10 +423,130 @@ async fn check_expired_suspensions(pool: &PgPool) {
             "#,
         )
         .bind(serde_json::json!({"timeout": true}))
-        .bind(&suspension_id)
+        .bind(suspension_id)
+        .execute(pool)
+        .await;
+    }
+}
+
+/// DbWait conditions evaluated at once
+const DB_WAIT_CONCURRENCY: usize = 5;
+
+/// Longest a pass spends evaluating DbWait conditions; ones not evaluated by
+/// then stay due for the next pass, so slow queries can't hold up the other
+/// slow checks
+const DB_WAIT_PASS_DEADLINE: Duration = Duration::from_secs(8);
+
+/// Re-check DbWait conditions that are due and resume the nodes whose query
+/// now returns a truthy row. Expiry is left to `check_expired_suspensions`.
+async fn check_db_wait_conditions(pool: &PgPool, redis_client: &redis::Client) {
+    use futures_util::StreamExt;
+
+    let due: Vec<(Uuid, String, Uuid, serde_json::Value)> = match sqlx::query_as(
+        r#"
+        SELECT s.id, s.node_id, s.run_id, s.execution_context FROM suspensions s
+        JOIN workflow_runs r ON r.id = s.run_id
+        WHERE s.resumed_at IS NULL
+          AND s.suspension_type = 'db_wait'
+          AND (s.resume_after IS NULL OR s.resume_after <= NOW())
+          AND (s.expires_at IS NULL OR s.expires_at > NOW())
+          AND r.status NOT IN ('completed', 'failed', 'cancelled')
+        ORDER BY s.resume_after NULLS FIRST
+        LIMIT 20
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Scheduler: Failed to query DbWait suspensions: {}", e);
+            return;
+        }
+    };
+
+    if due.is_empty() {
+        return;
+    }
+
+    let Ok(con) = redis_client.get_multiplexed_async_connection().await else {
+        tracing::error!("Scheduler: Failed to connect to Redis for DbWait checks");
+        return;
+    };
+
+    let deadline = tokio::time::Instant::now() + DB_WAIT_PASS_DEADLINE;
+    futures_util::stream::iter(due)
+        .map(|suspension| check_db_wait(pool, con.clone(), deadline, suspension))
+        .buffer_unordered(DB_WAIT_CONCURRENCY)
+        .collect::<Vec<()>>()
+        .await;
+}
+
+/// Evaluate one DbWait suspension (unless the pass `deadline` has passed)
+/// and resume it if its condition holds.
+async fn check_db_wait(
+    pool: &PgPool,
+    mut con: redis::aio::MultiplexedConnection,
+    deadline: tokio::time::Instant,
+    (suspension_id, node_id, run_id, context): (Uuid, String, Uuid, serde_json::Value),
+) {
+    let priority = JobPriority::from_stored(context.get("priority").and_then(|v| v.as_str()));
+    let data: DbWaitNodeData = match serde_json::from_value(context) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::error!(%run_id, %node_id, "Scheduler: Invalid DbWait context: {}", e);
+            return;
+        }
+    };
+
+    // Only the evaluation is bounded: a claimed suspension is always enqueued
+    let row = match tokio::time::timeout_at(deadline, db_wait::evaluate(pool, &data.query, &data.params)).await {
+        Ok(Ok(row)) => row,
+        Ok(Err(e)) => {
+            // Keep polling at the node's interval; a broken query fails at expiry
+            tracing::error!(%run_id, %node_id, "Scheduler: DbWait query failed: {}", e);
+            None
+        }
+        Err(_) => {
+            tracing::warn!(%run_id, %node_id, "Scheduler: DbWait check deferred to the next pass");
+            return;
+        }
+    };
+
+    let Some(row) = row else {
+        let _ = sqlx::query(
+            "UPDATE suspensions SET resume_after = NOW() + make_interval(secs => $1) WHERE id = $2",
+        )
+        .bind(data.poll_interval_ms as f64 / 1000.0)
+        .bind(suspension_id)
         .execute(pool)
         .await;
+        return;
+    };
+
+    // Claim the suspension so an overlapping tick can't resume it twice
+    let claimed = sqlx::query(
+        r#"
+        UPDATE suspensions
+        SET resumed_at = NOW(),
+            resumed_by = 'scheduler:db_wait',
+            resume_payload = $1
+        WHERE id = $2 AND resumed_at IS NULL
+        "#,
+    )
+    .bind(&row)
+    .bind(suspension_id)
+    .execute(pool)
+    .await
+    .map(|r| r.rows_affected() == 1)
+    .unwrap_or(false);
+    if !claimed {
+        return;
     }
+
+    tracing::info!(%run_id, %node_id, "Scheduler: DbWait condition met");
+    let job = db_wait::resume_job(suspension_id, &node_id, run_id, priority, row);
+    enqueue(&mut con, &job.to_string()).await;
 }
 
 /// Check for sub-flow timeouts and fail the parent node.
@@ -233,7 +567,7 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
     {
         Ok(rows) => rows,
         Err(e) => {
-            eprintln!("Scheduler: Failed to query sub-flow timeouts: {}", e);
+            tracing::error!("Scheduler: Failed to query sub-flow timeouts: {}", e);
             return;
         }
     };
@@ -242,13 +576,13 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         return;
     }
 
-    println!(
+    tracing::info!(
         "Scheduler: Found {} sub-flow timeout(s) to process",
         timed_out.len()
     );
 
     let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
-        eprintln!("Scheduler: Failed to connect to Redis for sub-flow timeouts");
+        tracing::error!("Scheduler: Failed to connect to Redis for sub-flow timeouts");
         return;
     };
 
@@ -256,7 +590,7 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         let child_run_id = context.get("child_run_id").and_then(|v| v.as_str()).unwrap_or("");
         let _fail_on_error = context.get("fail_on_error").and_then(|v| v.as_bool()).unwrap_or(false);
 
-        println!(
+        tracing::info!(
             "Scheduler: Sub-flow timeout for node {} in run {} (child: {})",
             node_id, parent_run_id, child_run_id
         );
@@ -271,25 +605,24 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         .ok()
         .flatten();
 
-        if let Some((status,)) = child_status {
-            if status == "completed" || status == "failed" || status == "cancelled" {
+        if let Some((status,)) = child_status
+            && (status == "completed" || status == "failed" || status == "cancelled") {
                 // Child already finished, just mark suspension as resolved
                 let _ = sqlx::query(
                     "UPDATE suspensions SET resumed_at = NOW(), resumed_by = 'scheduler:child_finished' WHERE id = $1"
                 )
-                .bind(&suspension_id)
+                .bind(suspension_id)
                 .execute(pool)
                 .await;
                 continue;
             }
-        }
 
         // Cancel the child run
         if let Ok(child_uuid) = Uuid::parse_str(child_run_id) {
             let _ = sqlx::query(
                 "UPDATE workflow_runs SET status = 'cancelled', completed_at = NOW() WHERE id = $1"
             )
-            .bind(&child_uuid)
+            .bind(child_uuid)
             .execute(pool)
             .await;
 
@@ -311,12 +644,11 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
                 }
             },
             "retry_count": 0,
-            "max_retries": 0
+            "max_retries": 0,
+            "priority": JobPriority::from_stored(context.get("priority").and_then(|v| v.as_str()))
         });
 
-        let _: RedisResult<String> = con
-            .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", resume_job.to_string())])
-            .await;
+        enqueue(&mut con, &resume_job.to_string()).await;
 
         // Mark suspension as resolved
         let _ = sqlx::query(
@@ -329,7 +661,7 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
             "#,
         )
         .bind(serde_json::json!({"timeout": true, "child_run_id": child_run_id}))
-        .bind(&suspension_id)
+        .bind(suspension_id)
         .execute(pool)
         .await;
 
@@ -337,7 +669,7 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         let _ = sqlx::query(
             "UPDATE workflow_runs SET status = 'running' WHERE id = $1"
         )
-        .bind(&parent_run_id)
+        .bind(parent_run_id)
         .execute(pool)
         .await;
     }
@@ -351,10 +683,11 @@ async fn check_subflow_timeouts(pool: &PgPool, redis_client: &redis::Client) {
 /// - Items remaining to process but no active children (active_count = 0)
 async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
     // Find running batches that appear stuck
-    let stale: Vec<(Uuid, String, Uuid, i32, i32, i32, i32, i32)> = match sqlx::query_as(
+    type StaleBatchRow = (Uuid, String, Uuid, i32, i32, i32, i32, i32, String);
+    let stale: Vec<StaleBatchRow> = match sqlx::query_as(
         r#"
         SELECT bo.id, bo.node_id, bo.run_id, bo.total_items, bo.completed_count, 
-               bo.failed_count, bo.active_count, bo.current_index
+               bo.failed_count, bo.active_count, bo.current_index, bo.priority
         FROM batch_operations bo
         WHERE bo.status = 'running'
           AND bo.created_at < NOW() - INTERVAL '60 seconds'
@@ -373,7 +706,7 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
     {
         Ok(rows) => rows,
         Err(e) => {
-            eprintln!("Scheduler: Failed to query stale batches: {}", e);
+            tracing::error!("Scheduler: Failed to query stale batches: {}", e);
             return;
         }
     };
@@ -382,22 +715,23 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
         return;
     }
 
-    println!(
+    tracing::info!(
         "Scheduler: Found {} stale batch(es) to recover",
         stale.len()
     );
 
     let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
-        eprintln!("Scheduler: Failed to connect to Redis for batch recovery");
+        tracing::error!("Scheduler: Failed to connect to Redis for batch recovery");
         return;
     };
 
-    for (batch_id, node_id, run_id, total_items, completed_count, failed_count, _active_count, current_index) in stale {
+    for (batch_id, node_id, run_id, total_items, completed_count, failed_count, _active_count, current_index, priority) in stale {
+        let priority = JobPriority::from_stored(Some(&priority));
         let finished = completed_count + failed_count;
         
         if finished >= total_items {
             // All items processed but batch not marked complete - push completion job
-            println!(
+            tracing::info!(
                 "Scheduler: Batch {} has all results ({}/{}), triggering completion",
                 batch_id, finished, total_items
             );
@@ -419,15 +753,14 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
                 },
                 "retry_count": 0,
                 "max_retries": 0,
-                "isolated": false
+                "isolated": false,
+                "priority": priority
             });
             
-            let _: RedisResult<String> = con
-                .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", complete_job.to_string())])
-                .await;
+            enqueue(&mut con, &complete_job.to_string()).await;
         } else if current_index < total_items {
             // More items to process - push a MAPSTEP to resume spawning
-            println!(
+            tracing::info!(
                 "Scheduler: Recovering stale batch {} for node {} ({}/{} completed, spawning more)",
                 batch_id, node_id, finished, total_items
             );
@@ -443,12 +776,11 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
                 },
                 "retry_count": 0,
                 "max_retries": 0,
-                "isolated": false
+                "isolated": false,
+                "priority": priority
             });
             
-            let _: RedisResult<String> = con
-                .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", step_job.to_string())])
-                .await;
+            enqueue(&mut con, &step_job.to_string()).await;
         } else {
             // All items spawned but not all completed - children may be stuck
             // Check for orphaned child runs
@@ -460,13 +792,13 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
                 AND created_at < NOW() - INTERVAL '30 seconds'
                 "#
             )
-            .bind(&run_id)
+            .bind(run_id)
             .fetch_one(pool)
             .await
             .unwrap_or(0);
             
             if orphaned > 0 {
-                println!(
+                tracing::info!(
                     "Scheduler: Batch {} has {} orphaned children, marking as failed",
                     batch_id, orphaned
                 );
@@ -481,7 +813,7 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
                     AND created_at < NOW() - INTERVAL '30 seconds'
                     "#
                 )
-                .bind(&run_id)
+                .bind(run_id)
                 .execute(pool)
                 .await;
                 
@@ -502,12 +834,11 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
                     },
                     "retry_count": 0,
                     "max_retries": 0,
-                    "isolated": false
+                    "isolated": false,
+                    "priority": priority
                 });
                 
-                let _: RedisResult<String> = con
-                    .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", complete_job.to_string())])
-                    .await;
+                enqueue(&mut con, &complete_job.to_string()).await;
             }
         }
     }
@@ -517,9 +848,10 @@ async fn check_stale_batches(pool: &PgPool, redis_client: &redis::Client) {
 /// A batch times out if it has a timeout_ms set and created_at + timeout_ms < NOW()
 async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
     // Find running batches that have exceeded their timeout
-    let timed_out: Vec<(Uuid, String, Uuid, i32, i32, i32, i32)> = match sqlx::query_as(
+    type TimedOutBatchRow = (Uuid, String, Uuid, i32, i32, i32, i32, String);
+    let timed_out: Vec<TimedOutBatchRow> = match sqlx::query_as(
         r#"
-        SELECT id, node_id, run_id, total_items, completed_count, failed_count, active_count
+        SELECT id, node_id, run_id, total_items, completed_count, failed_count, active_count, priority
         FROM batch_operations 
         WHERE status = 'running'
           AND timeout_ms IS NOT NULL 
@@ -532,7 +864,7 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
     {
         Ok(rows) => rows,
         Err(e) => {
-            eprintln!("Scheduler: Failed to query batch timeouts: {}", e);
+            tracing::error!("Scheduler: Failed to query batch timeouts: {}", e);
             return;
         }
     };
@@ -541,18 +873,18 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         return;
     }
 
-    println!(
+    tracing::info!(
         "Scheduler: Found {} batch timeout(s) to process",
         timed_out.len()
     );
 
     let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
-        eprintln!("Scheduler: Failed to connect to Redis for batch timeouts");
+        tracing::error!("Scheduler: Failed to connect to Redis for batch timeouts");
         return;
     };
 
-    for (batch_id, node_id, run_id, total_items, completed_count, failed_count, active_count) in timed_out {
-        println!(
+    for (batch_id, node_id, run_id, total_items, completed_count, failed_count, active_count, priority) in timed_out {
+        tracing::info!(
             "Scheduler: Batch timeout for node {} in run {} ({}/{} completed, {} active)",
             node_id, run_id, completed_count, total_items, active_count
         );
@@ -561,7 +893,7 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         let _ = sqlx::query(
             "UPDATE batch_operations SET status = 'timed_out', completed_at = NOW() WHERE id = $1"
         )
-        .bind(&batch_id)
+        .bind(batch_id)
         .execute(pool)
         .await;
 
@@ -574,7 +906,7 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
               AND status IN ('pending', 'running')
             "#
         )
-        .bind(&run_id)
+        .bind(run_id)
         .execute(pool)
         .await;
 
@@ -596,7 +928,8 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
             },
             "retry_count": 0,
             "max_retries": 0,
-            "isolated": false
+            "isolated": false,
+            "priority": JobPriority::from_stored(Some(&priority))
         });
 
         // Log the timeout event
@@ -606,7 +939,7 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
             VALUES ($1, $2, 'NODE_FAILED', $3)
             "#,
         )
-        .bind(&run_id)
+        .bind(run_id)
         .bind(&node_id)
         .bind(serde_json::json!({
             "error": "Batch operation timed out",
@@ -619,222 +952,603 @@ async fn check_batch_timeouts(pool: &PgPool, redis_client: &redis::Client) {
         .await;
 
         // Push the completion job to finalize results
-        let _: RedisResult<String> = con
-            .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", timeout_job.to_string())])
-        .await;
+        enqueue(&mut con, &timeout_job.to_string()).await;
     }
 }
 
-/// Check for scheduled workflows that are due to run.
+/// A cron schedule that is due to fire.
+///
+/// Comes either from the legacy `workflows.schedule_*` columns (`schedule_id`
+/// is None) or from a row in `workflow_schedules`; both fire the same way but
+/// each keeps its own input, overlap check and next-run bookkeeping.
+#[derive(Debug, Clone)]
+struct DueSchedule {
+    schedule_id: Option<i32>,
+    workflow_id: i32,
+    name: String,
+    graph: serde_json::Value,
+    cron_expr: String,
+    timezone: String,
+    input_data: Option<serde_json::Value>,
+    overlap_mode: String,
+    active_version_id: Option<Uuid>,
+    /// The slot this schedule was due at (its stored next_run)
+    due_at: DateTime<Utc>,
+    catchup_mode: CatchupMode,
+}
+
+type ScheduleRow = (
+    Option<i32>,
+    i32,
+    String,
+    serde_json::Value,
+    String,
+    String,
+    Option<serde_json::Value>,
+    String,
+    Option<Uuid>,
+    DateTime<Utc>,
+    String,
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OverlapAction {
+    Start,
+    Skip,
+    /// Create the run as `queued`; `promote_queued_runs` starts it later
+    Queue,
+}
+
+/// What to do with cron slots that passed while no worker was running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CatchupMode {
+    /// Drop them and resume at the next future slot
+    Skip,
+    /// Fire a single run for the whole missed window
+    RunOnce,
+    /// Fire one run per missed slot (up to `SCHEDULE_CATCHUP_MAX`)
+    RunAll,
+}
+
+impl CatchupMode {
+    fn parse(mode: &str) -> Self {
+        match mode {
+            "run_once" => CatchupMode::RunOnce,
+            "run_all" => CatchupMode::RunAll,
+            _ => CatchupMode::Skip,
+        }
+    }
+}
+
+/// A slot fired within this long of its time is on time, not a catch-up.
+/// Comfortably above the 10s schedule check interval.
+const SCHEDULE_GRACE: chrono::Duration = chrono::Duration::seconds(60);
+
+/// Upper bound on missed slots enumerated for one schedule (a per-second cron
+/// after a long outage would otherwise enumerate millions).
+const MAX_MISSED_SCAN: usize = 10_000;
+
+fn catchup_max() -> usize {
+    std::env::var("SCHEDULE_CATCHUP_MAX")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(24)
+}
+
+impl DueSchedule {
+    fn from_row(row: ScheduleRow) -> Self {
+        let (schedule_id, workflow_id, name, graph, cron_expr, timezone, input_data, overlap_mode, active_version_id, due_at, catchup_mode) =
+            row;
+        Self {
+            schedule_id,
+            workflow_id,
+            name,
+            graph,
+            cron_expr,
+            timezone,
+            input_data,
+            overlap_mode,
+            active_version_id,
+            due_at,
+            catchup_mode: CatchupMode::parse(&catchup_mode),
+        }
+    }
+
+    /// Every slot from `due_at` up to `now`, oldest first.
+    fn missed_slots(&self, now: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let mut slots = vec![self.due_at];
+        let Ok(expr) = normalize_cron_expression(&self.cron_expr) else {
+            return slots;
+        };
+        let Ok(schedule) = Schedule::from_str(&expr) else {
+            return slots;
+        };
+        let tz: Tz = self.timezone.parse().unwrap_or(chrono_tz::UTC);
+        slots.extend(
+            schedule
+                .after(&self.due_at.with_timezone(&tz))
+                .map(|dt| dt.with_timezone(&Utc))
+                .take_while(|dt| *dt <= now)
+                .take(MAX_MISSED_SCAN),
+        );
+        slots
+    }
+
+    /// Slots to start runs for now, per the catch-up mode, plus how many
+    /// missed slots are dropped.
+    fn slots_to_fire(&self, now: DateTime<Utc>, max: usize) -> (Vec<DateTime<Utc>>, usize) {
+        let missed = self.missed_slots(now);
+        let total = missed.len();
+        let fire: Vec<DateTime<Utc>> = match self.catchup_mode {
+            CatchupMode::Skip => missed.into_iter().filter(|slot| now - *slot <= SCHEDULE_GRACE).collect(),
+            CatchupMode::RunOnce => missed.last().copied().into_iter().collect(),
+            // The most recent slots when there are more than `max`
+            CatchupMode::RunAll => missed[total.saturating_sub(max.max(1))..].to_vec(),
+        };
+        let dropped = total - fire.len();
+        (fire, dropped)
+    }
+
+    /// Name used in scheduler logs ("workflow" or "workflow/#schedule").
+    fn label(&self) -> String {
+        match self.schedule_id {
+            Some(id) => format!("{}/#{}", self.name, id),
+            None => self.name.clone(),
+        }
+    }
+
+    /// `(active, queued)` counts among the workflow's in-flight cron runs,
+    /// given as `(schedule_id, status)`. Only runs fired by this same schedule count.
+    fn count_runs(&self, runs: &[(Option<i32>, String)]) -> (usize, usize) {
+        let mine = runs.iter().filter(|(id, _)| *id == self.schedule_id);
+        let queued = mine.clone().filter(|(_, status)| status == "queued").count();
+        (mine.count() - queued, queued)
+    }
+
+    /// What to do with a due slot given this schedule's in-flight runs.
+    ///
+    /// - `skip`: drop it while a run is active
+    /// - `queue`: queue it behind every earlier run, so runs are serialized
+    /// - `queue_one`: like `queue`, but with at most one run waiting
+    /// - `parallel` (and anything else): start it
+    fn overlap_action(&self, active: usize, queued: usize) -> OverlapAction {
+        match self.overlap_mode.as_str() {
+            "skip" if active > 0 => OverlapAction::Skip,
+            "queue" if active + queued > 0 => OverlapAction::Queue,
+            "queue_one" if queued > 0 => OverlapAction::Skip,
+            "queue_one" if active > 0 => OverlapAction::Queue,
+            _ => OverlapAction::Start,
+        }
+    }
+
+    fn run_created_payload(&self) -> serde_json::Value {
+        let mut payload = serde_json::json!({
+            "trigger": "cron",
+            "schedule": self.cron_expr,
+            "workflow_name": self.name,
+        });
+        if let Some(id) = self.schedule_id {
+            payload["schedule_id"] = serde_json::json!(id);
+        }
+        payload
+    }
+
+    /// Store the next fire time on whichever row this schedule came from.
+    async fn set_next_run(&self, pool: &PgPool, next_run: DateTime<Utc>, fired: bool) -> Result<u64, sqlx::Error> {
+        let result = match self.schedule_id {
+            Some(id) => {
+                sqlx::query(
+                    "UPDATE workflow_schedules SET next_run = $1, last_run = CASE WHEN $3 THEN NOW() ELSE last_run END WHERE id = $2",
+                )
+                .bind(next_run)
+                .bind(id)
+                .bind(fired)
+                .execute(pool)
+                .await?
+            }
+            None => {
+                sqlx::query("UPDATE workflows SET schedule_next_run = $1 WHERE id = $2")
+                    .bind(next_run)
+                    .bind(self.workflow_id)
+                    .execute(pool)
+                    .await?
+            }
+        };
+        Ok(result.rows_affected())
+    }
+}
+
+/// Load every schedule that is due: the legacy per-workflow columns plus the
+/// rows of `workflow_schedules`.
 /// Uses the active published version if available, otherwise falls back to draft.
-async fn check_scheduled_workflows(pool: &PgPool, redis_client: &redis::Client) {
-    // Query for workflows that are due to run
+async fn load_due_schedules(pool: &PgPool) -> Result<Vec<DueSchedule>, sqlx::Error> {
     // Use FOR UPDATE SKIP LOCKED to prevent multiple workers from picking up the same workflow
     // Join with workflow_versions to get the active version's graph if available
-    let due_workflows: Vec<(i32, String, serde_json::Value, String, String, Option<serde_json::Value>, String, Option<Uuid>)> = 
-        match sqlx::query_as(
-            r#"
-            SELECT 
-                w.id, 
-                w.name, 
-                COALESCE(wv.graph, w.graph) as graph,
-                w.schedule_cron, 
-                COALESCE(w.schedule_timezone, 'UTC') as timezone,
-                w.schedule_input_data,
-                COALESCE(w.schedule_overlap_mode, 'skip') as overlap_mode,
-                w.active_version_id
-            FROM workflows w
-            LEFT JOIN workflow_versions wv ON w.active_version_id = wv.id
-            WHERE w.schedule_enabled = true
-              AND w.schedule_next_run IS NOT NULL
-              AND w.schedule_next_run <= NOW()
-            FOR UPDATE OF w SKIP LOCKED
-            LIMIT 10
-            "#,
-        )
-        .fetch_all(pool)
-        .await
+    let legacy: Vec<ScheduleRow> = sqlx::query_as(
+        r#"
+        SELECT 
+            NULL::int as schedule_id,
+            w.id, 
+            w.name, 
+            COALESCE(wv.graph, w.graph) as graph,
+            w.schedule_cron, 
+            COALESCE(w.schedule_timezone, 'UTC') as timezone,
+            w.schedule_input_data,
+            COALESCE(w.schedule_overlap_mode, 'skip') as overlap_mode,
+            w.active_version_id,
+            w.schedule_next_run,
+            COALESCE(w.schedule_catchup_mode, 'skip') as catchup_mode
+        FROM workflows w
+        LEFT JOIN workflow_versions wv ON w.active_version_id = wv.id
+        WHERE w.schedule_enabled = true
+          AND w.schedule_next_run IS NOT NULL
+          AND w.schedule_next_run <= NOW()
+        FOR UPDATE OF w SKIP LOCKED
+        LIMIT 10
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let extra: Vec<ScheduleRow> = sqlx::query_as(
+        r#"
+        SELECT 
+            s.id,
+            w.id, 
+            w.name, 
+            COALESCE(wv.graph, w.graph) as graph,
+            s.cron, 
+            COALESCE(s.timezone, 'UTC') as timezone,
+            s.input_data,
+            COALESCE(s.overlap_mode, 'skip') as overlap_mode,
+            w.active_version_id,
+            s.next_run,
+            COALESCE(s.catchup_mode, 'skip') as catchup_mode
+        FROM workflow_schedules s
+        JOIN workflows w ON w.id = s.workflow_id
+        LEFT JOIN workflow_versions wv ON w.active_version_id = wv.id
+        WHERE s.enabled = true
+          AND s.next_run IS NOT NULL
+          AND s.next_run <= NOW()
+        FOR UPDATE OF s SKIP LOCKED
+        LIMIT 10
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(legacy.into_iter().chain(extra).map(DueSchedule::from_row).collect())
+}
+
+/// Give freshly inserted `workflow_schedules` rows their first fire time.
+async fn seed_schedule_next_runs(pool: &PgPool) {
+    let unseeded: Vec<(i32, String, String)> = match sqlx::query_as(
+        "SELECT id, cron, COALESCE(timezone, 'UTC') FROM workflow_schedules WHERE enabled = true AND next_run IS NULL LIMIT 100",
+    )
+    .fetch_all(pool)
+    .await
     {
         Ok(rows) => rows,
         Err(e) => {
-            eprintln!("Scheduler: Failed to query scheduled workflows: {}", e);
+            tracing::error!("Scheduler: Failed to query unseeded schedules: {}", e);
+            return;
+        }
+    };
+
+    for (id, cron_expr, timezone) in unseeded {
+        let Some(next_run) = calculate_next_cron_run(&cron_expr, &timezone) else {
+            tracing::warn!("Scheduler: Schedule #{} has invalid cron '{}'", id, cron_expr);
+            continue;
+        };
+        let _ = sqlx::query("UPDATE workflow_schedules SET next_run = $1 WHERE id = $2 AND next_run IS NULL")
+            .bind(next_run)
+            .bind(id)
+            .execute(pool)
+            .await;
+    }
+}
+
+/// Check for scheduled workflows that are due to run.
+async fn check_scheduled_workflows(pool: &PgPool, redis_client: &redis::Client) {
+    seed_schedule_next_runs(pool).await;
+
+    let due_schedules = match load_due_schedules(pool).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Scheduler: Failed to query scheduled workflows: {}", e);
             return;
         }
     };
 
-    if due_workflows.is_empty() {
+    if due_schedules.is_empty() {
         return;
     }
 
-    println!(
+    tracing::info!(
         "Scheduler: Found {} scheduled workflow(s) due to run",
-        due_workflows.len()
+        due_schedules.len()
     );
 
     let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
-        eprintln!("Scheduler: Failed to connect to Redis");
+        tracing::error!("Scheduler: Failed to connect to Redis");
         return;
     };
 
-    for (workflow_id, name, graph, cron_expr, timezone, input_data, overlap_mode, active_version_id) in due_workflows {
-        // Check overlap mode
-        if overlap_mode == "skip" {
-            // Check if there's already a running instance
-            let running_count: (i64,) = match sqlx::query_as(
-                r#"
-                SELECT COUNT(*) FROM workflow_runs 
-                WHERE workflow_id = $1 
-                  AND status IN ('pending', 'running')
-                  AND trigger = 'cron'
-                "#,
-            )
-            .bind(workflow_id)
-            .fetch_one(pool)
-            .await
-            {
-                Ok(count) => count,
-                Err(_) => (0,),
-            };
-
-            if running_count.0 > 0 {
-                // Update next_run time to prevent constant re-checking
-                if let Some(next_run) = calculate_next_cron_run(&cron_expr, &timezone) {
-                    match sqlx::query(
-                        "UPDATE workflows SET schedule_next_run = $1 WHERE id = $2",
-                    )
-                    .bind(next_run)
-                    .bind(workflow_id)
-                    .execute(pool)
-                    .await
-                    {
-                        Ok(result) => {
-                            if result.rows_affected() > 0 {
-                                println!(
-                                    "Scheduler: Skipping '{}' - {} pending/running cron run(s). Next check at {} UTC",
-                                    name,
-                                    running_count.0,
-                                    next_run.format("%Y-%m-%d %H:%M:%S")
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Scheduler: Failed to update next_run for '{}': {}", name, e);
+    for schedule in due_schedules {
+        let name = schedule.label();
+
+        // Check overlap mode against this schedule's in-flight runs
+        let in_flight: Vec<(Option<i32>, String)> = sqlx::query_as(
+            r#"
+            SELECT schedule_id, status FROM workflow_runs 
+            WHERE workflow_id = $1 
+              AND status IN ('pending', 'running', 'queued')
+              AND trigger = 'cron'
+            "#,
+        )
+        .bind(schedule.workflow_id)
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default();
+        let (mut active, mut queued) = schedule.count_runs(&in_flight);
+
+        if schedule.overlap_action(active, queued) == OverlapAction::Skip {
+            // Update next_run time to prevent constant re-checking
+            if let Some(next_run) = calculate_next_cron_run(&schedule.cron_expr, &schedule.timezone) {
+                match schedule.set_next_run(pool, next_run, false).await {
+                    Ok(rows) => {
+                        if rows > 0 {
+                            tracing::info!(
+                                "Scheduler: Skipping '{}' - {} in-flight cron run(s). Next check at {} UTC",
+                                name,
+                                active + queued,
+                                next_run.format("%Y-%m-%d %H:%M:%S")
+                            );
                         }
                     }
+                    Err(e) => {
+                        tracing::error!("Scheduler: Failed to update next_run for '{}': {}", name, e);
+                    }
                 }
-                continue;
             }
+            continue;
         }
 
-        // Create a new run
-        let run_id = Uuid::new_v4();
-
-        if active_version_id.is_some() {
-            println!(
-                "Scheduler: Starting cron run for '{}' (run_id: {}, using published version)",
+        let (slots, dropped) = schedule.slots_to_fire(Utc::now(), catchup_max());
+        if dropped > 0 {
+            tracing::info!(
+                "Scheduler: '{}' missed {} run(s) since {} UTC ({:?}), not firing them",
                 name,
-                &run_id.to_string()[..8]
+                dropped,
+                schedule.due_at.format("%Y-%m-%d %H:%M:%S"),
+                schedule.catchup_mode
             );
-        } else {
-            // Warn when running unpublished workflow - this shouldn't happen after migration
-            eprintln!(
-                "Scheduler: Starting cron run for '{}' (run_id: {}) using DRAFT - no published version exists!",
-            name,
-                &run_id.to_string()[..8]
-        );
         }
 
-        // Insert the workflow run (with version ID if using published version)
-        let insert_result = sqlx::query(
-            r#"
-            INSERT INTO workflow_runs (id, workflow_id, workflow_version_id, snapshot_graph, status, trigger, input_data)
-            VALUES ($1, $2, $3, $4, 'pending', 'cron', $5)
-            "#,
-        )
-        .bind(&run_id)
-        .bind(workflow_id)
-        .bind(&active_version_id)
-        .bind(&graph)
-        .bind(&input_data)
-        .execute(pool)
-        .await;
-
-        if let Err(e) = insert_result {
-            eprintln!("Scheduler: Failed to create run for '{}': {}", name, e);
+        let mut fired = 0;
+        for slot in slots {
+            let action = schedule.overlap_action(active, queued);
+            if action == OverlapAction::Skip {
+                break;
+            }
+            let queue = action == OverlapAction::Queue;
+            if !start_scheduled_run(pool, &mut con, &schedule, slot, queue).await {
+                break;
+            }
+            if queue {
+                queued += 1;
+            } else {
+                active += 1;
+            }
+            fired += 1;
+        }
+        // Nothing started because the insert failed: retry on the next tick
+        if fired == 0 && dropped == 0 {
             continue;
         }
 
-        // Log RUN_CREATED event
-        let _ = sqlx::query(
-            r#"
-            INSERT INTO run_events (run_id, event_type, payload)
-            VALUES ($1, 'RUN_CREATED', $2)
-            "#,
-        )
-        .bind(&run_id)
-        .bind(serde_json::json!({
-            "trigger": "cron",
-            "schedule": cron_expr,
-            "workflow_name": name,
-        }))
-        .execute(pool)
-        .await;
+        // Calculate and update next run time
+        if let Some(next_run) = calculate_next_cron_run(&schedule.cron_expr, &schedule.timezone) {
+            let _ = schedule.set_next_run(pool, next_run, fired > 0).await;
 
-        // Find and schedule starting nodes
-        if let Some(nodes) = graph.get("nodes").and_then(|n| n.as_array()) {
-            if let Some(edges) = graph.get("edges").and_then(|e| e.as_array()) {
-                // Find nodes with no incoming edges (starting nodes)
-                let target_ids: Vec<&str> = edges
-                    .iter()
-                    .filter_map(|e| e.get("target").and_then(|t| t.as_str()))
-                    .collect();
-
-                for node in nodes {
-                    let node_id = node.get("id").and_then(|id| id.as_str()).unwrap_or("");
-                    
-                    // Skip if this node has incoming edges
-                    if target_ids.contains(&node_id) {
-                        continue;
-                    }
+            tracing::info!(
+                "Scheduler: Next run for '{}' scheduled at {}",
+                name,
+                next_run.format("%Y-%m-%d %H:%M:%S %Z")
+            );
+        }
+    }
+}
 
-                    // Build job payload based on node type
-                    if let Some(job_payload) = build_job_payload(node, &run_id, &input_data) {
-                        let _: RedisResult<String> = con
-                            .xadd(ACTIVE_JOBS_KEY, "*", &[("payload", job_payload)])
-                            .await;
-                        
-                        // Log NODE_SCHEDULED event
-                        let _ = sqlx::query(
-                            r#"
-                            INSERT INTO run_events (run_id, node_id, event_type, payload)
-                            VALUES ($1, $2, 'NODE_SCHEDULED', $3)
-                            "#,
-                        )
-                        .bind(&run_id)
-                        .bind(node_id)
-                        .bind(serde_json::json!({"source": "cron_scheduler"}))
-                        .execute(pool)
-                        .await;
-                    }
+/// Create a run for one slot of a due schedule and enqueue its starting nodes
+/// (or leave it `queued` behind the schedule's active run).
+/// Returns false if the run couldn't be created.
+async fn start_scheduled_run(
+    pool: &PgPool,
+    con: &mut redis::aio::MultiplexedConnection,
+    schedule: &DueSchedule,
+    scheduled_for: DateTime<Utc>,
+    queue: bool,
+) -> bool {
+    let name = schedule.label();
+
+    // Create a new run
+    let run_id = Uuid::new_v4();
+
+    if queue {
+        tracing::info!(
+            "Scheduler: Queueing cron run for '{}' (run_id: {}) behind the active run",
+            name,
+            &run_id.to_string()[..8]
+        );
+    } else if schedule.active_version_id.is_some() {
+        tracing::info!(
+            "Scheduler: Starting cron run for '{}' (run_id: {}, using published version)",
+            name,
+            &run_id.to_string()[..8]
+        );
+    } else {
+        // Warn when running unpublished workflow - this shouldn't happen after migration
+        tracing::error!(
+            "Scheduler: Starting cron run for '{}' (run_id: {}) using DRAFT - no published version exists!",
+            name,
+            &run_id.to_string()[..8]
+        );
+    }
+
+    // Insert the workflow run (with version ID if using published version)
+    let insert_result = sqlx::query(
+        r#"
+        INSERT INTO workflow_runs (id, workflow_id, workflow_version_id, snapshot_graph, status, trigger, input_data, schedule_id)
+        VALUES ($1, $2, $3, $4, $7, 'cron', $5, $6)
+        "#,
+    )
+    .bind(run_id)
+    .bind(schedule.workflow_id)
+    .bind(schedule.active_version_id)
+    .bind(&schedule.graph)
+    .bind(&schedule.input_data)
+    .bind(schedule.schedule_id)
+    .bind(if queue { "queued" } else { "pending" })
+    .execute(pool)
+    .await;
+
+    if let Err(e) = insert_result {
+        tracing::error!("Scheduler: Failed to create run for '{}': {}", name, e);
+        return false;
+    }
+
+    // Log RUN_CREATED event
+    let mut payload = schedule.run_created_payload();
+    payload["scheduled_for"] = serde_json::json!(scheduled_for.to_rfc3339());
+    if Utc::now() - scheduled_for > SCHEDULE_GRACE {
+        payload["catchup"] = serde_json::json!(true);
+    }
+    if queue {
+        payload["queued"] = serde_json::json!(true);
+    }
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO run_events (run_id, event_type, payload)
+        VALUES ($1, 'RUN_CREATED', $2)
+        "#,
+    )
+    .bind(run_id)
+    .bind(payload)
+    .execute(pool)
+    .await;
+
+    if !queue {
+        enqueue_starting_nodes(pool, con, run_id, &schedule.graph, &schedule.input_data).await;
+    }
+
+    true
+}
+
+/// Push a run's starting nodes (those without incoming edges) to the job stream.
+async fn enqueue_starting_nodes(
+    pool: &PgPool,
+    con: &mut redis::aio::MultiplexedConnection,
+    run_id: Uuid,
+    graph: &serde_json::Value,
+    input_data: &Option<serde_json::Value>,
+) {
+    // Find and schedule starting nodes
+    if let Some(nodes) = graph.get("nodes").and_then(|n| n.as_array())
+        && let Some(edges) = graph.get("edges").and_then(|e| e.as_array()) {
+            // Find nodes with no incoming edges (starting nodes)
+            let target_ids: Vec<&str> = edges
+                .iter()
+                .filter_map(|e| e.get("target").and_then(|t| t.as_str()))
+                .collect();
+
+            for node in nodes {
+                let node_id = node.get("id").and_then(|id| id.as_str()).unwrap_or("");
+                
+                // Skip if this node has incoming edges
+                if target_ids.contains(&node_id) {
+                    continue;
+                }
+
+                // Build job payload based on node type
+                if let Some(job_payload) = build_job_payload(node, &run_id, input_data) {
+                    enqueue(con, &job_payload).await;
+                    
+                    // Log NODE_SCHEDULED event
+                    let _ = sqlx::query(
+                        r#"
+                        INSERT INTO run_events (run_id, node_id, event_type, payload)
+                        VALUES ($1, $2, 'NODE_SCHEDULED', $3)
+                        "#,
+                    )
+                    .bind(run_id)
+                    .bind(node_id)
+                    .bind(serde_json::json!({"source": "cron_scheduler"}))
+                    .execute(pool)
+                    .await;
                 }
             }
         }
+}
 
-        // Calculate and update next run time
-        if let Some(next_run) = calculate_next_cron_run(&cron_expr, &timezone) {
-            let _ = sqlx::query(
-                "UPDATE workflows SET schedule_next_run = $1 WHERE id = $2",
-            )
-            .bind(next_run)
-            .bind(workflow_id)
-            .execute(pool)
-            .await;
+/// Start the oldest queued cron run of each schedule whose previous run finished.
+async fn promote_queued_runs(pool: &PgPool, redis_client: &redis::Client) {
+    let candidates: Vec<(Uuid, serde_json::Value, Option<serde_json::Value>)> = match sqlx::query_as(
+        r#"
+        SELECT id, snapshot_graph, input_data FROM (
+            SELECT DISTINCT ON (workflow_id, schedule_id) id, snapshot_graph, input_data, created_at
+            FROM workflow_runs
+            WHERE status = 'queued' AND trigger = 'cron'
+            ORDER BY workflow_id, schedule_id, created_at
+        ) oldest
+        ORDER BY created_at
+        LIMIT 20
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("Scheduler: Failed to query queued runs: {}", e);
+            return;
+        }
+    };
 
-            println!(
-                "Scheduler: Next run for '{}' scheduled at {}",
-                name,
-                next_run.format("%Y-%m-%d %H:%M:%S %Z")
-            );
+    if candidates.is_empty() {
+        return;
+    }
+
+    let Ok(mut con) = redis_client.get_multiplexed_async_connection().await else {
+        tracing::error!("Scheduler: Failed to connect to Redis for queued runs");
+        return;
+    };
+
+    for (run_id, graph, input_data) in candidates {
+        // Promote only if no run of the same schedule is still active
+        let promoted = sqlx::query(
+            r#"
+            UPDATE workflow_runs q SET status = 'pending'
+            WHERE q.id = $1
+              AND q.status = 'queued'
+              AND NOT EXISTS (
+                SELECT 1 FROM workflow_runs a
+                WHERE a.workflow_id = q.workflow_id
+                  AND a.schedule_id IS NOT DISTINCT FROM q.schedule_id
+                  AND a.trigger = 'cron'
+                  AND a.status IN ('pending', 'running')
+              )
+            "#,
+        )
+        .bind(run_id)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected() == 1)
+        .unwrap_or(false);
+
+        if promoted {
+            tracing::info!(%run_id, "Scheduler: Starting queued cron run");
+            enqueue_starting_nodes(pool, &mut con, run_id, &graph, &input_data).await;
         }
     }
 }
@@ -848,7 +1562,7 @@ async fn check_scheduled_workflows(pool: &PgPool, redis_client: &redis::Client)
 /// This function automatically converts 5-field expressions to 6-field by prepending "0 ".
 fn calculate_next_cron_run(cron_expr: &str, timezone: &str) -> Option<DateTime<Utc>> {
     // Convert 5-field cron to 6-field by prepending "0 " for seconds
-    let extended_expr = normalize_cron_expression(cron_expr);
+    let extended_expr = normalize_cron_expression(cron_expr).ok()?;
     
     // Parse the cron expression
     let schedule = Schedule::from_str(&extended_expr).ok()?;
@@ -866,25 +1580,104 @@ fn calculate_next_cron_run(cron_expr: &str, timezone: &str) -> Option<DateTime<U
         .map(|dt| dt.with_timezone(&Utc))
 }
 
-/// Normalize a cron expression to 6-field format.
-/// 
-/// If the expression has 5 fields (standard cron), prepend "0 " for seconds.
-/// If the expression has 6 fields, use as-is.
-fn normalize_cron_expression(expr: &str) -> String {
-    let fields: Vec<&str> = expr.split_whitespace().collect();
-    
-    if fields.len() == 5 {
+/// Why a cron schedule can't be saved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CronError {
+    /// Neither 5 (standard) nor 6 (with seconds) fields
+    FieldCount { count: usize },
+    Unparseable { expr: String, reason: String },
+    InvalidTimezone(String),
+    /// Valid, but never fires again (e.g. a year in the past)
+    NoUpcomingRun,
+}
+
+impl std::fmt::Display for CronError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CronError::FieldCount { count } => {
+                write!(f, "Cron expression has {} field(s), expected 5 or 6", count)
+            }
+            CronError::Unparseable { expr, reason } => write!(f, "Invalid cron expression '{}': {}", expr, reason),
+            CronError::InvalidTimezone(tz) => write!(f, "Unknown timezone: {}", tz),
+            CronError::NoUpcomingRun => write!(f, "Cron expression has no upcoming run"),
+        }
+    }
+}
+
+impl std::error::Error for CronError {}
+
+/// Validate a schedule at save time and return its next fire time.
+///
+/// Unlike the scheduler's own lookup, an unknown timezone is an error here
+/// rather than a silent fallback to UTC.
+pub fn validate_cron(expr: &str, timezone: &str) -> Result<DateTime<Utc>, CronError> {
+    next_cron_run_after(expr, timezone, Utc::now())
+}
+
+/// The first time after `after` that a cron expression fires, read in
+/// `timezone` (strictly, like `validate_cron`).
+pub fn next_cron_run_after(expr: &str, timezone: &str, after: DateTime<Utc>) -> Result<DateTime<Utc>, CronError> {
+    let schedule = Schedule::from_str(&normalize_cron_expression(expr)?).map_err(|e| CronError::Unparseable {
+        expr: expr.to_string(),
+        reason: e.to_string(),
+    })?;
+    let tz: Tz = timezone.parse().map_err(|_| CronError::InvalidTimezone(timezone.to_string()))?;
+
+    schedule
+        .after(&after.with_timezone(&tz))
+        .next()
+        .map(|dt| dt.with_timezone(&Utc))
+        .ok_or(CronError::NoUpcomingRun)
+}
+
+/// Normalize a cron expression to the 6-field format the `cron` crate parses.
+///
+/// - 5 fields (standard cron): prepend "0 " for seconds
+/// - 6 fields (second-level precision): use as-is
+/// - `@yearly`/`@annually`, `@monthly`, `@weekly`, `@daily`/`@midnight`, `@hourly`
+/// - `@every Ns`, `@every Nm`, `@every Nh`, where N divides the minute/day evenly
+fn normalize_cron_expression(expr: &str) -> Result<String, CronError> {
+    let expr = expr.trim();
+
+    if let Some(shortcut) = expr.strip_prefix('@') {
+        let unparseable = |reason: &str| CronError::Unparseable { expr: expr.to_string(), reason: reason.to_string() };
+        let fields = match shortcut {
+            "yearly" | "annually" => "0 0 0 1 1 *".to_string(),
+            "monthly" => "0 0 0 1 * *".to_string(),
+            "weekly" => "0 0 0 * * Sun".to_string(),
+            "daily" | "midnight" => "0 0 0 * * *".to_string(),
+            "hourly" => "0 0 * * * *".to_string(),
+            _ => {
+                let interval = shortcut.strip_prefix("every ").ok_or_else(|| unparseable("unknown shortcut"))?.trim();
+                let unit_at = interval.char_indices().last().map_or(0, |(i, _)| i);
+                let (n, unit) = interval.split_at(unit_at);
+                let n: u32 = n.parse().map_err(|_| unparseable("expected @every <N>s|m|h"))?;
+                let (per, template) = match unit {
+                    "s" => (60, "*/{} * * * * *"),
+                    "m" => (60, "0 */{} * * * *"),
+                    "h" => (24, "0 0 */{} * * *"),
+                    _ => return Err(unparseable("expected @every <N>s|m|h")),
+                };
+                if n == 0 || per % n != 0 {
+                    return Err(unparseable(&format!("@every interval must divide {} evenly", per)));
+                }
+                template.replace("{}", &n.to_string())
+            }
+        };
+        return Ok(fields);
+    }
+
+    match expr.split_whitespace().count() {
         // Standard 5-field cron: minute hour day month weekday
         // Convert to 6-field: second minute hour day month weekday
-        format!("0 {}", expr)
-    } else {
-        // Already 6-field or invalid (let the parser handle it)
-        expr.to_string()
+        5 => Ok(format!("0 {}", expr)),
+        6 => Ok(expr.to_string()),
+        count => Err(CronError::FieldCount { count }),
     }
 }
 
 /// Build a job payload for a node to be scheduled.
-fn build_job_payload(
+pub(crate) fn build_job_payload(
     node: &serde_json::Value,
     run_id: &Uuid,
     input_data: &Option<serde_json::Value>,
@@ -906,7 +1699,54 @@ fn build_job_payload(
                         "url": node_data.get("url").and_then(|v| v.as_str()).unwrap_or(""),
                         "method": node_data.get("method").and_then(|v| v.as_str()).unwrap_or("GET"),
                         "headers": node_data.get("headers"),
-                        "body": node_data.get("body")
+                        "query": node_data.get("query"),
+                        "body": node_data.get("body"),
+                        "body_encoding": node_data.get("bodyEncoding"),
+                        "body_source": node_data.get("bodySource"),
+                        "content_type": node_data.get("contentType"),
+                        "stream_body": node_data.get("streamBody").and_then(|v| v.as_bool()).unwrap_or(false),
+                        "compress": node_data.get("compress"),
+                        "response_schema": node_data.get("responseSchema"),
+                        "max_response_bytes": node_data.get("maxResponseBytes"),
+                        "connect_retries": node_data.get("connectRetries"),
+                        "paginate": node_data.get("paginate"),
+                        "cache_ttl_ms": node_data.get("cacheTtlMs"),
+                        "auth": node_data.get("auth")
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
+        "websocket" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "WEBSOCKET",
+                    "data": {
+                        "url": node_data.get("url").and_then(|v| v.as_str()).unwrap_or(""),
+                        "send": node_data.get("send"),
+                        "collect_until": node_data.get("collectUntil"),
+                        "headers": node_data.get("headers")
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
+        "db-upsert" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "DBUPSERT",
+                    "data": {
+                        "table": node_data.get("table").and_then(|v| v.as_str()).unwrap_or(""),
+                        "rows": node_data.get("rows").cloned().unwrap_or(serde_json::json!([])),
+                        "conflict_columns": node_data.get("conflictColumns").cloned().unwrap_or(serde_json::json!([]))
                     }
                 },
                 "retry_count": 0,
@@ -922,7 +1762,9 @@ fn build_job_payload(
                     "type": "CODE",
                     "data": {
                         "code": node_data.get("code").and_then(|v| v.as_str()).unwrap_or("return {};"),
-                        "inputs": input_data
+                        "inputs": input_data,
+                        "timeout_ms": node_data.get("timeoutMs"),
+                        "api_style": node_data.get("apiStyle")
                     }
                 },
                 "retry_count": 0,
@@ -943,11 +1785,17 @@ fn build_job_payload(
                         "messages": node_data.get("messages").unwrap_or(&serde_json::json!([])),
                         "temperature": node_data.get("temperature"),
                         "max_tokens": node_data.get("maxTokens"),
-                        "stream": node_data.get("stream").and_then(|v| v.as_bool()).unwrap_or(true)
+                        "stream": node_data.get("stream").and_then(|v| v.as_bool()).unwrap_or(true),
+                        "token_flush_ms": node_data.get("tokenFlushMs"),
+                        "max_context_tokens": node_data.get("maxContextTokens"),
+                        "truncation": node_data.get("truncation"),
+                        "tools": node_data.get("tools"),
+                        "tool_choice": node_data.get("toolChoice"),
+                        "timeout_ms": node_data.get("timeoutMs")
                     }
                 },
                 "retry_count": 0,
-                "max_retries": 1,
+                "max_retries": llm::max_retries(),
                 "isolated": false
             })
         }
@@ -961,7 +1809,24 @@ fn build_job_payload(
                         "route_by": node_data.get("routeBy").and_then(|v| v.as_str()).unwrap_or(""),
                         "conditions": node_data.get("conditions").unwrap_or(&serde_json::json!([])),
                         "default_output": node_data.get("defaultOutput").and_then(|v| v.as_str()).unwrap_or("default"),
-                        "mode": node_data.get("routerMode").and_then(|v| v.as_str()).unwrap_or("first_match")
+                        "mode": node_data.get("routerMode").and_then(|v| v.as_str()).unwrap_or("first_match"),
+                        "evaluate": node_data.get("evaluate").and_then(|v| v.as_bool()).unwrap_or(false)
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 0,
+                "isolated": false
+            })
+        }
+        "conditional" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "CONDITIONAL",
+                    "data": {
+                        "expression": node_data.get("expression").and_then(|v| v.as_str()).unwrap_or(""),
+                        "input": input_data
                     }
                 },
                 "retry_count": 0,
@@ -977,7 +1842,8 @@ fn build_job_payload(
                     "type": "DELAY",
                     "data": {
                         "duration_ms": node_data.get("durationMs").and_then(|v| v.as_u64()).unwrap_or(1000),
-                        "duration_str": node_data.get("durationStr")
+                        "duration_str": node_data.get("durationStr"),
+                        "until": node_data.get("until")
                     }
                 },
                 "retry_count": 0,
@@ -993,7 +1859,77 @@ fn build_job_payload(
                     "type": "WEBHOOKWAIT",
                     "data": {
                         "description": node_data.get("description"),
-                        "timeout_ms": node_data.get("timeoutMs").and_then(|v| v.as_u64()).unwrap_or(604800000)
+                        "timeout_ms": node_data.get("timeoutMs").and_then(|v| v.as_u64()).unwrap_or(604800000),
+                        "signing_secret": node_data.get("signingSecret"),
+                        "outcomes": node_data.get("outcomes")
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 0,
+                "isolated": false
+            })
+        }
+        "dbWait" | "db-wait" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "DBWAIT",
+                    "data": {
+                        "query": node_data.get("query").and_then(|v| v.as_str()).unwrap_or(""),
+                        "params": node_data.get("params").cloned().unwrap_or(serde_json::json!([])),
+                        "poll_interval_ms": node_data.get("pollIntervalMs").and_then(|v| v.as_u64()).unwrap_or(30000),
+                        "timeout_ms": node_data.get("timeoutMs").and_then(|v| v.as_u64()).unwrap_or(604800000),
+                        "description": node_data.get("description")
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 0,
+                "isolated": false
+            })
+        }
+        "parallel" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "PARALLEL",
+                    "data": {
+                        "branches": node_data.get("branches").cloned().unwrap_or(serde_json::json!([])),
+                        "join_mode": node_data.get("joinMode").and_then(|v| v.as_str()).unwrap_or("all")
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 0,
+                "isolated": false
+            })
+        }
+        "loop" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "LOOP",
+                    "data": {
+                        "body": node_data.get("body").and_then(|v| v.as_str()).unwrap_or(""),
+                        "condition": node_data.get("condition").and_then(|v| v.as_str()).unwrap_or(""),
+                        "max_iterations": node_data.get("maxIterations").and_then(|v| v.as_u64()).unwrap_or(10)
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 0,
+                "isolated": false
+            })
+        }
+        "transform" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "TRANSFORM",
+                    "data": {
+                        "expression": node_data.get("expression").and_then(|v| v.as_str()).unwrap_or("."),
+                        "input": input_data
                     }
                 },
                 "retry_count": 0,
@@ -1001,6 +1937,60 @@ fn build_job_payload(
                 "isolated": false
             })
         }
+        "email" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "EMAIL",
+                    "data": {
+                        "to": node_data.get("to").cloned().unwrap_or_else(|| serde_json::json!([])),
+                        "cc": node_data.get("cc").cloned().unwrap_or_else(|| serde_json::json!([])),
+                        "subject": node_data.get("subject").and_then(|v| v.as_str()).unwrap_or(""),
+                        "body": node_data.get("body").and_then(|v| v.as_str()).unwrap_or(""),
+                        "is_html": node_data.get("isHtml").and_then(|v| v.as_bool()).unwrap_or(false)
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
+        "s3" => {
+            let operation = node_data.get("operation").and_then(|v| v.as_str()).unwrap_or("get");
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "S3",
+                    "data": {
+                        "operation": operation,
+                        "bucket": node_data.get("bucket").and_then(|v| v.as_str()).unwrap_or(""),
+                        "key": node_data.get("key").and_then(|v| v.as_str()).unwrap_or(""),
+                        "body": if operation == "put" { node_data.get("body").cloned() } else { None },
+                        "content_type": node_data.get("contentType").and_then(|v| v.as_str())
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
+        "aggregate" => {
+            serde_json::json!({
+                "id": node_id,
+                "run_id": run_id.to_string(),
+                "node": {
+                    "type": "AGGREGATE",
+                    "data": {
+                        "sources": node_data.get("sources").cloned().unwrap_or(serde_json::json!([]))
+                    }
+                },
+                "retry_count": 0,
+                "max_retries": 3,
+                "isolated": false
+            })
+        }
         _ => return None,
     };
 
@@ -1011,14 +2001,76 @@ fn build_job_payload(
 mod tests {
     use super::*;
 
+    fn bulk(s: &str) -> redis::Value {
+        redis::Value::BulkString(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_parse_autoclaim_accepts_redis_6_and_7_replies() {
+        let message = redis::Value::Array(vec![
+            bulk("1700000000000-0"),
+            redis::Value::Array(vec![bulk("payload"), bulk("{}"), bulk("deliveries"), bulk("2")]),
+        ]);
+        let expected = (
+            "1700000000001-0".to_string(),
+            vec![(
+                "1700000000000-0".to_string(),
+                vec![("payload".to_string(), "{}".to_string()), ("deliveries".to_string(), "2".to_string())],
+            )],
+        );
+
+        let v6 = redis::Value::Array(vec![bulk("1700000000001-0"), redis::Value::Array(vec![message.clone()])]);
+        assert_eq!(parse_autoclaim(v6).unwrap(), expected);
+
+        let v7 = redis::Value::Array(vec![
+            bulk("1700000000001-0"),
+            redis::Value::Array(vec![message]),
+            redis::Value::Array(vec![bulk("1699999999999-0")]),
+        ]);
+        assert_eq!(parse_autoclaim(v7).unwrap(), expected);
+
+        assert!(parse_autoclaim(redis::Value::Array(vec![bulk("0-0")])).is_err());
+        assert!(parse_autoclaim(redis::Value::Nil).is_err());
+    }
+
     #[test]
     fn test_cron_normalization() {
         // 5-field should become 6-field
-        assert_eq!(normalize_cron_expression("* * * * *"), "0 * * * * *");
-        assert_eq!(normalize_cron_expression("0 9 * * 1-5"), "0 0 9 * * 1-5");
+        assert_eq!(normalize_cron_expression("* * * * *").unwrap(), "0 * * * * *");
+        assert_eq!(normalize_cron_expression("0 9 * * 1-5").unwrap(), "0 0 9 * * 1-5");
         
         // 6-field should stay as-is
-        assert_eq!(normalize_cron_expression("0 0 9 * * 1-5"), "0 0 9 * * 1-5");
+        assert_eq!(normalize_cron_expression("0 0 9 * * 1-5").unwrap(), "0 0 9 * * 1-5");
+
+        // Anything else is rejected before it reaches the parser
+        assert_eq!(normalize_cron_expression("0 0 9 * * 1-5 2030"), Err(CronError::FieldCount { count: 7 }));
+        assert_eq!(normalize_cron_expression("* *"), Err(CronError::FieldCount { count: 2 }));
+    }
+
+    #[test]
+    fn test_cron_shortcuts() {
+        let cases = [
+            ("@yearly", "0 0 0 1 1 *"),
+            ("@annually", "0 0 0 1 1 *"),
+            ("@monthly", "0 0 0 1 * *"),
+            ("@weekly", "0 0 0 * * Sun"),
+            ("@daily", "0 0 0 * * *"),
+            ("@midnight", "0 0 0 * * *"),
+            ("@hourly", "0 0 * * * *"),
+            ("@every 15s", "*/15 * * * * *"),
+            ("@every 5m", "0 */5 * * * *"),
+            ("@every 6h", "0 0 */6 * * *"),
+        ];
+        for (shortcut, expected) in cases {
+            assert_eq!(normalize_cron_expression(shortcut).unwrap(), expected, "{}", shortcut);
+            assert!(calculate_next_cron_run(shortcut, "UTC").is_some(), "{} should parse", shortcut);
+        }
+
+        assert!(matches!(normalize_cron_expression("@fortnightly"), Err(CronError::Unparseable { .. })));
+        assert!(matches!(normalize_cron_expression("@every 7m"), Err(CronError::Unparseable { .. })));
+        assert!(matches!(normalize_cron_expression("@every 0s"), Err(CronError::Unparseable { .. })));
+        assert!(matches!(normalize_cron_expression("@every 10d"), Err(CronError::Unparseable { .. })));
+        assert!(matches!(normalize_cron_expression("@every 5é"), Err(CronError::Unparseable { .. })));
     }
 
     #[test]
@@ -1058,4 +2110,158 @@ mod tests {
         let next = calculate_next_cron_run("0 9 * * *", "Invalid/Zone");
         assert!(next.is_some(), "Invalid timezone should fall back to UTC");
     }
+
+    /// Needs a Redis at `REDIS_TEST_URL`; skipped without one.
+    #[tokio::test]
+    async fn test_failed_enqueue_leaves_delayed_job_in_set() {
+        let Ok(url) = std::env::var("REDIS_TEST_URL") else {
+            return;
+        };
+        let mut con = redis::Client::open(url).unwrap().get_multiplexed_async_connection().await.unwrap();
+        let prefix = format!("swiftgrid_test:{}", Uuid::new_v4());
+        let delayed = format!("{}:delayed", prefix);
+        let streams = [format!("{}:high", prefix), format!("{}:default", prefix), format!("{}:low", prefix)];
+        let streams = [streams[0].as_str(), streams[1].as_str(), streams[2].as_str()];
+        let job = r#"{"id":"job-1","priority":"high"}"#;
+        let _: () = con.zadd(&delayed, job, 1000).await.unwrap();
+
+        // A plain string where the stream should be makes XADD fail
+        let _: () = con.set(streams[0], "not a stream").await.unwrap();
+        assert!(move_due_jobs(&mut con, &delayed, streams, 2000).await.is_err());
+        let score: Option<f64> = con.zscore(&delayed, job).await.unwrap();
+        assert_eq!(score, Some(1000.0));
+
+        let _: () = con.del(streams[0]).await.unwrap();
+        assert_eq!(move_due_jobs(&mut con, &delayed, streams, 2000).await.unwrap(), 1);
+        let left: usize = con.zcard(&delayed).await.unwrap();
+        let pushed: usize = con.xlen(streams[0]).await.unwrap();
+        assert_eq!((left, pushed), (0, 1));
+
+        let _: () = con.del(&[delayed.as_str(), streams[0]]).await.unwrap();
+    }
+
+    #[test]
+    fn test_delayed_poll_backs_off_when_idle() {
+        let mut backoff = IdleBackoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let waits: Vec<u64> = (0..5).map(|_| backoff.next(false).as_secs()).collect();
+        assert_eq!(waits, vec![1, 2, 4, 5, 5]);
+
+        // A found job snaps straight back to the base interval
+        assert_eq!(backoff.next(true), Duration::from_secs(1));
+        assert_eq!(backoff.next(false), Duration::from_secs(1));
+        assert_eq!(backoff.next(false), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_validate_cron() {
+        let next = validate_cron("0 9 * * 1-5", "Europe/Amsterdam").unwrap();
+        assert!(next > Utc::now());
+        assert!(validate_cron("0 0 9 * * *", "UTC").is_ok());
+
+        assert_eq!(validate_cron("* * *", "UTC"), Err(CronError::FieldCount { count: 3 }));
+        assert_eq!(validate_cron("", "UTC"), Err(CronError::FieldCount { count: 0 }));
+        assert!(matches!(validate_cron("61 * * * *", "UTC"), Err(CronError::Unparseable { .. })));
+        assert!(matches!(validate_cron("* * * * banana", "UTC"), Err(CronError::Unparseable { .. })));
+        assert_eq!(
+            validate_cron("0 9 * * *", "Invalid/Zone"),
+            Err(CronError::InvalidTimezone("Invalid/Zone".to_string()))
+        );
+    }
+
+    fn schedule(schedule_id: Option<i32>, cron: &str, input: serde_json::Value) -> DueSchedule {
+        DueSchedule::from_row((
+            schedule_id,
+            7,
+            "report".to_string(),
+            serde_json::json!({"nodes": [], "edges": []}),
+            cron.to_string(),
+            "UTC".to_string(),
+            Some(input),
+            "skip".to_string(),
+            None,
+            Utc::now(),
+            "skip".to_string(),
+        ))
+    }
+
+    #[test]
+    fn test_schedules_fire_independently() {
+        let hourly = schedule(Some(1), "0 * * * *", serde_json::json!({"region": "eu"}));
+        let daily = schedule(Some(2), "0 9 * * *", serde_json::json!({"region": "us"}));
+        let legacy = schedule(None, "*/5 * * * *", serde_json::json!({}));
+
+        // Schedule 1 still has a run in flight; the others are free to fire
+        let in_flight = [(Some(1), "running".to_string())];
+        assert_eq!(hourly.count_runs(&in_flight), (1, 0));
+        assert_eq!(hourly.overlap_action(1, 0), OverlapAction::Skip);
+        assert_eq!(daily.count_runs(&in_flight), (0, 0));
+        assert_eq!(legacy.count_runs(&in_flight), (0, 0));
+        assert_eq!(legacy.count_runs(&[(None, "pending".to_string()), (Some(2), "running".to_string())]), (1, 0));
+
+        // Each run carries its own schedule's input and id
+        assert_eq!(daily.input_data, Some(serde_json::json!({"region": "us"})));
+        assert_eq!(daily.run_created_payload()["schedule_id"], 2);
+        assert_eq!(daily.run_created_payload()["schedule"], "0 9 * * *");
+        assert!(legacy.run_created_payload().get("schedule_id").is_none());
+        assert_eq!(daily.label(), "report/#2");
+    }
+
+    #[test]
+    fn test_catchup_modes_after_downtime() {
+        let now = Utc::now();
+        let mut s = schedule(Some(1), "0 * * * *", serde_json::json!({}));
+        // Worker was down for ~5 hours: due at the top of an hour 5h ago
+        let due = now - chrono::Duration::hours(5);
+        s.due_at = due - chrono::Duration::seconds(due.timestamp() % 3600);
+        let missed = s.missed_slots(now).len();
+        assert!((5..=6).contains(&missed));
+
+        s.catchup_mode = CatchupMode::Skip;
+        assert_eq!(s.slots_to_fire(now, 24), (vec![], missed));
+
+        s.catchup_mode = CatchupMode::RunOnce;
+        let (slots, dropped) = s.slots_to_fire(now, 24);
+        assert_eq!((slots.len(), dropped), (1, missed - 1));
+        assert!(now - slots[0] < chrono::Duration::hours(1), "catch-up run covers the latest slot");
+
+        s.catchup_mode = CatchupMode::RunAll;
+        assert_eq!(s.slots_to_fire(now, 24).0.len(), missed);
+        let (slots, dropped) = s.slots_to_fire(now, 2);
+        assert_eq!((slots.len(), dropped), (2, missed - 2));
+        assert!(slots[0] < slots[1]);
+
+        // On time: every mode fires exactly the due slot
+        s.due_at = now - chrono::Duration::seconds(5);
+        for mode in [CatchupMode::Skip, CatchupMode::RunOnce, CatchupMode::RunAll] {
+            s.catchup_mode = mode;
+            assert_eq!(s.slots_to_fire(now, 24), (vec![s.due_at], 0));
+        }
+        assert_eq!(CatchupMode::parse("run_all"), CatchupMode::RunAll);
+        assert_eq!(CatchupMode::parse("bogus"), CatchupMode::Skip);
+    }
+
+    #[test]
+    fn test_parallel_schedule_ignores_overlap() {
+        let mut s = schedule(Some(1), "* * * * *", serde_json::json!({}));
+        s.overlap_mode = "parallel".to_string();
+        assert_eq!(s.overlap_action(2, 0), OverlapAction::Start);
+    }
+
+    #[test]
+    fn test_queue_overlap_serializes_runs() {
+        let mut s = schedule(Some(1), "0 * * * *", serde_json::json!({}));
+        s.overlap_mode = "queue".to_string();
+        let in_flight = [(Some(1), "running".to_string()), (Some(1), "queued".to_string()), (Some(2), "queued".to_string())];
+        assert_eq!(s.count_runs(&in_flight), (1, 1));
+
+        assert_eq!(s.overlap_action(0, 0), OverlapAction::Start);
+        assert_eq!(s.overlap_action(1, 0), OverlapAction::Queue);
+        // Still queued behind earlier queued runs, even once the active one finished
+        assert_eq!(s.overlap_action(0, 1), OverlapAction::Queue);
+        assert_eq!(s.overlap_action(1, 5), OverlapAction::Queue);
+
+        s.overlap_mode = "queue_one".to_string();
+        assert_eq!(s.overlap_action(1, 0), OverlapAction::Queue);
+        assert_eq!(s.overlap_action(1, 1), OverlapAction::Skip);
+    }
 }
@@ -0,0 +1,318 @@
+//! SSRF protection for outbound node connections.
+//!
+//! Workflow authors choose the URLs HTTP, LLM (`base_url`) and WebSocket nodes
+//! call, so without a guard a node could reach cloud metadata endpoints or
+//! services on the worker's private network. The S3 endpoint and SMTP host go
+//! through the same policy, so a node can't be pointed at them to get in
+//! either. Requests to loopback, private, link-local and other internal ranges
+//! are rejected unless `HTTP_ALLOW_PRIVATE_NETWORKS` is set. `HTTP_ALLOWED_HOSTS`
+//! (comma-separated, `*.example.com` matches subdomains) restricts nodes to the
+//! listed hosts, which are trusted wherever they resolve.
+//!
+//! The hostname is resolved and every address checked before the request, and
+//! the node client's resolver filters addresses again at connect time, so a
+//! record that changes between the check and the connection (DNS rebinding)
+//! can't slip through. Redirects are checked the same way. Raw TCP connections
+//! (SMTP) connect to the addresses `resolve_host` checked.
+//!
+//! Internal addresses are denied by default, including `localhost`: a local
+//! LLM server, MinIO or SMTP relay needs `HTTP_ALLOWED_HOSTS` (or
+//! `HTTP_ALLOW_PRIVATE_NETWORKS=true`).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+
+/// Redirects followed by node requests (reqwest's default)
+const MAX_REDIRECTS: usize = 10;
+
+/// Which destinations nodes may connect to.
+#[derive(Debug, Clone, Default)]
+pub struct SsrfPolicy {
+    /// Allow internal addresses (`HTTP_ALLOW_PRIVATE_NETWORKS`)
+    pub allow_private: bool,
+    /// When non-empty, the only hosts nodes may call (`HTTP_ALLOWED_HOSTS`)
+    pub allowed_hosts: Vec<String>,
+}
+
+/// Why a URL was refused.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SsrfError {
+    UnsupportedScheme(String),
+    MissingHost,
+    HostNotAllowed(String),
+    InternalAddress { host: String, ip: IpAddr },
+    Unresolvable { host: String, reason: String },
+}
+
+impl std::fmt::Display for SsrfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SsrfError::UnsupportedScheme(s) => write!(f, "URL scheme '{}' is not allowed (use http or https)", s),
+            SsrfError::MissingHost => write!(f, "URL has no host"),
+            SsrfError::HostNotAllowed(h) => write!(f, "Host {} is not in HTTP_ALLOWED_HOSTS", h),
+            SsrfError::InternalAddress { host, ip } => write!(
+                f,
+                "Host {} resolves to internal address {}; set HTTP_ALLOW_PRIVATE_NETWORKS to allow it",
+                host, ip
+            ),
+            SsrfError::Unresolvable { host, reason } => write!(f, "Could not resolve host {}: {}", host, reason),
+        }
+    }
+}
+
+impl std::error::Error for SsrfError {}
+
+impl SsrfPolicy {
+    pub fn from_env() -> Self {
+        let allow_private = std::env::var("HTTP_ALLOW_PRIVATE_NETWORKS")
+            .map(|v| v == "1" || v == "true")
+            .unwrap_or(false);
+        let allowed_hosts = std::env::var("HTTP_ALLOWED_HOSTS")
+            .map(|v| parse_hosts(&v))
+            .unwrap_or_default();
+        Self { allow_private, allowed_hosts }
+    }
+
+    /// Whether `host` passes the allowlist (always true without one).
+    pub fn host_allowed(&self, host: &str) -> bool {
+        self.allowed_hosts.is_empty() || self.allowed_hosts.iter().any(|pattern| host_matches(pattern, host))
+    }
+
+    /// Allowlisted hosts are explicitly trusted, so only the rest are range-checked.
+    fn checks_addresses(&self, host: &str) -> bool {
+        !self.allow_private && (self.allowed_hosts.is_empty() || !self.host_allowed(host))
+    }
+
+    /// Check the parts of a URL that need no DNS lookup: scheme, allowlist, and
+    /// literal IP hosts.
+    pub fn check_static(&self, url: &Url) -> Result<(), SsrfError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(SsrfError::UnsupportedScheme(url.scheme().to_string()));
+        }
+        let host = bare_host(url)?;
+        if !self.host_allowed(&host) {
+            return Err(SsrfError::HostNotAllowed(host));
+        }
+        if let Ok(ip) = host.parse::<IpAddr>()
+            && self.checks_addresses(&host)
+            && is_internal(ip)
+        {
+            return Err(SsrfError::InternalAddress { host, ip });
+        }
+        Ok(())
+    }
+
+    /// Resolve a host reached without a URL (e.g. an SMTP server) and return
+    /// the addresses to connect to, refusing it like `check_url` would.
+    pub async fn resolve_host(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, SsrfError> {
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        if host.is_empty() {
+            return Err(SsrfError::MissingHost);
+        }
+        if !self.host_allowed(&host) {
+            return Err(SsrfError::HostNotAllowed(host));
+        }
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|e| SsrfError::Unresolvable { host: host.clone(), reason: e.to_string() })?
+            .collect();
+        if self.checks_addresses(&host)
+            && let Some(ip) = addrs.iter().map(|a| a.ip()).find(|ip| is_internal(*ip))
+        {
+            return Err(SsrfError::InternalAddress { host, ip });
+        }
+        Ok(addrs)
+    }
+
+    /// Check a URL before requesting it, resolving the host and checking every
+    /// address it resolves to.
+    pub async fn check_url(&self, url: &Url) -> Result<(), SsrfError> {
+        self.check_static(url)?;
+        let host = bare_host(url)?;
+        if host.parse::<IpAddr>().is_ok() || !self.checks_addresses(&host) {
+            return Ok(());
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let internal = tokio::net::lookup_host((host.as_str(), port))
+            .await
+            .map_err(|e| SsrfError::Unresolvable { host: host.clone(), reason: e.to_string() })?
+            .map(|a| a.ip())
+            .find(|ip| is_internal(*ip));
+        match internal {
+            Some(ip) => Err(SsrfError::InternalAddress { host, ip }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Result body for a node whose destination was refused.
+pub fn blocked_body(e: &SsrfError) -> serde_json::Value {
+    serde_json::json!({ "error": format!("Request blocked: {}", e), "ssrf_blocked": true })
+}
+
+/// Lowercased host without the brackets around IPv6 literals.
+fn bare_host(url: &Url) -> Result<String, SsrfError> {
+    let host = url.host_str().ok_or(SsrfError::MissingHost)?;
+    Ok(host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase())
+}
+
+fn parse_hosts(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|h| h.trim().to_ascii_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect()
+}
+
+/// `*.example.com` matches subdomains of example.com (not example.com itself);
+/// anything else is an exact, case-insensitive match.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host.len() > suffix.len() && host.ends_with(suffix) && host[..host.len() - suffix.len()].ends_with('.'),
+        None => pattern == host,
+    }
+}
+
+/// Loopback, private, link-local, shared (CGNAT), unspecified or broadcast.
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_internal_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_internal_v4(v4),
+            None => is_internal_v6(v6),
+        },
+    }
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || (first & 0xfe00) == 0xfc00 // unique local fc00::/7
+        || (first & 0xffc0) == 0xfe80 // link-local fe80::/10
+}
+
+/// DNS resolver that drops internal addresses for hosts the policy range-checks.
+struct GuardedResolver {
+    policy: Arc<SsrfPolicy>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        let host = name.as_str().to_ascii_lowercase();
+        Box::pin(async move {
+            let resolved: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if !policy.checks_addresses(&host) {
+                return Ok(Box::new(resolved.into_iter()) as Addrs);
+            }
+            let allowed: Vec<SocketAddr> = resolved.iter().copied().filter(|a| !is_internal(a.ip())).collect();
+            if allowed.is_empty() {
+                let ip = resolved.first().map(|a| a.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                return Err(Box::new(SsrfError::InternalAddress { host, ip }) as Box<dyn std::error::Error + Send + Sync>);
+            }
+            Ok(Box::new(allowed.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Apply the policy to a client builder: addresses are filtered at connect time
+/// and redirect targets are checked before they're followed.
+pub fn guard(builder: reqwest::ClientBuilder, policy: Arc<SsrfPolicy>) -> reqwest::ClientBuilder {
+    let redirect_policy = policy.clone();
+    builder
+        .dns_resolver(Arc::new(GuardedResolver { policy }))
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match redirect_policy.check_static(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_internal_ranges() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254",
+            "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "172.32.0.1", "100.128.0.1", "2606:4700::1111"] {
+            assert!(!is_internal(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_internal_literals_are_rejected_unless_allowed() {
+        let policy = SsrfPolicy::default();
+        let err = policy.check_url(&url("http://169.254.169.254/latest/meta-data")).await.unwrap_err();
+        assert!(matches!(err, SsrfError::InternalAddress { .. }));
+        assert!(policy.check_url(&url("http://[::1]:8080/")).await.is_err());
+        assert!(policy.check_url(&url("http://8.8.8.8/")).await.is_ok());
+        assert!(matches!(
+            policy.check_url(&url("file:///etc/passwd")).await,
+            Err(SsrfError::UnsupportedScheme(_))
+        ));
+
+        let permissive = SsrfPolicy { allow_private: true, ..Default::default() };
+        assert!(permissive.check_url(&url("http://127.0.0.1:3000/")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_host_applies_the_same_policy() {
+        let policy = SsrfPolicy::default();
+        assert!(matches!(
+            policy.resolve_host("127.0.0.1", 25).await,
+            Err(SsrfError::InternalAddress { .. })
+        ));
+        assert!(matches!(policy.resolve_host("[::1]", 25).await, Err(SsrfError::InternalAddress { .. })));
+        assert_eq!(policy.resolve_host("8.8.8.8", 587).await.unwrap(), vec!["8.8.8.8:587".parse().unwrap()]);
+
+        let allowlisted = SsrfPolicy { allow_private: false, allowed_hosts: parse_hosts("127.0.0.1") };
+        assert!(allowlisted.resolve_host("127.0.0.1", 25).await.is_ok());
+        assert!(matches!(allowlisted.resolve_host("10.0.0.1", 25).await, Err(SsrfError::HostNotAllowed(_))));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let policy = SsrfPolicy { allow_private: false, allowed_hosts: parse_hosts("api.example.com, *.internal.corp") };
+        assert!(policy.host_allowed("API.example.com"));
+        assert!(policy.host_allowed("svc.internal.corp"));
+        assert!(!policy.host_allowed("internal.corp"));
+        assert!(!policy.host_allowed("evilinternal.corp"));
+        assert!(matches!(
+            policy.check_static(&url("https://other.com/")),
+            Err(SsrfError::HostNotAllowed(_))
+        ));
+        // Allowlisted hosts are trusted wherever they point
+        assert!(!policy.checks_addresses("svc.internal.corp"));
+    }
+}
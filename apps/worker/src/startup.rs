@@ -0,0 +1,108 @@
+//! Waiting for dependencies at startup.
+//!
+//! In orchestrated environments the worker often starts before Postgres or
+//! Redis accept connections. Instead of exiting (and crash-looping), startup
+//! retries each connection with exponential backoff, logging every attempt,
+//! until `STARTUP_MAX_WAIT_MS` has passed; then the last error is returned
+//! and the process exits non-zero.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Default time to wait for a dependency before giving up (1 minute)
+const DEFAULT_STARTUP_MAX_WAIT_MS: u64 = 60_000;
+
+/// Delay after the first failed attempt; doubles per attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest delay between attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// How long to keep retrying a dependency (`STARTUP_MAX_WAIT_MS`, 0 = try once)
+pub fn startup_max_wait() -> Duration {
+    let ms = std::env::var("STARTUP_MAX_WAIT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_STARTUP_MAX_WAIT_MS);
+    Duration::from_millis(ms)
+}
+
+/// Delay after failed attempt `attempt` (1-based).
+fn retry_delay(attempt: u32) -> Duration {
+    INITIAL_RETRY_DELAY
+        .saturating_mul(1 << attempt.saturating_sub(1).min(5))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Call `connect` until it succeeds or the next attempt would start after
+/// `max_wait`, then return its last error.
+pub async fn wait_for<T, E, F, Fut>(name: &str, max_wait: Duration, mut connect: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match connect().await {
+            Ok(connected) => {
+                if attempt > 1 {
+                    tracing::info!("{} is up after {} attempts", name, attempt);
+                }
+                return Ok(connected);
+            }
+            Err(e) => {
+                let delay = retry_delay(attempt);
+                if started.elapsed() + delay > max_wait {
+                    tracing::error!(
+                        "{} still unavailable after {} attempts over {:?}, giving up: {}",
+                        name,
+                        attempt,
+                        started.elapsed(),
+                        e
+                    );
+                    return Err(e);
+                }
+                tracing::warn!("{} unavailable (attempt {}): {}; retrying in {:?}", name, attempt, e, delay);
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay_doubles_up_to_cap() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(1));
+        assert_eq!(retry_delay(4), Duration::from_secs(4));
+        assert_eq!(retry_delay(6), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_retries_then_gives_up() {
+        let mut calls = 0;
+        let connected = wait_for("db", Duration::from_secs(5), || {
+            calls += 1;
+            let result = if calls < 2 { Err("refused") } else { Ok(calls) };
+            async move { result }
+        })
+        .await;
+        assert_eq!(connected, Ok(2));
+
+        let mut calls = 0;
+        let failed: Result<(), _> = wait_for("redis", Duration::ZERO, || {
+            calls += 1;
+            async { Err("refused") }
+        })
+        .await;
+        assert_eq!((failed, calls), (Err("refused"), 1));
+    }
+}
@@ -3,6 +3,7 @@
 //! Provides `StreamContext` for sending progress updates, tokens, and other
 //! streaming data from node execution to the frontend via SSE.
 
+use crate::events::{log_event, EventType};
 use redis::{AsyncCommands, RedisResult};
 use sqlx::PgPool;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -13,6 +14,17 @@ use uuid::Uuid;
 /// Redis stream name for real-time chunks
 pub const STREAM_CHUNKS: &str = "swiftgrid_chunks";
 
+/// Destination for progress messages.
+///
+/// Implemented by `StreamContext`; lets spawn paths report progress without
+/// depending on Redis/PostgreSQL directly.
+pub trait ProgressSink {
+    fn progress(&self, message: &str) -> impl std::future::Future<Output = ()> + Send;
+
+    /// A durable NODE_PROGRESS milestone (see `StreamContext::milestone`).
+    fn milestone(&self, payload: serde_json::Value) -> impl std::future::Future<Output = ()> + Send;
+}
+
 /// Context for streaming output during node execution.
 ///
 /// Sends chunks to both Redis (for real-time SSE) and PostgreSQL (for replay).
@@ -40,7 +52,12 @@ impl StreamContext {
     /// Send a streaming chunk to both Redis (real-time) and PostgreSQL (persistence).
     pub async fn send_chunk(&self, chunk_type: &str, content: &str) {
         let index = self.chunk_index.fetch_add(1, Ordering::SeqCst);
+        self.send_chunk_at(index, chunk_type, content).await;
+    }
 
+    /// Send a chunk with a caller-chosen index, for chunks sent from separate
+    /// jobs of the same node (e.g. map item results) that need stable indexes.
+    pub async fn send_chunk_at(&self, index: usize, chunk_type: &str, content: &str) {
         // 1. Publish to Redis for real-time SSE
         let chunk_payload = serde_json::json!({
             "run_id": self.run_id.to_string(),
@@ -74,7 +91,7 @@ impl StreamContext {
             VALUES ($1, $2, $3, $4, $5)
             "#,
         )
-        .bind(&self.run_id)
+        .bind(self.run_id)
         .bind(&self.node_id)
         .bind(index as i32)
         .bind(chunk_type)
@@ -108,4 +125,22 @@ impl StreamContext {
     pub async fn token(&self, token: &str) {
         self.send_chunk("token", token).await;
     }
+
+    /// Log a durable NODE_PROGRESS milestone to `run_events`.
+    ///
+    /// Unlike chunks, these survive stream trimming and show up when replaying
+    /// a run's timeline. Use sparingly (milestones, not every token).
+    pub async fn milestone(&self, payload: serde_json::Value) {
+        let _ = log_event(&self.pool, &self.run_id, &self.node_id, EventType::NodeProgress, payload).await;
+    }
+}
+
+impl ProgressSink for StreamContext {
+    async fn progress(&self, message: &str) {
+        StreamContext::progress(self, message).await;
+    }
+
+    async fn milestone(&self, payload: serde_json::Value) {
+        StreamContext::milestone(self, payload).await;
+    }
 }
@@ -0,0 +1,232 @@
+//! `{{...}}` template resolution for node data.
+//!
+//! Mirrors the web orchestrator's `buildJobFromNode` interpolation so jobs the
+//! worker builds itself (e.g. node reruns) see the same values:
+//! - `{{nodeId}}` / `{{nodeId.field.nested}}`: a recorded node output
+//!   (also accepted as `{{nodes.nodeId.field}}`)
+//! - `{{$input.field}}` / `{{$trigger.field}}`: the run's input data
+//!
+//! Strings are inserted as-is, other values as JSON. Unknown references are
+//! left untouched, including `{{$env.*}}`: the web app fills those from its
+//! secrets, and `resolve_env` fills the rest from the job's `run_env` or the
+//! worker's environment for credential fields (see `nodes::resolve_env_secrets`).
+
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::types::RunEnv;
+
+/// Values a template can reference.
+#[derive(Debug)]
+pub struct TemplateContext<'a> {
+    /// Recorded node outputs, keyed by node ID
+    pub node_outputs: &'a HashMap<String, Value>,
+    /// The run's input data
+    pub input: Option<&'a Value>,
+}
+
+impl TemplateContext<'_> {
+    fn lookup(&self, path: &str) -> Option<&Value> {
+        if let Some(field_path) = path.strip_prefix("$input.").or_else(|| path.strip_prefix("$trigger.")) {
+            return get_path(self.input?, field_path);
+        }
+        if path.starts_with('$') {
+            return None;
+        }
+        let path = match path.strip_prefix("nodes.") {
+            Some(rest) if !self.node_outputs.contains_key("nodes") => rest,
+            _ => path,
+        };
+        match path.split_once('.') {
+            Some((node_id, field_path)) => get_path(self.node_outputs.get(node_id)?, field_path),
+            None => self.node_outputs.get(path),
+        }
+    }
+}
+
+fn get_path<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+    path.split('.').try_fold(value, |v, part| v.as_object()?.get(part))
+}
+
+/// Resolve every `{{...}}` in a string.
+pub fn resolve_str(s: &str, ctx: &TemplateContext) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + 2 + len + 2];
+        out.push_str(&rest[..start]);
+
+        match ctx.lookup(rest[start + 2..start + 2 + len].trim()) {
+            Some(Value::String(v)) => out.push_str(v),
+            Some(v) => out.push_str(&v.to_string()),
+            None => out.push_str(placeholder),
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Resolve templates in every string inside a JSON value.
+pub fn resolve_value(value: &Value, ctx: &TemplateContext) -> Value {
+    match value {
+        Value::String(s) => Value::String(resolve_str(s, ctx)),
+        Value::Array(items) => Value::Array(items.iter().map(|v| resolve_value(v, ctx)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), resolve_value(v, ctx))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Like `resolve_value`, but a string that is exactly one placeholder takes the
+/// referenced value with its JSON type (so `"{{fetch.body}}"` becomes an object).
+pub fn resolve_input(value: &Value, ctx: &TemplateContext) -> Value {
+    match value {
+        Value::String(s) => {
+            let whole = s.trim().strip_prefix("{{").and_then(|r| r.strip_suffix("}}"));
+            match whole.filter(|inner| !inner.contains("{{")).and_then(|inner| ctx.lookup(inner.trim())) {
+                Some(v) => v.clone(),
+                None => Value::String(resolve_str(s, ctx)),
+            }
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|v| resolve_input(v, ctx)).collect()),
+        Value::Object(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), resolve_input(v, ctx))).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Resolve every `{{$env.NAME}}` in a string from the worker's environment.
+/// Other placeholders are left as written; an unset variable is an error.
+pub fn resolve_env(s: &str) -> Result<String, String> {
+    resolve_run_env(s, None)
+}
+
+/// `resolve_env`, but a run's own values win over the worker's environment.
+pub fn resolve_run_env(s: &str, run_env: Option<&RunEnv>) -> Result<String, String> {
+    resolve_env_with(s, |name| match run_env.and_then(|env| env.get(name)) {
+        Some(value) => Some(value.to_string()),
+        None => std::env::var(name).ok(),
+    })
+}
+
+fn resolve_env_with(s: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + 2 + len + 2];
+        out.push_str(&rest[..start]);
+
+        match rest[start + 2..start + 2 + len].trim().strip_prefix("$env.") {
+            Some(name) => out.push_str(&lookup(name).ok_or_else(|| format!("Environment variable {} is not set", name))?),
+            None => out.push_str(placeholder),
+        }
+        rest = &rest[start + placeholder.len()..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Whether any string inside `value` contains a placeholder.
+pub fn has_templates(value: &Value) -> bool {
+    match value {
+        Value::String(s) => s.contains("{{"),
+        Value::Array(items) => items.iter().any(has_templates),
+        Value::Object(map) => map.values().any(has_templates),
+        _ => false,
+    }
+}
+
+/// Latest recorded output of every completed node in a run.
+pub async fn recorded_outputs(pool: &PgPool, run_id: &Uuid) -> Result<HashMap<String, Value>, sqlx::Error> {
+    let rows: Vec<(String, Option<Value>)> = sqlx::query_as(
+        r#"
+        SELECT node_id, payload FROM run_events
+        WHERE run_id = $1 AND event_type = 'NODE_COMPLETED' AND node_id IS NOT NULL
+        ORDER BY id
+        "#,
+    )
+    .bind(run_id)
+    .fetch_all(pool)
+    .await?;
+
+    // Later events win (e.g. a node that completed again after a resume)
+    Ok(rows
+        .into_iter()
+        .map(|(node_id, payload)| {
+            let result = payload.and_then(|p| p.get("result").cloned()).unwrap_or(Value::Null);
+            (node_id, result)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolves_outputs_and_input() {
+        let outputs = HashMap::from([("fetch".to_string(), json!({"body": {"id": 7, "tags": ["a"]}}))]);
+        let input = json!({"user": "ada"});
+        let ctx = TemplateContext { node_outputs: &outputs, input: Some(&input) };
+
+        assert_eq!(resolve_str("id={{ fetch.body.id }}", &ctx), "id=7");
+        assert_eq!(resolve_str("{{fetch.body.tags}}", &ctx), "[\"a\"]");
+        assert_eq!(resolve_str("hi {{$input.user}} / {{$trigger.user}}", &ctx), "hi ada / ada");
+
+        // Unknown references stay as written
+        assert_eq!(resolve_str("{{missing.x}} {{$env.KEY}} {{open", &ctx), "{{missing.x}} {{$env.KEY}} {{open");
+
+        let resolved = resolve_value(&json!({"url": "/u/{{fetch.body.id}}", "n": 1}), &ctx);
+        assert_eq!(resolved, json!({"url": "/u/7", "n": 1}));
+    }
+
+    #[test]
+    fn test_run_env_wins_over_process_env() {
+        let path = std::env::var("PATH").unwrap();
+        let run_env = RunEnv(HashMap::from([("TENANT_KEY".to_string(), "tenant-a".to_string())]));
+        assert_eq!(resolve_run_env("{{$env.TENANT_KEY}}", Some(&run_env)).unwrap(), "tenant-a");
+        assert_eq!(resolve_run_env("{{$env.PATH}}", Some(&run_env)).unwrap(), path);
+        assert!(resolve_run_env("{{$env.TENANT_KEY}}", None).is_err());
+
+        let overridden = RunEnv(HashMap::from([("PATH".to_string(), "/tenant/bin".to_string())]));
+        assert_eq!(resolve_run_env("{{$env.PATH}}", Some(&overridden)).unwrap(), "/tenant/bin");
+        assert!(!format!("{:?}", overridden).contains("/tenant/bin"));
+    }
+
+    #[test]
+    fn test_resolve_env() {
+        let lookup = |name: &str| (name == "API_TOKEN").then(|| "s3cret".to_string());
+        assert_eq!(resolve_env_with("Bearer {{ $env.API_TOKEN }}", lookup).unwrap(), "Bearer s3cret");
+        assert_eq!(resolve_env_with("{{fetch.id}} plain", lookup).unwrap(), "{{fetch.id}} plain");
+        assert_eq!(
+            resolve_env_with("{{$env.MISSING}}", lookup).unwrap_err(),
+            "Environment variable MISSING is not set"
+        );
+    }
+
+    #[test]
+    fn test_resolve_input_keeps_types_of_whole_placeholders() {
+        let outputs = HashMap::from([("fetch".to_string(), json!({"data": [1, 2]}))]);
+        let ctx = TemplateContext { node_outputs: &outputs, input: None };
+
+        let input = json!({"items": "{{nodes.fetch.data}}", "label": "n={{fetch.data}}", "raw": "{{other}}"});
+        assert!(has_templates(&input));
+        assert_eq!(
+            resolve_input(&input, &ctx),
+            json!({"items": [1, 2], "label": "n=[1,2]", "raw": "{{other}}"})
+        );
+        assert!(!has_templates(&json!({"n": 1, "s": ["plain"]})));
+    }
+}
@@ -0,0 +1,214 @@
+//! Per-provider tokens-per-minute (TPM) budgets for LLM nodes.
+//!
+//! Providers rate-limit on tokens as well as requests, so a map of LLM calls
+//! can trip 429s even with modest concurrency. Each provider/model gets a
+//! sliding one-minute window: a request reserves its estimated tokens before
+//! the call (waiting if the window is full), and the reservation is corrected
+//! to the actual usage afterwards.
+//!
+//! Limits come from `LLM_TPM_LIMITS`, a comma-separated list of `key=tpm`
+//! where the key is `host/model` or just `host` (all models on that provider),
+//! e.g. `LLM_TPM_LIMITS="api.openai.com/gpt-4o=30000,api.groq.com=6000"`.
+//! Providers without a limit are not tracked.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+/// Length of the rate-limit window
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Shared budget windows, keyed by `host/model`.
+pub type TokenBudgets = Arc<Mutex<HashMap<String, TokenWindow>>>;
+
+/// Create an empty budget registry.
+pub fn new_registry() -> TokenBudgets {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+/// Configured TPM limits.
+#[derive(Clone, Debug)]
+pub struct BudgetConfig {
+    limits: HashMap<String, u64>,
+    pub window: Duration,
+}
+
+impl BudgetConfig {
+    /// Load from `LLM_TPM_LIMITS` (empty = no budgeting).
+    pub fn from_env() -> Self {
+        Self::parse(&std::env::var("LLM_TPM_LIMITS").unwrap_or_default())
+    }
+
+    /// Parse `key=tpm,...`; malformed entries are ignored.
+    pub fn parse(spec: &str) -> Self {
+        let limits = spec
+            .split(',')
+            .filter_map(|entry| {
+                let (key, tpm) = entry.split_once('=')?;
+                Some((key.trim().to_string(), tpm.trim().parse().ok()?))
+            })
+            .collect();
+        Self { limits, window: WINDOW }
+    }
+
+    /// Limit for a model on a provider: exact `host/model` first, then `host`.
+    pub fn limit_for(&self, host: &str, model: &str) -> Option<u64> {
+        self.limits
+            .get(&format!("{}/{}", host, model))
+            .or_else(|| self.limits.get(host))
+            .copied()
+    }
+}
+
+/// Tokens used in the current window, one entry per request.
+#[derive(Debug, Default)]
+pub struct TokenWindow {
+    entries: VecDeque<(Instant, u64, u64)>, // (reserved_at, id, tokens)
+    next_id: u64,
+}
+
+impl TokenWindow {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while let Some(&(at, _, _)) = self.entries.front() {
+            if now.duration_since(at) < window {
+                break;
+            }
+            self.entries.pop_front();
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.entries.iter().map(|&(_, _, tokens)| tokens).sum()
+    }
+
+    /// Reserve `tokens` if they fit in the window. Otherwise returns how long
+    /// until enough earlier usage ages out.
+    ///
+    /// A request larger than the whole limit is let through once the window
+    /// is empty, so it can't wait forever.
+    pub fn try_reserve(&mut self, tokens: u64, limit: u64, now: Instant, window: Duration) -> Result<u64, Duration> {
+        self.prune(now, window);
+
+        let used = self.used();
+        if used + tokens <= limit || self.entries.is_empty() {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.entries.push_back((now, id, tokens));
+            return Ok(id);
+        }
+
+        let mut remaining = used;
+        for &(at, _, entry_tokens) in &self.entries {
+            remaining -= entry_tokens;
+            if remaining + tokens <= limit || remaining == 0 {
+                return Err((at + window).saturating_duration_since(now));
+            }
+        }
+        unreachable!("window drains to empty")
+    }
+
+    /// Replace a reservation's estimate with the actual usage.
+    pub fn record(&mut self, id: u64, actual: u64) {
+        if let Some(entry) = self.entries.iter_mut().find(|(_, entry_id, _)| *entry_id == id) {
+            entry.2 = actual;
+        }
+    }
+}
+
+/// A reservation held while an LLM request is in flight.
+#[derive(Debug)]
+pub struct Reservation {
+    key: String,
+    id: u64,
+}
+
+/// Reserve `tokens` against `key`, waiting until the window has room.
+/// Returns None if cancelled while waiting.
+pub async fn acquire(
+    budgets: &TokenBudgets,
+    key: &str,
+    limit: u64,
+    tokens: u64,
+    window: Duration,
+    cancel_token: &CancellationToken,
+) -> Option<Reservation> {
+    loop {
+        let wait = {
+            let mut budgets = budgets.lock().await;
+            match budgets.entry(key.to_string()).or_default().try_reserve(tokens, limit, Instant::now(), window) {
+                Ok(id) => return Some(Reservation { key: key.to_string(), id }),
+                Err(wait) => wait,
+            }
+        };
+
+        tracing::info!("LLM: TPM budget for {} exhausted, waiting {}ms", key, wait.as_millis());
+        tokio::select! {
+            _ = cancel_token.cancelled() => return None,
+            // Re-check rather than assume: other requests may have settled lower
+            _ = tokio::time::sleep(wait.max(Duration::from_millis(10))) => {}
+        }
+    }
+}
+
+/// Correct a reservation to the tokens the provider actually reported.
+pub async fn settle(budgets: &TokenBudgets, reservation: Reservation, actual: u64) {
+    if let Some(window) = budgets.lock().await.get_mut(&reservation.key) {
+        window.record(reservation.id, actual);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let config = BudgetConfig::parse("api.openai.com/gpt-4o=30000, api.groq.com=6000, bad");
+        assert_eq!(config.limit_for("api.openai.com", "gpt-4o"), Some(30000));
+        assert_eq!(config.limit_for("api.openai.com", "gpt-4o-mini"), None);
+        assert_eq!(config.limit_for("api.groq.com", "llama3"), Some(6000));
+    }
+
+    #[test]
+    fn test_window_exhausts_and_refills() {
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut w = TokenWindow::default();
+
+        let first = w.try_reserve(600, 1000, start, window).unwrap();
+        assert!(w.try_reserve(300, 1000, start, window).is_ok());
+
+        // Full: must wait until the first reservation ages out
+        let wait = w.try_reserve(300, 1000, start + Duration::from_secs(10), window).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(50));
+
+        // Actual usage was lower than estimated, freeing room
+        w.record(first, 200);
+        assert!(w.try_reserve(300, 1000, start + Duration::from_secs(10), window).is_ok());
+
+        // Everything ages out after a minute
+        assert!(w.try_reserve(1000, 1000, start + Duration::from_secs(71), window).is_ok());
+
+        // Oversized requests still run once the window is empty
+        assert!(w.try_reserve(5000, 1000, start + Duration::from_secs(200), window).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_delays_until_window_refills() {
+        let budgets = new_registry();
+        let window = Duration::from_millis(200);
+        let cancel = CancellationToken::new();
+
+        acquire(&budgets, "host/model", 100, 80, window, &cancel).await.unwrap();
+
+        let start = Instant::now();
+        acquire(&budgets, "host/model", 100, 80, window, &cancel).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150), "waited {:?}", start.elapsed());
+
+        // Cancellation aborts the wait
+        cancel.cancel();
+        assert!(acquire(&budgets, "host/model", 100, 80, window, &cancel).await.is_none());
+    }
+}
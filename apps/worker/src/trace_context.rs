@@ -0,0 +1,135 @@
+//! W3C Trace Context propagation through the job pipeline.
+//!
+//! A job may carry the `traceparent`/`tracestate` of whatever enqueued it in
+//! `WorkerJob.trace_context`. `process_job` starts a span as its child (or a
+//! fresh root when the job has none or it's malformed) and HTTP nodes send
+//! that span's `traceparent` downstream, so one trace covers
+//! API → Redis → worker → downstream service. The worker doesn't export spans
+//! itself; the ids are recorded on NODE_STARTED so they can be correlated.
+
+use std::collections::HashMap;
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+/// The span a job executes in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    /// 32 lowercase hex chars
+    pub trace_id: String,
+    /// 16 lowercase hex chars
+    pub span_id: String,
+    /// Span id from the incoming `traceparent` (None for a root span)
+    pub parent_span_id: Option<String>,
+    pub sampled: bool,
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Start a span: a child of the carrier's `traceparent` if it is valid,
+    /// otherwise a new (sampled) root.
+    pub fn start(carrier: Option<&HashMap<String, String>>) -> Self {
+        let parent = carrier.and_then(|c| c.get(TRACEPARENT)).and_then(|tp| parse_traceparent(tp));
+
+        match parent {
+            Some((trace_id, parent_span_id, flags)) => Self {
+                trace_id,
+                span_id: new_span_id(),
+                parent_span_id: Some(parent_span_id),
+                sampled: flags & 0x01 == 0x01,
+                tracestate: carrier.and_then(|c| c.get(TRACESTATE)).cloned(),
+            },
+            None => Self {
+                trace_id: format!("{:032x}", rand::random::<u128>().max(1)),
+                span_id: new_span_id(),
+                parent_span_id: None,
+                sampled: true,
+                tracestate: None,
+            },
+        }
+    }
+
+    /// `traceparent` naming this span as the parent of downstream work.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, u8::from(self.sampled))
+    }
+
+    /// Headers to send on outgoing requests.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(TRACEPARENT, self.traceparent())];
+        if let Some(state) = &self.tracestate {
+            headers.push((TRACESTATE, state.clone()));
+        }
+        headers
+    }
+}
+
+fn new_span_id() -> String {
+    format!("{:016x}", rand::random::<u64>().max(1))
+}
+
+/// `version-traceid-parentid-flags`, returning (trace_id, parent_id, flags).
+/// All-zero ids and the reserved version `ff` are invalid.
+fn parse_traceparent(value: &str) -> Option<(String, String, u8)> {
+    let parts: Vec<&str> = value.trim().split('-').collect();
+    let [version, trace_id, parent_id, flags, ..] = parts.as_slice() else {
+        return None;
+    };
+    let is_hex = |s: &str, len: usize| s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase());
+    let non_zero = |s: &str| s.chars().any(|c| c != '0');
+
+    // Version 00 has exactly four fields; later versions may append more
+    if !is_hex(version, 2) || *version == "ff" || (*version == "00" && parts.len() != 4) {
+        return None;
+    }
+    if !is_hex(trace_id, 32) || !non_zero(trace_id) || !is_hex(parent_id, 16) || !non_zero(parent_id) || !is_hex(flags, 2) {
+        return None;
+    }
+    Some((trace_id.to_string(), parent_id.to_string(), u8::from_str_radix(flags, 16).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn carrier(traceparent: &str) -> HashMap<String, String> {
+        HashMap::from([
+            (TRACEPARENT.to_string(), traceparent.to_string()),
+            (TRACESTATE.to_string(), "vendor=abc".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_child_span_continues_the_incoming_trace() {
+        let incoming = carrier("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        let span = TraceContext::start(Some(&incoming));
+
+        assert_eq!(span.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(span.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_ne!(span.span_id, "00f067aa0ba902b7");
+        assert!(span.sampled);
+
+        let headers = span.headers();
+        assert_eq!(headers[0].1, format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{}-01", span.span_id));
+        assert_eq!(headers[1], (TRACESTATE, "vendor=abc".to_string()));
+    }
+
+    #[test]
+    fn test_missing_or_invalid_traceparent_starts_a_root_span() {
+        for bad in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "garbage",
+        ] {
+            let span = TraceContext::start(Some(&carrier(bad)));
+            assert!(span.parent_span_id.is_none(), "{}", bad);
+            assert!(span.tracestate.is_none(), "tracestate is dropped with an invalid parent");
+        }
+
+        let root = TraceContext::start(None);
+        assert!(parse_traceparent(&root.traceparent()).is_some());
+        assert_eq!(root.trace_id.len(), 32);
+    }
+}
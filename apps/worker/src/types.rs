@@ -25,6 +25,85 @@ pub enum HttpMethod {
     PATCH,
 }
 
+/// How the HTTP node serializes its body.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpBodyEncoding {
+    /// `application/json` (default)
+    #[default]
+    Json,
+    /// `application/x-www-form-urlencoded` from a flat JSON object
+    Form,
+    /// `multipart/form-data` from a JSON object (see `nodes::http`)
+    Multipart,
+    /// String body sent as-is with `content_type`
+    Raw,
+}
+
+/// Where the HTTP node streams its request body from, instead of `body`.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum BodySource {
+    /// A Redis string key, read in slices
+    RedisKey(String),
+    /// The `data`/`token` chunks an earlier node of the run streamed, in order
+    NodeStream(String),
+}
+
+/// Credentials for the HTTP node. Values may be `{{$env.NAME}}` references,
+/// resolved by the worker so secrets stay out of the graph.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum HttpAuth {
+    /// `Authorization: Basic base64(username:password)`
+    Basic { username: String, password: String },
+    /// `Authorization: Bearer <token>`
+    Bearer { token: String },
+    /// The key sent in a header of its choosing (e.g. `X-API-Key`)
+    ApiKey { header: String, value: String },
+}
+
+/// Request body compression for the HTTP node.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Deflate,
+}
+
+/// Where an HTTP node finds the next page.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum PageCursor {
+    /// Dotted path (e.g. `meta.next_cursor`) to a cursor sent back as query parameter `param`
+    Cursor { path: String, param: String },
+    /// Dotted path to the next page's URL
+    NextUrl { path: String },
+    /// The `Link` response header's `rel="next"` URL
+    LinkHeader,
+}
+
+fn default_max_pages() -> u32 {
+    10
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaginateConfig {
+    pub next: PageCursor,
+    /// Dotted path to each page's items array (default: the body itself)
+    #[serde(default)]
+    pub items_path: Option<String>,
+    /// Stop after this many pages (default: 10)
+    #[serde(default = "default_max_pages")]
+    pub max_pages: u32,
+}
+
 #[typeshare]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct HttpNodeData {
@@ -32,9 +111,101 @@ pub struct HttpNodeData {
     pub method: HttpMethod,
     #[serde(default)]
     pub headers: Option<HashMap<String, String>>,
+    /// Query parameters, URL-encoded and appended to any already in `url`
+    #[serde(default)]
+    pub query: Option<HashMap<String, String>>,
     #[typeshare(serialized_as = "any")]
     #[serde(default)]
     pub body: Option<serde_json::Value>,
+    /// Body encoding (default: json)
+    #[serde(default)]
+    pub body_encoding: Option<HttpBodyEncoding>,
+    /// Stream the body from here rather than inlining it (see `nodes::http_upload`)
+    #[serde(default)]
+    pub body_source: Option<BodySource>,
+    /// Content-Type override (used for raw bodies, default: "text/plain")
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Forward the response chunk-by-chunk instead of buffering it (body becomes a summary)
+    #[serde(default)]
+    pub stream_body: bool,
+    /// Compress the request body (skipped below HTTP_COMPRESS_MIN_BYTES, and
+    /// for streamed multipart / `body_source` bodies, with a warning)
+    #[serde(default)]
+    pub compress: Option<Compression>,
+    /// JSON Schema that successful responses must satisfy (mismatch = 422)
+    #[typeshare(serialized_as = "any")]
+    #[serde(default)]
+    pub response_schema: Option<serde_json::Value>,
+    /// Largest buffered response body in bytes (default: HTTP_MAX_RESPONSE_BYTES, over = 413)
+    #[serde(default)]
+    pub max_response_bytes: Option<u64>,
+    /// In-node retries for connection failures, separate from the job's
+    /// `max_retries` (default: HTTP_CONNECT_RETRIES)
+    #[serde(default)]
+    pub connect_retries: Option<u32>,
+    /// Credentials; replaces any header of the same name in `headers`
+    #[serde(default)]
+    pub auth: Option<HttpAuth>,
+    /// Follow pages and return their combined items
+    #[serde(default)]
+    pub paginate: Option<PaginateConfig>,
+    /// GET only: answer from a Redis cache for this long after a successful
+    /// response (skipped when the response is `Cache-Control: no-store`)
+    #[typeshare(serialized_as = "number")]
+    #[serde(default)]
+    pub cache_ttl_ms: Option<u64>,
+}
+
+// =============================================================================
+// WEBSOCKET NODE
+// =============================================================================
+
+/// When the WebSocket node stops collecting messages.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "mode", content = "value", rename_all = "snake_case")]
+pub enum CollectMode {
+    /// Stop after this many messages
+    Messages(u32),
+    /// Collect for this many milliseconds
+    Timeout(u32),
+    /// Collect until the server sends a close frame (default)
+    #[default]
+    CloseFrame,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebSocketNodeData {
+    /// `ws://` or `wss://` endpoint
+    pub url: String,
+    /// Text message to send once connected
+    #[serde(default)]
+    pub send: Option<String>,
+    /// When to stop collecting (default: close frame)
+    #[serde(default)]
+    pub collect_until: Option<CollectMode>,
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+}
+
+// =============================================================================
+// DB UPSERT NODE
+// =============================================================================
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbUpsertNodeData {
+    /// Target table (must be in DB_UPSERT_TABLES)
+    pub table: String,
+    /// Rows as JSON objects keyed by column name
+    #[typeshare(serialized_as = "any[]")]
+    #[serde(default)]
+    pub rows: Vec<serde_json::Value>,
+    /// Columns for ON CONFLICT (empty = plain insert)
+    #[serde(default)]
+    pub conflict_columns: Vec<String>,
 }
 
 // =============================================================================
@@ -48,6 +219,10 @@ pub struct CodeNodeData {
     #[typeshare(serialized_as = "any")]
     #[serde(default)]
     pub inputs: Option<serde_json::Value>,
+    /// Per-node JS timeout override (falls back to JS_TIMEOUT_MS)
+    #[typeshare(serialized_as = "number")]
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
 }
 
 // =============================================================================
@@ -57,12 +232,33 @@ pub struct CodeNodeData {
 #[typeshare]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DelayNodeData {
-    /// Delay duration in milliseconds
+    /// Delay duration in milliseconds (ignored when `until` is set)
     #[typeshare(serialized_as = "number")]
+    #[serde(default)]
     pub duration_ms: u64,
     /// Human-readable duration string: "5s", "2m", "1h"
     #[serde(default)]
     pub duration_str: Option<String>,
+    /// Wait until a point in time instead of for a duration
+    #[serde(default)]
+    pub until: Option<UntilSpec>,
+}
+
+/// When a calendar-aware delay ends.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum UntilSpec {
+    /// An absolute time (RFC 3339); one in the past ends the delay at once
+    At { time: String },
+    /// The next occurrence of a cron expression, e.g. `0 9 * * MON-FRI` for the
+    /// next business day at 9am
+    Cron {
+        expression: String,
+        /// IANA timezone the expression is read in (default UTC)
+        #[serde(default)]
+        timezone: Option<String>,
+    },
 }
 
 #[typeshare]
@@ -89,6 +285,12 @@ pub struct WebhookWaitData {
     #[typeshare(serialized_as = "number")]
     #[serde(default = "default_timeout_ms")]
     pub timeout_ms: u64,
+    /// HMAC-SHA256 secret resume calls must sign their body with (may be `{{$env.NAME}}`)
+    #[serde(default)]
+    pub signing_secret: Option<String>,
+    /// Named outcomes (e.g. approve/reject), each with its own resume token
+    #[serde(default)]
+    pub outcomes: Option<Vec<String>>,
 }
 
 #[typeshare]
@@ -97,6 +299,48 @@ pub struct WebhookResumeData {
     pub resume_token: String,
     #[typeshare(serialized_as = "any")]
     pub payload: Option<serde_json::Value>,
+    /// Request body exactly as received (what the signature covers)
+    #[serde(default)]
+    pub raw_body: Option<String>,
+    /// Signature header value, `sha256=<hex>` or bare hex
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+// =============================================================================
+// DB WAIT NODE
+// =============================================================================
+
+fn default_db_wait_poll_ms() -> u64 {
+    30_000
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbWaitNodeData {
+    /// SELECT whose first row decides: "SELECT paid FROM invoices WHERE id = $1"
+    pub query: String,
+    /// Positional parameters for $1, $2, ...
+    #[typeshare(serialized_as = "any[]")]
+    #[serde(default)]
+    pub params: Vec<serde_json::Value>,
+    /// How often the scheduler re-checks (default: 30s, checked on its 10s tick)
+    #[typeshare(serialized_as = "number")]
+    #[serde(default = "default_db_wait_poll_ms")]
+    pub poll_interval_ms: u64,
+    /// Timeout in milliseconds (default: 7 days)
+    #[typeshare(serialized_as = "number")]
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub description: Option<String>,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbWaitResumeData {
+    /// The row that satisfied the condition
+    #[typeshare(serialized_as = "any")]
+    pub row: Option<serde_json::Value>,
 }
 
 // =============================================================================
@@ -130,6 +374,21 @@ pub struct RouterNodeData {
     /// "first_match" or "broadcast"
     #[serde(default = "default_router_mode")]
     pub mode: String,
+    /// Evaluate the conditions in the worker (JS sandbox) instead of leaving them to the orchestrator
+    #[serde(default)]
+    pub evaluate: bool,
+}
+
+/// A boolean gate: follows the "true" or "false" handle.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConditionalNodeData {
+    /// JS expression over `input`, e.g. `input.status === 200`; truthy takes "true"
+    pub expression: String,
+    /// The value the expression sees as `input`; null when absent
+    #[typeshare(serialized_as = "any")]
+    #[serde(default)]
+    pub input: Option<serde_json::Value>,
 }
 
 // =============================================================================
@@ -139,9 +398,42 @@ pub struct RouterNodeData {
 #[typeshare]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LlmMessage {
-    /// "system", "user", or "assistant"
+    /// "system", "user", "assistant", or "tool"
     pub role: String,
     pub content: String,
+    /// Tool calls an assistant message made (OpenAI shape), so a conversation
+    /// can be continued after running them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<serde_json::Value>,
+    /// For "tool" messages: the id of the call this is the result of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Wire format of the LLM provider.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmApiStyle {
+    /// OpenAI-compatible `/chat/completions` (OpenAI, Groq, Together, Ollama, ...)
+    #[default]
+    OpenAi,
+    /// Anthropic `/messages`
+    Anthropic,
+}
+
+/// How an LLM conversation is shortened to fit `max_context_tokens`.
+/// System messages and the latest message are always kept.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmTruncation {
+    /// Drop the oldest messages
+    #[default]
+    DropOldest,
+    /// Replace the oldest messages with one system note quoting the start of
+    /// each (truncated text, not a model-written summary)
+    Summarize,
 }
 
 #[typeshare]
@@ -164,6 +456,27 @@ pub struct LlmNodeData {
     /// Enable streaming (default: false)
     #[serde(default)]
     pub stream: bool,
+    /// Coalesce streamed tokens into batches every N ms (0 = per-token)
+    #[serde(default)]
+    pub token_flush_ms: Option<u32>,
+    /// Context window to fit the request into (prompt + max_tokens)
+    #[serde(default)]
+    pub max_context_tokens: Option<u32>,
+    /// How to shrink the conversation when it doesn't fit (default: drop_oldest)
+    #[serde(default)]
+    pub truncation: Option<LlmTruncation>,
+    /// OpenAI-style tool definitions (mapped to Anthropic's shape for `api_style: anthropic`)
+    #[serde(default)]
+    pub tools: Option<serde_json::Value>,
+    /// "auto", "none", "required", or a specific function
+    #[serde(default)]
+    pub tool_choice: Option<serde_json::Value>,
+    /// Max duration of a streamed response in ms (504 with the partial content when exceeded)
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Provider wire format (default: open_ai)
+    #[serde(default)]
+    pub api_style: Option<LlmApiStyle>,
 }
 
 // =============================================================================
@@ -219,6 +532,9 @@ pub struct SubFlowResumeData {
     /// Error message if failed
     #[serde(default)]
     pub error: Option<String>,
+    /// Dotted path to extract from the output (from the sub-flow node's `output_path`)
+    #[serde(default)]
+    pub output_path: Option<String>,
 }
 
 // =============================================================================
@@ -243,6 +559,15 @@ pub struct MapNodeData {
     /// Max concurrent executions (default: 5)
     #[serde(default = "default_concurrency")]
     pub concurrency: u32,
+    /// Let the batch raise or lower its concurrency from error rate and latency
+    #[serde(default)]
+    pub adaptive_concurrency: bool,
+    /// Ceiling for adaptive concurrency (default: 4x `concurrency`, at most 200)
+    #[serde(default)]
+    pub max_concurrency: Option<u32>,
+    /// Stream each child's result as an `item` chunk as it completes (for live results grids)
+    #[serde(default)]
+    pub stream_results: bool,
     /// If true, stop on first failure
     #[serde(default)]
     pub fail_fast: bool,
@@ -283,6 +608,213 @@ pub struct MapChildCompleteData {
     pub error: Option<String>,
 }
 
+// =============================================================================
+// PARALLEL NODE
+// =============================================================================
+
+/// When a Parallel node resumes.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinMode {
+    /// Once every branch finished; fails if any branch failed (default)
+    #[default]
+    All,
+    /// On the first branch to succeed; fails only if every branch failed
+    Any,
+    /// On the first branch to finish, with that branch's outcome
+    Race,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParallelNodeData {
+    /// Node ids (in this workflow) to execute concurrently
+    pub branches: Vec<String>,
+    #[serde(default)]
+    pub join_mode: JoinMode,
+}
+
+/// Marks a job as one branch of a Parallel node; its final result is
+/// reported back to the Parallel node instead of the orchestrator.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParallelBranch {
+    /// Batch operation ID of the Parallel node
+    pub batch_id: String,
+    /// The Parallel node waiting on this branch
+    pub parallel_node_id: String,
+    /// Position in the Parallel node's `branches`
+    pub branch_index: i32,
+    pub join_mode: JoinMode,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ParallelBranchCompleteData {
+    /// Batch operation ID
+    pub batch_id: String,
+    /// Position in the Parallel node's `branches`
+    pub branch_index: i32,
+    /// The branch node that finished
+    pub branch_node_id: String,
+    #[serde(default)]
+    pub join_mode: JoinMode,
+    /// Whether the branch succeeded
+    pub success: bool,
+    /// The branch's result body
+    #[typeshare(serialized_as = "any")]
+    pub output: Option<serde_json::Value>,
+    /// Error message if failed
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+// =============================================================================
+// LOOP NODE
+// =============================================================================
+
+fn default_max_iterations() -> u32 {
+    10
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoopNodeData {
+    /// Node id (in this workflow) run on each iteration
+    pub body: String,
+    /// JS expression over `output` (the body's last result) and `iteration`
+    /// (1-based); the loop ends once it's truthy
+    pub condition: String,
+    /// Iterations before the loop ends anyway (capped by `LOOP_MAX_ITERATIONS`)
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: u32,
+}
+
+/// Marks a job as one iteration of a Loop node's body; its final result is
+/// reported back to the Loop node instead of the orchestrator.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoopIteration {
+    /// The Loop node waiting on this iteration
+    pub loop_node_id: String,
+    /// 0-based
+    pub iteration: u32,
+    pub condition: String,
+    pub max_iterations: u32,
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoopIterationCompleteData {
+    /// The body node that finished
+    pub body_node_id: String,
+    /// 0-based
+    pub iteration: u32,
+    pub condition: String,
+    pub max_iterations: u32,
+    /// Whether the body succeeded
+    pub success: bool,
+    /// The body's result body
+    #[typeshare(serialized_as = "any")]
+    pub output: Option<serde_json::Value>,
+    /// Error message if failed
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+// =============================================================================
+// AGGREGATE NODE
+// =============================================================================
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateSource {
+    /// Upstream node whose latest result is read
+    pub node_id: String,
+    /// Key in the merged object (default: the node id)
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+impl AggregateSource {
+    pub fn output_key(&self) -> &str {
+        self.key.as_deref().unwrap_or(&self.node_id)
+    }
+}
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateNodeData {
+    pub sources: Vec<AggregateSource>,
+}
+
+// =============================================================================
+// TRANSFORM NODE
+// =============================================================================
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransformNodeData {
+    /// jq filter, e.g. `{id, name: .user.full_name}` or `[.items[] | select(.active)]`
+    pub expression: String,
+    /// The JSON the filter runs on (`.`); null when absent
+    #[typeshare(serialized_as = "any")]
+    #[serde(default)]
+    pub input: Option<serde_json::Value>,
+}
+
+// =============================================================================
+// EMAIL NODE
+// =============================================================================
+
+/// Send one message through the SMTP server configured with `SMTP_*`.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailNodeData {
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    /// `{{...}}` templates (e.g. `{{$trigger.name}}`) are resolved against the run
+    pub subject: String,
+    /// `{{...}}` templates are resolved like the subject
+    pub body: String,
+    /// Send `body` as text/html instead of text/plain
+    #[serde(default)]
+    pub is_html: bool,
+}
+
+// =============================================================================
+// S3 NODE
+// =============================================================================
+
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum S3Operation {
+    Get,
+    Put,
+    Delete,
+}
+
+/// Read, write or delete one object in the S3-compatible store configured
+/// with `S3_*` (AWS, MinIO, R2, ...).
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct S3NodeData {
+    pub operation: S3Operation,
+    pub bucket: String,
+    pub key: String,
+    /// Put only: a string is stored as text, a `{ "_binary": true, "base64": ... }`
+    /// value (e.g. an HTTP node's binary response) as its bytes, anything else as JSON
+    #[typeshare(serialized_as = "any")]
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+    /// Put only: overrides the Content-Type derived from `body`
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
 // =============================================================================
 // NODE TYPE ENUM
 // =============================================================================
@@ -291,20 +823,36 @@ pub struct MapChildCompleteData {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", content = "data")]
 #[serde(rename_all = "UPPERCASE")]
+// One short-lived value per job; boxing Http would only add an allocation
+#[allow(clippy::large_enum_variant)]
 pub enum NodeType {
     Http(HttpNodeData),
     Code(CodeNodeData),
+    #[serde(alias = "SLEEP")]
     Delay(DelayNodeData),
     DelayResume(DelayResumeData),
     WebhookWait(WebhookWaitData),
     WebhookResume(WebhookResumeData),
+    DbWait(DbWaitNodeData),
+    DbWaitResume(DbWaitResumeData),
     Router(RouterNodeData),
+    Conditional(ConditionalNodeData),
     Llm(LlmNodeData),
     SubFlow(SubFlowNodeData),
     SubFlowResume(SubFlowResumeData),
     Map(MapNodeData),
     MapStep(MapStepData),
     MapChildComplete(MapChildCompleteData),
+    Parallel(ParallelNodeData),
+    ParallelBranchComplete(ParallelBranchCompleteData),
+    Loop(LoopNodeData),
+    LoopIterationComplete(LoopIterationCompleteData),
+    Aggregate(AggregateNodeData),
+    WebSocket(WebSocketNodeData),
+    DbUpsert(DbUpsertNodeData),
+    Transform(TransformNodeData),
+    Email(EmailNodeData),
+    S3(S3NodeData),
 }
 
 // =============================================================================
@@ -315,6 +863,20 @@ fn default_max_retries() -> u32 {
     3
 }
 
+/// Which job stream a job is enqueued on (see `priority`).
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    /// Interactive work; read first
+    High,
+    /// Everything without a priority
+    #[default]
+    Default,
+    /// Bulk work (e.g. map children); read last, but never starved
+    Low,
+}
+
 #[typeshare]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorkerJob {
@@ -334,6 +896,57 @@ pub struct WorkerJob {
     /// If true, don't trigger downstream nodes
     #[serde(default)]
     pub isolated: bool,
+    /// Optional caller-supplied key for dedup across runs (lifecycle events:
+    /// claimed in `processed_jobs` so racing deliveries run once)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Total time allowed across all attempts, in ms since the first one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    /// When the first attempt started (unix ms); set by the worker and carried across retries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_attempt_at: Option<u64>,
+    /// W3C `traceparent`/`tracestate` of whatever enqueued the job
+    #[typeshare(serialized_as = "Record<string, string>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_context: Option<HashMap<String, String>>,
+    /// Set when the job is a branch of a Parallel node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_branch: Option<ParallelBranch>,
+    /// Set when the job is an iteration of a Loop node's body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loop_iteration: Option<LoopIteration>,
+    /// Describe what the node would do instead of doing it (see `nodes::dry_run`)
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Picks the stream the job (and its retries) are enqueued on
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// Run-scoped values for `{{$env.NAME}}`, consulted before the worker's
+    /// environment (e.g. a tenant's API key)
+    #[typeshare(serialized_as = "Record<string, string>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_env: Option<RunEnv>,
+}
+
+/// Run-scoped `{{$env.*}}` values. Its `Debug` output lists names only, so a
+/// logged job never shows the secrets.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct RunEnv(pub HashMap<String, String>);
+
+impl RunEnv {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+impl std::fmt::Debug for RunEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        f.debug_struct("RunEnv").field("names", &names).finish_non_exhaustive()
+    }
 }
 
 // =============================================================================
@@ -355,4 +968,73 @@ pub struct ExecutionResult {
     /// If true, frontend should not trigger downstream
     #[serde(default)]
     pub isolated: bool,
+    /// Output handle the orchestrator should follow (e.g. "success", "error", "branchB").
+    /// None = default routing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route_to: Option<String>,
+    /// Non-fatal issues the node ran into (shown separately from errors)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<NodeWarning>,
+}
+
+/// A caveat on an otherwise successful node result.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeWarning {
+    /// Stable identifier: "temperature_clamped", "context_truncated", ...
+    pub code: String,
+    pub message: String,
+}
+
+// =============================================================================
+// SUSPENSION
+// =============================================================================
+
+/// What a suspended node is waiting for.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuspensionKind {
+    Webhook,
+    Delay,
+    DbWait,
+    SubFlow,
+    Map,
+    Parallel,
+    Loop,
+}
+
+/// What resumes a suspended node.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResumeVia {
+    /// A call to the resume URL
+    Webhook,
+    /// The scheduler (a timer, or a condition it polls)
+    Scheduler,
+    /// A child run finishing
+    ChildRun,
+    /// All of a batch's child runs finishing
+    ChildRuns,
+    /// The branches joining
+    Branches,
+    /// The loop condition holding (or running out of iterations)
+    Iterations,
+}
+
+/// Uniform description of a suspension, carried as `suspension` in every
+/// NODE_SUSPENDED event (and the suspended result body) whatever the node type.
+#[typeshare]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Suspension {
+    pub kind: SuspensionKind,
+    pub resume_via: ResumeVia,
+    /// RFC 3339; None when the wait has no deadline
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Node-specific details (resume token, child run, batch size, ...)
+    #[typeshare(serialized_as = "any")]
+    #[serde(default)]
+    pub detail: serde_json::Value,
 }
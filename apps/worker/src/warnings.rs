@@ -0,0 +1,24 @@
+//! Per-job collector for non-fatal node warnings.
+//!
+//! Nodes push caveats (clamped parameters, truncated payloads, ...) here
+//! instead of inventing ad-hoc body flags; the worker attaches them to the
+//! job's `ExecutionResult` and NODE_COMPLETED event.
+
+use crate::types::NodeWarning;
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+pub struct Warnings(Mutex<Vec<NodeWarning>>);
+
+impl Warnings {
+    pub fn push(&self, code: &str, message: impl Into<String>) {
+        let warning = NodeWarning { code: code.to_string(), message: message.into() };
+        tracing::warn!("Warning [{}]: {}", warning.code, warning.message);
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(warning);
+    }
+
+    /// Everything collected so far (leaves the collector empty).
+    pub fn take(&self) -> Vec<NodeWarning> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
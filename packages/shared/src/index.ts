@@ -45,6 +45,7 @@ export const EVENT_TYPES = {
     NODE_RETRY_SCHEDULED: 'NODE_RETRY_SCHEDULED',
     NODE_SUSPENDED: 'NODE_SUSPENDED',
     NODE_RESUMED: 'NODE_RESUMED',
+    NODE_PROGRESS: 'NODE_PROGRESS',
     NODE_SKIPPED: 'NODE_SKIPPED'
 } as const;
 